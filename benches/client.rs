@@ -0,0 +1,172 @@
+//! Throughput and latency of puts, gets, transactions and watches against a running etcd.
+//!
+//! ```text
+//! cargo bench --bench client -- [filter...]
+//! ```
+//!
+//! Each operation is benchmarked once with the default retry policy and once with retries
+//! disabled, and again over TLS when built with a TLS feature and `ETCD3_BENCH_TLS_ENDPOINT` is
+//! set. Benchmarks are named `<operation>/<connection>`, and criterion's own options, such as
+//! filters and `--save-baseline`, are passed after `--`.
+//!
+//! Settings are read from the environment:
+//!
+//! * `ETCD3_BENCH_ENDPOINT`: endpoint to benchmark, `http://127.0.0.1:2379` by default
+//! * `ETCD3_BENCH_TLS_ENDPOINT`: endpoint to benchmark over TLS
+//! * `ETCD3_BENCH_CA`: PEM file of the CA which signed the TLS endpoint's certificate
+//!
+//! The keys written are under a prefix of their own, deleted once the run is over.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use etcd3::prelude::*;
+use etcd3::{EtcdClientBuilder, OpRequest, PutRequest, RequestOp, RetryPolicy, TxnRequest};
+use std::env;
+use tokio::runtime::Runtime;
+use tonic::transport::Channel;
+
+/// The clients to benchmark, named after how they connect
+fn connections() -> Vec<(&'static str, EtcdClientBuilder)> {
+    let endpoint =
+        env::var("ETCD3_BENCH_ENDPOINT").unwrap_or_else(|_| "http://127.0.0.1:2379".to_string());
+
+    // Only pushed to when one of the TLS features is enabled
+    #[allow(unused_mut)]
+    let mut connections = vec![
+        ("default", EtcdClient::builder().endpoint(endpoint.as_str())),
+        (
+            "no-retries",
+            EtcdClient::builder()
+                .endpoint(endpoint.as_str())
+                .retry(RetryPolicy::disabled()),
+        ),
+    ];
+    #[cfg(any(feature = "tls", feature = "tls-native"))]
+    {
+        if let Ok(endpoint) = env::var("ETCD3_BENCH_TLS_ENDPOINT") {
+            let ca_certificate = env::var("ETCD3_BENCH_CA")
+                .ok()
+                .map(|ca| std::fs::read(ca).expect("failed to read ETCD3_BENCH_CA"));
+            let tls = etcd3::TlsConfig {
+                ca_certificate,
+                ..Default::default()
+            };
+            connections.push((
+                "tls",
+                EtcdClient::builder()
+                    .endpoint(endpoint.as_str())
+                    .tls(tls.clone()),
+            ));
+            connections.push((
+                "tls-no-retries",
+                EtcdClient::builder()
+                    .endpoint(endpoint.as_str())
+                    .tls(tls)
+                    .retry(RetryPolicy::disabled()),
+            ));
+        }
+    }
+    connections
+}
+
+fn client(c: &mut Criterion) {
+    let runtime = Runtime::new().expect("failed to start a runtime");
+    let clients: Vec<(&str, EtcdClient<Channel>)> = connections()
+        .into_iter()
+        .map(|(connection, builder)| {
+            let client = runtime.block_on(builder.connect());
+            (connection, client.expect("failed to connect to etcd"))
+        })
+        .collect();
+    let prefix = format!("etcd3-bench/{}/", std::process::id());
+
+    let mut group = c.benchmark_group("put");
+    group.throughput(Throughput::Elements(1));
+    for (connection, client) in &clients {
+        let (kv, key) = (client.kv(), format!("{}put/{}", prefix, connection));
+        let mut i = 0u64;
+        group.bench_function(*connection, |b| {
+            b.iter(|| {
+                i += 1;
+                runtime.block_on(kv.put(&key, i.to_string())).unwrap()
+            })
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("get");
+    group.throughput(Throughput::Elements(1));
+    for (connection, client) in &clients {
+        let (kv, key) = (client.kv(), format!("{}get/{}", prefix, connection));
+        runtime.block_on(kv.put(&key, "value")).unwrap();
+        group.bench_function(*connection, |b| {
+            b.iter(|| runtime.block_on(kv.get_bytes(&key)).unwrap())
+        });
+    }
+    group.finish();
+
+    // Transactions replace the key's value if it holds the one put before
+    let mut group = c.benchmark_group("txn");
+    group.throughput(Throughput::Elements(1));
+    for (connection, client) in &clients {
+        let (kv, key) = (client.kv(), format!("{}txn/{}", prefix, connection));
+        runtime.block_on(kv.put(&key, "0")).unwrap();
+        let mut i = 0u64;
+        group.bench_function(*connection, |b| {
+            b.iter(|| {
+                let request = TxnRequest {
+                    compare: vec![compare_value(&key, CompareResult::Equal, i.to_string())],
+                    success: vec![RequestOp {
+                        request: Some(OpRequest::RequestPut(PutRequest {
+                            key: key.as_bytes().to_vec(),
+                            value: (i + 1).to_string().into_bytes(),
+                            ..Default::default()
+                        })),
+                    }],
+                    failure: Vec::new(),
+                };
+                let response = runtime.block_on(kv.txn(request)).unwrap();
+                assert!(response.succeeded(), "transaction {} failed", i);
+                i += 1;
+            })
+        });
+    }
+    group.finish();
+
+    // Time from putting a key until its watcher receives the event
+    let mut group = c.benchmark_group("watch");
+    group.throughput(Throughput::Elements(1));
+    for (connection, client) in &clients {
+        let (kv, key) = (client.kv(), format!("{}watch/{}", prefix, connection));
+        let mut stream = runtime.block_on(client.watch().watch(&key)).unwrap();
+        let mut i = 0u64;
+        group.bench_function(*connection, |b| {
+            b.iter(|| {
+                i += 1;
+                runtime.block_on(async {
+                    let revision = kv.put(&key, i.to_string()).await.unwrap().revision();
+                    loop {
+                        let response = stream.message().await.unwrap().expect("the watch ended");
+                        if response
+                            .events()
+                            .iter()
+                            .any(|event| event.kv().mod_revision() >= revision)
+                        {
+                            break;
+                        }
+                    }
+                })
+            })
+        });
+    }
+    group.finish();
+
+    // The prefix ends with '/', so its keys are those before the same prefix ending in '0'
+    let end = format!("{}0", &prefix[..prefix.len() - 1]);
+    if let Some((_, client)) = clients.first() {
+        let range = client.range(prefix.as_str(), Some(end.as_str()));
+        runtime.block_on(range.delete()).unwrap();
+    }
+}
+
+criterion_group!(benches, client);
+criterion_main!(benches);
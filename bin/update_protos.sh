@@ -1,14 +1,26 @@
 #!/bin/bash -e
 
 protodir=proto
-sources=(etcdserver/etcdserverpb/rpc.proto mvcc/mvccpb/kv.proto auth/authpb/auth.proto)
-branch="${1:-v3.4.3}"
+sources=(
+  api/etcdserverpb/rpc.proto
+  api/mvccpb/kv.proto
+  api/authpb/auth.proto
+  server/etcdserver/api/v3lock/v3lockpb/v3lock.proto
+  server/etcdserver/api/v3election/v3electionpb/v3election.proto
+)
+branch="${1:-v3.5.0}"
 
 mkdir -p $protodir
 
 sedscript='
 /gogoproto/d;
 /annotations.proto/d;
+/^option (grpc\.gateway\.protoc_gen_openapiv2\.options\.openapiv2_swagger) = {/ {
+  :b;
+  N;
+  /\n};/!bb;
+  d
+}
 /^import/s|".*/|"|;
 /^\s*option (google\.api\.http) = {/ {
   :a;
@@ -1,15 +1,61 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
 fn main() {
-    // Top level protobuf file, this includes the other files
-    let protos = &["proto/rpc.proto"];
-
-    match tonic_build::configure()
-        // Do not build the server files, as we are just writing a client
-        .build_server(false)
-        .compile(protos, &["proto"])
-    {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=proto");
+    println!("cargo:rerun-if-env-changed=ETCD3_VENDOR_PROTOS");
+
+    // With vendored-protos the code checked in under src/proto/vendored is compiled instead, so
+    // building needs neither protoc nor the proto files. Setting ETCD3_VENDOR_PROTOS
+    // regenerates it.
+    let vendor = env::var_os("ETCD3_VENDOR_PROTOS").is_some();
+    if env::var_os("CARGO_FEATURE_VENDORED_PROTOS").is_some() && !vendor {
+        return;
+    }
+
+    // Top level protobuf files, these include the other files
+    let mut protos = vec!["proto/rpc.proto"];
+    // The lock and election services are only compiled in when their features are enabled,
+    // but are always vendored
+    if vendor || env::var_os("CARGO_FEATURE_LOCK").is_some() {
+        protos.push("proto/v3lock.proto");
+    }
+    if vendor || env::var_os("CARGO_FEATURE_ELECTION").is_some() {
+        protos.push("proto/v3election.proto");
+    }
+
+    // Do not build the server files, as we are just writing a client. Keys and values are
+    // generated as Vec<u8>, since prost 0.5 cannot generate bytes fields as Bytes, so
+    // responses hand out the decoded buffers themselves rather than copies of them. The serde
+    // derives are behind a cfg_attr, so the vendored code serves builds with and without the
+    // feature.
+    let builder = tonic_build::configure().build_server(false).type_attribute(
+        ".",
+        "#[cfg_attr(feature = \"serde\", derive(serde::Serialize, serde::Deserialize))]",
+    );
+
+    match builder.compile(&protos, &["proto"]) {
         Ok(_) => {}
         Err(e) => {
             panic!("{}", e);
         }
     }
+
+    if vendor {
+        let out = env::var("OUT_DIR").unwrap();
+        let manifest = env::var("CARGO_MANIFEST_DIR").unwrap();
+        let vendored = Path::new(&manifest).join("src/proto/vendored");
+        for package in &[
+            "mvccpb",
+            "authpb",
+            "etcdserverpb",
+            "v3lockpb",
+            "v3electionpb",
+        ] {
+            let file = format!("{}.rs", package);
+            fs::copy(Path::new(&out).join(&file), vendored.join(&file)).unwrap();
+        }
+    }
 }
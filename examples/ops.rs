@@ -1,6 +1,6 @@
 //! Mirror of the low level ops example, but using the high level API provided by the etcd3 crate.
 //!
-use etcd3::EtcdClient;
+use etcd3::prelude::*;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -25,7 +25,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("{:#?}", members);
 
     // Watch
-    let mut stream = client.watch("foo").await?;
+    let mut stream = client.watch().watch("foo").await?;
     while let Some(msg) = stream.message().await? {
         println!("{:#?}", msg);
     }
@@ -59,7 +59,7 @@ async fn run_status() -> Result<(), Box<dyn Error>> {
 async fn run_cluster() -> Result<(), Box<dyn Error>> {
     let mut client = etcd3::etcdserver::client::ClusterClient::connect(HOST_STR).await?;
 
-    let request = etcd3::etcdserver::MemberListRequest {};
+    let request = etcd3::etcdserver::MemberListRequest::default();
     let response = client.member_list(request).await?;
 
     println!("{:#?}", response);
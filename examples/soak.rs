@@ -0,0 +1,281 @@
+//! Long running load against a running etcd, checking the client stays correct under it.
+//!
+//! Workers put, get and increment a shared counter with transactions for as long as the soak
+//! runs, while a watch follows the counter. The soak fails if a worker reads back a value
+//! other than the one it put, if the watch misses, repeats or reorders a change to the counter,
+//! or if the counter ends at a value other than the number of increments made.
+//!
+//! Settings are read from the environment:
+//!
+//! * `ETCD3_SOAK_ENDPOINT`: endpoint to load, `http://127.0.0.1:2379` by default
+//! * `ETCD3_SOAK_SECONDS`: how long to run, 600 by default
+//! * `ETCD3_SOAK_WORKERS`: workers making requests concurrently, 8 by default
+//! * `ETCD3_SOAK_REPORT_SECONDS`: interval between printing statistics, 10 by default
+use etcd3::prelude::*;
+use etcd3::{OpRequest, PutRequest, RequestOp, TxnRequest};
+use futures::future::{self, Either};
+use std::env;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tonic::transport::Channel;
+
+type SoakResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+/// Counts shared by the workers, the watch and the statistics
+#[derive(Default)]
+struct Stats {
+    operations: AtomicU64,
+    errors: AtomicU64,
+    increments: AtomicU64,
+    /// Increments whose transaction failed without telling whether it was applied
+    uncertain: AtomicU64,
+    conflicts: AtomicU64,
+    watched: AtomicU64,
+    violations: AtomicU64,
+    done: AtomicBool,
+}
+
+impl Stats {
+    fn violation(&self, message: String) {
+        eprintln!("violation: {}", message);
+        self.violations.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn print(&self, elapsed: Duration) {
+        println!(
+            "{:>6}s operations={} errors={} increments={} conflicts={} watched={} violations={}",
+            elapsed.as_secs(),
+            self.operations.load(Ordering::SeqCst),
+            self.errors.load(Ordering::SeqCst),
+            self.increments.load(Ordering::SeqCst),
+            self.conflicts.load(Ordering::SeqCst),
+            self.watched.load(Ordering::SeqCst),
+            self.violations.load(Ordering::SeqCst),
+        );
+    }
+}
+
+fn setting(name: &str, default: u64) -> SoakResult<u64> {
+    match env::var(name) {
+        Ok(value) => Ok(value.parse()?),
+        Err(_) => Ok(default),
+    }
+}
+
+async fn sleep(duration: Duration) {
+    tokio::timer::delay(tokio::clock::now() + duration).await;
+}
+
+#[tokio::main]
+async fn main() -> SoakResult<()> {
+    let endpoint =
+        env::var("ETCD3_SOAK_ENDPOINT").unwrap_or_else(|_| "http://127.0.0.1:2379".to_string());
+    let duration = Duration::from_secs(setting("ETCD3_SOAK_SECONDS", 600)?);
+    let workers = setting("ETCD3_SOAK_WORKERS", 8)?;
+    let report = Duration::from_secs(setting("ETCD3_SOAK_REPORT_SECONDS", 10)?);
+
+    let client = EtcdClient::connect(endpoint).await?;
+    let prefix = format!("etcd3-soak/{}/", std::process::id());
+    let counter = format!("{}counter", prefix);
+    client.kv().put(counter.as_str(), "0").await?;
+
+    let stats = Stats::default();
+    let start = Instant::now();
+    let watch = follow(&client, &counter, &stats);
+    let run = async {
+        let load = future::join_all((0..workers).map(|worker| {
+            let key = format!("{}worker/{}", prefix, worker);
+            work(&client, key, &counter, &stats, start + duration)
+        }));
+        let printing = async {
+            let mut printed = Instant::now();
+            while !stats.done.load(Ordering::SeqCst) {
+                sleep(Duration::from_secs(1)).await;
+                if printed.elapsed() >= report {
+                    stats.print(start.elapsed());
+                    printed = Instant::now();
+                }
+            }
+        };
+        let load = async {
+            load.await;
+            stats.done.store(true, Ordering::SeqCst);
+        };
+        futures::join!(load, printing);
+        check_counter(&client, &counter, &stats).await
+    };
+    let outcome = match future::select(Box::pin(watch), Box::pin(run)).await {
+        Either::Left((watched, _)) => match watched {
+            Ok(()) => Err("the watch ended".into()),
+            Err(e) => Err(format!("the watch failed: {}", e).into()),
+        },
+        Either::Right((checked, _)) => checked,
+    };
+
+    // The prefix ends with '/', so its keys are those before the same prefix ending in '0'
+    let end = format!("{}0", &prefix[..prefix.len() - 1]);
+    client
+        .range(prefix.as_str(), Some(end.as_str()))
+        .delete()
+        .await?;
+
+    stats.print(start.elapsed());
+    if let Err(e) = outcome {
+        stats.violation(e.to_string());
+    }
+    if stats.violations.load(Ordering::SeqCst) > 0 {
+        std::process::exit(1);
+    }
+    println!("soak passed");
+    Ok(())
+}
+
+/// Put, read back and increment until `until`
+async fn work(
+    client: &EtcdClient<Channel>,
+    key: String,
+    counter: &str,
+    stats: &Stats,
+    until: Instant,
+) {
+    let kv = client.kv();
+    let mut iteration = 0u64;
+    while Instant::now() < until {
+        iteration += 1;
+        let value = iteration.to_string();
+        let written = async {
+            kv.put(key.as_str(), value.as_str()).await?;
+            kv.get_bytes(key.as_str()).await
+        };
+        match written.await {
+            Ok(read) if read.as_ref().map(|read| &read[..]) == Some(value.as_bytes()) => {}
+            Ok(read) => stats.violation(format!("{} read back {:?}, not {}", key, read, value)),
+            Err(_) => {
+                stats.errors.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        stats.operations.fetch_add(2, Ordering::SeqCst);
+
+        increment(client, counter, stats).await;
+    }
+}
+
+/// Add one to `counter`, retrying the transaction while other workers get there first
+async fn increment(client: &EtcdClient<Channel>, counter: &str, stats: &Stats) {
+    let kv = client.kv();
+    loop {
+        stats.operations.fetch_add(1, Ordering::SeqCst);
+        let current = match kv.get(counter).await {
+            Ok(Some(current)) => current,
+            Ok(None) => return stats.violation(format!("{} was deleted", counter)),
+            Err(_) => {
+                stats.errors.fetch_add(1, Ordering::SeqCst);
+                continue;
+            }
+        };
+        let next = match current.parse::<u64>() {
+            Ok(current) => current + 1,
+            Err(_) => return stats.violation(format!("{} holds {:?}", counter, current)),
+        };
+        let request = TxnRequest {
+            compare: vec![compare_value(
+                counter,
+                CompareResult::Equal,
+                current.as_str(),
+            )],
+            success: vec![RequestOp {
+                request: Some(OpRequest::RequestPut(PutRequest {
+                    key: counter.as_bytes().to_vec(),
+                    value: next.to_string().into_bytes(),
+                    ..Default::default()
+                })),
+            }],
+            failure: Vec::new(),
+        };
+        stats.operations.fetch_add(1, Ordering::SeqCst);
+        match kv.txn(request).await {
+            Ok(response) if response.succeeded() => {
+                stats.increments.fetch_add(1, Ordering::SeqCst);
+                return;
+            }
+            Ok(_) => {
+                stats.conflicts.fetch_add(1, Ordering::SeqCst);
+            }
+            Err(_) => {
+                // The transaction may have been applied before the error, so retrying could
+                // increment twice
+                stats.errors.fetch_add(1, Ordering::SeqCst);
+                stats.uncertain.fetch_add(1, Ordering::SeqCst);
+                return;
+            }
+        }
+    }
+}
+
+/// Follow `counter`, checking each change adds one to the last at a later revision. Runs
+/// until the watch ends.
+async fn follow(client: &EtcdClient<Channel>, counter: &str, stats: &Stats) -> EtcdResult<()> {
+    let mut stream = client.watch().watch(counter).await?;
+    let mut last = (0, 0);
+    while let Some(response) = stream.message().await? {
+        for event in response.events() {
+            let kv = event.kv();
+            let value = kv
+                .value_str()
+                .ok()
+                .and_then(|value| value.parse::<u64>().ok());
+            match value {
+                Some(value) if value == last.0 + 1 && kv.mod_revision() > last.1 => {
+                    last = (value, kv.mod_revision());
+                }
+                _ => stats.violation(format!(
+                    "the watch saw {:?} at revision {} after {} at revision {}",
+                    kv.value_str(),
+                    kv.mod_revision(),
+                    last.0,
+                    last.1
+                )),
+            }
+            stats.watched.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+    Ok(())
+}
+
+/// Check the counter holds the number of increments made, and that the watch caught up with it
+async fn check_counter(
+    client: &EtcdClient<Channel>,
+    counter: &str,
+    stats: &Stats,
+) -> SoakResult<()> {
+    let value: u64 = client
+        .kv()
+        .get(counter)
+        .await?
+        .ok_or("the counter was deleted")?
+        .parse()?;
+    let increments = stats.increments.load(Ordering::SeqCst);
+    let uncertain = stats.uncertain.load(Ordering::SeqCst);
+    if value < increments || value > increments + uncertain {
+        return Err(format!(
+            "the counter is {} after {} increments, {} of them uncertain",
+            value,
+            increments + uncertain,
+            uncertain
+        )
+        .into());
+    }
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while stats.watched.load(Ordering::SeqCst) < value {
+        if Instant::now() > deadline {
+            return Err(format!(
+                "the watch saw {} of {} changes",
+                stats.watched.load(Ordering::SeqCst),
+                value
+            )
+            .into());
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use etcd3::{EtcdClient, EtcdResult};
+use etcd3::prelude::*;
 use std::time;
 use tokio::timer::delay;
 
@@ -10,10 +10,10 @@ async fn sleep() {
 #[tokio::main]
 async fn main() -> EtcdResult<()> {
     // Connect to the server
-    let mut client = EtcdClient::connect("http://127.0.0.1:2379").await?;
+    let client = EtcdClient::connect("http://127.0.0.1:2379").await?;
 
     // Set up the watch
-    let mut stream = client.watch("foo").await?;
+    let mut stream = client.watch().watch("foo").await?;
 
     // Spawn the watcher task in the background
     tokio::spawn(async move {
@@ -25,7 +25,7 @@ async fn main() -> EtcdResult<()> {
     sleep().await;
 
     // Now we write the key a few times
-    let mut range = client.range("foo", None);
+    let range = client.range("foo", None);
     range.put("bar").await?;
 
     sleep().await;
@@ -0,0 +1,170 @@
+//! Traits over the KV, watch and lease services, and the cluster and maintenance services with
+//! their features, so code using etcd can be written against them and tested with the
+//! in-memory [`FakeEtcd`](crate::fake::FakeEtcd) instead of a running cluster. [`EtcdClient`]
+//! implements every trait, and so does the fake.
+//!
+//! The traits cover the operations most code needs, taking keys as byte slices so they can
+//! be used as trait objects. Keys, revisions and errors behave as with a real cluster.
+
+use crate::{
+    etcdserver, DeleteResponse, EtcdClient, EtcdResult, GetResponse, KeyValue, PutOptions,
+    PutResponse, Transport, WatchResponse,
+};
+use futures::Stream;
+use std::pin::Pin;
+use tonic::async_trait;
+
+/// The responses to a watch, see [`Watcher::watch`]. The stream ends once the watch does.
+pub type WatchEvents = Pin<Box<dyn Stream<Item = EtcdResult<WatchResponse>> + Send>>;
+
+/// Reading and writing keys
+#[async_trait]
+pub trait Kv: Send + Sync {
+    /// Put `value` at `key`, see [`PutOptions`]
+    async fn put(&self, key: &[u8], value: &[u8], options: PutOptions) -> EtcdResult<PutResponse>;
+
+    /// The keys from `start` up to `end`, see [`EtcdClient::range`]
+    async fn range(&self, start: &[u8], end: Option<&[u8]>) -> EtcdResult<GetResponse>;
+
+    /// Delete the keys from `start` up to `end`, see [`EtcdClient::range`]
+    async fn delete_range(&self, start: &[u8], end: Option<&[u8]>) -> EtcdResult<DeleteResponse>;
+
+    /// The key `key`, if it exists
+    async fn get(&self, key: &[u8]) -> EtcdResult<Option<KeyValue>> {
+        Ok(self.range(key, None).await?.into_iter().next())
+    }
+
+    /// Delete the key `key`
+    async fn delete(&self, key: &[u8]) -> EtcdResult<DeleteResponse> {
+        self.delete_range(key, None).await
+    }
+}
+
+/// Following changes to keys
+#[async_trait]
+pub trait Watcher: Send + Sync {
+    /// Watch `key` for changes made from now on, see [`Watch::watch`](crate::Watch::watch)
+    async fn watch(&self, key: &[u8]) -> EtcdResult<WatchEvents>;
+}
+
+/// Leases which keys can be attached to
+#[async_trait]
+pub trait Leaser: Send + Sync {
+    /// Grant a lease which expires `ttl` seconds from now unless it is kept alive
+    async fn grant(&self, ttl: i64) -> EtcdResult<etcdserver::LeaseGrantResponse>;
+
+    /// Revoke the lease `id`, deleting every key attached to it
+    async fn revoke(&self, id: i64) -> EtcdResult<()>;
+
+    /// Refresh the lease `id` once, returning its new TTL in seconds
+    async fn keep_alive_once(&self, id: i64) -> EtcdResult<i64>;
+
+    /// Remaining time to live of the lease `id`, along with the keys attached to it if `keys`
+    /// is set
+    async fn time_to_live(
+        &self,
+        id: i64,
+        keys: bool,
+    ) -> EtcdResult<etcdserver::LeaseTimeToLiveResponse>;
+
+    /// IDs of every lease which has not expired
+    async fn leases(&self) -> EtcdResult<Vec<i64>>;
+}
+
+/// The cluster's membership
+#[cfg(feature = "cluster")]
+#[async_trait]
+pub trait Cluster: Send + Sync {
+    /// Every member of the cluster, see [`Cluster::members`](crate::Cluster::members)
+    async fn members(&self) -> EtcdResult<Vec<etcdserver::Member>>;
+}
+
+/// The health of the members
+#[cfg(feature = "maintenance")]
+#[async_trait]
+pub trait Maintenance: Send + Sync {
+    /// Status of the member the client is connected to
+    async fn status(&self) -> EtcdResult<etcdserver::StatusResponse>;
+
+    /// Every alarm raised by a member, such as a member running out of space
+    async fn alarms(&self) -> EtcdResult<Vec<etcdserver::AlarmMember>>;
+}
+
+#[async_trait]
+impl<T: Transport> Kv for EtcdClient<T> {
+    async fn put(&self, key: &[u8], value: &[u8], options: PutOptions) -> EtcdResult<PutResponse> {
+        self.range(key, None).put_with_options(value, options).await
+    }
+
+    async fn range(&self, start: &[u8], end: Option<&[u8]>) -> EtcdResult<GetResponse> {
+        EtcdClient::range(self, start, end).get().await
+    }
+
+    async fn delete_range(&self, start: &[u8], end: Option<&[u8]>) -> EtcdResult<DeleteResponse> {
+        EtcdClient::range(self, start, end).delete().await
+    }
+}
+
+#[async_trait]
+impl<T: Transport> Watcher for EtcdClient<T> {
+    async fn watch(&self, key: &[u8]) -> EtcdResult<WatchEvents> {
+        let stream = EtcdClient::watch(self).watch(key).await?;
+        // The watch ends after its first error
+        let responses = futures::stream::unfold(Some(stream), |stream| async move {
+            let mut stream = stream?;
+            match stream.message().await {
+                Ok(Some(response)) => Some((Ok(response), Some(stream))),
+                Ok(None) => None,
+                Err(e) => Some((Err(e), None)),
+            }
+        });
+        Ok(Box::pin(responses))
+    }
+}
+
+#[async_trait]
+impl<T: Transport> Leaser for EtcdClient<T> {
+    async fn grant(&self, ttl: i64) -> EtcdResult<etcdserver::LeaseGrantResponse> {
+        self.lease().grant(ttl).await
+    }
+
+    async fn revoke(&self, id: i64) -> EtcdResult<()> {
+        self.lease().revoke(id).await
+    }
+
+    async fn keep_alive_once(&self, id: i64) -> EtcdResult<i64> {
+        self.lease().keep_alive_once(id).await
+    }
+
+    async fn time_to_live(
+        &self,
+        id: i64,
+        keys: bool,
+    ) -> EtcdResult<etcdserver::LeaseTimeToLiveResponse> {
+        self.lease().time_to_live(id, keys).await
+    }
+
+    async fn leases(&self) -> EtcdResult<Vec<i64>> {
+        self.lease().leases().await
+    }
+}
+
+#[cfg(feature = "cluster")]
+#[async_trait]
+impl<T: Transport> Cluster for EtcdClient<T> {
+    async fn members(&self) -> EtcdResult<Vec<etcdserver::Member>> {
+        self.cluster().members().await
+    }
+}
+
+#[cfg(feature = "maintenance")]
+#[async_trait]
+impl<T: Transport> Maintenance for EtcdClient<T> {
+    async fn status(&self) -> EtcdResult<etcdserver::StatusResponse> {
+        self.maintenance().status().await
+    }
+
+    async fn alarms(&self) -> EtcdResult<Vec<etcdserver::AlarmMember>> {
+        self.maintenance().alarms().await
+    }
+}
@@ -0,0 +1,169 @@
+//! The auth service: enabling authentication, and the users and roles it checks.
+
+use crate::{etcdserver, Budget, EtcdClient, EtcdResult, Retry, Transport};
+use std::fmt;
+
+/// Authentication, users and roles, see [`EtcdClient::auth`]
+pub struct Auth<T> {
+    pub(crate) client: EtcdClient<T>,
+}
+
+impl<T> fmt::Debug for Auth<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Auth")
+            .field("client", &self.client)
+            .finish()
+    }
+}
+
+impl<T: Transport> Auth<T> {
+    /// Turn authentication on. The `root` user must exist first.
+    pub async fn enable(&self) -> EtcdResult<()> {
+        let request = etcdserver::AuthEnableRequest {};
+        let context = self.client.error_context("auth enable", None);
+        let rpc = || {
+            let (mut auth_client, request) = (self.client.auth_client(), request.clone());
+            async move { auth_client.auth_enable(request).await }
+        };
+        self.client
+            .call(
+                "auth.enable",
+                context,
+                Budget::Write,
+                Retry::Mutation,
+                rpc,
+                |_| Ok(()),
+            )
+            .await
+    }
+
+    /// Turn authentication off
+    pub async fn disable(&self) -> EtcdResult<()> {
+        let request = etcdserver::AuthDisableRequest {};
+        let context = self.client.error_context("auth disable", None);
+        let rpc = || {
+            let (mut auth_client, request) = (self.client.auth_client(), request.clone());
+            async move { auth_client.auth_disable(request).await }
+        };
+        self.client
+            .call(
+                "auth.disable",
+                context,
+                Budget::Write,
+                Retry::Mutation,
+                rpc,
+                |_| Ok(()),
+            )
+            .await
+    }
+
+    /// Authenticate as `name`, returning the token to send with requests. Clients built with
+    /// [credentials](crate::EtcdClientBuilder::credentials) do this themselves.
+    pub async fn authenticate<N, P>(&self, name: N, password: P) -> EtcdResult<String>
+    where
+        N: Into<String>,
+        P: Into<String>,
+    {
+        let request = etcdserver::AuthenticateRequest {
+            name: name.into(),
+            password: password.into(),
+        };
+        let context = self.client.error_context("authenticate", None);
+        let rpc = || {
+            let (mut auth_client, request) = (self.client.auth_client(), request.clone());
+            async move { auth_client.authenticate(request).await }
+        };
+        self.client
+            .call(
+                "auth.authenticate",
+                context,
+                Budget::Write,
+                Retry::Idempotent,
+                rpc,
+                |response| Ok(response.token),
+            )
+            .await
+    }
+
+    /// Names of every user
+    pub async fn users(&self) -> EtcdResult<Vec<String>> {
+        let request = etcdserver::AuthUserListRequest {};
+        let context = self.client.error_context("user list", None);
+        let rpc = || {
+            let (mut auth_client, request) = (self.client.auth_client(), request.clone());
+            async move { auth_client.user_list(request).await }
+        };
+        self.client
+            .call(
+                "auth.user_list",
+                context,
+                Budget::Read,
+                Retry::Idempotent,
+                rpc,
+                |response| Ok(response.users),
+            )
+            .await
+    }
+
+    /// Names of every role
+    pub async fn roles(&self) -> EtcdResult<Vec<String>> {
+        let request = etcdserver::AuthRoleListRequest {};
+        let context = self.client.error_context("role list", None);
+        let rpc = || {
+            let (mut auth_client, request) = (self.client.auth_client(), request.clone());
+            async move { auth_client.role_list(request).await }
+        };
+        self.client
+            .call(
+                "auth.role_list",
+                context,
+                Budget::Read,
+                Retry::Idempotent,
+                rpc,
+                |response| Ok(response.roles),
+            )
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::etcdserver;
+    use crate::recording::{Exchange, Replay};
+    use crate::EtcdClient;
+
+    #[tokio::test]
+    async fn test_attaching_error_context() {
+        let denied = Exchange {
+            code: tonic::Code::PermissionDenied,
+            message: "etcdserver: permission denied".into(),
+            ..Exchange::answered(
+                "/etcdserverpb.Auth/UserList",
+                etcdserver::AuthUserListResponse::default(),
+            )
+        };
+        let roles = etcdserver::AuthRoleListResponse {
+            header: Some(Default::default()),
+            roles: vec!["root".into()],
+        };
+        let replay = Replay::new(vec![
+            denied,
+            Exchange::answered("/etcdserverpb.Auth/RoleList", roles),
+        ]);
+        let client = EtcdClient::from_transport(replay.clone());
+
+        let err = client.auth().users().await.unwrap_err();
+        let context = err.context().unwrap();
+        assert_eq!(context.operation, "user list");
+        assert_eq!(context.attempt, Some(1));
+        assert_eq!(client.auth().roles().await.unwrap(), ["root"]);
+        assert_eq!(replay.remaining(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_listing_users() {
+        let client = crate::testing::namespace().await.unwrap();
+        assert!(client.auth().users().await.is_ok());
+        assert!(client.auth().roles().await.is_ok());
+    }
+}
@@ -0,0 +1,349 @@
+//! Snapshots of the cluster taken on a schedule and kept in a storage of the caller's choosing.
+//!
+//! A [`BackupScheduler`] saves a snapshot with
+//! [`Maintenance::save_snapshot`](crate::Maintenance::save_snapshot), so only snapshots which
+//! pass their integrity check are kept, hands it to a [`BackupStorage`] and prunes the oldest
+//! backups beyond [`BackupOptions::retain`]. [`FilesystemStorage`] keeps them in a directory:
+//!
+//! ```no_run
+//! # async fn run(client: etcd3::EtcdClient<tonic::transport::Channel>) -> etcd3::EtcdResult<()> {
+//! use etcd3::backup::{BackupOptions, BackupScheduler, FilesystemStorage};
+//! use std::time::Duration;
+//!
+//! let options = BackupOptions {
+//!     interval: Duration::from_secs(15 * 60),
+//!     retain: Some(96),
+//!     ..Default::default()
+//! };
+//! let storage = FilesystemStorage::new("/var/backups/etcd");
+//! let backups = BackupScheduler::new(&client, storage, options)
+//!     .on_failure(|e| eprintln!("backing up etcd failed: {}", e))
+//!     .start();
+//! // Backups are taken until `backups` is dropped
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Backups are named after the time they were taken, so that their names sort from the oldest
+//! to the newest.
+
+use crate::{runtime, Error, EtcdClient, EtcdResult, Maintenance, SnapshotInfo, Transport};
+use futures::channel::oneshot;
+use futures::future::{self, FutureExt};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tonic::async_trait;
+
+/// Where backups are kept
+#[async_trait]
+pub trait BackupStorage: Send + Sync {
+    /// Keep the snapshot saved at `snapshot` as the backup `name`. The file is removed once
+    /// this returns.
+    async fn store(&self, name: &str, snapshot: &Path) -> EtcdResult<()>;
+
+    /// Names of the backups kept, in any order
+    async fn list(&self) -> EtcdResult<Vec<String>>;
+
+    /// Remove the backup `name`
+    async fn delete(&self, name: &str) -> EtcdResult<()>;
+}
+
+/// Backups kept as files in a directory, which is created on the first backup
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilesystemStorage {
+    dir: PathBuf,
+}
+
+impl FilesystemStorage {
+    pub fn new<P>(dir: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        Self { dir: dir.into() }
+    }
+
+    /// The directory the backups are kept in
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+/// Suffix of backups still being copied into a [`FilesystemStorage`]
+const PARTIAL_SUFFIX: &str = ".part";
+
+#[async_trait]
+impl BackupStorage for FilesystemStorage {
+    async fn store(&self, name: &str, snapshot: &Path) -> EtcdResult<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        // Copy next to the backup and move it into place, so a backup is never seen half
+        // written
+        let partial = self.dir.join(format!("{}{}", name, PARTIAL_SUFFIX));
+        std::fs::copy(snapshot, &partial)?;
+        std::fs::File::open(&partial)?.sync_all()?;
+        std::fs::rename(&partial, self.dir.join(name))?;
+        Ok(())
+    }
+
+    async fn list(&self) -> EtcdResult<Vec<String>> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut names = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            if let Ok(name) = entry.file_name().into_string() {
+                if !name.ends_with(PARTIAL_SUFFIX) {
+                    names.push(name);
+                }
+            }
+        }
+        Ok(names)
+    }
+
+    async fn delete(&self, name: &str) -> EtcdResult<()> {
+        match std::fs::remove_file(self.dir.join(name)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// How often backups are taken and how many are kept
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupOptions {
+    /// Time between backups. An hour by default.
+    pub interval: Duration,
+    /// Most backups to keep, with the oldest pruned first. 24 by default, and every backup is
+    /// kept if `None`.
+    pub retain: Option<usize>,
+    /// Prefix of the backups' names, followed by the time they were taken. Only backups named
+    /// with it are pruned. `etcd-snapshot-` by default.
+    pub name_prefix: String,
+    /// Directory snapshots are saved to before they are stored. The system's temporary
+    /// directory by default.
+    pub staging_dir: PathBuf,
+}
+
+impl Default for BackupOptions {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(60 * 60),
+            retain: Some(24),
+            name_prefix: "etcd-snapshot-".into(),
+            staging_dir: std::env::temp_dir(),
+        }
+    }
+}
+
+/// A backup which was taken and stored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Backup {
+    /// Name the backup is stored under
+    pub name: String,
+    /// The snapshot's size, checksum and revision
+    pub info: SnapshotInfo,
+}
+
+type FailureCallback = Arc<dyn Fn(&Error) + Send + Sync>;
+
+/// Takes backups on a schedule, see the [module documentation](self)
+pub struct BackupScheduler<T, S> {
+    maintenance: Maintenance<T>,
+    storage: Arc<S>,
+    options: BackupOptions,
+    on_failure: Option<FailureCallback>,
+}
+
+impl<T, S> fmt::Debug for BackupScheduler<T, S>
+where
+    S: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BackupScheduler")
+            .field("storage", &self.storage)
+            .field("options", &self.options)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T, S> BackupScheduler<T, S>
+where
+    T: Transport,
+    S: BackupStorage + 'static,
+{
+    /// Back up the cluster `client` is connected to into `storage`, as `options` say
+    pub fn new(client: &EtcdClient<T>, storage: S, options: BackupOptions) -> Self {
+        Self {
+            maintenance: client.maintenance(),
+            storage: Arc::new(storage),
+            options,
+            on_failure: None,
+        }
+    }
+
+    /// Call `on_failure` with the error of each scheduled backup or pruning which fails. The
+    /// next backup is still taken at the next interval.
+    pub fn on_failure<F>(mut self, on_failure: F) -> Self
+    where
+        F: Fn(&Error) + Send + Sync + 'static,
+    {
+        self.on_failure = Some(Arc::new(on_failure));
+        self
+    }
+
+    /// The storage backups are kept in
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    /// Take a backup and store it straight away, without pruning older ones
+    pub async fn backup(&self) -> EtcdResult<Backup> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        // Padded so that the names sort in the order the backups were taken
+        let name = format!("{}{:020}.db", self.options.name_prefix, now.as_millis());
+        let staged = self
+            .options
+            .staging_dir
+            .join(format!("{}.{}", name, std::process::id()));
+
+        let stored = async {
+            let info = self.maintenance.save_snapshot(&staged).await?;
+            self.storage.store(&name, &staged).await?;
+            Ok::<_, Error>(info)
+        }
+        .await;
+        let _ = std::fs::remove_file(&staged);
+        Ok(Backup {
+            name,
+            info: stored?,
+        })
+    }
+
+    /// Delete the oldest backups beyond [`BackupOptions::retain`], returning the names of those
+    /// deleted
+    pub async fn prune(&self) -> EtcdResult<Vec<String>> {
+        let retain = match self.options.retain {
+            Some(retain) => retain,
+            None => return Ok(Vec::new()),
+        };
+        let mut names: Vec<_> = self
+            .storage
+            .list()
+            .await?
+            .into_iter()
+            .filter(|name| name.starts_with(&self.options.name_prefix))
+            .collect();
+        names.sort();
+        let excess = names.len().saturating_sub(retain);
+        names.truncate(excess);
+        for name in &names {
+            self.storage.delete(name).await?;
+        }
+        Ok(names)
+    }
+
+    /// Take a backup straight away and another every [`BackupOptions::interval`], pruning
+    /// older ones after each, until the returned task is dropped. Must be called from within a
+    /// tokio runtime.
+    pub fn start(self) -> BackupTask {
+        let (stop, stopped) = oneshot::channel();
+        let clock = self.maintenance.client.config.clock.clone();
+        let backups = async move {
+            loop {
+                let outcome = match self.backup().await {
+                    Ok(_) => self.prune().await.map(|_| ()),
+                    Err(e) => Err(e),
+                };
+                if let (Err(e), Some(on_failure)) = (outcome, &self.on_failure) {
+                    on_failure(&e);
+                }
+                clock.delay_for(self.options.interval).await;
+            }
+        };
+        runtime::spawn(future::select(backups.boxed(), stopped).map(|_| ()));
+        BackupTask { _stop: stop }
+    }
+}
+
+/// Backups being taken on a schedule, see [`BackupScheduler::start`]. Dropping it stops taking
+/// them, abandoning any backup in progress.
+#[derive(Debug)]
+pub struct BackupTask {
+    _stop: oneshot::Sender<()>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::etcdserver;
+    use crate::recording::{Exchange, Replay};
+    use sha2::Digest;
+
+    fn snapshot() -> Exchange {
+        let mut blob = b"not really a bolt database".to_vec();
+        let sha256 = sha2::Sha256::digest(&blob);
+        blob.extend_from_slice(sha256.as_slice());
+        let response = etcdserver::SnapshotResponse {
+            header: Some(etcdserver::ResponseHeader {
+                revision: 12,
+                ..Default::default()
+            }),
+            remaining_bytes: 0,
+            blob,
+        };
+        Exchange::answered("/etcdserverpb.Maintenance/Snapshot", response)
+    }
+
+    #[tokio::test]
+    async fn test_backing_up() {
+        let dir = std::env::temp_dir().join(format!("etcd3-backups-{}", std::process::id()));
+        let replay = Replay::new(vec![snapshot(), snapshot()]);
+        let client = EtcdClient::from_transport(replay.clone());
+        let options = BackupOptions {
+            retain: Some(1),
+            staging_dir: std::env::temp_dir(),
+            ..Default::default()
+        };
+        let scheduler = BackupScheduler::new(&client, FilesystemStorage::new(&dir), options);
+
+        let first = scheduler.backup().await.unwrap();
+        assert_eq!(first.info.revision, Some(12));
+        runtime::delay_for(Duration::from_millis(2)).await;
+        let second = scheduler.backup().await.unwrap();
+        assert!(first.name < second.name);
+        let mut names = scheduler.storage().list().await.unwrap();
+        names.sort();
+        assert_eq!(names, vec![first.name.clone(), second.name.clone()]);
+
+        std::fs::write(dir.join("notes.txt"), b"kept").unwrap();
+        assert_eq!(scheduler.prune().await.unwrap(), vec![first.name]);
+        let mut names = scheduler.storage().list().await.unwrap();
+        names.sort();
+        assert_eq!(names, vec![second.name, "notes.txt".to_string()]);
+
+        // The replay has no snapshot left to answer with
+        let (failed, failure) = oneshot::channel();
+        let failed = std::sync::Mutex::new(Some(failed));
+        let _task = scheduler
+            .on_failure(move |e| {
+                if let Some(failed) = failed.lock().unwrap().take() {
+                    let _ = failed.send(e.to_string());
+                }
+            })
+            .start();
+        assert!(failure.await.unwrap().contains("no recorded response left"));
+        assert_eq!(replay.remaining(), 0);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
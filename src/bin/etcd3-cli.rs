@@ -0,0 +1,238 @@
+//! A small etcdctl, built on the crate's public API with the `cli` feature:
+//!
+//! ```text
+//! cargo run --features cli --bin etcd3-cli -- put foo bar
+//! ```
+//!
+//! It connects to the cluster etcdctl would in the same environment, using the `ETCDCTL_*`
+//! variables, or to the endpoints given with `--endpoints`, and prints in the same format as
+//! etcdctl's default one.
+
+use etcd3::prelude::*;
+use etcd3::EtcdClientBuilder;
+use std::env;
+use std::process;
+
+const USAGE: &str = "usage: etcd3-cli [--endpoints=URL,...] COMMAND [ARGS]
+
+commands:
+    get KEY [--prefix]                  print the value of KEY, or of every key under it
+    put KEY VALUE [--lease=ID]          put VALUE at KEY, attached to the lease in hex
+    del KEY [--prefix]                  delete KEY, or every key under it
+    watch KEY                           print changes to KEY until interrupted
+    lease grant TTL                     grant a lease of TTL seconds
+    lease revoke ID                     revoke a lease
+    lease timetolive ID [--keys]        print the remaining time to live of a lease
+    lease list                          print the ID of every lease
+    lease keep-alive ID                 keep a lease alive until interrupted
+    member list                         print the members of the cluster
+    status                              print the status of each endpoint";
+
+type CliResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+#[tokio::main]
+async fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty() || args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        println!("{}", USAGE);
+        return;
+    }
+    let endpoints = take_option(&mut args, "--endpoints");
+    if let Err(e) = run(endpoints, args).await {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
+
+async fn run(endpoints: Option<String>, mut args: Vec<String>) -> CliResult<()> {
+    let prefix = take_flag(&mut args, "--prefix");
+    let keys = take_flag(&mut args, "--keys");
+    let lease = take_option(&mut args, "--lease");
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let client = builder(endpoints.as_deref())?.connect().await?;
+
+    match args.as_slice() {
+        ["get", key] => {
+            let response = client
+                .range(key.as_bytes(), end(key, prefix).as_deref())
+                .get()
+                .await?;
+            for kv in &response {
+                println!("{}", String::from_utf8_lossy(kv.key()));
+                println!("{}", String::from_utf8_lossy(kv.value()));
+            }
+        }
+        ["put", key, value] => {
+            let options = PutOptions {
+                lease: lease.as_deref().map(parse_lease).transpose()?.unwrap_or(0),
+                ..Default::default()
+            };
+            client.kv().put_with_options(*key, *value, options).await?;
+            println!("OK");
+        }
+        ["del", key] => {
+            let response = client
+                .range(key.as_bytes(), end(key, prefix).as_deref())
+                .delete()
+                .await?;
+            println!("{}", response.deleted());
+        }
+        ["watch", key] => {
+            let mut stream = client.watch().watch(*key).await?;
+            while let Some(response) = stream.message().await? {
+                for event in response.events() {
+                    let kind = match event.kind() {
+                        EventKind::Put => "PUT",
+                        EventKind::Delete => "DELETE",
+                    };
+                    println!("{}", kind);
+                    println!("{}", String::from_utf8_lossy(event.kv().key()));
+                    println!("{}", String::from_utf8_lossy(event.kv().value()));
+                }
+            }
+        }
+        ["lease", "grant", ttl] => {
+            let response = client.lease().grant(ttl.parse()?).await?;
+            println!(
+                "lease {:016x} granted with TTL({}s)",
+                response.id, response.ttl
+            );
+        }
+        ["lease", "revoke", id] => {
+            let id = parse_lease(id)?;
+            client.lease().revoke(id).await?;
+            println!("lease {:016x} revoked", id);
+        }
+        ["lease", "timetolive", id] => {
+            let id = parse_lease(id)?;
+            let response = client.lease().time_to_live(id, keys).await?;
+            if response.ttl == -1 {
+                println!("lease {:016x} already expired", id);
+                return Ok(());
+            }
+            print!(
+                "lease {:016x} granted with TTL({}s), remaining({}s)",
+                id, response.granted_ttl, response.ttl
+            );
+            if keys {
+                let attached: Vec<_> = response
+                    .keys
+                    .iter()
+                    .map(|key| String::from_utf8_lossy(key))
+                    .collect();
+                print!(", attached keys({:?})", attached);
+            }
+            println!();
+        }
+        ["lease", "list"] => {
+            let leases = client.lease().leases().await?;
+            println!("found {} leases", leases.len());
+            for id in leases {
+                println!("{:016x}", id);
+            }
+        }
+        ["lease", "keep-alive", id] => {
+            let id = parse_lease(id)?;
+            loop {
+                let ttl = client.lease().keep_alive_once(id).await?;
+                println!("lease {:016x} keepalived with TTL({})", id, ttl);
+                // Refresh a third of the way through the TTL, as etcdctl does
+                let interval = std::time::Duration::from_secs((ttl.max(3) / 3) as u64);
+                tokio::timer::delay(tokio::clock::now() + interval).await;
+            }
+        }
+        ["member", "list"] => {
+            for member in client.cluster().members().await? {
+                let status = if member.is_learner {
+                    "learner"
+                } else {
+                    "started"
+                };
+                println!(
+                    "{:x}, {}, {}, {}, {}, {}",
+                    member.id,
+                    status,
+                    member.name,
+                    member.peer_ur_ls.join(","),
+                    member.client_ur_ls.join(","),
+                    member.is_learner
+                );
+            }
+        }
+        ["status"] => {
+            for url in client.endpoints() {
+                let endpoint = builder(Some(&url))?.connect().await?;
+                let status = endpoint.maintenance().status().await?;
+                let member_id = status.header.as_ref().map_or(0, |header| header.member_id);
+                println!(
+                    "{}, {:x}, {}, {}, {}, {}, {}",
+                    url,
+                    member_id,
+                    status.version,
+                    status.db_size,
+                    status.leader == member_id,
+                    status.raft_term,
+                    status.raft_index
+                );
+            }
+        }
+        _ => return Err(format!("unknown command {:?}\n\n{}", args.join(" "), USAGE).into()),
+    }
+    Ok(())
+}
+
+/// Builder for the cluster in the environment, or for `endpoints` if given
+fn builder(endpoints: Option<&str>) -> CliResult<EtcdClientBuilder> {
+    if let Some(endpoints) = endpoints {
+        // Read the same way as the other ETCDCTL_ variables, so endpoints without a scheme
+        // use https if TLS is configured
+        env::set_var("ETCDCTL_ENDPOINTS", endpoints);
+        env::remove_var("ETCDCTL_DISCOVERY_SRV");
+    }
+    Ok(EtcdClientBuilder::from_env()?)
+}
+
+/// End of the range starting at `key`: every key it prefixes if `prefix` is set, otherwise
+/// just `key`
+fn end(key: &str, prefix: bool) -> Option<Vec<u8>> {
+    if !prefix {
+        return None;
+    }
+    let mut end = key.as_bytes().to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xff {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    // Every key is prefixed by the empty key, or one made only of 0xff bytes
+    Some(vec![0])
+}
+
+/// Lease ID given in hex, as etcdctl prints them
+fn parse_lease(id: &str) -> CliResult<i64> {
+    u64::from_str_radix(id, 16)
+        .map(|id| id as i64)
+        .map_err(|e| format!("invalid lease ID {:?}: {}", id, e).into())
+}
+
+/// Remove `flag` from `args`, returning whether it was there
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    let before = args.len();
+    args.retain(|arg| arg != flag);
+    args.len() != before
+}
+
+/// Remove `name=VALUE` or `name VALUE` from `args`, returning the value
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let with_value = format!("{}=", name);
+    let index = args
+        .iter()
+        .position(|arg| arg == name || arg.starts_with(&with_value))?;
+    let arg = args.remove(index);
+    match arg.strip_prefix(&with_value) {
+        Some(value) => Some(value.to_string()),
+        None if index < args.len() => Some(args.remove(index)),
+        None => None,
+    }
+}
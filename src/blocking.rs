@@ -0,0 +1,190 @@
+//! A blocking client, for command line tools and code which is not async.
+//!
+//! [`EtcdClient`] wraps the async [`crate::EtcdClient`] and runs it on a runtime of its own,
+//! blocking the calling thread until each operation completes. Background work such as
+//! keep-alives carries on between calls on the runtime's threads.
+//!
+//! The blocking client must not be used from within an async runtime, where blocking would
+//! stall it; use the async client there instead.
+
+#[cfg(feature = "maintenance")]
+use crate::VersionInfo;
+use crate::{DeleteResponse, Error, EtcdClientBuilder, EtcdResult, GetResponse, PutResponse};
+use std::convert::TryInto;
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tonic::codegen::StdError;
+use tonic::transport::channel::Channel;
+use tonic::transport::Endpoint;
+
+/// A client whose operations block until they complete.
+///
+/// Clones are cheap and share the client's connections and runtime.
+#[derive(Clone)]
+pub struct EtcdClient {
+    client: crate::EtcdClient<Channel>,
+    runtime: Arc<Runtime>,
+}
+
+impl fmt::Debug for EtcdClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EtcdClient")
+            .field("client", &self.client)
+            .finish_non_exhaustive()
+    }
+}
+
+impl EtcdClient {
+    /// Connect to a cluster through the single endpoint `dst`, see
+    /// [`crate::EtcdClient::connect`]
+    pub fn connect<D>(dst: D) -> EtcdResult<Self>
+    where
+        D: TryInto<Endpoint>,
+        D::Error: Into<StdError>,
+    {
+        Self::start(crate::EtcdClient::connect(dst))
+    }
+
+    /// Connect to a cluster through any of `endpoints`, see [`crate::EtcdClient::connect_all`]
+    pub fn connect_all<I, S>(endpoints: I) -> EtcdResult<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self::start(crate::EtcdClient::connect_all(endpoints))
+    }
+
+    /// Create a client for the single endpoint `dst` without connecting to it, see
+    /// [`EtcdClientBuilder::connect_lazy`]
+    pub fn connect_lazy<S>(dst: S) -> EtcdResult<Self>
+    where
+        S: Into<String>,
+    {
+        let dst = dst.into();
+        Self::start(async { crate::EtcdClient::connect_lazy(dst) })
+    }
+
+    /// Connect with the configuration of `builder`, for TLS, credentials or timeouts
+    pub fn from_builder(builder: EtcdClientBuilder) -> EtcdResult<Self> {
+        Self::start(builder.connect())
+    }
+
+    /// Start a runtime and connect the client on it with `connect`
+    fn start<F>(connect: F) -> EtcdResult<Self>
+    where
+        F: Future<Output = EtcdResult<crate::EtcdClient<Channel>>>,
+    {
+        let runtime = Runtime::new().map_err(|e| Error::Other(Box::new(e)))?;
+        let client = runtime.block_on(connect)?;
+        Ok(Self {
+            client,
+            runtime: Arc::new(runtime),
+        })
+    }
+
+    /// Run `future` on the client's runtime, blocking until it completes. This is the way to
+    /// reach operations the blocking client does not wrap, through [`async_client`].
+    ///
+    /// [`async_client`]: Self::async_client
+    pub fn block_on<F>(&self, future: F) -> F::Output
+    where
+        F: Future,
+    {
+        self.runtime.block_on(future)
+    }
+
+    /// The async client this client wraps. Its futures must be run with
+    /// [`block_on`](Self::block_on).
+    pub fn async_client(&self) -> &crate::EtcdClient<Channel> {
+        &self.client
+    }
+
+    /// Put `value` at `key`
+    pub fn put<K, V>(&self, key: K, value: V) -> EtcdResult<PutResponse>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        self.block_on(self.client.kv().put(key, value))
+    }
+
+    /// Value of `key`, if it exists
+    pub fn get<K>(&self, key: K) -> EtcdResult<Option<String>>
+    where
+        K: AsRef<[u8]>,
+    {
+        self.block_on(self.client.kv().get(key))
+    }
+
+    /// The keys from `start` up to `end`, or the key `start` alone if there is no `end`
+    pub fn get_range<K>(&self, start: K, end: Option<K>) -> EtcdResult<GetResponse>
+    where
+        K: AsRef<[u8]>,
+    {
+        self.block_on(self.client.range(start, end).get())
+    }
+
+    /// Delete `key`
+    pub fn delete<K>(&self, key: K) -> EtcdResult<DeleteResponse>
+    where
+        K: AsRef<[u8]>,
+    {
+        self.block_on(self.client.kv().delete(key))
+    }
+
+    /// Server and cluster version, see [`crate::Maintenance::version`]
+    #[cfg(feature = "maintenance")]
+    pub fn version(&self) -> EtcdResult<VersionInfo> {
+        self.block_on(self.client.version())
+    }
+
+    /// Close the client, waiting up to `timeout` for requests in flight, see
+    /// [`crate::EtcdClient::close`]
+    pub fn close(self, timeout: Duration) -> EtcdResult<()> {
+        let Self { client, runtime } = self;
+        runtime.block_on(client.close(timeout))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unreachable() {
+        // Nothing listens on port 1, so connecting fails rather than blocking forever
+        assert!(EtcdClient::connect("http://127.0.0.1:1").is_err());
+
+        let client = EtcdClient::connect_lazy("http://127.0.0.1:1").unwrap();
+        let err = client.get("foo").unwrap_err();
+        assert!(matches!(err.root(), Error::Status(_)));
+    }
+
+    #[test]
+    fn test_blocking() {
+        let client = EtcdClient::connect(crate::testing::shared_endpoint()).unwrap();
+        client.put("test_blocking", "bar").unwrap();
+        assert_eq!(
+            client.get("test_blocking").unwrap(),
+            Some("bar".to_string())
+        );
+        let values = client.get_range("test_blocking", None).unwrap();
+        assert_eq!(values.kvs()[0].value(), b"bar");
+        client.delete("test_blocking").unwrap();
+        assert_eq!(client.get("test_blocking").unwrap(), None);
+
+        let clone = client.clone();
+        std::thread::spawn(move || clone.put("test_blocking", "baz").unwrap())
+            .join()
+            .unwrap();
+        assert_eq!(
+            client.get("test_blocking").unwrap(),
+            Some("baz".to_string())
+        );
+        client.delete("test_blocking").unwrap();
+        client.close(Duration::from_secs(1)).unwrap();
+    }
+}
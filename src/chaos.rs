@@ -0,0 +1,307 @@
+//! Faults injected into a client's requests, for testing how an application copes with etcd
+//! being slow, unreachable or failing.
+//!
+//! [`FaultInjection`] is a `tower` layer for
+//! [`EtcdClientBuilder::connect_with_layer`](crate::EtcdClientBuilder::connect_with_layer).
+//! Each request is checked against its [`FaultRule`]s, in order, and the first rule which
+//! matches the request's method and fires at its probability decides what happens to it. The
+//! layer is a handle, so rules can be added and cleared while the client is in use:
+//!
+//! ```no_run
+//! # async fn run() -> etcd3::EtcdResult<()> {
+//! use etcd3::chaos::{Fault, FaultInjection, FaultRule};
+//! use etcd3::EtcdClient;
+//!
+//! let faults = FaultInjection::new();
+//! let client = EtcdClient::builder()
+//!     .endpoint("http://127.0.0.1:2379")
+//!     .connect_with_layer(faults.clone())
+//!     .await?;
+//!
+//! // Half of all puts fail as if the cluster had lost its leader
+//! faults.add(FaultRule {
+//!     method: "KV/Put".into(),
+//!     probability: 0.5,
+//!     fault: Fault::error(tonic::Code::Unavailable, "etcdserver: no leader"),
+//! });
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::runtime;
+use futures::future::{self, BoxFuture, FutureExt};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tonic::codegen::{HyperBody, Service};
+
+/// What happens to a request a rule fires for
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Fault {
+    /// The request is never sent nor answered, as if it was lost on the network. It only
+    /// fails once its timeout passes, if it has one.
+    Drop,
+    /// The request is sent after a delay
+    Delay(Duration),
+    /// The request is not sent, and fails with a gRPC status instead
+    Error {
+        /// The status's code
+        code: tonic::Code,
+        /// The status's message, such as `etcdserver: no leader` for the error the server
+        /// would send
+        message: String,
+    },
+}
+
+impl Fault {
+    /// Fail with the status `code` and `message`
+    pub fn error<S>(code: tonic::Code, message: S) -> Self
+    where
+        S: Into<String>,
+    {
+        Fault::Error {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// When to inject a fault, see [`FaultInjection::add`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaultRule {
+    /// The methods the rule applies to: a method such as `Range`, a service and method such
+    /// as `KV/Range`, every method of a service such as `Lease/*`, or the full path such as
+    /// `/etcdserverpb.KV/Range`. Empty for every method.
+    pub method: String,
+    /// The fraction of matching requests the rule fires for, from 0 to 1
+    pub probability: f64,
+    /// What happens to the requests the rule fires for
+    pub fault: Fault,
+}
+
+impl FaultRule {
+    /// Whether the rule applies to the request to `path`
+    fn matches(&self, path: &str) -> bool {
+        if self.method.is_empty() || self.method == path {
+            return true;
+        }
+        let (service, method) = match path.trim_start_matches('/').split_once('/') {
+            Some(parts) => parts,
+            None => return false,
+        };
+        // Services are named for their package, such as etcdserverpb.KV
+        let service = service.rsplit('.').next().unwrap_or(service);
+        match self.method.split_once('/') {
+            Some((rule_service, "*")) => rule_service == service,
+            Some((rule_service, rule_method)) => rule_service == service && rule_method == method,
+            None => self.method == method,
+        }
+    }
+}
+
+/// A `tower` layer injecting faults into the requests sent through it. Clones share their
+/// rules.
+#[derive(Debug, Clone, Default)]
+pub struct FaultInjection {
+    rules: Arc<Mutex<Vec<FaultRule>>>,
+    injected: Arc<AtomicU64>,
+}
+
+impl FaultInjection {
+    /// A layer which injects no faults until rules are added
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inject faults according to `rule`, after the rules already added
+    pub fn add(&self, rule: FaultRule) {
+        self.rules.lock().unwrap().push(rule);
+    }
+
+    /// Stop injecting faults, so requests are sent as usual again
+    pub fn clear(&self) {
+        self.rules.lock().unwrap().clear();
+    }
+
+    /// How many faults have been injected so far
+    pub fn injected(&self) -> u64 {
+        self.injected.load(Ordering::Relaxed)
+    }
+
+    /// The fault to inject into the request to `path`, if any
+    fn fault(&self, path: &str) -> Option<Fault> {
+        let rules = self.rules.lock().unwrap();
+        let fault = rules
+            .iter()
+            .filter(|rule| rule.matches(path))
+            .find(|rule| fires(rule.probability))
+            .map(|rule| rule.fault.clone());
+        if fault.is_some() {
+            self.injected.fetch_add(1, Ordering::Relaxed);
+        }
+        fault
+    }
+}
+
+/// Whether an event of `probability` happens this time
+fn fires(probability: f64) -> bool {
+    if probability >= 1.0 {
+        return true;
+    }
+    (crate::random() as f64 / u64::MAX as f64) < probability
+}
+
+impl<S> tower_layer::Layer<S> for FaultInjection {
+    type Service = FaultInjected<S>;
+
+    fn layer(&self, inner: S) -> FaultInjected<S> {
+        FaultInjected {
+            inner,
+            faults: self.clone(),
+        }
+    }
+}
+
+/// A service with faults injected into its requests, see [`FaultInjection`]
+#[derive(Debug, Clone)]
+pub struct FaultInjected<S> {
+    inner: S,
+    faults: FaultInjection,
+}
+
+impl<S, B> Service<http::Request<B>> for FaultInjected<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<HyperBody>> + Clone + Send + 'static,
+    S::Future: Send,
+    S::Error: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<S::Response, S::Error>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // Faults are injected whether or not the inner service is ready, so it is only made
+        // ready for the requests which are sent to it
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let delay = match self.faults.fault(request.uri().path()) {
+            None => Duration::from_secs(0),
+            Some(Fault::Delay(delay)) => delay,
+            Some(Fault::Drop) => return future::pending().boxed(),
+            Some(Fault::Error { code, message }) => {
+                return future::ok(status(code, &message)).boxed();
+            }
+        };
+        let mut inner = self.inner.clone();
+        async move {
+            if delay > Duration::from_secs(0) {
+                runtime::delay_for(delay).await;
+            }
+            future::poll_fn(|cx| inner.poll_ready(cx)).await?;
+            inner.call(request).await
+        }
+        .boxed()
+    }
+}
+
+/// A response carrying only the status `code` and `message`, as servers send for requests
+/// which fail before anything is answered
+fn status(code: tonic::Code, message: &str) -> http::Response<HyperBody> {
+    let mut response = http::Response::new(HyperBody::empty());
+    let headers = response.headers_mut();
+    headers.insert(
+        "content-type",
+        http::HeaderValue::from_static("application/grpc"),
+    );
+    headers.insert("grpc-status", http::HeaderValue::from(code as i32));
+    // The message is percent-decoded, so only the escape character needs escaping
+    let message = message.replace('%', "%25");
+    if let Ok(message) = http::HeaderValue::from_str(&message) {
+        headers.insert("grpc-message", message);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EtcdClient, RetryPolicy};
+    use std::time::Instant;
+
+    fn rule(method: &str) -> FaultRule {
+        FaultRule {
+            method: method.into(),
+            probability: 1.0,
+            fault: Fault::Drop,
+        }
+    }
+
+    #[test]
+    fn test_matching_methods() {
+        let range = "/etcdserverpb.KV/Range";
+        assert!(rule("").matches(range));
+        assert!(rule("Range").matches(range));
+        assert!(rule("KV/Range").matches(range));
+        assert!(rule("KV/*").matches(range));
+        assert!(rule(range).matches(range));
+        assert!(!rule("DeleteRange").matches(range));
+        assert!(!rule("Lease/*").matches(range));
+        assert!(!rule("Watch/Range").matches(range));
+    }
+
+    #[tokio::test]
+    async fn test_injecting_faults() {
+        // Nothing listens on port 1, so requests which are sent fail to connect
+        let faults = FaultInjection::new();
+        let client = EtcdClient::builder()
+            .endpoint("http://127.0.0.1:1")
+            .retry(RetryPolicy::disabled())
+            .timeout(Duration::from_secs(5))
+            .connect_with_layer(faults.clone())
+            .await
+            .unwrap();
+
+        faults.add(FaultRule {
+            probability: 0.0,
+            ..rule("KV/Range")
+        });
+        faults.add(FaultRule {
+            fault: Fault::error(tonic::Code::Unavailable, "etcdserver: no leader"),
+            ..rule("KV/Range")
+        });
+        let err = client.range("foo", None).get().await.unwrap_err();
+        assert!(matches!(err.root(), crate::Error::NoLeader));
+        assert_eq!(faults.injected(), 1);
+
+        faults.clear();
+        faults.add(FaultRule {
+            fault: Fault::Delay(Duration::from_millis(50)),
+            ..rule("Put")
+        });
+        let started = Instant::now();
+        assert!(client.range("foo", None).put("bar").await.is_err());
+        assert!(started.elapsed() >= Duration::from_millis(50));
+
+        faults.add(rule("DeleteRange"));
+        let deleted = client
+            .range("foo", None)
+            .timeout(Duration::from_millis(50))
+            .delete()
+            .await;
+        assert!(matches!(
+            deleted.unwrap_err().root(),
+            crate::Error::Timeout { .. }
+        ));
+        assert_eq!(faults.injected(), 3);
+
+        faults.clear();
+        let err = client.range("foo", None).get().await.unwrap_err();
+        assert!(!matches!(err.root(), crate::Error::NoLeader));
+        assert_eq!(faults.injected(), 3);
+    }
+}
@@ -0,0 +1,463 @@
+//! Values larger than etcd accepts in a single request, split across sub-keys.
+//!
+//! etcd refuses requests bigger than its `--max-request-bytes`, 1.5 MiB by default, so a
+//! value which does not fit is stored by [`Chunked`] as a manifest at its key and parts of at
+//! most [`ChunkedOptions::chunk_size`] bytes at `key/<generation>/part<N>`. The parts of each
+//! write are put first, each in a request of its own, and the manifest is then switched to
+//! them in a transaction which also deletes the parts it replaces, so readers see either the
+//! old value or the new one. Reads check the reassembled value against the length and sha256
+//! in the manifest:
+//!
+//! ```no_run
+//! # async fn run(client: etcd3::EtcdClient<tonic::transport::Channel>) -> etcd3::EtcdResult<()> {
+//! use etcd3::chunked::{Chunked, ChunkedOptions};
+//!
+//! let models = Chunked::new(&client, ChunkedOptions::default());
+//! let model = std::fs::read("model.bin")?;
+//! models.put("models/current", &model).await?;
+//! assert_eq!(models.get("models/current").await?, Some(model));
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Keys, and the parts under them, are inside the client's
+//! [namespace](crate::EtcdClient::namespaced). Parts written by a client which fails before
+//! switching the manifest to them are left behind under a generation no manifest refers to.
+
+use crate::concurrency::{prefix_end, read_revision};
+use crate::redact::Key;
+use crate::{client, etcdserver, mvccpb, Error, EtcdClient, EtcdResult, Transport};
+use sha2::{Digest, Sha256};
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// First word of every manifest, with the version of the layout
+const MANIFEST_MAGIC: &str = "etcd3-chunked/1";
+
+/// Times a value is read again after one of its parts went missing, because the value was
+/// replaced while it was read
+const READ_ATTEMPTS: usize = 3;
+
+/// How values are split by [`Chunked`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkedOptions {
+    /// Largest part a value is split into, which with its key must stay under the cluster's
+    /// `--max-request-bytes`. 1 MiB by default.
+    pub chunk_size: usize,
+}
+
+impl Default for ChunkedOptions {
+    fn default() -> Self {
+        Self {
+            chunk_size: 1024 * 1024,
+        }
+    }
+}
+
+/// Where the parts of a value are and what they add up to
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Manifest {
+    /// Names the write the parts belong to, so a new value never overwrites the parts of the
+    /// old one while it is read
+    generation: String,
+    parts: usize,
+    len: usize,
+    /// Hex sha256 of the whole value
+    sha256: String,
+}
+
+impl Manifest {
+    fn new(generation: String, value: &[u8], chunk_size: usize) -> Self {
+        Self {
+            generation,
+            parts: value.len().div_ceil(chunk_size),
+            len: value.len(),
+            sha256: hex_sha256(value),
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        format!(
+            "{} {} {} {} {}",
+            MANIFEST_MAGIC, self.generation, self.parts, self.len, self.sha256
+        )
+        .into_bytes()
+    }
+
+    /// The manifest stored at `key` as `value`
+    fn decode(key: &[u8], value: &[u8]) -> EtcdResult<Self> {
+        let invalid =
+            || -> Error { format!("{:?} does not hold a chunked value", Key(key)).into() };
+        let value = std::str::from_utf8(value).map_err(|_| invalid())?;
+        let mut words = value.split(' ');
+        if words.next() != Some(MANIFEST_MAGIC) {
+            return Err(invalid());
+        }
+        let mut next = || words.next().ok_or_else(invalid);
+        let generation = next()?.to_string();
+        let parts = next()?.parse().map_err(|_| invalid())?;
+        let len = next()?.parse().map_err(|_| invalid())?;
+        let sha256 = next()?.to_string();
+        Ok(Self {
+            generation,
+            parts,
+            len,
+            sha256,
+        })
+    }
+
+    /// Prefix of the keys of the parts for the value at `key`
+    fn prefix(&self, key: &[u8]) -> Vec<u8> {
+        let mut prefix = key.to_vec();
+        prefix.extend(format!("/{}/", self.generation).into_bytes());
+        prefix
+    }
+
+    /// Key of part `n` for the value at `key`
+    fn part(&self, key: &[u8], n: usize) -> Vec<u8> {
+        let mut part = self.prefix(key);
+        part.extend(format!("part{}", n).into_bytes());
+        part
+    }
+}
+
+fn hex_sha256(value: &[u8]) -> String {
+    Sha256::digest(value)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// A generation no other write picks, from the time and the randomly keyed hasher of the
+/// standard library
+fn new_generation() -> String {
+    let mut hasher = RandomState::new().build_hasher();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    hasher.write_u128(now.as_nanos());
+    format!("{:016x}", hasher.finish())
+}
+
+/// Values split across sub-keys, see the [module documentation](self)
+pub struct Chunked<T> {
+    kv_client: client::KvClient<T>,
+    /// Prefix of every key, from the client's namespace
+    namespace: Arc<[u8]>,
+    options: ChunkedOptions,
+    /// Whether values are first read serializably
+    serializable: bool,
+}
+
+impl<T> fmt::Debug for Chunked<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Chunked")
+            .field("options", &self.options)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: Transport> Chunked<T> {
+    /// Store values through `client`, split as `options` say
+    pub fn new(client: &EtcdClient<T>, options: ChunkedOptions) -> Self {
+        Self {
+            kv_client: client.kv_client(),
+            namespace: client.namespace.clone(),
+            options,
+            serializable: client.serializable_reads(),
+        }
+    }
+
+    /// Set `key` to `value`, replacing and deleting the parts of any value it held, and
+    /// return the revision the value was written at
+    pub async fn put<K, V>(&self, key: K, value: V) -> EtcdResult<i64>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        let (key, value) = (&self.namespaced(key.as_ref())[..], value.as_ref());
+        let chunk_size = self.options.chunk_size.max(1);
+        let manifest = Manifest::new(new_generation(), value, chunk_size);
+        for (n, chunk) in value.chunks(chunk_size).enumerate() {
+            let request = etcdserver::PutRequest {
+                key: manifest.part(key, n),
+                value: chunk.to_vec(),
+                ..Default::default()
+            };
+            if let Err(e) = self.kv_client.clone().put(request).await {
+                self.delete_parts(key, &manifest).await;
+                return Err(e.into());
+            }
+        }
+        match self.switch(key, Some(&manifest)).await {
+            Ok(switched) => Ok(switched.0),
+            Err(e) => {
+                self.delete_parts(key, &manifest).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Value of `key` put by [`put`](Self::put), if it is set. Fails if the key holds a value
+    /// which was not, or if the parts do not add up to the value in the manifest.
+    pub async fn get<K>(&self, key: K) -> EtcdResult<Option<Vec<u8>>>
+    where
+        K: AsRef<[u8]>,
+    {
+        let key = &self.namespaced(key.as_ref())[..];
+        for attempt in 0..READ_ATTEMPTS {
+            // A part missing from a serializable read may only not have reached the member
+            // yet, so values are read again linearizably
+            let serializable = self.serializable && attempt == 0;
+            let manifest = match self.read(key.to_vec(), serializable).await? {
+                Some(kv) => Manifest::decode(key, &kv.value)?,
+                None => return Ok(None),
+            };
+            if let Some(value) = self.assemble(key, &manifest, serializable).await? {
+                return Ok(Some(value));
+            }
+        }
+        Err(format!("{:?} kept changing while it was read", Key(key)).into())
+    }
+
+    /// Delete `key` and its parts, returning whether it was set
+    pub async fn delete<K>(&self, key: K) -> EtcdResult<bool>
+    where
+        K: AsRef<[u8]>,
+    {
+        Ok(self.switch(&self.namespaced(key.as_ref()), None).await?.1)
+    }
+
+    /// `key` inside the client's namespace
+    fn namespaced(&self, key: &[u8]) -> Vec<u8> {
+        let mut namespaced = self.namespace.to_vec();
+        namespaced.extend(key);
+        namespaced
+    }
+
+    /// Point `key` at the parts of `manifest`, or delete it if there is none, deleting the
+    /// parts it pointed at before. Returns the revision written at and whether the key was
+    /// set.
+    async fn switch(&self, key: &[u8], manifest: Option<&Manifest>) -> EtcdResult<(i64, bool)> {
+        loop {
+            let current = self.read(key.to_vec(), false).await?;
+            let mod_revision = current.as_ref().map_or(0, |kv| kv.mod_revision);
+            let mut ops = vec![match manifest {
+                Some(manifest) => put(key.to_vec(), manifest.encode()),
+                None => delete(key.to_vec(), Vec::new()),
+            }];
+            // A key holding a value which is not chunked has no parts to delete
+            if let Some(Ok(old)) = current.as_ref().map(|kv| Manifest::decode(key, &kv.value)) {
+                let prefix = old.prefix(key);
+                let end = prefix_end(&prefix);
+                ops.push(delete(prefix, end));
+            }
+            let request = etcdserver::TxnRequest {
+                compare: vec![etcdserver::Compare {
+                    result: etcdserver::compare::CompareResult::Equal as i32,
+                    target: etcdserver::compare::CompareTarget::Mod as i32,
+                    key: key.to_vec(),
+                    target_union: Some(etcdserver::compare::TargetUnion::ModRevision(mod_revision)),
+                    range_end: Vec::new(),
+                }],
+                success: ops,
+                failure: Vec::new(),
+            };
+            let response = self.kv_client.clone().txn(request).await?.into_inner();
+            if response.succeeded {
+                let revision = read_revision(response.header, "txn")?;
+                return Ok((revision, current.is_some()));
+            }
+        }
+    }
+
+    /// The parts of `manifest` put together, or `None` if one of them is gone because the
+    /// value was replaced as they were read
+    async fn assemble(
+        &self,
+        key: &[u8],
+        manifest: &Manifest,
+        serializable: bool,
+    ) -> EtcdResult<Option<Vec<u8>>> {
+        let mut value = Vec::with_capacity(manifest.len);
+        for n in 0..manifest.parts {
+            match self.read(manifest.part(key, n), serializable).await? {
+                Some(kv) => value.extend(kv.value),
+                None => return Ok(None),
+            }
+        }
+        if value.len() != manifest.len || hex_sha256(&value) != manifest.sha256 {
+            return Err(format!("{:?} failed its sha256 integrity check", Key(key)).into());
+        }
+        Ok(Some(value))
+    }
+
+    async fn read(&self, key: Vec<u8>, serializable: bool) -> EtcdResult<Option<mvccpb::KeyValue>> {
+        let request = etcdserver::RangeRequest {
+            key,
+            serializable,
+            ..Default::default()
+        };
+        let response = self.kv_client.clone().range(request).await?;
+        Ok(response.into_inner().kvs.into_iter().next())
+    }
+
+    /// Delete the parts of `manifest` after failing to switch the key to them, leaving them
+    /// behind if that fails too
+    async fn delete_parts(&self, key: &[u8], manifest: &Manifest) {
+        let prefix = manifest.prefix(key);
+        let request = etcdserver::DeleteRangeRequest {
+            range_end: prefix_end(&prefix),
+            key: prefix,
+            ..Default::default()
+        };
+        let _ = self.kv_client.clone().delete_range(request).await;
+    }
+}
+
+fn put(key: Vec<u8>, value: Vec<u8>) -> etcdserver::RequestOp {
+    let put = etcdserver::PutRequest {
+        key,
+        value,
+        ..Default::default()
+    };
+    etcdserver::RequestOp {
+        request: Some(etcdserver::request_op::Request::RequestPut(put)),
+    }
+}
+
+fn delete(key: Vec<u8>, range_end: Vec<u8>) -> etcdserver::RequestOp {
+    let delete = etcdserver::DeleteRangeRequest {
+        key,
+        range_end,
+        ..Default::default()
+    };
+    etcdserver::RequestOp {
+        request: Some(etcdserver::request_op::Request::RequestDeleteRange(delete)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recording::{Exchange, Replay};
+
+    fn range(kvs: Vec<(Vec<u8>, Vec<u8>)>) -> Exchange {
+        let response = etcdserver::RangeResponse {
+            header: Some(Default::default()),
+            count: kvs.len() as i64,
+            kvs: kvs
+                .into_iter()
+                .map(|(key, value)| mvccpb::KeyValue {
+                    key,
+                    value,
+                    mod_revision: 4,
+                    ..Default::default()
+                })
+                .collect(),
+            more: false,
+        };
+        Exchange::answered("/etcdserverpb.KV/Range", response)
+    }
+
+    fn txn(succeeded: bool) -> Exchange {
+        let response = etcdserver::TxnResponse {
+            header: Some(etcdserver::ResponseHeader {
+                revision: 9,
+                ..Default::default()
+            }),
+            succeeded,
+            responses: Vec::new(),
+        };
+        Exchange::answered("/etcdserverpb.KV/Txn", response)
+    }
+
+    #[test]
+    fn test_manifests() {
+        let manifest = Manifest::new("0a".into(), b"hello", 2);
+        assert_eq!(manifest.parts, 3);
+        assert_eq!(
+            Manifest::decode(b"k", &manifest.encode()).unwrap(),
+            manifest
+        );
+        assert_eq!(manifest.part(b"k", 2), b"k/0a/part2".to_vec());
+        assert_eq!(Manifest::new("0a".into(), b"", 2).parts, 0);
+        assert!(Manifest::decode(b"k", b"hello").is_err());
+        assert!(Manifest::decode(b"k", b"etcd3-chunked/1 0a 3").is_err());
+        assert_ne!(new_generation(), new_generation());
+    }
+
+    #[tokio::test]
+    async fn test_chunking() {
+        let manifest = Manifest::new("0a".into(), b"hello", 2);
+        let parts = |last: &[u8]| {
+            vec![
+                range(vec![(b"k".to_vec(), manifest.encode())]),
+                range(vec![(manifest.part(b"k", 0), b"he".to_vec())]),
+                range(vec![(manifest.part(b"k", 1), b"ll".to_vec())]),
+                range(vec![(manifest.part(b"k", 2), last.to_vec())]),
+            ]
+        };
+        let put = Exchange::answered("/etcdserverpb.KV/Put", etcdserver::PutResponse::default());
+        let mut exchanges = vec![put.clone(), put.clone(), put];
+        // The first switch loses a race with another writer
+        exchanges.extend(vec![range(Vec::new()), txn(false)]);
+        exchanges.extend(vec![
+            range(vec![(b"k".to_vec(), manifest.encode())]),
+            txn(true),
+        ]);
+        exchanges.extend(parts(b"o"));
+        exchanges.extend(parts(b"x"));
+        // The value is replaced while it is read
+        exchanges.extend(vec![
+            range(vec![(b"k".to_vec(), manifest.encode())]),
+            range(Vec::new()),
+        ]);
+        exchanges.extend(parts(b"o"));
+        exchanges.extend(vec![
+            range(vec![(b"k".to_vec(), b"plain".to_vec())]),
+            txn(true),
+        ]);
+        exchanges.push(range(Vec::new()));
+        let replay = Replay::new(exchanges);
+        let client = EtcdClient::from_transport(replay.clone());
+        let chunked = Chunked::new(&client, ChunkedOptions { chunk_size: 2 });
+
+        assert_eq!(chunked.put("k", b"world").await.unwrap(), 9);
+        assert_eq!(chunked.get("k").await.unwrap(), Some(b"hello".to_vec()));
+        let corrupt = chunked.get("k").await.unwrap_err();
+        assert!(corrupt.to_string().contains("integrity"));
+        assert_eq!(chunked.get("k").await.unwrap(), Some(b"hello".to_vec()));
+        assert!(chunked.delete("k").await.unwrap());
+        assert_eq!(chunked.get("k").await.unwrap(), None);
+        assert_eq!(replay.remaining(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_chunked_namespace() {
+        let client = crate::testing::namespace().await.unwrap();
+        let chunked = Chunked::new(&client, ChunkedOptions { chunk_size: 2 });
+        chunked.put("test_chunked", b"hello").await.unwrap();
+        assert_eq!(
+            chunked.get("test_chunked").await.unwrap(),
+            Some(b"hello".to_vec())
+        );
+
+        // The manifest and its parts are all inside the namespace
+        let outside = crate::testing::shared_client().await.unwrap();
+        let prefix = client.key("test_chunked");
+        let end = prefix_end(prefix.as_bytes());
+        let end = String::from_utf8(end).unwrap();
+        let keys = outside
+            .range(prefix.as_str(), Some(end.as_str()))
+            .get()
+            .await;
+        assert_eq!(keys.unwrap().count(), 4);
+
+        assert!(chunked.delete("test_chunked").await.unwrap());
+        assert_eq!(client.kv().get_bytes("test_chunked").await.unwrap(), None);
+    }
+}
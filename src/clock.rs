@@ -0,0 +1,280 @@
+//! The clock a client takes the time from, so that lease keep-alives, retry backoff and
+//! timeouts can be tested without waiting for them, see
+//! [`EtcdClientBuilder::clock`](crate::EtcdClientBuilder::clock).
+//!
+//! Clients use the [`SystemClock`] unless given another one. A [`MockClock`] only moves when
+//! it is told to, waking the delays which are due:
+//!
+//! ```
+//! use etcd3::clock::{Clock, MockClock};
+//! use std::time::Duration;
+//!
+//! let clock = MockClock::new();
+//! let started = clock.now();
+//! clock.advance(Duration::from_secs(60));
+//! assert_eq!(clock.now() - started, Duration::from_secs(60));
+//! ```
+
+use crate::runtime::{self, Delay, Elapsed, Interval};
+use futures::future::{self, Either};
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+/// A source of the current time and of delays until a time
+pub trait Clock: Send + Sync + 'static {
+    /// The current time
+    fn now(&self) -> Instant;
+
+    /// A future which completes once the clock reaches `deadline`
+    fn delay_until(&self, deadline: Instant) -> Delay;
+
+    /// A future which completes once `duration` has passed on the clock
+    fn delay_for(&self, duration: Duration) -> Delay {
+        self.delay_until(self.now() + duration)
+    }
+}
+
+/// The system's monotonic clock, with delays from the installed
+/// [`Runtime`](crate::runtime::Runtime)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn delay_until(&self, deadline: Instant) -> Delay {
+        runtime::delay_until(deadline)
+    }
+}
+
+/// A clock which stands still until it is [advanced](Self::advance). Clones share the time.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    state: Arc<Mutex<MockState>>,
+}
+
+#[derive(Debug)]
+struct MockState {
+    now: Instant,
+    /// Wakers of the delays still waiting, by delay
+    sleepers: HashMap<u64, (Instant, Waker)>,
+    next_id: u64,
+}
+
+impl MockClock {
+    /// A clock starting at the current system time
+    pub fn new() -> Self {
+        let state = MockState {
+            now: Instant::now(),
+            sleepers: HashMap::new(),
+            next_id: 0,
+        };
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// Move the clock on by `duration`, waking every delay which is due by then
+    pub fn advance(&self, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.now += duration;
+        let now = state.now;
+        let mut due = Vec::new();
+        state.sleepers.retain(|_, (deadline, waker)| {
+            if *deadline > now {
+                return true;
+            }
+            due.push(waker.clone());
+            false
+        });
+        drop(state);
+        due.into_iter().for_each(Waker::wake);
+    }
+
+    /// How many delays are waiting for the clock, to tell when a task has gone to sleep
+    pub fn sleeping(&self) -> usize {
+        self.state.lock().unwrap().sleepers.len()
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.state.lock().unwrap().now
+    }
+
+    fn delay_until(&self, deadline: Instant) -> Delay {
+        let id = {
+            let mut state = self.state.lock().unwrap();
+            state.next_id += 1;
+            state.next_id
+        };
+        Box::pin(MockDelay {
+            clock: self.clone(),
+            id,
+            deadline,
+        })
+    }
+}
+
+/// A delay on a [`MockClock`]
+struct MockDelay {
+    clock: MockClock,
+    id: u64,
+    deadline: Instant,
+}
+
+impl Future for MockDelay {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.clock.state.lock().unwrap();
+        if state.now >= self.deadline {
+            state.sleepers.remove(&self.id);
+            return Poll::Ready(());
+        }
+        let sleeper = (self.deadline, cx.waker().clone());
+        state.sleepers.insert(self.id, sleeper);
+        Poll::Pending
+    }
+}
+
+impl Drop for MockDelay {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.clock.state.lock() {
+            state.sleepers.remove(&self.id);
+        }
+    }
+}
+
+/// The clock shared by a client and everything created from it
+#[derive(Clone)]
+pub(crate) struct SharedClock(Arc<dyn Clock>);
+
+impl SharedClock {
+    pub(crate) fn new<C>(clock: C) -> Self
+    where
+        C: Clock,
+    {
+        Self(Arc::new(clock))
+    }
+
+    pub(crate) fn now(&self) -> Instant {
+        self.0.now()
+    }
+
+    pub(crate) fn delay_for(&self, duration: Duration) -> Delay {
+        self.0.delay_for(duration)
+    }
+
+    /// `future`'s output, unless it does not complete before the clock reaches `deadline`
+    pub(crate) async fn timeout_at<F>(
+        &self,
+        future: F,
+        deadline: Instant,
+    ) -> Result<F::Output, Elapsed>
+    where
+        F: Future,
+    {
+        futures::pin_mut!(future);
+        match future::select(future, self.0.delay_until(deadline)).await {
+            Either::Left((output, _)) => Ok(output),
+            Either::Right(_) => Err(Elapsed),
+        }
+    }
+
+    /// `future`'s output, unless it does not complete within `timeout` on the clock
+    pub(crate) async fn timeout<F>(
+        &self,
+        future: F,
+        timeout: Duration,
+    ) -> Result<F::Output, Elapsed>
+    where
+        F: Future,
+    {
+        self.timeout_at(future, self.now() + timeout).await
+    }
+
+    /// Ticks every `period` on the clock, starting one period from now
+    pub(crate) fn interval(&self, period: Duration) -> Interval {
+        let clock = self.0.clone();
+        Interval::with(period, self.now(), move |deadline| {
+            clock.delay_until(deadline)
+        })
+    }
+}
+
+impl Default for SharedClock {
+    fn default() -> Self {
+        Self::new(SystemClock)
+    }
+}
+
+impl fmt::Debug for SharedClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Clock")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+
+    #[test]
+    fn test_mock_clock() {
+        let clock = MockClock::new();
+        let started = clock.now();
+        let mut delay = clock.delay_for(Duration::from_secs(10));
+        assert!(delay.as_mut().now_or_never().is_none());
+        assert_eq!(clock.sleeping(), 1);
+
+        clock.advance(Duration::from_secs(9));
+        assert!(delay.as_mut().now_or_never().is_none());
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(clock.sleeping(), 0);
+        assert!(delay.now_or_never().is_some());
+        assert_eq!(clock.now(), started + Duration::from_secs(10));
+
+        // Dropped delays stop waiting
+        let mut delay = clock.delay_for(Duration::from_secs(1));
+        assert!(delay.as_mut().now_or_never().is_none());
+        drop(delay);
+        assert_eq!(clock.sleeping(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_mock_timers() {
+        let mock = MockClock::new();
+        let clock = SharedClock::new(mock.clone());
+        let never = future::pending::<()>();
+        let timeout = clock.timeout(never, Duration::from_secs(3600));
+        let advance = async {
+            while mock.sleeping() == 0 {
+                runtime::delay_for(Duration::from_millis(1)).await;
+            }
+            mock.advance(Duration::from_secs(3600));
+        };
+        let (timed_out, ()) = future::join(timeout, advance).await;
+        assert_eq!(timed_out, Err(Elapsed));
+
+        let started = mock.now();
+        let mut ticks = clock.interval(Duration::from_secs(60));
+        mock.advance(Duration::from_secs(150));
+        assert_eq!(ticks.next().await, Some(started + Duration::from_secs(60)));
+        assert_eq!(ticks.next().await, Some(started + Duration::from_secs(120)));
+        assert!(ticks.next().now_or_never().is_none());
+    }
+}
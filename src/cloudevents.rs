@@ -0,0 +1,234 @@
+//! Watch events as [CloudEvents](https://cloudevents.io), for forwarding changes to event buses
+//! which speak them.
+//!
+//! [`CloudEvent::from_watch_event`] converts an event into a CloudEvent of the 1.0
+//! specification, which serializes as the event in its JSON format:
+//!
+//! ```no_run
+//! # async fn run(client: etcd3::EtcdClient<tonic::transport::Channel>) -> etcd3::EtcdResult<()> {
+//! use etcd3::cloudevents::{CloudEvent, CloudEventOptions};
+//!
+//! let options = CloudEventOptions {
+//!     source: "//etcd.example.com/deployments".into(),
+//!     ..Default::default()
+//! };
+//! let mut changes = client.watch().watch("deployments/web").await?;
+//! while let Some(response) = changes.message().await? {
+//!     for event in CloudEvent::from_watch_response(&response, &options) {
+//!         println!("{}", event.to_json());
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The event's type is the kind of change, `io.etcd.kv.put` or `io.etcd.kv.delete` by default,
+//! and its subject the key. Values which are JSON are carried as JSON data, other UTF-8 values
+//! as text, and the rest base64 encoded. The key's revisions and lease are carried as extension
+//! attributes, and the ID, made of the revision and the key, is unique to the change.
+
+use crate::{EventKind, WatchEvent, WatchResponse};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// The CloudEvents specification version of the events converted
+pub const SPEC_VERSION: &str = "1.0";
+
+/// How watch events are converted into CloudEvents
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloudEventOptions {
+    /// The events' `source`, identifying the cluster or the keys watched. `etcd` by default.
+    pub source: String,
+    /// Prefix of the events' `type`, followed by `.put` or `.delete`. `io.etcd.kv` by default.
+    pub type_prefix: String,
+}
+
+impl Default for CloudEventOptions {
+    fn default() -> Self {
+        Self {
+            source: "etcd".into(),
+            type_prefix: "io.etcd.kv".into(),
+        }
+    }
+}
+
+/// A change to a key as a CloudEvent, serializing as the event in the JSON format
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CloudEvent {
+    /// Version of the CloudEvents specification, [`SPEC_VERSION`]
+    pub specversion: String,
+    /// ID of the change, its revision and key
+    pub id: String,
+    /// Where the change was made, see [`CloudEventOptions::source`]
+    pub source: String,
+    /// Kind of change, see [`CloudEventOptions::type_prefix`]
+    pub r#type: String,
+    /// The key changed, base64 encoded if it is not UTF-8, as `subjectencoding` then tells
+    pub subject: String,
+    /// `base64` if the subject is the base64 encoded key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subjectencoding: Option<String>,
+    /// Media type of the data: `application/json`, `text/plain` or `application/octet-stream`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub datacontenttype: Option<String>,
+    /// The value put, if it is JSON or UTF-8 text
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+    /// The value put, base64 encoded, if it is neither JSON nor UTF-8 text
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_base64: Option<String>,
+    /// Revision of the change
+    pub revision: i64,
+    /// Revision the key was created at, for puts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub createrevision: Option<i64>,
+    /// Number of times the key was modified since it was created, for puts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<i64>,
+    /// ID of the lease the key is attached to, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lease: Option<i64>,
+}
+
+impl CloudEvent {
+    /// `event` as a CloudEvent, converted as `options` say
+    pub fn from_watch_event(event: &WatchEvent, options: &CloudEventOptions) -> Self {
+        let kv = event.kv();
+        let (subject, subjectencoding) = match std::str::from_utf8(kv.key()) {
+            Ok(key) => (key.to_string(), None),
+            Err(_) => (base64::encode(kv.key()), Some("base64".to_string())),
+        };
+        let kind = match event.kind() {
+            EventKind::Put => "put",
+            EventKind::Delete => "delete",
+        };
+        let mut cloud_event = Self {
+            specversion: SPEC_VERSION.into(),
+            id: format!("{}:{}", kv.mod_revision(), subject),
+            source: options.source.clone(),
+            r#type: format!("{}.{}", options.type_prefix, kind),
+            subject,
+            subjectencoding,
+            datacontenttype: None,
+            data: None,
+            data_base64: None,
+            revision: kv.mod_revision(),
+            createrevision: None,
+            version: None,
+            lease: kv.lease(),
+        };
+        if event.kind() == EventKind::Put {
+            cloud_event.createrevision = Some(kv.create_revision());
+            cloud_event.version = Some(kv.version());
+            let (content_type, data, data_base64) = data(kv.value());
+            cloud_event.datacontenttype = Some(content_type.into());
+            cloud_event.data = data;
+            cloud_event.data_base64 = data_base64;
+        }
+        cloud_event
+    }
+
+    /// Every event of `response` as a CloudEvent, in the order the changes were made
+    pub fn from_watch_response(response: &WatchResponse, options: &CloudEventOptions) -> Vec<Self> {
+        response
+            .events()
+            .iter()
+            .map(|event| Self::from_watch_event(event, options))
+            .collect()
+    }
+
+    /// The event in the CloudEvents JSON format
+    pub fn to_json(&self) -> String {
+        // The event is made of strings, numbers and JSON values, which always serialize
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+/// Media type of `value`, with its data and base64 encoded data
+fn data(value: &[u8]) -> (&'static str, Option<Value>, Option<String>) {
+    if let Ok(json) = serde_json::from_slice::<Value>(value) {
+        return ("application/json", Some(json), None);
+    }
+    match std::str::from_utf8(value) {
+        Ok(text) => ("text/plain", Some(Value::String(text.into())), None),
+        Err(_) => (
+            "application/octet-stream",
+            None,
+            Some(base64::encode(value)),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{etcdserver, mvccpb};
+
+    fn response(events: Vec<mvccpb::Event>) -> WatchResponse {
+        let response = etcdserver::WatchResponse {
+            header: Some(etcdserver::ResponseHeader {
+                revision: 9,
+                ..Default::default()
+            }),
+            events,
+            ..Default::default()
+        };
+        WatchResponse::from_raw(response, b"").unwrap()
+    }
+
+    fn event(event_type: mvccpb::event::EventType, key: &[u8], value: &[u8]) -> mvccpb::Event {
+        let put = event_type == mvccpb::event::EventType::Put;
+        mvccpb::Event {
+            r#type: event_type as i32,
+            kv: Some(mvccpb::KeyValue {
+                key: key.to_vec(),
+                value: value.to_vec(),
+                create_revision: if put { 5 } else { 0 },
+                mod_revision: 8,
+                version: if put { 2 } else { 0 },
+                lease: 0,
+            }),
+            prev_kv: None,
+        }
+    }
+
+    #[test]
+    fn test_converting_events() {
+        use mvccpb::event::EventType::{Delete, Put};
+        let response = response(vec![
+            event(Put, b"config/web", br#"{"replicas":3}"#),
+            event(Put, b"config/name", b"web"),
+            event(Put, b"\xff", b"\xff"),
+            event(Delete, b"config/old", b""),
+        ]);
+        let options = CloudEventOptions {
+            source: "//etcd.example.com".into(),
+            ..Default::default()
+        };
+        let events = CloudEvent::from_watch_response(&response, &options);
+        assert_eq!(
+            events[0].to_json(),
+            concat!(
+                r#"{"specversion":"1.0","id":"8:config/web","source":"//etcd.example.com","#,
+                r#""type":"io.etcd.kv.put","subject":"config/web","#,
+                r#""datacontenttype":"application/json","data":{"replicas":3},"revision":8,"#,
+                r#""createrevision":5,"version":2}"#
+            )
+        );
+        assert_eq!(events[1].data, Some(Value::String("web".into())));
+        assert_eq!(events[1].datacontenttype.as_deref(), Some("text/plain"));
+        assert_eq!(events[2].subject, "/w==");
+        assert_eq!(events[2].subjectencoding.as_deref(), Some("base64"));
+        assert_eq!(events[2].data_base64.as_deref(), Some("/w=="));
+        assert_eq!(
+            events[3].to_json(),
+            concat!(
+                r#"{"specversion":"1.0","id":"8:config/old","source":"//etcd.example.com","#,
+                r#""type":"io.etcd.kv.delete","subject":"config/old","revision":8}"#
+            )
+        );
+
+        let parsed: CloudEvent = serde_json::from_str(&events[0].to_json()).unwrap();
+        assert_eq!(parsed, events[0]);
+    }
+}
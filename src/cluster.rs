@@ -0,0 +1,1289 @@
+//! The cluster service: membership, member health and the cluster's leader.
+
+use crate::maintenance::{connect_member_maintenance, Capability};
+use crate::{
+    client, etcdserver, runtime, Budget, ChannelConfig, Error, EtcdClient, EtcdResult, Retry,
+    Transport,
+};
+use futures::channel::mpsc;
+use futures::{future, SinkExt};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Cluster information
+pub struct Cluster<T> {
+    pub(crate) client: EtcdClient<T>,
+}
+
+impl<T> fmt::Debug for Cluster<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cluster")
+            .field("client", &self.client)
+            .finish()
+    }
+}
+
+/// Options for listing cluster members
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemberListOptions {
+    /// Go through raft for a consistent view of membership, rather than answering from the
+    /// local member's possibly stale state. Requires etcd 3.5 or newer, see
+    /// [`Capability::LinearizableMemberList`].
+    pub linearizable: bool,
+}
+
+impl<T: Transport> Cluster<T> {
+    pub async fn members(&self) -> EtcdResult<Vec<etcdserver::Member>> {
+        self.members_with_options(MemberListOptions::default())
+            .await
+    }
+
+    /// List members along with the health of each of their client URLs.
+    ///
+    /// Every client URL of every member is probed concurrently with a Status request, giving
+    /// up on a URL after `timeout`.
+    pub async fn members_with_health(&self, timeout: Duration) -> EtcdResult<Vec<MemberHealth>> {
+        let members = self.members().await?;
+        let config = &self.client.config;
+        let probes = members.into_iter().map(|member| async move {
+            let endpoints = member
+                .client_ur_ls
+                .iter()
+                .map(|url| probe_endpoint(config, url.clone(), timeout));
+            let endpoints = future::join_all(endpoints).await;
+            MemberHealth { member, endpoints }
+        });
+        Ok(future::join_all(probes).await)
+    }
+
+    /// List members, see [`MemberListOptions`]
+    pub async fn members_with_options(
+        &self,
+        options: MemberListOptions,
+    ) -> EtcdResult<Vec<etcdserver::Member>> {
+        if options.linearizable {
+            // Older servers ignore the field, answering from possibly stale state
+            let version = self.client.version().await?;
+            version.check(Capability::LinearizableMemberList)?;
+        }
+        self.client.member_list(options.linearizable).await
+    }
+
+    /// Add a new member to the cluster, which will join using `peer_urls`.
+    ///
+    /// The URLs are validated before the request is sent, see [`validate_peer_urls`].
+    pub async fn member_add<I, S>(&self, peer_urls: I) -> EtcdResult<MemberAdded>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.add_member(peer_urls, false).await
+    }
+
+    /// Add a new member to the cluster as a raft learner. Learners receive the log but do not
+    /// vote, so adding one never affects quorum. Once it has caught up with the leader, promote
+    /// it with [`Cluster::member_promote`].
+    ///
+    /// Requires etcd 3.4 or newer, see [`Capability::Learners`].
+    pub async fn member_add_as_learner<I, S>(&self, peer_urls: I) -> EtcdResult<MemberAdded>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.add_member(peer_urls, true).await
+    }
+
+    async fn add_member<I, S>(&self, peer_urls: I, is_learner: bool) -> EtcdResult<MemberAdded>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let peer_urls: Vec<String> = peer_urls.into_iter().map(Into::into).collect();
+        if is_learner {
+            self.client.version().await?.check(Capability::Learners)?;
+        }
+        let members = self.members().await?;
+        validate_peer_urls(&peer_urls, &members, None)?;
+
+        let request = etcdserver::MemberAddRequest {
+            peer_ur_ls: peer_urls,
+            is_learner,
+        };
+        let context = self.client.error_context("member add", None);
+        let rpc = || {
+            let (mut cluster_client, request) = (self.client.cluster_client(), request.clone());
+            async move { cluster_client.member_add(request).await }
+        };
+        self.client
+            .call(
+                "cluster.member_add",
+                context,
+                Budget::Write,
+                Retry::Mutation,
+                rpc,
+                MemberAdded::from_response,
+            )
+            .await
+    }
+
+    /// Promote a learner to a voting member, returning the resulting member list.
+    ///
+    /// Requires etcd 3.4 or newer, see [`Capability::MemberPromote`]. The server rejects the
+    /// promotion if the learner has not yet caught up with the leader; see
+    /// [`Cluster::promote_when_caught_up`].
+    pub async fn member_promote(&self, id: u64) -> EtcdResult<Vec<etcdserver::Member>> {
+        self.client
+            .version()
+            .await?
+            .check(Capability::MemberPromote)?;
+
+        let request = etcdserver::MemberPromoteRequest { id };
+        let context = self.client.error_context("member promote", None);
+        let rpc = || {
+            let (mut cluster_client, request) = (self.client.cluster_client(), request.clone());
+            async move { cluster_client.member_promote(request).await }
+        };
+        self.client
+            .call(
+                "cluster.member_promote",
+                context,
+                Budget::Write,
+                Retry::Mutation,
+                rpc,
+                |response| Ok(response.members),
+            )
+            .await
+    }
+
+    /// Wait until the learner with the given ID has caught up with the leader, then promote it.
+    ///
+    /// The learner counts as caught up once its raft index is within 90% of the leader's, the
+    /// same threshold the server uses. Status is polled every `poll_interval`, and an error is
+    /// returned if the learner has not caught up within `timeout`.
+    pub async fn promote_when_caught_up(
+        &self,
+        id: u64,
+        poll_interval: Duration,
+        timeout: Duration,
+    ) -> EtcdResult<Vec<etcdserver::Member>> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            if self.learner_caught_up(id).await? {
+                return self.member_promote(id).await;
+            }
+            if Instant::now() + poll_interval > deadline {
+                return Err(format!(
+                    "learner {:x} did not catch up with the leader within {:?}",
+                    id, timeout
+                )
+                .into());
+            }
+            runtime::delay_for(poll_interval).await;
+        }
+    }
+
+    async fn learner_caught_up(&self, id: u64) -> EtcdResult<bool> {
+        let members = self.members().await?;
+        let learner = match members.iter().find(|m| m.id == id) {
+            Some(m) if m.is_learner => m,
+            Some(_) => return Err(format!("member {:x} is not a learner", id).into()),
+            None => return Err(format!("member {:x} is not in the cluster", id).into()),
+        };
+
+        // The learner has not started yet
+        if learner.client_ur_ls.is_empty() {
+            return Ok(false);
+        }
+
+        let leader_id = self.client.maintenance().status().await?.leader;
+        let leader = match members.iter().find(|m| m.id == leader_id) {
+            Some(m) => m,
+            None => return Err("the cluster has no leader".into()),
+        };
+
+        let request = etcdserver::StatusRequest {};
+        let leader_status = connect_member_maintenance(&self.client.config, leader)
+            .await?
+            .status(request.clone())
+            .await?
+            .into_inner();
+        let learner_status = match connect_member_maintenance(&self.client.config, learner).await {
+            Ok(mut c) => match c.status(request).await {
+                Ok(response) => response.into_inner(),
+                Err(_) => return Ok(false),
+            },
+            Err(_) => return Ok(false),
+        };
+
+        Ok(learner_caught_up(
+            learner_status.raft_index,
+            leader_status.raft_index,
+        ))
+    }
+
+    /// Remove the member with the given ID, returning the resulting member list
+    pub async fn member_remove(&self, id: u64) -> EtcdResult<Vec<etcdserver::Member>> {
+        let request = etcdserver::MemberRemoveRequest { id };
+        let context = self.client.error_context("member remove", None);
+        let rpc = || {
+            let (mut cluster_client, request) = (self.client.cluster_client(), request.clone());
+            async move { cluster_client.member_remove(request).await }
+        };
+        self.client
+            .call(
+                "cluster.member_remove",
+                context,
+                Budget::Write,
+                Retry::Mutation,
+                rpc,
+                |response| Ok(response.members),
+            )
+            .await
+    }
+
+    /// Remove a member, unless doing so would remove the current leader or leave the cluster
+    /// without a quorum of healthy voting members. Pass `force` to skip these checks, which
+    /// makes this the same as [`Cluster::member_remove`].
+    ///
+    /// Health is judged by asking each member for its status.
+    pub async fn safe_member_remove(
+        &self,
+        id: u64,
+        force: bool,
+    ) -> EtcdResult<Vec<etcdserver::Member>> {
+        if !force {
+            let members = self.members().await?;
+            let statuses = member_statuses(&self.client.config, &members).await;
+            check_member_removal(&members, &statuses, id)?;
+        }
+        self.member_remove(id).await
+    }
+
+    /// Change the peer URLs of the member with the given ID, returning the resulting member list.
+    ///
+    /// The URLs are validated before the request is sent, see [`validate_peer_urls`].
+    pub async fn member_update<I, S>(
+        &self,
+        id: u64,
+        peer_urls: I,
+    ) -> EtcdResult<Vec<etcdserver::Member>>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let peer_urls: Vec<String> = peer_urls.into_iter().map(Into::into).collect();
+        let members = self.members().await?;
+        validate_peer_urls(&peer_urls, &members, Some(id))?;
+
+        let request = etcdserver::MemberUpdateRequest {
+            id,
+            peer_ur_ls: peer_urls,
+        };
+        let context = self.client.error_context("member update", None);
+        let rpc = || {
+            let (mut cluster_client, request) = (self.client.cluster_client(), request.clone());
+            async move { cluster_client.member_update(request).await }
+        };
+        self.client
+            .call(
+                "cluster.member_update",
+                context,
+                Budget::Write,
+                Retry::Mutation,
+                rpc,
+                |response| Ok(response.members),
+            )
+            .await
+    }
+
+    /// The member which is currently the raft leader.
+    ///
+    /// Every member is asked for its status and the leader is the member which reports itself
+    /// as leader, falling back to the leader most members agree on. Returns an error if the
+    /// cluster has no leader, for example because it has lost quorum.
+    pub async fn leader(&self) -> EtcdResult<etcdserver::Member> {
+        let members = self.members().await?;
+        let statuses = member_statuses(&self.client.config, &members).await;
+        match leader_from_statuses(&members, &statuses) {
+            Some(leader) => Ok(leader.clone()),
+            None => Err("the cluster has no leader".into()),
+        }
+    }
+
+    /// Stream of leadership changes, checked every `interval`.
+    ///
+    /// The current leader is sent straight away, followed by the new leader each time it
+    /// changes. `None` is sent while the cluster has no leader. The background task checking
+    /// for changes stops within one interval of the receiver being dropped. Must be called from
+    /// within a tokio runtime.
+    pub fn leader_changes(&self, interval: Duration) -> mpsc::Receiver<Option<etcdserver::Member>> {
+        let (mut tx, rx) = mpsc::channel(1);
+        let mut cluster_client = self.client.cluster_client();
+        let config = self.client.config.clone();
+
+        runtime::spawn(async move {
+            let mut current: Option<Option<u64>> = None;
+            let mut ticks = runtime::Interval::new(interval);
+
+            loop {
+                ticks.next().await;
+                if tx.is_closed() {
+                    // The receiver has gone away
+                    break;
+                }
+
+                let members = match cluster_client
+                    .member_list(etcdserver::MemberListRequest::default())
+                    .await
+                {
+                    Ok(response) => response.into_inner().members,
+                    Err(_) => continue,
+                };
+                let statuses = member_statuses(&config, &members).await;
+                let leader = leader_from_statuses(&members, &statuses).cloned();
+
+                let id = leader.as_ref().map(|m| m.id);
+                if current == Some(id) {
+                    continue;
+                }
+                current = Some(id);
+                if tx.send(leader).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        rx
+    }
+
+    /// Current members, and a stream of changes to membership checked every `interval`.
+    ///
+    /// Changes are found by diffing the member list, so a member added and removed again
+    /// between two checks is never seen. The background task checking for changes stops within
+    /// one interval of the receiver being dropped. Must be called from within a tokio runtime.
+    pub async fn watch_membership(
+        &self,
+        interval: Duration,
+    ) -> EtcdResult<(Vec<etcdserver::Member>, mpsc::Receiver<MembershipEvent>)> {
+        let initial = self.members().await?;
+        let (mut tx, rx) = mpsc::channel(16);
+        let mut cluster_client = self.client.cluster_client();
+        let mut current = initial.clone();
+
+        runtime::spawn(async move {
+            let mut ticks = runtime::Interval::new(interval);
+
+            loop {
+                ticks.next().await;
+                if tx.is_closed() {
+                    break;
+                }
+
+                let members = match cluster_client
+                    .member_list(etcdserver::MemberListRequest::default())
+                    .await
+                {
+                    Ok(response) => response.into_inner().members,
+                    Err(_) => continue,
+                };
+
+                for event in diff_members(&current, &members) {
+                    if tx.send(event).await.is_err() {
+                        return;
+                    }
+                }
+                current = members;
+            }
+        });
+
+        Ok((initial, rx))
+    }
+
+    /// Run `f` against each member in turn, connected directly to that member, for rolling
+    /// operations such as defragmenting or restarting the cluster one member at a time.
+    ///
+    /// Members are visited in member list order, except that the leader goes last when
+    /// `leader_last` is set so leadership only moves once. Stops at the first member which
+    /// cannot be dialed or for which `f` fails, and returns that error; members after it are
+    /// not visited.
+    pub async fn for_each_member<F, Fut>(&self, leader_last: bool, mut f: F) -> EtcdResult<()>
+    where
+        F: FnMut(MemberClient) -> Fut,
+        Fut: std::future::Future<Output = EtcdResult<()>>,
+    {
+        let members = self.members().await?;
+        let leader = if leader_last {
+            let statuses = member_statuses(&self.client.config, &members).await;
+            match leader_from_statuses(&members, &statuses) {
+                Some(leader) => Some(leader.id),
+                None => return Err("the cluster has no leader".into()),
+            }
+        } else {
+            None
+        };
+
+        for member in rolling_order(members, leader) {
+            let url = match member.client_ur_ls.first() {
+                Some(url) => url.clone(),
+                None => return Err(format!("member {:x} has no client URLs", member.id).into()),
+            };
+            let client = self
+                .member_client(url)
+                .await
+                .map_err(|e| format!("member {:x} ({}): {}", member.id, member.name, e))?;
+            let (id, name) = (member.id, member.name.clone());
+            f(MemberClient { member, client })
+                .await
+                .map_err(|e| format!("member {:x} ({}): {}", id, name, e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Client for the single member endpoint `url`, with the settings and namespace of this
+    /// client but a connection state of its own, so members being down does not make the
+    /// client look disconnected
+    async fn member_client(
+        &self,
+        url: String,
+    ) -> EtcdResult<EtcdClient<tonic::transport::channel::Channel>> {
+        let channel = self.client.config.member_channel(&url).await?;
+        let mut config = self.client.config.clone();
+        config.hooks.remove(&config.connection);
+        config.connection = Default::default();
+        let mut client = EtcdClient::with_channel(channel, vec![url], config);
+        client.namespace = self.client.namespace.clone();
+        Ok(client)
+    }
+
+    /// Compare the hash of the key space on every member at the current revision of the
+    /// connected member.
+    ///
+    /// Members which cannot be dialed or fail to hash are listed in the report rather than
+    /// failing the whole check; use [`CorruptionReport::mismatches`] to find members which
+    /// disagree.
+    pub async fn check_corruption(&self) -> EtcdResult<CorruptionReport> {
+        let members = self.members().await?;
+        let revision = match self.client.maintenance().hash_kv(0).await?.header {
+            Some(header) => header.revision,
+            None => {
+                return Err(Error::invalid_response(
+                    "HashKV response is missing its header",
+                ))
+            }
+        };
+
+        let mut report = CorruptionReport {
+            revision,
+            hashes: Vec::with_capacity(members.len()),
+            unreachable: Vec::new(),
+        };
+
+        for member in members {
+            let result = match connect_member_maintenance(&self.client.config, &member).await {
+                Ok(mut c) => c
+                    .hash_kv(etcdserver::HashKvRequest { revision })
+                    .await
+                    .map(|r| r.into_inner())
+                    .map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+
+            match result {
+                Ok(response) => report.hashes.push(MemberHash {
+                    member_id: member.id,
+                    name: member.name,
+                    hash: response.hash,
+                    compact_revision: response.compact_revision,
+                }),
+                Err(error) => report.unreachable.push(UnreachableMember {
+                    member_id: member.id,
+                    name: member.name,
+                    error,
+                }),
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+/// Check peer URLs before they are used for a membership change.
+///
+/// Every URL must be an `http` or `https` URL with a host and explicit port and no path, and
+/// must not be listed twice or already be in use by a member other than `updating` (the member
+/// whose URLs are being replaced, if any). The server would reject most of these, but with an
+/// error which does not say which URL is at fault.
+pub fn validate_peer_urls(
+    peer_urls: &[String],
+    members: &[etcdserver::Member],
+    updating: Option<u64>,
+) -> EtcdResult<()> {
+    if peer_urls.is_empty() {
+        return Err("at least one peer URL is required".into());
+    }
+
+    let invalid = |url: &str, reason: &str| -> Error {
+        format!("invalid peer URL {:?}: {}", url, reason).into()
+    };
+
+    for (i, url) in peer_urls.iter().enumerate() {
+        let uri: http::Uri = url.parse().map_err(|e| invalid(url, &format!("{}", e)))?;
+        match uri.scheme_str() {
+            Some("http") | Some("https") => {}
+            Some(scheme) => return Err(invalid(url, &format!("unsupported scheme {:?}", scheme))),
+            None => return Err(invalid(url, "missing scheme")),
+        }
+        match uri.host() {
+            Some(host) if !host.is_empty() => {}
+            _ => return Err(invalid(url, "missing host")),
+        }
+        if uri.port_u16().is_none() {
+            return Err(invalid(url, "missing port"));
+        }
+        if !(uri.path().is_empty() || uri.path() == "/") || uri.query().is_some() {
+            return Err(invalid(url, "peer URLs must not have a path or query"));
+        }
+
+        if peer_urls[..i].contains(url) {
+            return Err(invalid(url, "listed more than once"));
+        }
+        if let Some(member) = members
+            .iter()
+            .filter(|m| Some(m.id) != updating)
+            .find(|m| m.peer_ur_ls.contains(url))
+        {
+            return Err(invalid(
+                url,
+                &format!("already in use by member {:x} ({})", member.id, member.name),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that removing the member `id` keeps the cluster available, given the status of each
+/// member in the same order as `members`. See [`Cluster::safe_member_remove`].
+fn check_member_removal(
+    members: &[etcdserver::Member],
+    statuses: &[Result<etcdserver::StatusResponse, String>],
+    id: u64,
+) -> Result<(), String> {
+    let target = match members.iter().find(|m| m.id == id) {
+        Some(m) => m,
+        None => return Err(format!("member {:x} is not in the cluster", id)),
+    };
+    // Learners do not vote, so removing them can never affect quorum
+    if target.is_learner {
+        return Ok(());
+    }
+
+    if leader_from_statuses(members, statuses).map(|m| m.id) == Some(id) {
+        return Err(format!(
+            "refusing to remove member {:x} ({}) as it is the current leader",
+            id, target.name
+        ));
+    }
+
+    let remaining = members
+        .iter()
+        .zip(statuses)
+        .filter(|(m, _)| !m.is_learner && m.id != id);
+    let voting = remaining.clone().count();
+    let healthy = remaining.filter(|(_, status)| status.is_ok()).count();
+    let quorum = voting / 2 + 1;
+    if healthy < quorum {
+        return Err(format!(
+            "refusing to remove member {:x} ({}): only {} of the remaining {} voting members are \
+             healthy, below the quorum of {}",
+            id, target.name, healthy, voting, quorum
+        ));
+    }
+
+    Ok(())
+}
+
+/// A client connected to a single member, passed to [`Cluster::for_each_member`]. It has the
+/// connection settings and namespace of the client it was created from.
+pub struct MemberClient {
+    pub member: etcdserver::Member,
+    pub client: EtcdClient<tonic::transport::channel::Channel>,
+}
+
+impl fmt::Debug for MemberClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemberClient")
+            .field("member", &self.member)
+            .field("client", &self.client)
+            .finish()
+    }
+}
+
+/// Order members for a rolling operation, moving `leader` (if given) to the end
+fn rolling_order(
+    mut members: Vec<etcdserver::Member>,
+    leader: Option<u64>,
+) -> Vec<etcdserver::Member> {
+    if let Some(leader) = leader {
+        // Stable, so the other members keep their order
+        members.sort_by_key(|m| m.id == leader);
+    }
+    members
+}
+
+/// Whether a learner at `learner_index` is close enough to `leader_index` to be promoted
+fn learner_caught_up(learner_index: u64, leader_index: u64) -> bool {
+    learner_index as f64 >= leader_index as f64 * 0.9
+}
+
+/// Result of adding a member to the cluster
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemberAdded {
+    /// The member that was added. It has no name or client URLs until it has started.
+    pub member: etcdserver::Member,
+    /// Member list after the addition, including the new member
+    pub members: Vec<etcdserver::Member>,
+}
+
+impl MemberAdded {
+    fn from_response(response: etcdserver::MemberAddResponse) -> EtcdResult<Self> {
+        match response.member {
+            Some(member) => Ok(Self {
+                member,
+                members: response.members,
+            }),
+            None => Err(Error::invalid_response(
+                "member add response is missing the added member",
+            )),
+        }
+    }
+}
+
+/// Fetch the status of every member concurrently. Failures are kept as strings, so the
+/// result can be held across await points in spawned tasks.
+pub(crate) async fn member_statuses(
+    config: &ChannelConfig,
+    members: &[etcdserver::Member],
+) -> Vec<Result<etcdserver::StatusResponse, String>> {
+    let statuses = members.iter().map(|member| async move {
+        let mut c = connect_member_maintenance(config, member)
+            .await
+            .map_err(|e| e.to_string())?;
+        c.status(etcdserver::StatusRequest {})
+            .await
+            .map(|response| response.into_inner())
+            .map_err(|e| e.to_string())
+    });
+    future::join_all(statuses).await
+}
+
+/// Find the leader given the status of each member, in the same order as `members`.
+///
+/// A member reporting itself as leader wins, preferring the highest raft term if several do
+/// (an old leader may not have noticed it was replaced yet). Otherwise the leader reported by
+/// the most members is used.
+pub(crate) fn leader_from_statuses<'a>(
+    members: &'a [etcdserver::Member],
+    statuses: &[Result<etcdserver::StatusResponse, String>],
+) -> Option<&'a etcdserver::Member> {
+    let responses = || statuses.iter().filter_map(|status| status.as_ref().ok());
+
+    let self_reported = responses()
+        .filter(|status| match &status.header {
+            Some(header) => status.leader != 0 && status.leader == header.member_id,
+            None => false,
+        })
+        .max_by_key(|status| status.raft_term)
+        .map(|status| status.leader);
+
+    let leader_id = match self_reported {
+        Some(id) => id,
+        None => {
+            let mut votes: HashMap<u64, usize> = HashMap::new();
+            for status in responses().filter(|status| status.leader != 0) {
+                *votes.entry(status.leader).or_insert(0) += 1;
+            }
+            votes
+                .into_iter()
+                .max_by_key(|&(id, count)| (count, std::cmp::Reverse(id)))?
+                .0
+        }
+    };
+
+    members.iter().find(|m| m.id == leader_id)
+}
+
+/// Change to cluster membership, see [`Cluster::watch_membership`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum MembershipEvent {
+    Added(etcdserver::Member),
+    Removed(etcdserver::Member),
+    /// A member's name or URLs changed, or it was promoted from learner
+    Updated {
+        old: etcdserver::Member,
+        new: etcdserver::Member,
+    },
+}
+
+/// Events turning the member list `old` into `new`: removals first, then updates, then
+/// additions, each ordered by member ID.
+fn diff_members(old: &[etcdserver::Member], new: &[etcdserver::Member]) -> Vec<MembershipEvent> {
+    let find = |members: &[etcdserver::Member], id| members.iter().find(|m| m.id == id).cloned();
+
+    let mut removed: Vec<_> = old.iter().filter(|m| find(new, m.id).is_none()).collect();
+    let mut added: Vec<_> = new.iter().filter(|m| find(old, m.id).is_none()).collect();
+    let mut updated: Vec<_> = new
+        .iter()
+        .filter_map(|m| match find(old, m.id) {
+            Some(previous) if previous != *m => Some((previous, m.clone())),
+            _ => None,
+        })
+        .collect();
+    removed.sort_by_key(|m| m.id);
+    added.sort_by_key(|m| m.id);
+    updated.sort_by_key(|(m, _)| m.id);
+
+    let removed = removed
+        .into_iter()
+        .map(|m| MembershipEvent::Removed(m.clone()));
+    let updated = updated
+        .into_iter()
+        .map(|(old, new)| MembershipEvent::Updated { old, new });
+    let added = added.into_iter().map(|m| MembershipEvent::Added(m.clone()));
+    removed.chain(updated).chain(added).collect()
+}
+
+/// Health of a single client URL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EndpointHealth {
+    pub url: String,
+    /// Round trip time of the probe, if it succeeded
+    pub latency: Option<Duration>,
+    /// Why the endpoint is unhealthy: it could not be reached, or the member reported errors
+    pub error: Option<String>,
+}
+
+impl EndpointHealth {
+    pub fn is_healthy(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// A member along with the health of each of its client URLs
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemberHealth {
+    pub member: etcdserver::Member,
+    pub endpoints: Vec<EndpointHealth>,
+}
+
+impl MemberHealth {
+    /// True if any of the member's client URLs is healthy
+    pub fn is_healthy(&self) -> bool {
+        self.endpoints.iter().any(EndpointHealth::is_healthy)
+    }
+
+    /// Lowest latency of the member's healthy client URLs
+    pub fn latency(&self) -> Option<Duration> {
+        self.endpoints
+            .iter()
+            .filter(|e| e.is_healthy())
+            .filter_map(|e| e.latency)
+            .min()
+    }
+}
+
+/// Time a Status request against `url`, dialled with the client's connection settings
+async fn probe_endpoint(config: &ChannelConfig, url: String, timeout: Duration) -> EndpointHealth {
+    let start = Instant::now();
+    let probe = async {
+        let channel = config
+            .member_channel(&url)
+            .await
+            .map_err(|e| e.to_string())?;
+        let mut c = client::MaintenanceClient::new(channel);
+        c.status(etcdserver::StatusRequest {})
+            .await
+            .map(|response| response.into_inner())
+            .map_err(|e| e.to_string())
+    };
+
+    let (latency, error) = match runtime::timeout(probe, timeout).await {
+        Ok(Ok(status)) if status.errors.is_empty() => (Some(start.elapsed()), None),
+        Ok(Ok(status)) => (Some(start.elapsed()), Some(status.errors.join(", "))),
+        Ok(Err(e)) => (None, Some(e)),
+        Err(_) => (None, Some(format!("no response within {:?}", timeout))),
+    };
+
+    EndpointHealth {
+        url,
+        latency,
+        error,
+    }
+}
+
+/// Hash of a member's key space, as reported by HashKV
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemberHash {
+    pub member_id: u64,
+    pub name: String,
+    pub hash: u32,
+    pub compact_revision: i64,
+}
+
+/// Member which could not be included in a corruption check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnreachableMember {
+    pub member_id: u64,
+    pub name: String,
+    pub error: String,
+}
+
+/// Result of comparing HashKV across all members at the same revision
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorruptionReport {
+    /// Revision every member hashed up to
+    pub revision: i64,
+    pub hashes: Vec<MemberHash>,
+    pub unreachable: Vec<UnreachableMember>,
+}
+
+impl CorruptionReport {
+    /// Members whose hash disagrees with the majority of members at the same compact revision.
+    ///
+    /// Hashes are only comparable between members which have compacted to the same revision,
+    /// so members still catching up on a compaction are never reported. If no hash is shared
+    /// by more than half of the members at a compact revision, there is no telling which of
+    /// them are corrupt and all of them are reported.
+    pub fn mismatches(&self) -> Vec<&MemberHash> {
+        let mut counts: HashMap<(i64, u32), usize> = HashMap::new();
+        let mut members: HashMap<i64, usize> = HashMap::new();
+        for h in &self.hashes {
+            *counts.entry((h.compact_revision, h.hash)).or_insert(0) += 1;
+            *members.entry(h.compact_revision).or_insert(0) += 1;
+        }
+
+        // The hash shared by a strict majority at each compact revision, where there is one
+        let majority: HashMap<i64, u32> = counts
+            .iter()
+            .filter(|&(&(compact_revision, _), &count)| count * 2 > members[&compact_revision])
+            .map(|(&(compact_revision, hash), _)| (compact_revision, hash))
+            .collect();
+
+        self.hashes
+            .iter()
+            .filter(|h| majority.get(&h.compact_revision) != Some(&h.hash))
+            .collect()
+    }
+
+    /// True if every reachable member agrees on the hash of the key space
+    pub fn is_consistent(&self) -> bool {
+        self.mismatches().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_listing_members() {
+        // There is only one member in the test cluster, so we check this.
+        let client = crate::testing::namespace().await.unwrap();
+
+        let cluster_info = client.cluster();
+        let members = cluster_info.members().await.unwrap();
+        assert_eq!(members.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_listing_members_linearizably() {
+        let client = crate::testing::namespace().await.unwrap();
+
+        let options = MemberListOptions { linearizable: true };
+        let members = client
+            .cluster()
+            .members_with_options(options)
+            .await
+            .unwrap();
+        assert_eq!(members, client.cluster().members().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_member_health() {
+        let client = crate::testing::namespace().await.unwrap();
+        let health = client
+            .cluster()
+            .members_with_health(Duration::from_secs(1))
+            .await
+            .unwrap();
+
+        assert_eq!(health.len(), 1);
+        assert!(health[0].is_healthy());
+        assert!(health[0].latency().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_probing_unreachable_endpoints() {
+        let url = "http://127.0.0.1:1".to_string();
+        let health = probe_endpoint(&ChannelConfig::default(), url, Duration::from_secs(1)).await;
+        assert!(!health.is_healthy());
+        assert_eq!(health.latency, None);
+    }
+
+    #[tokio::test]
+    async fn test_member_connections_use_client_settings() {
+        // Unix domain sockets can only be dialled through a tunnel the client's settings open
+        let url = "unix:///nonexistent/etcd.sock".to_string();
+        let member = etcdserver::Member {
+            id: 1,
+            client_ur_ls: vec![url.clone()],
+            ..Default::default()
+        };
+        let config = ChannelConfig {
+            connect_timeout: Some(Duration::from_millis(200)),
+            ..Default::default()
+        };
+        let statuses = member_statuses(&config, &[member]).await;
+        assert!(statuses[0].is_err());
+        assert!(config.tunnels.lock().unwrap().contains_key(&url));
+    }
+
+    #[tokio::test]
+    async fn test_updating_members() {
+        // Adding a voting member to the single member test cluster would lose quorum, so only
+        // check that updating a member round trips its peer URLs.
+        let client = crate::testing::namespace().await.unwrap();
+        let cluster = client.cluster();
+
+        let member = cluster.members().await.unwrap().remove(0);
+        let members = cluster
+            .member_update(member.id, member.peer_ur_ls.clone())
+            .await
+            .unwrap();
+        assert_eq!(members.len(), 1);
+        assert_eq!(members[0].peer_ur_ls, member.peer_ur_ls);
+    }
+
+    #[tokio::test]
+    async fn test_learners() {
+        let client = crate::testing::namespace().await.unwrap();
+        let cluster = client.cluster();
+
+        // Learners do not count towards quorum, so adding one which never starts is safe
+        let added = cluster
+            .member_add_as_learner(vec!["http://127.0.0.1:12380"])
+            .await
+            .unwrap();
+        assert!(added.member.is_learner);
+        assert_eq!(added.members.len(), 2);
+
+        // The learner has not started, so it can never catch up
+        assert!(cluster
+            .promote_when_caught_up(
+                added.member.id,
+                Duration::from_millis(10),
+                Duration::from_millis(50)
+            )
+            .await
+            .is_err());
+
+        let members = cluster.member_remove(added.member.id).await.unwrap();
+        assert_eq!(members.len(), 1);
+    }
+
+    #[test]
+    fn test_validating_peer_urls() {
+        let urls = |urls: &[&str]| urls.iter().map(|u| u.to_string()).collect::<Vec<_>>();
+        let members = vec![etcdserver::Member {
+            id: 0x1234,
+            name: "default".to_string(),
+            peer_ur_ls: urls(&["http://10.0.0.1:2380"]),
+            ..Default::default()
+        }];
+
+        assert!(validate_peer_urls(&urls(&["http://10.0.0.2:2380"]), &members, None).is_ok());
+        assert!(validate_peer_urls(&urls(&["https://etcd-2:2380/"]), &members, None).is_ok());
+        // A member keeping its own URL is fine
+        assert!(
+            validate_peer_urls(&urls(&["http://10.0.0.1:2380"]), &members, Some(0x1234)).is_ok()
+        );
+
+        let err = |u: &[&str]| {
+            validate_peer_urls(&urls(u), &members, None)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(err(&[]), "at least one peer URL is required");
+        assert!(err(&["10.0.0.2:2380"]).contains("missing scheme"));
+        assert!(err(&["unix://10.0.0.2:2380"]).contains("unsupported scheme"));
+        assert!(err(&["http://10.0.0.2"]).contains("missing port"));
+        assert!(err(&["http://10.0.0.2:2380/peers"]).contains("path"));
+        assert!(err(&["http://10.0.0.2:2380", "http://10.0.0.2:2380"]).contains("more than once"));
+        assert_eq!(
+            err(&["http://10.0.0.1:2380"]),
+            "invalid peer URL \"http://10.0.0.1:2380\": already in use by member 1234 (default)"
+        );
+    }
+
+    #[test]
+    fn test_learner_progress() {
+        assert!(learner_caught_up(100, 100));
+        assert!(learner_caught_up(90, 100));
+        assert!(!learner_caught_up(89, 100));
+        assert!(learner_caught_up(0, 0));
+    }
+
+    #[test]
+    fn test_finding_the_leader() {
+        let members: Vec<etcdserver::Member> = (1..=3)
+            .map(|id| etcdserver::Member {
+                id,
+                ..Default::default()
+            })
+            .collect();
+        let status = |member_id, leader, raft_term| {
+            Ok(etcdserver::StatusResponse {
+                header: Some(etcdserver::ResponseHeader {
+                    member_id,
+                    ..Default::default()
+                }),
+                leader,
+                raft_term,
+                ..Default::default()
+            })
+        };
+        let leader = |statuses: &[Result<etcdserver::StatusResponse, String>]| {
+            leader_from_statuses(&members, statuses).map(|m| m.id)
+        };
+
+        // Member 2 says it is the leader
+        assert_eq!(
+            leader(&[status(1, 2, 5), status(2, 2, 5), status(3, 2, 5)]),
+            Some(2)
+        );
+        // Member 1 is a stale leader from an older term
+        assert_eq!(
+            leader(&[status(1, 1, 4), status(2, 2, 5), Err("down".to_string())]),
+            Some(2)
+        );
+        // The leader is unreachable, go with what the others say
+        assert_eq!(
+            leader(&[status(1, 3, 5), status(2, 3, 5), Err("down".to_string())]),
+            Some(3)
+        );
+        // No leader
+        assert_eq!(leader(&[status(1, 0, 5), status(2, 0, 5)]), None);
+    }
+
+    #[tokio::test]
+    async fn test_leader() {
+        let client = crate::testing::namespace().await.unwrap();
+        let cluster = client.cluster();
+
+        let members = cluster.members().await.unwrap();
+        let leader = cluster.leader().await.unwrap();
+        assert_eq!(leader.id, members[0].id);
+
+        use futures::StreamExt;
+        let mut changes = cluster.leader_changes(Duration::from_millis(10));
+        assert_eq!(changes.next().await.unwrap().unwrap().id, leader.id);
+    }
+
+    #[test]
+    fn test_checking_member_removal() {
+        let member = |id, is_learner| etcdserver::Member {
+            id,
+            name: format!("m{}", id),
+            is_learner,
+            ..Default::default()
+        };
+        let members = vec![
+            member(1, false),
+            member(2, false),
+            member(3, false),
+            member(4, true),
+        ];
+        // Member 1 is the leader
+        let up = |member_id| {
+            Ok(etcdserver::StatusResponse {
+                header: Some(etcdserver::ResponseHeader {
+                    member_id,
+                    ..Default::default()
+                }),
+                leader: 1,
+                ..Default::default()
+            })
+        };
+        let down = || Err("unreachable".to_string());
+
+        let healthy = vec![up(1), up(2), up(3), up(4)];
+        assert!(check_member_removal(&members, &healthy, 2).is_ok());
+        assert!(check_member_removal(&members, &healthy, 4).is_ok());
+        assert!(check_member_removal(&members, &healthy, 1)
+            .unwrap_err()
+            .contains("current leader"));
+        assert!(check_member_removal(&members, &healthy, 5).is_err());
+
+        // With member 3 down, removing member 2 leaves one healthy member out of two
+        let degraded = vec![up(1), up(2), down(), up(4)];
+        assert!(check_member_removal(&members, &degraded, 2)
+            .unwrap_err()
+            .contains("below the quorum of 2"));
+        // Removing the broken member itself is fine
+        assert!(check_member_removal(&members, &degraded, 3).is_ok());
+        // Learners can always go
+        assert!(check_member_removal(&members, &degraded, 4).is_ok());
+    }
+
+    #[test]
+    fn test_rolling_order() {
+        let members: Vec<_> = [3, 1, 2]
+            .iter()
+            .map(|&id| etcdserver::Member {
+                id,
+                ..Default::default()
+            })
+            .collect();
+        let ids = |members: Vec<etcdserver::Member>| -> Vec<u64> {
+            members.into_iter().map(|m| m.id).collect()
+        };
+
+        assert_eq!(ids(rolling_order(members.clone(), None)), vec![3, 1, 2]);
+        assert_eq!(ids(rolling_order(members.clone(), Some(3))), vec![1, 2, 3]);
+        assert_eq!(ids(rolling_order(members.clone(), Some(1))), vec![3, 2, 1]);
+        assert_eq!(ids(rolling_order(members, Some(4))), vec![3, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_rolling_operations() {
+        let client = crate::testing::namespace().await.unwrap();
+        let mut visited = Vec::new();
+        client
+            .cluster()
+            .for_each_member(true, |m| {
+                visited.push(m.member.id);
+                async move {
+                    m.client.maintenance().status().await?;
+                    Ok(())
+                }
+            })
+            .await
+            .unwrap();
+        assert_eq!(visited.len(), 1);
+    }
+
+    #[test]
+    fn test_diffing_members() {
+        let member = |id, name: &str| etcdserver::Member {
+            id,
+            name: name.to_string(),
+            ..Default::default()
+        };
+        let old = vec![member(1, "a"), member(2, "b"), member(3, "c")];
+        let new = vec![member(4, "d"), member(3, "c"), member(1, "a2")];
+
+        assert_eq!(
+            diff_members(&old, &new),
+            vec![
+                MembershipEvent::Removed(member(2, "b")),
+                MembershipEvent::Updated {
+                    old: member(1, "a"),
+                    new: member(1, "a2"),
+                },
+                MembershipEvent::Added(member(4, "d")),
+            ]
+        );
+        assert!(diff_members(&new, &new).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_watching_membership() {
+        let client = crate::testing::namespace().await.unwrap();
+        let cluster = client.cluster();
+        let (members, mut events) = cluster
+            .watch_membership(Duration::from_millis(10))
+            .await
+            .unwrap();
+        assert_eq!(members.len(), 1);
+
+        let added = cluster
+            .member_add_as_learner(vec!["http://127.0.0.1:12380"])
+            .await
+            .unwrap();
+
+        use futures::StreamExt;
+        assert_eq!(
+            events.next().await.unwrap(),
+            MembershipEvent::Added(added.member.clone())
+        );
+
+        cluster.member_remove(added.member.id).await.unwrap();
+        assert_eq!(
+            events.next().await.unwrap(),
+            MembershipEvent::Removed(added.member)
+        );
+    }
+
+    #[test]
+    fn test_corruption_report_mismatches() {
+        let hash = |member_id, hash, compact_revision| MemberHash {
+            member_id,
+            name: format!("m{}", member_id),
+            hash,
+            compact_revision,
+        };
+        let mut report = CorruptionReport {
+            revision: 10,
+            hashes: vec![
+                hash(1, 0xaa, 5),
+                hash(2, 0xaa, 5),
+                hash(3, 0xbb, 5),
+                hash(4, 0xcc, 3),
+            ],
+            unreachable: vec![],
+        };
+
+        let mismatches: Vec<u64> = report.mismatches().iter().map(|h| h.member_id).collect();
+        assert_eq!(mismatches, vec![3]);
+        assert!(!report.is_consistent());
+
+        report.hashes.remove(2);
+        assert!(report.is_consistent());
+    }
+
+    #[test]
+    fn test_corruption_report_without_majority() {
+        let hash = |member_id, hash| MemberHash {
+            member_id,
+            name: format!("m{}", member_id),
+            hash,
+            compact_revision: 5,
+        };
+        let mut report = CorruptionReport {
+            revision: 10,
+            hashes: vec![hash(1, 0xaa), hash(2, 0xbb)],
+            unreachable: vec![],
+        };
+
+        // A tie cannot tell which side is corrupt, so both are reported
+        let mismatches: Vec<u64> = report.mismatches().iter().map(|h| h.member_id).collect();
+        assert_eq!(mismatches, vec![1, 2]);
+
+        report.hashes.extend(vec![hash(3, 0xaa), hash(4, 0xbb)]);
+        assert_eq!(report.mismatches().len(), 4);
+        report.hashes.push(hash(5, 0xaa));
+        let mismatches: Vec<u64> = report.mismatches().iter().map(|h| h.member_id).collect();
+        assert_eq!(mismatches, vec![2, 4]);
+    }
+
+    #[tokio::test]
+    async fn test_corruption_check() {
+        let client = crate::testing::namespace().await.unwrap();
+        let report = client.cluster().check_corruption().await.unwrap();
+        assert_eq!(report.hashes.len(), 1);
+        assert!(report.is_consistent());
+    }
+}
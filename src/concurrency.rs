@@ -0,0 +1,3519 @@
+//! Concurrency recipes built from leases, transactions and watches, along the lines of the Go
+//! client's `clientv3/concurrency` package. Unlike [`EtcdClient::lock`] these do not need the
+//! server's lock service to be enabled.
+//!
+//! The recipes work through the client they are built from, with its transport, retries,
+//! throttling and hooks, and their names are inside its [namespace](EtcdClient::namespaced).
+
+use crate::redact::Key;
+#[cfg(feature = "election")]
+use crate::v3election;
+use crate::{
+    etcdserver, runtime, Backoff, Error, EtcdClient, EtcdResult, EventKind, GetResponse, KeyValue,
+    Transport, TxnOpResponse, TxnResponse,
+};
+use futures::channel::{mpsc, oneshot};
+use futures::future::{self, Either};
+use futures::Stream;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+/// A reasonable TTL in seconds for a session's lease, long enough to ride out a leader
+/// election without the lease expiring
+pub const DEFAULT_SESSION_TTL: i64 = 60;
+
+/// A lease which is kept alive in the background for as long as the session exists.
+///
+/// Every recipe takes a session, and keys written by recipes are attached to its lease, so
+/// they are removed by the server if the process holding the session dies. Clones of a session
+/// share its lease and keep-alive stream, so one session can back any number of recipes.
+/// Dropping the last clone stops the keep-alive and the lease expires after its TTL; use
+/// [`Session::close`] to revoke it straight away.
+///
+/// If the keep-alive stream breaks, for example because the connection to the cluster was
+/// lost, it is re-established with backoff for as long as the lease could still be alive.
+/// Closing the client the session was created from ends the session and revokes its lease.
+#[derive(Clone)]
+pub struct Session<T> {
+    pub(crate) client: EtcdClient<T>,
+    lease: i64,
+    ttl: i64,
+    alive: watch::Receiver<bool>,
+    /// Health of the keep-alive stream, shared with the task running it
+    health: Arc<LeaseHealth>,
+    /// Mutexes held under the session, by key
+    holds: Arc<StdMutex<HashMap<Vec<u8>, Hold>>>,
+    /// Names of the [`Singleton`] jobs running under the session
+    jobs: Arc<StdMutex<HashSet<Vec<u8>>>>,
+    _stop: Arc<oneshot::Sender<()>>,
+}
+
+impl<T> fmt::Debug for Session<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Session")
+            .field("lease", &self.lease)
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A mutex held by a session, possibly several times over
+#[derive(Debug, Clone, Copy)]
+struct Hold {
+    count: usize,
+    revision: i64,
+}
+
+impl<T: Transport> Session<T> {
+    /// Grant a lease with a TTL of `ttl` seconds and start keeping it alive. Must be called
+    /// from within a tokio runtime.
+    pub async fn new(client: &EtcdClient<T>, ttl: i64) -> EtcdResult<Self> {
+        let client = client.clone();
+        let shutdown = client.config.shutdown.clone();
+        // The session is in flight until it ends, so closing the client waits for the lease
+        // to be revoked
+        let in_flight = shutdown.track()?;
+        let response = client.lease().grant(ttl).await?;
+        let (lease, ttl) = (response.id, response.ttl);
+
+        let (alive_tx, alive_rx) = watch::channel(true);
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let health = Arc::new(LeaseHealth::default());
+        let keep_alive_health = health.clone();
+        let keep_alive_client = client.clone();
+        let mut revoke_client = client.lease_client();
+        let closed = shutdown.closed();
+        // Refresh well before the lease could expire
+        let interval = Duration::from_secs((ttl as u64 / 3).max(1));
+
+        runtime::spawn(async move {
+            let stream_health = keep_alive_health.clone();
+            let keep_alive = async move {
+                let clock = keep_alive_client.config.clock.clone();
+                let mut keep_alive = KeepAlive {
+                    client: keep_alive_client,
+                    lease,
+                    interval,
+                    expires: clock.now() + Duration::from_secs(ttl as u64),
+                    backoff: Backoff::new(),
+                    health: stream_health,
+                };
+                while keep_alive.run().await.is_err() {
+                    // There is no point reconnecting once the lease has expired
+                    let delay = keep_alive.backoff.next();
+                    if clock.now() + delay >= keep_alive.expires {
+                        break;
+                    }
+                    clock.delay_for(delay).await;
+                }
+            };
+            futures::pin_mut!(keep_alive);
+            let stopped = future::select(stop_rx, closed);
+            match future::select(keep_alive, stopped).await {
+                // The lease expired, or could no longer be kept alive before it would
+                Either::Left(_) => keep_alive_health.lost(),
+                Either::Right((Either::Right(_), _)) => {
+                    // The client was closed, so the session ends with it. A closed client
+                    // refuses new operations, so the lease is revoked straight through the
+                    // lease service.
+                    let request = etcdserver::LeaseRevokeRequest { id: lease };
+                    let _ = revoke_client.lease_revoke(request).await;
+                }
+                Either::Right((Either::Left(_), _)) => {}
+            }
+            keep_alive_health.ended();
+            let _ = alive_tx.broadcast(false);
+            drop(in_flight);
+        });
+
+        Ok(Self {
+            client,
+            lease,
+            ttl,
+            alive: alive_rx,
+            health,
+            holds: Arc::default(),
+            jobs: Arc::default(),
+            _stop: Arc::new(stop_tx),
+        })
+    }
+
+    /// New session with the same TTL as this one, for carrying on after this session has
+    /// ended
+    #[cfg(feature = "election")]
+    async fn renew(&self) -> EtcdResult<Self> {
+        Self::new(&self.client, self.ttl).await
+    }
+
+    /// ID of the session's lease
+    pub fn lease(&self) -> i64 {
+        self.lease
+    }
+
+    /// TTL of the session's lease in seconds, as granted by the server
+    pub fn ttl(&self) -> i64 {
+        self.ttl
+    }
+
+    /// Whether the lease is still being kept alive. Once this is false the lease has expired
+    /// or soon will, and anything held under the session should be considered lost.
+    pub fn is_alive(&self) -> bool {
+        *self.alive.get_ref()
+    }
+
+    /// Wait until the lease stops being kept alive, see [`Session::is_alive`]
+    pub async fn done(&mut self) {
+        while *self.alive.get_ref() {
+            if self.alive.recv().await.is_none() {
+                break;
+            }
+        }
+    }
+
+    /// Stop keeping the lease alive and revoke it, deleting every key attached to it. This ends
+    /// the session for every clone of it.
+    pub async fn close(self) -> EtcdResult<()> {
+        self.client.lease().revoke(self.lease).await
+    }
+
+    /// Subscribe to the health of the session's keep-alives: how long each took to be
+    /// answered, keep-alives going unanswered, and the lease being lost, which usually means
+    /// whatever was held under the session, such as a lock, leadership or a registration, is
+    /// gone. With the `metrics` feature the same is recorded as metrics.
+    ///
+    /// Events are delivered from when this is called. The subscription ends once the session
+    /// does.
+    pub fn events(&self) -> SessionEvents {
+        self.health.subscribe()
+    }
+}
+
+/// What happened to a session's lease, see [`Session::events`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SessionEvent {
+    /// The lease was refreshed
+    KeptAlive {
+        /// Time between sending the keep-alive and its response arriving
+        rtt: Duration,
+        /// TTL of the lease in seconds from now on
+        ttl: i64,
+    },
+    /// A keep-alive was still unanswered when the next one was due, or the stream broke before
+    /// it was answered
+    Missed,
+    /// The lease expired, or the keep-alive stream could not be re-established before it would
+    /// have. The session is over.
+    Lost,
+}
+
+/// The events of a session from when they were subscribed to, see [`Session::events`]
+#[derive(Debug)]
+pub struct SessionEvents {
+    events: mpsc::UnboundedReceiver<SessionEvent>,
+}
+
+impl SessionEvents {
+    /// The next event, or `None` once the session has ended
+    pub async fn next(&mut self) -> Option<SessionEvent> {
+        futures::StreamExt::next(self).await
+    }
+}
+
+impl Stream for SessionEvents {
+    type Item = SessionEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.events).poll_next(cx)
+    }
+}
+
+/// Health of a session's keep-alives, published to its subscribers and recorded as metrics
+#[derive(Debug)]
+struct LeaseHealth {
+    /// Subscribers to the session's events, or `None` once it has ended
+    subscribers: StdMutex<Option<Vec<mpsc::UnboundedSender<SessionEvent>>>>,
+}
+
+impl LeaseHealth {
+    fn subscribe(&self) -> SessionEvents {
+        let (tx, rx) = mpsc::unbounded();
+        let mut subscribers = self.subscribers.lock().unwrap();
+        // Subscriptions to a session which has already ended end straight away
+        if let Some(subscribers) = subscribers.as_mut() {
+            subscribers.push(tx);
+        }
+        SessionEvents { events: rx }
+    }
+
+    fn publish(&self, event: SessionEvent) {
+        if let Some(subscribers) = self.subscribers.lock().unwrap().as_mut() {
+            subscribers.retain(|subscriber| subscriber.unbounded_send(event.clone()).is_ok());
+        }
+    }
+
+    fn kept_alive(&self, rtt: Duration, ttl: i64) {
+        #[cfg(feature = "metrics")]
+        crate::metrics::keep_alive_answered(rtt);
+        self.publish(SessionEvent::KeptAlive { rtt, ttl });
+    }
+
+    fn missed(&self) {
+        #[cfg(feature = "metrics")]
+        crate::metrics::keep_alive_missed();
+        self.publish(SessionEvent::Missed);
+    }
+
+    fn lost(&self) {
+        #[cfg(feature = "metrics")]
+        crate::metrics::lease_lost();
+        self.publish(SessionEvent::Lost);
+    }
+
+    /// End every subscription, once the session has ended
+    fn ended(&self) {
+        *self.subscribers.lock().unwrap() = None;
+    }
+}
+
+impl Default for LeaseHealth {
+    fn default() -> Self {
+        Self {
+            subscribers: StdMutex::new(Some(Vec::new())),
+        }
+    }
+}
+
+/// When each keep-alive sent on a stream was sent, until it is answered. The server answers
+/// them in order.
+#[derive(Debug, Clone, Default)]
+struct InFlight(Arc<StdMutex<VecDeque<Instant>>>);
+
+impl InFlight {
+    /// Record a keep-alive being sent, returning whether an earlier one is still unanswered
+    fn sent(&self) -> bool {
+        let mut sent = self.0.lock().unwrap();
+        sent.push_back(Instant::now());
+        sent.len() > 1
+    }
+
+    /// Time the oldest unanswered keep-alive took to be answered, now that it has been
+    fn answered(&self) -> Option<Duration> {
+        let sent = self.0.lock().unwrap().pop_front()?;
+        Some(sent.elapsed())
+    }
+
+    /// Whether any keep-alive is still unanswered
+    fn pending(&self) -> bool {
+        !self.0.lock().unwrap().is_empty()
+    }
+}
+
+/// A session's lease keep-alive stream
+struct KeepAlive<T> {
+    client: EtcdClient<T>,
+    lease: i64,
+    interval: Duration,
+    /// When the lease expires unless it is refreshed before then
+    expires: Instant,
+    /// Backoff between attempts to re-establish the stream
+    backoff: Backoff,
+    health: Arc<LeaseHealth>,
+}
+
+impl<T: Transport> KeepAlive<T> {
+    /// Keep the lease alive until it expires, or fail if the stream breaks first
+    async fn run(&mut self) -> EtcdResult<()> {
+        let (lease, interval) = (self.lease, self.interval);
+        let in_flight = InFlight::default();
+        let (sent, health) = (in_flight.clone(), self.health.clone());
+        let clock = self.client.config.clock.clone();
+        let mut ticks = clock.interval(interval);
+        let requests = async_stream::stream! {
+            // Refresh straight away, in case the stream was re-established late
+            sent.sent();
+            yield etcdserver::LeaseKeepAliveRequest { id: lease };
+            loop {
+                ticks.next().await;
+                if sent.sent() {
+                    health.missed();
+                }
+                yield etcdserver::LeaseKeepAliveRequest { id: lease };
+            }
+        };
+        let mut context = self.client.error_context("keep-alive", None);
+        let operation = self.client.operation("lease.keep_alive", &context);
+        // Keep-alives are not throttled, as shedding them would cost the session its lease
+        let call = async {
+            let mut responses = self
+                .client
+                .lease_client()
+                .lease_keep_alive(requests)
+                .await?
+                .into_inner();
+            while let Some(response) = responses.message().await? {
+                self.client.check_cluster_id(response.header.as_ref())?;
+                context.header = response.header;
+                let rtt = in_flight.answered();
+                // The lease has already expired
+                if response.ttl <= 0 {
+                    return Ok(());
+                }
+                self.expires = clock.now() + Duration::from_secs(response.ttl as u64);
+                self.backoff.reset();
+                if let Some(rtt) = rtt {
+                    self.health.kept_alive(rtt, response.ttl);
+                }
+            }
+            let ended = tonic::Status::new(tonic::Code::Unavailable, "keep-alive stream ended");
+            Err(ended.into())
+        };
+        let result = operation.run(call).await;
+        operation.finish(&context, &result);
+        if result.is_err() && in_flight.pending() {
+            self.health.missed();
+        }
+        // The stream only ends once it breaks or finds the lease expired
+        #[cfg(feature = "metrics")]
+        crate::metrics::keep_alive_failed();
+        result
+    }
+}
+
+/// A distributed mutex, held by at most one session at a time.
+///
+/// Each contender creates a key under the mutex's prefix attached to its session's lease, and
+/// the lock belongs to whichever key was created first. Waiting contenders watch only the key
+/// created just before theirs, so releasing the lock wakes a single waiter. The lock is
+/// released automatically if the holder's session ends.
+///
+/// The mutex is reentrant within a session: locking a mutex the session already holds, through
+/// this `Mutex` or any other for the same name, succeeds straight away and adds to a hold
+/// count. The lock is only released once it has been unlocked as many times as it was locked.
+pub struct Mutex<T> {
+    client: EtcdClient<T>,
+    holds: Arc<StdMutex<HashMap<Vec<u8>, Hold>>>,
+    alive: watch::Receiver<bool>,
+    prefix: Vec<u8>,
+    key: Vec<u8>,
+    lease: i64,
+    /// Create revision of our key while it exists
+    revision: Option<i64>,
+}
+
+impl<T> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mutex")
+            .field("prefix", &Key(&self.prefix))
+            .field("key", &Key(&self.key))
+            .field("lease", &self.lease)
+            .field("revision", &self.revision)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: Transport> Mutex<T> {
+    /// Mutex named `name`, contended for under `session`
+    pub fn new<N>(session: &Session<T>, name: N) -> Self
+    where
+        N: Into<Vec<u8>>,
+    {
+        let mut prefix = name.into();
+        prefix.push(b'/');
+        let mut key = prefix.clone();
+        key.extend(format!("{:x}", session.lease).into_bytes());
+
+        Self {
+            client: session.client.clone(),
+            holds: session.holds.clone(),
+            alive: session.alive.clone(),
+            prefix,
+            key,
+            lease: session.lease,
+            revision: None,
+        }
+    }
+
+    /// Acquire the mutex, waiting until it is free, and return a token proving ownership.
+    ///
+    /// If the returned future is dropped before the mutex is acquired, our key is deleted in
+    /// the background so it does not hold up the contenders queued behind it.
+    pub async fn lock(&mut self) -> EtcdResult<FencingToken> {
+        self.acquire(None).await
+    }
+
+    /// Acquire the mutex like [`lock`](Self::lock), but give up after waiting for `timeout`
+    pub async fn lock_timeout(&mut self, timeout: Duration) -> EtcdResult<FencingToken> {
+        self.acquire(Some(Instant::now() + timeout)).await
+    }
+
+    /// Acquire the mutex like [`lock`](Self::lock), but give up if it is not acquired by
+    /// `deadline`
+    pub async fn lock_until(&mut self, deadline: Instant) -> EtcdResult<FencingToken> {
+        self.acquire(Some(deadline)).await
+    }
+
+    /// Acquire the mutex if it is free, returning a token proving ownership if it was
+    /// acquired. Does not wait.
+    ///
+    /// If the mutex is not free, our key is only deleted if this call created it: a key which
+    /// already existed belongs to a wait under the same session, through this `Mutex` or
+    /// another for the same name.
+    pub async fn try_lock(&mut self) -> EtcdResult<Option<FencingToken>> {
+        if let Some(token) = self.reenter().await? {
+            return Ok(Some(token));
+        }
+        let revision = self.revision;
+        let (held, created) = self.try_acquire().await?;
+        if held {
+            return Ok(Some(self.hold()));
+        }
+        if created {
+            self.client.kv().delete(&self.key).await?;
+            self.revision = None;
+        } else {
+            self.revision = revision;
+        }
+        Ok(None)
+    }
+
+    /// Release the mutex, or give up waiting for it. If the session has locked the mutex more
+    /// than once, this only decrements the hold count.
+    pub async fn unlock(&mut self) -> EtcdResult<()> {
+        {
+            let mut holds = self.holds.lock().unwrap();
+            if let Some(hold) = holds.get_mut(&self.key) {
+                hold.count -= 1;
+                if hold.count > 0 {
+                    self.revision = None;
+                    return Ok(());
+                }
+                holds.remove(&self.key);
+            }
+        }
+
+        self.client.kv().delete(&self.key).await?;
+        self.revision = None;
+        Ok(())
+    }
+
+    /// Key identifying this contender, which exists while the mutex is held or waited on
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// Create revision of our key if the mutex has been acquired and not unlocked since
+    pub fn revision(&self) -> Option<i64> {
+        self.revision
+    }
+
+    /// Check with the server that we still own the mutex: it has been acquired, the session is
+    /// still alive and our key has not been removed. Worth calling before side effects which
+    /// must only happen under the lock, although a [`FencingToken`] is the safer guard.
+    pub async fn is_held(&mut self) -> EtcdResult<bool> {
+        let revision = match self.revision {
+            Some(revision) if *self.alive.get_ref() => revision,
+            _ => return Ok(false),
+        };
+        let response = self.client.kv().range_request(range(&self.key)).await?;
+        Ok(response.iter().any(|kv| kv.create_revision() == revision))
+    }
+
+    /// Key of whichever contender currently holds the mutex, if anyone does. Its value is
+    /// empty, but its lease identifies the holder's session.
+    pub async fn holder(&mut self) -> EtcdResult<Option<KeyValue>> {
+        let request = etcdserver::RangeRequest {
+            sort_order: etcdserver::range_request::SortOrder::Ascend as i32,
+            sort_target: etcdserver::range_request::SortTarget::Create as i32,
+            limit: 1,
+            ..range_prefix(&self.prefix)
+        };
+        let response = self.client.kv().range_request(request).await?;
+        Ok(response.into_iter().next())
+    }
+
+    async fn acquire(&mut self, deadline: Option<Instant>) -> EtcdResult<FencingToken> {
+        let started = Instant::now();
+        if let Some(token) = self.reenter().await? {
+            return Ok(token);
+        }
+
+        // A key we already had is not ours to clean up
+        let pending = match self.revision {
+            Some(_) => PendingKey::disarmed(&self.client),
+            None => PendingKey::new(&self.client, &self.key),
+        };
+        if self.try_acquire().await?.0 {
+            pending.disarm();
+            return Ok(self.hold());
+        }
+
+        let revision = self.revision.unwrap_or(0);
+        let wait = wait_for_older_keys(&self.client, &self.prefix, revision);
+        let waited = match deadline {
+            Some(deadline) => match runtime::timeout_at(wait, deadline).await {
+                Ok(waited) => waited,
+                Err(_) => {
+                    let timeout = deadline.saturating_duration_since(started);
+                    Err(Error::timeout(
+                        "timed out waiting for the lock",
+                        timeout,
+                        started,
+                    ))
+                }
+            },
+            None => wait.await,
+        };
+        pending.disarm();
+        if let Err(e) = waited {
+            // Do not leave our key behind to block the contenders after us
+            let _ = self.unlock().await;
+            return Err(e);
+        }
+
+        // Our key is attached to the session, so it disappears if the session expired while
+        // we were waiting
+        let response = self.client.kv().range_request(range(&self.key)).await?;
+        if response.is_empty() {
+            self.revision = None;
+            return Err(Error::LeaseNotFound(Some(self.lease)));
+        }
+        Ok(self.hold())
+    }
+
+    /// Add to the hold count if the session already holds the mutex. A hold whose key has
+    /// gone, because the session's lease expired, is forgotten.
+    async fn reenter(&mut self) -> EtcdResult<Option<FencingToken>> {
+        let revision = match self.holds.lock().unwrap().get(&self.key) {
+            Some(hold) => hold.revision,
+            None => return Ok(None),
+        };
+        let response = self.client.kv().range_request(range(&self.key)).await?;
+        let held = response.iter().any(|kv| kv.create_revision() == revision);
+
+        let mut holds = self.holds.lock().unwrap();
+        match holds.get_mut(&self.key) {
+            Some(hold) if held && hold.revision == revision => hold.count += 1,
+            _ => {
+                holds.remove(&self.key);
+                return Ok(None);
+            }
+        }
+        self.revision = Some(revision);
+        Ok(Some(self.fencing_token()))
+    }
+
+    /// Record that the mutex has been acquired by the session
+    fn hold(&mut self) -> FencingToken {
+        let revision = self.revision.unwrap_or(0);
+        let mut holds = self.holds.lock().unwrap();
+        let hold = holds
+            .entry(self.key.clone())
+            .or_insert(Hold { count: 0, revision });
+        // Our key was recreated since the session last held the mutex
+        if hold.revision != revision {
+            *hold = Hold { count: 0, revision };
+        }
+        hold.count += 1;
+        self.fencing_token()
+    }
+
+    fn fencing_token(&self) -> FencingToken {
+        FencingToken {
+            key: self.key.clone(),
+            revision: self.revision.unwrap_or(0),
+        }
+    }
+
+    /// Create our key if it does not exist yet, and find out whether it is the oldest key
+    /// under the prefix and whether this call created it
+    async fn try_acquire(&mut self) -> EtcdResult<(bool, bool)> {
+        let created = etcdserver::Compare {
+            result: etcdserver::compare::CompareResult::Equal as i32,
+            target: etcdserver::compare::CompareTarget::Create as i32,
+            key: self.key.clone(),
+            target_union: Some(etcdserver::compare::TargetUnion::CreateRevision(0)),
+            range_end: Vec::new(),
+        };
+        let put = etcdserver::PutRequest {
+            key: self.key.clone(),
+            lease: self.lease,
+            ..Default::default()
+        };
+        let owner = etcdserver::RangeRequest {
+            sort_order: etcdserver::range_request::SortOrder::Ascend as i32,
+            sort_target: etcdserver::range_request::SortTarget::Create as i32,
+            limit: 1,
+            ..range_prefix(&self.prefix)
+        };
+        let request = etcdserver::TxnRequest {
+            compare: vec![created],
+            success: vec![
+                request_op(etcdserver::request_op::Request::RequestPut(put)),
+                request_op(etcdserver::request_op::Request::RequestRange(owner.clone())),
+            ],
+            failure: vec![
+                request_op(etcdserver::request_op::Request::RequestRange(range(
+                    &self.key,
+                ))),
+                request_op(etcdserver::request_op::Request::RequestRange(owner)),
+            ],
+        };
+        let response = self.client.kv().txn(request).await?;
+
+        let mut ranges = txn_ranges(&response);
+        let revision = if response.succeeded() {
+            response.revision()
+        } else {
+            match ranges.next().and_then(|r| r.kvs().first()) {
+                Some(kv) => kv.create_revision(),
+                None => {
+                    return Err(Error::KeyNotFound(
+                        String::from_utf8_lossy(&self.key).into_owned(),
+                    ))
+                }
+            }
+        };
+        self.revision = Some(revision);
+
+        let held = match ranges.next().and_then(|r| r.kvs().first()) {
+            Some(kv) => kv.create_revision() == revision,
+            None => true,
+        };
+        Ok((held, response.succeeded()))
+    }
+}
+
+/// Options for an [`RwLock`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RwLockOptions {
+    /// Give up waiting for the lock after this long, rather than waiting indefinitely
+    pub timeout: Option<Duration>,
+}
+
+/// A distributed read-write lock, held either by any number of readers or by a single writer.
+///
+/// Contenders are served fairly, in the order they asked for the lock: a reader only waits
+/// for writers which asked before it, and a writer waits for everyone which asked before it.
+/// A steady stream of readers therefore cannot starve a waiting writer. As with [`Mutex`],
+/// contenders' keys are attached to their session's lease and each waiter only watches the
+/// key it is waiting on. Each handle has keys of its own, so handles on one session contend
+/// like handles on different sessions.
+pub struct RwLock<T> {
+    client: EtcdClient<T>,
+    prefix: Vec<u8>,
+    alive: watch::Receiver<bool>,
+    lease: i64,
+    /// Tells our keys apart from those of other handles on the same session
+    id: u64,
+    options: RwLockOptions,
+    /// Our key and its create revision while the lock is held or waited on
+    token: Option<FencingToken>,
+}
+
+impl<T> fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RwLock")
+            .field("prefix", &Key(&self.prefix))
+            .field("lease", &self.lease)
+            .field("options", &self.options)
+            .field("token", &self.token)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: Transport> RwLock<T> {
+    /// Read-write lock named `name`, contended for under `session`
+    pub fn new<N>(session: &Session<T>, name: N) -> Self
+    where
+        N: Into<Vec<u8>>,
+    {
+        Self::with_options(session, name, RwLockOptions::default())
+    }
+
+    /// Read-write lock named `name`, see [`RwLockOptions`]
+    pub fn with_options<N>(session: &Session<T>, name: N, options: RwLockOptions) -> Self
+    where
+        N: Into<Vec<u8>>,
+    {
+        let mut prefix = name.into();
+        prefix.push(b'/');
+
+        Self {
+            client: session.client.clone(),
+            alive: session.alive.clone(),
+            prefix,
+            lease: session.lease,
+            id: crate::random(),
+            options,
+            token: None,
+        }
+    }
+
+    /// Acquire the lock for reading, waiting until no writer which asked first holds it
+    pub async fn read_lock(&mut self) -> EtcdResult<FencingToken> {
+        self.acquire("read").await
+    }
+
+    /// Acquire the lock for writing, waiting until everyone which asked first has released it
+    pub async fn write_lock(&mut self) -> EtcdResult<FencingToken> {
+        self.acquire("write").await
+    }
+
+    /// Release the lock, or give up waiting for it
+    pub async fn unlock(&mut self) -> EtcdResult<()> {
+        if let Some(token) = &self.token {
+            self.client.kv().delete(&token.key).await?;
+        }
+        self.token = None;
+        Ok(())
+    }
+
+    /// Our key while the lock is held
+    pub fn key(&self) -> Option<&[u8]> {
+        self.token.as_ref().map(FencingToken::key)
+    }
+
+    /// Create revision of our key while the lock is held
+    pub fn revision(&self) -> Option<i64> {
+        self.token.as_ref().map(FencingToken::revision)
+    }
+
+    /// Check with the server that we still hold the lock, in either mode: it has been
+    /// acquired, the session is still alive and our key has not been removed
+    pub async fn is_held(&mut self) -> EtcdResult<bool> {
+        let token = match &self.token {
+            Some(token) if *self.alive.get_ref() => token,
+            _ => return Ok(false),
+        };
+        let response = self.client.kv().range_request(range(&token.key)).await?;
+        Ok(response
+            .iter()
+            .any(|kv| kv.create_revision() == token.revision))
+    }
+
+    async fn acquire(&mut self, kind: &str) -> EtcdResult<FencingToken> {
+        let started = Instant::now();
+        if self.token.is_some() {
+            return Err("the lock is already held or waited on".into());
+        }
+
+        let mut key = self.prefix.clone();
+        key.extend(format!("{}/{:x}/{:x}", kind, self.lease, self.id).into_bytes());
+        let pending = PendingKey::new(&self.client, &key);
+        let revision = create_key(&self.client, &key, self.lease).await?;
+        let token = FencingToken { key, revision };
+        self.token = Some(token.clone());
+
+        // Readers can share the lock, so they only queue behind writers
+        let mut waiting_on = self.prefix.clone();
+        if kind == "read" {
+            waiting_on.extend(b"write/");
+        }
+        let wait = wait_for_older_keys(&self.client, &waiting_on, revision);
+        let waited = match self.options.timeout {
+            Some(timeout) => match runtime::timeout(wait, timeout).await {
+                Ok(waited) => waited,
+                Err(_) => Err(Error::timeout(
+                    "timed out waiting for the lock",
+                    timeout,
+                    started,
+                )),
+            },
+            None => wait.await,
+        };
+        pending.disarm();
+        if let Err(e) = waited {
+            let _ = self.unlock().await;
+            return Err(e);
+        }
+
+        let response = self.client.kv().range_request(range(&token.key)).await?;
+        if response.is_empty() {
+            self.token = None;
+            return Err(Error::LeaseNotFound(Some(self.lease)));
+        }
+        Ok(token)
+    }
+}
+
+/// Proof that a lock or leadership was held, for guarding writes against holders which have
+/// lost it without noticing, for example after a long pause.
+///
+/// The token names the key which existed while the lock was held and the revision it was
+/// created at. Later holders always have a higher revision, so it can also be handed to
+/// other systems to reject requests from stale holders.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FencingToken {
+    key: Vec<u8>,
+    revision: i64,
+}
+
+impl FencingToken {
+    /// Key which exists for as long as the lock or leadership is held
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// Create revision of the key, which increases with each new holder
+    pub fn revision(&self) -> i64 {
+        self.revision
+    }
+
+    /// Compare for a transaction which only succeeds while the lock or leadership is still
+    /// held, because the key still exists with the same create revision
+    pub fn compare(&self) -> etcdserver::Compare {
+        etcdserver::Compare {
+            result: etcdserver::compare::CompareResult::Equal as i32,
+            target: etcdserver::compare::CompareTarget::Create as i32,
+            key: self.key.clone(),
+            target_union: Some(etcdserver::compare::TargetUnion::CreateRevision(
+                self.revision,
+            )),
+            range_end: Vec::new(),
+        }
+    }
+
+    /// Put `value` at `key` only if the lock or leadership is still held. Returns an error
+    /// without writing anything if it has been lost.
+    ///
+    /// Both `key` and the token's own key are inside `client`'s namespace, so the token should
+    /// be used with a client in the namespace of the one it was acquired through.
+    pub async fn put<T, K, V>(&self, client: &EtcdClient<T>, key: K, value: V) -> EtcdResult<()>
+    where
+        T: Transport,
+        K: Into<Vec<u8>>,
+        V: Into<Vec<u8>>,
+    {
+        let put = etcdserver::PutRequest {
+            key: key.into(),
+            value: value.into(),
+            ..Default::default()
+        };
+        let request = etcdserver::TxnRequest {
+            compare: vec![self.compare()],
+            success: vec![request_op(etcdserver::request_op::Request::RequestPut(put))],
+            failure: Vec::new(),
+        };
+        if !client.kv().txn(request).await?.succeeded() {
+            return Err(format!(
+                "fencing token {} for {:?} is stale",
+                self.revision,
+                String::from_utf8_lossy(&self.key)
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "election")]
+impl From<&v3election::LeaderKey> for FencingToken {
+    fn from(leader: &v3election::LeaderKey) -> Self {
+        Self {
+            key: leader.key.clone(),
+            revision: leader.rev,
+        }
+    }
+}
+
+/// A contender's key which is deleted in the background when dropped, unless disarmed first.
+///
+/// Acquisition holds one of these while it waits, so a dropped acquisition future does not
+/// leave its key behind to block everyone queued after it until the session ends.
+struct PendingKey<T: Transport> {
+    client: EtcdClient<T>,
+    key: Option<Vec<u8>>,
+}
+
+impl<T: Transport> PendingKey<T> {
+    fn new(client: &EtcdClient<T>, key: &[u8]) -> Self {
+        Self {
+            client: client.clone(),
+            key: Some(key.to_vec()),
+        }
+    }
+
+    fn disarmed(client: &EtcdClient<T>) -> Self {
+        Self {
+            client: client.clone(),
+            key: None,
+        }
+    }
+
+    fn disarm(mut self) {
+        self.key = None;
+    }
+}
+
+impl<T: Transport> Drop for PendingKey<T> {
+    fn drop(&mut self) {
+        let key = match self.key.take() {
+            Some(key) => key,
+            None => return,
+        };
+        let kv = self.client.kv();
+        let cleanup = async move {
+            let _ = kv.delete(key).await;
+        };
+        // Outside of a runtime the key is left for the session's lease to remove
+        let _ = runtime::try_spawn(cleanup);
+    }
+}
+
+/// Create `key` attached to `lease` if it does not already exist, returning its create
+/// revision
+async fn create_key<T: Transport>(
+    client: &EtcdClient<T>,
+    key: &[u8],
+    lease: i64,
+) -> EtcdResult<i64> {
+    let request = etcdserver::TxnRequest {
+        compare: vec![etcdserver::Compare {
+            result: etcdserver::compare::CompareResult::Equal as i32,
+            target: etcdserver::compare::CompareTarget::Create as i32,
+            key: key.to_vec(),
+            target_union: Some(etcdserver::compare::TargetUnion::CreateRevision(0)),
+            range_end: Vec::new(),
+        }],
+        success: vec![request_op(etcdserver::request_op::Request::RequestPut(
+            etcdserver::PutRequest {
+                key: key.to_vec(),
+                lease,
+                ..Default::default()
+            },
+        ))],
+        failure: vec![request_op(etcdserver::request_op::Request::RequestRange(
+            range(key),
+        ))],
+    };
+    let response = client.kv().txn(request).await?;
+
+    if response.succeeded() {
+        return Ok(response.revision());
+    }
+    let existing = txn_ranges(&response).find_map(|r| r.kvs().first());
+    match existing {
+        Some(kv) => Ok(kv.create_revision()),
+        None => Err(Error::KeyNotFound(
+            String::from_utf8_lossy(key).into_owned(),
+        )),
+    }
+}
+
+/// Wait until every key under `prefix` created before `revision` has been deleted, watching
+/// the newest of them each time
+async fn wait_for_older_keys<T: Transport>(
+    client: &EtcdClient<T>,
+    prefix: &[u8],
+    revision: i64,
+) -> EtcdResult<()> {
+    loop {
+        let request = etcdserver::RangeRequest {
+            sort_order: etcdserver::range_request::SortOrder::Descend as i32,
+            sort_target: etcdserver::range_request::SortTarget::Create as i32,
+            limit: 1,
+            max_create_revision: revision - 1,
+            ..range_prefix(prefix)
+        };
+        let response = client.kv().range_request(request).await?;
+        let read_at = response.revision();
+        let predecessor = match response.into_iter().next() {
+            Some(kv) => kv,
+            None => return Ok(()),
+        };
+        wait_for_delete(client, range(predecessor.key()), read_at).await?;
+    }
+}
+
+/// A distributed counting semaphore, letting up to a fixed number of holders in at once
+/// across the cluster.
+///
+/// Each holder has a key under the semaphore's prefix attached to its session's lease, and the
+/// holders of the oldest `permits` keys have a permit. If a holder dies its lease expires, its
+/// key is removed and the permit passes to the next waiter. Every user of a semaphore must
+/// agree on the number of permits. Each handle has a key of its own, so several handles on one
+/// session take a permit each.
+pub struct Semaphore<T> {
+    session: Session<T>,
+    prefix: Vec<u8>,
+    key: Vec<u8>,
+    permits: usize,
+    /// Create revision of our key while it exists
+    revision: Option<i64>,
+}
+
+impl<T> fmt::Debug for Semaphore<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Semaphore")
+            .field("prefix", &Key(&self.prefix))
+            .field("key", &Key(&self.key))
+            .field("permits", &self.permits)
+            .field("revision", &self.revision)
+            .field("session", &self.session)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: Transport> Semaphore<T> {
+    /// Semaphore named `name` with `permits` permits, contended for under `session`
+    pub fn new<N>(session: &Session<T>, name: N, permits: usize) -> EtcdResult<Self>
+    where
+        N: Into<Vec<u8>>,
+    {
+        if permits == 0 {
+            return Err("a semaphore needs at least one permit".into());
+        }
+        let session = session.clone();
+
+        let mut prefix = name.into();
+        prefix.push(b'/');
+        let mut key = prefix.clone();
+        key.extend(format!("{:x}/{:x}", session.lease, crate::random()).into_bytes());
+
+        Ok(Self {
+            session,
+            prefix,
+            key,
+            permits,
+            revision: None,
+        })
+    }
+
+    /// Take a permit, waiting until one is free
+    pub async fn acquire(&mut self) -> EtcdResult<()> {
+        let revision = self.enqueue().await?;
+        let waited = self.wait_for_permit(revision).await;
+        if let Err(e) = waited {
+            let _ = self.release().await;
+            return Err(e);
+        }
+
+        let response = self
+            .session
+            .client
+            .kv()
+            .range_request(range(&self.key))
+            .await?;
+        if response.is_empty() {
+            self.revision = None;
+            return Err(Error::LeaseNotFound(Some(self.session.lease)));
+        }
+        Ok(())
+    }
+
+    /// Take a permit if one is free, returning whether one was taken. Does not wait.
+    pub async fn try_acquire(&mut self) -> EtcdResult<bool> {
+        let revision = self.enqueue().await?;
+        let (free, _) = self.permit_free(revision).await?;
+        if !free {
+            self.release().await?;
+        }
+        Ok(free)
+    }
+
+    /// Give back the permit, or give up waiting for one
+    pub async fn release(&mut self) -> EtcdResult<()> {
+        self.session.client.kv().delete(&self.key).await?;
+        self.revision = None;
+        Ok(())
+    }
+
+    /// Number of permits, as given when the semaphore was created
+    pub fn permits(&self) -> usize {
+        self.permits
+    }
+
+    async fn enqueue(&mut self) -> EtcdResult<i64> {
+        let revision = create_key(&self.session.client, &self.key, self.session.lease).await?;
+        self.revision = Some(revision);
+        Ok(revision)
+    }
+
+    /// Whether fewer than `permits` keys were created before ours, and the revision this was
+    /// checked at
+    async fn permit_free(&mut self, revision: i64) -> EtcdResult<(bool, i64)> {
+        let request = etcdserver::RangeRequest {
+            count_only: true,
+            max_create_revision: revision - 1,
+            ..range_prefix(&self.prefix)
+        };
+        let response = self.session.client.kv().range_request(request).await?;
+        Ok((
+            (response.count() as usize) < self.permits,
+            response.revision(),
+        ))
+    }
+
+    async fn wait_for_permit(&mut self, revision: i64) -> EtcdResult<()> {
+        loop {
+            let (free, read_at) = self.permit_free(revision).await?;
+            if free {
+                return Ok(());
+            }
+            // Any holder leaving may free a permit for us
+            let holders = range_prefix(&self.prefix);
+            wait_for_delete(&self.session.client, holders, read_at).await?;
+        }
+    }
+}
+
+/// A barrier which blocks processes until it is released.
+///
+/// The barrier is up while its key exists. It is not attached to a lease, so it stays up
+/// if the process holding it dies; someone must then call [`Barrier::release`].
+pub struct Barrier<T> {
+    client: EtcdClient<T>,
+    key: Vec<u8>,
+}
+
+impl<T> fmt::Debug for Barrier<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Barrier")
+            .field("key", &Key(&self.key))
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: Transport> Barrier<T> {
+    /// Barrier stored at `key`
+    pub fn new<K>(client: &EtcdClient<T>, key: K) -> Self
+    where
+        K: Into<Vec<u8>>,
+    {
+        Self {
+            client: client.clone(),
+            key: key.into(),
+        }
+    }
+
+    /// Put the barrier up, so that [`Barrier::wait`] blocks. Fails if it is already up.
+    pub async fn hold(&mut self) -> EtcdResult<()> {
+        let created = etcdserver::Compare {
+            result: etcdserver::compare::CompareResult::Equal as i32,
+            target: etcdserver::compare::CompareTarget::Create as i32,
+            key: self.key.clone(),
+            target_union: Some(etcdserver::compare::TargetUnion::CreateRevision(0)),
+            range_end: Vec::new(),
+        };
+        let put = etcdserver::PutRequest {
+            key: self.key.clone(),
+            ..Default::default()
+        };
+        let request = etcdserver::TxnRequest {
+            compare: vec![created],
+            success: vec![request_op(etcdserver::request_op::Request::RequestPut(put))],
+            failure: Vec::new(),
+        };
+        if !self.client.kv().txn(request).await?.succeeded() {
+            return Err("the barrier is already held".into());
+        }
+        Ok(())
+    }
+
+    /// Take the barrier down, letting every waiter through
+    pub async fn release(&mut self) -> EtcdResult<()> {
+        self.client.kv().delete(&self.key).await?;
+        Ok(())
+    }
+
+    /// Wait until the barrier is down. Returns straight away if it is not up.
+    pub async fn wait(&mut self) -> EtcdResult<()> {
+        let response = self.client.kv().range_request(range(&self.key)).await?;
+        if response.is_empty() {
+            return Ok(());
+        }
+        wait_for_delete(&self.client, range(&self.key), response.revision()).await
+    }
+}
+
+/// A barrier for a fixed number of participants, which blocks them both on the way in and on
+/// the way out: [`DoubleBarrier::enter`] returns once all of them have entered, and
+/// [`DoubleBarrier::leave`] once all of them have left.
+///
+/// Participants' keys are attached to their session's lease, so a participant which dies
+/// stops being counted once its lease expires.
+pub struct DoubleBarrier<T> {
+    client: EtcdClient<T>,
+    /// Key which exists once every participant has entered
+    ready: Vec<u8>,
+    /// Prefix of the participants' keys
+    waiters: Vec<u8>,
+    key: Vec<u8>,
+    lease: i64,
+    count: usize,
+}
+
+impl<T> fmt::Debug for DoubleBarrier<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DoubleBarrier")
+            .field("key", &Key(&self.key))
+            .field("lease", &self.lease)
+            .field("count", &self.count)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: Transport> DoubleBarrier<T> {
+    /// Double barrier stored under `name`, for `count` participants
+    pub fn new<N>(session: &Session<T>, name: N, count: usize) -> Self
+    where
+        N: Into<Vec<u8>>,
+    {
+        let name = name.into();
+        let mut ready = name.clone();
+        ready.extend(b"/ready");
+        let mut waiters = name;
+        waiters.extend(b"/waiters/");
+        let mut key = waiters.clone();
+        key.extend(format!("{:x}", session.lease).into_bytes());
+
+        Self {
+            client: session.client.clone(),
+            ready,
+            waiters,
+            key,
+            lease: session.lease,
+            count,
+        }
+    }
+
+    /// Enter the barrier, waiting until every participant has entered
+    pub async fn enter(&mut self) -> EtcdResult<()> {
+        create_key(&self.client, &self.key, self.lease).await?;
+
+        let request = etcdserver::RangeRequest {
+            count_only: true,
+            ..range_prefix(&self.waiters)
+        };
+        let response = self.client.kv().range_request(request).await?;
+        let entered = response.count() as usize;
+        if entered > self.count {
+            let _ = self.delete_key().await;
+            return Err(format!(
+                "too many participants: {} entered a barrier for {}",
+                entered, self.count
+            )
+            .into());
+        }
+
+        if entered == self.count {
+            // We were the last one in, so let everyone through
+            self.client.kv().put(&self.ready, "").await?;
+            return Ok(());
+        }
+
+        let read_at = response.revision();
+        let ready = self.client.kv().range_request(range(&self.ready)).await?;
+        if !ready.is_empty() {
+            return Ok(());
+        }
+        wait_for_put(&self.client, range(&self.ready), read_at).await
+    }
+
+    /// Leave the barrier, waiting until every participant has left.
+    ///
+    /// The participant whose key is oldest leaves last and takes the barrier down for the next
+    /// round; everyone else removes their key straight away and waits for the oldest key to go.
+    pub async fn leave(&mut self) -> EtcdResult<()> {
+        loop {
+            let request = etcdserver::RangeRequest {
+                sort_order: etcdserver::range_request::SortOrder::Ascend as i32,
+                sort_target: etcdserver::range_request::SortTarget::Create as i32,
+                ..range_prefix(&self.waiters)
+            };
+            let response = self.client.kv().range_request(request).await?;
+            let read_at = response.revision();
+            let (lowest, highest) = match (response.kvs().first(), response.kvs().last()) {
+                (Some(lowest), Some(highest)) => (lowest.key().to_vec(), highest.key().to_vec()),
+                _ => return Ok(()),
+            };
+
+            if lowest == self.key && highest == self.key {
+                // Everyone else has gone, so finish up
+                self.client.kv().delete(&self.ready).await?;
+                return self.delete_key().await;
+            }
+
+            if lowest == self.key {
+                wait_for_delete(&self.client, range(&highest), read_at).await?;
+            } else {
+                self.delete_key().await?;
+                wait_for_delete(&self.client, range(&lowest), read_at).await?;
+            }
+        }
+    }
+
+    async fn delete_key(&mut self) -> EtcdResult<()> {
+        self.client.kv().delete(&self.key).await?;
+        Ok(())
+    }
+}
+
+/// A distributed FIFO queue.
+///
+/// Items are stored under the queue's prefix with sequence numbered keys, and each item is
+/// handed to exactly one caller of [`Queue::dequeue`].
+pub struct Queue<T> {
+    client: EtcdClient<T>,
+    prefix: Vec<u8>,
+}
+
+impl<T> fmt::Debug for Queue<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Queue")
+            .field("prefix", &Key(&self.prefix))
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: Transport> Queue<T> {
+    /// Queue named `name`
+    pub fn new<N>(client: &EtcdClient<T>, name: N) -> Self
+    where
+        N: Into<Vec<u8>>,
+    {
+        let mut prefix = name.into();
+        prefix.push(b'/');
+        Self {
+            client: client.clone(),
+            prefix,
+        }
+    }
+
+    /// Add an item to the back of the queue
+    pub async fn enqueue<V>(&mut self, value: V) -> EtcdResult<()>
+    where
+        V: Into<Vec<u8>>,
+    {
+        put_sequential(&self.client, &self.prefix, value.into()).await
+    }
+
+    /// Take the item at the front of the queue, waiting for one to be added if it is empty
+    pub async fn dequeue(&mut self) -> EtcdResult<Vec<u8>> {
+        dequeue(&self.client, &self.prefix).await
+    }
+
+    /// Take the item at the front of the queue, or `None` if it is empty. Does not wait.
+    pub async fn try_dequeue(&mut self) -> EtcdResult<Option<Vec<u8>>> {
+        Ok(claim_first(&self.client, &self.prefix).await?.0)
+    }
+
+    /// Number of items in the queue
+    pub async fn len(&mut self) -> EtcdResult<usize> {
+        queue_len(&self.client, &self.prefix).await
+    }
+
+    /// Whether the queue is empty
+    pub async fn is_empty(&mut self) -> EtcdResult<bool> {
+        Ok(self.len().await? == 0)
+    }
+}
+
+/// A distributed priority queue, handing out items with the lowest priority value first and
+/// items of equal priority in the order they were added.
+pub struct PriorityQueue<T> {
+    client: EtcdClient<T>,
+    prefix: Vec<u8>,
+}
+
+impl<T> fmt::Debug for PriorityQueue<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PriorityQueue")
+            .field("prefix", &Key(&self.prefix))
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: Transport> PriorityQueue<T> {
+    /// Priority queue named `name`
+    pub fn new<N>(client: &EtcdClient<T>, name: N) -> Self
+    where
+        N: Into<Vec<u8>>,
+    {
+        let mut prefix = name.into();
+        prefix.push(b'/');
+        Self {
+            client: client.clone(),
+            prefix,
+        }
+    }
+
+    /// Add an item with the given priority, where lower values are dequeued first
+    pub async fn enqueue<V>(&mut self, value: V, priority: u16) -> EtcdResult<()>
+    where
+        V: Into<Vec<u8>>,
+    {
+        // Fixed width, so that keys sort by priority
+        let mut prefix = self.prefix.clone();
+        prefix.extend(format!("{:05}/", priority).into_bytes());
+        put_sequential(&self.client, &prefix, value.into()).await
+    }
+
+    /// Take the item with the lowest priority value, waiting for one to be added if the queue
+    /// is empty
+    pub async fn dequeue(&mut self) -> EtcdResult<Vec<u8>> {
+        dequeue(&self.client, &self.prefix).await
+    }
+
+    /// Take the item with the lowest priority value, or `None` if the queue is empty. Does not
+    /// wait.
+    pub async fn try_dequeue(&mut self) -> EtcdResult<Option<Vec<u8>>> {
+        Ok(claim_first(&self.client, &self.prefix).await?.0)
+    }
+
+    /// Number of items in the queue
+    pub async fn len(&mut self) -> EtcdResult<usize> {
+        queue_len(&self.client, &self.prefix).await
+    }
+
+    /// Whether the queue is empty
+    pub async fn is_empty(&mut self) -> EtcdResult<bool> {
+        Ok(self.len().await? == 0)
+    }
+}
+
+/// A distributed work queue, where workers claim jobs and acknowledge them once they are done.
+///
+/// Jobs are stored under `name/items/` with sequence numbered keys, like a [`Queue`]. A worker
+/// claims a job by creating its key under `name/claims/`, attached to the worker's session,
+/// and the job is only removed when the claim is acknowledged. If the worker's session ends
+/// first, the claim goes away with it and the job becomes pending again for another worker.
+pub struct WorkQueue<T> {
+    client: EtcdClient<T>,
+    lease: i64,
+    items: Vec<u8>,
+    claims: Vec<u8>,
+}
+
+impl<T> fmt::Debug for WorkQueue<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WorkQueue")
+            .field("items", &Key(&self.items))
+            .field("claims", &Key(&self.claims))
+            .field("lease", &self.lease)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A job claimed from a [`WorkQueue`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Job {
+    item: Vec<u8>,
+    claim: Vec<u8>,
+    value: Vec<u8>,
+}
+
+impl Job {
+    /// The job's payload, as it was added to the queue
+    pub fn value(&self) -> &[u8] {
+        &self.value
+    }
+}
+
+impl<T: Transport> WorkQueue<T> {
+    /// Work queue named `name`, claiming jobs under `session`
+    pub fn new<N>(session: &Session<T>, name: N) -> Self
+    where
+        N: Into<Vec<u8>>,
+    {
+        let name = name.into();
+        let mut items = name.clone();
+        items.extend(b"/items/");
+        let mut claims = name;
+        claims.extend(b"/claims/");
+
+        Self {
+            client: session.client.clone(),
+            lease: session.lease,
+            items,
+            claims,
+        }
+    }
+
+    /// Add a job to the back of the queue
+    pub async fn enqueue<V>(&mut self, value: V) -> EtcdResult<()>
+    where
+        V: Into<Vec<u8>>,
+    {
+        put_sequential(&self.client, &self.items, value.into()).await
+    }
+
+    /// Claim the oldest pending job, waiting for one to be added or returned if there are none
+    pub async fn claim(&mut self) -> EtcdResult<Job> {
+        loop {
+            let (job, read_at) = self.claim_first().await?;
+            if let Some(job) = job {
+                return Ok(job);
+            }
+
+            let added = wait_for_put(&self.client, range_prefix(&self.items), read_at);
+            let returned = wait_for_delete(&self.client, range_prefix(&self.claims), read_at);
+            futures::pin_mut!(added);
+            futures::pin_mut!(returned);
+            match future::select(added, returned).await {
+                Either::Left((waited, _)) | Either::Right((waited, _)) => waited?,
+            }
+        }
+    }
+
+    /// Claim the oldest pending job, or `None` if there are none. Does not wait.
+    pub async fn try_claim(&mut self) -> EtcdResult<Option<Job>> {
+        Ok(self.claim_first().await?.0)
+    }
+
+    /// Acknowledge that `job` is done, removing it from the queue. Fails if the claim on it was
+    /// lost, as the job may then have been handed to another worker.
+    pub async fn ack(&mut self, job: Job) -> EtcdResult<()> {
+        let request = etcdserver::TxnRequest {
+            compare: vec![self.claimed(&job)],
+            success: vec![delete_op(job.item), delete_op(job.claim)],
+            failure: Vec::new(),
+        };
+        if !self.client.kv().txn(request).await?.succeeded() {
+            return Err("the claim on the job was lost before it was acknowledged".into());
+        }
+        Ok(())
+    }
+
+    /// Give up a claimed job without completing it, returning it to the queue for another
+    /// worker
+    pub async fn release(&mut self, job: Job) -> EtcdResult<()> {
+        let request = etcdserver::TxnRequest {
+            compare: vec![self.claimed(&job)],
+            success: vec![delete_op(job.claim)],
+            failure: Vec::new(),
+        };
+        self.client.kv().txn(request).await?;
+        Ok(())
+    }
+
+    /// Number of jobs in the queue, whether pending or claimed
+    pub async fn len(&mut self) -> EtcdResult<usize> {
+        queue_len(&self.client, &self.items).await
+    }
+
+    /// Whether the queue has no jobs, pending or claimed
+    pub async fn is_empty(&mut self) -> EtcdResult<bool> {
+        Ok(self.len().await? == 0)
+    }
+
+    /// Comparison which holds while `job` is still claimed by us
+    fn claimed(&self, job: &Job) -> etcdserver::Compare {
+        etcdserver::Compare {
+            result: etcdserver::compare::CompareResult::Equal as i32,
+            target: etcdserver::compare::CompareTarget::Lease as i32,
+            key: job.claim.clone(),
+            target_union: Some(etcdserver::compare::TargetUnion::Lease(self.lease)),
+            range_end: Vec::new(),
+        }
+    }
+
+    /// Claim the first job without a claim, along with the revision the queue was read at.
+    /// Retries if another worker claims the same job first.
+    async fn claim_first(&mut self) -> EtcdResult<(Option<Job>, i64)> {
+        loop {
+            // Read the jobs and claims at the same revision
+            let items = etcdserver::RangeRequest {
+                sort_order: etcdserver::range_request::SortOrder::Ascend as i32,
+                sort_target: etcdserver::range_request::SortTarget::Key as i32,
+                ..range_prefix(&self.items)
+            };
+            let claims = etcdserver::RangeRequest {
+                keys_only: true,
+                ..range_prefix(&self.claims)
+            };
+            let request = etcdserver::TxnRequest {
+                compare: Vec::new(),
+                success: vec![
+                    request_op(etcdserver::request_op::Request::RequestRange(items)),
+                    request_op(etcdserver::request_op::Request::RequestRange(claims)),
+                ],
+                failure: Vec::new(),
+            };
+            let response = self.client.kv().txn(request).await?;
+            let read_at = response.revision();
+            let mut ranges = txn_ranges(&response);
+            let (items, claims) = match (ranges.next(), ranges.next()) {
+                (Some(items), Some(claims)) => (items.kvs(), claims.kvs()),
+                _ => {
+                    let missing = "txn response is missing the queue's ranges";
+                    return Err(Error::invalid_response(missing));
+                }
+            };
+            let mut claimed = HashSet::new();
+            for kv in claims {
+                claimed.insert(key_suffix(kv.key(), &self.claims)?);
+            }
+
+            let mut pending = None;
+            for kv in items {
+                if !claimed.contains(key_suffix(kv.key(), &self.items)?) {
+                    pending = Some(kv);
+                    break;
+                }
+            }
+            let item = match pending {
+                Some(item) => item,
+                None => return Ok((None, read_at)),
+            };
+
+            let mut claim = self.claims.clone();
+            claim.extend(key_suffix(item.key(), &self.items)?);
+            let present = etcdserver::Compare {
+                result: etcdserver::compare::CompareResult::Equal as i32,
+                target: etcdserver::compare::CompareTarget::Mod as i32,
+                key: item.key().to_vec(),
+                target_union: Some(etcdserver::compare::TargetUnion::ModRevision(
+                    item.mod_revision(),
+                )),
+                range_end: Vec::new(),
+            };
+            let unclaimed = etcdserver::Compare {
+                result: etcdserver::compare::CompareResult::Equal as i32,
+                target: etcdserver::compare::CompareTarget::Create as i32,
+                key: claim.clone(),
+                target_union: Some(etcdserver::compare::TargetUnion::CreateRevision(0)),
+                range_end: Vec::new(),
+            };
+            let put = etcdserver::PutRequest {
+                key: claim.clone(),
+                lease: self.lease,
+                ..Default::default()
+            };
+            let request = etcdserver::TxnRequest {
+                compare: vec![present, unclaimed],
+                success: vec![request_op(etcdserver::request_op::Request::RequestPut(put))],
+                failure: Vec::new(),
+            };
+            if self.client.kv().txn(request).await?.succeeded() {
+                let job = Job {
+                    item: item.key().to_vec(),
+                    claim,
+                    value: item.value().to_vec(),
+                };
+                return Ok((Some(job), read_at));
+            }
+        }
+    }
+}
+
+/// Put `value` at the key under `prefix` with the next sequence number.
+///
+/// The sequence number is one more than that of the last key under the prefix. Writers
+/// racing for the same number are serialized by also writing a marker key outside the prefix
+/// and only committing if it has not changed since the last key was read.
+async fn put_sequential<T: Transport>(
+    client: &EtcdClient<T>,
+    prefix: &[u8],
+    value: Vec<u8>,
+) -> EtcdResult<()> {
+    let mut marker = b"__".to_vec();
+    marker.extend(prefix);
+
+    loop {
+        let request = etcdserver::RangeRequest {
+            sort_order: etcdserver::range_request::SortOrder::Descend as i32,
+            sort_target: etcdserver::range_request::SortTarget::Key as i32,
+            limit: 1,
+            keys_only: true,
+            ..range_prefix(prefix)
+        };
+        let response = client.kv().range_request(request).await?;
+        let read_at = response.revision();
+        let next = match response.kvs().first() {
+            Some(kv) => next_sequence(key_suffix(kv.key(), prefix)?)?,
+            None => 0,
+        };
+
+        let mut key = prefix.to_vec();
+        key.extend(format!("{:016}", next).into_bytes());
+        let unchanged = etcdserver::Compare {
+            result: etcdserver::compare::CompareResult::Less as i32,
+            target: etcdserver::compare::CompareTarget::Mod as i32,
+            key: marker.clone(),
+            target_union: Some(etcdserver::compare::TargetUnion::ModRevision(read_at + 1)),
+            range_end: Vec::new(),
+        };
+        let put = |key: Vec<u8>, value: Vec<u8>| {
+            request_op(etcdserver::request_op::Request::RequestPut(
+                etcdserver::PutRequest {
+                    key,
+                    value,
+                    ..Default::default()
+                },
+            ))
+        };
+        let request = etcdserver::TxnRequest {
+            compare: vec![unchanged],
+            success: vec![put(marker.clone(), Vec::new()), put(key, value.clone())],
+            failure: Vec::new(),
+        };
+        if client.kv().txn(request).await?.succeeded() {
+            return Ok(());
+        }
+    }
+}
+
+/// Sequence number following the one in `suffix`, the part of a sequential key after its
+/// prefix
+fn next_sequence(suffix: &[u8]) -> EtcdResult<u64> {
+    let sequence: u64 = std::str::from_utf8(suffix)?.parse()?;
+    Ok(sequence + 1)
+}
+
+/// Delete and return the first key under `prefix` in key order, along with the revision the
+/// queue was read at. Retries if another caller takes the same key first.
+async fn claim_first<T: Transport>(
+    client: &EtcdClient<T>,
+    prefix: &[u8],
+) -> EtcdResult<(Option<Vec<u8>>, i64)> {
+    loop {
+        let request = etcdserver::RangeRequest {
+            sort_order: etcdserver::range_request::SortOrder::Ascend as i32,
+            sort_target: etcdserver::range_request::SortTarget::Key as i32,
+            limit: 1,
+            ..range_prefix(prefix)
+        };
+        let response = client.kv().range_request(request).await?;
+        let read_at = response.revision();
+        let first = match response.into_iter().next() {
+            Some(kv) => kv,
+            None => return Ok((None, read_at)),
+        };
+
+        let unchanged = etcdserver::Compare {
+            result: etcdserver::compare::CompareResult::Equal as i32,
+            target: etcdserver::compare::CompareTarget::Mod as i32,
+            key: first.key().to_vec(),
+            target_union: Some(etcdserver::compare::TargetUnion::ModRevision(
+                first.mod_revision(),
+            )),
+            range_end: Vec::new(),
+        };
+        let (key, value) = first.into_key_value();
+        let request = etcdserver::TxnRequest {
+            compare: vec![unchanged],
+            success: vec![delete_op(key)],
+            failure: Vec::new(),
+        };
+        if client.kv().txn(request).await?.succeeded() {
+            return Ok((Some(value), read_at));
+        }
+    }
+}
+
+/// Take the first item under `prefix`, waiting for one to be put if there are none
+async fn dequeue<T: Transport>(client: &EtcdClient<T>, prefix: &[u8]) -> EtcdResult<Vec<u8>> {
+    loop {
+        let (value, read_at) = claim_first(client, prefix).await?;
+        if let Some(value) = value {
+            return Ok(value);
+        }
+        wait_for_put(client, range_prefix(prefix), read_at).await?;
+    }
+}
+
+async fn queue_len<T: Transport>(client: &EtcdClient<T>, prefix: &[u8]) -> EtcdResult<usize> {
+    let request = etcdserver::RangeRequest {
+        count_only: true,
+        ..range_prefix(prefix)
+    };
+    let response = client.kv().range_request(request).await?;
+    Ok(response.count() as usize)
+}
+
+/// Allocates unique, increasing IDs from a counter stored in etcd.
+///
+/// IDs are taken from the counter with a compare-and-swap transaction, so each one is handed
+/// out at most once across the cluster, even if a process crashes halfway through. With a
+/// block size above one, each round trip reserves a whole block of IDs which are then handed
+/// out locally, reducing contention on the counter. IDs from a block are only increasing
+/// within one sequencer, and any left in a block when the sequencer is dropped are never
+/// used.
+pub struct Sequencer<T> {
+    client: EtcdClient<T>,
+    key: Vec<u8>,
+    block_size: u64,
+    /// Next ID to hand out from the current block, and the end of the block
+    next: u64,
+    end: u64,
+}
+
+impl<T> fmt::Debug for Sequencer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sequencer")
+            .field("key", &Key(&self.key))
+            .field("block_size", &self.block_size)
+            .field("next", &self.next)
+            .field("end", &self.end)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: Transport> Sequencer<T> {
+    /// Sequencer for the counter at `key`, reserving one ID at a time so IDs increase across
+    /// every user of the counter
+    pub fn new<K>(client: &EtcdClient<T>, key: K) -> Self
+    where
+        K: Into<Vec<u8>>,
+    {
+        Self::with_block_size(client, key, 1)
+    }
+
+    /// Sequencer for the counter at `key`, reserving `block_size` IDs at a time
+    pub fn with_block_size<K>(client: &EtcdClient<T>, key: K, block_size: u64) -> Self
+    where
+        K: Into<Vec<u8>>,
+    {
+        Self {
+            client: client.clone(),
+            key: key.into(),
+            block_size: block_size.max(1),
+            next: 0,
+            end: 0,
+        }
+    }
+
+    /// The next ID. IDs start from 1.
+    pub async fn next_id(&mut self) -> EtcdResult<u64> {
+        if self.next == self.end {
+            let start = self.reserve().await?;
+            self.next = start;
+            self.end = start + self.block_size;
+        }
+        let id = self.next;
+        self.next += 1;
+        Ok(id)
+    }
+
+    /// Advance the counter by a block, returning the first ID of the block
+    async fn reserve(&mut self) -> EtcdResult<u64> {
+        loop {
+            let response = self.client.kv().range_request(range(&self.key)).await?;
+            let (current, mod_revision) = match response.kvs().first() {
+                Some(kv) => (parse_counter(kv.value())?, kv.mod_revision()),
+                None => (0, 0),
+            };
+            let reserved = current + self.block_size;
+
+            // A mod revision of 0 compares equal for a key which does not exist yet
+            let unchanged = etcdserver::Compare {
+                result: etcdserver::compare::CompareResult::Equal as i32,
+                target: etcdserver::compare::CompareTarget::Mod as i32,
+                key: self.key.clone(),
+                target_union: Some(etcdserver::compare::TargetUnion::ModRevision(mod_revision)),
+                range_end: Vec::new(),
+            };
+            let put = etcdserver::PutRequest {
+                key: self.key.clone(),
+                value: reserved.to_string().into_bytes(),
+                ..Default::default()
+            };
+            let request = etcdserver::TxnRequest {
+                compare: vec![unchanged],
+                success: vec![request_op(etcdserver::request_op::Request::RequestPut(put))],
+                failure: Vec::new(),
+            };
+            if self.client.kv().txn(request).await?.succeeded() {
+                return Ok(current + 1);
+            }
+        }
+    }
+}
+
+/// Value of a [`Sequencer`] counter: the last ID reserved
+fn parse_counter(value: &[u8]) -> EtcdResult<u64> {
+    match std::str::from_utf8(value)?.parse() {
+        Ok(counter) => Ok(counter),
+        Err(_) => Err(format!(
+            "invalid sequencer counter {:?}",
+            String::from_utf8_lossy(value)
+        )
+        .into()),
+    }
+}
+
+/// A token bucket shared by every process using the same key, for keeping a fleet of workers
+/// under a global rate limit.
+///
+/// The bucket holds up to `capacity` tokens and gains one every `interval`. Its state is a
+/// single key, updated with compare-and-swap transactions so tokens are never handed out
+/// twice. Refills are computed from the time on the processes' own clocks, which should be
+/// kept roughly in sync. The time is taken from the client's [clock](crate::clock), counted
+/// from the wall-clock time the limiter was created at.
+pub struct RateLimiter<T> {
+    client: EtcdClient<T>,
+    key: Vec<u8>,
+    capacity: u64,
+    interval: Duration,
+    /// Milliseconds since the Unix epoch when the limiter was created, and the client's clock
+    /// at the time
+    epoch: (u64, Instant),
+}
+
+impl<T> fmt::Debug for RateLimiter<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateLimiter")
+            .field("key", &Key(&self.key))
+            .field("capacity", &self.capacity)
+            .field("interval", &self.interval)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: Transport> RateLimiter<T> {
+    /// Rate limiter for the bucket at `key`. Every user of the bucket must agree on its
+    /// capacity and refill interval.
+    pub fn new<K>(
+        client: &EtcdClient<T>,
+        key: K,
+        capacity: u64,
+        interval: Duration,
+    ) -> EtcdResult<Self>
+    where
+        K: Into<Vec<u8>>,
+    {
+        if capacity == 0 {
+            return Err("a rate limiter needs a capacity of at least one token".into());
+        }
+        if interval.as_millis() == 0 {
+            return Err("a rate limiter needs a refill interval of at least a millisecond".into());
+        }
+        let elapsed = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+        Ok(Self {
+            client: client.clone(),
+            key: key.into(),
+            capacity,
+            interval,
+            epoch: (elapsed.as_millis() as u64, client.config.clock.now()),
+        })
+    }
+
+    /// Take `tokens` from the bucket, waiting until enough are available
+    pub async fn acquire(&mut self, tokens: u64) -> EtcdResult<()> {
+        loop {
+            match self.take(tokens).await? {
+                None => return Ok(()),
+                Some(wait) => self.client.config.clock.delay_for(wait).await,
+            }
+        }
+    }
+
+    /// Take `tokens` from the bucket if enough are available, without waiting
+    pub async fn try_acquire(&mut self, tokens: u64) -> EtcdResult<bool> {
+        Ok(self.take(tokens).await?.is_none())
+    }
+
+    /// Try to take `tokens`, returning how long to wait before enough could be available if
+    /// there are too few
+    async fn take(&mut self, tokens: u64) -> EtcdResult<Option<Duration>> {
+        if tokens > self.capacity {
+            return Err(format!(
+                "cannot take {} tokens from a bucket holding at most {}",
+                tokens, self.capacity
+            )
+            .into());
+        }
+
+        loop {
+            let now = self.unix_millis();
+            let response = self.client.kv().range_request(range(&self.key)).await?;
+            let (bucket, mod_revision) = match response.kvs().first() {
+                Some(kv) => (Bucket::parse(kv.value())?, kv.mod_revision()),
+                None => (Bucket::full(self.capacity, now), 0),
+            };
+            let interval = self.interval.as_millis() as u64;
+            let bucket = match bucket.refill(now, self.capacity, interval).take(tokens) {
+                Ok(bucket) => bucket,
+                Err(missing) => return Ok(Some(Duration::from_millis(missing * interval))),
+            };
+
+            // A mod revision of 0 compares equal for a key which does not exist yet
+            let unchanged = etcdserver::Compare {
+                result: etcdserver::compare::CompareResult::Equal as i32,
+                target: etcdserver::compare::CompareTarget::Mod as i32,
+                key: self.key.clone(),
+                target_union: Some(etcdserver::compare::TargetUnion::ModRevision(mod_revision)),
+                range_end: Vec::new(),
+            };
+            let put = etcdserver::PutRequest {
+                key: self.key.clone(),
+                value: bucket.to_string().into_bytes(),
+                ..Default::default()
+            };
+            let request = etcdserver::TxnRequest {
+                compare: vec![unchanged],
+                success: vec![request_op(etcdserver::request_op::Request::RequestPut(put))],
+                failure: Vec::new(),
+            };
+            if self.client.kv().txn(request).await?.succeeded() {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Milliseconds since the Unix epoch, going by the client's clock
+    fn unix_millis(&self) -> u64 {
+        let (millis, at) = self.epoch;
+        let elapsed = self.client.config.clock.now().saturating_duration_since(at);
+        millis + elapsed.as_millis() as u64
+    }
+}
+
+/// State of a [`RateLimiter`]'s bucket, stored as `<tokens>,<updated>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Bucket {
+    tokens: u64,
+    /// Milliseconds since the Unix epoch up to which refills have been counted
+    updated: u64,
+}
+
+impl Bucket {
+    fn full(capacity: u64, now: u64) -> Self {
+        Self {
+            tokens: capacity,
+            updated: now,
+        }
+    }
+
+    fn parse(value: &[u8]) -> EtcdResult<Self> {
+        let invalid = || {
+            format!(
+                "invalid rate limiter bucket {:?}",
+                String::from_utf8_lossy(value)
+            )
+        };
+        let value = std::str::from_utf8(value).map_err(|_| invalid())?;
+        let mut parts = value.splitn(2, ',');
+        match (parts.next().map(str::parse), parts.next().map(str::parse)) {
+            (Some(Ok(tokens)), Some(Ok(updated))) => Ok(Self { tokens, updated }),
+            _ => Err(invalid().into()),
+        }
+    }
+
+    /// Add the tokens gained since the last refill, one per `interval` milliseconds. Time
+    /// towards the next token is kept, unless the bucket is full.
+    fn refill(self, now: u64, capacity: u64, interval: u64) -> Self {
+        // Clocks running behind the last writer's gain nothing yet
+        let gained = now.saturating_sub(self.updated) / interval;
+        let tokens = self.tokens.saturating_add(gained);
+        if tokens >= capacity {
+            return Self::full(capacity, now.max(self.updated));
+        }
+        Self {
+            tokens,
+            updated: self.updated + gained * interval,
+        }
+    }
+
+    /// Take `tokens`, or return how many more are needed
+    fn take(self, tokens: u64) -> Result<Self, u64> {
+        if tokens > self.tokens {
+            return Err(tokens - self.tokens);
+        }
+        Ok(Self {
+            tokens: self.tokens - tokens,
+            ..self
+        })
+    }
+}
+
+impl std::fmt::Display for Bucket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{},{}", self.tokens, self.updated)
+    }
+}
+
+/// Campaigns in an election for as long as it runs, calling back when leadership is won and
+/// lost.
+///
+/// This is built on the server's election service, with leadership tied to a [`Session`]. If
+/// the session ends, leadership is lost and the elector carries on campaigning under a new
+/// session of its own with the same TTL.
+#[cfg(feature = "election")]
+pub struct LeaderElector<T> {
+    session: Session<T>,
+    name: Vec<u8>,
+    value: Vec<u8>,
+    leading: watch::Sender<bool>,
+    leadership: watch::Receiver<bool>,
+}
+
+#[cfg(feature = "election")]
+impl<T> fmt::Debug for LeaderElector<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LeaderElector")
+            .field("name", &Key(&self.name))
+            .field("value", &crate::redact::Value(&self.value))
+            .field("leading", &*self.leadership.get_ref())
+            .field("session", &self.session)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "election")]
+impl<T: Transport> LeaderElector<T> {
+    /// Elector for the election `name`, campaigning under `session` and announcing `value`
+    /// while leader
+    pub fn new<N, V>(session: &Session<T>, name: N, value: V) -> Self
+    where
+        N: Into<Vec<u8>>,
+        V: Into<Vec<u8>>,
+    {
+        let (leading, leadership) = watch::channel(false);
+        Self {
+            session: session.clone(),
+            name: name.into(),
+            value: value.into(),
+            leading,
+            leadership,
+        }
+    }
+
+    /// Whether this elector is currently the leader
+    pub fn is_leader(&self) -> bool {
+        *self.leadership.get_ref()
+    }
+
+    /// Receiver which sees every change of this elector's leadership
+    pub fn leadership(&self) -> watch::Receiver<bool> {
+        self.leadership.clone()
+    }
+
+    /// Campaign until `shutdown` completes, calling `on_elected` with a token for guarding
+    /// writes each time leadership is won, and `on_lost` each time it is lost.
+    ///
+    /// On shutdown the elector resigns if it is leader, so another campaigner can take over
+    /// straight away rather than after the session's lease expires. Returns an error if
+    /// campaigning fails for a reason other than the session ending. A lease which has gone,
+    /// even if revoked before the session notices, is reported as lost leadership.
+    pub async fn run<E, L, S>(
+        &mut self,
+        mut on_elected: E,
+        mut on_lost: L,
+        shutdown: S,
+    ) -> EtcdResult<()>
+    where
+        E: FnMut(&FencingToken),
+        L: FnMut(),
+        S: Future<Output = ()>,
+    {
+        futures::pin_mut!(shutdown);
+
+        let mut lease_lost = false;
+        loop {
+            if lease_lost || !self.session.is_alive() {
+                self.session = self.session.renew().await?;
+                lease_lost = false;
+            }
+
+            let election = self.session.client.election();
+            let (name, value) = (self.name.clone(), self.value.clone());
+            let campaign = election.campaign(name, self.session.lease, value);
+            futures::pin_mut!(campaign);
+            let leader = match future::select(campaign, shutdown.as_mut()).await {
+                Either::Left((Ok(leader), _)) => leader,
+                // Campaigning fails once the session's lease has gone
+                Either::Left((Err(e), _)) if self.lease_lost(&e) => {
+                    lease_lost = true;
+                    continue;
+                }
+                Either::Left((Err(e), _)) => return Err(e),
+                Either::Right(_) => return Ok(()),
+            };
+
+            let _ = self.leading.broadcast(true);
+            on_elected(&FencingToken::from(&leader));
+
+            // Leadership ends with the session, or if our key is removed from under us
+            let shut_down = {
+                let client = self.session.client.clone();
+                let key_deleted = wait_for_delete(&client, range(&leader.key), leader.rev);
+                let session_done = self.session.done();
+                futures::pin_mut!(key_deleted);
+                futures::pin_mut!(session_done);
+                let lost = future::select(key_deleted, session_done);
+                match future::select(lost, shutdown.as_mut()).await {
+                    Either::Left(_) => false,
+                    Either::Right(_) => true,
+                }
+            };
+
+            let _ = self.leading.broadcast(false);
+            on_lost();
+            if shut_down {
+                return match self.session.client.election().resign(leader).await {
+                    // Leadership has already gone with the lease
+                    Err(e) if !self.lease_lost(&e) => Err(e),
+                    _ => Ok(()),
+                };
+            }
+        }
+    }
+
+    /// Whether `error` is down to the session's lease having gone, which the session's
+    /// keep-alive may not have noticed yet
+    fn lease_lost(&self, error: &Error) -> bool {
+        !self.session.is_alive() || matches!(error.root(), Error::LeaseNotFound(_))
+    }
+}
+
+/// Runs jobs, such as periodic maintenance, at most once at a time across every process which
+/// runs them under the same name.
+///
+/// Each job is guarded by a [`Mutex`] named after it, held for as long as the job runs and
+/// released once it completes. If the process running a job dies, its session's lease expires
+/// and the job is free to run elsewhere. If the session ends while the job is running, the job
+/// is dropped rather than left running without the lock. If the future running the job is
+/// dropped, the mutex is released in the background.
+pub struct Singleton<T> {
+    session: Session<T>,
+}
+
+impl<T> fmt::Debug for Singleton<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Singleton")
+            .field("session", &self.session)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: Transport> Singleton<T> {
+    /// Run jobs under `session`
+    pub fn new(session: &Session<T>) -> Self {
+        Self {
+            session: session.clone(),
+        }
+    }
+
+    /// Wait until nobody else is running the job `name`, then run `job` and return its output
+    pub async fn run<N, F>(&mut self, name: N, job: F) -> EtcdResult<F::Output>
+    where
+        N: Into<Vec<u8>>,
+        F: Future,
+    {
+        let name = name.into();
+        let _running = match self.reserve(&name) {
+            Some(running) => running,
+            None => {
+                return Err(format!(
+                    "job {:?} is already running under this session",
+                    String::from_utf8_lossy(&name)
+                )
+                .into())
+            }
+        };
+        let mut mutex = Mutex::new(&self.session, name);
+        mutex.lock().await?;
+        self.guard(mutex, job).await
+    }
+
+    /// Run `job` if nobody else is running the job `name`, returning `None` straight away
+    /// otherwise
+    pub async fn try_run<N, F>(&mut self, name: N, job: F) -> EtcdResult<Option<F::Output>>
+    where
+        N: Into<Vec<u8>>,
+        F: Future,
+    {
+        let name = name.into();
+        let _running = match self.reserve(&name) {
+            Some(running) => running,
+            None => return Ok(None),
+        };
+        let mut mutex = Mutex::new(&self.session, name);
+        if mutex.try_lock().await?.is_none() {
+            return Ok(None);
+        }
+        self.guard(mutex, job).await.map(Some)
+    }
+
+    /// Note that the job `name` is running under the session. The mutex is reentrant within a
+    /// session, so it would not keep a second run under the same session out.
+    fn reserve(&self, name: &[u8]) -> Option<RunningJob> {
+        let mut jobs = self.session.jobs.lock().unwrap();
+        if !jobs.insert(name.to_vec()) {
+            return None;
+        }
+        Some(RunningJob {
+            jobs: self.session.jobs.clone(),
+            name: name.to_vec(),
+        })
+    }
+
+    /// Run `job` while holding `mutex`, for at most as long as the session lasts
+    async fn guard<F>(&mut self, mutex: Mutex<T>, job: F) -> EtcdResult<F::Output>
+    where
+        F: Future,
+    {
+        let mut held = HeldJob { mutex: Some(mutex) };
+        let mut session = self.session.clone();
+        let output = {
+            let done = session.done();
+            futures::pin_mut!(job);
+            futures::pin_mut!(done);
+            match future::select(job, done).await {
+                Either::Left((output, _)) => output,
+                Either::Right(_) => return Err("session ended while the job was running".into()),
+            }
+        };
+        if let Some(mut mutex) = held.mutex.take() {
+            mutex.unlock().await?;
+        }
+        Ok(output)
+    }
+}
+
+/// The mutex of a running job, released in the background if the job is dropped before it
+/// completes
+struct HeldJob<T: Transport> {
+    mutex: Option<Mutex<T>>,
+}
+
+impl<T: Transport> Drop for HeldJob<T> {
+    fn drop(&mut self) {
+        let mutex = match self.mutex.take() {
+            Some(mutex) => mutex,
+            None => return,
+        };
+        // Only one run of a job at a time under a session, so the whole hold is ours
+        mutex.holds.lock().unwrap().remove(&mutex.key);
+        let kv = mutex.client.kv();
+        let cleanup = async move {
+            let _ = kv.delete(mutex.key).await;
+        };
+        // Outside of a runtime the key is left for the session's lease to remove
+        let _ = runtime::try_spawn(cleanup);
+    }
+}
+
+/// A job running under a session, which is forgotten when dropped
+struct RunningJob {
+    jobs: Arc<StdMutex<HashSet<Vec<u8>>>>,
+    name: Vec<u8>,
+}
+
+impl Drop for RunningJob {
+    fn drop(&mut self) {
+        self.jobs.lock().unwrap().remove(&self.name);
+    }
+}
+
+/// Isolation level of a software transaction run with [`stm`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Isolation {
+    /// Every read sees the store as of the first read, and the transaction only commits if
+    /// nothing it read or wrote has changed since then.
+    Serializable,
+    /// Reads of the same key always return the same value, and the transaction only commits if
+    /// none of the keys it read have changed since they were read. Keys it only writes may
+    /// have changed.
+    RepeatableReads,
+}
+
+/// Handle to a software transaction, passed to the closure run by [`stm`].
+///
+/// Reads go to the server the first time each key is read and are then cached. Writes are
+/// buffered and only sent when the transaction commits.
+#[derive(Clone)]
+pub struct Stm<T> {
+    client: EtcdClient<T>,
+    state: Arc<StdMutex<StmState>>,
+}
+
+impl<T> fmt::Debug for Stm<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Stm").finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug)]
+struct StmState {
+    isolation: Isolation,
+    /// Value of each key read, along with the revision it was read at
+    reads: BTreeMap<Vec<u8>, (Option<Vec<u8>>, i64)>,
+    /// Buffered writes, with `None` for a delete
+    writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    /// Revision of the first read
+    revision: Option<i64>,
+}
+
+impl<T: Transport> Stm<T> {
+    /// Value of `key`, taking writes made earlier in the transaction into account
+    pub async fn get<K>(&self, key: K) -> EtcdResult<Option<Vec<u8>>>
+    where
+        K: Into<Vec<u8>>,
+    {
+        let key = key.into();
+        let revision = {
+            let state = self.state.lock().unwrap();
+            if let Some(value) = state.writes.get(&key) {
+                return Ok(value.clone());
+            }
+            if let Some((value, _)) = state.reads.get(&key) {
+                return Ok(value.clone());
+            }
+            match state.isolation {
+                Isolation::Serializable => state.revision.unwrap_or(0),
+                Isolation::RepeatableReads => 0,
+            }
+        };
+
+        let request = etcdserver::RangeRequest {
+            revision,
+            ..range(&key)
+        };
+        let response = self.client.kv().range_request(request).await?;
+        let read_at = response.revision();
+        let value = response.into_iter().next().map(|kv| kv.into_key_value().1);
+
+        let mut state = self.state.lock().unwrap();
+        state.revision.get_or_insert(read_at);
+        // Pinned reads are as of the pinned revision, even if the store has moved on
+        let read_at = if revision > 0 { revision } else { read_at };
+        Ok(state.reads.entry(key).or_insert((value, read_at)).0.clone())
+    }
+
+    /// Set `key` to `value` when the transaction commits
+    pub fn put<K, V>(&self, key: K, value: V)
+    where
+        K: Into<Vec<u8>>,
+        V: Into<Vec<u8>>,
+    {
+        let mut state = self.state.lock().unwrap();
+        state.writes.insert(key.into(), Some(value.into()));
+    }
+
+    /// Delete `key` when the transaction commits
+    pub fn delete<K>(&self, key: K)
+    where
+        K: Into<Vec<u8>>,
+    {
+        let mut state = self.state.lock().unwrap();
+        state.writes.insert(key.into(), None);
+    }
+}
+
+/// Run `apply` as a software transaction, retrying it from scratch until it commits without
+/// conflicting with other writers, and return its result from the attempt which committed.
+///
+/// The closure reads and writes through the [`Stm`] handle it is given. Its writes are only
+/// applied if, when it finishes, the keys it depended on (see [`Isolation`]) are unchanged; if
+/// not, everything it did is discarded and it runs again. It should therefore have no side
+/// effects outside the transaction. An error from the closure aborts the transaction without
+/// writing anything.
+pub async fn stm<T, F, Fut, R>(
+    client: &EtcdClient<T>,
+    isolation: Isolation,
+    mut apply: F,
+) -> EtcdResult<R>
+where
+    T: Transport,
+    F: FnMut(Stm<T>) -> Fut,
+    Fut: Future<Output = EtcdResult<R>>,
+{
+    loop {
+        let txn = Stm {
+            client: client.clone(),
+            state: Arc::new(StdMutex::new(StmState {
+                isolation,
+                reads: BTreeMap::new(),
+                writes: BTreeMap::new(),
+                revision: None,
+            })),
+        };
+        let result = apply(txn.clone()).await?;
+
+        let request = commit_request(&txn.state.lock().unwrap());
+        if client.kv().txn(request).await?.succeeded() {
+            return Ok(result);
+        }
+    }
+}
+
+/// Transaction applying the buffered writes if nothing the transaction depended on has changed
+fn commit_request(state: &StmState) -> etcdserver::TxnRequest {
+    let unmodified_since = |key: &[u8], revision: i64| etcdserver::Compare {
+        result: etcdserver::compare::CompareResult::Less as i32,
+        target: etcdserver::compare::CompareTarget::Mod as i32,
+        key: key.to_vec(),
+        target_union: Some(etcdserver::compare::TargetUnion::ModRevision(revision + 1)),
+        range_end: Vec::new(),
+    };
+
+    let mut compare: Vec<_> = state
+        .reads
+        .iter()
+        .map(|(key, (_, revision))| unmodified_since(key, *revision))
+        .collect();
+    if let (Isolation::Serializable, Some(revision)) = (state.isolation, state.revision) {
+        compare.extend(
+            state
+                .writes
+                .keys()
+                .filter(|key| !state.reads.contains_key(*key))
+                .map(|key| unmodified_since(key, revision)),
+        );
+    }
+
+    let success = state
+        .writes
+        .iter()
+        .map(|(key, value)| match value {
+            Some(value) => request_op(etcdserver::request_op::Request::RequestPut(
+                etcdserver::PutRequest {
+                    key: key.clone(),
+                    value: value.clone(),
+                    ..Default::default()
+                },
+            )),
+            None => request_op(etcdserver::request_op::Request::RequestDeleteRange(
+                etcdserver::DeleteRangeRequest {
+                    key: key.clone(),
+                    ..Default::default()
+                },
+            )),
+        })
+        .collect();
+
+    etcdserver::TxnRequest {
+        compare,
+        success,
+        failure: Vec::new(),
+    }
+}
+
+/// Wait until a key in `keys` is deleted at some revision after `revision`
+async fn wait_for_delete<T: Transport>(
+    client: &EtcdClient<T>,
+    keys: etcdserver::RangeRequest,
+    revision: i64,
+) -> EtcdResult<()> {
+    wait_for_event(client, keys, revision, EventKind::Delete).await
+}
+
+/// Wait until a key in `keys` is put at some revision after `revision`
+async fn wait_for_put<T: Transport>(
+    client: &EtcdClient<T>,
+    keys: etcdserver::RangeRequest,
+    revision: i64,
+) -> EtcdResult<()> {
+    wait_for_event(client, keys, revision, EventKind::Put).await
+}
+
+async fn wait_for_event<T: Transport>(
+    client: &EtcdClient<T>,
+    keys: etcdserver::RangeRequest,
+    revision: i64,
+    kind: EventKind,
+) -> EtcdResult<()> {
+    let filter = match kind {
+        EventKind::Put => etcdserver::watch_create_request::FilterType::Nodelete,
+        EventKind::Delete => etcdserver::watch_create_request::FilterType::Noput,
+    };
+    let create = etcdserver::WatchCreateRequest {
+        key: keys.key,
+        range_end: keys.range_end,
+        start_revision: revision + 1,
+        filters: vec![filter as i32],
+        ..Default::default()
+    };
+    // Watching again from the same revision cannot miss the event, so a broken watch is
+    // simply re-established
+    let watch = client.watch();
+    let mut backoff = Backoff::new();
+    loop {
+        let mut responses = match watch.watch_request(create.clone()).await {
+            Ok(responses) => responses,
+            Err(e) if e.is_retryable() => {
+                backoff.wait().await;
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        loop {
+            let response = match responses.message().await {
+                Ok(Some(response)) => response,
+                Ok(None) => break,
+                Err(e) if e.is_retryable() => break,
+                Err(e) => return Err(e),
+            };
+            if response.is_canceled() {
+                if response.compact_revision() > 0 {
+                    return Err(Error::Compacted(Some(response.compact_revision())));
+                }
+                return Err(format!("watch canceled: {}", response.cancel_reason()).into());
+            }
+            backoff.reset();
+            if response.events().iter().any(|event| event.kind() == kind) {
+                return Ok(());
+            }
+        }
+        backoff.wait().await;
+    }
+}
+
+/// Request to get a single key
+fn range(key: &[u8]) -> etcdserver::RangeRequest {
+    etcdserver::RangeRequest {
+        key: key.to_vec(),
+        ..Default::default()
+    }
+}
+
+/// Request to get every key starting with `prefix`
+fn range_prefix(prefix: &[u8]) -> etcdserver::RangeRequest {
+    etcdserver::RangeRequest {
+        key: prefix.to_vec(),
+        range_end: prefix_end(prefix),
+        ..Default::default()
+    }
+}
+
+/// Operation deleting a single key
+fn delete_op(key: Vec<u8>) -> etcdserver::RequestOp {
+    request_op(etcdserver::request_op::Request::RequestDeleteRange(
+        etcdserver::DeleteRangeRequest {
+            key,
+            ..Default::default()
+        },
+    ))
+}
+
+fn request_op(request: etcdserver::request_op::Request) -> etcdserver::RequestOp {
+    etcdserver::RequestOp {
+        request: Some(request),
+    }
+}
+
+/// Responses to the range operations of a transaction, in order
+fn txn_ranges(response: &TxnResponse) -> impl Iterator<Item = &GetResponse> {
+    response.responses().iter().filter_map(|op| match op {
+        TxnOpResponse::Get(range) => Some(range),
+        _ => None,
+    })
+}
+
+/// Revision the response carrying `header` was read at. Every response from etcd carries a
+/// header, so one without is malformed.
+pub(crate) fn read_revision(
+    header: Option<etcdserver::ResponseHeader>,
+    response: &str,
+) -> EtcdResult<i64> {
+    match header {
+        Some(header) => Ok(header.revision),
+        None => Err(Error::invalid_response(format!(
+            "{} response is missing its header",
+            response
+        ))),
+    }
+}
+
+/// The rest of `key` after `prefix`, which the keys of a range over the prefix all start with
+fn key_suffix<'a>(key: &'a [u8], prefix: &[u8]) -> EtcdResult<&'a [u8]> {
+    if key.starts_with(prefix) {
+        Ok(&key[prefix.len()..])
+    } else {
+        let key = String::from_utf8_lossy(key);
+        let prefix = String::from_utf8_lossy(prefix);
+        Err(Error::invalid_response(format!(
+            "key {:?} is outside the range of prefix {:?}",
+            key, prefix
+        )))
+    }
+}
+
+/// End of the range of keys starting with `prefix`: the prefix up to its last byte below
+/// `0xff`, with that byte incremented. A prefix of only `0xff` bytes (or an empty one) has no
+/// end, which etcd spells `[0]`.
+pub(crate) fn prefix_end(prefix: &[u8]) -> Vec<u8> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xff {
+            end.push(last + 1);
+            return end;
+        }
+    }
+    vec![0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefix_end() {
+        assert_eq!(prefix_end(b"a/"), b"a0");
+        assert_eq!(prefix_end(b"a\xff"), b"b");
+        assert_eq!(prefix_end(b"\xff\xff"), vec![0]);
+        assert_eq!(prefix_end(b""), vec![0]);
+    }
+
+    #[tokio::test]
+    async fn test_lease_health() {
+        use futures::StreamExt;
+
+        let in_flight = InFlight::default();
+        assert!(!in_flight.sent());
+        assert!(in_flight.sent());
+        assert!(in_flight.answered().is_some());
+        assert!(in_flight.pending());
+        assert!(in_flight.answered().is_some());
+        assert_eq!(in_flight.answered(), None);
+
+        let health = LeaseHealth::default();
+        let events = health.subscribe();
+        let rtt = Duration::from_millis(3);
+        health.kept_alive(rtt, 60);
+        health.missed();
+        health.lost();
+        health.ended();
+        let events: Vec<_> = events.collect().await;
+        assert_eq!(
+            events,
+            vec![
+                SessionEvent::KeptAlive { rtt, ttl: 60 },
+                SessionEvent::Missed,
+                SessionEvent::Lost,
+            ]
+        );
+        // Subscribing after the session ended gets no events
+        assert_eq!(health.subscribe().next().await, None);
+    }
+
+    #[test]
+    fn test_malformed_responses() {
+        assert_eq!(key_suffix(b"queue/1", b"queue/").unwrap(), b"1");
+        let err = key_suffix(b"q", b"queue/").unwrap_err();
+        assert!(matches!(err, Error::InvalidResponse(_)));
+
+        let header = etcdserver::ResponseHeader {
+            revision: 7,
+            ..Default::default()
+        };
+        assert_eq!(read_revision(Some(header), "range").unwrap(), 7);
+        let err = read_revision(None, "range").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid response: range response is missing its header"
+        );
+    }
+
+    #[test]
+    fn test_stm_commit_request() {
+        let mut state = StmState {
+            isolation: Isolation::Serializable,
+            reads: BTreeMap::new(),
+            writes: BTreeMap::new(),
+            revision: Some(10),
+        };
+        state.reads.insert(b"a".to_vec(), (Some(b"1".to_vec()), 10));
+        state.writes.insert(b"a".to_vec(), Some(b"2".to_vec()));
+        state.writes.insert(b"b".to_vec(), None);
+
+        let compared = |request: &etcdserver::TxnRequest| -> Vec<(Vec<u8>, i64)> {
+            request
+                .compare
+                .iter()
+                .map(|c| match c.target_union {
+                    Some(etcdserver::compare::TargetUnion::ModRevision(r)) => (c.key.clone(), r),
+                    _ => panic!("unexpected compare {:?}", c),
+                })
+                .collect()
+        };
+
+        // Serializable transactions also check that the keys they only write are unchanged
+        let request = commit_request(&state);
+        assert_eq!(
+            compared(&request),
+            vec![(b"a".to_vec(), 11), (b"b".to_vec(), 11)]
+        );
+        assert_eq!(request.success.len(), 2);
+        match &request.success[1].request {
+            Some(etcdserver::request_op::Request::RequestDeleteRange(r)) => assert_eq!(r.key, b"b"),
+            other => panic!("unexpected op {:?}", other),
+        }
+
+        state.isolation = Isolation::RepeatableReads;
+        let request = commit_request(&state);
+        assert_eq!(compared(&request), vec![(b"a".to_vec(), 11)]);
+    }
+
+    #[tokio::test]
+    async fn test_rwlock() {
+        let client = crate::testing::namespace().await.unwrap();
+        let first = Session::new(&client, 5).await.unwrap();
+        let second = Session::new(&client, 5).await.unwrap();
+        let options = RwLockOptions {
+            timeout: Some(Duration::from_millis(200)),
+        };
+
+        // Readers share the lock
+        let mut a = RwLock::with_options(&first, "test_rwlock", options);
+        let mut b = RwLock::with_options(&second, "test_rwlock", options);
+        a.read_lock().await.unwrap();
+        b.read_lock().await.unwrap();
+        b.unlock().await.unwrap();
+
+        // Writers wait for readers, and give up after the timeout
+        assert!(b.write_lock().await.is_err());
+        a.unlock().await.unwrap();
+        b.write_lock().await.unwrap();
+        assert!(a.read_lock().await.is_err());
+        b.unlock().await.unwrap();
+
+        first.close().await.unwrap();
+        second.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rwlock_handles_on_one_session() {
+        let client = crate::testing::namespace().await.unwrap();
+        let session = Session::new(&client, 5).await.unwrap();
+        let options = RwLockOptions {
+            timeout: Some(Duration::from_millis(200)),
+        };
+        let name = "test_rwlock_handles_on_one_session";
+        let mut a = RwLock::with_options(&session, name, options);
+        let mut b = RwLock::with_options(&session, name, options);
+
+        a.read_lock().await.unwrap();
+        b.read_lock().await.unwrap();
+        // Each reader releases only its own key
+        a.unlock().await.unwrap();
+        assert!(b.is_held().await.unwrap());
+
+        // A writer on the same session still waits for the other handle
+        assert!(a.write_lock().await.is_err());
+        assert!(b.is_held().await.unwrap());
+        b.unlock().await.unwrap();
+        a.write_lock().await.unwrap();
+        a.unlock().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_lock_ownership() {
+        let client = crate::testing::namespace().await.unwrap();
+        let first = Session::new(&client, 5).await.unwrap();
+        let second = Session::new(&client, 5).await.unwrap();
+
+        let mut a = Mutex::new(&first, "test_lock_ownership");
+        let mut b = Mutex::new(&second, "test_lock_ownership");
+        assert!(a.holder().await.unwrap().is_none());
+        assert!(!a.is_held().await.unwrap());
+        let token = a.lock().await.unwrap();
+        assert_eq!(a.revision(), Some(token.revision()));
+        assert!(a.is_held().await.unwrap());
+        assert!(!b.is_held().await.unwrap());
+        assert_eq!(
+            b.holder().await.unwrap().unwrap().lease(),
+            Some(first.lease())
+        );
+
+        // Ownership ends with the session
+        first.clone().close().await.unwrap();
+        assert!(!a.is_held().await.unwrap());
+
+        let mut rwlock = RwLock::new(&second, "test_lock_ownership_rw");
+        assert_eq!(rwlock.key(), None);
+        let token = rwlock.read_lock().await.unwrap();
+        assert_eq!(rwlock.key(), Some(token.key()));
+        assert_eq!(rwlock.revision(), Some(token.revision()));
+        assert!(rwlock.is_held().await.unwrap());
+        rwlock.unlock().await.unwrap();
+        assert!(!rwlock.is_held().await.unwrap());
+        second.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_semaphore() {
+        let client = crate::testing::namespace().await.unwrap();
+        let mut sessions = Vec::new();
+        for _ in 0..3 {
+            sessions.push(Session::new(&client, 5).await.unwrap());
+        }
+        let mut a = Semaphore::new(&sessions[0], "test_semaphore", 2).unwrap();
+        let mut b = Semaphore::new(&sessions[1], "test_semaphore", 2).unwrap();
+        let mut c = Semaphore::new(&sessions[2], "test_semaphore", 2).unwrap();
+
+        a.acquire().await.unwrap();
+        assert!(b.try_acquire().await.unwrap());
+        assert!(!c.try_acquire().await.unwrap());
+
+        a.release().await.unwrap();
+        c.acquire().await.unwrap();
+        b.release().await.unwrap();
+        c.release().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_semaphore_handles_on_one_session() {
+        let client = crate::testing::namespace().await.unwrap();
+        let session = Session::new(&client, 5).await.unwrap();
+        let name = "test_semaphore_handles_on_one_session";
+        let mut a = Semaphore::new(&session, name, 1).unwrap();
+        let mut b = Semaphore::new(&session, name, 1).unwrap();
+
+        a.acquire().await.unwrap();
+        assert!(!b.try_acquire().await.unwrap());
+        // Giving up the attempt must not take away the permit held by the other handle
+        let holders = range_prefix(format!("{}/", name).as_bytes());
+        let holders = client.kv().range_request(holders).await.unwrap();
+        assert_eq!(holders.kvs().len(), 1);
+
+        a.release().await.unwrap();
+        assert!(b.try_acquire().await.unwrap());
+        b.release().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_barrier() {
+        let client = crate::testing::namespace().await.unwrap();
+        let mut barrier = Barrier::new(&client, "test_barrier");
+        barrier.wait().await.unwrap();
+
+        barrier.hold().await.unwrap();
+        assert!(barrier.hold().await.is_err());
+        let mut waiter = Barrier::new(&client, "test_barrier");
+        let (waited, released) = future::join(waiter.wait(), barrier.release()).await;
+        waited.unwrap();
+        released.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_double_barrier() {
+        let client = crate::testing::namespace().await.unwrap();
+        let first = Session::new(&client, 5).await.unwrap();
+        let second = Session::new(&client, 5).await.unwrap();
+        let mut a = DoubleBarrier::new(&first, "test_double_barrier", 2);
+        let mut b = DoubleBarrier::new(&second, "test_double_barrier", 2);
+
+        let (entered_a, entered_b) = future::join(a.enter(), b.enter()).await;
+        entered_a.unwrap();
+        entered_b.unwrap();
+        let (left_a, left_b) = future::join(a.leave(), b.leave()).await;
+        left_a.unwrap();
+        left_b.unwrap();
+
+        first.close().await.unwrap();
+        second.close().await.unwrap();
+    }
+
+    #[test]
+    fn test_next_sequence() {
+        assert_eq!(next_sequence(b"0000000000000000").unwrap(), 1);
+        assert_eq!(next_sequence(b"0000000000000041").unwrap(), 42);
+        assert!(next_sequence(b"not a number").is_err());
+    }
+
+    #[cfg(feature = "election")]
+    #[test]
+    fn test_fencing_tokens() {
+        let leader = v3election::LeaderKey {
+            name: b"election".to_vec(),
+            key: b"election/1234".to_vec(),
+            rev: 42,
+            lease: 0x1234,
+        };
+        let token = FencingToken::from(&leader);
+        assert_eq!((token.key(), token.revision()), (&b"election/1234"[..], 42));
+
+        let compare = token.compare();
+        assert_eq!(compare.key, b"election/1234");
+        assert_eq!(
+            compare.target,
+            etcdserver::compare::CompareTarget::Create as i32
+        );
+        assert_eq!(
+            compare.target_union,
+            Some(etcdserver::compare::TargetUnion::CreateRevision(42))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fencing_token_namespace() {
+        let client = crate::testing::namespace().await.unwrap();
+        let held = client.kv().put("test_fencing_token", "").await.unwrap();
+        let token = FencingToken {
+            key: b"test_fencing_token".to_vec(),
+            revision: held.revision(),
+        };
+
+        // The token's key is compared inside the namespace, where it was created
+        token
+            .put(&client, "test_fencing_token/guarded", "1")
+            .await
+            .unwrap();
+        let guarded = client.kv().get("test_fencing_token/guarded").await.unwrap();
+        assert_eq!(guarded.as_deref(), Some("1"));
+
+        client.kv().delete("test_fencing_token").await.unwrap();
+        let err = token.put(&client, "test_fencing_token/guarded", "2").await;
+        assert!(err.unwrap_err().to_string().contains("is stale"));
+        let guarded = client.kv().get("test_fencing_token/guarded").await.unwrap();
+        assert_eq!(guarded.as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn test_parsing_counters() {
+        assert_eq!(parse_counter(b"0").unwrap(), 0);
+        assert_eq!(parse_counter(b"1234").unwrap(), 1234);
+        assert_eq!(
+            parse_counter(b"-1").unwrap_err().to_string(),
+            "invalid sequencer counter \"-1\""
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sequencer() {
+        let client = crate::testing::namespace().await.unwrap();
+        client.range("test_sequencer", None).delete().await.unwrap();
+
+        let mut single = Sequencer::new(&client, "test_sequencer");
+        assert_eq!(single.next_id().await.unwrap(), 1);
+        assert_eq!(single.next_id().await.unwrap(), 2);
+
+        let mut blocks = Sequencer::with_block_size(&client, "test_sequencer", 10);
+        assert_eq!(blocks.next_id().await.unwrap(), 3);
+        assert_eq!(blocks.next_id().await.unwrap(), 4);
+        // The rest of the block is reserved, so other sequencers skip it
+        assert_eq!(single.next_id().await.unwrap(), 13);
+    }
+
+    #[tokio::test]
+    async fn test_queue() {
+        let client = crate::testing::namespace().await.unwrap();
+        let mut queue = Queue::new(&client, "test_queue");
+        assert_eq!(queue.try_dequeue().await.unwrap(), None);
+
+        queue.enqueue("first").await.unwrap();
+        queue.enqueue("second").await.unwrap();
+        assert_eq!(queue.len().await.unwrap(), 2);
+        assert_eq!(queue.dequeue().await.unwrap(), b"first");
+        assert_eq!(queue.dequeue().await.unwrap(), b"second");
+        assert!(queue.is_empty().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_priority_queue() {
+        let client = crate::testing::namespace().await.unwrap();
+        let mut queue = PriorityQueue::new(&client, "test_priority_queue");
+        queue.enqueue("low", 10).await.unwrap();
+        queue.enqueue("high", 1).await.unwrap();
+        queue.enqueue("also high", 1).await.unwrap();
+
+        assert_eq!(queue.dequeue().await.unwrap(), b"high");
+        assert_eq!(queue.dequeue().await.unwrap(), b"also high");
+        assert_eq!(queue.dequeue().await.unwrap(), b"low");
+        assert_eq!(queue.try_dequeue().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_singleton() {
+        let client = crate::testing::namespace().await.unwrap();
+        let first = Session::new(&client, 5).await.unwrap();
+        let second = Session::new(&client, 5).await.unwrap();
+        let mut a = Singleton::new(&first);
+        let mut b = Singleton::new(&second);
+        let mut c = Singleton::new(&first);
+
+        // Nobody else gets to run the job while it is running
+        let job = async {
+            assert_eq!(
+                b.try_run("test_singleton", async { 1 }).await.unwrap(),
+                None
+            );
+            assert_eq!(
+                c.try_run("test_singleton", async { 1 }).await.unwrap(),
+                None
+            );
+            2
+        };
+        assert_eq!(a.run("test_singleton", job).await.unwrap(), 2);
+
+        // Once it completes the job is free again
+        assert_eq!(
+            b.try_run("test_singleton", async { 3 }).await.unwrap(),
+            Some(3)
+        );
+
+        first.close().await.unwrap();
+        second.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_dropped_singleton_job() {
+        let client = crate::testing::namespace().await.unwrap();
+        let first = Session::new(&client, 5).await.unwrap();
+        let second = Session::new(&client, 5).await.unwrap();
+        let mut a = Singleton::new(&first);
+        let mut b = Singleton::new(&second);
+
+        let name = "test_dropped_singleton_job";
+        let job = a.run(name, future::pending::<()>());
+        assert!(runtime::timeout(job, Duration::from_millis(200))
+            .await
+            .is_err());
+
+        // The lock is released without waiting for the first session to end
+        let run = b.run(name, async { 1 });
+        let ran = runtime::timeout(run, Duration::from_secs(2)).await;
+        assert_eq!(ran.unwrap().unwrap(), 1);
+        assert!(first.holds.lock().unwrap().is_empty());
+
+        first.close().await.unwrap();
+        second.close().await.unwrap();
+    }
+
+    #[test]
+    fn test_token_buckets() {
+        let bucket = Bucket::parse(b"2,1000").unwrap();
+        assert_eq!(
+            bucket,
+            Bucket {
+                tokens: 2,
+                updated: 1000
+            }
+        );
+        assert_eq!(
+            Bucket::parse(bucket.to_string().as_bytes()).unwrap(),
+            bucket
+        );
+        assert!(Bucket::parse(b"2").is_err());
+        assert!(Bucket::parse(b"two,1000").is_err());
+
+        // One token per 100ms, keeping the time towards the next one
+        let refilled = bucket.refill(1250, 5, 100);
+        assert_eq!(
+            refilled,
+            Bucket {
+                tokens: 4,
+                updated: 1200
+            }
+        );
+        assert_eq!(bucket.refill(1_000_000, 5, 100), Bucket::full(5, 1_000_000));
+        assert_eq!(bucket.refill(500, 5, 100), bucket);
+
+        assert_eq!(refilled.take(3).unwrap().tokens, 1);
+        assert_eq!(refilled.take(6), Err(2));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter() {
+        let client = crate::testing::namespace().await.unwrap();
+        client
+            .range("test_rate_limiter", None)
+            .delete()
+            .await
+            .unwrap();
+        let interval = Duration::from_millis(100);
+        let mut a = RateLimiter::new(&client, "test_rate_limiter", 2, interval).unwrap();
+        let mut b = RateLimiter::new(&client, "test_rate_limiter", 2, interval).unwrap();
+        assert!(RateLimiter::new(&client, "test_rate_limiter", 0, interval).is_err());
+
+        // The bucket is shared between limiters
+        assert!(a.try_acquire(1).await.unwrap());
+        assert!(b.try_acquire(1).await.unwrap());
+        assert!(!a.try_acquire(1).await.unwrap());
+        assert!(a.try_acquire(3).await.is_err());
+
+        let start = Instant::now();
+        b.acquire(1).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter_on_clock() {
+        let namespace = crate::testing::namespace().await.unwrap();
+        let clock = crate::MockClock::new();
+        let client = EtcdClient::builder()
+            .endpoint(crate::testing::shared_endpoint())
+            .clock(clock.clone())
+            .connect()
+            .await
+            .unwrap()
+            .namespaced(namespace.prefix());
+        let interval = Duration::from_secs(3600);
+        let mut limiter =
+            RateLimiter::new(&client, "test_rate_limiter_on_clock", 1, interval).unwrap();
+        assert!(limiter.try_acquire(1).await.unwrap());
+        assert!(!limiter.try_acquire(1).await.unwrap());
+
+        // Refills and waits only pass once the clock is moved on
+        let advance = async {
+            while clock.sleeping() == 0 {
+                runtime::delay_for(Duration::from_millis(1)).await;
+            }
+            clock.advance(interval);
+        };
+        let (acquired, ()) = future::join(limiter.acquire(1), advance).await;
+        acquired.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_work_queue() {
+        let client = crate::testing::namespace().await.unwrap();
+        client
+            .range("test_work_queue/", Some("test_work_queue0"))
+            .delete()
+            .await
+            .unwrap();
+        let first = Session::new(&client, 5).await.unwrap();
+        let second = Session::new(&client, 5).await.unwrap();
+        let mut a = WorkQueue::new(&first, "test_work_queue");
+        let mut b = WorkQueue::new(&second, "test_work_queue");
+
+        a.enqueue("one").await.unwrap();
+        a.enqueue("two").await.unwrap();
+        let one = a.claim().await.unwrap();
+        assert_eq!(one.value(), b"one");
+        let two = b.try_claim().await.unwrap().unwrap();
+        assert_eq!(two.value(), b"two");
+        assert!(b.try_claim().await.unwrap().is_none());
+
+        // Released jobs are handed out again, acknowledged ones are gone
+        b.release(two).await.unwrap();
+        let two = b.claim().await.unwrap();
+        assert_eq!(two.value(), b"two");
+        b.ack(two).await.unwrap();
+        assert_eq!(a.len().await.unwrap(), 1);
+
+        // A job goes back to the queue if its worker's session ends
+        first.clone().close().await.unwrap();
+        assert_eq!(b.claim().await.unwrap().value(), b"one");
+        assert!(a.ack(one).await.is_err());
+        second.close().await.unwrap();
+    }
+
+    #[cfg(feature = "election")]
+    #[tokio::test]
+    async fn test_leader_elector() {
+        let client = crate::testing::namespace().await.unwrap();
+        let session = Session::new(&client, 5).await.unwrap();
+        let mut elector = LeaderElector::new(&session, "test_leader_elector", "me");
+        let mut leadership = elector.leadership();
+        assert!(!elector.is_leader());
+
+        let (elected, lost) = (std::cell::Cell::new(0), std::cell::Cell::new(0));
+        let shutdown = async {
+            // Initial value, then elected
+            leadership.recv().await;
+            leadership.recv().await;
+        };
+        elector
+            .run(
+                |_| elected.set(elected.get() + 1),
+                || lost.set(lost.get() + 1),
+                shutdown,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!((elected.get(), lost.get()), (1, 1));
+        assert!(!elector.is_leader());
+    }
+
+    #[cfg(feature = "election")]
+    #[tokio::test]
+    async fn test_leader_elector_revoked_lease() {
+        let client = crate::testing::namespace().await.unwrap();
+        let session = Session::new(&client, 5).await.unwrap();
+        let name = "test_leader_elector_revoked_lease";
+        let mut elector = LeaderElector::new(&session, name, "me");
+        let mut leadership = elector.leadership();
+
+        let (elected, lost) = (std::cell::Cell::new(0), std::cell::Cell::new(0));
+        let shutdown = async {
+            // Initial value, then elected
+            leadership.recv().await;
+            leadership.recv().await;
+            client.lease().revoke(session.lease()).await.unwrap();
+            // Leadership is lost, then won again under a new session
+            assert_eq!(leadership.recv().await, Some(false));
+            assert_eq!(leadership.recv().await, Some(true));
+        };
+        elector
+            .run(
+                |_| elected.set(elected.get() + 1),
+                || lost.set(lost.get() + 1),
+                shutdown,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!((elected.get(), lost.get()), (2, 2));
+        assert_ne!(elector.session.lease(), session.lease());
+    }
+
+    #[tokio::test]
+    async fn test_stm() {
+        let client = crate::testing::namespace().await.unwrap();
+        client.range("test_stm", None).put("1").await.unwrap();
+
+        let previous = stm(&client, Isolation::Serializable, |txn| async move {
+            let value = txn.get("test_stm").await?.unwrap();
+            let n: u64 = String::from_utf8(value.clone())?.parse()?;
+            txn.put("test_stm", (n + 1).to_string());
+            Ok(value)
+        })
+        .await
+        .unwrap();
+        assert_eq!(previous, b"1");
+
+        let current = client.range("test_stm", None).get().await.unwrap();
+        assert_eq!(current.get(b"test_stm").unwrap().value(), b"2");
+    }
+
+    #[tokio::test]
+    async fn test_sessions() {
+        let client = crate::testing::namespace().await.unwrap();
+        let session = Session::new(&client, 5).await.unwrap();
+        assert!(session.is_alive());
+        assert!(session.ttl() > 0);
+        session.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sharing_sessions() {
+        let client = crate::testing::namespace().await.unwrap();
+        let session = Session::new(&client, 5).await.unwrap();
+        let mut mutex = Mutex::new(&session, "test_sharing_sessions/mutex");
+        let mut semaphore = Semaphore::new(&session, "test_sharing_sessions/semaphore", 1).unwrap();
+        mutex.lock().await.unwrap();
+        semaphore.acquire().await.unwrap();
+
+        // Both keys are attached to the one lease, so closing the session releases both
+        let clone = session.clone();
+        assert_eq!(clone.lease(), session.lease());
+        session.close().await.unwrap();
+
+        let other = Session::new(&client, 5).await.unwrap();
+        assert!(Mutex::new(&other, "test_sharing_sessions/mutex")
+            .try_lock()
+            .await
+            .unwrap()
+            .is_some());
+        assert!(Semaphore::new(&other, "test_sharing_sessions/semaphore", 1)
+            .unwrap()
+            .try_acquire()
+            .await
+            .unwrap());
+        other.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_mutex() {
+        let client = crate::testing::namespace().await.unwrap();
+        let first = Session::new(&client, 5).await.unwrap();
+        let second = Session::new(&client, 5).await.unwrap();
+
+        let mut a = Mutex::new(&first, "test_mutex");
+        let mut b = Mutex::new(&second, "test_mutex");
+        let token = a.lock().await.unwrap();
+        assert_eq!(token.key(), a.key());
+        assert!(b.try_lock().await.unwrap().is_none());
+
+        // The token guards writes until the lock is released
+        token.put(&client, "test_mutex_fenced", "a").await.unwrap();
+
+        a.unlock().await.unwrap();
+        assert!(token.put(&client, "test_mutex_fenced", "a").await.is_err());
+        let newer = b.try_lock().await.unwrap().unwrap();
+        assert!(newer.revision() > token.revision());
+
+        // Closing the holder's session releases the lock
+        second.close().await.unwrap();
+        a.lock().await.unwrap();
+        a.unlock().await.unwrap();
+        first.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reentrant_mutex() {
+        let client = crate::testing::namespace().await.unwrap();
+        let first = Session::new(&client, 5).await.unwrap();
+        let second = Session::new(&client, 5).await.unwrap();
+
+        let mut a = Mutex::new(&first, "test_reentrant_mutex");
+        let mut b = Mutex::new(&first, "test_reentrant_mutex");
+        let mut other = Mutex::new(&second, "test_reentrant_mutex");
+        let token = a.lock().await.unwrap();
+        assert_eq!(a.lock().await.unwrap(), token);
+        assert_eq!(b.try_lock().await.unwrap(), Some(token));
+
+        // Held until every lock has been undone
+        a.unlock().await.unwrap();
+        a.unlock().await.unwrap();
+        assert!(other.try_lock().await.unwrap().is_none());
+        b.unlock().await.unwrap();
+        assert!(other.try_lock().await.unwrap().is_some());
+
+        other.unlock().await.unwrap();
+        first.close().await.unwrap();
+        second.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_try_lock_leaves_waiters_alone() {
+        let client = crate::testing::namespace().await.unwrap();
+        let first = Session::new(&client, 5).await.unwrap();
+        let second = Session::new(&client, 5).await.unwrap();
+
+        let name = "test_try_lock_leaves_waiters_alone";
+        let mut holder = Mutex::new(&first, name);
+        let mut waiter = Mutex::new(&second, name);
+        let mut other = Mutex::new(&second, name);
+        holder.lock().await.unwrap();
+        let waiting = waiter.lock();
+        futures::pin_mut!(waiting);
+        assert!(
+            runtime::timeout(waiting.as_mut(), Duration::from_millis(200))
+                .await
+                .is_err()
+        );
+
+        // The key belongs to the waiter, so failing to lock must leave it in the queue
+        assert!(other.try_lock().await.unwrap().is_none());
+        holder.unlock().await.unwrap();
+        waiting.await.unwrap();
+
+        first.close().await.unwrap();
+        second.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_mutex_deadlines() {
+        let client = crate::testing::namespace().await.unwrap();
+        let first = Session::new(&client, 5).await.unwrap();
+        let second = Session::new(&client, 5).await.unwrap();
+
+        let mut a = Mutex::new(&first, "test_mutex_deadlines");
+        let mut b = Mutex::new(&second, "test_mutex_deadlines");
+        a.lock().await.unwrap();
+        let waiter_key = String::from_utf8(b.key().to_vec()).unwrap();
+
+        // Timing out removes the waiter's key
+        let timeout = Duration::from_millis(100);
+        assert!(b.lock_timeout(timeout).await.is_err());
+        let waiter = client.range(waiter_key.as_str(), None).get().await.unwrap();
+        assert!(waiter.is_empty());
+
+        // So does dropping the lock future while it waits
+        assert!(tokio::timer::Timeout::new(b.lock(), timeout).await.is_err());
+        tokio::timer::delay_for(timeout).await;
+        let waiter = client.range(waiter_key.as_str(), None).get().await.unwrap();
+        assert!(waiter.is_empty());
+
+        a.unlock().await.unwrap();
+        b.lock_until(Instant::now() + timeout).await.unwrap();
+        b.unlock().await.unwrap();
+        first.close().await.unwrap();
+        second.close().await.unwrap();
+    }
+}
@@ -0,0 +1,323 @@
+//! Client settings read from a configuration file, see [`EtcdClient::from_config_file`].
+//!
+//! Files are TOML or YAML, told apart by their extension, and use the names of etcdctl's
+//! flags:
+//!
+//! ```toml
+//! endpoints = ["https://10.0.0.1:2379", "https://10.0.0.2:2379"]
+//! user = "root"
+//! password = "secret"
+//! dial_timeout = "5s"
+//! command_timeout = "30s"
+//!
+//! [tls]
+//! cacert = "ca.pem"
+//! cert = "client.pem"
+//! key = "client-key.pem"
+//! ```
+//!
+//! Every field is optional, but a file must give endpoints or a domain to look them up
+//! under. Unknown fields are rejected, so misspelt settings are not silently ignored.
+//!
+//! [`EtcdClient::from_config_file`]: crate::EtcdClient::from_config_file
+
+use crate::env::{parse_duration, with_scheme};
+use crate::{EtcdClientBuilder, EtcdResult, KeepAlive};
+use serde::de::{Deserializer, Error as _};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Settings for connecting to a cluster
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientConfig {
+    /// URLs of the endpoints to connect through. Endpoints without a scheme use `https` if
+    /// TLS is configured.
+    pub endpoints: Vec<String>,
+    /// Domain to look up the endpoints under, see [`EtcdClientBuilder::discovery_srv`]
+    pub discovery_srv: Option<String>,
+    /// Files to connect with TLS with
+    pub tls: Option<TlsFiles>,
+    /// User to authenticate as
+    pub user: Option<String>,
+    /// Password of `user`
+    pub password: Option<String>,
+    /// See [`EtcdClientBuilder::connect_timeout`]
+    #[serde(deserialize_with = "duration")]
+    pub dial_timeout: Option<Duration>,
+    /// See [`EtcdClientBuilder::timeout`]
+    #[serde(deserialize_with = "duration")]
+    pub command_timeout: Option<Duration>,
+    /// Time between keep-alive pings, see [`KeepAlive`]
+    #[serde(deserialize_with = "duration")]
+    pub keepalive_time: Option<Duration>,
+    /// Time to wait for keep-alive pings to be answered
+    #[serde(deserialize_with = "duration")]
+    pub keepalive_timeout: Option<Duration>,
+}
+
+/// Paths of the PEM files to connect with TLS with. Relative paths in a file are relative to
+/// the directory the file is in.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsFiles {
+    /// Certificate of the CA which signed the servers' certificates
+    pub cacert: Option<PathBuf>,
+    /// Certificate to authenticate the client with
+    pub cert: Option<PathBuf>,
+    /// Private key of the client certificate
+    pub key: Option<PathBuf>,
+    /// Name to verify the servers' certificates against, instead of each endpoint's host
+    pub domain_name: Option<String>,
+}
+
+/// A duration such as `5s`, in the format etcdctl takes
+fn duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = String::deserialize(deserializer)?;
+    match parse_duration(&value) {
+        Some(duration) => Ok(Some(duration)),
+        None => Err(D::Error::custom(format!(
+            "invalid duration {:?}, expected one such as 5s or 500ms",
+            value
+        ))),
+    }
+}
+
+impl ClientConfig {
+    /// Settings in the TOML or YAML file at `path`, depending on whether its extension is
+    /// `.toml`, or `.yaml` or `.yml`. Fails with a message naming the file, and the line and
+    /// field at fault, if it cannot be read or is not valid.
+    pub fn from_file<P>(path: P) -> EtcdResult<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        let parse = match extension {
+            Some("toml") => Self::from_toml,
+            Some("yaml") | Some("yml") => Self::from_yaml,
+            _ => {
+                let message = format!(
+                    "config file {} must be .toml, .yaml or .yml",
+                    path.display()
+                );
+                return Err(message.into());
+            }
+        };
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read config file {}: {}", path.display(), e))?;
+        let mut config = parse(&contents)
+            .map_err(|e| format!("invalid config file {}: {}", path.display(), e))?;
+        if let (Some(tls), Some(dir)) = (&mut config.tls, path.parent()) {
+            for file in vec![&mut tls.cacert, &mut tls.cert, &mut tls.key]
+                .into_iter()
+                .flatten()
+            {
+                *file = dir.join(&*file);
+            }
+        }
+        Ok(config)
+    }
+
+    /// Settings written as TOML. Errors start with the path of the field at fault, such as
+    /// `tls.cacert`.
+    pub fn from_toml(contents: &str) -> EtcdResult<Self> {
+        let deserializer = toml::Deserializer::new(contents);
+        let config: Self =
+            serde_path_to_error::deserialize(deserializer).map_err(|e| e.to_string())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Settings written as YAML
+    pub fn from_yaml(contents: &str) -> EtcdResult<Self> {
+        let deserializer = serde_yaml::Deserializer::from_str(contents);
+        let config: Self =
+            serde_path_to_error::deserialize(deserializer).map_err(|e| e.to_string())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check the fields which depend on each other
+    fn validate(&self) -> EtcdResult<()> {
+        if self.endpoints.is_empty() && self.discovery_srv.is_none() {
+            return Err("one of endpoints or discovery_srv must be given".into());
+        }
+        if self.user.is_some() != self.password.is_some() {
+            return Err("user and password must be given together".into());
+        }
+        Ok(())
+    }
+
+    /// Builder configured with these settings, reading the TLS files. Fails if a file cannot
+    /// be read, or TLS is configured without the `tls` or `tls-native` feature.
+    pub fn builder(&self) -> EtcdResult<EtcdClientBuilder> {
+        self.validate()?;
+        let mut builder = EtcdClientBuilder::default();
+        let secure = self.tls.is_some();
+        for endpoint in &self.endpoints {
+            builder = builder.endpoint(with_scheme(endpoint, secure));
+        }
+        if let Some(domain) = &self.discovery_srv {
+            builder = builder.discovery_srv(domain.clone());
+        }
+        if let Some(tls) = &self.tls {
+            builder = tls.configure(builder)?;
+        }
+        if let (Some(user), Some(password)) = (&self.user, &self.password) {
+            builder = builder.credentials(user.clone(), password.clone());
+        }
+        if let Some(timeout) = self.dial_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.command_timeout {
+            builder = builder.timeout(timeout);
+        }
+        if self.keepalive_time.is_some() || self.keepalive_timeout.is_some() {
+            let defaults = KeepAlive::default();
+            builder = builder.keep_alive(KeepAlive {
+                interval: self.keepalive_time.unwrap_or(defaults.interval),
+                timeout: self.keepalive_timeout.unwrap_or(defaults.timeout),
+                ..defaults
+            });
+        }
+        Ok(builder)
+    }
+}
+
+impl TlsFiles {
+    /// `builder` connecting with TLS using these files
+    #[cfg(any(feature = "tls", feature = "tls-native"))]
+    fn configure(&self, builder: EtcdClientBuilder) -> EtcdResult<EtcdClientBuilder> {
+        let read = |name: &str, path: &Option<PathBuf>| -> EtcdResult<Option<Vec<u8>>> {
+            match path {
+                Some(path) => std::fs::read(path).map(Some).map_err(|e| {
+                    format!("failed to read tls.{} {}: {}", name, path.display(), e).into()
+                }),
+                None => Ok(None),
+            }
+        };
+        Ok(builder.tls(crate::TlsConfig {
+            ca_certificate: read("cacert", &self.cacert)?,
+            client_certificate: read("cert", &self.cert)?,
+            client_key: read("key", &self.key)?,
+            domain_name: self.domain_name.clone(),
+            ..Default::default()
+        }))
+    }
+
+    #[cfg(not(any(feature = "tls", feature = "tls-native")))]
+    fn configure(&self, _builder: EtcdClientBuilder) -> EtcdResult<EtcdClientBuilder> {
+        Err("tls is configured but needs the tls or tls-native feature".into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(result: EtcdResult<ClientConfig>) -> String {
+        result.unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_parsing() {
+        let toml = r#"
+            endpoints = ["10.0.0.1:2379", "http://10.0.0.2:2379"]
+            user = "root"
+            password = "secret"
+            dial_timeout = "5s"
+            keepalive_time = "1m"
+        "#;
+        let yaml = "
+            endpoints: ['10.0.0.1:2379', 'http://10.0.0.2:2379']
+            user: root
+            password: secret
+            dial_timeout: 5s
+            keepalive_time: 1m
+        ";
+        let config = ClientConfig::from_toml(toml).unwrap();
+        assert_eq!(ClientConfig::from_yaml(yaml).unwrap(), config);
+        assert_eq!(config.dial_timeout, Some(Duration::from_secs(5)));
+        assert_eq!(config.command_timeout, None);
+
+        let builder = config.builder().unwrap();
+        assert_eq!(
+            builder.endpoints,
+            vec!["http://10.0.0.1:2379", "http://10.0.0.2:2379"]
+        );
+        assert_eq!(
+            builder.credentials,
+            Some(("root".to_string(), "secret".to_string()))
+        );
+        assert_eq!(builder.config.connect_timeout, Some(Duration::from_secs(5)));
+        let keep_alive = builder.config.keep_alive.unwrap();
+        assert_eq!(keep_alive.interval, Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        let err = error(ClientConfig::from_toml("endpoint = [\"a:2379\"]"));
+        assert!(err.contains("unknown field `endpoint`"));
+        let err = error(ClientConfig::from_yaml("endpoints: [a]\ndial_timeout: 5"));
+        assert!(err.contains("invalid duration \"5\""));
+        assert!(err.starts_with("dial_timeout: "));
+        let err = error(ClientConfig::from_toml("endpoints = \"a:2379\""));
+        assert!(err.contains("invalid type"));
+        let err = error(ClientConfig::from_toml("user = \"root\""));
+        assert!(err.contains("endpoints or discovery_srv"));
+        let err = error(ClientConfig::from_toml(
+            "endpoints = [\"a\"]\nuser = \"root\"",
+        ));
+        assert!(err.contains("user and password"));
+
+        let err = error(ClientConfig::from_file("etcd.json"));
+        assert!(err.contains("must be .toml, .yaml or .yml"));
+        let err = error(ClientConfig::from_file("/nonexistent/etcd.toml"));
+        assert!(err.contains("failed to read config file /nonexistent/etcd.toml"));
+
+        let path = std::env::temp_dir().join("test_invalid_files.toml");
+        std::fs::write(&path, "endpoints = [\"a\"]\n[tls]\ncacert = 1").unwrap();
+        let err = error(ClientConfig::from_file(&path));
+        std::fs::remove_file(&path).unwrap();
+        assert!(err.contains(&format!("invalid config file {}", path.display())));
+        assert!(err.contains("tls.cacert: "));
+        assert!(err.contains("invalid type: integer"));
+    }
+
+    #[test]
+    fn test_tls_files() {
+        let dir = std::env::temp_dir().join("test_tls_files");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("etcd.yaml");
+        std::fs::write(&path, "endpoints: [a:2379]\ntls:\n  cacert: ca.pem\n").unwrap();
+        let config = ClientConfig::from_file(&path).unwrap();
+        assert_eq!(
+            config.tls.as_ref().unwrap().cacert,
+            Some(dir.join("ca.pem"))
+        );
+
+        let err = match config.builder() {
+            Ok(_) => panic!("the certificate should not be found"),
+            Err(e) => e.to_string(),
+        };
+        #[cfg(any(feature = "tls", feature = "tls-native"))]
+        assert!(err.contains("failed to read tls.cacert"));
+        #[cfg(not(any(feature = "tls", feature = "tls-native")))]
+        assert!(err.contains("needs the tls or tls-native feature"));
+
+        #[cfg(any(feature = "tls", feature = "tls-native"))]
+        {
+            std::fs::write(dir.join("ca.pem"), b"ca").unwrap();
+            let builder = config.builder().unwrap();
+            assert_eq!(builder.endpoints, vec!["https://a:2379"]);
+            let tls = builder.config.tls.unwrap();
+            assert_eq!(tls.ca_certificate, Some(b"ca".to_vec()));
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,178 @@
+//! Changes to a client's connection to the cluster, see
+//! [`EtcdClient::connection_events`](crate::EtcdClient::connection_events).
+
+use crate::{Call, ClientHook, Error};
+use futures::channel::mpsc;
+use futures::Stream;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/// A change to how the client reaches the cluster
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ConnectionEvent {
+    /// Requests reach the cluster again, after failing to or after endpoints were unhealthy
+    Connected,
+    /// A request could not reach the cluster, so the transport is reconnecting. Followed by
+    /// [`Connected`](Self::Connected) once a request gets through again.
+    Reconnecting,
+    /// Requests are routed to other endpoints, after an endpoint sync or a health check
+    EndpointsSwitched {
+        /// The endpoints requests are now routed to
+        endpoints: Vec<String>,
+    },
+    /// Health checks found some of the endpoints unhealthy, see
+    /// [`EtcdClient::track_endpoint_health`](crate::EtcdClient::track_endpoint_health)
+    Degraded {
+        /// The endpoints found to be unhealthy
+        unhealthy: Vec<String>,
+    },
+}
+
+/// The connection events of a client from when they were subscribed to, see
+/// [`EtcdClient::connection_events`](crate::EtcdClient::connection_events)
+#[derive(Debug)]
+pub struct ConnectionEvents {
+    events: mpsc::UnboundedReceiver<ConnectionEvent>,
+}
+
+impl ConnectionEvents {
+    /// The next event, or `None` once the client and all its clones have been dropped
+    pub async fn next(&mut self) -> Option<ConnectionEvent> {
+        futures::StreamExt::next(self).await
+    }
+}
+
+impl Stream for ConnectionEvents {
+    type Item = ConnectionEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.events).poll_next(cx)
+    }
+}
+
+/// What is known about the connection, shared by a client and everything created from it
+#[derive(Debug, Default)]
+pub(crate) struct Connection {
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Whether the last request failed to reach the cluster
+    reconnecting: bool,
+    /// Endpoints the last health check found unhealthy
+    unhealthy: Vec<String>,
+    subscribers: Vec<mpsc::UnboundedSender<ConnectionEvent>>,
+}
+
+impl State {
+    fn publish(&mut self, event: ConnectionEvent) {
+        self.subscribers
+            .retain(|subscriber| subscriber.unbounded_send(event.clone()).is_ok());
+    }
+}
+
+impl Connection {
+    pub(crate) fn subscribe(&self) -> ConnectionEvents {
+        let (tx, rx) = mpsc::unbounded();
+        self.state.lock().unwrap().subscribers.push(tx);
+        ConnectionEvents { events: rx }
+    }
+
+    /// Record the outcome of a request, failing with `error` if given
+    pub(crate) fn record(&self, error: Option<&Error>) {
+        let unreachable = error.is_some_and(is_unreachable);
+        let mut state = self.state.lock().unwrap();
+        if unreachable && !state.reconnecting {
+            state.reconnecting = true;
+            state.publish(ConnectionEvent::Reconnecting);
+        } else if error.is_none() && state.reconnecting {
+            state.reconnecting = false;
+            state.publish(ConnectionEvent::Connected);
+        }
+    }
+
+    /// Record that requests are now routed to `endpoints`
+    pub(crate) fn switched(&self, endpoints: &[String]) {
+        let endpoints = endpoints.to_vec();
+        let mut state = self.state.lock().unwrap();
+        state.publish(ConnectionEvent::EndpointsSwitched { endpoints });
+    }
+
+    /// Record that the last health check found `unhealthy` to be unhealthy
+    pub(crate) fn checked(&self, unhealthy: &[String]) {
+        let mut state = self.state.lock().unwrap();
+        if state.unhealthy == unhealthy {
+            return;
+        }
+        state.unhealthy = unhealthy.to_vec();
+        if unhealthy.is_empty() {
+            state.publish(ConnectionEvent::Connected);
+        } else {
+            let unhealthy = unhealthy.to_vec();
+            state.publish(ConnectionEvent::Degraded { unhealthy });
+        }
+    }
+}
+
+impl ClientHook for Connection {
+    fn on_response(&self, _: &Call<'_>, _: Duration) {
+        self.record(None);
+    }
+
+    fn on_error(&self, _: &Call<'_>, _: Duration, error: &Error) {
+        self.record(Some(error));
+    }
+}
+
+/// Whether `error` means the request did not reach the cluster at all
+fn is_unreachable(error: &Error) -> bool {
+    match error.root() {
+        Error::Transport(_) => true,
+        Error::Status(status) => status.code() == tonic::Code::Unavailable,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn test_connection_events() {
+        let connection = Connection::default();
+        let mut events = connection.subscribe();
+        let unavailable = tonic::Status::new(tonic::Code::Unavailable, "connection refused");
+        let unavailable = Error::Status(unavailable);
+
+        connection.record(None);
+        connection.record(Some(&Error::NoSpace));
+        connection.record(Some(&unavailable));
+        connection.record(Some(&unavailable));
+        connection.record(None);
+        let endpoints = vec!["http://10.0.0.2:2379".to_string()];
+        connection.switched(&endpoints);
+        connection.checked(&["http://10.0.0.1:2379".to_string()]);
+        connection.checked(&["http://10.0.0.1:2379".to_string()]);
+        connection.checked(&[]);
+        drop(connection);
+
+        let events: Vec<_> = events.by_ref().collect().await;
+        assert_eq!(
+            events,
+            vec![
+                ConnectionEvent::Reconnecting,
+                ConnectionEvent::Connected,
+                ConnectionEvent::EndpointsSwitched { endpoints },
+                ConnectionEvent::Degraded {
+                    unhealthy: vec!["http://10.0.0.1:2379".to_string()]
+                },
+                ConnectionEvent::Connected,
+            ]
+        );
+    }
+}
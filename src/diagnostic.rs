@@ -0,0 +1,198 @@
+//! Diagnostic events of what a client does on its own to keep working, for investigating
+//! incidents, see [`EtcdClientBuilder::diagnostics`](crate::EtcdClientBuilder::diagnostics).
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Something the client did without being asked to, such as retrying a request or routing
+/// requests to other endpoints. Prints as a line fit for a log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Diagnostic {
+    /// A request failed and is sent again after `delay`, see
+    /// [`EtcdClientBuilder::retry`](crate::EtcdClientBuilder::retry)
+    Retry {
+        /// The operation, such as `put` or `member list`
+        operation: &'static str,
+        /// The attempt which failed, counting from one
+        attempt: u32,
+        /// How long the client waits before sending the request again
+        delay: Duration,
+        /// Why the attempt failed
+        error: String,
+    },
+    /// Requests are routed to other endpoints, after an endpoint sync or a health check
+    Failover {
+        /// The endpoints requests were routed to
+        from: Vec<String>,
+        /// The endpoints requests are now routed to
+        to: Vec<String>,
+    },
+    /// A watch broke or failed to be created, and was created again
+    WatchRecreated {
+        /// The key or prefix watched, unless the client redacts keys
+        key: Option<String>,
+        /// How many times it was created again
+        attempts: u32,
+    },
+    /// The client authenticated and was given a token. Clients authenticate once, while
+    /// connecting, see [`EtcdClientBuilder::credentials`](crate::EtcdClientBuilder::credentials).
+    Authenticated {
+        /// The user authenticated as
+        user: String,
+    },
+    /// Following an election's leaders failed, and the election is observed again after
+    /// [`OBSERVE_RETRY_INTERVAL`](crate::OBSERVE_RETRY_INTERVAL), see
+    /// [`Election::leaders`](crate::Election::leaders)
+    ObserveFailed {
+        /// The election observed, unless the client redacts keys
+        election: Option<String>,
+        /// Why the observation failed
+        error: String,
+    },
+    /// Background compaction failed, and is tried again at the next check, see
+    /// [`Kv::auto_compact`](crate::Kv::auto_compact)
+    CompactionFailed {
+        /// The revision being compacted to, or `None` if reading the current revision failed
+        revision: Option<i64>,
+        /// Why it failed
+        error: String,
+    },
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Diagnostic::Retry {
+                operation,
+                attempt,
+                delay,
+                error,
+            } => write!(
+                f,
+                "retrying {} in {:?} after attempt {} failed: {}",
+                operation, delay, attempt, error
+            ),
+            Diagnostic::Failover { from, to } => {
+                write!(f, "failing over from {:?} to {:?}", from, to)
+            }
+            Diagnostic::WatchRecreated { key, attempts } => {
+                f.write_str("recreated watch")?;
+                if let Some(key) = key {
+                    write!(f, " on {:?}", key)?;
+                }
+                write!(f, " ({} times)", attempts)
+            }
+            Diagnostic::Authenticated { user } => write!(f, "authenticated as {:?}", user),
+            Diagnostic::ObserveFailed { election, error } => {
+                f.write_str("observing election")?;
+                if let Some(election) = election {
+                    write!(f, " {:?}", election)?;
+                }
+                write!(f, " failed: {}", error)
+            }
+            Diagnostic::CompactionFailed { revision, error } => match revision {
+                Some(revision) => {
+                    write!(f, "compacting to revision {} failed: {}", revision, error)
+                }
+                None => write!(f, "reading the revision to compact to failed: {}", error),
+            },
+        }
+    }
+}
+
+type Callback = dyn Fn(&Diagnostic) + Send + Sync;
+
+/// Where a client's diagnostics go, if anywhere
+#[derive(Clone, Default)]
+pub(crate) struct Diagnostics(Option<Arc<Callback>>);
+
+impl Diagnostics {
+    pub(crate) fn new<F>(callback: F) -> Self
+    where
+        F: Fn(&Diagnostic) + Send + Sync + 'static,
+    {
+        Self(Some(Arc::new(callback)))
+    }
+
+    /// Emit the diagnostic made by `diagnostic`, which is only made if diagnostics are enabled
+    pub(crate) fn emit<F>(&self, diagnostic: F)
+    where
+        F: FnOnce() -> Diagnostic,
+    {
+        if let Some(callback) = &self.0 {
+            callback(&diagnostic());
+        }
+    }
+}
+
+impl fmt::Debug for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(_) => f.write_str("enabled"),
+            None => f.write_str("disabled"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_diagnostics() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let diagnostics = Diagnostics::default();
+        diagnostics.emit(|| unreachable!("diagnostics are disabled"));
+
+        let lines = seen.clone();
+        let diagnostics = Diagnostics::new(move |diagnostic| {
+            lines.lock().unwrap().push(diagnostic.to_string());
+        });
+        diagnostics.emit(|| Diagnostic::Retry {
+            operation: "put",
+            attempt: 1,
+            delay: Duration::from_millis(100),
+            error: "Unavailable: connection refused".to_string(),
+        });
+        diagnostics.emit(|| Diagnostic::Failover {
+            from: vec!["http://10.0.0.1:2379".to_string()],
+            to: vec!["http://10.0.0.2:2379".to_string()],
+        });
+        diagnostics.emit(|| Diagnostic::WatchRecreated {
+            key: Some("foo".to_string()),
+            attempts: 2,
+        });
+        diagnostics.emit(|| Diagnostic::Authenticated {
+            user: "root".to_string(),
+        });
+        diagnostics.emit(|| Diagnostic::ObserveFailed {
+            election: None,
+            error: "Unavailable: connection refused".to_string(),
+        });
+        diagnostics.emit(|| Diagnostic::CompactionFailed {
+            revision: Some(42),
+            error: "OutOfRange: mvcc: required revision has been compacted".to_string(),
+        });
+        diagnostics.emit(|| Diagnostic::CompactionFailed {
+            revision: None,
+            error: "Unavailable: connection refused".to_string(),
+        });
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                "retrying put in 100ms after attempt 1 failed: Unavailable: connection refused",
+                "failing over from [\"http://10.0.0.1:2379\"] to [\"http://10.0.0.2:2379\"]",
+                "recreated watch on \"foo\" (2 times)",
+                "authenticated as \"root\"",
+                "observing election failed: Unavailable: connection refused",
+                "compacting to revision 42 failed: OutOfRange: mvcc: required revision has been \
+                 compacted",
+                "reading the revision to compact to failed: Unavailable: connection refused",
+            ]
+        );
+        assert_eq!(format!("{:?}", diagnostics), "enabled");
+    }
+}
@@ -0,0 +1,402 @@
+//! Service registration and discovery.
+//!
+//! Each instance of a service registers itself with a key under the service's prefix,
+//! attached to its [`Session`]'s lease, so the session's keep-alive doubles as the instance's
+//! heartbeat: an instance which dies disappears once its lease expires. Clients discover the
+//! instances under a prefix and follow changes to them as they happen.
+
+use crate::concurrency::{prefix_end, read_revision, Session};
+use crate::redact::Key;
+use crate::{
+    client, etcdserver, is_retryable, mvccpb, runtime, Backoff, Diagnostic, EtcdClient, EtcdResult,
+};
+use futures::channel::mpsc;
+use futures::{SinkExt, StreamExt};
+use std::collections::BTreeMap;
+use std::fmt;
+use tonic::transport::channel::Channel;
+
+/// An instance's payload, stored as the value of its registration key
+pub trait Instance: Sized {
+    /// Serialize the instance into a key's value
+    fn encode(&self) -> Vec<u8>;
+
+    /// Deserialize an instance from a key's value
+    fn decode(bytes: &[u8]) -> Result<Self, String>;
+}
+
+impl Instance for Vec<u8> {
+    fn encode(&self) -> Vec<u8> {
+        self.clone()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, String> {
+        Ok(bytes.to_vec())
+    }
+}
+
+impl Instance for String {
+    fn encode(&self) -> Vec<u8> {
+        self.clone().into_bytes()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, String> {
+        String::from_utf8(bytes.to_vec()).map_err(|e| e.to_string())
+    }
+}
+
+/// The registration of a service instance, which lasts until it is deregistered or its
+/// session ends
+pub struct Registration {
+    kv_client: client::KvClient<Channel>,
+    key: Vec<u8>,
+    lease: i64,
+}
+
+impl fmt::Debug for Registration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Registration")
+            .field("key", &Key(&self.key))
+            .field("lease", &self.lease)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Register `instance` as `id` under the service `prefix` for as long as `session` lasts. An
+/// existing registration with the same ID is replaced.
+pub async fn register<P, I>(
+    session: &Session<Channel>,
+    prefix: P,
+    id: &str,
+    instance: &I,
+) -> EtcdResult<Registration>
+where
+    P: Into<Vec<u8>>,
+    I: Instance,
+{
+    let mut key = prefix.into();
+    key.extend(id.as_bytes());
+    let mut registration = Registration {
+        kv_client: session.client.kv_client(),
+        key,
+        lease: session.lease(),
+    };
+    registration.update(instance).await?;
+    Ok(registration)
+}
+
+impl Registration {
+    /// Key the instance is registered at
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// Replace the registered payload, for example to advertise a change in load
+    pub async fn update<I>(&mut self, instance: &I) -> EtcdResult<()>
+    where
+        I: Instance,
+    {
+        let request = etcdserver::PutRequest {
+            key: self.key.clone(),
+            value: instance.encode(),
+            lease: self.lease,
+            ..Default::default()
+        };
+        self.kv_client.put(request).await?;
+        Ok(())
+    }
+
+    /// Remove the instance straight away, rather than waiting for its session to end
+    pub async fn deregister(mut self) -> EtcdResult<()> {
+        let request = etcdserver::DeleteRangeRequest {
+            key: self.key.clone(),
+            ..Default::default()
+        };
+        self.kv_client.delete_range(request).await?;
+        Ok(())
+    }
+}
+
+/// A change to the instances of a service
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change<I> {
+    /// An instance registered, or updated its registration
+    Registered(String, I),
+    /// An instance deregistered or its session ended
+    Deregistered(String),
+}
+
+/// The instances of a service, kept up to date by following changes to them
+pub struct Discovery<I> {
+    instances: BTreeMap<String, I>,
+    changes: mpsc::Receiver<Result<Change<I>, String>>,
+}
+
+impl<I> fmt::Debug for Discovery<I>
+where
+    I: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Discovery")
+            .field("instances", &self.instances)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Discover the instances registered under the service `prefix`, and start following changes
+/// to them. Must be called from within a tokio runtime.
+///
+/// Instances whose payload cannot be decoded are reported as an error. If the watch following
+/// the changes breaks it is re-established with backoff, carrying on from the last change seen.
+pub async fn discover<P, I>(client: &EtcdClient<Channel>, prefix: P) -> EtcdResult<Discovery<I>>
+where
+    P: Into<Vec<u8>>,
+    I: Instance + Send + 'static,
+{
+    let prefix = prefix.into();
+    let request = etcdserver::RangeRequest {
+        key: prefix.clone(),
+        range_end: prefix_end(&prefix),
+        serializable: client.serializable_reads(),
+        ..Default::default()
+    };
+    let response = client.kv_client().range(request).await?.into_inner();
+    client.check_cluster_id(response.header.as_ref())?;
+    let revision = read_revision(response.header, "range")?;
+
+    let mut instances = BTreeMap::new();
+    for kv in response.kvs {
+        let id = instance_id(&prefix, &kv.key);
+        let instance =
+            I::decode(&kv.value).map_err(|e| format!("invalid instance {:?}: {}", id, e))?;
+        instances.insert(id, instance);
+    }
+
+    // Follow on from the revision the instances were read at, so no change is missed
+    let mut watch_client = client.watch_client();
+    let mut responses = watch(&mut watch_client, &prefix, revision + 1).await?;
+    let (mut tx, rx) = mpsc::channel(16);
+    let diagnostics = client.config.diagnostics.clone();
+    let key = client.error_context("watch", Some(&prefix)).key;
+
+    runtime::spawn(async move {
+        let mut backoff = Backoff::new();
+        // Revision of the last change seen, to carry on from if the watch breaks
+        let mut revision = revision;
+        loop {
+            let response = match responses.message().await {
+                Ok(Some(response)) => response,
+                Err(status) if !is_retryable(&status) => {
+                    let _ = tx.send(Err(status.to_string())).await;
+                    return;
+                }
+                // The watch broke, so carry on from the last change seen
+                _ => {
+                    let next = revision + 1;
+                    match rewatch(&mut watch_client, &prefix, next, &mut backoff, &mut tx).await {
+                        Some(rewatched) => {
+                            let key = key.clone();
+                            diagnostics.emit(|| Diagnostic::WatchRecreated { key, attempts: 1 });
+                            responses = rewatched;
+                        }
+                        None => return,
+                    }
+                    continue;
+                }
+            };
+            backoff.reset();
+            if response.canceled {
+                let reason = format!("watch canceled: {}", response.cancel_reason);
+                let _ = tx.send(Err(reason)).await;
+                return;
+            }
+            for event in response.events {
+                if let Some(kv) = &event.kv {
+                    revision = revision.max(kv.mod_revision);
+                }
+                if let Some(change) = change(&prefix, event) {
+                    if tx.send(change).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    });
+
+    Ok(Discovery {
+        instances,
+        changes: rx,
+    })
+}
+
+/// Watch for changes to the instances under `prefix` from `revision` onwards
+async fn watch(
+    watch_client: &mut client::WatchClient<Channel>,
+    prefix: &[u8],
+    revision: i64,
+) -> Result<tonic::Streaming<etcdserver::WatchResponse>, tonic::Status> {
+    let create = etcdserver::WatchCreateRequest {
+        key: prefix.to_vec(),
+        range_end: prefix_end(prefix),
+        start_revision: revision,
+        ..Default::default()
+    };
+    let requests = async_stream::stream! {
+        yield etcdserver::WatchRequest {
+            request_union: Some(etcdserver::watch_request::RequestUnion::CreateRequest(create)),
+        };
+    };
+    Ok(watch_client.watch(requests).await?.into_inner())
+}
+
+/// Re-establish a broken watch from `revision` onwards, backing off between attempts. Returns
+/// `None` once `changes` is closed or after sending it the error which stopped the watch
+/// being re-established.
+async fn rewatch<I>(
+    watch_client: &mut client::WatchClient<Channel>,
+    prefix: &[u8],
+    revision: i64,
+    backoff: &mut Backoff,
+    changes: &mut mpsc::Sender<Result<Change<I>, String>>,
+) -> Option<tonic::Streaming<etcdserver::WatchResponse>> {
+    loop {
+        backoff.wait().await;
+        if changes.is_closed() {
+            return None;
+        }
+        match watch(watch_client, prefix, revision).await {
+            Ok(responses) => {
+                #[cfg(feature = "metrics")]
+                crate::metrics::watch_reconnected(1);
+                return Some(responses);
+            }
+            Err(status) if is_retryable(&status) => continue,
+            Err(status) => {
+                let _ = changes.send(Err(status.to_string())).await;
+                return None;
+            }
+        }
+    }
+}
+
+impl<I> Discovery<I>
+where
+    I: Clone,
+{
+    /// The instances as of the last change received, by ID
+    pub fn instances(&self) -> &BTreeMap<String, I> {
+        &self.instances
+    }
+
+    /// Wait for the next change to the instances and apply it. Returns `None` once the changes
+    /// can no longer be followed, after the error which ended them.
+    ///
+    /// An instance whose new payload cannot be decoded is reported as an error and keeps its
+    /// previous payload.
+    pub async fn changed(&mut self) -> Option<Result<Change<I>, String>> {
+        let change = self.changes.next().await?;
+        match &change {
+            Ok(Change::Registered(id, instance)) => {
+                self.instances.insert(id.clone(), instance.clone());
+            }
+            Ok(Change::Deregistered(id)) => {
+                self.instances.remove(id);
+            }
+            Err(_) => {}
+        }
+        Some(change)
+    }
+}
+
+/// ID of the instance registered at `key` under `prefix`
+fn instance_id(prefix: &[u8], key: &[u8]) -> String {
+    String::from_utf8_lossy(&key[prefix.len().min(key.len())..]).into_owned()
+}
+
+/// The change to the instances under `prefix` made by `event`
+fn change<I>(prefix: &[u8], event: mvccpb::Event) -> Option<Result<Change<I>, String>>
+where
+    I: Instance,
+{
+    let kv = event.kv?;
+    let id = instance_id(prefix, &kv.key);
+    if event.r#type == mvccpb::event::EventType::Delete as i32 {
+        return Some(Ok(Change::Deregistered(id)));
+    }
+    Some(match I::decode(&kv.value) {
+        Ok(instance) => Ok(Change::Registered(id, instance)),
+        Err(e) => Err(format!("invalid instance {:?}: {}", id, e)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(event_type: mvccpb::event::EventType, key: &str, value: &[u8]) -> mvccpb::Event {
+        mvccpb::Event {
+            r#type: event_type as i32,
+            kv: Some(mvccpb::KeyValue {
+                key: key.as_bytes().to_vec(),
+                value: value.to_vec(),
+                ..Default::default()
+            }),
+            prev_kv: None,
+        }
+    }
+
+    #[test]
+    fn test_changes() {
+        let put = event(mvccpb::event::EventType::Put, "svc/a", b"10.0.0.1:80");
+        assert_eq!(
+            change::<String>(b"svc/", put),
+            Some(Ok(Change::Registered("a".into(), "10.0.0.1:80".into())))
+        );
+
+        let delete = event(mvccpb::event::EventType::Delete, "svc/a", b"");
+        assert_eq!(
+            change::<String>(b"svc/", delete),
+            Some(Ok(Change::Deregistered("a".into())))
+        );
+
+        let invalid = event(mvccpb::event::EventType::Put, "svc/b", &[0xff]);
+        assert!(change::<String>(b"svc/", invalid).unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_discovery() {
+        let client = crate::testing::namespace().await.unwrap();
+        let first = Session::new(&client, 5).await.unwrap();
+        let second = Session::new(&client, 5).await.unwrap();
+
+        let prefix = "test_discovery/";
+        let a = register(&first, prefix, "a", &"10.0.0.1:80".to_string())
+            .await
+            .unwrap();
+        let mut discovery = discover::<_, String>(&client, prefix).await.unwrap();
+        assert_eq!(discovery.instances().len(), 1);
+        assert_eq!(discovery.instances()["a"], "10.0.0.1:80");
+
+        let _b = register(&second, prefix, "b", &"10.0.0.2:80".to_string())
+            .await
+            .unwrap();
+        assert_eq!(
+            discovery.changed().await.unwrap().unwrap(),
+            Change::Registered("b".into(), "10.0.0.2:80".into())
+        );
+
+        // Instances go away when they deregister or their session ends
+        a.deregister().await.unwrap();
+        assert_eq!(
+            discovery.changed().await.unwrap().unwrap(),
+            Change::Deregistered("a".into())
+        );
+        second.close().await.unwrap();
+        assert_eq!(
+            discovery.changed().await.unwrap().unwrap(),
+            Change::Deregistered("b".into())
+        );
+        assert!(discovery.instances().is_empty());
+        first.close().await.unwrap();
+    }
+}
@@ -0,0 +1,387 @@
+//! The election service: campaigning for leadership and following the leader.
+
+use crate::{
+    mvccpb, pin_cluster_id, runtime, strip_namespace, v3election, Budget, Diagnostic, Error,
+    EtcdClient, EtcdResult, KeyValue, Retry, Transport,
+};
+use futures::channel::mpsc;
+use futures::SinkExt;
+use std::fmt;
+use std::time::Duration;
+
+/// How long [`Election::leaders`] waits before re-establishing a failed observation
+pub const OBSERVE_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Build the key identifying the leader of the election `name` from its proclamation
+fn leader_key(name: &[u8], kv: mvccpb::KeyValue) -> v3election::LeaderKey {
+    v3election::LeaderKey {
+        name: name.to_vec(),
+        key: kv.key,
+        rev: kv.create_revision,
+        lease: kv.lease,
+    }
+}
+
+/// The leader key `leader` inside `namespace`, as the server knows it
+fn namespaced_leader(namespace: &[u8], leader: &v3election::LeaderKey) -> v3election::LeaderKey {
+    let within = |key: &[u8]| [namespace, key].concat();
+    v3election::LeaderKey {
+        name: within(&leader.name),
+        key: within(&leader.key),
+        ..leader.clone()
+    }
+}
+
+/// The leader key `leader` from the server, outside of `namespace`
+fn strip_leader(namespace: &[u8], leader: v3election::LeaderKey) -> v3election::LeaderKey {
+    v3election::LeaderKey {
+        name: strip_namespace(namespace, &leader.name).to_vec(),
+        key: strip_namespace(namespace, &leader.key).to_vec(),
+        ..leader
+    }
+}
+
+/// `kv` from the server, with its key outside of `namespace`
+fn strip_kv(namespace: &[u8], kv: mvccpb::KeyValue) -> mvccpb::KeyValue {
+    mvccpb::KeyValue {
+        key: strip_namespace(namespace, &kv.key).to_vec(),
+        ..kv
+    }
+}
+
+/// Leader election using etcd's election service
+pub struct Election<T> {
+    pub(crate) client: EtcdClient<T>,
+}
+
+impl<T> fmt::Debug for Election<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Election")
+            .field("client", &self.client)
+            .finish()
+    }
+}
+
+impl<T: Transport> Election<T> {
+    /// Campaign to become leader of the election `name`, waiting until leadership is acquired,
+    /// and announce `value` once elected.
+    ///
+    /// Leadership is tied to `lease`: it is lost if the lease expires or is revoked. The
+    /// returned key is needed to proclaim new values and to resign.
+    pub async fn campaign<N, V>(
+        &self,
+        name: N,
+        lease: i64,
+        value: V,
+    ) -> EtcdResult<v3election::LeaderKey>
+    where
+        N: Into<Vec<u8>>,
+        V: Into<Vec<u8>>,
+    {
+        let name = name.into();
+        let request = v3election::CampaignRequest {
+            name: self.client.namespaced_key(&name),
+            lease,
+            value: value.into(),
+        };
+        let context = self.client.error_context("campaign", Some(&name));
+        let rpc = || {
+            let (mut election_client, request) = (self.client.election_client(), request.clone());
+            async move { election_client.campaign(request).await }
+        };
+        let elected = |response: v3election::CampaignResponse| match response.leader {
+            Some(leader) => Ok(strip_leader(&self.client.namespace, leader)),
+            None => Err(Error::invalid_response(
+                "campaign response is missing the leader key",
+            )),
+        };
+        self.client
+            .call(
+                "election.campaign",
+                context,
+                Budget::Write,
+                Retry::Never,
+                rpc,
+                elected,
+            )
+            .await
+    }
+
+    /// Announce a new value as the leader holding `leader`. Fails if leadership has been lost.
+    pub async fn proclaim<V>(&self, leader: &v3election::LeaderKey, value: V) -> EtcdResult<()>
+    where
+        V: Into<Vec<u8>>,
+    {
+        let request = v3election::ProclaimRequest {
+            leader: Some(namespaced_leader(&self.client.namespace, leader)),
+            value: value.into(),
+        };
+        let context = self.client.error_context("proclaim", Some(&leader.name));
+        let rpc = || {
+            let (mut election_client, request) = (self.client.election_client(), request.clone());
+            async move { election_client.proclaim(request).await }
+        };
+        self.client
+            .call(
+                "election.proclaim",
+                context,
+                Budget::Write,
+                Retry::Never,
+                rpc,
+                |_| Ok(()),
+            )
+            .await
+    }
+
+    /// The current leader's key and value. The server returns an error if the election has no
+    /// leader.
+    pub async fn leader<N>(&self, name: N) -> EtcdResult<KeyValue>
+    where
+        N: Into<Vec<u8>>,
+    {
+        let name = name.into();
+        let request = v3election::LeaderRequest {
+            name: self.client.namespaced_key(&name),
+        };
+        let context = self.client.error_context("leader", Some(&name));
+        let rpc = || {
+            let (mut election_client, request) = (self.client.election_client(), request.clone());
+            async move { election_client.leader(request).await }
+        };
+        let leader = |response: v3election::LeaderResponse| match response.kv {
+            Some(kv) => Ok(KeyValue::new(kv, &self.client.namespace)),
+            None => Err("the election has no leader".into()),
+        };
+        self.client
+            .call(
+                "election.leader",
+                context,
+                Budget::Read,
+                Retry::Never,
+                rpc,
+                leader,
+            )
+            .await
+    }
+
+    /// Stream of the values proclaimed in the election `name`, in order, starting with the
+    /// current leader's.
+    ///
+    /// The stream ends after the first error. The background task reading from the server
+    /// stops once the receiver is dropped and the next proclamation arrives. Must be called
+    /// from within a tokio runtime.
+    pub async fn observe<N>(
+        &self,
+        name: N,
+    ) -> EtcdResult<mpsc::Receiver<Result<KeyValue, tonic::Status>>>
+    where
+        N: Into<Vec<u8>>,
+    {
+        let name = name.into();
+        let request = v3election::LeaderRequest {
+            name: self.client.namespaced_key(&name),
+        };
+        let namespace = self.client.namespace.clone();
+        let context = self.client.error_context("observe", Some(&name));
+        let operation = self.client.operation("election.observe", &context);
+        let call = async {
+            let _in_flight = self.client.config.throttle(Budget::Read).await?;
+            let response = self.client.election_client().observe(request).await?;
+            Ok(response.into_inner())
+        };
+        let result: EtcdResult<_> = operation.run(call).await;
+        operation.finish(&context, &result);
+        let mut inbound = result.map_err(|e| context.attach(e))?;
+        let (mut tx, rx) = mpsc::channel(1);
+
+        runtime::spawn(async move {
+            loop {
+                let item = match inbound.message().await {
+                    Ok(Some(response)) => match response.kv {
+                        Some(kv) => Ok(KeyValue::new(kv, &namespace)),
+                        None => continue,
+                    },
+                    Ok(None) => break,
+                    Err(status) => Err(status),
+                };
+                let failed = item.is_err();
+                if tx.send(item).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        Ok(rx)
+    }
+
+    /// Follow the leadership of the election `name`, yielding the key of each new leader.
+    ///
+    /// Unlike [`observe`](Self::observe), the stream survives errors: each is reported as a
+    /// [`Diagnostic::ObserveFailed`], the observation is re-established after
+    /// [`OBSERVE_RETRY_INTERVAL`] and a leader already yielded is not yielded again. The stream
+    /// only ends once it is dropped, or if the server turns out to belong to a different
+    /// cluster than the one the client is pinned to. Must be called from within a tokio
+    /// runtime.
+    pub fn leaders<N>(&self, name: N) -> mpsc::Receiver<v3election::LeaderKey>
+    where
+        N: Into<Vec<u8>>,
+    {
+        let name = name.into();
+        let namespace = self.client.namespace.clone();
+        let mut election_client = self.client.election_client();
+        let mut cluster_id = self.client.cluster_id();
+        let diagnostics = self.client.config.diagnostics.clone();
+        let election = self.client.error_context("observe", Some(&name)).key;
+        let (mut tx, rx) = mpsc::channel(1);
+
+        runtime::spawn(async move {
+            let mut last: Option<v3election::LeaderKey> = None;
+            loop {
+                let request = v3election::LeaderRequest {
+                    name: [&namespace[..], &name].concat(),
+                };
+                let failed = match election_client.observe(request).await {
+                    Ok(response) => {
+                        let mut inbound = response.into_inner();
+                        loop {
+                            let response = match inbound.message().await {
+                                Ok(Some(response)) => response,
+                                Ok(None) => break None,
+                                Err(status) => break Some(status),
+                            };
+                            if pin_cluster_id(&mut cluster_id, response.header.as_ref()).is_err() {
+                                return;
+                            }
+                            let leader = match response.kv {
+                                Some(kv) => leader_key(&name, strip_kv(&namespace, kv)),
+                                None => continue,
+                            };
+                            if last.as_ref() == Some(&leader) {
+                                continue;
+                            }
+                            last = Some(leader.clone());
+                            if tx.send(leader).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    Err(status) => Some(status),
+                };
+                if let Some(status) = failed {
+                    diagnostics.emit(|| Diagnostic::ObserveFailed {
+                        election: election.clone(),
+                        error: format!("{:?}: {}", status.code(), status.message()),
+                    });
+                }
+
+                runtime::delay_for(OBSERVE_RETRY_INTERVAL).await;
+                if tx.is_closed() {
+                    return;
+                }
+            }
+        });
+
+        rx
+    }
+
+    /// Give up leadership, letting the next campaigner take over
+    pub async fn resign(&self, leader: v3election::LeaderKey) -> EtcdResult<()> {
+        let request = v3election::ResignRequest {
+            leader: Some(namespaced_leader(&self.client.namespace, &leader)),
+        };
+        let context = self.client.error_context("resign", Some(&leader.name));
+        let rpc = || {
+            let (mut election_client, request) = (self.client.election_client(), request.clone());
+            async move { election_client.resign(request).await }
+        };
+        self.client
+            .call(
+                "election.resign",
+                context,
+                Budget::Write,
+                Retry::Never,
+                rpc,
+                |_| Ok(()),
+            )
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_elections() {
+        use futures::StreamExt;
+
+        let client = crate::testing::namespace().await.unwrap();
+        let election = client.election();
+        let leader = election
+            .campaign("test_elections", 0, "first")
+            .await
+            .unwrap();
+        assert_eq!(
+            election.leader("test_elections").await.unwrap().value(),
+            b"first"
+        );
+
+        let mut proclamations = election.observe("test_elections").await.unwrap();
+        let first = proclamations.next().await.unwrap().unwrap();
+        assert_eq!(first.value(), b"first");
+        election.proclaim(&leader, "second").await.unwrap();
+        assert_eq!(
+            proclamations.next().await.unwrap().unwrap().value(),
+            b"second"
+        );
+
+        election.resign(leader).await.unwrap();
+        assert!(election.leader("test_elections").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_following_leaders() {
+        use futures::StreamExt;
+
+        let client = crate::testing::namespace().await.unwrap();
+        let election = client.election();
+        let first = election
+            .campaign("test_following_leaders", 0, "first")
+            .await
+            .unwrap();
+
+        let mut leaders = election.leaders("test_following_leaders");
+        assert_eq!(leaders.next().await.unwrap(), first);
+
+        // Proclamations by the same leader are not yielded again
+        election.proclaim(&first, "again").await.unwrap();
+        election.resign(first.clone()).await.unwrap();
+        let second = election
+            .campaign("test_following_leaders", 0, "second")
+            .await
+            .unwrap();
+        assert_eq!(leaders.next().await.unwrap(), second);
+        election.resign(second).await.unwrap();
+    }
+
+    #[test]
+    fn test_leader_key() {
+        let kv = mvccpb::KeyValue {
+            key: b"election/694d7".to_vec(),
+            create_revision: 12,
+            mod_revision: 15,
+            lease: 0x694d7,
+            value: b"leader".to_vec(),
+            ..Default::default()
+        };
+        assert_eq!(
+            leader_key(b"election", kv),
+            v3election::LeaderKey {
+                name: b"election".to_vec(),
+                key: b"election/694d7".to_vec(),
+                rev: 12,
+                lease: 0x694d7,
+            }
+        );
+    }
+}
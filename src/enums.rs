@@ -0,0 +1,186 @@
+//! Enums for the integer fields of the protocol's messages.
+//!
+//! The generated messages store enums as raw `i32`s. Each enum here converts into them with
+//! `From`, and from them with `TryFrom`, failing on values this client does not know.
+
+use crate::{authpb, etcdserver, mvccpb, Error};
+use std::convert::TryFrom;
+
+macro_rules! proto_enum {
+    (
+        $(#[$meta:meta])*
+        $name:ident => $proto:ty {
+            $($(#[$variant_meta:meta])* $variant:ident => $proto_variant:ident),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+        pub enum $name {
+            $($(#[$variant_meta])* $variant),*
+        }
+
+        impl From<$name> for $proto {
+            fn from(value: $name) -> Self {
+                type Proto = $proto;
+                match value {
+                    $($name::$variant => Proto::$proto_variant),*
+                }
+            }
+        }
+
+        impl From<$proto> for $name {
+            fn from(value: $proto) -> Self {
+                type Proto = $proto;
+                match value {
+                    $(Proto::$proto_variant => $name::$variant),*
+                }
+            }
+        }
+
+        impl From<$name> for i32 {
+            fn from(value: $name) -> Self {
+                <$proto>::from(value) as i32
+            }
+        }
+
+        impl TryFrom<i32> for $name {
+            type Error = Error;
+
+            fn try_from(value: i32) -> Result<Self, Error> {
+                match <$proto>::from_i32(value) {
+                    Some(value) => Ok(value.into()),
+                    None => {
+                        let unknown = format!("unknown {} {}", stringify!($name), value);
+                        Err(Error::invalid_response(unknown))
+                    }
+                }
+            }
+        }
+    };
+}
+
+proto_enum! {
+    /// Kind of change a [`WatchEvent`](crate::WatchEvent) reports
+    EventKind => mvccpb::event::EventType {
+        Put => Put,
+        Delete => Delete,
+    }
+}
+
+proto_enum! {
+    /// Order the keys of a range are read in
+    SortOrder => etcdserver::range_request::SortOrder {
+        /// Key order, the default
+        None => None,
+        Ascend => Ascend,
+        Descend => Descend,
+    }
+}
+
+proto_enum! {
+    /// What the keys of a range are sorted by
+    SortTarget => etcdserver::range_request::SortTarget {
+        Key => Key,
+        Version => Version,
+        Create => Create,
+        Mod => Mod,
+        Value => Value,
+    }
+}
+
+proto_enum! {
+    /// How a transaction's comparison checks its target
+    CompareResult => etcdserver::compare::CompareResult {
+        Equal => Equal,
+        Greater => Greater,
+        Less => Less,
+        NotEqual => NotEqual,
+    }
+}
+
+proto_enum! {
+    /// What of a key a transaction's comparison checks
+    CompareTarget => etcdserver::compare::CompareTarget {
+        Version => Version,
+        Create => Create,
+        Mod => Mod,
+        Value => Value,
+        Lease => Lease,
+    }
+}
+
+proto_enum! {
+    /// Which watch events to leave out
+    WatchFilter => etcdserver::watch_create_request::FilterType {
+        NoPut => Noput,
+        NoDelete => Nodelete,
+    }
+}
+
+proto_enum! {
+    /// Access a role's permission grants to a range of keys
+    PermissionType => authpb::permission::Type {
+        Read => Read,
+        Write => Write,
+        ReadWrite => Readwrite,
+    }
+}
+
+proto_enum! {
+    /// Kind of alarm a member raises
+    AlarmType => etcdserver::AlarmType {
+        /// No alarm, used to ask for every active alarm
+        None => None,
+        /// The member's space quota is exhausted
+        NoSpace => Nospace,
+        /// The member's store is corrupt
+        Corrupt => Corrupt,
+    }
+}
+
+proto_enum! {
+    /// What an alarm request does
+    AlarmAction => etcdserver::alarm_request::AlarmAction {
+        Get => Get,
+        Activate => Activate,
+        Deactivate => Deactivate,
+    }
+}
+
+proto_enum! {
+    /// What a downgrade request does, see
+    /// [`Maintenance::downgrade`](crate::Maintenance::downgrade)
+    DowngradeAction => etcdserver::downgrade_request::DowngradeAction {
+        /// Check the cluster can be downgraded to the version, without downgrading it
+        Validate => Validate,
+        /// Start downgrading the cluster to the version
+        Enable => Enable,
+        /// Stop the downgrade in progress
+        Cancel => Cancel,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(i32::from(SortOrder::Descend), 2);
+        assert_eq!(SortTarget::try_from(3).unwrap(), SortTarget::Mod);
+        assert_eq!(
+            etcdserver::compare::CompareResult::from(CompareResult::NotEqual),
+            etcdserver::compare::CompareResult::NotEqual
+        );
+        assert_eq!(
+            PermissionType::from(authpb::permission::Type::Readwrite),
+            PermissionType::ReadWrite
+        );
+        assert_eq!(i32::from(AlarmType::NoSpace), 1);
+
+        let err = EventKind::try_from(7).unwrap_err();
+        assert!(matches!(err, Error::InvalidResponse(_)));
+        assert!(err.to_string().contains("unknown EventKind 7"));
+    }
+}
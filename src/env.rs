@@ -0,0 +1,313 @@
+//! Connection settings from the environment variables etcdctl reads.
+//!
+//! Deployments often configure etcdctl through `ETCDCTL_*` variables, so a binary using
+//! [`EtcdClient::from_env`](crate::EtcdClient::from_env) talks to the same cluster, with the
+//! same certificates and credentials, without any configuration of its own.
+
+use crate::{EtcdClientBuilder, EtcdResult, KeepAlive};
+use std::time::Duration;
+
+/// Endpoint etcdctl connects to if `ETCDCTL_ENDPOINTS` is not set
+const DEFAULT_ENDPOINT: &str = "127.0.0.1:2379";
+
+/// Builder configured from the variables given by `var`, which returns `None` for variables
+/// which are not set
+pub(crate) fn builder<F>(var: F) -> EtcdResult<EtcdClientBuilder>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let var = |name: &str| var(name).filter(|value| !value.is_empty());
+    let mut builder = EtcdClientBuilder::default();
+
+    if flag(var, "ETCDCTL_INSECURE_SKIP_TLS_VERIFY")? {
+        return Err("ETCDCTL_INSECURE_SKIP_TLS_VERIFY is not supported".into());
+    }
+    let tls = tls(var)?;
+    #[cfg(any(feature = "tls", feature = "tls-native"))]
+    let secure = tls.is_some();
+    #[cfg(not(any(feature = "tls", feature = "tls-native")))]
+    let secure = tls;
+
+    if let Some(domain) = var("ETCDCTL_DISCOVERY_SRV") {
+        builder = builder.discovery_srv(domain);
+    } else {
+        let endpoints = var("ETCDCTL_ENDPOINTS").unwrap_or_else(|| DEFAULT_ENDPOINT.to_string());
+        for endpoint in endpoints.split(',').map(str::trim) {
+            if endpoint.is_empty() {
+                continue;
+            }
+            builder = builder.endpoint(with_scheme(endpoint, secure));
+        }
+    }
+
+    #[cfg(any(feature = "tls", feature = "tls-native"))]
+    {
+        if let Some(tls) = tls {
+            builder = builder.tls(tls);
+        }
+    }
+
+    if let Some(user) = var("ETCDCTL_USER") {
+        // etcdctl takes the password after a colon, or from ETCDCTL_PASSWORD
+        let (user, password) = match var("ETCDCTL_PASSWORD") {
+            Some(password) => (user, password),
+            None => match user.find(':') {
+                Some(colon) => (user[..colon].to_string(), user[colon + 1..].to_string()),
+                None => {
+                    return Err(
+                        "ETCDCTL_USER must be user:password if ETCDCTL_PASSWORD is not set".into(),
+                    )
+                }
+            },
+        };
+        builder = builder.credentials(user, password);
+    }
+
+    if let Some(timeout) = duration(var, "ETCDCTL_DIAL_TIMEOUT")? {
+        builder = builder.connect_timeout(timeout);
+    }
+    if let Some(timeout) = duration(var, "ETCDCTL_COMMAND_TIMEOUT")? {
+        builder = builder.timeout(timeout);
+    }
+    let interval = duration(var, "ETCDCTL_KEEPALIVE_TIME")?;
+    let timeout = duration(var, "ETCDCTL_KEEPALIVE_TIMEOUT")?;
+    if interval.is_some() || timeout.is_some() {
+        let defaults = KeepAlive::default();
+        builder = builder.keep_alive(KeepAlive {
+            interval: interval.unwrap_or(defaults.interval),
+            timeout: timeout.unwrap_or(defaults.timeout),
+            ..defaults
+        });
+    }
+    Ok(builder)
+}
+
+/// `endpoint` as a URL, with the scheme etcdctl assumes if it has none: `https` if the
+/// connection is `secure`
+pub(crate) fn with_scheme(endpoint: &str, secure: bool) -> String {
+    if endpoint.contains("://") {
+        endpoint.to_string()
+    } else if secure {
+        format!("https://{}", endpoint)
+    } else {
+        format!("http://{}", endpoint)
+    }
+}
+
+/// TLS settings from `ETCDCTL_CACERT`, `ETCDCTL_CERT` and `ETCDCTL_KEY`, which name PEM files
+#[cfg(any(feature = "tls", feature = "tls-native"))]
+fn tls<F>(var: F) -> EtcdResult<Option<crate::TlsConfig>>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let read = |name: &str| -> EtcdResult<Option<Vec<u8>>> {
+        match var(name) {
+            Some(path) => std::fs::read(&path)
+                .map(Some)
+                .map_err(|e| format!("failed to read {} {}: {}", name, path, e).into()),
+            None => Ok(None),
+        }
+    };
+    let config = crate::TlsConfig {
+        ca_certificate: read("ETCDCTL_CACERT")?,
+        client_certificate: read("ETCDCTL_CERT")?,
+        client_key: read("ETCDCTL_KEY")?,
+        ..Default::default()
+    };
+    if config == crate::TlsConfig::default() {
+        Ok(None)
+    } else {
+        Ok(Some(config))
+    }
+}
+
+/// Whether any TLS files are named, which cannot be used without a TLS feature
+#[cfg(not(any(feature = "tls", feature = "tls-native")))]
+fn tls<F>(var: F) -> EtcdResult<bool>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    for name in &["ETCDCTL_CACERT", "ETCDCTL_CERT", "ETCDCTL_KEY"] {
+        if var(name).is_some() {
+            let message = format!(
+                "{} is set but TLS needs the tls or tls-native feature",
+                name
+            );
+            return Err(message.into());
+        }
+    }
+    Ok(false)
+}
+
+/// The boolean variable `name`, false if it is not set
+fn flag<F>(var: F, name: &str) -> EtcdResult<bool>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    match var(name).as_deref() {
+        None | Some("false") | Some("0") => Ok(false),
+        Some("true") | Some("1") => Ok(true),
+        Some(value) => Err(format!("{} must be true or false, not {:?}", name, value).into()),
+    }
+}
+
+/// The duration variable `name`, see [`parse_duration`]
+fn duration<F>(var: F, name: &str) -> EtcdResult<Option<Duration>>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    match var(name) {
+        Some(value) => match parse_duration(&value) {
+            Some(duration) => Ok(Some(duration)),
+            None => Err(format!("{} must be a duration such as 5s, not {:?}", name, value).into()),
+        },
+        None => Ok(None),
+    }
+}
+
+/// A duration written the way etcdctl takes them, as a sequence of numbers with units such
+/// as `500ms`, `2s` or `1m30s`. `None` if `value` is not one.
+pub(crate) fn parse_duration(value: &str) -> Option<Duration> {
+    if value == "0" {
+        return Some(Duration::from_secs(0));
+    }
+    let mut rest = value;
+    let mut total = Duration::from_secs(0);
+    while !rest.is_empty() {
+        let number_len = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let number: f64 = rest[..number_len].parse().ok()?;
+        rest = &rest[number_len..];
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        let nanos = match &rest[..unit_len] {
+            "ns" => 1.0,
+            "us" | "µs" => 1e3,
+            "ms" => 1e6,
+            "s" => 1e9,
+            "m" => 60e9,
+            "h" => 3600e9,
+            _ => return None,
+        };
+        rest = &rest[unit_len..];
+        total += Duration::from_nanos((number * nanos).round() as u64);
+    }
+    if value.is_empty() {
+        None
+    } else {
+        Some(total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn vars(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    fn error(vars: impl Fn(&str) -> Option<String>) -> String {
+        match builder(vars) {
+            Ok(_) => panic!("the variables should be rejected"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("2s"), Some(Duration::from_secs(2)));
+        assert_eq!(parse_duration("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_duration("1m30s"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("1.5h"), Some(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("0"), Some(Duration::from_secs(0)));
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("5"), None);
+        assert_eq!(parse_duration("5 s"), None);
+        assert_eq!(parse_duration("fast"), None);
+    }
+
+    #[test]
+    fn test_builder_from_vars() {
+        let settings = builder(vars(&[])).unwrap();
+        assert_eq!(settings.endpoints, vec!["http://127.0.0.1:2379"]);
+        assert_eq!(settings.credentials, None);
+
+        let settings = builder(vars(&[
+            ("ETCDCTL_ENDPOINTS", "10.0.0.1:2379, http://10.0.0.2:2379"),
+            ("ETCDCTL_USER", "root:se:cret"),
+            ("ETCDCTL_DIAL_TIMEOUT", "3s"),
+            ("ETCDCTL_COMMAND_TIMEOUT", "1m"),
+            ("ETCDCTL_KEEPALIVE_TIME", "20s"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            settings.endpoints,
+            vec!["http://10.0.0.1:2379", "http://10.0.0.2:2379"]
+        );
+        assert_eq!(
+            settings.credentials,
+            Some(("root".to_string(), "se:cret".to_string()))
+        );
+        assert_eq!(
+            settings.config.connect_timeout,
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(settings.config.timeout, Some(Duration::from_secs(60)));
+        let keep_alive = settings.config.keep_alive.unwrap();
+        assert_eq!(keep_alive.interval, Duration::from_secs(20));
+        assert_eq!(keep_alive.timeout, KeepAlive::default().timeout);
+
+        let settings = builder(vars(&[
+            ("ETCDCTL_USER", "root"),
+            ("ETCDCTL_PASSWORD", "secret"),
+            ("ETCDCTL_DISCOVERY_SRV", "example.com"),
+        ]))
+        .unwrap();
+        assert!(settings.endpoints.is_empty());
+        assert_eq!(settings.discovery_srv.as_deref(), Some("example.com"));
+        assert_eq!(
+            settings.credentials,
+            Some(("root".to_string(), "secret".to_string()))
+        );
+
+        let err = error(vars(&[("ETCDCTL_USER", "root")]));
+        assert!(err.contains("ETCDCTL_PASSWORD"));
+        let err = error(vars(&[("ETCDCTL_DIAL_TIMEOUT", "3")]));
+        assert!(err.contains("ETCDCTL_DIAL_TIMEOUT"));
+        let err = error(vars(&[("ETCDCTL_INSECURE_SKIP_TLS_VERIFY", "true")]));
+        assert!(err.contains("not supported"));
+    }
+
+    #[test]
+    fn test_tls_from_vars() {
+        let err = error(vars(&[("ETCDCTL_CACERT", "/nonexistent/ca.pem")]));
+        #[cfg(any(feature = "tls", feature = "tls-native"))]
+        assert!(err.contains("failed to read ETCDCTL_CACERT"));
+        #[cfg(not(any(feature = "tls", feature = "tls-native")))]
+        assert!(err.to_string().contains("TLS needs"));
+
+        #[cfg(any(feature = "tls", feature = "tls-native"))]
+        {
+            let path = std::env::temp_dir().join("test_tls_from_vars.pem");
+            std::fs::write(&path, b"ca").unwrap();
+            let settings = builder(vars(&[
+                ("ETCDCTL_ENDPOINTS", "10.0.0.1:2379"),
+                ("ETCDCTL_CACERT", path.to_str().unwrap()),
+            ]))
+            .unwrap();
+            std::fs::remove_file(path).unwrap();
+            assert_eq!(settings.endpoints, vec!["https://10.0.0.1:2379"]);
+            let tls = settings.config.tls.unwrap();
+            assert_eq!(tls.ca_certificate, Some(b"ca".to_vec()));
+            assert_eq!(tls.client_certificate, None);
+        }
+    }
+}
@@ -0,0 +1,741 @@
+//! Errors returned by the client.
+//!
+//! Every operation fails with an [`Error`], which tells the causes callers commonly handle
+//! apart from one another. It converts into a `Box<dyn std::error::Error>` like any other
+//! error, so code written against the boxed errors the client used to return keeps working
+//! with `?`. Errors are `Send` and `Sync`, so they can be returned from spawned tasks.
+//!
+//! Errors from operations on keys and the cluster come with an [`ErrorContext`] saying which
+//! operation failed and how; match on [`Error::root`] to look past it.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Result of an operation on the cluster
+pub type EtcdResult<T> = Result<T, Error>;
+
+/// Why an operation on the cluster failed
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The client could not connect to the cluster
+    #[error(transparent)]
+    Transport(#[from] tonic::transport::Error),
+    /// The server answered the request with an error not told apart by another variant
+    #[error(transparent)]
+    Status(tonic::Status),
+    /// The server's response was missing something it should always carry
+    #[error("invalid response: {0}")]
+    InvalidResponse(String),
+    /// A key or value expected to be text was not valid UTF-8
+    #[error("invalid UTF-8: {0}")]
+    Utf8(#[from] std::str::Utf8Error),
+    /// A value could not be encoded or decoded by the codec of a typed KV facade
+    #[error("codec error: {0}")]
+    Codec(Box<dyn std::error::Error + Send + Sync>),
+    /// A key the operation relies on does not exist
+    #[error("key {0:?} not found")]
+    KeyNotFound(String),
+    /// The revision asked for has been compacted away. The oldest revision still available
+    /// is given when the server said what it is.
+    #[error("required revision has been compacted{}", oldest_revision(.0))]
+    Compacted(Option<i64>),
+    /// The revision asked for is newer than the cluster's current revision
+    #[error("required revision is a future revision")]
+    FutureRevision,
+    /// The lease has expired or been revoked. Its ID is given when the client knows which
+    /// lease it was.
+    #[error("{} not found", lease(.0))]
+    LeaseNotFound(Option<i64>),
+    /// The cluster's backend database has run out of space, and only accepts reads and
+    /// deletes until it is compacted, defragmented and its alarm disarmed
+    #[error("database space exceeded")]
+    NoSpace,
+    /// The server is applying too many requests already, and shed this one
+    #[error("too many requests")]
+    TooManyRequests,
+    /// The client's own rate limit or bound on requests in flight was reached, and it shed
+    /// this background request rather than make interactive ones wait, see
+    /// [`TrafficClass::Background`](crate::TrafficClass::Background)
+    #[error("background request shed by the client's limits")]
+    Shed,
+    /// A write broke the client's [`KeySchema`](crate::schema::KeySchema), and was not sent
+    #[error("key schema violated: {0}")]
+    SchemaViolation(#[from] crate::schema::Violation),
+    /// The request is bigger than the server accepts
+    #[error("request is too large")]
+    RequestTooLarge,
+    /// The cluster has no leader to serve the request
+    #[error("no leader")]
+    NoLeader,
+    /// The authenticated user lacks the permissions the request needs
+    #[error("permission denied")]
+    PermissionDenied,
+    /// The cluster refused the client's credentials or token
+    #[error("authentication failed: {}", .0.message())]
+    Auth(tonic::Status),
+    /// The client gave up on the operation once its timeout passed, `elapsed` after starting
+    /// it. `what` describes what timed out.
+    #[error("{what} (after {elapsed:?}, with a timeout of {timeout:?})")]
+    Timeout {
+        what: String,
+        timeout: Duration,
+        elapsed: Duration,
+    },
+    /// The server gave up on the request before answering it, for example while the cluster
+    /// was electing a new leader
+    #[error("server timed out: {}", .0.message())]
+    ServerTimeout(tonic::Status),
+    /// The cluster runs a version of etcd older than `feature` needs. Versions are given as
+    /// `(major, minor)`: `required` is the oldest supporting `feature`.
+    #[error(
+        "{feature} requires etcd {}.{} or newer, but the cluster version is {}.{}",
+        .required.0,
+        .required.1,
+        .cluster.0,
+        .cluster.1
+    )]
+    Unsupported {
+        feature: String,
+        required: (u64, u64),
+        cluster: (u64, u64),
+    },
+    /// Whether the cluster supports `feature` cannot be told, as the versions of the members
+    /// with the IDs in `members` are unknown, see [`VersionInfo`](crate::VersionInfo)
+    #[error(
+        "cannot tell whether the cluster supports {feature}: the versions of members {} are \
+         unknown",
+        member_ids(.members)
+    )]
+    UnknownVersion { feature: String, members: Vec<u64> },
+    /// Anything else, such as invalid arguments or configuration
+    #[error("{0}")]
+    Other(Box<dyn std::error::Error + Send + Sync>),
+    /// The error `source`, with the context of the operation it failed
+    #[error("{context}: {source}")]
+    Context {
+        context: Box<ErrorContext>,
+        source: Box<Error>,
+    },
+}
+
+/// Which operation failed and how, to tell failures apart when debugging
+#[derive(Debug, Clone, Default, PartialEq)]
+#[non_exhaustive]
+pub struct ErrorContext {
+    /// The operation which failed, such as `put` or `watch`
+    pub operation: &'static str,
+    /// The key or prefix operated on, unless the client redacts keys from errors, see
+    /// [`EtcdClientBuilder::redact_keys`](crate::EtcdClientBuilder::redact_keys)
+    pub key: Option<String>,
+    /// The endpoint the request was sent to, if the client only routes requests to one
+    pub endpoint: Option<String>,
+    /// How many times the request was sent, counting retries, if it was sent at all
+    pub attempt: Option<u32>,
+    /// Header of the response the error was found in, if a response arrived
+    pub header: Option<crate::etcdserver::ResponseHeader>,
+}
+
+impl ErrorContext {
+    /// Add the context to `error`
+    pub(crate) fn attach(&self, error: impl Into<Error>) -> Error {
+        match error.into() {
+            // The innermost operation says the most about what went wrong
+            error @ Error::Context { .. } => error,
+            error => Error::Context {
+                context: Box::new(self.clone()),
+                source: Box::new(error),
+            },
+        }
+    }
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.operation)?;
+        if let Some(key) = &self.key {
+            write!(f, " {:?}", key)?;
+        }
+        if let Some(endpoint) = &self.endpoint {
+            write!(f, " on {}", endpoint)?;
+        }
+        if let Some(attempt) = self.attempt {
+            write!(f, " (attempt {})", attempt)?;
+        }
+        if let Some(header) = &self.header {
+            write!(f, " at revision {}", header.revision)?;
+        }
+        Ok(())
+    }
+}
+
+/// Details a server attached to an error beyond its message, sent as a `google.rpc.Status`
+/// message in the `grpc-status-details-bin` trailer
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusDetails {
+    raw: Vec<u8>,
+    details: Vec<StatusDetail>,
+}
+
+impl StatusDetails {
+    /// Decode the details in `raw`, as far as they can be
+    fn decode(raw: &[u8]) -> Self {
+        let details = match <RpcStatus as prost::Message>::decode(raw) {
+            Ok(status) => status
+                .details
+                .into_iter()
+                .map(StatusDetail::decode)
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        StatusDetails {
+            raw: raw.to_vec(),
+            details,
+        }
+    }
+
+    /// The details as the server sent them
+    pub fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+
+    /// Each of the details, empty if they could not be decoded
+    pub fn details(&self) -> &[StatusDetail] {
+        &self.details
+    }
+}
+
+/// One of the details attached to an error, decoded if it is one of the standard
+/// `google.rpc` error details
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum StatusDetail {
+    /// Why the error happened, as a machine-readable `reason` within `domain`
+    ErrorInfo {
+        reason: String,
+        domain: String,
+        metadata: HashMap<String, String>,
+    },
+    /// How long to wait before retrying, if the server said
+    RetryInfo { retry_delay: Option<Duration> },
+    /// Where the error happened within the server, for debugging it
+    DebugInfo {
+        stack_entries: Vec<String>,
+        detail: String,
+    },
+    /// A detail of any other type, or one which could not be decoded
+    Other { type_url: String, value: Vec<u8> },
+}
+
+impl StatusDetail {
+    fn decode(any: prost_types::Any) -> Self {
+        use prost::Message;
+
+        let decoded = match any.type_url.rsplit('/').next() {
+            Some("google.rpc.ErrorInfo") => {
+                ErrorInfo::decode(&any.value[..]).map(|info| StatusDetail::ErrorInfo {
+                    reason: info.reason,
+                    domain: info.domain,
+                    metadata: info.metadata,
+                })
+            }
+            Some("google.rpc.RetryInfo") => {
+                RetryInfo::decode(&any.value[..]).map(|info| StatusDetail::RetryInfo {
+                    retry_delay: info.retry_delay.and_then(|delay| {
+                        let nanos = u32::try_from(delay.nanos).ok()?;
+                        Some(Duration::new(u64::try_from(delay.seconds).ok()?, nanos))
+                    }),
+                })
+            }
+            Some("google.rpc.DebugInfo") => {
+                DebugInfo::decode(&any.value[..]).map(|info| StatusDetail::DebugInfo {
+                    stack_entries: info.stack_entries,
+                    detail: info.detail,
+                })
+            }
+            _ => return StatusDetail::other(any),
+        };
+        match decoded {
+            Ok(detail) => detail,
+            Err(_) => StatusDetail::other(any),
+        }
+    }
+
+    fn other(any: prost_types::Any) -> Self {
+        StatusDetail::Other {
+            type_url: any.type_url,
+            value: any.value,
+        }
+    }
+}
+
+/// The `google.rpc.Status` message error details are sent in
+#[derive(Clone, PartialEq, prost::Message)]
+struct RpcStatus {
+    #[prost(int32, tag = "1")]
+    code: i32,
+    #[prost(string, tag = "2")]
+    message: String,
+    #[prost(message, repeated, tag = "3")]
+    details: Vec<prost_types::Any>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct ErrorInfo {
+    #[prost(string, tag = "1")]
+    reason: String,
+    #[prost(string, tag = "2")]
+    domain: String,
+    #[prost(map = "string, string", tag = "3")]
+    metadata: HashMap<String, String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct RetryInfo {
+    #[prost(message, optional, tag = "1")]
+    retry_delay: Option<prost_types::Duration>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct DebugInfo {
+    #[prost(string, repeated, tag = "1")]
+    stack_entries: Vec<String>,
+    #[prost(string, tag = "2")]
+    detail: String,
+}
+
+impl Error {
+    /// The error itself, without the context it was returned with
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::Context { source, .. } => source.root(),
+            error => error,
+        }
+    }
+
+    /// Which operation failed and how, if the error says
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            Error::Context { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    /// Details the server attached to the error beyond its message, if it attached any
+    pub fn status_details(&self) -> Option<StatusDetails> {
+        let status = match self.root() {
+            Error::Status(status) | Error::Auth(status) | Error::ServerTimeout(status) => status,
+            _ => return None,
+        };
+        if status.details().is_empty() {
+            return None;
+        }
+        Some(StatusDetails::decode(status.details()))
+    }
+
+    /// Whether the operation may succeed if it is tried again, by the same rules the client
+    /// retries requests by itself, see [`RetryPolicy`](crate::RetryPolicy): the server was
+    /// unavailable or did not answer in time, or the client could not connect.
+    ///
+    /// Only idempotent operations are safe to repeat; a write which timed out may have been
+    /// applied despite the error.
+    pub fn is_retryable(&self) -> bool {
+        match self.root() {
+            Error::Status(status) => crate::is_retryable(status),
+            Error::Transport(_) | Error::NoLeader | Error::Shed => true,
+            Error::Timeout { .. } | Error::ServerTimeout(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the cluster refused the client's token only because it has expired or was
+    /// issued before the cluster's users or roles changed, so authenticating again and
+    /// retrying may succeed
+    pub fn is_transient_auth(&self) -> bool {
+        match self.root() {
+            Error::Auth(status) => matches!(
+                status.message(),
+                "etcdserver: invalid auth token" | "etcdserver: revision of auth store is old"
+            ),
+            _ => false,
+        }
+    }
+
+    /// An error for an operation started at `started` not finishing within `timeout`, as
+    /// described by `what`
+    pub(crate) fn timeout(what: impl fmt::Display, timeout: Duration, started: Instant) -> Self {
+        Error::Timeout {
+            what: what.to_string(),
+            timeout,
+            elapsed: started.elapsed(),
+        }
+    }
+
+    /// An error for a response which is missing `what`
+    pub(crate) fn invalid_response(what: impl fmt::Display) -> Self {
+        Error::InvalidResponse(what.to_string())
+    }
+}
+
+/// etcd's messages for the errors told apart by their own variant, without the
+/// `etcdserver: ` prefix every message has
+impl From<tonic::Status> for Error {
+    fn from(status: tonic::Status) -> Self {
+        let message = status.message().strip_prefix("etcdserver: ");
+        match message.unwrap_or_default() {
+            "mvcc: required revision has been compacted" => Error::Compacted(None),
+            "mvcc: required revision is a future revision" => Error::FutureRevision,
+            "requested lease not found" => Error::LeaseNotFound(None),
+            "mvcc: database space exceeded" => Error::NoSpace,
+            "too many requests" => Error::TooManyRequests,
+            "request is too large" => Error::RequestTooLarge,
+            "no leader" => Error::NoLeader,
+            "permission denied" => Error::PermissionDenied,
+            "authentication failed, invalid user ID or password"
+            | "invalid auth token"
+            | "revision of auth store is old"
+            | "user name is empty"
+            | "authentication is not enabled" => Error::Auth(status),
+            "request timed out"
+            | "request timed out, possibly due to previous leader failure"
+            | "request timed out, possibly due to connection lost" => Error::ServerTimeout(status),
+            _ if status.code() == tonic::Code::DeadlineExceeded => Error::ServerTimeout(status),
+            _ => Error::Status(status),
+        }
+    }
+}
+
+fn oldest_revision(revision: &Option<i64>) -> String {
+    match revision {
+        Some(revision) => format!(", the oldest available is {}", revision),
+        None => String::new(),
+    }
+}
+
+fn member_ids(members: &[u64]) -> String {
+    let members: Vec<_> = members.iter().map(|id| format!("{:x}", id)).collect();
+    members.join(", ")
+}
+
+fn lease(id: &Option<i64>) -> String {
+    match id {
+        Some(id) => format!("lease {:x}", id),
+        None => "requested lease".to_string(),
+    }
+}
+
+/// The kind of I/O error closest to each error, so that code handling I/O errors can tell
+/// missing keys, refused permissions and timeouts apart from other failures
+#[cfg(feature = "io-error")]
+impl From<Error> for std::io::Error {
+    fn from(error: Error) -> Self {
+        use std::io::ErrorKind;
+
+        let kind = match error.root() {
+            Error::Transport(_) => ErrorKind::ConnectionRefused,
+            Error::Status(status) => match status.code() {
+                tonic::Code::NotFound => ErrorKind::NotFound,
+                tonic::Code::AlreadyExists => ErrorKind::AlreadyExists,
+                tonic::Code::InvalidArgument | tonic::Code::OutOfRange => ErrorKind::InvalidInput,
+                tonic::Code::PermissionDenied | tonic::Code::Unauthenticated => {
+                    ErrorKind::PermissionDenied
+                }
+                tonic::Code::DeadlineExceeded => ErrorKind::TimedOut,
+                tonic::Code::Cancelled => ErrorKind::Interrupted,
+                tonic::Code::Unavailable => ErrorKind::NotConnected,
+                tonic::Code::DataLoss => ErrorKind::InvalidData,
+                _ => ErrorKind::Other,
+            },
+            Error::InvalidResponse(_) | Error::Utf8(_) | Error::Codec(_) => ErrorKind::InvalidData,
+            Error::KeyNotFound(_) | Error::LeaseNotFound(_) => ErrorKind::NotFound,
+            Error::Compacted(_)
+            | Error::FutureRevision
+            | Error::RequestTooLarge
+            | Error::SchemaViolation(_) => ErrorKind::InvalidInput,
+            Error::NoLeader => ErrorKind::NotConnected,
+            Error::PermissionDenied | Error::Auth(_) => ErrorKind::PermissionDenied,
+            Error::Timeout { .. } | Error::ServerTimeout(_) => ErrorKind::TimedOut,
+            Error::Unsupported { .. } => ErrorKind::Unsupported,
+            _ => ErrorKind::Other,
+        };
+        std::io::Error::new(kind, error)
+    }
+}
+
+impl From<std::string::FromUtf8Error> for Error {
+    fn from(e: std::string::FromUtf8Error) -> Self {
+        Error::Utf8(e.utf8_error())
+    }
+}
+
+impl From<Box<dyn std::error::Error + Send + Sync>> for Error {
+    fn from(e: Box<dyn std::error::Error + Send + Sync>) -> Self {
+        Error::Other(e)
+    }
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Error::Other(message.into())
+    }
+}
+
+impl From<&str> for Error {
+    fn from(message: &str) -> Self {
+        Error::Other(message.into())
+    }
+}
+
+macro_rules! from_other {
+    ($($error:ty),* $(,)?) => {
+        $(
+            impl From<$error> for Error {
+                fn from(e: $error) -> Self {
+                    Error::Other(e.into())
+                }
+            }
+        )*
+    };
+}
+
+from_other!(
+    std::io::Error,
+    std::num::ParseIntError,
+    std::time::SystemTimeError,
+    http::header::InvalidHeaderName,
+    http::header::InvalidHeaderValue,
+    http::uri::InvalidUri,
+    http::uri::InvalidUriBytes,
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compatibility() {
+        fn boxed() -> Result<(), Box<dyn std::error::Error>> {
+            Err(Error::LeaseNotFound(Some(0x1f)))?;
+            Ok(())
+        }
+        assert_eq!(boxed().unwrap_err().to_string(), "lease 1f not found");
+
+        // Errors read the same as the boxed errors they replace
+        let status = || tonic::Status::new(tonic::Code::Unavailable, "no leader");
+        assert_eq!(Error::from(status()).to_string(), status().to_string());
+        let unknown = || tonic::Status::new(tonic::Code::Unknown, "etcdserver: not capable");
+        assert!(matches!(Error::from(unknown()), Error::Status(_)));
+        assert!(matches!(
+            Error::from("no endpoints to connect to"),
+            Error::Other(_)
+        ));
+        assert!(matches!(
+            Error::from(String::from_utf8(vec![0xff]).unwrap_err()),
+            Error::Utf8(_)
+        ));
+    }
+
+    #[test]
+    fn test_server_errors() {
+        let status = |code, message| Error::from(tonic::Status::new(code, message));
+        let compacted = "etcdserver: mvcc: required revision has been compacted";
+        assert!(matches!(
+            status(tonic::Code::OutOfRange, compacted),
+            Error::Compacted(None)
+        ));
+        let future = "etcdserver: mvcc: required revision is a future revision";
+        assert!(matches!(
+            status(tonic::Code::OutOfRange, future),
+            Error::FutureRevision
+        ));
+        let lease = "etcdserver: requested lease not found";
+        let lease = status(tonic::Code::NotFound, lease);
+        assert_eq!(lease.to_string(), "requested lease not found");
+        assert!(matches!(lease, Error::LeaseNotFound(None)));
+        let no_space = "etcdserver: mvcc: database space exceeded";
+        assert!(matches!(
+            status(tonic::Code::ResourceExhausted, no_space),
+            Error::NoSpace
+        ));
+        let too_many = "etcdserver: too many requests";
+        assert!(matches!(
+            status(tonic::Code::ResourceExhausted, too_many),
+            Error::TooManyRequests
+        ));
+        let denied = "etcdserver: permission denied";
+        assert!(matches!(
+            status(tonic::Code::PermissionDenied, denied),
+            Error::PermissionDenied
+        ));
+        let token = "etcdserver: invalid auth token";
+        assert!(matches!(
+            status(tonic::Code::Unauthenticated, token),
+            Error::Auth(_)
+        ));
+
+        assert_eq!(
+            Error::Compacted(Some(7)).to_string(),
+            "required revision has been compacted, the oldest available is 7"
+        );
+    }
+
+    #[test]
+    fn test_retryable_errors() {
+        let status = |code, message| Error::from(tonic::Status::new(code, message));
+        assert!(status(tonic::Code::Unavailable, "connection refused").is_retryable());
+        assert!(status(tonic::Code::DeadlineExceeded, "deadline").is_retryable());
+        assert!(status(tonic::Code::Unavailable, "etcdserver: no leader").is_retryable());
+        let timeout = Duration::from_secs(1);
+        assert!(Error::timeout("request timed out", timeout, Instant::now()).is_retryable());
+        assert!(!status(tonic::Code::InvalidArgument, "bad").is_retryable());
+        assert!(!Error::Compacted(None).is_retryable());
+        assert!(!Error::from("invalid endpoint").is_retryable());
+
+        let expired = status(
+            tonic::Code::Unauthenticated,
+            "etcdserver: invalid auth token",
+        );
+        assert!(expired.is_transient_auth() && !expired.is_retryable());
+        let old = status(
+            tonic::Code::InvalidArgument,
+            "etcdserver: revision of auth store is old",
+        );
+        assert!(old.is_transient_auth());
+        let refused = "etcdserver: authentication failed, invalid user ID or password";
+        assert!(!status(tonic::Code::InvalidArgument, refused).is_transient_auth());
+    }
+
+    #[test]
+    fn test_context() {
+        let context = ErrorContext {
+            operation: "put",
+            key: Some("a".to_string()),
+            attempt: Some(3),
+            ..Default::default()
+        };
+        let status = tonic::Status::new(tonic::Code::Unavailable, "etcdserver: no leader");
+        let error = context.attach(status);
+        assert_eq!(error.to_string(), "put \"a\" (attempt 3): no leader");
+        assert!(matches!(error.root(), Error::NoLeader));
+        assert_eq!(error.context(), Some(&context));
+        assert!(error.is_retryable());
+
+        // Context is only attached once, by the innermost operation
+        let outer = ErrorContext {
+            operation: "version",
+            ..Default::default()
+        };
+        assert_eq!(outer.attach(error).context(), Some(&context));
+    }
+
+    #[test]
+    fn test_timeouts() {
+        let (timeout, started) = (Duration::from_millis(10), Instant::now());
+        std::thread::sleep(timeout);
+        match Error::timeout("request timed out", timeout, started) {
+            Error::Timeout { elapsed, .. } => assert!(elapsed >= timeout),
+            error => panic!("unexpected error {:?}", error),
+        }
+
+        // Timeouts enforced by the server are told apart from the client's own
+        let status = |code, message| Error::from(tonic::Status::new(code, message));
+        let exceeded = status(tonic::Code::DeadlineExceeded, "deadline exceeded");
+        assert!(matches!(exceeded, Error::ServerTimeout(_)));
+        let timed_out = status(tonic::Code::Unavailable, "etcdserver: request timed out");
+        assert!(matches!(timed_out, Error::ServerTimeout(_)));
+        assert!(timed_out.is_retryable());
+    }
+
+    #[test]
+    fn test_status_details() {
+        use prost::Message;
+
+        fn any<M: Message>(name: &str, message: M) -> prost_types::Any {
+            let mut value = Vec::new();
+            message.encode(&mut value).unwrap();
+            prost_types::Any {
+                type_url: format!("type.googleapis.com/google.rpc.{}", name),
+                value,
+            }
+        }
+
+        let info = ErrorInfo {
+            reason: "QUOTA".to_string(),
+            domain: "etcd.io".to_string(),
+            metadata: vec![("limit".to_string(), "10".to_string())]
+                .into_iter()
+                .collect(),
+        };
+        let retry = RetryInfo {
+            retry_delay: Some(prost_types::Duration {
+                seconds: 1,
+                nanos: 500,
+            }),
+        };
+        let unknown = prost_types::Any {
+            type_url: "type.googleapis.com/example.Custom".to_string(),
+            value: vec![1, 2, 3],
+        };
+        let status = RpcStatus {
+            code: tonic::Code::ResourceExhausted as i32,
+            message: "slow down".to_string(),
+            details: vec![any("ErrorInfo", info), any("RetryInfo", retry), unknown],
+        };
+        let mut raw = Vec::new();
+        status.encode(&mut raw).unwrap();
+
+        let details = StatusDetails::decode(&raw);
+        assert_eq!(details.raw(), &raw[..]);
+        match &details.details()[0] {
+            StatusDetail::ErrorInfo {
+                reason, metadata, ..
+            } => {
+                assert_eq!(reason, "QUOTA");
+                assert_eq!(metadata["limit"], "10");
+            }
+            detail => panic!("unexpected detail {:?}", detail),
+        }
+        assert_eq!(
+            details.details()[1],
+            StatusDetail::RetryInfo {
+                retry_delay: Some(Duration::new(1, 500))
+            }
+        );
+        assert!(matches!(details.details()[2], StatusDetail::Other { .. }));
+
+        // Details which are not a status are still available undecoded
+        let garbage = StatusDetails::decode(&[0xff, 0xff]);
+        assert_eq!(garbage.raw(), [0xff, 0xff]);
+        assert!(garbage.details().is_empty());
+
+        let status = tonic::Status::new(tonic::Code::Unknown, "no details");
+        assert_eq!(Error::from(status).status_details(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "io-error")]
+    fn test_io_errors() {
+        use std::io::ErrorKind;
+
+        let kind = |error: Error| std::io::Error::from(error).kind();
+        assert_eq!(kind(Error::LeaseNotFound(None)), ErrorKind::NotFound);
+        assert_eq!(kind(Error::PermissionDenied), ErrorKind::PermissionDenied);
+        let timeout = Error::timeout("request timed out", Duration::from_secs(1), Instant::now());
+        assert_eq!(kind(timeout), ErrorKind::TimedOut);
+        let status = tonic::Status::new(tonic::Code::AlreadyExists, "exists");
+        assert_eq!(kind(Error::from(status)), ErrorKind::AlreadyExists);
+        assert_eq!(kind(Error::from("invalid endpoint")), ErrorKind::Other);
+
+        // The error is kept as the I/O error's source, context and all
+        let context = ErrorContext {
+            operation: "get",
+            ..Default::default()
+        };
+        let error = std::io::Error::from(context.attach(Error::NoSpace));
+        assert_eq!(error.kind(), ErrorKind::Other);
+        let inner = error.into_inner().unwrap().downcast::<Error>().unwrap();
+        assert!(matches!(inner.root(), Error::NoSpace));
+    }
+}
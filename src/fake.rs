@@ -0,0 +1,775 @@
+//! An in-memory stand-in for a cluster, implementing the traits in [`api`](crate::api), for
+//! testing code which uses etcd without running a server.
+
+#[cfg(feature = "cluster")]
+use crate::api::Cluster;
+#[cfg(feature = "maintenance")]
+use crate::api::Maintenance;
+use crate::api::{Kv, Leaser, WatchEvents, Watcher};
+use crate::clock::{Clock, SharedClock};
+use crate::{
+    etcdserver, mvccpb, AlarmType, DeleteResponse, Error, EtcdResult, GetResponse, PutOptions,
+    PutResponse, WatchResponse,
+};
+use futures::channel::mpsc;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+#[cfg(feature = "maintenance")]
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tonic::async_trait;
+
+/// A single-member cluster held in memory.
+///
+/// Like a real cluster, every write which changes something makes a new revision, keys keep
+/// their create and mod revisions and versions, watches see each change as it is made, and
+/// leases expire unless they are kept alive, deleting the keys attached to them. Expired
+/// leases are noticed by the next operation, or can be expired straight away with
+/// [`expire`](Self::expire). Leases run out on the system clock, or on the clock given to
+/// [`with_clock`](Self::with_clock). Clones share the same store.
+///
+/// Tests can also push changes, compactions and lost connections into the watches, as if
+/// they had happened on the cluster, to exercise how an application follows a watch and
+/// starts it again. The fake reports itself as the cluster's only member, healthy and without
+/// alarms, unless other members, a status or alarms are given, and any call can be made to
+/// fail with [`fail_next`](Self::fail_next), so tooling can be tested against members which
+/// are unreachable or out of space.
+#[derive(Clone, Default)]
+pub struct FakeEtcd {
+    store: Arc<Mutex<Store>>,
+}
+
+impl fmt::Debug for FakeEtcd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let store = self.store.lock().unwrap();
+        f.debug_struct("FakeEtcd")
+            .field("revision", &store.revision())
+            .field("keys", &store.kvs.len())
+            .field("leases", &store.leases.len())
+            .finish()
+    }
+}
+
+impl FakeEtcd {
+    /// An empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An empty store whose leases run out on `clock`, such as a
+    /// [`MockClock`](crate::clock::MockClock)
+    pub fn with_clock<C>(clock: C) -> Self
+    where
+        C: Clock,
+    {
+        let store = Store {
+            clock: SharedClock::new(clock),
+            ..Default::default()
+        };
+        Self {
+            store: Arc::new(Mutex::new(store)),
+        }
+    }
+
+    /// Current revision of the store. An empty store starts at revision 1.
+    pub fn revision(&self) -> i64 {
+        self.store.lock().unwrap().revision()
+    }
+
+    /// Fail the next call of `operation` with `status`, as if the server had answered with it.
+    /// Operations are named for the methods of the traits in [`api`](crate::api), such as
+    /// `put` or `status`. Failures of the same operation are used up in the order given.
+    pub fn fail_next(&self, operation: &str, status: tonic::Status) {
+        let mut store = self.store.lock().unwrap();
+        let failures = store.failures.entry(operation.to_string()).or_default();
+        failures.push_back(status);
+    }
+
+    /// Raise `alarm` on the member `member_id`. While a member is out of space, puts and
+    /// lease grants fail with [`Error::NoSpace`], as they do on a real cluster.
+    pub fn raise_alarm(&self, member_id: u64, alarm: AlarmType) {
+        let alarm = etcdserver::AlarmMember {
+            member_id,
+            alarm: alarm.into(),
+        };
+        let mut store = self.store.lock().unwrap();
+        if !store.alarms.contains(&alarm) {
+            store.alarms.push(alarm);
+        }
+    }
+
+    /// Clear every alarm, as the cluster's operators do once they have fixed its cause
+    pub fn clear_alarms(&self) {
+        self.store.lock().unwrap().alarms.clear();
+    }
+
+    /// Report `members` as the cluster's members from now on, instead of the fake alone
+    #[cfg(feature = "maintenance")]
+    pub fn set_members(&self, members: Vec<etcdserver::Member>) {
+        self.store.lock().unwrap().members = Some(members);
+    }
+
+    /// Report `status` as the status of the member connected to from now on, instead of one
+    /// made from the store
+    #[cfg(feature = "maintenance")]
+    pub fn set_status(&self, status: etcdserver::StatusResponse) {
+        self.store.lock().unwrap().status = Some(status);
+    }
+
+    /// The store, with the leases which ran out expired, unless a failure of `operation` is
+    /// due, see [`fail_next`](Self::fail_next)
+    fn store(&self, operation: &str) -> EtcdResult<MutexGuard<'_, Store>> {
+        let mut store = self.store.lock().unwrap();
+        let failure = store
+            .failures
+            .get_mut(operation)
+            .and_then(VecDeque::pop_front);
+        if let Some(status) = failure {
+            return Err(Error::from(status));
+        }
+        store.expire_due();
+        Ok(store)
+    }
+
+    /// Expire the lease `id` now, as if its TTL had run out, deleting every key attached to it
+    pub fn expire(&self, id: i64) -> EtcdResult<()> {
+        self.store.lock().unwrap().revoke(id)
+    }
+
+    /// Tell the watches of `key` it was put with `value` at the current revision, without
+    /// changing the store, returning how many watches were told
+    pub fn inject_put(&self, key: &[u8], value: &[u8]) -> usize {
+        let mut store = self.store.lock().unwrap();
+        let revision = store.revision();
+        let kv = mvccpb::KeyValue {
+            key: key.to_vec(),
+            value: value.to_vec(),
+            create_revision: revision,
+            mod_revision: revision,
+            version: 1,
+            lease: 0,
+        };
+        store.commit(vec![event(mvccpb::event::EventType::Put, kv)])
+    }
+
+    /// Tell the watches of `key` it was deleted at the current revision, without changing
+    /// the store, returning how many watches were told
+    pub fn inject_delete(&self, key: &[u8]) -> usize {
+        let mut store = self.store.lock().unwrap();
+        let kv = mvccpb::KeyValue {
+            key: key.to_vec(),
+            mod_revision: store.revision(),
+            ..Default::default()
+        };
+        store.commit(vec![event(mvccpb::event::EventType::Delete, kv)])
+    }
+
+    /// Cancel every watch as the cluster does when the revision a watch needs has been
+    /// compacted, with history compacted up to `revision`, returning how many watches were
+    /// cancelled. Each watch's last response is the cancellation.
+    pub fn inject_compaction(&self, revision: i64) -> usize {
+        let mut store = self.store.lock().unwrap();
+        let response = etcdserver::WatchResponse {
+            header: header(store.revision()),
+            canceled: true,
+            compact_revision: revision,
+            cancel_reason: "etcdserver: mvcc: required revision has been compacted".into(),
+            ..Default::default()
+        };
+        store.end_watches(|| WatchResponse::from_raw(response.clone(), b""))
+    }
+
+    /// Fail every watch as if the connection to the cluster had been lost, returning how
+    /// many watches failed. Each watch ends after its error.
+    pub fn disconnect_watches(&self) -> usize {
+        let mut store = self.store.lock().unwrap();
+        let lost = || tonic::Status::new(tonic::Code::Unavailable, "transport is closing");
+        store.end_watches(|| Err(Error::from(lost())))
+    }
+}
+
+#[derive(Default)]
+struct Store {
+    /// Revisions made since the store was created, the first being revision 1
+    revisions: i64,
+    kvs: BTreeMap<Vec<u8>, mvccpb::KeyValue>,
+    leases: HashMap<i64, FakeLease>,
+    /// ID of the last lease granted
+    last_lease: i64,
+    watchers: Vec<FakeWatch>,
+    /// What leases run out on
+    clock: SharedClock,
+    /// Failures to answer the next calls of each operation with
+    failures: HashMap<String, VecDeque<tonic::Status>>,
+    alarms: Vec<etcdserver::AlarmMember>,
+    /// The members reported instead of the fake alone, if any
+    #[cfg(feature = "maintenance")]
+    members: Option<Vec<etcdserver::Member>>,
+    /// The status reported instead of one made from the store, if any
+    #[cfg(feature = "maintenance")]
+    status: Option<etcdserver::StatusResponse>,
+}
+
+/// ID of the fake as a member, the ID etcd gives a single member with the default settings
+#[cfg(feature = "maintenance")]
+const MEMBER_ID: u64 = 0x8e9e_05c5_2164_694d;
+
+/// Server version the fake reports
+#[cfg(feature = "maintenance")]
+const VERSION: &str = "3.5.17";
+
+struct FakeLease {
+    granted_ttl: i64,
+    expires: Instant,
+}
+
+struct FakeWatch {
+    key: Vec<u8>,
+    responses: mpsc::UnboundedSender<EtcdResult<WatchResponse>>,
+}
+
+fn header(revision: i64) -> Option<etcdserver::ResponseHeader> {
+    Some(etcdserver::ResponseHeader {
+        revision,
+        ..Default::default()
+    })
+}
+
+fn event(kind: mvccpb::event::EventType, kv: mvccpb::KeyValue) -> mvccpb::Event {
+    mvccpb::Event {
+        r#type: kind as i32,
+        kv: Some(kv),
+        prev_kv: None,
+    }
+}
+
+/// Whether `key` is in the range from `start` up to `end`, see
+/// [`EtcdClient::range`](crate::EtcdClient::range)
+fn in_range(key: &[u8], start: &[u8], end: Option<&[u8]>) -> bool {
+    match end {
+        None | Some(b"") => key == start,
+        Some(b"\0") => key >= start,
+        Some(end) => key >= start && key < end,
+    }
+}
+
+impl Store {
+    fn revision(&self) -> i64 {
+        self.revisions + 1
+    }
+
+    /// Revoke every lease whose TTL has run out
+    fn expire_due(&mut self) {
+        let now = self.clock.now();
+        let mut expired: Vec<i64> = self
+            .leases
+            .iter()
+            .filter(|(_, lease)| lease.expires <= now)
+            .map(|(id, _)| *id)
+            .collect();
+        expired.sort_unstable();
+        for id in expired {
+            let _ = self.revoke(id);
+        }
+    }
+
+    /// Make a new revision out of `events`, telling the watches of their keys. Returns how
+    /// many watches were told.
+    fn commit(&mut self, events: Vec<mvccpb::Event>) -> usize {
+        let revision = self.revision();
+        let mut told = 0;
+        self.watchers.retain(|watch| {
+            let events: Vec<_> = events
+                .iter()
+                .filter(|event| event.kv.as_ref().map(|kv| &kv.key) == Some(&watch.key))
+                .cloned()
+                .collect();
+            if events.is_empty() {
+                return !watch.responses.is_closed();
+            }
+            let response = etcdserver::WatchResponse {
+                header: header(revision),
+                events,
+                ..Default::default()
+            };
+            let response = WatchResponse::from_raw(response, b"");
+            let sent = watch.responses.unbounded_send(response).is_ok();
+            told += sent as usize;
+            sent
+        });
+        told
+    }
+
+    /// Send every watch its last response, ending it. Returns how many watches were ended.
+    fn end_watches<F>(&mut self, last: F) -> usize
+    where
+        F: Fn() -> EtcdResult<WatchResponse>,
+    {
+        self.watchers
+            .drain(..)
+            .filter(|watch| watch.responses.unbounded_send(last()).is_ok())
+            .count()
+    }
+
+    /// Fail with [`Error::NoSpace`] while a member is out of space
+    fn check_space(&self) -> EtcdResult<()> {
+        let no_space = i32::from(AlarmType::NoSpace);
+        match self.alarms.iter().any(|alarm| alarm.alarm == no_space) {
+            true => Err(Error::NoSpace),
+            false => Ok(()),
+        }
+    }
+
+    #[cfg(feature = "maintenance")]
+    fn members(&self) -> Vec<etcdserver::Member> {
+        let fake = || etcdserver::Member {
+            id: MEMBER_ID,
+            name: "default".into(),
+            peer_ur_ls: vec!["http://localhost:2380".into()],
+            client_ur_ls: vec!["http://localhost:2379".into()],
+            is_learner: false,
+        };
+        self.members.clone().unwrap_or_else(|| vec![fake()])
+    }
+
+    #[cfg(feature = "maintenance")]
+    fn status(&self) -> etcdserver::StatusResponse {
+        if let Some(status) = &self.status {
+            return status.clone();
+        }
+        let revision = self.revision();
+        let size: usize = self
+            .kvs
+            .values()
+            .map(|kv| kv.key.len() + kv.value.len())
+            .sum();
+        let errors = self
+            .alarms
+            .iter()
+            .map(|alarm| {
+                let name = match AlarmType::try_from(alarm.alarm) {
+                    Ok(AlarmType::NoSpace) => "NOSPACE",
+                    Ok(AlarmType::Corrupt) => "CORRUPT",
+                    _ => "NONE",
+                };
+                format!("memberID:{} alarm:{}", alarm.member_id, name)
+            })
+            .collect();
+        etcdserver::StatusResponse {
+            header: header(revision),
+            version: VERSION.into(),
+            db_size: size as i64,
+            leader: self.members().first().map_or(0, |member| member.id),
+            raft_index: revision as u64,
+            raft_term: 1,
+            raft_applied_index: revision as u64,
+            errors,
+            db_size_in_use: size as i64,
+            is_learner: false,
+        }
+    }
+
+    fn put(&mut self, key: &[u8], value: &[u8], options: PutOptions) -> EtcdResult<PutResponse> {
+        self.check_space()?;
+        if options.lease != 0 && !self.leases.contains_key(&options.lease) {
+            return Err(Error::LeaseNotFound(Some(options.lease)));
+        }
+        self.revisions += 1;
+        let revision = self.revision();
+        let prev_kv = self.kvs.get(key).cloned();
+        let kv = mvccpb::KeyValue {
+            key: key.to_vec(),
+            value: value.to_vec(),
+            create_revision: prev_kv.as_ref().map_or(revision, |kv| kv.create_revision),
+            mod_revision: revision,
+            version: prev_kv.as_ref().map_or(0, |kv| kv.version) + 1,
+            lease: options.lease,
+        };
+        self.kvs.insert(key.to_vec(), kv.clone());
+        self.commit(vec![event(mvccpb::event::EventType::Put, kv)]);
+        let response = etcdserver::PutResponse {
+            header: header(revision),
+            prev_kv: prev_kv.filter(|_| options.prev_kv),
+        };
+        PutResponse::from_raw(response, b"")
+    }
+
+    fn range(&self, start: &[u8], end: Option<&[u8]>) -> EtcdResult<GetResponse> {
+        let kvs: Vec<_> = self
+            .kvs
+            .values()
+            .filter(|kv| in_range(&kv.key, start, end))
+            .cloned()
+            .collect();
+        let response = etcdserver::RangeResponse {
+            header: header(self.revision()),
+            count: kvs.len() as i64,
+            kvs,
+            more: false,
+        };
+        GetResponse::from_raw(response, b"")
+    }
+
+    /// Delete the keys matching `matches` in a single revision, if there are any
+    fn delete_where<F>(&mut self, matches: F) -> Vec<mvccpb::KeyValue>
+    where
+        F: Fn(&mvccpb::KeyValue) -> bool,
+    {
+        let keys: Vec<_> = self
+            .kvs
+            .values()
+            .filter(|kv| matches(kv))
+            .map(|kv| kv.key.clone())
+            .collect();
+        if keys.is_empty() {
+            return Vec::new();
+        }
+        self.revisions += 1;
+        let revision = self.revision();
+        let deleted: Vec<_> = keys.iter().filter_map(|key| self.kvs.remove(key)).collect();
+        let events = deleted
+            .iter()
+            .map(|kv| {
+                let deleted = mvccpb::KeyValue {
+                    key: kv.key.clone(),
+                    mod_revision: revision,
+                    ..Default::default()
+                };
+                event(mvccpb::event::EventType::Delete, deleted)
+            })
+            .collect();
+        self.commit(events);
+        deleted
+    }
+
+    fn delete_range(&mut self, start: &[u8], end: Option<&[u8]>) -> EtcdResult<DeleteResponse> {
+        let deleted = self.delete_where(|kv| in_range(&kv.key, start, end));
+        let response = etcdserver::DeleteRangeResponse {
+            header: header(self.revision()),
+            deleted: deleted.len() as i64,
+            prev_kvs: Vec::new(),
+        };
+        DeleteResponse::from_raw(response, b"")
+    }
+
+    fn revoke(&mut self, id: i64) -> EtcdResult<()> {
+        if self.leases.remove(&id).is_none() {
+            return Err(Error::LeaseNotFound(Some(id)));
+        }
+        self.delete_where(|kv| kv.lease == id);
+        Ok(())
+    }
+
+    fn lease(&mut self, id: i64) -> EtcdResult<&mut FakeLease> {
+        self.leases
+            .get_mut(&id)
+            .ok_or(Error::LeaseNotFound(Some(id)))
+    }
+}
+
+#[async_trait]
+impl Kv for FakeEtcd {
+    async fn put(&self, key: &[u8], value: &[u8], options: PutOptions) -> EtcdResult<PutResponse> {
+        self.store("put")?.put(key, value, options)
+    }
+
+    async fn range(&self, start: &[u8], end: Option<&[u8]>) -> EtcdResult<GetResponse> {
+        self.store("range")?.range(start, end)
+    }
+
+    async fn delete_range(&self, start: &[u8], end: Option<&[u8]>) -> EtcdResult<DeleteResponse> {
+        self.store("delete_range")?.delete_range(start, end)
+    }
+}
+
+#[async_trait]
+impl Watcher for FakeEtcd {
+    async fn watch(&self, key: &[u8]) -> EtcdResult<WatchEvents> {
+        let (tx, rx) = mpsc::unbounded();
+        self.store("watch")?.watchers.push(FakeWatch {
+            key: key.to_vec(),
+            responses: tx,
+        });
+        Ok(Box::pin(rx))
+    }
+}
+
+#[async_trait]
+impl Leaser for FakeEtcd {
+    async fn grant(&self, ttl: i64) -> EtcdResult<etcdserver::LeaseGrantResponse> {
+        let mut store = self.store("grant")?;
+        store.check_space()?;
+        store.last_lease += 1;
+        let id = store.last_lease;
+        let lease = FakeLease {
+            granted_ttl: ttl,
+            expires: store.clock.now() + Duration::from_secs(ttl.max(0) as u64),
+        };
+        store.leases.insert(id, lease);
+        Ok(etcdserver::LeaseGrantResponse {
+            header: header(store.revision()),
+            id,
+            ttl,
+            error: String::new(),
+        })
+    }
+
+    async fn revoke(&self, id: i64) -> EtcdResult<()> {
+        self.store("revoke")?.revoke(id)
+    }
+
+    async fn keep_alive_once(&self, id: i64) -> EtcdResult<i64> {
+        let mut store = self.store("keep_alive_once")?;
+        let now = store.clock.now();
+        let lease = store.lease(id)?;
+        lease.expires = now + Duration::from_secs(lease.granted_ttl.max(0) as u64);
+        Ok(lease.granted_ttl)
+    }
+
+    async fn time_to_live(
+        &self,
+        id: i64,
+        keys: bool,
+    ) -> EtcdResult<etcdserver::LeaseTimeToLiveResponse> {
+        let mut store = self.store("time_to_live")?;
+        let revision = store.revision();
+        let attached = match keys {
+            true => store
+                .kvs
+                .values()
+                .filter(|kv| kv.lease == id)
+                .map(|kv| kv.key.clone())
+                .collect(),
+            false => Vec::new(),
+        };
+        let now = store.clock.now();
+        let lease = store.lease(id)?;
+        let remaining = lease.expires.saturating_duration_since(now);
+        Ok(etcdserver::LeaseTimeToLiveResponse {
+            header: header(revision),
+            id,
+            ttl: remaining.as_secs() as i64,
+            granted_ttl: lease.granted_ttl,
+            keys: attached,
+        })
+    }
+
+    async fn leases(&self) -> EtcdResult<Vec<i64>> {
+        let store = self.store("leases")?;
+        let mut leases: Vec<_> = store.leases.keys().copied().collect();
+        leases.sort_unstable();
+        Ok(leases)
+    }
+}
+
+#[cfg(feature = "cluster")]
+#[async_trait]
+impl Cluster for FakeEtcd {
+    async fn members(&self) -> EtcdResult<Vec<etcdserver::Member>> {
+        Ok(self.store("members")?.members())
+    }
+}
+
+#[cfg(feature = "maintenance")]
+#[async_trait]
+impl Maintenance for FakeEtcd {
+    async fn status(&self) -> EtcdResult<etcdserver::StatusResponse> {
+        Ok(self.store("status")?.status())
+    }
+
+    async fn alarms(&self) -> EtcdResult<Vec<etcdserver::AlarmMember>> {
+        Ok(self.store("alarms")?.alarms.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::EventKind;
+    use futures::StreamExt;
+
+    /// Code written against the traits, as an application would
+    async fn register(kv: &dyn Kv, leaser: &dyn Leaser, name: &str) -> EtcdResult<i64> {
+        let lease = leaser.grant(10).await?.id;
+        let key = format!("services/{}", name);
+        let options = PutOptions {
+            lease,
+            ..Default::default()
+        };
+        kv.put(key.as_bytes(), b"up", options).await?;
+        Ok(lease)
+    }
+
+    #[tokio::test]
+    async fn test_revisions() {
+        let etcd = FakeEtcd::new();
+        assert_eq!(etcd.revision(), 1);
+        let put = etcd.put(b"foo", b"1", PutOptions::default()).await.unwrap();
+        assert_eq!(put.revision(), 2);
+        let options = PutOptions {
+            prev_kv: true,
+            ..Default::default()
+        };
+        let put = etcd.put(b"foo", b"2", options).await.unwrap();
+        assert_eq!(put.prev_kv().unwrap().value(), b"1");
+        etcd.put(b"foz", b"3", PutOptions::default()).await.unwrap();
+
+        let foo = etcd.get(b"foo").await.unwrap().unwrap();
+        assert_eq!(foo.value(), b"2");
+        assert_eq!(foo.create_revision(), 2);
+        assert_eq!(foo.mod_revision(), 3);
+        assert_eq!(foo.version(), 2);
+        let range = etcd.range(b"fo", Some(b"fp")).await.unwrap();
+        assert_eq!(range.len(), 2);
+        assert_eq!(range.revision(), 4);
+        assert_eq!(etcd.range(b"foz", Some(b"\0")).await.unwrap().len(), 1);
+
+        // Deleting nothing makes no revision
+        assert_eq!(etcd.delete(b"bar").await.unwrap().deleted(), 0);
+        assert_eq!(etcd.revision(), 4);
+        let deleted = etcd.delete_range(b"fo", Some(b"fp")).await.unwrap();
+        assert_eq!((deleted.deleted(), deleted.revision()), (2, 5));
+        assert_eq!(etcd.get(b"foo").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_watches() {
+        let etcd = FakeEtcd::new();
+        let mut events = etcd.watch(b"foo").await.unwrap();
+        etcd.put(b"foo", b"1", PutOptions::default()).await.unwrap();
+        etcd.put(b"bar", b"2", PutOptions::default()).await.unwrap();
+        etcd.delete(b"foo").await.unwrap();
+
+        let put = events.next().await.unwrap().unwrap();
+        assert_eq!(put.revision(), 2);
+        assert_eq!(put.events()[0].kind(), EventKind::Put);
+        assert_eq!(put.events()[0].kv().value(), b"1");
+        let delete = events.next().await.unwrap().unwrap();
+        assert_eq!(delete.events()[0].kind(), EventKind::Delete);
+        assert_eq!(delete.events()[0].kv().mod_revision(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_injecting_watch_events() {
+        let etcd = FakeEtcd::new();
+        let mut events = etcd.watch(b"foo").await.unwrap();
+        assert_eq!(etcd.inject_put(b"foo", b"1"), 1);
+        assert_eq!(etcd.inject_put(b"bar", b"2"), 0);
+        assert_eq!(etcd.inject_delete(b"foo"), 1);
+        assert_eq!(etcd.revision(), 1);
+        assert_eq!(etcd.get(b"foo").await.unwrap(), None);
+
+        let put = events.next().await.unwrap().unwrap();
+        assert_eq!(put.events()[0].kind(), EventKind::Put);
+        assert_eq!(put.events()[0].kv().value(), b"1");
+        let delete = events.next().await.unwrap().unwrap();
+        assert_eq!(delete.events()[0].kind(), EventKind::Delete);
+
+        assert_eq!(etcd.inject_compaction(5), 1);
+        let compacted = events.next().await.unwrap().unwrap();
+        assert!(compacted.is_canceled());
+        assert_eq!(compacted.compact_revision(), 5);
+        assert!(events.next().await.is_none());
+
+        // Watches started again see changes as usual, until the connection is lost
+        let mut events = etcd.watch(b"foo").await.unwrap();
+        etcd.put(b"foo", b"3", PutOptions::default()).await.unwrap();
+        assert_eq!(etcd.disconnect_watches(), 1);
+        let put = events.next().await.unwrap().unwrap();
+        assert_eq!(put.events()[0].kv().value(), b"3");
+        let err = events.next().await.unwrap().unwrap_err();
+        assert!(err.is_retryable());
+        assert!(events.next().await.is_none());
+        assert_eq!(etcd.disconnect_watches(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_leases() {
+        let etcd = FakeEtcd::new();
+        let lease = register(&etcd, &etcd, "web").await.unwrap();
+        let mut events = etcd.watch(b"services/web").await.unwrap();
+        assert_eq!(etcd.keep_alive_once(lease).await.unwrap(), 10);
+        let ttl = etcd.time_to_live(lease, true).await.unwrap();
+        assert_eq!(ttl.granted_ttl, 10);
+        assert_eq!(ttl.keys, vec![b"services/web".to_vec()]);
+        assert_eq!(etcd.leases().await.unwrap(), vec![lease]);
+
+        etcd.expire(lease).unwrap();
+        let expired = events.next().await.unwrap().unwrap();
+        assert_eq!(expired.events()[0].kind(), EventKind::Delete);
+        assert_eq!(etcd.get(b"services/web").await.unwrap(), None);
+        assert!(matches!(
+            etcd.keep_alive_once(lease).await,
+            Err(Error::LeaseNotFound(Some(_)))
+        ));
+        let options = PutOptions {
+            lease,
+            ..Default::default()
+        };
+        assert!(etcd.put(b"foo", b"1", options).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_leases_running_out() {
+        let clock = MockClock::new();
+        let etcd = FakeEtcd::with_clock(clock.clone());
+        let lease = register(&etcd, &etcd, "web").await.unwrap();
+
+        clock.advance(Duration::from_secs(6));
+        assert_eq!(etcd.time_to_live(lease, false).await.unwrap().ttl, 4);
+        etcd.keep_alive_once(lease).await.unwrap();
+        clock.advance(Duration::from_secs(6));
+        assert!(etcd.get(b"services/web").await.unwrap().is_some());
+
+        clock.advance(Duration::from_secs(4));
+        assert_eq!(etcd.get(b"services/web").await.unwrap(), None);
+        assert!(etcd.leases().await.unwrap().is_empty());
+    }
+
+    #[cfg(feature = "cluster")]
+    #[tokio::test]
+    async fn test_scripting_members_and_alarms() {
+        let etcd = FakeEtcd::new();
+        let members = etcd.members().await.unwrap();
+        assert_eq!(members.len(), 1);
+        let status = etcd.status().await.unwrap();
+        assert_eq!(status.leader, members[0].id);
+        assert!(status.errors.is_empty());
+        assert!(etcd.alarms().await.unwrap().is_empty());
+
+        etcd.raise_alarm(members[0].id, AlarmType::NoSpace);
+        let alarms = etcd.alarms().await.unwrap();
+        assert_eq!(alarms[0].alarm, i32::from(AlarmType::NoSpace));
+        let status = etcd.status().await.unwrap();
+        assert_eq!(
+            status.errors,
+            vec![format!("memberID:{} alarm:NOSPACE", MEMBER_ID)]
+        );
+        let put = etcd.put(b"foo", b"1", PutOptions::default()).await;
+        assert!(matches!(put, Err(Error::NoSpace)));
+        assert!(matches!(etcd.grant(10).await, Err(Error::NoSpace)));
+        etcd.clear_alarms();
+        etcd.put(b"foo", b"1", PutOptions::default()).await.unwrap();
+
+        // A member which cannot be reached
+        let unreachable = tonic::Status::new(tonic::Code::Unavailable, "connection refused");
+        etcd.fail_next("status", unreachable);
+        assert!(etcd.status().await.unwrap_err().is_retryable());
+        assert!(etcd.status().await.is_ok());
+
+        let learner = etcdserver::Member {
+            id: 2,
+            is_learner: true,
+            ..Default::default()
+        };
+        etcd.set_members(vec![members[0].clone(), learner.clone()]);
+        assert_eq!(etcd.members().await.unwrap()[1], learner);
+        etcd.set_status(etcdserver::StatusResponse {
+            is_learner: true,
+            ..Default::default()
+        });
+        assert!(etcd.status().await.unwrap().is_learner);
+    }
+}
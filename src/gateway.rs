@@ -0,0 +1,819 @@
+//! A transport talking to etcd's gRPC gateway, the HTTP/JSON API etcd serves under `/v3`, for
+//! networks which let HTTP/1.1 through but block gRPC.
+//!
+//! Connect through it with
+//! [`EtcdClientBuilder::connect_gateway`](crate::EtcdClientBuilder::connect_gateway):
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! use etcd3::EtcdClient;
+//!
+//! let client = EtcdClient::builder()
+//!     .endpoint("http://127.0.0.1:2379")
+//!     .connect_gateway()
+//!     .await?;
+//! client.range("foo", None).put("bar").await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The gateway carries the KV calls, including transactions and compaction, leases other than
+//! keep-alives, the member list, status and authentication. Streaming calls, such as watches,
+//! lease keep-alives and snapshots, fail with [`tonic::Code::Unimplemented`], as do the other
+//! services. Only `http` endpoints are supported.
+
+use crate::{etcdserver, mvccpb, ChannelConfig, EtcdResult};
+use bytes::Buf;
+use futures::future::{self, BoxFuture, FutureExt};
+use prost::Message;
+use serde_json::{json, Value};
+use std::convert::Infallible;
+use std::fmt;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tonic::body::BoxBody;
+use tonic::codegen::{HttpBody, HyperBody, Service};
+use tonic::{Code, Status};
+
+/// Frames of a response body
+type Chunk = <HyperBody as HttpBody>::Data;
+
+/// A transport sending each call to the gRPC gateway of one of its endpoints in turn, as JSON
+/// over HTTP/1.1, see the [module documentation](self)
+#[derive(Clone)]
+pub struct Gateway {
+    client: hyper::Client<hyper::client::HttpConnector>,
+    endpoints: Arc<[String]>,
+    next: Arc<AtomicUsize>,
+    /// Headers sent with every request, such as the token and the client's metadata
+    headers: http::HeaderMap,
+}
+
+impl fmt::Debug for Gateway {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Gateway")
+            .field("endpoints", &self.endpoints)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Gateway {
+    /// Transport sending calls to the gateways of `endpoints`, which must be `http` URLs
+    pub fn new<I, S>(endpoints: I) -> EtcdResult<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let endpoints: Vec<String> = endpoints.into_iter().map(Into::into).collect();
+        if endpoints.is_empty() {
+            return Err("no endpoints to connect to".into());
+        }
+        for url in &endpoints {
+            if !url.starts_with("http://") {
+                let message = format!("the gateway transport only supports http URLs, not {}", url);
+                return Err(message.into());
+            }
+        }
+        Ok(Gateway {
+            client: hyper::Client::new(),
+            endpoints: endpoints.into(),
+            next: Arc::new(AtomicUsize::new(0)),
+            headers: http::HeaderMap::new(),
+        })
+    }
+
+    /// Transport for `endpoints` sending the token and metadata of `config`
+    pub(crate) fn with_config(config: &ChannelConfig, endpoints: &[String]) -> EtcdResult<Self> {
+        let mut gateway = Gateway::new(endpoints.iter().cloned())?;
+        gateway.headers = config.metadata.clone();
+        if let Some(token) = &config.token {
+            gateway.headers.insert("token", token.clone());
+        }
+        Ok(gateway)
+    }
+}
+
+impl Service<http::Request<BoxBody>> for Gateway {
+    type Response = http::Response<GatewayBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Self::Response, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        let next = self.next.fetch_add(1, Ordering::Relaxed);
+        let endpoint = self.endpoints[next % self.endpoints.len()].clone();
+        let client = self.client.clone();
+        let mut headers = self.headers.clone();
+        // Metadata given for the request takes precedence
+        for (name, value) in request.headers() {
+            headers.insert(name, value.clone());
+        }
+        let path = request.uri().path().to_string();
+        let body = request.into_body();
+        async move {
+            let response = match call(client, &endpoint, &path, &headers, body).await {
+                Ok(message) => GatewayBody::message(&message),
+                Err(status) => GatewayBody::status(&status),
+            };
+            Ok(response)
+        }
+        .boxed()
+    }
+}
+
+/// Make the call to `path` with the gRPC request `body` through the gateway at `endpoint`,
+/// returning the encoded response message
+async fn call(
+    client: hyper::Client<hyper::client::HttpConnector>,
+    endpoint: &str,
+    path: &str,
+    headers: &http::HeaderMap,
+    body: BoxBody,
+) -> Result<Vec<u8>, Status> {
+    let body = read_body(body).await?;
+    // The message follows the frame's compression flag and length
+    let message = body.get(5..).unwrap_or_default();
+    let (route, json) = request_json(path, message)?;
+
+    let url = format!("{}{}", endpoint.trim_end_matches('/'), route);
+    let mut request = http::Request::new(hyper::Body::from(json.to_string()));
+    *request.method_mut() = http::Method::POST;
+    *request.uri_mut() = url
+        .parse()
+        .map_err(|e| Status::new(Code::InvalidArgument, format!("invalid URL {}: {}", url, e)))?;
+    let sent = request.headers_mut();
+    for (name, value) in headers {
+        match name.as_str() {
+            // The gateway reads the token from the Authorization header
+            "token" => {
+                sent.insert(http::header::AUTHORIZATION, value.clone());
+            }
+            "te" | "content-type" | "content-length" | "user-agent" => {}
+            name if name.starts_with("grpc-") => {}
+            // The gateway passes these headers on as the request's metadata
+            name => {
+                let forwarded = format!("grpc-metadata-{}", name);
+                if let Ok(forwarded) = http::header::HeaderName::from_bytes(forwarded.as_bytes()) {
+                    sent.insert(forwarded, value.clone());
+                }
+            }
+        }
+    }
+    let json_type = http::HeaderValue::from_static("application/json");
+    sent.insert(http::header::CONTENT_TYPE, json_type);
+
+    let response = client
+        .request(request)
+        .await
+        .map_err(|e| Status::new(Code::Unavailable, format!("gateway request failed: {}", e)))?;
+    let status = response.status();
+    let body = read_body(response.into_body())
+        .await
+        .map_err(|e| Status::new(Code::Unavailable, format!("gateway response failed: {}", e)))?;
+    let value: Value = serde_json::from_slice(&body)
+        .map_err(|e| invalid(format!("{} (HTTP status {})", e, status)))?;
+    if !status.is_success() {
+        return Err(gateway_error(status, &value));
+    }
+    response_message(path, &value)
+}
+
+/// Every frame of `body`, concatenated
+async fn read_body<B>(mut body: B) -> Result<Vec<u8>, B::Error>
+where
+    B: HttpBody + Unpin,
+{
+    let mut bytes = Vec::new();
+    while let Some(data) = future::poll_fn(|cx| Pin::new(&mut body).poll_data(cx)).await {
+        let mut data = data?;
+        while data.has_remaining() {
+            let chunk = data.bytes();
+            let read = chunk.len();
+            bytes.extend_from_slice(chunk);
+            data.advance(read);
+        }
+    }
+    Ok(bytes)
+}
+
+/// Status of the call which failed with the gateway's error response `value`
+fn gateway_error(status: http::StatusCode, value: &Value) -> Status {
+    let message = field(value, "message")
+        .as_str()
+        .or_else(|| field(value, "error").as_str())
+        .unwrap_or_else(|| status.canonical_reason().unwrap_or("gateway error"))
+        .to_string();
+    let code = match field(value, "code").as_i64() {
+        Some(code) => Code::from(code as i32),
+        None => match status {
+            http::StatusCode::UNAUTHORIZED => Code::Unauthenticated,
+            http::StatusCode::NOT_FOUND => Code::Unimplemented,
+            http::StatusCode::SERVICE_UNAVAILABLE => Code::Unavailable,
+            _ => Code::Unknown,
+        },
+    };
+    Status::new(code, message)
+}
+
+/// Body of a response from the gateway, see [`Gateway`]
+pub struct GatewayBody {
+    message: Option<Chunk>,
+    trailers: Option<http::HeaderMap>,
+}
+
+impl GatewayBody {
+    /// Successful response with the encoded `message`
+    fn message(message: &[u8]) -> http::Response<Self> {
+        let mut frame = Vec::with_capacity(5 + message.len());
+        frame.push(0);
+        frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        frame.extend_from_slice(message);
+        http::Response::new(GatewayBody {
+            message: Some(Chunk::from(frame)),
+            trailers: Some(status_headers(&Status::new(Code::Ok, ""))),
+        })
+    }
+
+    /// Response to a failed call, with its status in the headers
+    fn status(status: &Status) -> http::Response<Self> {
+        let mut response = http::Response::new(GatewayBody {
+            message: None,
+            trailers: None,
+        });
+        *response.headers_mut() = status_headers(status);
+        response
+    }
+}
+
+impl HttpBody for GatewayBody {
+    type Data = Chunk;
+    type Error = Infallible;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+    ) -> Poll<Option<Result<Chunk, Infallible>>> {
+        Poll::Ready(self.message.take().map(Ok))
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+    ) -> Poll<Result<Option<http::HeaderMap>, Infallible>> {
+        Poll::Ready(Ok(self.trailers.take()))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.message.is_none() && self.trailers.is_none()
+    }
+}
+
+fn status_headers(status: &Status) -> http::HeaderMap {
+    let mut headers = http::HeaderMap::new();
+    headers.insert("grpc-status", http::HeaderValue::from(status.code() as i32));
+    // Messages are percent-decoded, so only the escape character needs escaping
+    let message = status.message().replace('%', "%25");
+    if let Ok(message) = http::HeaderValue::from_str(&message) {
+        headers.insert("grpc-message", message);
+    }
+    headers
+}
+
+/// Gateway route and JSON body of the call to the gRPC method `path` with the encoded
+/// request `message`
+fn request_json(path: &str, message: &[u8]) -> Result<(&'static str, Value), Status> {
+    Ok(match path {
+        "/etcdserverpb.KV/Range" => ("/v3/kv/range", range_request(&decode(message)?)),
+        "/etcdserverpb.KV/Put" => ("/v3/kv/put", put_request(&decode(message)?)),
+        "/etcdserverpb.KV/DeleteRange" => (
+            "/v3/kv/deleterange",
+            delete_range_request(&decode(message)?),
+        ),
+        "/etcdserverpb.KV/Txn" => ("/v3/kv/txn", txn_request(&decode(message)?)),
+        "/etcdserverpb.KV/Compact" => {
+            let request: etcdserver::CompactionRequest = decode(message)?;
+            let json = json!({"revision": request.revision, "physical": request.physical});
+            ("/v3/kv/compaction", json)
+        }
+        "/etcdserverpb.Lease/LeaseGrant" => {
+            let request: etcdserver::LeaseGrantRequest = decode(message)?;
+            (
+                "/v3/lease/grant",
+                json!({"TTL": request.ttl, "ID": request.id}),
+            )
+        }
+        "/etcdserverpb.Lease/LeaseRevoke" => {
+            let request: etcdserver::LeaseRevokeRequest = decode(message)?;
+            ("/v3/lease/revoke", json!({ "ID": request.id }))
+        }
+        "/etcdserverpb.Lease/LeaseTimeToLive" => {
+            let request: etcdserver::LeaseTimeToLiveRequest = decode(message)?;
+            let json = json!({"ID": request.id, "keys": request.keys});
+            ("/v3/lease/timetolive", json)
+        }
+        "/etcdserverpb.Lease/LeaseLeases" => ("/v3/lease/leases", json!({})),
+        "/etcdserverpb.Cluster/MemberList" => {
+            let request: etcdserver::MemberListRequest = decode(message)?;
+            let json = json!({ "linearizable": request.linearizable });
+            ("/v3/cluster/member/list", json)
+        }
+        "/etcdserverpb.Maintenance/Status" => ("/v3/maintenance/status", json!({})),
+        "/etcdserverpb.Auth/Authenticate" => {
+            let request: etcdserver::AuthenticateRequest = decode(message)?;
+            let json = json!({"name": request.name, "password": request.password});
+            ("/v3/auth/authenticate", json)
+        }
+        path => {
+            let message = format!("the gateway transport does not support {}", path);
+            return Err(Status::unimplemented(message));
+        }
+    })
+}
+
+/// Encoded response to the gRPC method `path`, from the gateway's JSON response `value`
+fn response_message(path: &str, value: &Value) -> Result<Vec<u8>, Status> {
+    Ok(match path {
+        "/etcdserverpb.KV/Range" => encode(&range_response(value)?),
+        "/etcdserverpb.KV/Put" => encode(&etcdserver::PutResponse {
+            header: header(value)?,
+            prev_kv: optional(value, "prev_kv", key_value)?,
+        }),
+        "/etcdserverpb.KV/DeleteRange" => encode(&delete_range_response(value)?),
+        "/etcdserverpb.KV/Txn" => encode(&txn_response(value)?),
+        "/etcdserverpb.KV/Compact" => encode(&etcdserver::CompactionResponse {
+            header: header(value)?,
+        }),
+        "/etcdserverpb.Lease/LeaseGrant" => encode(&etcdserver::LeaseGrantResponse {
+            header: header(value)?,
+            id: int(value, "ID")?,
+            ttl: int(value, "TTL")?,
+            error: string(value, "error"),
+        }),
+        "/etcdserverpb.Lease/LeaseRevoke" => encode(&etcdserver::LeaseRevokeResponse {
+            header: header(value)?,
+        }),
+        "/etcdserverpb.Lease/LeaseTimeToLive" => encode(&etcdserver::LeaseTimeToLiveResponse {
+            header: header(value)?,
+            id: int(value, "ID")?,
+            ttl: int(value, "TTL")?,
+            granted_ttl: int(value, "grantedTTL")?,
+            keys: list(value, "keys")
+                .iter()
+                .map(base64)
+                .collect::<Result<_, _>>()?,
+        }),
+        "/etcdserverpb.Lease/LeaseLeases" => encode(&etcdserver::LeaseLeasesResponse {
+            header: header(value)?,
+            leases: list(value, "leases")
+                .iter()
+                .map(|lease| {
+                    Ok(etcdserver::LeaseStatus {
+                        id: int(lease, "ID")?,
+                    })
+                })
+                .collect::<Result<_, Status>>()?,
+        }),
+        "/etcdserverpb.Cluster/MemberList" => encode(&etcdserver::MemberListResponse {
+            header: header(value)?,
+            members: list(value, "members")
+                .iter()
+                .map(member)
+                .collect::<Result<_, _>>()?,
+        }),
+        "/etcdserverpb.Maintenance/Status" => encode(&etcdserver::StatusResponse {
+            header: header(value)?,
+            version: string(value, "version"),
+            db_size: int(value, "dbSize")?,
+            leader: uint(value, "leader")?,
+            raft_index: uint(value, "raftIndex")?,
+            raft_term: uint(value, "raftTerm")?,
+            raft_applied_index: uint(value, "raftAppliedIndex")?,
+            errors: strings(value, "errors"),
+            db_size_in_use: int(value, "dbSizeInUse")?,
+            is_learner: boolean(value, "isLearner"),
+        }),
+        "/etcdserverpb.Auth/Authenticate" => encode(&etcdserver::AuthenticateResponse {
+            header: header(value)?,
+            token: string(value, "token"),
+        }),
+        path => {
+            let message = format!("the gateway transport does not support {}", path);
+            return Err(Status::unimplemented(message));
+        }
+    })
+}
+
+fn decode<M>(message: &[u8]) -> Result<M, Status>
+where
+    M: Message + Default,
+{
+    M::decode(message).map_err(|e| Status::new(Code::Internal, format!("invalid request: {}", e)))
+}
+
+fn encode<M>(message: &M) -> Vec<u8>
+where
+    M: Message,
+{
+    let mut encoded = Vec::with_capacity(message.encoded_len());
+    // Vec grows as needed, so encoding into it cannot fail
+    let _ = message.encode(&mut encoded);
+    encoded
+}
+
+fn range_request(request: &etcdserver::RangeRequest) -> Value {
+    json!({
+        "key": base64::encode(&request.key),
+        "range_end": base64::encode(&request.range_end),
+        "limit": request.limit,
+        "revision": request.revision,
+        "sort_order": request.sort_order,
+        "sort_target": request.sort_target,
+        "serializable": request.serializable,
+        "keys_only": request.keys_only,
+        "count_only": request.count_only,
+        "min_mod_revision": request.min_mod_revision,
+        "max_mod_revision": request.max_mod_revision,
+        "min_create_revision": request.min_create_revision,
+        "max_create_revision": request.max_create_revision,
+    })
+}
+
+fn put_request(request: &etcdserver::PutRequest) -> Value {
+    json!({
+        "key": base64::encode(&request.key),
+        "value": base64::encode(&request.value),
+        "lease": request.lease,
+        "prev_kv": request.prev_kv,
+        "ignore_value": request.ignore_value,
+        "ignore_lease": request.ignore_lease,
+    })
+}
+
+fn delete_range_request(request: &etcdserver::DeleteRangeRequest) -> Value {
+    json!({
+        "key": base64::encode(&request.key),
+        "range_end": base64::encode(&request.range_end),
+        "prev_kv": request.prev_kv,
+    })
+}
+
+fn txn_request(request: &etcdserver::TxnRequest) -> Value {
+    let ops = |ops: &[etcdserver::RequestOp]| ops.iter().map(request_op).collect::<Vec<_>>();
+    json!({
+        "compare": request.compare.iter().map(compare).collect::<Vec<_>>(),
+        "success": ops(&request.success),
+        "failure": ops(&request.failure),
+    })
+}
+
+fn compare(compare: &etcdserver::Compare) -> Value {
+    use etcdserver::compare::TargetUnion;
+
+    let mut json = json!({
+        "result": compare.result,
+        "target": compare.target,
+        "key": base64::encode(&compare.key),
+        "range_end": base64::encode(&compare.range_end),
+    });
+    let (name, target) = match &compare.target_union {
+        Some(TargetUnion::Version(version)) => ("version", json!(version)),
+        Some(TargetUnion::CreateRevision(revision)) => ("create_revision", json!(revision)),
+        Some(TargetUnion::ModRevision(revision)) => ("mod_revision", json!(revision)),
+        Some(TargetUnion::Value(value)) => ("value", json!(base64::encode(value))),
+        Some(TargetUnion::Lease(lease)) => ("lease", json!(lease)),
+        None => return json,
+    };
+    json[name] = target;
+    json
+}
+
+fn request_op(op: &etcdserver::RequestOp) -> Value {
+    use etcdserver::request_op::Request;
+
+    match &op.request {
+        Some(Request::RequestRange(request)) => json!({ "request_range": range_request(request) }),
+        Some(Request::RequestPut(request)) => json!({ "request_put": put_request(request) }),
+        Some(Request::RequestDeleteRange(request)) => {
+            json!({ "request_delete_range": delete_range_request(request) })
+        }
+        Some(Request::RequestTxn(request)) => json!({ "request_txn": txn_request(request) }),
+        None => json!({}),
+    }
+}
+
+fn range_response(value: &Value) -> Result<etcdserver::RangeResponse, Status> {
+    Ok(etcdserver::RangeResponse {
+        header: header(value)?,
+        kvs: list(value, "kvs")
+            .iter()
+            .map(key_value)
+            .collect::<Result<_, _>>()?,
+        more: boolean(value, "more"),
+        count: int(value, "count")?,
+    })
+}
+
+fn delete_range_response(value: &Value) -> Result<etcdserver::DeleteRangeResponse, Status> {
+    Ok(etcdserver::DeleteRangeResponse {
+        header: header(value)?,
+        deleted: int(value, "deleted")?,
+        prev_kvs: list(value, "prev_kvs")
+            .iter()
+            .map(key_value)
+            .collect::<Result<_, _>>()?,
+    })
+}
+
+fn txn_response(value: &Value) -> Result<etcdserver::TxnResponse, Status> {
+    use etcdserver::response_op::Response;
+
+    let response_op = |op: &Value| {
+        let response = if let Some(range) = op.get("response_range") {
+            Some(Response::ResponseRange(range_response(range)?))
+        } else if let Some(put) = op.get("response_put") {
+            Some(Response::ResponsePut(etcdserver::PutResponse {
+                header: header(put)?,
+                prev_kv: optional(put, "prev_kv", key_value)?,
+            }))
+        } else if let Some(delete) = op.get("response_delete_range") {
+            Some(Response::ResponseDeleteRange(delete_range_response(
+                delete,
+            )?))
+        } else if let Some(txn) = op.get("response_txn") {
+            Some(Response::ResponseTxn(txn_response(txn)?))
+        } else {
+            None
+        };
+        Ok(etcdserver::ResponseOp { response })
+    };
+    Ok(etcdserver::TxnResponse {
+        header: header(value)?,
+        succeeded: boolean(value, "succeeded"),
+        responses: list(value, "responses")
+            .iter()
+            .map(response_op)
+            .collect::<Result<_, Status>>()?,
+    })
+}
+
+fn header(value: &Value) -> Result<Option<etcdserver::ResponseHeader>, Status> {
+    optional(value, "header", |header| {
+        Ok(etcdserver::ResponseHeader {
+            cluster_id: uint(header, "cluster_id")?,
+            member_id: uint(header, "member_id")?,
+            revision: int(header, "revision")?,
+            raft_term: uint(header, "raft_term")?,
+        })
+    })
+}
+
+fn key_value(value: &Value) -> Result<mvccpb::KeyValue, Status> {
+    Ok(mvccpb::KeyValue {
+        key: base64(field(value, "key"))?,
+        create_revision: int(value, "create_revision")?,
+        mod_revision: int(value, "mod_revision")?,
+        version: int(value, "version")?,
+        value: base64(field(value, "value"))?,
+        lease: int(value, "lease")?,
+    })
+}
+
+fn member(value: &Value) -> Result<etcdserver::Member, Status> {
+    Ok(etcdserver::Member {
+        id: uint(value, "ID")?,
+        name: string(value, "name"),
+        peer_ur_ls: strings(value, "peerURLs"),
+        client_ur_ls: strings(value, "clientURLs"),
+        is_learner: boolean(value, "isLearner"),
+    })
+}
+
+fn invalid(message: String) -> Status {
+    Status::new(
+        Code::Internal,
+        format!("invalid gateway response: {}", message),
+    )
+}
+
+/// The field `name` of `value`, null if it is missing. Fields with default values are left
+/// out of the gateway's responses.
+fn field<'v>(value: &'v Value, name: &str) -> &'v Value {
+    value.get(name).unwrap_or(&Value::Null)
+}
+
+fn optional<T, F>(value: &Value, name: &str, parse: F) -> Result<Option<T>, Status>
+where
+    F: FnOnce(&Value) -> Result<T, Status>,
+{
+    match field(value, name) {
+        Value::Null => Ok(None),
+        field => parse(field).map(Some),
+    }
+}
+
+fn list<'v>(value: &'v Value, name: &str) -> &'v [Value] {
+    field(value, name)
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+}
+
+/// 64-bit integers are sent as strings
+fn int(value: &Value, name: &str) -> Result<i64, Status> {
+    match field(value, name) {
+        Value::Null => Some(0),
+        Value::String(int) => int.parse().ok(),
+        field => field.as_i64(),
+    }
+    .ok_or_else(|| invalid(format!("{} is not an integer", name)))
+}
+
+fn uint(value: &Value, name: &str) -> Result<u64, Status> {
+    match field(value, name) {
+        Value::Null => Some(0),
+        Value::String(uint) => uint.parse().ok(),
+        field => field.as_u64(),
+    }
+    .ok_or_else(|| invalid(format!("{} is not an unsigned integer", name)))
+}
+
+fn boolean(value: &Value, name: &str) -> bool {
+    field(value, name).as_bool().unwrap_or(false)
+}
+
+fn string(value: &Value, name: &str) -> String {
+    field(value, name).as_str().unwrap_or_default().to_string()
+}
+
+fn strings(value: &Value, name: &str) -> Vec<String> {
+    let strings = list(value, name).iter().filter_map(Value::as_str);
+    strings.map(str::to_string).collect()
+}
+
+/// Bytes are sent base64 encoded
+fn base64(value: &Value) -> Result<Vec<u8>, Status> {
+    match value {
+        Value::Null => Ok(Vec::new()),
+        Value::String(encoded) => base64::decode(encoded).map_err(|e| invalid(e.to_string())),
+        _ => Err(invalid("bytes are not base64 encoded".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compare_value, CompareResult, Error, EtcdClient};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::Mutex;
+
+    /// A request received by a [`fake_gateway`]
+    #[derive(Debug)]
+    struct Received {
+        path: String,
+        headers: Vec<String>,
+        body: Value,
+    }
+
+    /// A gateway answering each request with the next of `responses`, its HTTP status and
+    /// JSON body, on a thread of its own. Returns its URL and the requests it receives.
+    fn fake_gateway(responses: Vec<(u16, Value)>) -> (String, Arc<Mutex<Vec<Received>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let requests = Arc::clone(&received);
+        std::thread::spawn(move || {
+            for (status, body) in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let path = line.split(' ').nth(1).unwrap().to_string();
+                let mut headers = Vec::new();
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end().to_lowercase();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line.strip_prefix("content-length: ") {
+                        length = value.parse().unwrap();
+                    }
+                    headers.push(line);
+                }
+                let mut sent = vec![0; length];
+                reader.read_exact(&mut sent).unwrap();
+                requests.lock().unwrap().push(Received {
+                    path,
+                    headers,
+                    body: serde_json::from_slice(&sent).unwrap(),
+                });
+
+                let body = body.to_string();
+                let response = format!(
+                    "HTTP/1.1 {} Status\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+            }
+        });
+        (url, received)
+    }
+
+    #[test]
+    fn test_converting_messages() {
+        use etcdserver::request_op::Request;
+
+        let request = etcdserver::TxnRequest {
+            compare: vec![compare_value("foo", CompareResult::Equal, "bar")],
+            success: vec![etcdserver::RequestOp {
+                request: Some(Request::RequestPut(etcdserver::PutRequest {
+                    key: b"foo".to_vec(),
+                    value: b"baz".to_vec(),
+                    ..Default::default()
+                })),
+            }],
+            failure: Vec::new(),
+        };
+        let (route, json) = request_json("/etcdserverpb.KV/Txn", &encode(&request)).unwrap();
+        assert_eq!(route, "/v3/kv/txn");
+        assert_eq!(json["compare"][0]["key"], "Zm9v");
+        assert_eq!(json["compare"][0]["value"], "YmFy");
+        assert_eq!(json["success"][0]["request_put"]["value"], "YmF6");
+
+        // Integers come as strings, and fields with default values are left out
+        let response = json!({
+            "header": {"cluster_id": "14841639068965178418", "revision": "7"},
+            "kvs": [{"key": "Zm9v", "value": "YmFy", "mod_revision": "7", "version": "1"}],
+            "count": "1",
+        });
+        let response = range_response(&response).unwrap();
+        let header = response.header.unwrap();
+        assert_eq!(header.cluster_id, 14841639068965178418);
+        assert_eq!(header.revision, 7);
+        assert_eq!(response.kvs[0].key, b"foo");
+        assert_eq!(response.kvs[0].value, b"bar");
+        assert_eq!(response.kvs[0].create_revision, 0);
+        assert_eq!(response.count, 1);
+        assert!(!response.more);
+
+        assert!(range_response(&json!({"count": "many"})).is_err());
+        let unsupported = request_json("/etcdserverpb.Watch/Watch", &[]).unwrap_err();
+        assert_eq!(unsupported.code(), Code::Unimplemented);
+    }
+
+    #[tokio::test]
+    async fn test_calls_through_the_gateway() {
+        let (url, received) = fake_gateway(vec![
+            (200, json!({"header": {"revision": "12"}})),
+            (
+                404,
+                json!({
+                    "error": "etcdserver: requested lease not found",
+                    "message": "etcdserver: requested lease not found",
+                    "code": 5,
+                }),
+            ),
+        ]);
+        let client = EtcdClient::builder()
+            .endpoint(url)
+            .metadata("x-request-id", "abc")
+            .retry(crate::RetryPolicy::disabled())
+            .connect_gateway()
+            .await
+            .unwrap();
+
+        let put = client.range("foo", None).put("bar").await.unwrap();
+        assert_eq!(put.revision(), 12);
+        let revoked = client.lease().revoke(0x1234).await;
+        assert!(
+            matches!(
+                revoked.as_ref().map_err(Error::root),
+                Err(Error::LeaseNotFound(_))
+            ),
+            "{:?}",
+            revoked
+        );
+
+        let received = received.lock().unwrap();
+        assert_eq!(received[0].path, "/v3/kv/put");
+        assert_eq!(received[0].body["key"], "Zm9v");
+        assert_eq!(received[0].body["value"], "YmFy");
+        assert!(received[0]
+            .headers
+            .contains(&"grpc-metadata-x-request-id: abc".to_string()));
+        assert_eq!(received[1].path, "/v3/lease/revoke");
+        assert_eq!(received[1].body["ID"], 0x1234);
+
+        assert!(Gateway::new(vec!["https://127.0.0.1:2379"]).is_err());
+    }
+}
@@ -0,0 +1,345 @@
+//! Talking to etcd through a grpc-web proxy, such as Envoy's `grpc_web` filter or
+//! `grpcwebproxy`, for clients which cannot speak gRPC itself, like those running in a
+//! browser.
+//!
+//! [`GrpcWeb`] wraps an HTTP service sending requests to the proxy: it marks requests as
+//! grpc-web, and decodes the status the proxy sends at the end of the response body rather
+//! than in trailers. Wrapped around any service making HTTP requests, such as one built on the
+//! browser's `fetch`, it is a transport for
+//! [`EtcdClient::from_transport`](crate::EtcdClient::from_transport). [`GrpcWebLayer`] wraps
+//! the client's own channels, for proxies accepting HTTP/2:
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! use etcd3::grpc_web::GrpcWebLayer;
+//! use etcd3::EtcdClient;
+//!
+//! let client = EtcdClient::builder()
+//!     .endpoint("http://127.0.0.1:8080")
+//!     .connect_with_layer(GrpcWebLayer)
+//!     .await?;
+//! client.range("foo", None).put("bar").await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! grpc-web carries unary calls and responses streamed by the server, which covers the KV,
+//! lease and maintenance calls. It cannot carry requests streamed by the client, so watches and
+//! lease keep-alives only work through proxies which forward request bodies as they are sent.
+
+use bytes::{Buf, BytesMut};
+use futures::future::{BoxFuture, FutureExt};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tonic::body::BoxBody;
+use tonic::codegen::{HttpBody, HyperBody, Service, StdError};
+
+/// Content type of grpc-web requests with binary protobuf messages
+const CONTENT_TYPE: &str = "application/grpc-web+proto";
+
+/// Flag set on the frame carrying the trailers at the end of a grpc-web response body
+const TRAILERS_FLAG: u8 = 0x80;
+
+/// Layer wrapping a channel in [`GrpcWeb`], for
+/// [`EtcdClientBuilder::connect_with_layer`](crate::EtcdClientBuilder::connect_with_layer)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GrpcWebLayer;
+
+impl<S> tower_layer::Layer<S> for GrpcWebLayer {
+    type Service = GrpcWeb<S>;
+
+    fn layer(&self, inner: S) -> GrpcWeb<S> {
+        GrpcWeb::new(inner)
+    }
+}
+
+/// A service making gRPC calls as grpc-web requests through `S`, see the
+/// [module documentation](self)
+#[derive(Debug, Clone)]
+pub struct GrpcWeb<S> {
+    inner: S,
+}
+
+impl<S> GrpcWeb<S> {
+    /// Make gRPC calls as grpc-web requests sent through `inner`
+    pub fn new(inner: S) -> Self {
+        GrpcWeb { inner }
+    }
+
+    /// The service the requests are sent through
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S, B> Service<http::Request<BoxBody>> for GrpcWeb<S>
+where
+    S: Service<http::Request<BoxBody>, Response = http::Response<B>>,
+    S::Future: Send + 'static,
+    B: HttpBody + Unpin,
+    B::Error: Into<StdError>,
+{
+    type Response = http::Response<GrpcWebBody<B>>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: http::Request<BoxBody>) -> Self::Future {
+        let headers = request.headers_mut();
+        headers.insert(
+            http::header::CONTENT_TYPE,
+            http::HeaderValue::from_static(CONTENT_TYPE),
+        );
+        headers.insert("x-grpc-web", http::HeaderValue::from_static("1"));
+        // Trailers are sent in the body instead
+        headers.remove(http::header::TE);
+        let response = self.inner.call(request);
+        async move { Ok(response.await?.map(GrpcWebBody::new)) }.boxed()
+    }
+}
+
+/// Body of a grpc-web response, passing on its messages and decoding the trailers framed at
+/// its end, see [`GrpcWeb`]
+#[derive(Debug)]
+pub struct GrpcWebBody<B> {
+    inner: B,
+    /// Data received which does not make up a whole frame yet
+    buffer: BytesMut,
+    trailers: Option<http::HeaderMap>,
+    /// Whether the inner body has ended
+    ended: bool,
+}
+
+impl<B> GrpcWebBody<B> {
+    fn new(inner: B) -> Self {
+        GrpcWebBody {
+            inner,
+            buffer: BytesMut::new(),
+            trailers: None,
+            ended: false,
+        }
+    }
+
+    /// Take the next message frame out of the buffer, decoding the trailers if they come
+    /// first. `None` until a whole frame has been received.
+    fn next_frame(&mut self) -> Result<Option<<HyperBody as HttpBody>::Data>, StdError> {
+        loop {
+            if self.buffer.len() < 5 {
+                return Ok(None);
+            }
+            let mut length = [0; 4];
+            length.copy_from_slice(&self.buffer[1..5]);
+            let length = u32::from_be_bytes(length) as usize;
+            if self.buffer.len() < 5 + length {
+                return Ok(None);
+            }
+            let frame = self.buffer.split_to(5 + length).freeze();
+            if frame[0] & TRAILERS_FLAG == 0 {
+                return Ok(Some(frame.into()));
+            }
+            self.trailers = Some(decode_trailers(&frame[5..])?);
+        }
+    }
+}
+
+impl<B> HttpBody for GrpcWebBody<B>
+where
+    B: HttpBody + Unpin,
+    B::Error: Into<StdError>,
+{
+    type Data = <HyperBody as HttpBody>::Data;
+    type Error = StdError;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        loop {
+            if let Some(frame) = self.next_frame().transpose() {
+                return Poll::Ready(Some(frame));
+            }
+            if self.ended {
+                if !self.buffer.is_empty() {
+                    let truncated = "grpc-web response ended in the middle of a frame";
+                    return Poll::Ready(Some(Err(truncated.into())));
+                }
+                return Poll::Ready(None);
+            }
+            match futures::ready!(Pin::new(&mut self.inner).poll_data(cx)) {
+                Some(Ok(mut data)) => {
+                    while data.has_remaining() {
+                        let chunk = data.bytes();
+                        let read = chunk.len();
+                        self.buffer.extend_from_slice(chunk);
+                        data.advance(read);
+                    }
+                }
+                Some(Err(e)) => return Poll::Ready(Some(Err(e.into()))),
+                None => self.ended = true,
+            }
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<http::HeaderMap>, Self::Error>> {
+        if let Some(trailers) = self.trailers.take() {
+            return Poll::Ready(Ok(Some(trailers)));
+        }
+        // Proxies speaking HTTP/2 may send the status as trailers after all
+        Pin::new(&mut self.inner)
+            .poll_trailers(cx)
+            .map_err(Into::into)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.ended && self.buffer.is_empty() && self.trailers.is_none()
+    }
+}
+
+/// Trailers sent in a grpc-web frame, as header lines
+fn decode_trailers(block: &[u8]) -> Result<http::HeaderMap, StdError> {
+    let mut trailers = http::HeaderMap::new();
+    let block = std::str::from_utf8(block)?;
+    for line in block.split("\r\n").filter(|line| !line.trim().is_empty()) {
+        let (name, value) = match line.find(':') {
+            Some(colon) => (&line[..colon], &line[colon + 1..]),
+            None => return Err(format!("invalid grpc-web trailer {:?}", line).into()),
+        };
+        let name = http::header::HeaderName::from_bytes(name.trim().to_lowercase().as_bytes())?;
+        trailers.append(name, http::HeaderValue::from_str(value.trim())?);
+    }
+    Ok(trailers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{etcdserver, EtcdClient};
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    /// Body sent in chunks
+    struct Chunks(VecDeque<Vec<u8>>);
+
+    impl HttpBody for Chunks {
+        type Data = <HyperBody as HttpBody>::Data;
+        type Error = StdError;
+
+        fn poll_data(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+        ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+            Poll::Ready(self.0.pop_front().map(|chunk| Ok(chunk.into())))
+        }
+
+        fn poll_trailers(
+            self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+        ) -> Poll<Result<Option<http::HeaderMap>, Self::Error>> {
+            Poll::Ready(Ok(None))
+        }
+    }
+
+    /// A proxy answering with a grpc-web response body, split into chunks of `chunk` bytes,
+    /// and keeping the headers of the requests it is sent
+    #[derive(Clone)]
+    struct Proxy {
+        body: Vec<u8>,
+        chunk: usize,
+        requests: Arc<Mutex<Vec<http::HeaderMap>>>,
+    }
+
+    impl Service<http::Request<BoxBody>> for Proxy {
+        type Response = http::Response<Chunks>;
+        type Error = StdError;
+        type Future = futures::future::Ready<Result<Self::Response, StdError>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), StdError>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+            self.requests
+                .lock()
+                .unwrap()
+                .push(request.headers().clone());
+            let chunks = self.body.chunks(self.chunk).map(<[u8]>::to_vec).collect();
+            futures::future::ready(Ok(http::Response::new(Chunks(chunks))))
+        }
+    }
+
+    fn frame(flags: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![flags];
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    fn response(revision: i64, trailers: &str) -> Vec<u8> {
+        let response = etcdserver::PutResponse {
+            header: Some(etcdserver::ResponseHeader {
+                revision,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut message = Vec::new();
+        prost::Message::encode(&response, &mut message).unwrap();
+        let mut body = frame(0, &message);
+        body.extend(frame(TRAILERS_FLAG, trailers.as_bytes()));
+        body
+    }
+
+    #[test]
+    fn test_decoding_trailers() {
+        let trailers = decode_trailers(b"grpc-status: 0\r\nGrpc-Message: all good\r\n").unwrap();
+        assert_eq!(trailers["grpc-status"], "0");
+        assert_eq!(trailers["grpc-message"], "all good");
+        assert!(decode_trailers(b"grpc-status").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_calls_through_a_proxy() {
+        for &chunk in &[1, 7, 1024] {
+            let proxy = Proxy {
+                body: response(12, "grpc-status: 0\r\n"),
+                chunk,
+                requests: Arc::default(),
+            };
+            let client = EtcdClient::from_transport(GrpcWeb::new(proxy.clone()));
+            let put = client.range("foo", None).put("bar").await.unwrap();
+            assert_eq!(put.revision(), 12, "in chunks of {} bytes", chunk);
+
+            let requests = proxy.requests.lock().unwrap();
+            assert_eq!(requests[0]["content-type"], CONTENT_TYPE);
+            assert_eq!(requests[0]["x-grpc-web"], "1");
+            assert!(requests[0].get("te").is_none());
+        }
+
+        // The status in the trailers frame is the call's
+        let proxy = Proxy {
+            body: response(12, "grpc-status: 9\r\ngrpc-message: nope\r\n"),
+            chunk: 1024,
+            requests: Arc::default(),
+        };
+        let client = EtcdClient::from_transport(GrpcWeb::new(proxy));
+        let error = client.range("foo", None).put("bar").await.unwrap_err();
+        assert!(error.to_string().contains("nope"), "{}", error);
+
+        // A body cut short is an error rather than an empty response
+        let mut body = response(12, "grpc-status: 0\r\n");
+        body.truncate(3);
+        let proxy = Proxy {
+            body,
+            chunk: 1024,
+            requests: Arc::default(),
+        };
+        let client = EtcdClient::from_transport(GrpcWeb::new(proxy));
+        assert!(client.range("foo", None).put("bar").await.is_err());
+    }
+}
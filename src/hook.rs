@@ -0,0 +1,246 @@
+//! Hooks run around the client's operations, for custom logging, auditing or capturing
+//! traffic, see [`EtcdClientBuilder::hook`](crate::EtcdClientBuilder::hook).
+
+use crate::{etcdserver, Error, ErrorContext};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Called before and after each operation the client makes, such as a put, a watch being
+/// created or a lease being kept alive.
+///
+/// Hooks run inline on the task making the operation, so they should be quick, handing
+/// anything slow off to a task of their own. Every method does nothing by default.
+pub trait ClientHook: Send + Sync + 'static {
+    /// `call` is about to be sent
+    fn on_request(&self, call: &Call<'_>) {
+        let _ = call;
+    }
+
+    /// `call` succeeded after `latency`
+    fn on_response(&self, call: &Call<'_>, latency: Duration) {
+        let _ = (call, latency);
+    }
+
+    /// `call` failed with `error` after `latency`
+    fn on_error(&self, call: &Call<'_>, latency: Duration, error: &Error) {
+        let _ = (call, latency, error);
+    }
+}
+
+/// An operation made by the client, as seen by a [`ClientHook`]
+#[derive(Debug, Clone, Copy)]
+pub struct Call<'a> {
+    pub(crate) name: &'static str,
+    pub(crate) context: &'a ErrorContext,
+}
+
+impl<'a> Call<'a> {
+    /// Name of the operation, such as `kv.put`, `watch.create` or `lease.keep_alive`
+    pub fn operation(&self) -> &'static str {
+        self.name
+    }
+
+    /// The key or prefix operated on, unless the client redacts keys, see
+    /// [`EtcdClientBuilder::redact_keys`](crate::EtcdClientBuilder::redact_keys)
+    pub fn key(&self) -> Option<&'a str> {
+        self.context.key.as_deref()
+    }
+
+    /// The endpoint the request is sent to, if the client only routes requests to one
+    pub fn endpoint(&self) -> Option<&'a str> {
+        self.context.endpoint.as_deref()
+    }
+
+    /// How many times the request was sent, counting retries, once it has been sent
+    pub fn attempts(&self) -> Option<u32> {
+        self.context.attempt
+    }
+
+    /// Header of the response, once one has arrived
+    pub fn header(&self) -> Option<&'a etcdserver::ResponseHeader> {
+        self.context.header.as_ref()
+    }
+}
+
+/// The operation's name and everything known about it, such as
+/// `kv.put "foo" on http://10.0.0.1:2379 (attempt 2)`
+impl fmt::Display for Call<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name)?;
+        if let Some(key) = self.key() {
+            write!(f, " {:?}", key)?;
+        }
+        if let Some(endpoint) = self.endpoint() {
+            write!(f, " on {}", endpoint)?;
+        }
+        if let Some(attempts) = self.attempts() {
+            write!(f, " (attempt {})", attempts)?;
+        }
+        Ok(())
+    }
+}
+
+/// A hook calling back with every operation which takes longer than a threshold, whether it
+/// succeeds or fails, see [`EtcdClientBuilder::slow_requests`]
+///
+/// [`EtcdClientBuilder::slow_requests`]: crate::EtcdClientBuilder::slow_requests
+pub struct SlowRequests<F> {
+    threshold: Duration,
+    callback: F,
+}
+
+impl<F> SlowRequests<F>
+where
+    F: Fn(&Call<'_>, Duration) + Send + Sync + 'static,
+{
+    /// Call `callback` with each operation taking longer than `threshold`, and how long it took
+    pub fn new(threshold: Duration, callback: F) -> Self {
+        Self {
+            threshold,
+            callback,
+        }
+    }
+
+    fn check(&self, call: &Call<'_>, latency: Duration) {
+        if latency > self.threshold {
+            (self.callback)(call, latency);
+        }
+    }
+}
+
+impl<F> ClientHook for SlowRequests<F>
+where
+    F: Fn(&Call<'_>, Duration) + Send + Sync + 'static,
+{
+    fn on_response(&self, call: &Call<'_>, latency: Duration) {
+        self.check(call, latency);
+    }
+
+    fn on_error(&self, call: &Call<'_>, latency: Duration, _: &Error) {
+        self.check(call, latency);
+    }
+}
+
+impl<F> fmt::Debug for SlowRequests<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SlowRequests")
+            .field("threshold", &self.threshold)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Hooks run around every operation, in the order they were added
+#[derive(Clone, Default)]
+pub(crate) struct Hooks(Arc<Vec<Arc<dyn ClientHook>>>);
+
+impl Hooks {
+    pub(crate) fn push(&mut self, hook: Arc<dyn ClientHook>) {
+        Arc::make_mut(&mut self.0).push(hook);
+    }
+
+    /// Remove `hook`, if it was added
+    #[cfg(feature = "cluster")]
+    pub(crate) fn remove<H>(&mut self, hook: &Arc<H>) {
+        let hook = Arc::as_ptr(hook) as *const u8;
+        Arc::make_mut(&mut self.0).retain(|h| Arc::as_ptr(h) as *const u8 != hook);
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &dyn ClientHook> {
+        self.0.iter().map(|hook| hook.as_ref())
+    }
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} hooks", self.0.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::Operation;
+    use crate::EtcdResult;
+    use std::sync::Mutex;
+
+    /// Records the calls it sees, prefixed with its name
+    struct Recorder(&'static str, Arc<Mutex<Vec<String>>>);
+
+    impl ClientHook for Recorder {
+        fn on_request(&self, call: &Call<'_>) {
+            let seen = format!("{} request {} {:?}", self.0, call.operation(), call.key());
+            self.1.lock().unwrap().push(seen);
+        }
+
+        fn on_error(&self, call: &Call<'_>, _: Duration, error: &Error) {
+            let seen = format!("{} error {:?} {}", self.0, call.attempts(), error);
+            self.1.lock().unwrap().push(seen);
+        }
+    }
+
+    #[test]
+    fn test_hooks() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut hooks = Hooks::default();
+        hooks.push(Arc::new(Recorder("first", seen.clone())));
+        hooks.push(Arc::new(Recorder("second", seen.clone())));
+
+        let mut context = ErrorContext {
+            operation: "put",
+            key: Some("foo".to_string()),
+            ..Default::default()
+        };
+        let operation = Operation::start("kv.put", &context, &hooks);
+        context.attempt = Some(3);
+        let result: EtcdResult<()> = Err(Error::NoLeader);
+        operation.finish(&context, &result);
+        // Successes only reach on_response, which the recorder leaves as it is
+        let operation = Operation::start("kv.get", &context, &hooks);
+        operation.finish(&context, &Ok(()));
+
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                "first request kv.put Some(\"foo\")",
+                "second request kv.put Some(\"foo\")",
+                "first error Some(3) no leader",
+                "second error Some(3) no leader",
+                "first request kv.get Some(\"foo\")",
+                "second request kv.get Some(\"foo\")",
+            ]
+        );
+        assert_eq!(format!("{:?}", hooks), "2 hooks");
+    }
+
+    #[test]
+    fn test_slow_requests() {
+        let slow = Arc::new(Mutex::new(Vec::new()));
+        let seen = slow.clone();
+        let hook = SlowRequests::new(Duration::from_millis(100), move |call, latency| {
+            let call = format!("{} took {:?}", call, latency);
+            seen.lock().unwrap().push(call);
+        });
+        let context = ErrorContext {
+            operation: "put",
+            key: Some("foo".to_string()),
+            endpoint: Some("http://10.0.0.1:2379".to_string()),
+            attempt: Some(2),
+            ..Default::default()
+        };
+        let call = Call {
+            name: "kv.put",
+            context: &context,
+        };
+        hook.on_response(&call, Duration::from_millis(50));
+        hook.on_response(&call, Duration::from_millis(150));
+        hook.on_error(&call, Duration::from_secs(1), &Error::NoLeader);
+        assert_eq!(
+            *slow.lock().unwrap(),
+            vec![
+                "kv.put \"foo\" on http://10.0.0.1:2379 (attempt 2) took 150ms",
+                "kv.put \"foo\" on http://10.0.0.1:2379 (attempt 2) took 1s",
+            ]
+        );
+    }
+}
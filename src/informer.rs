@@ -0,0 +1,480 @@
+//! Informers: a list of the keys under a prefix followed by a watch of their changes, the
+//! pattern controllers built on etcd use to reconcile the state they are given.
+//!
+//! [`inform`] lists the keys under a prefix at a revision R, a page at a time, and watches them
+//! from R + 1, so no change is missed or seen twice. [`Informer::next`] delivers what changed as
+//! typed [`InformerEvent`]s, keeping a [store](Informer::store) of every object as of the
+//! informer's [revision](Informer::revision), the resource version of Kubernetes:
+//!
+//! ```no_run
+//! # async fn run(client: etcd3::EtcdClient<tonic::transport::Channel>) -> etcd3::EtcdResult<()> {
+//! use etcd3::informer::{self, InformerEvent};
+//!
+//! let mut informer = informer::inform::<_, String>(&client, "deployments/").await?;
+//! while let Some(event) = informer.next().await {
+//!     match event? {
+//!         InformerEvent::Added(object) | InformerEvent::Modified(object) => {
+//!             println!("reconcile {}", object.value)
+//!         }
+//!         InformerEvent::Deleted(object) => println!("clean up {}", object.value),
+//!         InformerEvent::Synced(revision) => println!("up to date as of {}", revision),
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! If the watch breaks it is re-established with backoff, carrying on from the informer's
+//! revision. If the changes since were compacted away the keys are listed again, and the
+//! differences from the store delivered as events, so the store ends up as it would have had
+//! every change been seen. Keys are taken as given, rather than in the client's namespace.
+
+use crate::concurrency::{prefix_end, read_revision};
+use crate::discovery::Instance;
+use crate::redact::Key;
+use crate::{client, etcdserver, is_retryable, mvccpb, Backoff, EtcdClient, EtcdResult};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+use tonic::transport::channel::Channel;
+
+/// Most keys read from the server at once while listing
+const LIST_PAGE: i64 = 1000;
+
+/// A key under an informer's prefix and its decoded value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Object<V> {
+    /// The object's key
+    pub key: Vec<u8>,
+    /// The object's value, decoded
+    pub value: V,
+    /// Revision the key was created at
+    pub create_revision: i64,
+    /// Revision the key was last modified at, the object's resource version
+    pub mod_revision: i64,
+    /// Number of times the key was modified since it was created
+    pub version: i64,
+}
+
+/// A change delivered by an [`Informer`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InformerEvent<V> {
+    /// An object was created, or listed for the first time
+    Added(Object<V>),
+    /// An object was modified, with its new value
+    Modified(Object<V>),
+    /// An object was deleted, with its last value
+    Deleted(Object<V>),
+    /// The store holds every object as of the revision, delivered once the initial list and
+    /// every relist have been
+    Synced(i64),
+}
+
+/// A list of the keys under a prefix followed by a watch of their changes, see [`inform`]
+pub struct Informer<V> {
+    client: EtcdClient<Channel>,
+    kv_client: client::KvClient<Channel>,
+    watch_client: client::WatchClient<Channel>,
+    prefix: Vec<u8>,
+    cache: Cache<V>,
+    /// The watch, or `None` once it cannot be followed any longer
+    responses: Option<tonic::Streaming<etcdserver::WatchResponse>>,
+    backoff: Backoff,
+}
+
+impl<V> fmt::Debug for Informer<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Informer")
+            .field("prefix", &Key(&self.prefix))
+            .field("objects", &self.cache.objects.len())
+            .field("revision", &self.cache.revision)
+            .finish_non_exhaustive()
+    }
+}
+
+/// List the keys under `prefix`, decoding their values as `V`, and start watching them. The
+/// first events delivered add every object listed, followed by [`InformerEvent::Synced`].
+pub async fn inform<P, V>(client: &EtcdClient<Channel>, prefix: P) -> EtcdResult<Informer<V>>
+where
+    P: Into<Vec<u8>>,
+    V: Instance + Clone,
+{
+    let prefix = prefix.into();
+    let mut kv_client = client.kv_client();
+    let (revision, kvs) = list(client, &mut kv_client, &prefix).await?;
+
+    // Follow on from the revision the keys were listed at, so no change is missed
+    let mut watch_client = client.watch_client();
+    let responses = watch(&mut watch_client, &prefix, revision + 1).await?;
+    let mut informer = Informer {
+        client: client.clone(),
+        kv_client,
+        watch_client,
+        prefix,
+        cache: Cache::new(),
+        responses: Some(responses),
+        backoff: Backoff::new(),
+    };
+    informer.cache.replace(revision, kvs);
+    Ok(informer)
+}
+
+/// Every key under `prefix`, a page at a time, with the revision they were all read at
+async fn list(
+    client: &EtcdClient<Channel>,
+    kv_client: &mut client::KvClient<Channel>,
+    prefix: &[u8],
+) -> EtcdResult<(i64, Vec<mvccpb::KeyValue>)> {
+    let end = prefix_end(prefix);
+    let mut key = prefix.to_vec();
+    // Every page is read at the revision of the first, so they fit together
+    let mut revision = 0;
+    let mut kvs = Vec::new();
+    loop {
+        let request = etcdserver::RangeRequest {
+            key,
+            range_end: end.clone(),
+            limit: LIST_PAGE,
+            revision,
+            serializable: client.serializable_reads(),
+            ..Default::default()
+        };
+        let response = kv_client.range(request).await?.into_inner();
+        client.check_cluster_id(response.header.as_ref())?;
+        if revision == 0 {
+            revision = read_revision(response.header, "range")?;
+        }
+        let more = response.more;
+        kvs.extend(response.kvs);
+        match kvs.last() {
+            Some(last) if more => {
+                key = last.key.clone();
+                key.push(0);
+            }
+            _ => return Ok((revision, kvs)),
+        }
+    }
+}
+
+/// Watch for changes to the keys under `prefix` from `revision` onwards
+async fn watch(
+    watch_client: &mut client::WatchClient<Channel>,
+    prefix: &[u8],
+    revision: i64,
+) -> Result<tonic::Streaming<etcdserver::WatchResponse>, tonic::Status> {
+    let create = etcdserver::WatchCreateRequest {
+        key: prefix.to_vec(),
+        range_end: prefix_end(prefix),
+        start_revision: revision,
+        ..Default::default()
+    };
+    let requests = async_stream::stream! {
+        yield etcdserver::WatchRequest {
+            request_union: Some(etcdserver::watch_request::RequestUnion::CreateRequest(create)),
+        };
+    };
+    Ok(watch_client.watch(requests).await?.into_inner())
+}
+
+/// The object stored at `kv`, if its value decodes
+fn decode<V>(kv: mvccpb::KeyValue) -> EtcdResult<Object<V>>
+where
+    V: Instance,
+{
+    let value = V::decode(&kv.value)
+        .map_err(|e| format!("invalid object {}: {}", String::from_utf8_lossy(&kv.key), e))?;
+    Ok(Object {
+        key: kv.key,
+        value,
+        create_revision: kv.create_revision,
+        mod_revision: kv.mod_revision,
+        version: kv.version,
+    })
+}
+
+/// The objects an informer delivered, and the changes it has yet to
+struct Cache<V> {
+    objects: BTreeMap<Vec<u8>, Object<V>>,
+    /// Revision of the last change delivered
+    revision: i64,
+    /// Changes to deliver from the last list, decided on against the objects when listed
+    listed: VecDeque<EtcdResult<InformerEvent<V>>>,
+    /// Changes received from the watch, decided on as they are delivered, as each depends on
+    /// the one before
+    watched: VecDeque<mvccpb::Event>,
+}
+
+impl<V> Cache<V>
+where
+    V: Instance + Clone,
+{
+    fn new() -> Self {
+        Self {
+            objects: BTreeMap::new(),
+            revision: 0,
+            listed: VecDeque::new(),
+            watched: VecDeque::new(),
+        }
+    }
+
+    /// Queue the changes taking the objects to `kvs`, listed at `revision`
+    fn replace(&mut self, revision: i64, kvs: Vec<mvccpb::KeyValue>) {
+        let mut listed = BTreeSet::new();
+        for kv in kvs {
+            listed.insert(kv.key.clone());
+            match self.objects.get(&kv.key) {
+                Some(held) if held.mod_revision == kv.mod_revision => {}
+                Some(_) => self
+                    .listed
+                    .push_back(decode(kv).map(InformerEvent::Modified)),
+                None => self.listed.push_back(decode(kv).map(InformerEvent::Added)),
+            }
+        }
+        for (key, object) in &self.objects {
+            if !listed.contains(key) {
+                let deleted = InformerEvent::Deleted(object.clone());
+                self.listed.push_back(Ok(deleted));
+            }
+        }
+        self.listed.push_back(Ok(InformerEvent::Synced(revision)));
+    }
+
+    /// Deliver the next change queued, if there is one, applying it to the objects
+    fn next(&mut self) -> Option<EtcdResult<InformerEvent<V>>> {
+        if let Some(listed) = self.listed.pop_front() {
+            match &listed {
+                Ok(InformerEvent::Added(object)) | Ok(InformerEvent::Modified(object)) => {
+                    self.objects.insert(object.key.clone(), object.clone());
+                }
+                Ok(InformerEvent::Deleted(object)) => {
+                    self.objects.remove(&object.key);
+                }
+                Ok(InformerEvent::Synced(revision)) => self.revision = *revision,
+                Err(_) => {}
+            }
+            return Some(listed);
+        }
+        while let Some(event) = self.watched.pop_front() {
+            let kv = match event.kv {
+                Some(kv) => kv,
+                None => continue,
+            };
+            self.revision = self.revision.max(kv.mod_revision);
+            if event.r#type == mvccpb::event::EventType::Delete as i32 {
+                // A key whose value never decoded is not held, so has nothing to delete
+                match self.objects.remove(&kv.key) {
+                    Some(object) => return Some(Ok(InformerEvent::Deleted(object))),
+                    None => continue,
+                }
+            }
+            let object = match decode::<V>(kv) {
+                Ok(object) => object,
+                Err(e) => return Some(Err(e)),
+            };
+            let held = self.objects.insert(object.key.clone(), object.clone());
+            return Some(Ok(match held {
+                Some(_) => InformerEvent::Modified(object),
+                None => InformerEvent::Added(object),
+            }));
+        }
+        None
+    }
+}
+
+impl<V> Informer<V>
+where
+    V: Instance + Clone,
+{
+    /// The objects as of the last change delivered, by key
+    pub fn store(&self) -> &BTreeMap<Vec<u8>, Object<V>> {
+        &self.cache.objects
+    }
+
+    /// The object at `key` as of the last change delivered
+    pub fn get<K>(&self, key: K) -> Option<&Object<V>>
+    where
+        K: AsRef<[u8]>,
+    {
+        self.cache.objects.get(key.as_ref())
+    }
+
+    /// Revision of the last change delivered, which the store is up to date with. Watching the
+    /// prefix from the next revision carries on where the informer is.
+    pub fn revision(&self) -> i64 {
+        self.cache.revision
+    }
+
+    /// Wait for the next change and apply it to the store. Returns `None` once the changes
+    /// can no longer be followed, after the error which ended them.
+    ///
+    /// A key whose new value cannot be decoded is reported as an error and keeps its previous
+    /// object in the store.
+    pub async fn next(&mut self) -> Option<EtcdResult<InformerEvent<V>>> {
+        loop {
+            if let Some(event) = self.cache.next() {
+                return Some(event);
+            }
+            let responses = self.responses.as_mut()?;
+            let response = match responses.message().await {
+                Ok(Some(response)) => response,
+                Err(status) if !is_retryable(&status) => {
+                    self.responses = None;
+                    return Some(Err(status.into()));
+                }
+                // The watch broke, so carry on from the last change seen
+                _ => {
+                    if let Err(e) = self.rewatch().await {
+                        self.responses = None;
+                        return Some(Err(e));
+                    }
+                    continue;
+                }
+            };
+            self.backoff.reset();
+            if response.canceled && response.compact_revision > 0 {
+                // The changes since the informer's revision are gone, so list the keys again
+                if let Err(e) = self.relist().await {
+                    self.responses = None;
+                    return Some(Err(e));
+                }
+                continue;
+            }
+            if response.canceled {
+                self.responses = None;
+                let reason = format!("watch canceled: {}", response.cancel_reason);
+                return Some(Err(reason.into()));
+            }
+            self.cache.watched.extend(response.events);
+        }
+    }
+
+    /// Re-establish the broken watch from the next revision, backing off between attempts,
+    /// until it is re-established or fails with an error which is not worth retrying
+    async fn rewatch(&mut self) -> EtcdResult<()> {
+        loop {
+            self.backoff.wait().await;
+            let next = self.cache.revision + 1;
+            match watch(&mut self.watch_client, &self.prefix, next).await {
+                Ok(responses) => {
+                    #[cfg(feature = "metrics")]
+                    crate::metrics::watch_reconnected(1);
+                    self.responses = Some(responses);
+                    return Ok(());
+                }
+                Err(status) if is_retryable(&status) => continue,
+                Err(status) => return Err(status.into()),
+            }
+        }
+    }
+
+    /// List the keys again and watch on from the revision they were listed at
+    async fn relist(&mut self) -> EtcdResult<()> {
+        let (revision, kvs) = list(&self.client, &mut self.kv_client, &self.prefix).await?;
+        let responses = watch(&mut self.watch_client, &self.prefix, revision + 1).await?;
+        self.responses = Some(responses);
+        self.cache.replace(revision, kvs);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mvccpb::event::EventType;
+
+    fn kv(key: &str, value: &str, revision: i64) -> mvccpb::KeyValue {
+        mvccpb::KeyValue {
+            key: key.as_bytes().to_vec(),
+            value: value.as_bytes().to_vec(),
+            create_revision: revision,
+            mod_revision: revision,
+            version: 1,
+            lease: 0,
+        }
+    }
+
+    fn event(event_type: EventType, kv: mvccpb::KeyValue) -> mvccpb::Event {
+        mvccpb::Event {
+            r#type: event_type as i32,
+            kv: Some(kv),
+            prev_kv: None,
+        }
+    }
+
+    /// Key and value of every change delivered, with the kind of change
+    fn drain(cache: &mut Cache<String>) -> Vec<String> {
+        std::iter::from_fn(|| cache.next())
+            .map(|event| match event {
+                Ok(InformerEvent::Added(o)) => format!("added {}", o.value),
+                Ok(InformerEvent::Modified(o)) => format!("modified {}", o.value),
+                Ok(InformerEvent::Deleted(o)) => format!("deleted {}", o.value),
+                Ok(InformerEvent::Synced(revision)) => format!("synced {}", revision),
+                Err(e) => e.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_delivering_changes() {
+        let mut cache = Cache::new();
+        cache.replace(3, vec![kv("a", "a1", 2), kv("b", "b1", 3)]);
+        assert_eq!(drain(&mut cache), ["added a1", "added b1", "synced 3"]);
+        assert_eq!(cache.revision, 3);
+
+        // Each change is decided on against the one before, even within a response
+        let invalid = mvccpb::KeyValue {
+            value: vec![0xff],
+            ..kv("a", "", 7)
+        };
+        cache.watched.extend(vec![
+            event(EventType::Put, kv("c", "c1", 4)),
+            event(EventType::Put, kv("c", "c2", 5)),
+            event(EventType::Delete, kv("c", "", 6)),
+            event(EventType::Put, invalid),
+            event(EventType::Delete, kv("never", "", 8)),
+        ]);
+        let delivered = drain(&mut cache);
+        assert_eq!(delivered[..3], ["added c1", "modified c2", "deleted c2"]);
+        assert!(
+            delivered[3].starts_with("invalid object a"),
+            "{}",
+            delivered[3]
+        );
+        assert_eq!(delivered.len(), 4);
+        assert_eq!(cache.revision, 8);
+        assert_eq!(cache.objects[&b"a"[..]].value, "a1");
+
+        // A relist delivers the differences from the objects held
+        cache.replace(
+            12,
+            vec![kv("a", "a1", 2), kv("b", "b2", 10), kv("d", "d1", 11)],
+        );
+        assert_eq!(drain(&mut cache), ["modified b2", "added d1", "synced 12"]);
+        cache.replace(13, vec![kv("d", "d1", 11)]);
+        assert_eq!(drain(&mut cache), ["deleted a1", "deleted b2", "synced 13"]);
+        let keys: Vec<_> = cache.objects.keys().cloned().collect();
+        assert_eq!(keys, [b"d".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn test_informing() {
+        let client = crate::testing::namespace().await.unwrap();
+        let prefix = client.key("test_informing/");
+        let kv = client.kv();
+        kv.put("test_informing/a", "a1").await.unwrap();
+
+        let mut informer = inform::<_, String>(&client, prefix.as_str()).await.unwrap();
+        let added = informer.next().await.unwrap().unwrap();
+        assert!(matches!(added, InformerEvent::Added(ref o) if o.value == "a1"));
+        let synced = informer.next().await.unwrap().unwrap();
+        assert_eq!(synced, InformerEvent::Synced(informer.revision()));
+
+        kv.put("test_informing/a", "a2").await.unwrap();
+        let revision = kv.delete("test_informing/a").await.unwrap().revision();
+        let modified = informer.next().await.unwrap().unwrap();
+        assert!(matches!(modified, InformerEvent::Modified(ref o) if o.value == "a2"));
+        let deleted = informer.next().await.unwrap().unwrap();
+        assert!(matches!(deleted, InformerEvent::Deleted(ref o) if o.value == "a2"));
+        assert_eq!(informer.revision(), revision);
+        assert!(informer.store().is_empty());
+    }
+}
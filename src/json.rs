@@ -0,0 +1,701 @@
+//! JSON rendering of members, statuses, alarms and health reports, in the format `etcdctl -w
+//! json` prints them in, so tooling built on the client can stand in for etcdctl in scripts
+//! and dashboards reading its output.
+//!
+//! Each item renders with [`ToJson::to_json`]. The functions of the module render whole
+//! commands' output around the items, such as [`member_list`] for `etcdctl member list -w
+//! json`:
+//!
+//! ```no_run
+//! # async fn run(client: etcd3::EtcdClient<tonic::transport::Channel>) -> etcd3::EtcdResult<()> {
+//! let members = client.cluster().members().await?;
+//! println!("{}", etcd3::json::member_list(&members));
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Like etcdctl, fields holding their default value are left out and IDs are printed as
+//! decimal numbers.
+//!
+//! Keys are dumped in the format of `etcdctl get --prefix -w json` with [`dump_json`], and
+//! restored from it with [`restore_json`], so the client can take part in backup scripts
+//! built around etcdctl.
+
+use crate::concurrency::prefix_end;
+use crate::{etcdserver, Budget, Error, EtcdClient, EtcdResult, OpRequest, Transport};
+use serde::Serialize;
+use serde_json::Value;
+use std::io;
+#[cfg(feature = "cluster")]
+use std::time::Duration;
+
+/// Keys read from the cluster for each page of a dump
+const DUMP_PAGE: i64 = 1000;
+
+/// Keys put by each transaction of a restore, etcd's default limit on a transaction's
+/// operations
+const RESTORE_BATCH: usize = 128;
+
+/// Rendering as the JSON etcdctl prints, see the [module documentation](self)
+pub trait ToJson {
+    /// The JSON etcdctl prints for the value
+    fn to_json(&self) -> String;
+}
+
+/// A list renders as a JSON array of its items
+impl<T: ToJson> ToJson for [T] {
+    fn to_json(&self) -> String {
+        let items: Vec<String> = self.iter().map(ToJson::to_json).collect();
+        format!("[{}]", items.join(","))
+    }
+}
+
+impl<T: ToJson> ToJson for Vec<T> {
+    fn to_json(&self) -> String {
+        self.as_slice().to_json()
+    }
+}
+
+fn is_zero<N: Default + PartialEq>(n: &N) -> bool {
+    *n == N::default()
+}
+
+fn render<S: Serialize>(value: &S) -> String {
+    // The values rendered are plain structs of numbers, strings and lists, which always
+    // serialize
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+#[derive(Serialize)]
+struct Header {
+    #[serde(skip_serializing_if = "is_zero")]
+    cluster_id: u64,
+    #[serde(skip_serializing_if = "is_zero")]
+    member_id: u64,
+    #[serde(skip_serializing_if = "is_zero")]
+    revision: i64,
+    #[serde(skip_serializing_if = "is_zero")]
+    raft_term: u64,
+}
+
+impl Header {
+    fn new(header: &Option<etcdserver::ResponseHeader>) -> Option<Self> {
+        header.as_ref().map(|header| Header {
+            cluster_id: header.cluster_id,
+            member_id: header.member_id,
+            revision: header.revision,
+            raft_term: header.raft_term,
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct Member<'a> {
+    #[serde(rename = "ID", skip_serializing_if = "is_zero")]
+    id: u64,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    name: &'a str,
+    #[serde(rename = "peerURLs", skip_serializing_if = "<[_]>::is_empty")]
+    peer_urls: &'a [String],
+    #[serde(rename = "clientURLs", skip_serializing_if = "<[_]>::is_empty")]
+    client_urls: &'a [String],
+    #[serde(rename = "isLearner", skip_serializing_if = "is_zero")]
+    is_learner: bool,
+}
+
+impl<'a> Member<'a> {
+    fn new(member: &'a etcdserver::Member) -> Self {
+        Member {
+            id: member.id,
+            name: &member.name,
+            peer_urls: &member.peer_ur_ls,
+            client_urls: &member.client_ur_ls,
+            is_learner: member.is_learner,
+        }
+    }
+}
+
+/// A member as `etcdctl member list -w json` prints it
+impl ToJson for etcdserver::Member {
+    fn to_json(&self) -> String {
+        render(&Member::new(self))
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Status<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    header: Option<Header>,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    version: &'a str,
+    #[serde(skip_serializing_if = "is_zero")]
+    db_size: i64,
+    #[serde(skip_serializing_if = "is_zero")]
+    leader: u64,
+    #[serde(skip_serializing_if = "is_zero")]
+    raft_index: u64,
+    #[serde(skip_serializing_if = "is_zero")]
+    raft_term: u64,
+    #[serde(skip_serializing_if = "is_zero")]
+    raft_applied_index: u64,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    errors: &'a [String],
+    #[serde(skip_serializing_if = "is_zero")]
+    db_size_in_use: i64,
+    #[serde(skip_serializing_if = "is_zero")]
+    is_learner: bool,
+}
+
+impl<'a> Status<'a> {
+    fn new(status: &'a etcdserver::StatusResponse) -> Self {
+        Status {
+            header: Header::new(&status.header),
+            version: &status.version,
+            db_size: status.db_size,
+            leader: status.leader,
+            raft_index: status.raft_index,
+            raft_term: status.raft_term,
+            raft_applied_index: status.raft_applied_index,
+            errors: &status.errors,
+            db_size_in_use: status.db_size_in_use,
+            is_learner: status.is_learner,
+        }
+    }
+}
+
+/// A member's status as `etcdctl endpoint status -w json` prints it for each endpoint, see
+/// [`endpoint_status`]
+impl ToJson for etcdserver::StatusResponse {
+    fn to_json(&self) -> String {
+        render(&Status::new(self))
+    }
+}
+
+#[derive(Serialize)]
+struct Alarm {
+    #[serde(rename = "memberID", skip_serializing_if = "is_zero")]
+    member_id: u64,
+    #[serde(skip_serializing_if = "is_zero")]
+    alarm: i32,
+}
+
+impl Alarm {
+    fn new(alarm: &etcdserver::AlarmMember) -> Self {
+        Alarm {
+            member_id: alarm.member_id,
+            alarm: alarm.alarm,
+        }
+    }
+}
+
+/// An alarm as `etcdctl alarm list -w json` prints it, its type as a number
+impl ToJson for etcdserver::AlarmMember {
+    fn to_json(&self) -> String {
+        render(&Alarm::new(self))
+    }
+}
+
+#[cfg(feature = "cluster")]
+#[derive(Serialize)]
+struct Health<'a> {
+    endpoint: &'a str,
+    health: bool,
+    took: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+}
+
+#[cfg(feature = "cluster")]
+impl<'a> Health<'a> {
+    fn new(health: &'a crate::EndpointHealth) -> Self {
+        Health {
+            endpoint: &health.url,
+            health: health.is_healthy(),
+            took: go_duration(health.latency.unwrap_or_default()),
+            error: health.error.as_deref(),
+        }
+    }
+}
+
+/// An endpoint's health as `etcdctl endpoint health -w json` prints it, with the time the
+/// probe took formatted like a Go duration, such as `1.534ms`
+#[cfg(feature = "cluster")]
+impl ToJson for crate::EndpointHealth {
+    fn to_json(&self) -> String {
+        render(&Health::new(self))
+    }
+}
+
+/// A member along with the health of each of its client URLs, as `{"member": ...,
+/// "endpoints": [...]}`. etcdctl has no output combining the two; the member and endpoints
+/// render as above.
+#[cfg(feature = "cluster")]
+impl ToJson for crate::MemberHealth {
+    fn to_json(&self) -> String {
+        #[derive(Serialize)]
+        struct MemberHealth<'a> {
+            member: Member<'a>,
+            endpoints: Vec<Health<'a>>,
+        }
+
+        render(&MemberHealth {
+            member: Member::new(&self.member),
+            endpoints: self.endpoints.iter().map(Health::new).collect(),
+        })
+    }
+}
+
+/// The output of `etcdctl member list -w json` for `members`
+pub fn member_list(members: &[etcdserver::Member]) -> String {
+    #[derive(Serialize)]
+    struct MemberList<'a> {
+        members: Vec<Member<'a>>,
+    }
+
+    render(&MemberList {
+        members: members.iter().map(Member::new).collect(),
+    })
+}
+
+/// The output of `etcdctl endpoint status -w json` for the status of each endpoint
+pub fn endpoint_status<S>(statuses: &[(S, etcdserver::StatusResponse)]) -> String
+where
+    S: AsRef<str>,
+{
+    #[derive(Serialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct EndpointStatus<'a> {
+        endpoint: &'a str,
+        status: Status<'a>,
+    }
+
+    let statuses: Vec<_> = statuses
+        .iter()
+        .map(|(endpoint, status)| EndpointStatus {
+            endpoint: endpoint.as_ref(),
+            status: Status::new(status),
+        })
+        .collect();
+    render(&statuses)
+}
+
+/// The output of `etcdctl alarm list -w json` for `alarms`
+pub fn alarm_list(alarms: &[etcdserver::AlarmMember]) -> String {
+    #[derive(Serialize)]
+    struct AlarmList {
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        alarms: Vec<Alarm>,
+    }
+
+    render(&AlarmList {
+        alarms: alarms.iter().map(Alarm::new).collect(),
+    })
+}
+
+/// What [`dump_json`] wrote
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dumped {
+    /// Revision the keys were read at, to watch for changes made since from the next one
+    pub revision: i64,
+    /// Number of keys written
+    pub keys: usize,
+}
+
+#[derive(Serialize)]
+struct DumpedKeyValue {
+    key: String,
+    #[serde(skip_serializing_if = "is_zero")]
+    create_revision: i64,
+    #[serde(skip_serializing_if = "is_zero")]
+    mod_revision: i64,
+    #[serde(skip_serializing_if = "is_zero")]
+    version: i64,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    value: String,
+    #[serde(skip_serializing_if = "is_zero")]
+    lease: i64,
+}
+
+/// Write every key under `prefix` to `writer` as `etcdctl get --prefix -w json` prints them:
+/// one JSON object with the header of the read and the keys, their values base64 encoded,
+/// along with their revisions and leases. An empty prefix dumps every key.
+///
+/// The keys are read in pages, every page at the revision of the first, so the dump is a
+/// consistent view of the prefix however many keys it holds, and written out as they are
+/// read. Keys are dumped relative to the client's namespace.
+pub async fn dump_json<T, P, W>(
+    client: &EtcdClient<T>,
+    prefix: P,
+    mut writer: W,
+) -> EtcdResult<Dumped>
+where
+    T: Transport,
+    P: AsRef<[u8]>,
+    W: io::Write,
+{
+    let prefix = client.namespaced_key(prefix.as_ref());
+    let context = client.error_context("dump", Some(&prefix));
+    let range_end = prefix_end(&prefix);
+    let mut key = prefix;
+    let mut revision = 0;
+    let mut keys = 0;
+    let dumped: EtcdResult<()> = async {
+        loop {
+            let request = etcdserver::RangeRequest {
+                key,
+                range_end: range_end.clone(),
+                limit: DUMP_PAGE,
+                revision,
+                serializable: client.serializable_reads(),
+                ..Default::default()
+            };
+            let response = {
+                let _in_flight = client.config.throttle(Budget::Read).await?;
+                client.kv_client().range(request).await?.into_inner()
+            };
+            client.check_cluster_id(response.header.as_ref())?;
+            if revision == 0 {
+                revision = crate::concurrency::read_revision(response.header.clone(), "range")?;
+                let header = serde_json::to_string(&Header::new(&response.header))
+                    .map_err(|e| Error::Other(e.into()))?;
+                write!(writer, "{{\"header\":{}", header)?;
+            }
+            let mut next = None;
+            for kv in response.kvs {
+                writer.write_all(if keys == 0 { b",\"kvs\":[" } else { b"," })?;
+                let dumped = DumpedKeyValue {
+                    key: base64::encode(crate::strip_namespace(&client.namespace, &kv.key)),
+                    create_revision: kv.create_revision,
+                    mod_revision: kv.mod_revision,
+                    version: kv.version,
+                    value: base64::encode(&kv.value),
+                    lease: kv.lease,
+                };
+                serde_json::to_writer(&mut writer, &dumped).map_err(|e| Error::Other(e.into()))?;
+                keys += 1;
+                next = Some(kv.key);
+            }
+            match next {
+                Some(mut last) if response.more => {
+                    last.push(0);
+                    key = last;
+                }
+                _ => break,
+            }
+        }
+        if keys > 0 {
+            write!(writer, "],\"count\":{}", keys)?;
+        }
+        writer.write_all(b"}")?;
+        writer.flush()?;
+        Ok(())
+    }
+    .await;
+    dumped.map_err(|e| context.attach(e))?;
+    Ok(Dumped { revision, keys })
+}
+
+/// Put every key of a dump in the format of `etcdctl get -w json` read from `reader`, such as
+/// one written by [`dump_json`], returning how many keys were restored. Keys are restored
+/// relative to the client's namespace.
+///
+/// Only keys and values are restored: the cluster assigns the keys new revisions, and they are
+/// not attached to the leases they had, which the cluster restored to is unlikely to hold.
+/// Keys are put in transactions of 128, so a restore which fails part of the way through
+/// leaves the keys of the transactions before in place.
+pub async fn restore_json<T, R>(client: &EtcdClient<T>, reader: R) -> EtcdResult<usize>
+where
+    T: Transport,
+    R: io::Read,
+{
+    let dump: Value =
+        serde_json::from_reader(reader).map_err(|e| format!("invalid dump: {}", e))?;
+    let kvs = match dump.get("kvs") {
+        Some(Value::Array(kvs)) => &kvs[..],
+        Some(_) => return Err("invalid dump: kvs is not a list".into()),
+        None => &[],
+    };
+    let puts = kvs
+        .iter()
+        .enumerate()
+        .map(|(i, kv)| {
+            let field = |name| match kv.get(name) {
+                Some(Value::String(encoded)) => base64::decode(encoded).map_err(|e| {
+                    format!("invalid dump: {} of key {} is not base64: {}", name, i, e)
+                }),
+                None => Ok(Vec::new()),
+                Some(_) => Err(format!(
+                    "invalid dump: {} of key {} is not a string",
+                    name, i
+                )),
+            };
+            Ok(etcdserver::RequestOp {
+                request: Some(OpRequest::RequestPut(etcdserver::PutRequest {
+                    key: field("key")?,
+                    value: field("value")?,
+                    ..Default::default()
+                })),
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let kv = client.kv();
+    for batch in puts.chunks(RESTORE_BATCH) {
+        let request = etcdserver::TxnRequest {
+            success: batch.to_vec(),
+            ..Default::default()
+        };
+        kv.txn(request).await?;
+    }
+    Ok(puts.len())
+}
+
+/// `duration` formatted as Go formats durations, the units etcdctl prints times in
+#[cfg(feature = "cluster")]
+fn go_duration(duration: Duration) -> String {
+    let nanos = duration.as_nanos();
+    let (value, scale, unit) = if nanos == 0 {
+        return "0s".to_string();
+    } else if nanos < 1_000 {
+        return format!("{}ns", nanos);
+    } else if nanos < 1_000_000 {
+        (nanos, 1_000, "µs")
+    } else if nanos < 1_000_000_000 {
+        (nanos, 1_000_000, "ms")
+    } else {
+        let seconds = duration.as_secs();
+        let mut formatted = String::new();
+        if seconds >= 3600 {
+            formatted.push_str(&format!("{}h", seconds / 3600));
+        }
+        if seconds >= 60 {
+            formatted.push_str(&format!("{}m", seconds / 60 % 60));
+        }
+        let seconds =
+            u128::from(seconds % 60) * 1_000_000_000 + u128::from(duration.subsec_nanos());
+        formatted.push_str(&decimal(seconds, 1_000_000_000));
+        formatted.push('s');
+        return formatted;
+    };
+    format!("{}{}", decimal(value, scale), unit)
+}
+
+/// `value / scale` in decimal, without trailing zeros
+#[cfg(feature = "cluster")]
+fn decimal(value: u128, scale: u128) -> String {
+    let whole = value / scale;
+    let fraction = value % scale;
+    if fraction == 0 {
+        return whole.to_string();
+    }
+    let digits = scale.to_string().len() - 1;
+    let fraction = format!("{:0width$}", fraction, width = digits);
+    format!("{}.{}", whole, fraction.trim_end_matches('0'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recording::{Exchange, Replay};
+
+    #[test]
+    fn test_rendering_members() {
+        let member = etcdserver::Member {
+            id: 10501334649042878790,
+            name: "infra1".into(),
+            peer_ur_ls: vec!["http://127.0.0.1:2380".into()],
+            client_ur_ls: vec!["http://127.0.0.1:2379".into()],
+            is_learner: false,
+        };
+        let expected = r#"{"ID":10501334649042878790,"name":"infra1","peerURLs":["http://127.0.0.1:2380"],"clientURLs":["http://127.0.0.1:2379"]}"#;
+        assert_eq!(member.to_json(), expected);
+        assert_eq!(
+            member_list(&[member]),
+            format!(r#"{{"members":[{}]}}"#, expected)
+        );
+
+        // A learner which has not started yet has no name or client URLs
+        let learner = etcdserver::Member {
+            id: 1,
+            peer_ur_ls: vec!["http://127.0.0.1:2480".into()],
+            is_learner: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            learner.to_json(),
+            r#"{"ID":1,"peerURLs":["http://127.0.0.1:2480"],"isLearner":true}"#
+        );
+    }
+
+    #[test]
+    fn test_rendering_statuses_and_alarms() {
+        let status = etcdserver::StatusResponse {
+            header: Some(etcdserver::ResponseHeader {
+                cluster_id: 17237436991929493444,
+                member_id: 9372538179322589801,
+                revision: 5,
+                raft_term: 2,
+            }),
+            version: "3.5.17".into(),
+            db_size: 20480,
+            leader: 9372538179322589801,
+            raft_index: 8,
+            raft_term: 2,
+            raft_applied_index: 8,
+            db_size_in_use: 16384,
+            ..Default::default()
+        };
+        assert_eq!(
+            endpoint_status(&[("127.0.0.1:2379", status)]),
+            concat!(
+                r#"[{"Endpoint":"127.0.0.1:2379","Status":{"header":{"cluster_id":17237436991929493444,"#,
+                r#""member_id":9372538179322589801,"revision":5,"raft_term":2},"version":"3.5.17","#,
+                r#""dbSize":20480,"leader":9372538179322589801,"raftIndex":8,"raftTerm":2,"#,
+                r#""raftAppliedIndex":8,"dbSizeInUse":16384}}]"#
+            )
+        );
+
+        let alarm = etcdserver::AlarmMember {
+            member_id: 9372538179322589801,
+            alarm: crate::AlarmType::NoSpace.into(),
+        };
+        assert_eq!(
+            alarm_list(&[alarm]),
+            r#"{"alarms":[{"memberID":9372538179322589801,"alarm":1}]}"#
+        );
+        assert_eq!(alarm_list(&[]), "{}");
+    }
+
+    #[cfg(feature = "cluster")]
+    #[test]
+    fn test_rendering_health() {
+        let healthy = crate::EndpointHealth {
+            url: "http://127.0.0.1:2379".into(),
+            latency: Some(Duration::from_micros(1534)),
+            error: None,
+        };
+        assert_eq!(
+            healthy.to_json(),
+            r#"{"endpoint":"http://127.0.0.1:2379","health":true,"took":"1.534ms"}"#
+        );
+        let unhealthy = crate::EndpointHealth {
+            url: "http://127.0.0.1:2479".into(),
+            latency: None,
+            error: Some("connection refused".into()),
+        };
+        assert_eq!(
+            vec![unhealthy].to_json(),
+            r#"[{"endpoint":"http://127.0.0.1:2479","health":false,"took":"0s","error":"connection refused"}]"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dumping_and_restoring_keys() {
+        let header = Some(etcdserver::ResponseHeader {
+            cluster_id: 1,
+            member_id: 2,
+            revision: 7,
+            raft_term: 3,
+        });
+        let kv = |key: &[u8], value: &[u8], revision| crate::mvccpb::KeyValue {
+            key: key.to_vec(),
+            create_revision: revision,
+            mod_revision: revision,
+            version: 1,
+            value: value.to_vec(),
+            lease: 0,
+        };
+        let replay = Replay::new(vec![
+            Exchange::answered(
+                "/etcdserverpb.KV/Range",
+                etcdserver::RangeResponse {
+                    header: header.clone(),
+                    kvs: vec![kv(b"foo/a", b"1", 5)],
+                    more: true,
+                    count: 2,
+                },
+            ),
+            Exchange::answered(
+                "/etcdserverpb.KV/Range",
+                etcdserver::RangeResponse {
+                    header: header.clone(),
+                    kvs: vec![kv(b"foo/b", b"", 6)],
+                    more: false,
+                    count: 1,
+                },
+            ),
+            Exchange::answered(
+                "/etcdserverpb.KV/Txn",
+                etcdserver::TxnResponse {
+                    header,
+                    succeeded: true,
+                    ..Default::default()
+                },
+            ),
+        ]);
+        let client = EtcdClient::from_transport(replay.clone());
+
+        let mut dump = Vec::new();
+        let dumped = dump_json(&client, "foo/", &mut dump).await.unwrap();
+        assert_eq!(
+            dumped,
+            Dumped {
+                revision: 7,
+                keys: 2
+            }
+        );
+        assert_eq!(
+            String::from_utf8(dump.clone()).unwrap(),
+            concat!(
+                r#"{"header":{"cluster_id":1,"member_id":2,"revision":7,"raft_term":3},"kvs":["#,
+                r#"{"key":"Zm9vL2E=","create_revision":5,"mod_revision":5,"version":1,"value":"MQ=="},"#,
+                r#"{"key":"Zm9vL2I=","create_revision":6,"mod_revision":6,"version":1}],"count":2}"#
+            )
+        );
+
+        assert_eq!(restore_json(&client, &dump[..]).await.unwrap(), 2);
+        assert_eq!(replay.remaining(), 0);
+
+        // Nothing is put unless the whole dump is valid
+        let invalid = r#"{"kvs":[{"key":"Zm9v"},{"key":"not base64!"}]}"#;
+        let err = restore_json(&client, invalid.as_bytes()).await.unwrap_err();
+        assert!(
+            err.to_string().contains("key of key 1 is not base64"),
+            "{}",
+            err
+        );
+        assert_eq!(restore_json(&client, &b"{}"[..]).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_restoring_a_dump_elsewhere() {
+        let client = crate::testing::namespace().await.unwrap();
+        let kv = client.kv();
+        for i in 0..300 {
+            let key = format!("test_restoring_a_dump_elsewhere/{:03}", i);
+            kv.put(key, i.to_string()).await.unwrap();
+        }
+        let mut dump = Vec::new();
+        let dumped = dump_json(&client, "test_restoring_a_dump_elsewhere/", &mut dump)
+            .await
+            .unwrap();
+        assert_eq!(dumped.keys, 300);
+
+        let other = crate::testing::namespace().await.unwrap();
+        assert_eq!(restore_json(&other, &dump[..]).await.unwrap(), 300);
+        let restored = other.kv().get("test_restoring_a_dump_elsewhere/042").await;
+        assert_eq!(restored.unwrap().as_deref(), Some("42"));
+    }
+
+    #[cfg(feature = "cluster")]
+    #[test]
+    fn test_go_durations() {
+        assert_eq!(go_duration(Duration::from_nanos(850)), "850ns");
+        assert_eq!(go_duration(Duration::from_nanos(12_340)), "12.34µs");
+        assert_eq!(go_duration(Duration::from_millis(20)), "20ms");
+        assert_eq!(go_duration(Duration::from_millis(2500)), "2.5s");
+        assert_eq!(go_duration(Duration::from_secs(3723)), "1h2m3s");
+        assert_eq!(go_duration(Duration::from_secs(120)), "2m0s");
+    }
+}
@@ -0,0 +1,1616 @@
+//! The KV service: reading, writing and compacting keys.
+
+use crate::redact::Key;
+use crate::response::{
+    ConsistencyToken, DeleteResponse, GetResponse, KeyValue, PutResponse, TxnResponse,
+};
+use crate::{
+    client, etcdserver, hedge_request, metadata_map, runtime, with_metadata, with_retries,
+    with_timeout, Budget, CallContext, CompareResult, CompareTarget, Diagnostic, Error,
+    ErrorContext, EtcdClient, EtcdResult, InFlight, OpRequest, ReadConsistency, Retry,
+    TrafficClass, Transport, TRAFFIC_CLASS_METADATA,
+};
+use bytes::Bytes;
+use futures::channel::oneshot;
+use futures::future::{self, BoxFuture, Either};
+use futures::stream::{BoxStream, FuturesUnordered, Stream, StreamExt};
+use futures::Sink;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::Mutex as StdMutex;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+/// Reads and writes of keys, see [`EtcdClient::kv`]. Like the other service handles it owns a
+/// clone of its client, so it can be stored in structs and moved into spawned tasks.
+pub struct Kv<T> {
+    pub(crate) client: EtcdClient<T>,
+}
+
+impl<T> fmt::Debug for Kv<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Kv").field("client", &self.client).finish()
+    }
+}
+
+impl<T: Transport> Kv<T> {
+    /// The keys from `start` up to `end`, or only the key `start` if there is no end
+    pub fn range<K>(&self, start: K, end: Option<K>) -> Range<T>
+    where
+        K: AsRef<[u8]>,
+    {
+        Range {
+            start: start.as_ref().to_vec(),
+            end: end.map(|end| end.as_ref().to_vec()),
+            timeout: None,
+            deadline: None,
+            metadata: Vec::new(),
+            consistency: None,
+            min_revision: None,
+            traffic_class: None,
+            client: self.client.clone(),
+        }
+    }
+
+    /// Put `value` at `key`
+    pub async fn put<K, V>(&self, key: K, value: V) -> EtcdResult<PutResponse>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        self.range(key, None).put(value).await
+    }
+
+    /// Put `value` at `key`, see [`PutOptions`]
+    pub async fn put_with_options<K, V>(
+        &self,
+        key: K,
+        value: V,
+        options: PutOptions,
+    ) -> EtcdResult<PutResponse>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        self.range(key, None).put_with_options(value, options).await
+    }
+
+    /// Value of `key`, if it exists
+    pub async fn get<K>(&self, key: K) -> EtcdResult<Option<String>>
+    where
+        K: AsRef<[u8]>,
+    {
+        let key = key.as_ref();
+        let response = self.range(key, None).get().await?;
+        let kv = response.into_iter().find(|kv| kv.key() == key);
+        kv.map(KeyValue::into_value_string).transpose()
+    }
+
+    /// Value of `key`, if it exists, taken out of the response without copying it
+    pub async fn get_bytes<K>(&self, key: K) -> EtcdResult<Option<Bytes>>
+    where
+        K: AsRef<[u8]>,
+    {
+        let key = key.as_ref();
+        let response = self.range(key, None).get().await?;
+        let kv = response.into_iter().find(|kv| kv.key() == key);
+        Ok(kv.map(KeyValue::into_value))
+    }
+
+    /// Delete `key`
+    pub async fn delete<K>(&self, key: K) -> EtcdResult<DeleteResponse>
+    where
+        K: AsRef<[u8]>,
+    {
+        self.range(key, None).delete().await
+    }
+
+    /// Read the keys of `request`, with whichever of the server's range options it sets, such
+    /// as sorting, limits and revision bounds. Its keys are inside the client's namespace.
+    /// Reads are linearizable unless the request asks otherwise.
+    pub(crate) async fn range_request(
+        &self,
+        mut request: etcdserver::RangeRequest,
+    ) -> EtcdResult<GetResponse> {
+        let context = self.client.error_context("get", Some(&request.key));
+        request.key = self.client.namespaced_key(&request.key);
+        request.range_end = self.client.namespaced_range_end(&request.range_end);
+        let rpc = || {
+            let (mut kv_client, request) = (self.client.kv_client(), request.clone());
+            async move { kv_client.range(request).await }
+        };
+        let namespace = &self.client.namespace;
+        self.client
+            .call(
+                "kv.get",
+                context,
+                Budget::Read,
+                Retry::Idempotent,
+                rpc,
+                |response| GetResponse::from_raw(response, namespace),
+            )
+            .await
+    }
+
+    /// Apply the transaction `request` atomically: its success operations if every comparison
+    /// holds, otherwise its failure ones. The keys it compares and operates on are inside the
+    /// client's namespace.
+    pub async fn txn(&self, request: etcdserver::TxnRequest) -> EtcdResult<TxnResponse> {
+        let request = namespaced_txn(&self.client, request);
+        let mut context = self.client.error_context("txn", None);
+        let operation = self.client.operation("kv.txn", &context);
+        let call = async {
+            if let Some(schema) = &self.client.config.key_schema {
+                schema.check_txn(&request)?;
+            }
+            let _in_flight = self.client.config.throttle(Budget::Write).await?;
+            let response = self.client.kv_client().txn(request).await?.into_inner();
+            context.header = response.header.clone();
+            self.client.check_cluster_id(response.header.as_ref())?;
+            TxnResponse::from_raw(response, &self.client.namespace)
+        };
+        let result: EtcdResult<_> = operation.run(call).await;
+        operation.finish(&context, &result);
+        result.map_err(|e| context.attach(e))
+    }
+
+    /// Compact the event history up to `revision`. With `physical` set, the call only returns
+    /// once the compacted entries have been removed from the backend database.
+    pub async fn compact(&self, revision: i64, physical: bool) -> EtcdResult<()> {
+        let request = etcdserver::CompactionRequest { revision, physical };
+        let context = self.client.error_context("compact", None);
+        let rpc = || {
+            let (mut kv_client, request) = (self.client.kv_client(), request.clone());
+            async move { kv_client.compact(request).await }
+        };
+        self.client
+            .call(
+                "kv.compact",
+                context,
+                Budget::Write,
+                Retry::Never,
+                rpc,
+                |_| Ok(()),
+            )
+            .await
+    }
+
+    /// Spawn a background task which checks the store revision every `interval` and compacts
+    /// according to `policy`.
+    ///
+    /// This is for clusters where server side auto-compaction cannot be configured. Failures
+    /// are reported as [`Diagnostic::CompactionFailed`](crate::Diagnostic::CompactionFailed).
+    pub fn auto_compact(&self, policy: CompactionPolicy, interval: Duration) -> AutoCompactor {
+        let (tx, rx) = oneshot::channel();
+        let mut rx = future::select(rx, self.client.config.shutdown.closed());
+        let mut kv_client = self.client.kv_client();
+        let clock = self.client.config.clock.clone();
+        let diagnostics = self.client.config.diagnostics.clone();
+
+        runtime::spawn(async move {
+            let mut state = CompactionState::new(policy);
+            let mut ticks = clock.interval(interval);
+
+            loop {
+                let tick = ticks.next();
+                futures::pin_mut!(tick);
+                if let Either::Right(_) = future::select(tick, &mut rx).await {
+                    // Stopped, the compactor was dropped or the client was closed
+                    break;
+                }
+
+                let revision = match current_revision(&mut kv_client).await {
+                    Ok(revision) => revision,
+                    Err(status) => {
+                        diagnostics.emit(|| Diagnostic::CompactionFailed {
+                            revision: None,
+                            error: format!("{:?}: {}", status.code(), status.message()),
+                        });
+                        continue;
+                    }
+                };
+
+                if let Some(target) = state.observe(clock.now(), revision) {
+                    let request = etcdserver::CompactionRequest {
+                        revision: target,
+                        physical: false,
+                    };
+                    // On failure the same target is retried on the next tick
+                    match kv_client.compact(request).await {
+                        Ok(_) => state.compacted(target),
+                        Err(status) => diagnostics.emit(|| Diagnostic::CompactionFailed {
+                            revision: Some(target),
+                            error: format!("{:?}: {}", status.code(), status.message()),
+                        }),
+                    }
+                }
+            }
+        });
+
+        AutoCompactor { _stop: tx }
+    }
+
+    /// Put each of the `items`, key and value, reporting the outcome of each rather than
+    /// stopping at the first failure. The puts are separate requests, so the batch is not
+    /// atomic: some items may be put while others fail.
+    pub async fn put_many<I, K, V>(&self, items: I) -> BatchOutcome<()>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: AsRef<[u8]>,
+    {
+        let mut outcomes = Vec::new();
+        for (key, value) in items {
+            let key = key.into();
+            let outcome = self.range(key.as_str(), None).put(value).await;
+            outcomes.push((key, outcome.map(drop)));
+        }
+        BatchOutcome { outcomes }
+    }
+
+    /// Get the value of each of `keys`, reporting the outcome of each rather than stopping at
+    /// the first failure. Keys which do not exist have no value.
+    pub async fn get_many<I, K>(&self, keys: I) -> BatchOutcome<Option<String>>
+    where
+        I: IntoIterator<Item = K>,
+        K: Into<String>,
+    {
+        let mut outcomes = Vec::new();
+        for key in keys {
+            let key = key.into();
+            let outcome = self.range(key.as_str(), None).get().await;
+            let outcome = outcome.and_then(|response| {
+                let kv = response.into_iter().find(|kv| kv.key() == key.as_bytes());
+                kv.map(KeyValue::into_value_string).transpose()
+            });
+            outcomes.push((key, outcome));
+        }
+        BatchOutcome { outcomes }
+    }
+
+    /// A sink putting the keys and values sent into it, batched into transactions as
+    /// `options` say, for pipelines writing many keys. See [`PutSink`].
+    pub fn put_sink<K, V>(&self, options: PutSinkOptions) -> PutSink<T, K, V>
+    where
+        K: Into<Vec<u8>>,
+        V: Into<Vec<u8>>,
+    {
+        PutSink {
+            kv: self.client.kv(),
+            options,
+            batch: Vec::new(),
+            deadline: None,
+            in_flight: FuturesUnordered::new(),
+            error: None,
+            written: 0,
+            _items: PhantomData,
+        }
+    }
+
+    /// Read each of `ranges`, each a start and an optional end as for [`range`](Self::range),
+    /// with up to `max_concurrency` reads in flight at once over the client's connections.
+    ///
+    /// The reads are yielded as they complete rather than in the order given, each with the
+    /// index of its range in `ranges`. A read failing does not stop the others.
+    pub fn get_ranges<I, K>(&self, ranges: I, max_concurrency: usize) -> RangeReads
+    where
+        I: IntoIterator<Item = (K, Option<K>)>,
+        K: AsRef<[u8]>,
+    {
+        let ranges: Vec<_> = ranges
+            .into_iter()
+            .map(|(start, end)| self.range(start, end))
+            .collect();
+        let reads = futures::stream::iter(ranges.into_iter().enumerate())
+            .map(|(index, range)| async move { (index, range.get().await) })
+            .buffer_unordered(max_concurrency.max(1));
+        RangeReads {
+            reads: Box::pin(reads),
+        }
+    }
+}
+
+/// The reads of [`Kv::get_ranges`], as they complete, each with the index of its range
+pub struct RangeReads {
+    reads: BoxStream<'static, (usize, EtcdResult<GetResponse>)>,
+}
+
+impl RangeReads {
+    /// The next read to complete, or `None` once every range has been read
+    pub async fn next(&mut self) -> Option<(usize, EtcdResult<GetResponse>)> {
+        StreamExt::next(self).await
+    }
+}
+
+impl Stream for RangeReads {
+    type Item = (usize, EtcdResult<GetResponse>);
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.reads.as_mut().poll_next(cx)
+    }
+}
+
+impl fmt::Debug for RangeReads {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RangeReads").finish_non_exhaustive()
+    }
+}
+
+/// How a [`PutSink`] batches its puts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PutSinkOptions {
+    /// Most puts in each transaction. 128 by default, the most etcd allows unless configured
+    /// otherwise with `--max-txn-ops`.
+    pub max_batch: usize,
+    /// Longest a put waits for its batch to fill before the batch is written anyway. 10ms by
+    /// default.
+    pub max_delay: Duration,
+    /// Most transactions in flight at once. 1 by default, which writes batches in the order
+    /// they were made. With more, a later batch can be applied before an earlier one, so a key
+    /// sent twice may be left with its first value.
+    pub max_in_flight: usize,
+}
+
+impl Default for PutSinkOptions {
+    fn default() -> Self {
+        Self {
+            max_batch: 128,
+            max_delay: Duration::from_millis(10),
+            max_in_flight: 1,
+        }
+    }
+}
+
+/// A [`Sink`] of keys and values, putting them in batched transactions, see [`Kv::put_sink`].
+///
+/// The sink is ready for another item while its batch has room. Once the batch is full, or
+/// its first put has waited [`max_delay`](PutSinkOptions::max_delay), it is sent as a
+/// transaction, and while [`max_in_flight`](PutSinkOptions::max_in_flight) transactions are
+/// already in flight the sink is not ready, pushing back on the producer until one completes.
+/// Flushing or closing the sink writes the batch and waits for every transaction in flight.
+///
+/// A transaction which fails loses its batch's puts, and its error is returned by the next
+/// call on the sink, which carries on with the items sent after it. Keys are put in the
+/// client's namespace.
+///
+/// ```no_run
+/// # async fn run(client: etcd3::EtcdClient<tonic::transport::Channel>) -> etcd3::EtcdResult<()> {
+/// use futures::{stream, SinkExt, StreamExt};
+///
+/// let mut sink = client.kv().put_sink(Default::default());
+/// let items = (0..10_000).map(|i| Ok((format!("events/{:05}", i), i.to_string())));
+/// sink.send_all(&mut stream::iter(items)).await?;
+/// sink.close().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct PutSink<T, K, V> {
+    kv: Kv<T>,
+    options: PutSinkOptions,
+    /// Puts of the batch being filled
+    batch: Vec<etcdserver::RequestOp>,
+    /// When the batch is due to be written, once it has a put
+    deadline: Option<runtime::Delay>,
+    in_flight: FuturesUnordered<BoxFuture<'static, EtcdResult<usize>>>,
+    /// Failure of a transaction, to return from the next call
+    error: Option<Error>,
+    written: u64,
+    _items: PhantomData<fn(K, V)>,
+}
+
+impl<T, K, V> fmt::Debug for PutSink<T, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PutSink")
+            .field("options", &self.options)
+            .field("batched", &self.batch.len())
+            .field("in_flight", &self.in_flight.len())
+            .field("written", &self.written)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: Transport, K, V> PutSink<T, K, V> {
+    /// Number of puts written so far
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    /// Take the transactions which completed, keeping the first failure to return
+    fn poll_completed(&mut self, cx: &mut Context<'_>) {
+        while let Poll::Ready(Some(result)) = self.in_flight.poll_next_unpin(cx) {
+            match result {
+                Ok(puts) => self.written += puts as u64,
+                Err(e) => {
+                    if self.error.is_none() {
+                        self.error = Some(e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Send the batch as a transaction once there is room for another in flight, returning
+    /// whether it was sent
+    fn poll_send_batch(&mut self, cx: &mut Context<'_>) -> bool {
+        self.poll_completed(cx);
+        if self.in_flight.len() >= self.options.max_in_flight.max(1) {
+            return false;
+        }
+        let batch = std::mem::take(&mut self.batch);
+        self.deadline = None;
+        let puts = batch.len();
+        let kv = self.kv.client.kv();
+        let request = etcdserver::TxnRequest {
+            success: batch,
+            ..Default::default()
+        };
+        let txn = async move { kv.txn(request).await.map(|_| puts) };
+        self.in_flight.push(Box::pin(txn));
+        // Polled once, so the sink is woken when the transaction completes
+        self.poll_completed(cx);
+        true
+    }
+
+    /// The failure of a transaction, if one failed since the last call
+    fn take_error(&mut self) -> Poll<EtcdResult<()>> {
+        match self.error.take() {
+            Some(e) => Poll::Ready(Err(e)),
+            None => Poll::Ready(Ok(())),
+        }
+    }
+}
+
+impl<T, K, V> Sink<(K, V)> for PutSink<T, K, V>
+where
+    T: Transport,
+    K: Into<Vec<u8>>,
+    V: Into<Vec<u8>>,
+{
+    type Error = Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<EtcdResult<()>> {
+        let this = &mut *self;
+        this.poll_completed(cx);
+        if this.error.is_some() {
+            return this.take_error();
+        }
+        let due = match &mut this.deadline {
+            Some(deadline) => deadline.as_mut().poll(cx).is_ready(),
+            None => false,
+        };
+        if (due || this.batch.len() >= this.options.max_batch.max(1)) && !this.poll_send_batch(cx) {
+            return Poll::Pending;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, (key, value): (K, V)) -> EtcdResult<()> {
+        let put = etcdserver::PutRequest {
+            key: key.into(),
+            value: value.into(),
+            ..Default::default()
+        };
+        if self.batch.is_empty() {
+            self.deadline = Some(runtime::delay_for(self.options.max_delay));
+        }
+        self.batch.push(etcdserver::RequestOp {
+            request: Some(OpRequest::RequestPut(put)),
+        });
+        Ok(())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<EtcdResult<()>> {
+        let this = &mut *self;
+        if !this.batch.is_empty() && !this.poll_send_batch(cx) {
+            return Poll::Pending;
+        }
+        this.poll_completed(cx);
+        if !this.in_flight.is_empty() {
+            return Poll::Pending;
+        }
+        this.take_error()
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<EtcdResult<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+/// Comparison of `target` of `key` in a transaction, see [`Kv::txn`]
+fn compare<K>(
+    key: K,
+    result: CompareResult,
+    target: CompareTarget,
+    target_union: etcdserver::compare::TargetUnion,
+) -> etcdserver::Compare
+where
+    K: AsRef<[u8]>,
+{
+    etcdserver::Compare {
+        result: result.into(),
+        target: target.into(),
+        key: key.as_ref().to_vec(),
+        target_union: Some(target_union),
+        ..Default::default()
+    }
+}
+
+/// Compare the value of `key` with `value`
+pub fn compare_value<K, V>(key: K, result: CompareResult, value: V) -> etcdserver::Compare
+where
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+{
+    let value = etcdserver::compare::TargetUnion::Value(value.as_ref().to_vec());
+    compare(key, result, CompareTarget::Value, value)
+}
+
+/// Compare the version of `key`, 0 if it does not exist, with `version`
+pub fn compare_version<K>(key: K, result: CompareResult, version: i64) -> etcdserver::Compare
+where
+    K: AsRef<[u8]>,
+{
+    let version = etcdserver::compare::TargetUnion::Version(version);
+    compare(key, result, CompareTarget::Version, version)
+}
+
+/// Compare the revision `key` was created at with `revision`
+pub fn compare_create_revision<K>(
+    key: K,
+    result: CompareResult,
+    revision: i64,
+) -> etcdserver::Compare
+where
+    K: AsRef<[u8]>,
+{
+    let revision = etcdserver::compare::TargetUnion::CreateRevision(revision);
+    compare(key, result, CompareTarget::Create, revision)
+}
+
+/// Compare the revision `key` was last modified at with `revision`
+pub fn compare_mod_revision<K>(key: K, result: CompareResult, revision: i64) -> etcdserver::Compare
+where
+    K: AsRef<[u8]>,
+{
+    let revision = etcdserver::compare::TargetUnion::ModRevision(revision);
+    compare(key, result, CompareTarget::Mod, revision)
+}
+
+/// `request` with the keys it compares and operates on inside `client`'s namespace
+fn namespaced_txn<T: Transport>(
+    client: &EtcdClient<T>,
+    mut request: etcdserver::TxnRequest,
+) -> etcdserver::TxnRequest {
+    use etcdserver::request_op::Request;
+
+    if client.namespace.is_empty() {
+        return request;
+    }
+    for compare in &mut request.compare {
+        compare.key = client.namespaced_key(&compare.key);
+        compare.range_end = client.namespaced_range_end(&compare.range_end);
+    }
+    for op in request.success.iter_mut().chain(request.failure.iter_mut()) {
+        match &mut op.request {
+            Some(Request::RequestRange(range)) => {
+                range.key = client.namespaced_key(&range.key);
+                range.range_end = client.namespaced_range_end(&range.range_end);
+            }
+            Some(Request::RequestPut(put)) => put.key = client.namespaced_key(&put.key),
+            Some(Request::RequestDeleteRange(delete)) => {
+                delete.key = client.namespaced_key(&delete.key);
+                delete.range_end = client.namespaced_range_end(&delete.range_end);
+            }
+            Some(Request::RequestTxn(txn)) => *txn = namespaced_txn(client, txn.clone()),
+            None => {}
+        }
+    }
+    request
+}
+
+/// Options for putting a key
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PutOptions {
+    /// Return the key as it was before the put, see [`PutResponse::prev_kv`]. Off by default,
+    /// as it costs the server a read and grows the response.
+    pub prev_kv: bool,
+    /// Attach the key to this lease, so it is deleted when the lease expires. 0 for no lease.
+    pub lease: i64,
+}
+
+/// Range of keys. Owns a clone of its client, so it can be kept across awaits alongside other
+/// operations and moved into spawned tasks.
+pub struct Range<T> {
+    start: Vec<u8>,
+    end: Option<Vec<u8>>,
+    timeout: Option<Duration>,
+    /// Deadline of the call the operation is made for, see [`CallContext`]
+    deadline: Option<Instant>,
+    pub(crate) metadata: Vec<(String, String)>,
+    /// How the range is read, if not as the client reads by default
+    consistency: Option<ReadConsistency>,
+    /// Oldest revision a serializable read may be served at, see [`Range::min_revision`]
+    min_revision: Option<i64>,
+    /// Class of the operation, if not the client's class
+    traffic_class: Option<TrafficClass>,
+    client: EtcdClient<T>,
+}
+
+impl<T> fmt::Debug for Range<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Range")
+            .field("start", &Key(&self.start))
+            .field("end", &self.end.as_deref().map(Key))
+            .field("timeout", &self.timeout)
+            .field("deadline", &self.deadline)
+            .field("consistency", &self.consistency)
+            .field("min_revision", &self.min_revision)
+            .field("traffic_class", &self.traffic_class)
+            .field("client", &self.client)
+            .finish()
+    }
+}
+
+impl<T: Transport> Range<T> {
+    /// Give up on the operation after `timeout`. The client's default timeout still applies
+    /// if it is shorter.
+    pub fn timeout(self, timeout: Duration) -> Self {
+        Self {
+            timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Send the gRPC metadata `key: value` with the operation, in place of any metadata of the
+    /// same name configured for the client
+    pub fn metadata<K, V>(mut self, key: K, value: V) -> Self
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.metadata.push((key.into(), value.into()));
+        self
+    }
+
+    /// Make the operation in `context`: give up at its deadline, unless the timeout is
+    /// shorter, and send its metadata and token. Metadata given with
+    /// [`metadata`](Self::metadata) afterwards takes precedence.
+    pub fn context(mut self, context: &CallContext) -> Self {
+        self.deadline = match (self.deadline, context.deadline) {
+            (Some(deadline), Some(other)) => Some(deadline.min(other)),
+            (deadline, other) => deadline.or(other),
+        };
+        self.metadata.extend(context.metadata.iter().cloned());
+        if let Some(token) = &context.token {
+            self.metadata.push(("token".to_string(), token.clone()));
+        }
+        match context.traffic_class {
+            Some(class) => self.traffic_class(class),
+            None => self,
+        }
+    }
+
+    /// Treat the operation as `class`, in place of the client's class, see
+    /// [`EtcdClientBuilder::traffic_class`]
+    pub fn traffic_class(mut self, class: TrafficClass) -> Self {
+        self.traffic_class = Some(class);
+        self.metadata.push((
+            TRAFFIC_CLASS_METADATA.to_string(),
+            class.as_str().to_string(),
+        ));
+        self
+    }
+
+    /// Wait until the operation may be sent under the client's limits, as its traffic class
+    async fn throttle(&self, budget: Budget) -> EtcdResult<InFlight> {
+        let class = self
+            .traffic_class
+            .unwrap_or(self.client.config.traffic_class);
+        self.client.config.throttle_as(budget, class).await
+    }
+
+    /// Time the operation may take: its timeout, or the time left until its deadline if that
+    /// is sooner
+    pub(crate) fn budget(&self) -> Option<Duration> {
+        let remaining = self
+            .deadline
+            .map(|deadline| deadline.saturating_duration_since(self.client.config.clock.now()));
+        match (self.timeout, remaining) {
+            (Some(timeout), Some(remaining)) => Some(timeout.min(remaining)),
+            (timeout, remaining) => timeout.or(remaining),
+        }
+    }
+
+    /// Read from whichever member serves the request, rather than confirming with the
+    /// leader that the member is up to date. Serializable reads are faster and keep working
+    /// without a leader, but may return stale values. They are hedged if the client is
+    /// configured to, see [`EtcdClientBuilder::hedge_reads`].
+    pub fn serializable(self) -> Self {
+        self.consistency(ReadConsistency::Serializable)
+    }
+
+    /// Confirm with the leader that the member serving the read is up to date, even if the
+    /// client makes serializable reads by default, see
+    /// [`EtcdClientBuilder::read_consistency`]
+    pub fn linearizable(self) -> Self {
+        self.consistency(ReadConsistency::Linearizable)
+    }
+
+    /// Read `consistency`, rather than as the client reads by default
+    pub fn consistency(self, consistency: ReadConsistency) -> Self {
+        Self {
+            consistency: Some(consistency),
+            ..self
+        }
+    }
+
+    /// Whether the range is read serializably
+    fn is_serializable(&self) -> bool {
+        match self.consistency {
+            Some(consistency) => consistency == ReadConsistency::Serializable,
+            None => self.client.serializable_reads(),
+        }
+    }
+
+    /// Read the keys as of `token` or later, so a serializable read sees the write the token
+    /// came from. A serializable read served by a member which has not caught up with it yet
+    /// is made again as a linearizable read, which always has. Linearizable reads are as of
+    /// the latest revision anyway.
+    pub fn min_revision(self, token: ConsistencyToken) -> Self {
+        Self {
+            min_revision: Some(token.revision()),
+            ..self
+        }
+    }
+
+    /// End of the range inside the client's namespace, empty for a single key
+    fn range_end(&self) -> Vec<u8> {
+        let end = self.end.as_deref().unwrap_or_default();
+        self.client.namespaced_range_end(end)
+    }
+
+    /// Context to attach to errors from `operation` on the range
+    fn error_context(&self, operation: &'static str) -> ErrorContext {
+        self.client.error_context(operation, Some(&self.start))
+    }
+
+    pub async fn put<V>(&self, value: V) -> EtcdResult<PutResponse>
+    where
+        V: AsRef<[u8]>,
+    {
+        self.put_with_options(value, PutOptions::default()).await
+    }
+
+    /// Put `value`, see [`PutOptions`]
+    pub async fn put_with_options<V>(
+        &self,
+        value: V,
+        options: PutOptions,
+    ) -> EtcdResult<PutResponse>
+    where
+        V: AsRef<[u8]>,
+    {
+        let request = etcdserver::PutRequest {
+            key: self.client.namespaced_key(&self.start),
+            value: value.as_ref().to_vec(),
+            lease: options.lease,
+            prev_kv: options.prev_kv,
+            ..Default::default()
+        };
+
+        let mut context = self.error_context("put");
+        let operation = self.client.operation("kv.put", &context);
+        let call = async {
+            if let Some(schema) = &self.client.config.key_schema {
+                schema.check(&request.key, &request.value)?;
+            }
+            let metadata = metadata_map(&self.metadata)?;
+            let _in_flight = self.throttle(Budget::Write).await?;
+            let kv_client = self.client.kv_client();
+            let retries = self.client.config.retries(context.operation);
+            let mut attempts = 0;
+            let rpc = with_retries(retries, false, &mut attempts, || {
+                let mut kv_client = kv_client.clone();
+                let request = with_metadata(request.clone(), &metadata);
+                async move { kv_client.put(request).await }
+            });
+            let response = with_timeout(&self.client.config.clock, self.budget(), rpc).await;
+            context.attempt = Some(attempts);
+            let response = response?.into_inner();
+            context.header = response.header.clone();
+            self.client.check_cluster_id(response.header.as_ref())?;
+            PutResponse::from_raw(response, &self.client.namespace)
+        };
+        let result: EtcdResult<_> = operation.run(call).await;
+        operation.finish(&context, &result);
+        result.map_err(|e| context.attach(e))
+    }
+
+    pub async fn get(&self) -> EtcdResult<GetResponse> {
+        let serializable = self.is_serializable();
+        let response = self.coalesced_read(serializable).await?;
+        match self.min_revision {
+            Some(min_revision) if serializable && response.revision() < min_revision => {
+                self.coalesced_read(false).await
+            }
+            _ => Ok(response),
+        }
+    }
+
+    /// Read the range, sharing the response of the same read in flight if the client
+    /// coalesces reads, see [`EtcdClientBuilder::coalesce_reads`]
+    async fn coalesced_read(&self, serializable: bool) -> EtcdResult<GetResponse> {
+        let coalescer = match &self.client.config.coalescer {
+            Some(coalescer) => coalescer,
+            None => return self.read(serializable).await,
+        };
+        let mut request = Vec::new();
+        // Vectors grow to fit whatever is encoded
+        let _ = prost::Message::encode(&self.range_request(serializable), &mut request);
+        let read = CoalescedRead {
+            request,
+            namespace: self.client.namespace.to_vec(),
+            metadata: self.metadata.clone(),
+        };
+        match coalescer.join(read) {
+            Joined::Leading(lead) => {
+                let response = self.read(serializable).await;
+                lead.finish(response.as_ref().ok());
+                response
+            }
+            Joined::Waiting(shared) => {
+                let shared = async { Ok::<_, tonic::Status>(shared.await) };
+                match with_timeout(&self.client.config.clock, self.budget(), shared).await? {
+                    Ok(response) => Ok(response),
+                    // The read failed or was abandoned, so this one is made by itself
+                    Err(_) => self.read(serializable).await,
+                }
+            }
+        }
+    }
+
+    fn range_request(&self, serializable: bool) -> etcdserver::RangeRequest {
+        etcdserver::RangeRequest {
+            key: self.client.namespaced_key(&self.start),
+            range_end: self.range_end(),
+            serializable,
+            ..Default::default()
+        }
+    }
+
+    /// Read the range, from whichever member serves the request if `serializable`
+    async fn read(&self, serializable: bool) -> EtcdResult<GetResponse> {
+        let request = self.range_request(serializable);
+        let mut context = self.error_context("get");
+        let operation = self.client.operation("kv.get", &context);
+        let call = async {
+            let metadata = metadata_map(&self.metadata)?;
+            let _in_flight = self.throttle(Budget::Read).await?;
+            let kv_client = self.client.kv_client();
+            let hedge = if serializable {
+                self.client.hedge_clients()
+            } else {
+                None
+            };
+            let retries = self.client.config.retries(context.operation);
+            let mut attempts = 0;
+            let rpc = with_retries(retries, true, &mut attempts, || {
+                let (mut kv_client, hedge) = (kv_client.clone(), hedge.clone());
+                let (request, metadata) = (request.clone(), metadata.clone());
+                async move {
+                    match hedge {
+                        Some((delay, mut first, mut second)) => {
+                            let primary = first.range(with_metadata(request.clone(), &metadata));
+                            let hedged = second.range(with_metadata(request, &metadata));
+                            hedge_request(delay, primary, hedged).await
+                        }
+                        None => kv_client.range(with_metadata(request, &metadata)).await,
+                    }
+                }
+            });
+            let response = with_timeout(&self.client.config.clock, self.budget(), rpc).await;
+            context.attempt = Some(attempts);
+            let range_response = response?.into_inner();
+            context.header = range_response.header.clone();
+            self.client
+                .check_cluster_id(range_response.header.as_ref())?;
+            GetResponse::from_raw(range_response, &self.client.namespace)
+        };
+        let result: EtcdResult<_> = operation.run(call).await;
+        operation.finish(&context, &result);
+        result.map_err(|e| context.attach(e))
+    }
+
+    pub async fn delete(self) -> EtcdResult<DeleteResponse> {
+        let request = etcdserver::DeleteRangeRequest {
+            key: self.client.namespaced_key(&self.start),
+            range_end: self.range_end(),
+            ..Default::default()
+        };
+
+        let mut context = self.error_context("delete");
+        let operation = self.client.operation("kv.delete", &context);
+        let call = async {
+            let metadata = metadata_map(&self.metadata)?;
+            let _in_flight = self.throttle(Budget::Write).await?;
+            let kv_client = self.client.kv_client();
+            let retries = self.client.config.retries(context.operation);
+            let mut attempts = 0;
+            let rpc = with_retries(retries, false, &mut attempts, || {
+                let mut kv_client = kv_client.clone();
+                let request = with_metadata(request.clone(), &metadata);
+                async move { kv_client.delete_range(request).await }
+            });
+            let response = with_timeout(&self.client.config.clock, self.budget(), rpc).await;
+            context.attempt = Some(attempts);
+            let response = response?.into_inner();
+            context.header = response.header.clone();
+            self.client.check_cluster_id(response.header.as_ref())?;
+            DeleteResponse::from_raw(response, &self.client.namespace)
+        };
+        let result: EtcdResult<_> = operation.run(call).await;
+        operation.finish(&context, &result);
+        result.map_err(|e| context.attach(e))
+    }
+}
+
+/// What a read is told apart from other reads by, for coalescing: the same request for the
+/// same namespace, with the same metadata and so the same token, gets the same response
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CoalescedRead {
+    request: Vec<u8>,
+    namespace: Vec<u8>,
+    metadata: Vec<(String, String)>,
+}
+
+/// Reads in flight, shared by every clone of a client which coalesces reads, see
+/// [`EtcdClientBuilder::coalesce_reads`]
+#[derive(Debug, Default)]
+pub(crate) struct Coalescer {
+    /// Where to send the response of each read in flight, for the callers waiting for it
+    waiting: StdMutex<HashMap<CoalescedRead, Vec<oneshot::Sender<GetResponse>>>>,
+}
+
+/// Whether a caller makes a read or waits for the same read made by another
+enum Joined<'a> {
+    Leading(Lead<'a>),
+    Waiting(oneshot::Receiver<GetResponse>),
+}
+
+impl Coalescer {
+    fn join(&self, read: CoalescedRead) -> Joined<'_> {
+        let mut waiting = self.waiting.lock().unwrap();
+        match waiting.entry(read) {
+            Entry::Occupied(entry) => {
+                let (done, shared) = oneshot::channel();
+                entry.into_mut().push(done);
+                Joined::Waiting(shared)
+            }
+            Entry::Vacant(entry) => {
+                let read = entry.key().clone();
+                entry.insert(Vec::new());
+                Joined::Leading(Lead {
+                    coalescer: self,
+                    read,
+                    finished: false,
+                })
+            }
+        }
+    }
+}
+
+/// A read made for every caller waiting for it. Those still waiting when it is dropped
+/// unfinished make the read by themselves.
+struct Lead<'a> {
+    coalescer: &'a Coalescer,
+    read: CoalescedRead,
+    finished: bool,
+}
+
+impl Lead<'_> {
+    /// Share `response` with the callers waiting for it, or have them make the read by
+    /// themselves if it failed
+    fn finish(mut self, response: Option<&GetResponse>) {
+        let waiting = self.take_waiting();
+        if let Some(response) = response {
+            for done in waiting {
+                let _ = done.send(response.clone());
+            }
+        }
+    }
+
+    /// Stop taking callers for the read, returning those waiting for it
+    fn take_waiting(&mut self) -> Vec<oneshot::Sender<GetResponse>> {
+        self.finished = true;
+        let mut waiting = self.coalescer.waiting.lock().unwrap();
+        waiting.remove(&self.read).unwrap_or_default()
+    }
+}
+
+impl Drop for Lead<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.take_waiting();
+        }
+    }
+}
+
+/// Outcome of each item of a batch operation, such as [`EtcdClient::put_many`], by key in the
+/// order the items were given. An item failing does not stop the rest of the batch.
+#[derive(Debug)]
+pub struct BatchOutcome<T> {
+    outcomes: Vec<(String, EtcdResult<T>)>,
+}
+
+impl<T> BatchOutcome<T> {
+    /// Outcome of each item, by key
+    pub fn outcomes(&self) -> &[(String, EtcdResult<T>)] {
+        &self.outcomes
+    }
+
+    /// Whether every item succeeded
+    pub fn is_success(&self) -> bool {
+        self.outcomes.iter().all(|(_, outcome)| outcome.is_ok())
+    }
+
+    /// The items which failed, by key
+    pub fn failures(&self) -> impl Iterator<Item = (&str, &Error)> {
+        self.outcomes
+            .iter()
+            .filter_map(|(key, outcome)| match outcome {
+                Ok(_) => None,
+                Err(e) => Some((key.as_str(), e)),
+            })
+    }
+
+    /// The result of every item, by key, or the first failure if any item failed
+    pub fn into_result(self) -> EtcdResult<Vec<(String, T)>> {
+        self.outcomes
+            .into_iter()
+            .map(|(key, outcome)| outcome.map(|value| (key, value)))
+            .collect()
+    }
+}
+
+/// How much history an [`AutoCompactor`] retains
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompactionPolicy {
+    /// Keep the most recent `n` revisions, compacting to "current revision minus n"
+    Revisions(i64),
+    /// Keep revisions written within the given duration. The revision at each point in time is
+    /// sampled from response headers, so the retained window is accurate to one check interval.
+    Periodic(Duration),
+}
+
+/// Decides which revision to compact to, given samples of the store revision over time
+#[derive(Debug)]
+struct CompactionState {
+    policy: CompactionPolicy,
+    samples: VecDeque<(Instant, i64)>,
+    /// Newest revision the policy allows compacting to
+    target: i64,
+    /// Last revision successfully compacted to
+    compacted: i64,
+}
+
+impl CompactionState {
+    fn new(policy: CompactionPolicy) -> Self {
+        Self {
+            policy,
+            samples: VecDeque::new(),
+            target: 0,
+            compacted: 0,
+        }
+    }
+
+    /// Record the current revision and return the revision to compact to, if any. A target
+    /// which has not been marked as [`compacted`](Self::compacted) is returned again.
+    fn observe(&mut self, now: Instant, revision: i64) -> Option<i64> {
+        let candidate = match self.policy {
+            CompactionPolicy::Revisions(n) => Some(revision - n),
+            CompactionPolicy::Periodic(retention) => {
+                self.samples.push_back((now, revision));
+
+                // Find the newest sample which has aged out of the retention window, dropping
+                // everything before it as it is no longer needed.
+                let mut target = None;
+                while let Some(&(at, rev)) = self.samples.front() {
+                    if now.duration_since(at) < retention {
+                        break;
+                    }
+                    target = Some(rev);
+                    self.samples.pop_front();
+                }
+                target
+            }
+        };
+
+        if let Some(candidate) = candidate {
+            self.target = self.target.max(candidate);
+        }
+        if self.target > self.compacted {
+            Some(self.target)
+        } else {
+            None
+        }
+    }
+
+    fn compacted(&mut self, revision: i64) {
+        self.compacted = self.compacted.max(revision);
+    }
+}
+
+/// Background task compacting the key space according to a [`CompactionPolicy`].
+///
+/// The task stops when the `AutoCompactor` is dropped, [`AutoCompactor::stop`] is called or the
+/// client is [closed](EtcdClient::close). Failed compactions are retried on the next interval.
+#[derive(Debug)]
+pub struct AutoCompactor {
+    _stop: oneshot::Sender<()>,
+}
+
+impl AutoCompactor {
+    /// Stop the background task
+    pub fn stop(self) {}
+}
+
+/// Current revision of the store, taken from the header of a minimal range request
+async fn current_revision<T: Transport>(
+    kv_client: &mut client::KvClient<T>,
+) -> Result<i64, tonic::Status> {
+    let request = etcdserver::RangeRequest {
+        key: vec![0],
+        count_only: true,
+        ..Default::default()
+    };
+    let response = kv_client.range(request).await?.into_inner();
+    match response.header {
+        Some(header) => Ok(header.revision),
+        None => Err(tonic::Status::new(
+            tonic::Code::Internal,
+            "range response is missing its header",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recording::{Exchange, Replay};
+    use futures::SinkExt;
+
+    #[tokio::test]
+    async fn test_reading_your_writes() {
+        let header = |revision| {
+            Some(etcdserver::ResponseHeader {
+                revision,
+                ..Default::default()
+            })
+        };
+        let read = |revision| {
+            let response = etcdserver::RangeResponse {
+                header: header(revision),
+                ..Default::default()
+            };
+            Exchange::answered("/etcdserverpb.KV/Range", response)
+        };
+        let put = etcdserver::PutResponse {
+            header: header(7),
+            ..Default::default()
+        };
+        let put = Exchange::answered("/etcdserverpb.KV/Put", put);
+        let replay = Replay::new(vec![put, read(5), read(7), read(9)]);
+        let kv = EtcdClient::from_transport(replay.clone()).kv();
+
+        let token = kv.put("key", "value").await.unwrap().token();
+        assert_eq!(token, ConsistencyToken::new(7));
+        assert_eq!(
+            token.to_string().parse::<ConsistencyToken>().unwrap(),
+            token
+        );
+
+        // The member serving the first read is behind, so it is read again
+        let range = kv.range("key", None).serializable().min_revision(token);
+        assert_eq!(range.get().await.unwrap().revision(), 7);
+        assert_eq!(range.get().await.unwrap().revision(), 9);
+        assert_eq!(replay.remaining(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_default_read_consistency() {
+        let read = |revision| {
+            let response = etcdserver::RangeResponse {
+                header: Some(etcdserver::ResponseHeader {
+                    revision,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+            Exchange::answered("/etcdserverpb.KV/Range", response)
+        };
+        let replay = Replay::new(vec![read(5), read(7), read(5)]);
+        let config = crate::ChannelConfig {
+            read_consistency: ReadConsistency::Serializable,
+            ..Default::default()
+        };
+        let client = EtcdClient::with_transport(replay.clone(), Vec::new(), config, None);
+        assert_eq!(client.read_consistency(), ReadConsistency::Serializable);
+        let kv = client.kv();
+
+        // Reads are serializable unless they ask otherwise, so only those are read again
+        let token = ConsistencyToken::new(7);
+        let range = kv.range("key", None).min_revision(token);
+        assert_eq!(range.get().await.unwrap().revision(), 7);
+        let range = kv.range("key", None).linearizable().min_revision(token);
+        assert_eq!(range.get().await.unwrap().revision(), 5);
+        assert_eq!(replay.remaining(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_coalescing_reads() {
+        let read = |revision| {
+            let response = etcdserver::RangeResponse {
+                header: Some(etcdserver::ResponseHeader {
+                    revision,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+            Exchange::answered("/etcdserverpb.KV/Range", response)
+        };
+        let failed = Exchange {
+            code: tonic::Code::InvalidArgument,
+            message: "etcdserver: invalid range".into(),
+            ..read(0)
+        };
+        let replay = Replay::new(vec![read(5), read(6), failed, read(7), read(7)]);
+        let limit = crate::RateLimit {
+            per_second: 20,
+            burst: 1,
+        };
+        let config = crate::ChannelConfig {
+            coalescer: Some(std::sync::Arc::new(Coalescer::default())),
+            read_limit: Some(std::sync::Arc::new(crate::Limiter::new(limit))),
+            ..Default::default()
+        };
+        let client = EtcdClient::with_transport(replay.clone(), Vec::new(), config, None);
+        let kv = client.kv();
+        // With the rate limit's only token gone, each read waits for a while before it is sent
+        drop(client.config.throttle(Budget::Read).await.unwrap());
+        let range = || kv.range("config", None);
+        let (a, b, c) = (range(), range(), range());
+        // The reads are boxed so that three of them fit on the test's stack
+        let reads = || future::join3(Box::pin(a.get()), Box::pin(b.get()), Box::pin(c.get()));
+
+        // Reads made while the same read is in flight share its response
+        let (first, second, third) = reads().await;
+        let revisions = [first, second, third].map(|read| read.unwrap().revision());
+        assert_eq!(revisions, [5, 5, 5]);
+        assert_eq!(kv.range("config", None).get().await.unwrap().revision(), 6);
+
+        // Once the shared read fails, the others are made by themselves
+        let (first, second, third) = reads().await;
+        assert!(first.is_err());
+        assert_eq!(second.unwrap().revision(), 7);
+        assert_eq!(third.unwrap().revision(), 7);
+        assert_eq!(replay.remaining(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_key_schema() {
+        use crate::schema::{KeyRule, KeySchema, Violation};
+
+        let put = etcdserver::PutResponse {
+            header: Some(Default::default()),
+            ..Default::default()
+        };
+        let replay = Replay::new(vec![Exchange::answered("/etcdserverpb.KV/Put", put)]);
+        let schema = KeySchema::new().rule(KeyRule::new("app/"));
+        let config = crate::ChannelConfig {
+            key_schema: Some(std::sync::Arc::new(schema)),
+            ..Default::default()
+        };
+        let client = EtcdClient::with_transport(replay.clone(), Vec::new(), config, None);
+        let app = client.namespaced("app/");
+
+        // Keys are checked with the namespace, and the puts which break the schema are not sent
+        app.kv().put("foo", "bar").await.unwrap();
+        let put = client.kv().put("foo", "bar").await.unwrap_err();
+        assert!(matches!(
+            put.root(),
+            Error::SchemaViolation(Violation::Prefix { .. })
+        ));
+        let kv = client.kv();
+        let txn = kv.txn(etcdserver::TxnRequest {
+            success: vec![etcdserver::RequestOp {
+                request: Some(OpRequest::RequestPut(etcdserver::PutRequest {
+                    key: b"foo".to_vec(),
+                    ..Default::default()
+                })),
+            }],
+            ..Default::default()
+        });
+        assert!(matches!(
+            txn.await.unwrap_err().root(),
+            Error::SchemaViolation(_)
+        ));
+        assert_eq!(replay.remaining(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_put_sink() {
+        let committed = || {
+            let response = etcdserver::TxnResponse {
+                header: Some(Default::default()),
+                succeeded: true,
+                ..Default::default()
+            };
+            Exchange::answered("/etcdserverpb.KV/Txn", response)
+        };
+        let too_many = Exchange {
+            code: tonic::Code::InvalidArgument,
+            message: "etcdserver: too many operations in txn request".into(),
+            ..committed()
+        };
+        let replay = Replay::new(vec![
+            committed(),
+            committed(),
+            committed(),
+            too_many,
+            committed(),
+        ]);
+        let client = EtcdClient::from_transport(replay.clone());
+        let options = PutSinkOptions {
+            max_batch: 2,
+            max_delay: Duration::from_secs(60),
+            max_in_flight: 2,
+        };
+        let mut sink = client.kv().put_sink(options);
+
+        // Five puts make two full batches, and a third written when the sink is flushed
+        for i in 0..5 {
+            sink.feed((format!("key{}", i), "value")).await.unwrap();
+        }
+        sink.flush().await.unwrap();
+        assert_eq!(sink.written(), 5);
+
+        // A failed batch is reported by the next call, and the sink carries on
+        sink.send(("key5".to_string(), "value")).await.unwrap_err();
+        sink.send(("key6".to_string(), "value")).await.unwrap();
+        sink.close().await.unwrap();
+        assert_eq!(sink.written(), 6);
+        assert_eq!(replay.remaining(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_ranges() {
+        let client = crate::testing::namespace().await.unwrap();
+        let range = client.range("foo", None);
+
+        let put = range.put("bar").await.unwrap();
+
+        let keys = range.get().await.unwrap();
+
+        assert_eq!(keys.get(b"foo").unwrap().value_str().unwrap(), "bar");
+        assert!(keys.revision() >= put.revision());
+
+        // Test delete a range
+        assert_eq!(range.delete().await.unwrap().deleted(), 1);
+
+        // Have to get the range again as `delete` drops the range.
+        let range = client.range("foo", None);
+        let keys = range.get().await.unwrap();
+        assert!(keys.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_put_options() {
+        let client = crate::testing::namespace().await.unwrap();
+        let kv = client.kv();
+        kv.put("test_put_options", "first").await.unwrap();
+        let put = kv.put("test_put_options", "second").await.unwrap();
+        assert!(put.prev_kv().is_none());
+
+        let options = PutOptions {
+            prev_kv: true,
+            ..Default::default()
+        };
+        let put = kv
+            .put_with_options("test_put_options", "third", options)
+            .await
+            .unwrap();
+        assert_eq!(put.prev_kv().unwrap().value(), b"second");
+        kv.delete("test_put_options").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_getting_ranges() {
+        let client = crate::testing::namespace().await.unwrap();
+        for key in &["a", "b1", "b2", "c"] {
+            client.range(*key, None).put("1").await.unwrap();
+        }
+        let ranges = vec![("a", None), ("b", Some("c")), ("d", None), ("", Some("\0"))];
+        let mut reads = client.kv().get_ranges(ranges, 2);
+        let mut counts = vec![None; 4];
+        while let Some((index, read)) = reads.next().await {
+            counts[index] = Some(read.unwrap().len());
+        }
+        assert_eq!(counts, vec![Some(1), Some(2), Some(0), Some(4)]);
+    }
+
+    #[tokio::test]
+    async fn test_generic_keys() {
+        let client = crate::testing::namespace().await.unwrap();
+        let kv = client.kv();
+        kv.put(b"test_generic_keys".to_vec(), Bytes::from("bar"))
+            .await
+            .unwrap();
+        let key = String::from("test_generic_keys");
+        assert_eq!(kv.get(&key).await.unwrap(), Some("bar".to_string()));
+        assert_eq!(kv.get_bytes(&key).await.unwrap(), Some(Bytes::from("bar")));
+        let values = client.range(key.as_bytes(), None).get().await.unwrap();
+        assert_eq!(values.kvs()[0].value(), b"bar");
+        assert_eq!(kv.delete(key).await.unwrap().deleted(), 1);
+    }
+
+    #[test]
+    fn test_compares() {
+        use etcdserver::compare::TargetUnion;
+
+        let compare = compare_value("foo", CompareResult::Equal, b"bar");
+        assert_eq!(compare.key, b"foo");
+        assert_eq!(compare.result, i32::from(CompareResult::Equal));
+        assert_eq!(compare.target, i32::from(CompareTarget::Value));
+        assert_eq!(
+            compare.target_union,
+            Some(TargetUnion::Value(b"bar".to_vec()))
+        );
+
+        let compare = compare_version(String::from("foo"), CompareResult::Greater, 0);
+        assert_eq!(compare.target, i32::from(CompareTarget::Version));
+        assert_eq!(compare.target_union, Some(TargetUnion::Version(0)));
+
+        let compare = compare_create_revision("foo", CompareResult::Less, 7);
+        assert_eq!(compare.target_union, Some(TargetUnion::CreateRevision(7)));
+        let compare = compare_mod_revision("foo", CompareResult::NotEqual, 7);
+        assert_eq!(compare.target, i32::from(CompareTarget::Mod));
+        assert_eq!(compare.target_union, Some(TargetUnion::ModRevision(7)));
+    }
+
+    #[tokio::test]
+    async fn test_namespaced_txns() {
+        use etcdserver::request_op::Request;
+
+        let client = EtcdClient::connect_lazy("http://127.0.0.1:1").unwrap();
+        let app = client.namespaced("app/");
+        let put = etcdserver::RequestOp {
+            request: Some(Request::RequestPut(etcdserver::PutRequest {
+                key: b"foo".to_vec(),
+                ..Default::default()
+            })),
+        };
+        let nested = etcdserver::TxnRequest {
+            success: vec![put.clone()],
+            ..Default::default()
+        };
+        let request = etcdserver::TxnRequest {
+            compare: vec![etcdserver::Compare {
+                key: b"foo".to_vec(),
+                range_end: b"\0".to_vec(),
+                ..Default::default()
+            }],
+            success: vec![put],
+            failure: vec![etcdserver::RequestOp {
+                request: Some(Request::RequestTxn(nested)),
+            }],
+        };
+        let request = namespaced_txn(&app, request);
+        assert_eq!(request.compare[0].key, b"app/foo");
+        assert_eq!(request.compare[0].range_end, b"app0");
+        fn put_key(op: &etcdserver::RequestOp) -> &[u8] {
+            match &op.request {
+                Some(Request::RequestPut(put)) => &put.key,
+                Some(Request::RequestTxn(txn)) => put_key(&txn.success[0]),
+                request => panic!("unexpected request {:?}", request),
+            }
+        }
+        assert_eq!(put_key(&request.success[0]), b"app/foo");
+        assert_eq!(put_key(&request.failure[0]), b"app/foo");
+    }
+
+    #[tokio::test]
+    async fn test_batches() {
+        let outcome = BatchOutcome {
+            outcomes: vec![
+                ("a".to_string(), Ok(1)),
+                ("b".to_string(), Err(Error::from("failed"))),
+                ("c".to_string(), Ok(3)),
+            ],
+        };
+        assert!(!outcome.is_success());
+        let failed: Vec<_> = outcome.failures().map(|(key, _)| key).collect();
+        assert_eq!(failed, ["b"]);
+        assert!(outcome.into_result().is_err());
+
+        // Every item is tried, even after one fails
+        let client = EtcdClient::connect_lazy("http://127.0.0.1:1").unwrap();
+        let items = vec![("test_batches/a", "1"), ("test_batches/b", "2")];
+        let outcome = client.put_many(items).await;
+        assert_eq!(outcome.outcomes().len(), 2);
+        assert_eq!(outcome.failures().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_putting_and_getting_many() {
+        let client = crate::testing::namespace().await.unwrap();
+        let items = vec![("test_many/a", "1"), ("test_many/b", "2")];
+        assert!(client.put_many(items).await.is_success());
+        let keys = vec!["test_many/a", "test_many/b", "test_many/missing"];
+        let values = client.get_many(keys).await.into_result().unwrap();
+        assert_eq!(
+            values,
+            [
+                ("test_many/a".to_string(), Some("1".to_string())),
+                ("test_many/b".to_string(), Some("2".to_string())),
+                ("test_many/missing".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compaction_by_revisions() {
+        let mut state = CompactionState::new(CompactionPolicy::Revisions(100));
+        let now = Instant::now();
+
+        assert_eq!(state.observe(now, 50), None);
+        assert_eq!(state.observe(now, 150), Some(50));
+        // Not compacted yet, so the same target is returned again
+        assert_eq!(state.observe(now, 150), Some(50));
+        state.compacted(50);
+        assert_eq!(state.observe(now, 150), None);
+        assert_eq!(state.observe(now, 400), Some(300));
+    }
+
+    #[test]
+    fn test_compaction_by_duration() {
+        let mut state = CompactionState::new(CompactionPolicy::Periodic(Duration::from_secs(60)));
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert_eq!(state.observe(at(0), 10), None);
+        assert_eq!(state.observe(at(30), 20), None);
+        // The sample from t=0 is now a minute old
+        assert_eq!(state.observe(at(60), 30), Some(10));
+        state.compacted(10);
+        // Both t=30 and t=60 have aged out, compact to the newer of the two
+        assert_eq!(state.observe(at(125), 40), Some(30));
+        assert_eq!(state.samples.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_auto_compaction_failures() {
+        // Nothing listens on port 1, so every check fails
+        let (tx, mut rx) = futures::channel::mpsc::unbounded();
+        let client = EtcdClient::builder()
+            .endpoint("http://127.0.0.1:1")
+            .retry(crate::RetryPolicy::disabled())
+            .diagnostics(move |diagnostic| {
+                let _ = tx.unbounded_send(diagnostic.clone());
+            })
+            .connect()
+            .await
+            .unwrap();
+        let compactor =
+            client.auto_compact(CompactionPolicy::Revisions(10), Duration::from_millis(10));
+
+        let failed = rx.next().await.unwrap();
+        assert!(matches!(
+            failed,
+            Diagnostic::CompactionFailed { revision: None, .. }
+        ));
+        compactor.stop();
+    }
+}
@@ -0,0 +1,180 @@
+//! The lease service: leases which keys can be attached to, expiring unless kept alive.
+
+use crate::{etcdserver, Budget, Error, EtcdClient, EtcdResult, Retry, Transport};
+use std::fmt;
+
+/// Leases which keys can be attached to, see [`EtcdClient::lease`]
+pub struct Lease<T> {
+    pub(crate) client: EtcdClient<T>,
+}
+
+impl<T> fmt::Debug for Lease<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Lease")
+            .field("client", &self.client)
+            .finish()
+    }
+}
+
+impl<T: Transport> Lease<T> {
+    /// Grant a lease which expires `ttl` seconds from now unless it is kept alive. The server
+    /// may grant a longer TTL than asked for.
+    pub async fn grant(&self, ttl: i64) -> EtcdResult<etcdserver::LeaseGrantResponse> {
+        let request = etcdserver::LeaseGrantRequest { ttl, id: 0 };
+        let context = self.client.error_context("lease grant", None);
+        let rpc = || {
+            let (mut lease_client, request) = (self.client.lease_client(), request.clone());
+            async move { lease_client.lease_grant(request).await }
+        };
+        let granted = |response: etcdserver::LeaseGrantResponse| {
+            if !response.error.is_empty() {
+                return Err(format!("failed to grant lease: {}", response.error).into());
+            }
+            Ok(response)
+        };
+        self.client
+            .call(
+                "lease.grant",
+                context,
+                Budget::Write,
+                Retry::Never,
+                rpc,
+                granted,
+            )
+            .await
+    }
+
+    /// Revoke the lease `id`, deleting every key attached to it
+    pub async fn revoke(&self, id: i64) -> EtcdResult<()> {
+        let request = etcdserver::LeaseRevokeRequest { id };
+        let context = self.client.error_context("lease revoke", None);
+        let rpc = || {
+            let (mut lease_client, request) = (self.client.lease_client(), request.clone());
+            async move { lease_client.lease_revoke(request).await }
+        };
+        self.client
+            .call(
+                "lease.revoke",
+                context,
+                Budget::Write,
+                Retry::Never,
+                rpc,
+                |_| Ok(()),
+            )
+            .await
+    }
+
+    /// Refresh the lease `id` once, returning its new TTL in seconds
+    pub async fn keep_alive_once(&self, id: i64) -> EtcdResult<i64> {
+        let mut context = self.client.error_context("lease keep-alive", None);
+        let operation = self.client.operation("lease.keep_alive", &context);
+        let call = async {
+            let _in_flight = self.client.config.throttle(Budget::Write).await?;
+            let requests = async_stream::stream! {
+                yield etcdserver::LeaseKeepAliveRequest { id };
+            };
+            let mut responses = self
+                .client
+                .lease_client()
+                .lease_keep_alive(requests)
+                .await?
+                .into_inner();
+            let response = match responses.message().await? {
+                Some(response) => response,
+                None => {
+                    let missing = "lease keep-alive stream ended without a response";
+                    return Err(Error::invalid_response(missing));
+                }
+            };
+            context.header = response.header.clone();
+            self.client.check_cluster_id(response.header.as_ref())?;
+            // Leases which have expired are refreshed with a TTL of zero
+            if response.ttl <= 0 {
+                return Err(Error::LeaseNotFound(Some(id)));
+            }
+            Ok(response.ttl)
+        };
+        let result: EtcdResult<_> = operation.run(call).await;
+        operation.finish(&context, &result);
+        result.map_err(|e| context.attach(e))
+    }
+
+    /// Remaining time to live of the lease `id`, along with the keys attached to it if `keys`
+    /// is set
+    pub async fn time_to_live(
+        &self,
+        id: i64,
+        keys: bool,
+    ) -> EtcdResult<etcdserver::LeaseTimeToLiveResponse> {
+        let request = etcdserver::LeaseTimeToLiveRequest { id, keys };
+        let context = self.client.error_context("lease time to live", None);
+        let rpc = || {
+            let (mut lease_client, request) = (self.client.lease_client(), request.clone());
+            async move { lease_client.lease_time_to_live(request).await }
+        };
+        let found = |response: etcdserver::LeaseTimeToLiveResponse| {
+            // Leases which do not exist have a TTL of -1
+            if response.ttl < 0 {
+                return Err(Error::LeaseNotFound(Some(id)));
+            }
+            Ok(response)
+        };
+        self.client
+            .call(
+                "lease.time_to_live",
+                context,
+                Budget::Read,
+                Retry::Never,
+                rpc,
+                found,
+            )
+            .await
+    }
+
+    /// IDs of every lease the cluster has granted which has not expired
+    pub async fn leases(&self) -> EtcdResult<Vec<i64>> {
+        let request = etcdserver::LeaseLeasesRequest {};
+        let context = self.client.error_context("leases", None);
+        let rpc = || {
+            let (mut lease_client, request) = (self.client.lease_client(), request.clone());
+            async move { lease_client.lease_leases(request).await }
+        };
+        let ids = |response: etcdserver::LeaseLeasesResponse| {
+            Ok(response.leases.into_iter().map(|lease| lease.id).collect())
+        };
+        self.client
+            .call(
+                "lease.leases",
+                context,
+                Budget::Read,
+                Retry::Never,
+                rpc,
+                ids,
+            )
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_leases() {
+        let client = crate::testing::namespace().await.unwrap();
+        let lease = client.lease().grant(60).await.unwrap();
+        assert!(lease.ttl >= 60);
+        assert!(client.lease().leases().await.unwrap().contains(&lease.id));
+        assert!(client.lease().keep_alive_once(lease.id).await.unwrap() > 0);
+        let ttl = client.lease().time_to_live(lease.id, true).await.unwrap();
+        assert_eq!(ttl.granted_ttl, lease.ttl);
+
+        client.lease().revoke(lease.id).await.unwrap();
+        let err = client
+            .lease()
+            .time_to_live(lease.id, false)
+            .await
+            .unwrap_err();
+        assert!(matches!(err.root(), Error::LeaseNotFound(_)));
+    }
+}
@@ -1,280 +1,4476 @@
+use futures::channel::{mpsc, oneshot};
+use futures::future::{self, Either, FutureExt};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
 use std::convert::TryInto;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch as updates;
 use tonic::codegen::StdError;
 use tonic::transport::Endpoint;
-use std::collections::HashMap;
 
-pub type EtcdResult<T> = Result<T, Box<dyn std::error::Error>>;
+/// Code generated from the protocol's proto files. The client's API only exposes the messages
+/// re-exported at the crate root; the rest are public with the `raw-protos` feature, for
+/// making calls the client does not wrap.
+// Generated doc comments are copied from the proto files, whatever their formatting
+#[allow(clippy::doc_lazy_continuation)]
+// Without `raw-protos` the generated code is private, and lints skipping public items apply
+#[cfg_attr(
+    not(feature = "raw-protos"),
+    allow(dead_code, clippy::enum_variant_names)
+)]
+mod proto {
+    /// Include the code generated for a proto package, by the build script or checked in
+    /// under `src/proto/vendored` with the `vendored-protos` feature
+    macro_rules! include_package {
+        ($package:literal) => {
+            #[cfg(not(feature = "vendored-protos"))]
+            tonic::include_proto!($package);
+            #[cfg(feature = "vendored-protos")]
+            include!(concat!("proto/vendored/", $package, ".rs"));
+        };
+    }
 
-// Internal names, which are unfortunately named.
-pub mod mvccpb {
-    // Proto file: kv.proto
-    tonic::include_proto!("mvccpb");
-}
+    // Internal names, which are unfortunately named.
+    pub mod mvccpb {
+        // Proto file: kv.proto
+        include_package!("mvccpb");
+    }
 
-pub mod authpb {
-    // Proto file: auth.proto
-    tonic::include_proto!("authpb");
-}
+    pub mod authpb {
+        // Proto file: auth.proto
+        include_package!("authpb");
+    }
 
-pub mod etcdserver {
-    // Proto file: rpc.proto
-    tonic::include_proto!("etcdserverpb");
+    pub mod etcdserver {
+        // Proto file: rpc.proto
+        include_package!("etcdserverpb");
+    }
+
+    // Code generated from the other proto files refers to rpc.proto by its package name
+    #[cfg(any(feature = "lock", feature = "election"))]
+    use etcdserver as etcdserverpb;
+
+    #[cfg(feature = "lock")]
+    pub mod v3lock {
+        // Proto file: v3lock.proto
+        include_package!("v3lockpb");
+    }
+
+    #[cfg(feature = "election")]
+    pub mod v3election {
+        // Proto file: v3election.proto
+        include_package!("v3electionpb");
+    }
 }
 
+#[cfg(all(feature = "election", feature = "raw-protos"))]
+pub use proto::v3election;
+#[cfg(all(feature = "election", not(feature = "raw-protos")))]
+use proto::v3election;
+#[cfg(all(feature = "lock", feature = "raw-protos"))]
+pub use proto::v3lock;
+#[cfg(all(feature = "lock", not(feature = "raw-protos")))]
+use proto::v3lock;
+#[cfg(feature = "raw-protos")]
+pub use proto::{authpb, etcdserver, mvccpb};
+#[cfg(not(feature = "raw-protos"))]
+use proto::{authpb, etcdserver, mvccpb};
+
+use clock::SharedClock;
+#[cfg(feature = "cluster")]
+use cluster::{leader_from_statuses, member_statuses};
+use connection::Connection;
+use diagnostic::Diagnostics;
 use etcdserver::client;
+use hook::Hooks;
+use trace::Operation;
+
+pub mod api;
+#[cfg(feature = "auth")]
+pub mod auth;
+#[cfg(feature = "maintenance")]
+pub mod backup;
+#[cfg(feature = "sync")]
+pub mod blocking;
+pub mod chaos;
+pub mod chunked;
+pub mod clock;
+#[cfg(feature = "serde")]
+pub mod cloudevents;
+#[cfg(feature = "cluster")]
+pub mod cluster;
+pub mod concurrency;
+#[cfg(feature = "config")]
+pub mod config;
+pub mod connection;
+pub mod diagnostic;
+pub mod discovery;
+#[cfg(feature = "election")]
+pub mod election;
+pub mod enums;
+mod env;
+mod error;
+pub mod fake;
+#[cfg(feature = "gateway")]
+pub mod gateway;
+#[cfg(feature = "grpc-web")]
+pub mod grpc_web;
+pub mod hook;
+pub mod informer;
+#[cfg(feature = "serde")]
+pub mod json;
+pub mod kv;
+pub mod lease;
+#[cfg(feature = "maintenance")]
+pub mod maintenance;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod mirror;
+pub mod mirror_maker;
+#[cfg(feature = "otel")]
+mod otel;
+pub mod prelude;
+pub mod recording;
+pub mod redact;
+pub mod resolver;
+pub mod response;
+pub mod runtime;
+pub mod schema;
+pub mod service;
+#[cfg(feature = "signal")]
+pub mod shutdown;
+mod srv;
+#[cfg(any(test, feature = "test-util"))]
+pub mod testing;
+mod trace;
+pub mod ttl_map;
+mod tunnel;
+#[cfg(feature = "serde")]
+pub mod typed;
+pub mod watch;
 
-/// Range of keys
-pub struct Range<'a, 'b, T> {
-    start: &'b str,
-    end: Option<&'b str>,
-    client: &'a mut EtcdClient<T>,
+#[cfg(feature = "auth")]
+pub use auth::Auth;
+pub use clock::{Clock, MockClock, SystemClock};
+#[cfg(feature = "cluster")]
+pub use cluster::{
+    validate_peer_urls, Cluster, CorruptionReport, EndpointHealth, MemberAdded, MemberClient,
+    MemberHash, MemberHealth, MemberListOptions, MembershipEvent, UnreachableMember,
+};
+#[cfg(feature = "config")]
+pub use config::{ClientConfig, TlsFiles};
+pub use connection::{ConnectionEvent, ConnectionEvents};
+pub use diagnostic::Diagnostic;
+#[cfg(feature = "election")]
+pub use election::{Election, OBSERVE_RETRY_INTERVAL};
+pub use enums::{
+    AlarmAction, AlarmType, CompareResult, CompareTarget, DowngradeAction, EventKind,
+    PermissionType, SortOrder, SortTarget, WatchFilter,
+};
+pub use error::{Error, ErrorContext, EtcdResult, StatusDetail, StatusDetails};
+pub use hook::{Call, ClientHook, SlowRequests};
+pub use kv::{
+    compare_create_revision, compare_mod_revision, compare_value, compare_version, AutoCompactor,
+    BatchOutcome, CompactionPolicy, Kv, PutOptions, Range, RangeReads,
+};
+pub use lease::Lease;
+#[cfg(feature = "maintenance")]
+pub use maintenance::{
+    verify_snapshot, Capability, Maintenance, SnapshotInfo, Version, VersionInfo,
+};
+pub use redact::{set_value_redaction, Redaction};
+pub use response::{
+    ConsistencyToken, DeleteResponse, GetResponse, KeyValue, PutResponse, TxnOpResponse,
+    TxnResponse, WatchEvent, WatchResponse,
+};
+pub use runtime::{set_runtime, Delay, Runtime};
+#[cfg(feature = "bincode")]
+pub use typed::Bincode;
+#[cfg(feature = "msgpack")]
+pub use typed::MessagePack;
+#[cfg(feature = "serde")]
+pub use typed::{Codec, ConfigWatcher, Json, TypedEvent, TypedKv, TypedWatchStream};
+pub use watch::{BatchOptions, Watch, WatchBatches, WatchStream};
+
+// The generated messages which appear in the client's API
+pub use etcdserver::compare::TargetUnion as CompareTargetUnion;
+pub use etcdserver::request_op::Request as OpRequest;
+pub use etcdserver::{
+    Compare, DeleteRangeRequest, HashKvResponse, LeaseGrantResponse, LeaseTimeToLiveResponse,
+    Member, PutRequest, RangeRequest, RequestOp, ResponseHeader, SnapshotResponse, StatusResponse,
+    TxnRequest,
+};
+#[cfg(feature = "election")]
+pub use v3election::LeaderKey;
+
+/// The context of a call which makes etcd operations on behalf of its own caller, such as a
+/// request handler, to thread through each of them with [`Range::context`].
+///
+/// Operations made in a context give up once its deadline passes, so a handler's remaining
+/// budget bounds every call it makes downstream, and send its metadata and token. Derive a
+/// context with a tighter deadline for a single operation with [`timeout`](Self::timeout).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CallContext {
+    deadline: Option<Instant>,
+    metadata: Vec<(String, String)>,
+    token: Option<String>,
+    traffic_class: Option<TrafficClass>,
 }
 
-impl<'a, 'b> Range<'a, 'b, tonic::transport::channel::Channel> {
-    pub async fn put<S>(&mut self, value: S) -> EtcdResult<()>
-    where S: Into<String>
-    {
-        let request = etcdserver::PutRequest {
-            key: self.start.to_string().into_bytes(),
-            value: value.into().into_bytes(),
-            prev_kv: true,
-            ..Default::default()
+impl CallContext {
+    /// A context with no deadline, metadata or token
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Give up on operations at `deadline`, or at the context's current deadline if that is
+    /// earlier
+    pub fn deadline(self, deadline: Instant) -> Self {
+        let deadline = match self.deadline {
+            Some(current) => current.min(deadline),
+            None => deadline,
         };
+        Self {
+            deadline: Some(deadline),
+            ..self
+        }
+    }
 
-        let _response = self.client.kv_client.put(request).await?;
-        // TODO: check the response for errors
-        Ok(())
+    /// Give up on operations `timeout` from now, or at the context's current deadline if that
+    /// is earlier
+    pub fn timeout(self, timeout: Duration) -> Self {
+        self.deadline(Instant::now() + timeout)
     }
 
-    pub async fn get(&mut self) -> EtcdResult<HashMap<String, String>> {
-        let request = etcdserver::RangeRequest {
-            key: self.start.to_string().into_bytes(),
-            range_end: match self.end {
-                Some(s) => s.to_string().into_bytes(),
-                None => "".to_string().into_bytes(),
-            },
-            ..Default::default()
-        };
-        let response = self.client.kv_client.range(request).await?;
-        let range_response = response.into_inner();
+    /// Send the gRPC metadata `key: value` with every operation, in place of any metadata of
+    /// the same name configured for the client
+    pub fn metadata<K, V>(mut self, key: K, value: V) -> Self
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.metadata.push((key.into(), value.into()));
+        self
+    }
+
+    /// Authenticate operations with `token`, such as a token the caller passed on, in place
+    /// of the client's own
+    pub fn token<S>(self, token: S) -> Self
+    where
+        S: Into<String>,
+    {
+        Self {
+            token: Some(token.into()),
+            ..self
+        }
+    }
 
-        let mut out = HashMap::new();
-        range_response.kvs.iter().for_each(|kv| {
-            let key = std::str::from_utf8(&kv.key).unwrap();
-            let value = std::str::from_utf8(&kv.value).unwrap();
+    /// Treat operations as `class`, in place of the client's class, see
+    /// [`EtcdClientBuilder::traffic_class`]
+    pub fn traffic_class(self, class: TrafficClass) -> Self {
+        Self {
+            traffic_class: Some(class),
+            ..self
+        }
+    }
 
-            out.insert(key.to_string(), value.to_string());
-        });
+    /// Time left until the deadline, if there is one. Zero once it has passed.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+}
 
-        Ok(out)
+/// Headers for the gRPC metadata `pairs`
+fn metadata_headers(pairs: &[(String, String)]) -> EtcdResult<http::HeaderMap> {
+    let mut headers = http::HeaderMap::new();
+    for (key, value) in pairs {
+        let key = http::header::HeaderName::from_bytes(key.as_bytes())?;
+        headers.insert(key, http::HeaderValue::from_str(value)?);
     }
+    Ok(headers)
+}
 
-    pub async fn delete(self) -> EtcdResult<()> {
-        let request = etcdserver::DeleteRangeRequest {
-            key: self.start.to_string().into_bytes(),
-            range_end: match self.end {
-                Some(s) => s.to_string().into_bytes(),
-                None => "".to_string().into_bytes(),
-            },
-            ..Default::default()
+/// The gRPC metadata `pairs`, to send with a single request
+fn metadata_map(pairs: &[(String, String)]) -> EtcdResult<tonic::metadata::MetadataMap> {
+    Ok(tonic::metadata::MetadataMap::from_headers(
+        metadata_headers(pairs)?,
+    ))
+}
+
+/// Request sending `message` with `metadata`
+fn with_metadata<T>(message: T, metadata: &tonic::metadata::MetadataMap) -> tonic::Request<T> {
+    let mut request = tonic::Request::new(message);
+    *request.metadata_mut() = metadata.clone();
+    request
+}
+
+/// Wait for `primary`, sending `hedged` as well if there is no response after `delay` or the
+/// primary fails, and take the first successful response
+async fn hedge_request<A, B, T>(delay: Duration, primary: A, hedged: B) -> Result<T, tonic::Status>
+where
+    A: std::future::Future<Output = Result<T, tonic::Status>>,
+    B: std::future::Future<Output = Result<T, tonic::Status>>,
+{
+    futures::pin_mut!(primary, hedged);
+    match future::select(primary.as_mut(), runtime::delay_for(delay)).await {
+        Either::Left((Ok(response), _)) => return Ok(response),
+        Either::Left((Err(_), _)) => return hedged.await,
+        Either::Right(_) => {}
+    }
+    match future::select(primary, hedged).await {
+        Either::Left((Ok(response), _)) | Either::Right((Ok(response), _)) => Ok(response),
+        Either::Left((Err(_), hedged)) => hedged.await,
+        Either::Right((Err(_), primary)) => primary.await,
+    }
+}
+
+/// Wait for `rpc`, giving up after `timeout` on `clock` if there is one
+async fn with_timeout<F, T>(clock: &SharedClock, timeout: Option<Duration>, rpc: F) -> EtcdResult<T>
+where
+    F: std::future::Future<Output = Result<T, tonic::Status>>,
+{
+    let started = clock.now();
+    let timed_out = |timeout| Error::Timeout {
+        what: "request timed out".to_string(),
+        timeout,
+        elapsed: clock.now().saturating_duration_since(started),
+    };
+    match timeout {
+        // Requests whose time is already up are not sent at all
+        Some(timeout) if timeout == Duration::from_secs(0) => Err(timed_out(timeout)),
+        Some(timeout) => match clock.timeout(rpc, timeout).await {
+            Ok(response) => Ok(response?),
+            Err(_) => Err(timed_out(timeout)),
+        },
+        None => Ok(rpc.await?),
+    }
+}
+
+/// How often [`wait_for_ready`] checks the cluster
+#[cfg(feature = "cluster")]
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Wait until the cluster behind `endpoints` can serve requests: a quorum of its voting
+/// members respond to Status and there is a leader.
+///
+/// This is meant for applications and tests starting alongside etcd, which would otherwise
+/// need their own retry loop around [`EtcdClient::connect`]. Returns an error describing the
+/// last problem seen if the cluster is not ready within `timeout`. Use
+/// [`EtcdClientBuilder::wait_for_ready`] to reach the cluster with connection settings, such
+/// as TLS or a proxy.
+#[cfg(feature = "cluster")]
+pub async fn wait_for_ready<I, S>(endpoints: I, timeout: Duration) -> EtcdResult<()>
+where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+{
+    EtcdClient::builder()
+        .endpoints(endpoints)
+        .wait_for_ready(timeout)
+        .await
+}
+
+/// Check once whether the cluster is ready, see [`wait_for_ready`]
+#[cfg(feature = "cluster")]
+async fn cluster_ready(config: &ChannelConfig, endpoints: &[String]) -> Result<(), String> {
+    let mut reason = String::new();
+    let mut members = None;
+    for endpoint in endpoints {
+        let mut cluster_client = match config.member_channel(endpoint).await {
+            Ok(channel) => client::ClusterClient::new(channel),
+            Err(e) => {
+                reason = format!("cannot connect to {}: {}", endpoint, e);
+                continue;
+            }
         };
+        match cluster_client
+            .member_list(etcdserver::MemberListRequest::default())
+            .await
+        {
+            Ok(response) => {
+                members = Some(response.into_inner().members);
+                break;
+            }
+            Err(e) => reason = format!("cannot list members from {}: {}", endpoint, e),
+        }
+    }
+    let members = members.ok_or(reason)?;
 
-        let _response = self.client.kv_client.delete_range(request).await?;
-        Ok(())
+    let statuses = member_statuses(config, &members).await;
+    let voting = members.iter().filter(|m| !m.is_learner).count();
+    let responding = members
+        .iter()
+        .zip(&statuses)
+        .filter(|(m, status)| !m.is_learner && status.is_ok())
+        .count();
+    if responding < voting / 2 + 1 {
+        return Err(format!(
+            "only {} of {} voting members are responding",
+            responding, voting
+        ));
     }
+
+    match leader_from_statuses(&members, &statuses) {
+        Some(_) => Ok(()),
+        None => Err("the cluster has no leader".to_string()),
+    }
+}
+
+/// Handle to a background task keeping a client's endpoints in sync with the member list,
+/// created by [`EtcdClient::auto_sync_endpoints`].
+///
+/// The task stops when the `EndpointSync` is dropped, [`EndpointSync::stop`] is called or the client is
+/// [closed](EtcdClient::close).
+#[derive(Debug)]
+pub struct EndpointSync {
+    _stop: oneshot::Sender<()>,
+}
+
+impl EndpointSync {
+    /// Stop the background task
+    pub fn stop(self) {}
+}
+
+/// Handle to a background task tracking the health of a client's endpoints, created by
+/// [`EtcdClient::track_endpoint_health`].
+///
+/// The task stops when the `HealthTracker` is dropped, [`HealthTracker::stop`] is called or the client is
+/// [closed](EtcdClient::close).
+/// Endpoints found to be unhealthy by then stay out of the rotation.
+#[derive(Debug)]
+pub struct HealthTracker {
+    _stop: oneshot::Sender<()>,
 }
 
-/// Cluster information
-pub struct Cluster<'a, T> {
-    client: &'a mut EtcdClient<T>,
+impl HealthTracker {
+    /// Stop the background task
+    pub fn stop(self) {}
 }
 
-impl<'a> Cluster<'a, tonic::transport::channel::Channel> {
-    pub async fn members(&mut self) -> EtcdResult<Vec<etcdserver::Member>> {
-        let request = etcdserver::MemberListRequest {};
-        let response = self.client.cluster_client.member_list(request).await?;
-        Ok(response.into_inner().members)
+/// Circuit breaker settings for the endpoint health tracker, see
+/// [`EtcdClientBuilder::circuit_breaker`].
+///
+/// An endpoint's circuit opens after `failure_threshold` probes in a row fail, taking it out
+/// of the rotation. It is left alone for `open_for`, then half-opens: a single probe is sent,
+/// which closes the circuit if it succeeds and opens it again for `open_for` if it fails. A
+/// member which is down is then neither sent requests nor probed on every tick, so clients
+/// retrying against it do not pile onto it as it comes back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitBreaker {
+    /// Probes which must fail in a row to open an endpoint's circuit
+    pub failure_threshold: u32,
+    /// Time an open circuit waits before a probe is sent through it again
+    pub open_for: Duration,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        CircuitBreaker {
+            failure_threshold: 3,
+            open_for: Duration::from_secs(30),
+        }
     }
 }
 
-/// Etcd client
-pub struct EtcdClient<T> {
-    #[allow(dead_code)]
-    auth_client: client::AuthClient<T>,
-    cluster_client: client::ClusterClient<T>,
-    kv_client: client::KvClient<T>,
-    #[allow(dead_code)]
-    lease_client: client::LeaseClient<T>,
-    #[allow(dead_code)]
-    status_client: client::MaintenanceClient<T>,
-    watch_client: client::WatchClient<T>,
+impl CircuitBreaker {
+    /// Take an endpoint out of the rotation on its first failed probe, and probe it again on
+    /// every tick, which is how the health tracker behaves without a circuit breaker
+    fn immediate() -> Self {
+        CircuitBreaker {
+            failure_threshold: 1,
+            open_for: Duration::from_secs(0),
+        }
+    }
 }
 
-impl EtcdClient<tonic::transport::channel::Channel> {
-    pub async fn connect<D>(dst: D) -> EtcdResult<Self>
-    where
-        D: TryInto<Endpoint> + Clone,
-        D::Error: Into<StdError>,
-    {
-        let auth_client = client::AuthClient::connect(dst.clone()).await?;
-        let cluster_client = client::ClusterClient::connect(dst.clone()).await?;
-        let kv_client = client::KvClient::connect(dst.clone()).await?;
-        let lease_client = client::LeaseClient::connect(dst.clone()).await?;
-        let status_client = client::MaintenanceClient::connect(dst.clone()).await?;
-        let watch_client = client::WatchClient::connect(dst.clone()).await?;
-
-        Ok(Self {
-            auth_client,
-            cluster_client,
-            kv_client,
-            lease_client,
-            status_client,
-            watch_client,
-        })
+/// State of an endpoint's circuit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Circuit {
+    /// Probes failed in a row
+    failures: u32,
+    /// When the circuit was last opened, `None` while it is closed
+    opened_at: Option<Instant>,
+}
+
+impl Circuit {
+    fn is_open(&self) -> bool {
+        self.opened_at.is_some()
     }
 
-    pub fn range<'a, 'b>(
-        &'a mut self,
-        start: &'b str,
-        end: Option<&'b str>,
-    ) -> Range<'a, 'b, tonic::transport::channel::Channel> {
-        Range {
-            start,
-            end,
-            client: self,
+    /// Whether the endpoint should be probed at `now`: always while the circuit is closed,
+    /// and once it has been open for long enough to half-open
+    fn probe_due(&self, settings: CircuitBreaker, now: Instant) -> bool {
+        match self.opened_at {
+            Some(opened_at) => now.duration_since(opened_at) >= settings.open_for,
+            None => true,
         }
     }
 
-    pub async fn watch<K>(
-        &mut self,
-        key: K,
-    ) -> EtcdResult<tonic::Streaming<etcdserver::WatchResponse>>
-    where
-        K: Into<Vec<u8>> + Sync + Send + 'static,
-    {
-        let request = async_stream::stream! {
-            let watch_create_req = etcdserver::WatchCreateRequest {
-                key: key.into(),
-                ..Default::default()
-            };
-            let request_union = etcdserver::watch_request::RequestUnion::CreateRequest(watch_create_req);
-            let request = etcdserver::WatchRequest {
-                request_union: Some(request_union),
-            };
+    /// Record the outcome of a probe made at `now`
+    fn record(&mut self, healthy: bool, settings: CircuitBreaker, now: Instant) {
+        if healthy {
+            *self = Circuit::default();
+            return;
+        }
+        self.failures = self.failures.saturating_add(1);
+        // A failed probe through a half-open circuit opens it again straight away
+        if self.is_open() || self.failures >= settings.failure_threshold {
+            self.opened_at = Some(now);
+        }
+    }
+}
+
+/// The `endpoints` which are not `unhealthy`, or all of them if none are healthy
+fn healthy_endpoints(endpoints: &[String], unhealthy: &[String]) -> Vec<String> {
+    let healthy: Vec<String> = endpoints
+        .iter()
+        .filter(|url| !unhealthy.contains(url))
+        .cloned()
+        .collect();
+    if healthy.is_empty() {
+        endpoints.to_vec()
+    } else {
+        healthy
+    }
+}
+
+/// Client URLs of every started voting member, sorted and de-duplicated. Learners are left out
+/// as they reject most requests.
+fn member_endpoints(members: &[etcdserver::Member]) -> Vec<String> {
+    let mut endpoints: Vec<String> = members
+        .iter()
+        .filter(|m| !m.is_learner)
+        .flat_map(|m| m.client_ur_ls.iter().cloned())
+        .collect();
+    endpoints.sort();
+    endpoints.dedup();
+    endpoints
+}
+
+/// TLS implementation used to connect to a cluster, see [`TlsConfig`]
+#[cfg(any(feature = "tls", feature = "tls-native"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsBackend {
+    /// rustls, with the `tls` feature. Only the configured CA certificate is trusted.
+    #[cfg(feature = "tls")]
+    Rustls,
+    /// The platform's OpenSSL, with the `tls-native` feature. CA certificates from the
+    /// operating system's store are trusted as well as the configured one, for environments
+    /// with corporate CAs installed system wide.
+    #[cfg(feature = "tls-native")]
+    Native,
+}
+
+#[cfg(any(feature = "tls", feature = "tls-native"))]
+impl Default for TlsBackend {
+    /// rustls if the `tls` feature is enabled, otherwise the platform's OpenSSL
+    fn default() -> Self {
+        #[cfg(feature = "tls")]
+        return TlsBackend::Rustls;
+        #[cfg(not(feature = "tls"))]
+        return TlsBackend::Native;
+    }
+}
+
+/// TLS settings for connecting to a cluster secured with TLS, see [`EtcdClientBuilder::tls`].
+///
+/// Certificates and keys are PEM encoded. One client uses a single [`TlsBackend`] for all of
+/// its connections, even if both TLS features are enabled.
+#[cfg(any(feature = "tls", feature = "tls-native"))]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TlsConfig {
+    /// TLS implementation to connect with
+    pub backend: TlsBackend,
+    /// Certificate of the CA which signed the servers' certificates
+    pub ca_certificate: Option<Vec<u8>>,
+    /// Certificate to authenticate the client with, for clusters checking client certificates
+    pub client_certificate: Option<Vec<u8>>,
+    /// Private key of the client certificate
+    pub client_key: Option<Vec<u8>>,
+    /// Name to verify the servers' certificates against, instead of each endpoint's host
+    pub domain_name: Option<String>,
+}
 
-            yield request;
+#[cfg(any(feature = "tls", feature = "tls-native"))]
+impl TlsConfig {
+    /// TLS settings for connecting to `url`
+    fn client_config(&self, url: &str) -> EtcdResult<tonic::transport::ClientTlsConfig> {
+        let mut config = match self.backend {
+            #[cfg(feature = "tls")]
+            TlsBackend::Rustls => tonic::transport::ClientTlsConfig::with_rustls(),
+            #[cfg(feature = "tls-native")]
+            TlsBackend::Native => tonic::transport::ClientTlsConfig::with_openssl(),
         };
+        if let Some(ca_certificate) = &self.ca_certificate {
+            config.ca_certificate(tonic::transport::Certificate::from_pem(ca_certificate));
+        }
+        match (&self.client_certificate, &self.client_key) {
+            (Some(certificate), Some(key)) => {
+                config.identity(tonic::transport::Identity::from_pem(certificate, key));
+            }
+            (None, None) => {}
+            _ => return Err("a client certificate and its key must be given together".into()),
+        }
+        config.domain_name(match &self.domain_name {
+            Some(domain_name) => domain_name.clone(),
+            None => endpoint_host(url)?,
+        });
+        Ok(config)
+    }
+}
+
+/// Host part of the endpoint `url`, which its certificate is expected to be issued for
+#[cfg(any(feature = "tls", feature = "tls-native"))]
+fn endpoint_host(url: &str) -> EtcdResult<String> {
+    let uri: http::Uri = url.parse()?;
+    match uri.host() {
+        Some(host) => Ok(host.to_string()),
+        None => Err(format!("endpoint {:?} has no host", url).into()),
+    }
+}
+
+/// When and how often to retry requests which fail because a member is unavailable or did
+/// not answer in time, see [`EtcdClientBuilder::retry`].
+///
+/// Only requests which are safe to repeat are retried by default: reading ranges, listing
+/// members, member status and creating watches. Mutations may have been applied even though
+/// the request failed, so they are only retried if `retry_mutations` is set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts made in total, including the first; 1 disables retries
+    pub max_attempts: u32,
+    /// Backoff before the first retry, which doubles with every further retry
+    pub initial_backoff: Duration,
+    /// Upper bound on the backoff between attempts
+    pub max_backoff: Duration,
+    /// Also retry puts and deletes
+    pub retry_mutations: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(1),
+            retry_mutations: false,
+        }
+    }
+}
 
-        let response = self.watch_client.watch(request).await?;
-        let inbound = response.into_inner();
+impl RetryPolicy {
+    /// Never retry requests
+    pub fn disabled() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
 
-        Ok(inbound)
+    /// Backoff before retry number `retry`, counting from 1, without jitter
+    fn backoff(&self, retry: u32) -> Duration {
+        let doublings = retry.saturating_sub(1);
+        let factor = 1u32.checked_shl(doublings).unwrap_or(u32::MAX);
+        self.initial_backoff
+            .checked_mul(factor)
+            .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff))
     }
+}
+
+/// A random number, good enough to spread out retries but not for anything secret
+pub(crate) fn random() -> u64 {
+    use std::hash::{BuildHasher, Hasher};
+
+    std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish()
+}
+
+/// A random duration between half of `backoff` and `backoff`, so that clients which failed
+/// together do not retry together
+fn jitter(backoff: Duration) -> Duration {
+    let half = backoff / 2;
+    let spread = (backoff - half).as_nanos() as u64;
+    half + Duration::from_nanos(random() % (spread + 1))
+}
+
+/// Whether a request failing with `status` may succeed if it is sent again
+fn is_retryable(status: &tonic::Status) -> bool {
+    matches!(
+        status.code(),
+        tonic::Code::Unavailable | tonic::Code::DeadlineExceeded
+    )
+}
+
+/// How an operation's requests are retried
+#[derive(Debug, Clone)]
+struct Retries {
+    policy: RetryPolicy,
+    /// Where each retry is reported
+    diagnostics: Diagnostics,
+    /// The operation retried, as in its error context
+    operation: &'static str,
+    /// The clock backoff is waited out on
+    clock: SharedClock,
+}
+
+/// Whether a request made with [`EtcdClient::call`] is sent again when it fails
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Retry {
+    /// The request is only sent once
+    Never,
+    /// The request is retried unless retries are disabled
+    Idempotent,
+    /// The request is retried only if the policy retries mutations
+    #[cfg_attr(
+        not(any(feature = "auth", feature = "cluster", feature = "maintenance")),
+        allow(dead_code)
+    )]
+    Mutation,
+}
+
+/// A response carrying the header every etcd response starts with
+trait Reply {
+    fn header(&self) -> Option<&etcdserver::ResponseHeader>;
+}
+
+macro_rules! reply {
+    ($($response:path),* $(,)?) => {
+        $(
+            impl Reply for $response {
+                fn header(&self) -> Option<&etcdserver::ResponseHeader> {
+                    self.header.as_ref()
+                }
+            }
+        )*
+    };
+}
 
-    /*
-    pub async fn status(&mut self) -> EtcdResult<etcdserver::StatusResponse> {
-        let request = etcdserver::StatusRequest {};
-        let response = self.status_client.status(request).await?;
-        Ok(response.into_inner())
+reply!(
+    etcdserver::RangeResponse,
+    etcdserver::PutResponse,
+    etcdserver::DeleteRangeResponse,
+    etcdserver::TxnResponse,
+    etcdserver::CompactionResponse,
+    etcdserver::LeaseGrantResponse,
+    etcdserver::LeaseRevokeResponse,
+    etcdserver::LeaseTimeToLiveResponse,
+    etcdserver::LeaseLeasesResponse,
+    etcdserver::MemberAddResponse,
+    etcdserver::MemberRemoveResponse,
+    etcdserver::MemberUpdateResponse,
+    etcdserver::MemberListResponse,
+    etcdserver::MemberPromoteResponse,
+    etcdserver::StatusResponse,
+    etcdserver::HashKvResponse,
+    etcdserver::AlarmResponse,
+    etcdserver::DowngradeResponse,
+    etcdserver::AuthEnableResponse,
+    etcdserver::AuthDisableResponse,
+    etcdserver::AuthenticateResponse,
+    etcdserver::AuthUserListResponse,
+    etcdserver::AuthRoleListResponse,
+);
+#[cfg(feature = "election")]
+reply!(
+    v3election::CampaignResponse,
+    v3election::ProclaimResponse,
+    v3election::LeaderResponse,
+    v3election::ResignResponse,
+);
+
+/// Send a request with `rpc`, retrying it according to `retries`. `idempotent` requests are
+/// retried unless retries are disabled, others only if the policy retries mutations. The
+/// number of the attempt being made is kept in `attempts`.
+async fn with_retries<F, R, T>(
+    retries: Retries,
+    idempotent: bool,
+    attempts: &mut u32,
+    mut rpc: F,
+) -> Result<T, tonic::Status>
+where
+    F: FnMut() -> R,
+    R: std::future::Future<Output = Result<T, tonic::Status>>,
+{
+    let policy = retries.policy;
+    let retry = idempotent || policy.retry_mutations;
+    let mut attempt = 1;
+    loop {
+        *attempts = attempt;
+        match rpc().await {
+            Err(status) if retry && attempt < policy.max_attempts && is_retryable(&status) => {
+                let delay = jitter(policy.backoff(attempt));
+                retries.diagnostics.emit(|| Diagnostic::Retry {
+                    operation: retries.operation,
+                    attempt,
+                    delay,
+                    error: format!("{:?}: {}", status.code(), status.message()),
+                });
+                retries.clock.delay_for(delay).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
     }
+}
+
+/// Exponential backoff with jitter between attempts to re-establish a broken stream, such as
+/// a watch or a lease keep-alive
+#[derive(Debug, Clone)]
+pub(crate) struct Backoff {
+    policy: RetryPolicy,
+    retries: u32,
+}
 
-    pub async fn server_alarms(&mut self) -> EtcdResult<etcdserver::AlarmResponse> {
-        let mut request = etcdserver::AlarmRequest::default();
-        request.set_action(etcdserver::alarm_request::AlarmAction::Get);
-        let response = self.status_client.alarm(request).await?;
-        Ok(response.into_inner())
+impl Backoff {
+    pub(crate) fn new() -> Self {
+        Backoff {
+            policy: RetryPolicy {
+                max_attempts: u32::MAX,
+                initial_backoff: Duration::from_millis(100),
+                max_backoff: Duration::from_secs(5),
+                retry_mutations: false,
+            },
+            retries: 0,
+        }
     }
 
-    pub async fn cluster_members(&mut self) -> EtcdResult<etcdserver::MemberListResponse> {
-        let request = etcdserver::MemberListRequest {};
-        let response = self.cluster_client.member_list(request).await?;
-        Ok(response.into_inner())
+    /// Start again from the initial backoff, once the stream is working again
+    pub(crate) fn reset(&mut self) {
+        self.retries = 0;
     }
-    */
 
-    pub fn cluster<'a>(&'a mut self) -> Cluster<'a, tonic::transport::channel::Channel> {
-        Cluster {
-            client: self,
+    /// Backoff before the next attempt
+    pub(crate) fn next(&mut self) -> Duration {
+        self.retries = self.retries.saturating_add(1);
+        jitter(self.policy.backoff(self.retries))
+    }
+
+    /// Wait out the backoff before the next attempt
+    pub(crate) async fn wait(&mut self) {
+        runtime::delay_for(self.next()).await;
+    }
+}
+
+/// A budget for the rate at which the client sends requests, see
+/// [`EtcdClientBuilder::read_rate_limit`] and [`EtcdClientBuilder::write_rate_limit`].
+///
+/// Requests over budget wait for it to refill rather than failing, so a hot loop in the
+/// application slows down instead of overwhelming the cluster. The budget refills at
+/// `per_second` requests a second, and up to `burst` requests can be sent at once after the
+/// client has been quiet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Requests allowed per second, on average
+    pub per_second: u32,
+    /// Requests allowed at once
+    pub burst: u32,
+}
+
+impl RateLimit {
+    /// Allow `per_second` requests a second, in bursts of up to as many
+    pub fn per_second(per_second: u32) -> Self {
+        RateLimit {
+            per_second,
+            burst: per_second,
         }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Which budget a request is taken from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Budget {
+    Read,
+    Write,
+}
 
-    #[tokio::test]
-    async fn connecting_to_instance() {
-        let _client = EtcdClient::connect("http://127.0.0.1:2379").await.unwrap();
+/// A token bucket enforcing a [`RateLimit`], shared by every clone of a client
+#[derive(Debug)]
+struct Limiter {
+    limit: RateLimit,
+    /// Tokens left and when they were counted. Requests waiting for a token are counted
+    /// against the bucket straight away, so it goes negative while they wait.
+    bucket: StdMutex<(f64, Instant)>,
+}
+
+impl Limiter {
+    fn new(limit: RateLimit) -> Self {
+        Limiter {
+            limit,
+            bucket: StdMutex::new((f64::from(limit.burst), Instant::now())),
+        }
     }
 
-    #[tokio::test]
-    async fn test_ranges() {
-        let mut client = EtcdClient::connect("http://127.0.0.1:2379").await.unwrap();
-        let mut range = client.range("foo", None);
+    /// Take a token at `now`, returning how long to wait before the request may be sent
+    fn take(&self, now: Instant) -> Duration {
+        let rate = f64::from(self.limit.per_second.max(1));
+        let burst = f64::from(self.limit.burst.max(1));
+        let mut bucket = self.bucket.lock().unwrap();
+        let (tokens, counted) = *bucket;
+        let refilled = now.saturating_duration_since(counted).as_secs_f64() * rate;
+        let tokens = (tokens + refilled).min(burst) - 1.0;
+        *bucket = (tokens, now);
+        if tokens >= 0.0 {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_secs_f64(-tokens / rate)
+        }
+    }
+
+    /// Take a token at `now` only if one is left, so the request may be sent straight away
+    /// without counting against any requests waiting for one
+    fn try_take(&self, now: Instant) -> bool {
+        let rate = f64::from(self.limit.per_second.max(1));
+        let burst = f64::from(self.limit.burst.max(1));
+        let mut bucket = self.bucket.lock().unwrap();
+        let (tokens, counted) = *bucket;
+        let refilled = now.saturating_duration_since(counted).as_secs_f64() * rate;
+        let tokens = (tokens + refilled).min(burst);
+        if tokens < 1.0 {
+            return false;
+        }
+        *bucket = (tokens - 1.0, now);
+        true
+    }
+}
 
-        range.put("bar").await.unwrap();
+/// A bound on the requests a client has in flight at once, shared by every clone of the client
+#[derive(Debug)]
+struct InFlightLimit {
+    /// A token for every request which may be sent, taken while it is in flight
+    tokens: futures::lock::Mutex<mpsc::Receiver<()>>,
+    returned: mpsc::Sender<()>,
+}
 
-        let keys = range.get().await.unwrap();
+impl InFlightLimit {
+    fn new(limit: usize) -> Self {
+        let limit = limit.max(1);
+        let (mut returned, tokens) = mpsc::channel(limit);
+        for _ in 0..limit {
+            // The channel has room for every token
+            let _ = returned.try_send(());
+        }
+        InFlightLimit {
+            tokens: futures::lock::Mutex::new(tokens),
+            returned,
+        }
+    }
 
-        assert_eq!(keys["foo"], "bar");
+    /// Wait for a request to be allowed in flight, returning where to give its token back
+    async fn acquire(&self) -> mpsc::Sender<()> {
+        use futures::StreamExt;
 
-        // Test delete a range
-        range.delete().await.unwrap();
+        // The sender is held here, so the channel never closes
+        let _ = self.tokens.lock().await.next().await;
+        self.returned.clone()
+    }
 
-        // Have to get the range again as `delete` drops the range.
-        let mut range = client.range("foo", None);
-        let keys = range.get().await.unwrap();
-        assert!(keys.is_empty());
+    /// A token for a request if one is free and no other request is waiting for one
+    fn try_acquire(&self) -> Option<mpsc::Sender<()>> {
+        let mut tokens = self.tokens.try_lock()?;
+        tokens.try_recv().ok().map(|()| self.returned.clone())
     }
+}
 
-    #[tokio::test]
-    async fn test_watching() {
-        use futures::channel::oneshot;
-        use std::sync::atomic::{AtomicBool, Ordering};
-        use std::sync::Arc;
+/// A request or stream in flight, which stops counting against the client's [`Shutdown`]
+/// and any [`InFlightLimit`] when dropped
+#[derive(Debug)]
+pub(crate) struct InFlight {
+    shutdown: Arc<Shutdown>,
+    /// Where to give back the token taken from the in-flight limit, if there is one
+    token: Option<mpsc::Sender<()>>,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if let Some(token) = &mut self.token {
+            // Every sender has a slot of its own, so there is always room for the token
+            let _ = token.try_send(());
+        }
+        self.shutdown.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
 
-        let mut client = EtcdClient::connect("http://127.0.0.1:2379").await.unwrap();
+/// How often [`EtcdClient::close`] checks whether the requests in flight have finished
+const CLOSE_POLL_INTERVAL: Duration = Duration::from_millis(10);
 
-        // Get a stream of events from etcd for the "foo" key
-        let mut stream = client.watch("foo").await.unwrap();
+/// Resolves once a client has been closed
+pub(crate) type Closed = future::Shared<oneshot::Receiver<()>>;
 
-        // Channel coordinates the watch task, to wait until the value has been seen, then end the
-        // task. This ensures we do not need sleeps in the test, but that the two separate spawned
-        // tasks can run concurrently.
-        let (tx, mut rx) = oneshot::channel();
+/// Shutdown state shared by a client and everything created from it, see
+/// [`EtcdClient::close`]
+pub(crate) struct Shutdown {
+    closed: AtomicBool,
+    /// Requests and streams in flight, which closing the client waits for
+    in_flight: AtomicUsize,
+    signal: Closed,
+    trigger: StdMutex<Option<oneshot::Sender<()>>>,
+}
 
-        // Have we seen a change yet?
-        let seen = Arc::new(AtomicBool::new(false));
-        let cp = seen.clone();
+impl Default for Shutdown {
+    fn default() -> Self {
+        let (trigger, signal) = oneshot::channel();
+        Shutdown {
+            closed: AtomicBool::new(false),
+            in_flight: AtomicUsize::new(0),
+            signal: signal.shared(),
+            trigger: StdMutex::new(Some(trigger)),
+        }
+    }
+}
 
-        // Spawn a task to poll any changes
-        tokio::spawn(async move {
-            while let Some(_val) = stream.message().await.unwrap() {
-                cp.store(true, Ordering::SeqCst);
-                tx.send(true).unwrap();
+impl std::fmt::Debug for Shutdown {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Shutdown")
+            .field("closed", &self.closed)
+            .field("in_flight", &self.in_flight)
+            .finish()
+    }
+}
+
+impl Shutdown {
+    /// Count a request or stream as in flight until the returned guard is dropped. Fails
+    /// once the client has been closed.
+    pub(crate) fn track(self: &Arc<Self>) -> EtcdResult<InFlight> {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        let in_flight = InFlight {
+            shutdown: self.clone(),
+            token: None,
+        };
+        if self.closed.load(Ordering::Relaxed) {
+            return Err("the client is closed".into());
+        }
+        Ok(in_flight)
+    }
+
+    /// Resolves once the client has been closed
+    pub(crate) fn closed(&self) -> Closed {
+        self.signal.clone()
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::Relaxed);
+        self.trigger.lock().unwrap().take();
+    }
+}
+
+/// Keep-alive settings for clients which may sit idle for long periods, see
+/// [`EtcdClientBuilder::keep_alive`].
+///
+/// Connections which carry no traffic are liable to be dropped silently by NATs and load
+/// balancers, so that the next request only fails after the connection times out. Keeping
+/// them alive stops idle connections from being dropped and notices broken ones, so they are
+/// re-established before they are needed. The client pings the cluster with a status request
+/// every `interval`; with several endpoints the pings are balanced across them like any other
+/// request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeepAlive {
+    /// Time between pings
+    pub interval: Duration,
+    /// Give up on a ping which has not been answered within `timeout`
+    pub timeout: Duration,
+    /// Keep pinging while the client sends no requests of its own. Otherwise pings are only
+    /// sent after intervals in which the client was in use.
+    pub while_idle: bool,
+}
+
+impl Default for KeepAlive {
+    fn default() -> Self {
+        KeepAlive {
+            interval: Duration::from_secs(30),
+            timeout: Duration::from_secs(10),
+            while_idle: true,
+        }
+    }
+}
+
+/// Ping the cluster through `status_client` according to `settings` until the returned sender
+/// is dropped. `active` is set whenever the client sends a request.
+fn keep_alive<T: Transport>(
+    settings: KeepAlive,
+    mut status_client: client::MaintenanceClient<T>,
+    active: Arc<AtomicBool>,
+) -> oneshot::Sender<()> {
+    let (stop_tx, mut stop_rx) = oneshot::channel();
+    runtime::spawn(async move {
+        let mut ticks = runtime::Interval::new(settings.interval);
+        loop {
+            let tick = ticks.next();
+            futures::pin_mut!(tick);
+            if let Either::Right(_) = future::select(tick, &mut stop_rx).await {
                 break;
             }
-        });
+            if !settings.while_idle && !active.load(Ordering::Relaxed) {
+                continue;
+            }
+
+            let ping = status_client.status(etcdserver::StatusRequest {});
+            // A failed ping has the connection re-established, there is nothing else to do
+            let _ = runtime::timeout(ping, settings.timeout).await;
+            // Pings themselves do not count as use of the client
+            active.store(false, Ordering::Relaxed);
+        }
+    });
+    stop_tx
+}
+
+/// An HTTP proxy to connect to the cluster through, see [`EtcdClientBuilder::proxy`].
+///
+/// Connections are tunnelled through the proxy with CONNECT requests, so TLS is still
+/// negotiated with the cluster members themselves. Proxies which require authentication are
+/// not supported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Proxy {
+    /// URL of the proxy, such as `http://proxy.example.com:3128`
+    pub url: String,
+    /// Hosts connected to directly instead of through the proxy. An entry matches the host
+    /// itself and its subdomains, and `*` matches every host.
+    pub no_proxy: Vec<String>,
+}
 
-        // Get a range so we can change the "foo" node value
-        let mut range = client.range("foo", None);
-        range.put("bar").await.unwrap();
+impl Proxy {
+    /// Connect to every host through the proxy at `url`
+    pub fn new<S>(url: S) -> Self
+    where
+        S: Into<String>,
+    {
+        Proxy {
+            url: url.into(),
+            no_proxy: Vec::new(),
+        }
+    }
+
+    /// The proxy named by the `HTTPS_PROXY` environment variable, which is used for plain
+    /// and TLS connections alike, bypassed for the hosts listed in `NO_PROXY`. The lowercase
+    /// variables are read if the uppercase ones are not set. `None` if no proxy is set.
+    pub fn from_env() -> Option<Self> {
+        let var = |name: &str| {
+            std::env::var(name)
+                .or_else(|_| std::env::var(name.to_lowercase()))
+                .ok()
+                .filter(|value| !value.is_empty())
+        };
+        let no_proxy = var("NO_PROXY").unwrap_or_default();
+        Some(Proxy {
+            url: var("HTTPS_PROXY")?,
+            no_proxy: no_proxy
+                .split(',')
+                .map(str::trim)
+                .filter(|host| !host.is_empty())
+                .map(String::from)
+                .collect(),
+        })
+    }
 
-        // Wait until the task finishes
-        rx.try_recv().unwrap();
+    /// Whether connections to `host` bypass the proxy
+    fn bypasses(&self, host: &str) -> bool {
+        self.no_proxy.iter().any(|entry| {
+            let entry = entry.trim_start_matches('.');
+            entry == "*"
+                || host.eq_ignore_ascii_case(entry)
+                || (host.len() > entry.len()
+                    && host.as_bytes()[host.len() - entry.len() - 1] == b'.'
+                    && host[host.len() - entry.len()..].eq_ignore_ascii_case(entry))
+        })
+    }
 
-        // Check that we've seen the change
-        assert!(seen.load(Ordering::SeqCst));
+    /// Where to tunnel connections to the endpoint `url` to, `None` if they bypass the proxy
+    fn target(&self, url: &str) -> EtcdResult<Option<tunnel::Target>> {
+        let (host, port) = host_and_port(url, 2379)?;
+        if self.bypasses(&host) {
+            return Ok(None);
+        }
+        let (proxy_host, proxy_port) = host_and_port(&self.url, 80)?;
+        Ok(Some(tunnel::Target::Proxy {
+            proxy: format!("{}:{}", proxy_host, proxy_port),
+            authority: format!("{}:{}", host, port),
+        }))
     }
+}
 
-    #[tokio::test]
-    async fn test_listing_members() {
-        // There is only one member in the test cluster, so we check this.
-        let mut client = EtcdClient::connect("http://127.0.0.1:2379").await.unwrap();
+/// Host and port of `url`, with `default_port` if it has none
+fn host_and_port(url: &str, default_port: u16) -> EtcdResult<(String, u16)> {
+    let uri: http::Uri = url.parse()?;
+    match uri.host() {
+        Some(host) => Ok((host.to_string(), uri.port_u16().unwrap_or(default_port))),
+        None => Err(format!("{:?} has no host", url).into()),
+    }
+}
 
-        let mut cluster_info = client.cluster();
-        let members = cluster_info.members().await.unwrap();
-        assert_eq!(members.len(), 1);
+/// How requests are spread across a client's endpoints, see [`EtcdClientBuilder::balance`].
+///
+/// Endpoints are only skipped for being unhealthy while their health is tracked, see
+/// [`EtcdClient::track_endpoint_health`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BalancePolicy {
+    /// Spread requests across every healthy endpoint
+    #[default]
+    RoundRobin,
+    /// Send every request to the first healthy endpoint, in the order the endpoints were
+    /// given or, after a sync, sorted by URL
+    PickFirst,
+    /// Spread requests across the healthy endpoints with the best priority, falling back to
+    /// endpoints with worse priorities once none of those are healthy. Lower values are
+    /// better, and endpoints without a priority rank below every endpoint with one; see
+    /// [`EtcdClientBuilder::endpoint_priority`].
+    Priority,
+}
+
+/// How reads are made unless an operation asks otherwise, see
+/// [`EtcdClientBuilder::read_consistency`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReadConsistency {
+    /// Confirm with the leader that the member serving the read is up to date, so the read
+    /// sees every write made before it
+    #[default]
+    Linearizable,
+    /// Read from whichever member serves the request, faster and working without a leader,
+    /// but possibly stale, see [`Range::serializable`]
+    Serializable,
+}
+
+/// The class of traffic an operation belongs to, for the client's own rate limits and bound
+/// on requests in flight to favour interactive requests over background jobs, see
+/// [`EtcdClientBuilder::traffic_class`]. The class is sent in the
+/// [`TRAFFIC_CLASS_METADATA`] gRPC metadata, for proxies and gateways in front of the cluster.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TrafficClass {
+    /// Requests someone is waiting on, which wait their turn under the client's limits
+    #[default]
+    Interactive,
+    /// Requests of jobs which can be retried later, such as bulk loads, backups and
+    /// compactions. Once the client's limits are reached they are shed with
+    /// [`Error::Shed`] instead of waiting, so they only use the capacity left over.
+    Background,
+}
+
+impl TrafficClass {
+    /// The class as sent in the [`TRAFFIC_CLASS_METADATA`] metadata
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TrafficClass::Interactive => "interactive",
+            TrafficClass::Background => "background",
+        }
+    }
+}
+
+impl std::fmt::Display for TrafficClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Name of the gRPC metadata recording the [`TrafficClass`] of a request
+pub const TRAFFIC_CLASS_METADATA: &str = "etcd3-traffic-class";
+
+/// A hook run on the headers of every request a client sends, see
+/// [`EtcdClientBuilder::interceptor`]
+pub type Interceptor = Arc<dyn Fn(&mut http::HeaderMap) + Send + Sync>;
+
+/// Interceptors run on every request, in the order they were added
+#[derive(Clone, Default)]
+struct Interceptors(Vec<Interceptor>);
+
+impl std::fmt::Debug for Interceptors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} interceptors", self.0.len())
+    }
+}
+
+/// Settings applied to every connection the client makes, including to the endpoints found
+/// by syncing with the member list
+#[derive(Debug, Clone, Default)]
+struct ChannelConfig {
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    retry: RetryPolicy,
+    keep_alive: Option<KeepAlive>,
+    proxy: Option<Proxy>,
+    balance: BalancePolicy,
+    /// Budgets requests are taken from, shared with every client built from the config
+    read_limit: Option<Arc<Limiter>>,
+    write_limit: Option<Arc<Limiter>>,
+    /// Bound on the requests in flight at once, across every endpoint
+    in_flight: Option<Arc<InFlightLimit>>,
+    /// Bound on the requests in flight on each connection
+    concurrent_streams: Option<usize>,
+    /// Settings for the endpoint health tracker's circuits, if it uses a circuit breaker
+    circuit_breaker: Option<CircuitBreaker>,
+    /// Delay after which serializable reads are hedged
+    hedge: Option<Duration>,
+    /// How reads are made unless an operation asks otherwise
+    read_consistency: ReadConsistency,
+    /// Class of the operations which do not give their own
+    traffic_class: TrafficClass,
+    /// How often the lag of each watch is measured, if it is
+    #[cfg(feature = "metrics")]
+    watch_lag: Option<Duration>,
+    /// Range reads in flight, shared by every clone of the client, if it coalesces them
+    coalescer: Option<Arc<kv::Coalescer>>,
+    /// Rules every put must follow, checked before it is sent
+    key_schema: Option<Arc<schema::KeySchema>>,
+    /// Leave keys out of the context attached to errors
+    redact_keys: bool,
+    /// Priorities of endpoints, by URL, for [`BalancePolicy::Priority`]
+    priorities: HashMap<String, u32>,
+    /// Shutdown state shared with everything created from the client
+    shutdown: Arc<Shutdown>,
+    /// Set whenever a request is sent, to tell when the client is idle
+    active: Arc<AtomicBool>,
+    interceptors: Interceptors,
+    hooks: Hooks,
+    /// Connection state shared with everything created from the client
+    connection: Arc<Connection>,
+    diagnostics: Diagnostics,
+    /// Where timeouts, backoff and lease keep-alives take the time from
+    clock: SharedClock,
+    /// gRPC metadata sent with every request
+    metadata: http::HeaderMap,
+    /// Tunnels to endpoints the transport cannot dial itself, by endpoint URL. They are
+    /// closed once the last client using them is dropped.
+    tunnels: Arc<StdMutex<HashMap<String, tunnel::Tunnel>>>,
+    /// Authentication token sent with every request
+    token: Option<http::HeaderValue>,
+    #[cfg(any(feature = "tls", feature = "tls-native"))]
+    tls: Option<TlsConfig>,
+}
+
+impl ChannelConfig {
+    fn endpoint(&self, url: &str) -> EtcdResult<Endpoint> {
+        let target = match (tunnel::Target::parse(url)?, &self.proxy) {
+            (None, Some(proxy)) => proxy.target(url)?,
+            (target, _) => target,
+        };
+        let mut endpoint = match target {
+            Some(target) => Endpoint::from_shared(self.tunnel(url, target)?)?,
+            None => Endpoint::from_shared(url.to_string())?,
+        };
+        if let Some(timeout) = self.timeout {
+            endpoint.timeout(timeout);
+        }
+        if let Some(limit) = self.concurrent_streams {
+            endpoint.concurrency_limit(limit);
+        }
+        let token = self.token.clone();
+        let active = match self.keep_alive {
+            Some(keep_alive) if !keep_alive.while_idle => Some(self.active.clone()),
+            _ => None,
+        };
+        let interceptors = self.interceptors.clone();
+        let metadata = self.metadata.clone();
+        let intercepted =
+            !interceptors.0.is_empty() || !metadata.is_empty() || cfg!(feature = "otel");
+        if token.is_some() || active.is_some() || intercepted {
+            endpoint.intercept_headers(move |headers| {
+                // A token given for a single request takes precedence
+                if let Some(token) = &token {
+                    if !headers.contains_key("token") {
+                        headers.insert("token", token.clone());
+                    }
+                }
+                if let Some(active) = &active {
+                    active.store(true, Ordering::Relaxed);
+                }
+                // Metadata given for a single request takes precedence
+                for (key, value) in &metadata {
+                    if !headers.contains_key(key) {
+                        headers.insert(key.clone(), value.clone());
+                    }
+                }
+                #[cfg(feature = "otel")]
+                otel::inject(headers);
+                for interceptor in &interceptors.0 {
+                    interceptor(headers);
+                }
+            });
+        }
+        #[cfg(any(feature = "tls", feature = "tls-native"))]
+        {
+            if let Some(tls) = &self.tls {
+                endpoint.tls_config(&tls.client_config(url)?);
+            }
+        }
+        Ok(endpoint)
+    }
+
+    /// Wait until a request may be sent under the rate limit of its `budget` and the bound on
+    /// requests in flight, if there are any. The request is in flight until the returned
+    /// guard is dropped. Fails once the client has been closed.
+    async fn throttle(&self, budget: Budget) -> EtcdResult<InFlight> {
+        self.throttle_as(budget, self.traffic_class).await
+    }
+
+    /// [`throttle`](Self::throttle) a request of the traffic `class`. Background requests do
+    /// not wait: they fail with [`Error::Shed`] unless they can be sent straight away.
+    async fn throttle_as(&self, budget: Budget, class: TrafficClass) -> EtcdResult<InFlight> {
+        let mut in_flight = self.shutdown.track()?;
+        let limiter = match budget {
+            Budget::Read => &self.read_limit,
+            Budget::Write => &self.write_limit,
+        };
+        if class == TrafficClass::Background {
+            // The bound in flight is checked first: its token goes back if the request is shed
+            // after all, while a rate token taken would be gone for good
+            if let Some(limit) = &self.in_flight {
+                in_flight.token = Some(limit.try_acquire().ok_or(Error::Shed)?);
+            }
+            if let Some(limiter) = limiter {
+                if !limiter.try_take(Instant::now()) {
+                    return Err(Error::Shed);
+                }
+            }
+            return Ok(in_flight);
+        }
+        if let Some(limiter) = limiter {
+            let wait = limiter.take(Instant::now());
+            if wait > Duration::from_secs(0) {
+                runtime::delay_for(wait).await;
+            }
+        }
+        if let Some(limit) = &self.in_flight {
+            in_flight.token = Some(limit.acquire().await);
+        }
+        Ok(in_flight)
+    }
+
+    /// URL of the end of the tunnel to `target` for the endpoint `url`, opening the tunnel if
+    /// it is not open yet
+    fn tunnel(&self, url: &str, target: tunnel::Target) -> EtcdResult<String> {
+        let mut tunnels = self.tunnels.lock().unwrap();
+        let tunnel = match tunnels.entry(url.to_string()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(tunnel::Tunnel::open(target)?),
+        };
+        #[cfg(any(feature = "tls", feature = "tls-native"))]
+        {
+            if self.tls.is_some() {
+                return Ok(format!("https://{}", tunnel.addr()));
+            }
+        }
+        Ok(format!("http://{}", tunnel.addr()))
+    }
+
+    /// Check that at least one of `endpoints` accepts a connection within the connect
+    /// timeout, if there is one
+    async fn check_connectable(&self, endpoints: &[String]) -> EtcdResult<()> {
+        let timeout = match self.connect_timeout {
+            Some(timeout) => timeout,
+            None => return Ok(()),
+        };
+        let mut attempts = Vec::new();
+        for url in endpoints {
+            let endpoint = self.endpoint(url)?;
+            attempts.push(Box::pin(async move { endpoint.connect().await }));
+        }
+        let started = Instant::now();
+        match runtime::timeout(future::select_ok(attempts), timeout).await {
+            Ok(Ok(_)) => Ok(()),
+            Ok(Err(e)) => Err(Error::Transport(e)),
+            Err(_) => Err(Error::timeout("timed out connecting", timeout, started)),
+        }
+    }
+
+    /// How the requests of `operation` are retried
+    fn retries(&self, operation: &'static str) -> Retries {
+        Retries {
+            policy: self.retry,
+            diagnostics: self.diagnostics.clone(),
+            operation,
+            clock: self.clock.clone(),
+        }
+    }
+
+    /// The `endpoints` requests should be routed to under the balancing policy, leaving out
+    /// the `unhealthy` ones unless none are healthy
+    fn routed_endpoints(&self, endpoints: &[String], unhealthy: &[String]) -> Vec<String> {
+        let healthy = healthy_endpoints(endpoints, unhealthy);
+        match self.balance {
+            BalancePolicy::RoundRobin => healthy,
+            BalancePolicy::PickFirst => healthy.into_iter().take(1).collect(),
+            BalancePolicy::Priority => {
+                let priority = |url: &String| self.priorities.get(url).copied();
+                // Endpoints without a priority rank below every endpoint with one
+                let rank = |url: &String| priority(url).unwrap_or(u32::MAX);
+                let best = healthy.iter().map(rank).min();
+                healthy
+                    .into_iter()
+                    .filter(|url| Some(rank(url)) == best)
+                    .collect()
+            }
+        }
+    }
+
+    /// Channel load balancing over `endpoints`. Connections are established lazily.
+    fn balanced_channel(
+        &self,
+        endpoints: &[String],
+    ) -> EtcdResult<tonic::transport::channel::Channel> {
+        let endpoints = endpoints
+            .iter()
+            .map(|url| self.endpoint(url))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(tonic::transport::channel::Channel::balance_list(
+            endpoints.into_iter(),
+        ))
+    }
+
+    /// Connection to the single endpoint `url`, such as a member's client URL, made with the
+    /// same settings as the client's own connections, within the connect timeout
+    #[cfg(feature = "maintenance")]
+    async fn member_channel(&self, url: &str) -> EtcdResult<tonic::transport::channel::Channel> {
+        let endpoint = self.endpoint(url)?;
+        within_connect_timeout(self.connect_timeout, async {
+            Ok(endpoint.connect().await?)
+        })
+        .await
+    }
+}
+
+/// Builder for an [`EtcdClient`] with connection settings, see [`EtcdClient::builder`].
+///
+/// The settings also apply to the connections made to endpoints found by syncing with the
+/// member list.
+///
+/// The transport does not limit the size of messages in either direction, so there is no
+/// maximum message size to configure: large range responses are never rejected for their
+/// size. Requests are still subject to the server's own limit, `--max-request-bytes`.
+#[derive(Clone, Default)]
+pub struct EtcdClientBuilder {
+    endpoints: Vec<String>,
+    credentials: Option<(String, String)>,
+    metadata: Vec<(String, String)>,
+    /// Domain to look up the cluster's endpoints under
+    discovery_srv: Option<String>,
+    config: ChannelConfig,
+}
+
+/// Prints the user to authenticate as but not the password, and the names of the metadata
+/// sent but not their values
+impl std::fmt::Debug for EtcdClientBuilder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let metadata: Vec<&str> = self.metadata.iter().map(|(key, _)| &key[..]).collect();
+        f.debug_struct("EtcdClientBuilder")
+            .field("endpoints", &self.endpoints)
+            .field("user", &self.credentials.as_ref().map(|(user, _)| user))
+            .field("metadata", &metadata)
+            .field("discovery_srv", &self.discovery_srv)
+            .finish_non_exhaustive()
+    }
+}
+
+impl EtcdClientBuilder {
+    /// Add an endpoint to connect through. Requests are balanced round-robin across every
+    /// endpoint added.
+    ///
+    /// `url` may also be a comma separated list of endpoints, the format endpoints are usually
+    /// given in through environment variables, such as
+    /// `https://a:2379,https://b:2379,https://c:2379`. Endpoints which were already added are
+    /// skipped, and each one is checked to be a valid URL when connecting.
+    pub fn endpoint<S>(mut self, url: S) -> Self
+    where
+        S: Into<String>,
+    {
+        for url in url.into().split(',').map(str::trim) {
+            if !url.is_empty() && !self.endpoints.iter().any(|added| added == url) {
+                self.endpoints.push(url.to_string());
+            }
+        }
+        self
+    }
+
+    /// Add several endpoints to connect through, see [`endpoint`](Self::endpoint)
+    pub fn endpoints<I, S>(mut self, urls: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        for url in urls {
+            self = self.endpoint(url);
+        }
+        self
+    }
+
+    /// Builder configured from the environment variables etcdctl reads, so a program connects
+    /// to the same cluster as etcdctl would in the same environment:
+    ///
+    /// * `ETCDCTL_ENDPOINTS`, comma separated, or `ETCDCTL_DISCOVERY_SRV`. Endpoints without
+    ///   a scheme use `https` if TLS is configured. Defaults to `127.0.0.1:2379`.
+    /// * `ETCDCTL_CACERT`, `ETCDCTL_CERT` and `ETCDCTL_KEY`, paths of PEM files, which need
+    ///   the `tls` or `tls-native` feature
+    /// * `ETCDCTL_USER`, as `user:password` or with `ETCDCTL_PASSWORD`
+    /// * `ETCDCTL_DIAL_TIMEOUT`, `ETCDCTL_COMMAND_TIMEOUT`, `ETCDCTL_KEEPALIVE_TIME` and
+    ///   `ETCDCTL_KEEPALIVE_TIMEOUT`, as durations such as `5s`
+    ///
+    /// Fails if a variable has an invalid value, a file cannot be read, or
+    /// `ETCDCTL_INSECURE_SKIP_TLS_VERIFY` is set, which is not supported.
+    pub fn from_env() -> EtcdResult<Self> {
+        env::builder(|name| std::env::var(name).ok())
+    }
+
+    /// Builder configured from the TOML or YAML file at `path`, see [`ClientConfig`]
+    #[cfg(feature = "config")]
+    pub fn from_config_file<P>(path: P) -> EtcdResult<Self>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        ClientConfig::from_file(path)?.builder()
+    }
+
+    /// Look up the cluster's endpoints in the SRV records published under `domain` while
+    /// connecting, like `etcdctl --discovery-srv`: `_etcd-client-ssl._tcp.<domain>` for
+    /// TLS endpoints and `_etcd-client._tcp.<domain>` for plain ones. They are added to any
+    /// endpoints given directly.
+    pub fn discovery_srv<S>(mut self, domain: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.discovery_srv = Some(domain.into());
+        self
+    }
+
+    /// Spread requests across the endpoints according to `policy`, instead of round-robin
+    pub fn balance(mut self, policy: BalancePolicy) -> Self {
+        self.config.balance = policy;
+        self
+    }
+
+    /// Give the endpoint `url` a priority, for the [`BalancePolicy::Priority`] policy. Lower
+    /// priorities are preferred, so members in the same zone as the client might be given
+    /// priority 0 and members elsewhere priority 1. The endpoint does not need to have been
+    /// added, so members found by syncing can be given priorities too.
+    pub fn endpoint_priority<S>(mut self, url: S, priority: u32) -> Self
+    where
+        S: Into<String>,
+    {
+        self.config.priorities.insert(url.into(), priority);
+        self
+    }
+
+    /// Hedge serializable reads: if a member has not answered one within `delay`, send it to
+    /// a second endpoint as well and take whichever response arrives first. This cuts the
+    /// tail latency caused by a slow member at the cost of some extra reads. Only applies
+    /// with several endpoints. See [`Range::serializable`].
+    pub fn hedge_reads(mut self, delay: Duration) -> Self {
+        self.config.hedge = Some(delay);
+        self
+    }
+
+    /// Make reads `consistency` unless they ask otherwise, with [`Range::serializable`] or
+    /// [`Range::linearizable`]. Reads are linearizable by default.
+    ///
+    /// This applies to every read of keys: ranges, typed values, dumps, and the reads of the
+    /// recipes which list keys and follow changes to them, such as [`discovery`], informers,
+    /// mirrors and config watchers, which carry on watching from the revision they read at
+    /// either way. Recipes coordinating processes, such as locks, elections, queues, barriers
+    /// and software transactional memory, always read linearizably, as they are only correct
+    /// reading the latest revision.
+    pub fn read_consistency(mut self, consistency: ReadConsistency) -> Self {
+        self.config.read_consistency = consistency;
+        self
+    }
+
+    /// Measure how many revisions the consumer of each watch is behind the store every
+    /// `interval`, and record it in the [`metrics::WATCH_LAG`] gauge, to see which consumers
+    /// are falling behind.
+    ///
+    /// Each watch asks the server for its progress every `interval`, so it keeps up with the
+    /// store's revision while it sees no events, and reads the store's current revision with
+    /// a cheap range. The lag is the revisions between the last response the consumer took
+    /// from the watch and the store, so consumers of busy watches which are keeping up still
+    /// show a lag of up to the revisions written in one `interval`.
+    #[cfg(feature = "metrics")]
+    pub fn watch_lag_interval(mut self, interval: Duration) -> Self {
+        self.config.watch_lag = Some(interval);
+        self
+    }
+
+    /// Leave keys out of the [`ErrorContext`] attached to errors, giving only their length,
+    /// for keys which should not end up in logs
+    pub fn redact_keys(mut self) -> Self {
+        self.config.redact_keys = true;
+        self
+    }
+
+    /// Check every put, including those in transactions, against `schema` before sending it,
+    /// failing those which break it with [`Error::SchemaViolation`]. See [`schema`].
+    pub fn key_schema(mut self, schema: schema::KeySchema) -> Self {
+        self.config.key_schema = Some(Arc::new(schema));
+        self
+    }
+
+    /// Send the gRPC metadata `key: value` with every request, for example to tag requests
+    /// with their source or tenant for a gateway in front of the cluster. Ranges can override
+    /// it for a single operation, see [`Range::metadata`].
+    pub fn metadata<K, V>(mut self, key: K, value: V) -> Self
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.metadata.push((key.into(), value.into()));
+        self
+    }
+
+    /// Run `interceptor` on the headers of every request sent by the client, to every service
+    /// and endpoint, for example to add authentication for a gateway in front of the cluster
+    /// or to trace requests. Interceptors run in the order they were added, after the
+    /// client's own headers have been set.
+    ///
+    /// To wrap the connections in `tower` layers instead, connect with
+    /// [`connect_with_layer`](Self::connect_with_layer).
+    pub fn interceptor<F>(mut self, interceptor: F) -> Self
+    where
+        F: Fn(&mut http::HeaderMap) + Send + Sync + 'static,
+    {
+        self.config.interceptors.0.push(Arc::new(interceptor));
+        self
+    }
+
+    /// Run `hook` around every operation the client makes, for example to log or audit them.
+    /// Hooks run in the order they were added, see [`ClientHook`].
+    pub fn hook<H>(mut self, hook: H) -> Self
+    where
+        H: ClientHook,
+    {
+        self.config.hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// Call `callback` with every operation which takes longer than `threshold`, counting
+    /// retries, along with how long it took, to find the causes of slow requests. The call
+    /// prints as the operation, key, endpoint and attempts, so logging it is enough:
+    ///
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// let builder = etcd3::EtcdClientBuilder::default()
+    ///     .slow_requests(Duration::from_millis(500), |call, latency| {
+    ///         eprintln!("slow etcd request: {} took {:?}", call, latency);
+    ///     });
+    /// ```
+    pub fn slow_requests<F>(self, threshold: Duration, callback: F) -> Self
+    where
+        F: Fn(&Call<'_>, Duration) + Send + Sync + 'static,
+    {
+        self.hook(hook::SlowRequests::new(threshold, callback))
+    }
+
+    /// Call `callback` with a [`Diagnostic`] whenever the client does something on its own to
+    /// keep working: retrying a request, failing over to other endpoints, creating a watch
+    /// again or authenticating. Diagnostics print as log lines, so logging them is enough
+    /// when investigating an incident:
+    ///
+    /// ```no_run
+    /// let builder = etcd3::EtcdClientBuilder::default()
+    ///     .diagnostics(|diagnostic| eprintln!("etcd: {}", diagnostic));
+    /// ```
+    ///
+    /// Like hooks, the callback runs inline on the task the diagnostic comes from.
+    pub fn diagnostics<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&Diagnostic) + Send + Sync + 'static,
+    {
+        self.config.diagnostics = Diagnostics::new(callback);
+        self
+    }
+
+    /// Take the time from `clock` for request timeouts, retry backoff and the lease
+    /// keep-alives of sessions, rather than from the [`SystemClock`](clock::SystemClock). With
+    /// a [`MockClock`](clock::MockClock), tests can step through them without waiting.
+    pub fn clock<C>(mut self, clock: C) -> Self
+    where
+        C: Clock,
+    {
+        self.config.clock = SharedClock::new(clock);
+        self
+    }
+
+    /// Connect with TLS, for clusters with TLS enabled
+    #[cfg(any(feature = "tls", feature = "tls-native"))]
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.config.tls = Some(tls);
+        self
+    }
+
+    /// Authenticate as `user`, for clusters with authentication enabled.
+    ///
+    /// The client authenticates once while connecting and sends the token it is given with
+    /// every request. Tokens are not refreshed, so with JWT tokens the client stops working
+    /// once its token expires.
+    pub fn credentials<U, P>(mut self, user: U, password: P) -> Self
+    where
+        U: Into<String>,
+        P: Into<String>,
+    {
+        self.credentials = Some((user.into(), password.into()));
+        self
+    }
+
+    /// Fail to connect if none of the endpoints accepts a connection within `timeout`, or if
+    /// connecting takes longer than that altogether, including looking up SRV records and
+    /// authenticating. The client then connects eagerly rather than when the first request is
+    /// sent.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.config.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Give up on any request which has not been answered within `timeout`, unless a shorter
+    /// timeout is given for the request. Streams such as watches are only subject to it until
+    /// the server accepts them.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.config.timeout = Some(timeout);
+        self
+    }
+
+    /// Connect through an HTTP proxy, such as the one given by [`Proxy::from_env`]. Unix
+    /// domain socket endpoints are always connected to directly.
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.config.proxy = Some(proxy);
+        self
+    }
+
+    /// Keep idle connections alive by pinging the cluster, see [`KeepAlive`]
+    pub fn keep_alive(mut self, keep_alive: KeepAlive) -> Self {
+        self.config.keep_alive = Some(keep_alive);
+        self
+    }
+
+    /// Retry requests which fail because a member is unavailable according to `policy`,
+    /// instead of the [default policy](RetryPolicy::default)
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.config.retry = policy;
+        self
+    }
+
+    /// Limit the rate at which the client sends reads, such as ranges, watches and member
+    /// lists, see [`RateLimit`]. The limit is shared by every clone of the client. Requests
+    /// the client sends by itself, such as keep-alive pings and health probes, are exempt,
+    /// and time spent waiting does not count towards request timeouts.
+    pub fn read_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.config.read_limit = Some(Arc::new(Limiter::new(limit)));
+        self
+    }
+
+    /// Limit the rate at which the client sends writes, such as puts, deletes, compactions,
+    /// locks and membership changes, separately from reads. See
+    /// [`read_rate_limit`](Self::read_rate_limit).
+    pub fn write_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.config.write_limit = Some(Arc::new(Limiter::new(limit)));
+        self
+    }
+
+    /// Allow at most `limit` requests in flight at once, across every endpoint and every clone
+    /// of the client, so bulk jobs can be throttled without wrapping every call. Further
+    /// requests wait for one in flight to finish. Like rate limits, this applies to the
+    /// requests the application sends, and streams such as watches are only in flight until
+    /// the server accepts them.
+    pub fn max_in_flight(mut self, limit: usize) -> Self {
+        self.config.in_flight = Some(Arc::new(InFlightLimit::new(limit)));
+        self
+    }
+
+    /// Treat operations as `class` unless they say otherwise, with [`Range::traffic_class`] or
+    /// [`CallContext::traffic_class`]. Operations are interactive by default. A client built
+    /// for a background job, such as a bulk load or a backup scheduler, can be made
+    /// [`TrafficClass::Background`], so that under the [rate limits](Self::read_rate_limit)
+    /// and [bound on requests in flight](Self::max_in_flight) it shares with interactive
+    /// clients its requests are shed first. The class is sent with every request in the
+    /// [`TRAFFIC_CLASS_METADATA`] metadata.
+    pub fn traffic_class(mut self, class: TrafficClass) -> Self {
+        self.config.traffic_class = class;
+        self.metadata.push((
+            TRAFFIC_CLASS_METADATA.to_string(),
+            class.as_str().to_string(),
+        ));
+        self
+    }
+
+    /// Coalesce identical range reads: a read issued while the same read is in flight waits
+    /// for its response instead of being sent too, so a herd of tasks fetching the same
+    /// configuration at once costs the cluster a single read. Reads are the same if they are
+    /// of the same range with the same consistency, through the same namespace and with the
+    /// same metadata and token. The reads in flight are shared by every clone of the client.
+    ///
+    /// A read which waits still gives up at its own timeout or deadline, and is sent by
+    /// itself if the read it waited for fails. It skips the client's rate limits and hooks,
+    /// as it sends no request.
+    pub fn coalesce_reads(mut self) -> Self {
+        self.config.coalescer = Some(Arc::new(kv::Coalescer::default()));
+        self
+    }
+
+    /// Allow at most `limit` requests in flight at once on each connection, including those
+    /// the client sends by itself. Further requests on the connection wait for one to
+    /// finish. Streams are only in flight until the server accepts them.
+    pub fn max_concurrent_streams(mut self, limit: usize) -> Self {
+        self.config.concurrent_streams = Some(limit);
+        self
+    }
+
+    /// Have the endpoint health tracker use a circuit breaker, see [`CircuitBreaker`] and
+    /// [`EtcdClient::track_endpoint_health`]. Otherwise an endpoint is taken out of the
+    /// rotation on its first failed probe and probed again on every tick.
+    pub fn circuit_breaker(mut self, breaker: CircuitBreaker) -> Self {
+        self.config.circuit_breaker = Some(breaker);
+        self
+    }
+
+    /// Connect with the configured settings. Fails if no endpoints were given or found in SRV
+    /// records, any of them is not a valid URL or any metadata is not a valid header, or if
+    /// authentication fails.
+    pub async fn connect(self) -> EtcdResult<EtcdClient<tonic::transport::channel::Channel>> {
+        let (endpoints, config) = self.prepare().await?;
+        let channel = config.balanced_channel(&config.routed_endpoints(&endpoints, &[]))?;
+        Ok(EtcdClient::with_channel(channel, endpoints, config))
+    }
+
+    /// Connect with the configured settings like [`connect`](Self::connect), sending every
+    /// request through `layer` wrapped around the channel as a [`ChannelService`], such as a
+    /// `tower` stack classifying gRPC statuses or adding request IDs. The layer wraps the
+    /// channel to each set of endpoints the client dials, so unlike a channel wrapped for
+    /// [`EtcdClient::from_transport`] the client still syncs endpoints, tracks their health
+    /// and hedges reads.
+    ///
+    /// Recipes which need a client connected over a plain channel, such as sessions, are not
+    /// available to the layered client.
+    pub async fn connect_with_layer<L>(self, layer: L) -> EtcdResult<EtcdClient<L::Service>>
+    where
+        L: tower_layer::Layer<ChannelService> + Send + Sync + 'static,
+        L::Service: Transport,
+    {
+        let (endpoints, config) = self.prepare().await?;
+        let connector: Connector<L::Service> = Arc::new(move |config, endpoints| {
+            let channel = config.balanced_channel(endpoints)?;
+            Ok(layer.layer(ChannelService::new(channel)))
+        });
+        let transport = connector(&config, &config.routed_endpoints(&endpoints, &[]))?;
+        Ok(EtcdClient::with_transport(
+            transport,
+            endpoints,
+            config,
+            Some(connector),
+        ))
+    }
+
+    /// Connect with the configured settings through etcd's HTTP/JSON gateway rather than gRPC,
+    /// for networks which block gRPC, see the [`gateway`] module. Fails like
+    /// [`connect`](Self::connect), or if any endpoint is not an `http` URL.
+    ///
+    /// Only the unary calls the gateway serves are available, so watches, lease keep-alives
+    /// and the recipes built on them fail with [`tonic::Code::Unimplemented`]. TLS and
+    /// connection settings such as keep-alives and proxies do not apply to the gateway.
+    #[cfg(feature = "gateway")]
+    pub async fn connect_gateway(self) -> EtcdResult<EtcdClient<gateway::Gateway>> {
+        let timeout = self.config.connect_timeout;
+        let (endpoints, config) = within_connect_timeout(timeout, self.prepare_gateway()).await?;
+        let connector: Connector<gateway::Gateway> = Arc::new(gateway::Gateway::with_config);
+        let transport = connector(&config, &config.routed_endpoints(&endpoints, &[]))?;
+        Ok(EtcdClient::with_transport(
+            transport,
+            endpoints,
+            config,
+            Some(connector),
+        ))
+    }
+
+    #[cfg(feature = "gateway")]
+    async fn prepare_gateway(self) -> EtcdResult<(Vec<String>, ChannelConfig)> {
+        let Self {
+            mut endpoints,
+            credentials,
+            metadata,
+            discovery_srv,
+            mut config,
+        } = self;
+        if let Some(domain) = discovery_srv {
+            let found = srv::endpoints(&domain)
+                .await
+                .map_err(|e| format!("failed to look up SRV records for {}: {}", domain, e))?;
+            endpoints.extend(found);
+        }
+        config.metadata = metadata_headers(&metadata)?;
+        let gateway = gateway::Gateway::with_config(&config, &endpoints)?;
+        if let Some((name, password)) = credentials {
+            let user = name.clone();
+            let request = etcdserver::AuthenticateRequest { name, password };
+            let response = client::AuthClient::new(gateway)
+                .authenticate(request)
+                .await
+                .map_err(Error::Auth)?
+                .into_inner();
+            config.token = Some(http::HeaderValue::from_str(&response.token)?);
+            let authenticated = || Diagnostic::Authenticated { user };
+            config.diagnostics.emit(authenticated);
+        }
+        Ok((endpoints, config))
+    }
+
+    /// The endpoints to connect to and the settings to connect with, looking up SRV records
+    /// and authenticating if asked to, all within the connect timeout
+    async fn prepare(self) -> EtcdResult<(Vec<String>, ChannelConfig)> {
+        let timeout = self.config.connect_timeout;
+        within_connect_timeout(timeout, self.prepare_now()).await
+    }
+
+    async fn prepare_now(self) -> EtcdResult<(Vec<String>, ChannelConfig)> {
+        let Self {
+            mut endpoints,
+            credentials,
+            metadata,
+            discovery_srv,
+            mut config,
+        } = self;
+        if let Some(domain) = discovery_srv {
+            let found = srv::endpoints(&domain)
+                .await
+                .map_err(|e| format!("failed to look up SRV records for {}: {}", domain, e))?;
+            if found.is_empty() {
+                return Err(format!("no SRV records for the cluster under {}", domain).into());
+            }
+            endpoints.extend(found);
+        }
+        if endpoints.is_empty() {
+            return Err("no endpoints to connect to".into());
+        }
+        for url in &endpoints {
+            validate_endpoint(url)?;
+        }
+        config.metadata = metadata_headers(&metadata)?;
+
+        // Authenticate while the endpoints are dialled rather than after, as both wait on
+        // connections being established
+        let authenticate = async {
+            let (name, password) = match credentials {
+                Some(credentials) => credentials,
+                None => return Ok(None),
+            };
+            let channel = config.balanced_channel(&endpoints)?;
+            let user = name.clone();
+            let request = etcdserver::AuthenticateRequest { name, password };
+            let response = client::AuthClient::new(channel)
+                .authenticate(request)
+                .await
+                .map_err(Error::Auth)?
+                .into_inner();
+            let token = http::HeaderValue::from_str(&response.token)?;
+            Ok(Some((user, token)))
+        };
+        let connectable = config.check_connectable(&endpoints);
+        let ((), authenticated) = future::try_join(connectable, authenticate).await?;
+        if let Some((user, token)) = authenticated {
+            config.token = Some(token);
+            let authenticated = || Diagnostic::Authenticated { user };
+            config.diagnostics.emit(authenticated);
+        }
+        Ok((endpoints, config))
+    }
+
+    /// Create the client without connecting to the cluster, which is only dialled once the
+    /// first request is sent, so the client can be created before the cluster is reachable.
+    /// Must be called from within a tokio runtime.
+    ///
+    /// Fails if no endpoints were given, any of them is not a valid URL or any metadata is
+    /// not a valid header. Authenticating and looking up SRV records need a connection, so
+    /// this also fails if credentials or a discovery domain were given, and the connect
+    /// timeout only applies to [`connect`](Self::connect).
+    pub fn connect_lazy(self) -> EtcdResult<EtcdClient<tonic::transport::channel::Channel>> {
+        if self.endpoints.is_empty() {
+            return Err("no endpoints to connect to".into());
+        }
+        for url in &self.endpoints {
+            validate_endpoint(url)?;
+        }
+        if self.credentials.is_some() {
+            return Err("cannot authenticate without connecting, use connect instead".into());
+        }
+        if self.discovery_srv.is_some() {
+            return Err("cannot look up SRV records without connecting, use connect".into());
+        }
+        let Self {
+            endpoints,
+            metadata,
+            mut config,
+            ..
+        } = self;
+        config.metadata = metadata_headers(&metadata)?;
+        let channel = config.balanced_channel(&config.routed_endpoints(&endpoints, &[]))?;
+        Ok(EtcdClient::with_channel(channel, endpoints, config))
+    }
+
+    /// Wait until the cluster behind the endpoints can serve requests, see [`wait_for_ready`],
+    /// dialling the endpoints and every member with the configured settings.
+    ///
+    /// Membership and member status need no authentication, so credentials are not used, and
+    /// a discovery domain is not looked up.
+    #[cfg(feature = "cluster")]
+    pub async fn wait_for_ready(self, timeout: Duration) -> EtcdResult<()> {
+        let Self {
+            endpoints,
+            metadata,
+            mut config,
+            ..
+        } = self;
+        if endpoints.is_empty() {
+            return Err("no endpoints to wait for".into());
+        }
+        config.metadata = metadata_headers(&metadata)?;
+
+        let started = Instant::now();
+        let deadline = started + timeout;
+        loop {
+            let check = runtime::timeout_at(cluster_ready(&config, &endpoints), deadline);
+            let reason = match check.await {
+                Ok(Ok(())) => return Ok(()),
+                Ok(Err(reason)) => reason,
+                Err(_) => "timed out waiting for a response".to_string(),
+            };
+
+            if Instant::now() + READY_POLL_INTERVAL > deadline {
+                let what = format!("cluster not ready: {}", reason);
+                return Err(Error::timeout(what, timeout, started));
+            }
+            runtime::delay_for(READY_POLL_INTERVAL).await;
+        }
+    }
+}
+
+/// Check `url` is an endpoint the client can connect to: an `http` or `https` URL with a host,
+/// or a `unix` socket path
+fn validate_endpoint(url: &str) -> EtcdResult<()> {
+    if tunnel::Target::parse(url)?.is_some() {
+        return Ok(());
+    }
+    let valid = match url.parse::<http::Uri>() {
+        Ok(uri) => {
+            let scheme = uri.scheme_part().map(|scheme| scheme.as_str());
+            let host = uri.host().unwrap_or_default();
+            matches!(scheme, Some("http") | Some("https")) && !host.is_empty()
+        }
+        Err(_) => false,
+    };
+    if valid {
+        Ok(())
+    } else {
+        let message = format!(
+            "invalid endpoint {:?}, expected a URL such as http://127.0.0.1:2379",
+            url
+        );
+        Err(message.into())
+    }
+}
+
+/// `key` outside of `namespace`. Keys which are not in the namespace are returned unchanged.
+fn strip_namespace<'k>(namespace: &[u8], key: &'k [u8]) -> &'k [u8] {
+    if key.starts_with(namespace) {
+        &key[namespace.len()..]
+    } else {
+        key
+    }
+}
+
+/// Pin `pinned` to the cluster named in `header` if it is not pinned yet, otherwise check the
+/// header names the same cluster. Missing headers and a cluster ID of 0 are ignored.
+fn pin_cluster_id(
+    pinned: &mut Option<u64>,
+    header: Option<&etcdserver::ResponseHeader>,
+) -> Result<(), String> {
+    let cluster_id = match header {
+        Some(header) if header.cluster_id != 0 => header.cluster_id,
+        _ => return Ok(()),
+    };
+    match *pinned {
+        None => {
+            *pinned = Some(cluster_id);
+            Ok(())
+        }
+        Some(expected) if expected == cluster_id => Ok(()),
+        Some(expected) => Err(format!(
+            "response from cluster {:x}, but the client is pinned to cluster {:x}",
+            cluster_id, expected
+        )),
+    }
+}
+
+/// A transport clients can send requests over.
+///
+/// Implemented for the transport's own [`Channel`](tonic::transport::channel::Channel) and
+/// every other gRPC service which can be sent between threads, such as a channel wrapped in
+/// `tower` layers. See [`EtcdClientBuilder::connect_with_layer`] and
+/// [`EtcdClient::from_transport`].
+pub trait Transport:
+    tonic::client::GrpcService<
+        tonic::body::BoxBody,
+        ResponseBody: Send
+                          + 'static
+                          + tonic::codegen::HttpBody<
+            Data: Into<bytes::Bytes> + Send,
+            Error: Into<StdError> + Send,
+        >,
+        Future: Send,
+    > + Clone
+    + Send
+    + Sync
+    + 'static
+{
+}
+
+impl<T> Transport for T where
+    T: tonic::client::GrpcService<
+            tonic::body::BoxBody,
+            ResponseBody: Send
+                              + 'static
+                              + tonic::codegen::HttpBody<
+                Data: Into<bytes::Bytes> + Send,
+                Error: Into<StdError> + Send,
+            >,
+            Future: Send,
+        > + Clone
+        + Send
+        + Sync
+        + 'static
+{
+}
+
+/// The transport's [`Channel`](tonic::transport::channel::Channel) as a `tower` service, for
+/// wrapping in the `tower` layers built on the same `tower` version as the transport, see
+/// [`EtcdClientBuilder::connect_with_layer`]. The channel is only a gRPC service.
+#[derive(Debug, Clone)]
+pub struct ChannelService(tonic::transport::channel::Channel);
+
+impl ChannelService {
+    /// `channel` as a `tower` service
+    pub fn new(channel: tonic::transport::channel::Channel) -> Self {
+        Self(channel)
+    }
+}
+
+/// Future of a request sent over the channel
+type ChannelFuture = <tonic::transport::channel::Channel as tonic::client::GrpcService<
+    tonic::body::BoxBody,
+>>::Future;
+
+impl tonic::codegen::Service<http::Request<tonic::body::BoxBody>> for ChannelService {
+    type Response = http::Response<tonic::codegen::HyperBody>;
+    type Error = tonic::transport::Error;
+    type Future = ChannelFuture;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        tonic::client::GrpcService::poll_ready(&mut self.0, cx)
+    }
+
+    fn call(&mut self, request: http::Request<tonic::body::BoxBody>) -> Self::Future {
+        tonic::client::GrpcService::call(&mut self.0, request)
+    }
+}
+
+type Connector<T> = Arc<dyn Fn(&ChannelConfig, &[String]) -> EtcdResult<T> + Send + Sync>;
+
+/// Etcd client.
+///
+/// Operations take `&self`, so one client can be used from many tasks at once. Clones are
+/// cheap and share the client's connections and routing, so a client can also be kept in
+/// application state and a clone handed to each task using it.
+#[derive(Clone)]
+pub struct EtcdClient<T> {
+    /// How requests are routed, shared with every clone of the client
+    state: Arc<StdMutex<State<T>>>,
+    config: ChannelConfig,
+    /// Creates transports balancing across other endpoints, if the client can dial them
+    connector: Option<Connector<T>>,
+    /// Prefix of every key the client reads and writes, see [`namespaced`](Self::namespaced)
+    namespace: Arc<[u8]>,
+}
+
+impl<T> std::fmt::Debug for EtcdClient<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let endpoints = self.state.lock().unwrap().services.endpoints.clone();
+        f.debug_struct("EtcdClient")
+            .field("endpoints", &endpoints)
+            .field("namespace", &redact::Key(&self.namespace))
+            .finish_non_exhaustive()
+    }
+}
+
+/// How a client routes requests, updated as its endpoints are synced and checked
+struct State<T> {
+    /// Clients of the services over the current transport, shared with the operations made
+    /// over it
+    services: Arc<Services<T>>,
+    /// Endpoint sets published by an auto-sync task
+    endpoint_updates: Option<updates::Receiver<Vec<String>>>,
+    /// Endpoints found to be unhealthy by the last health check
+    unhealthy: Vec<String>,
+    /// Unhealthy endpoint sets published by a health tracking task
+    health_updates: Option<updates::Receiver<Vec<String>>>,
+    /// ID of the cluster the client is talking to, taken from the first response header
+    cluster_id: Option<u64>,
+    /// A KV client for each routed endpoint, to hedge reads with
+    hedge_clients: Vec<client::KvClient<T>>,
+    /// Index of the endpoint to send the next hedged read to first
+    next_hedge: usize,
+}
+
+/// The clients of each service, all sending requests over one transport
+struct Services<T> {
+    #[cfg(feature = "auth")]
+    auth_client: client::AuthClient<T>,
+    cluster_client: client::ClusterClient<T>,
+    kv_client: client::KvClient<T>,
+    lease_client: client::LeaseClient<T>,
+    #[cfg(feature = "lock")]
+    lock_client: v3lock::client::LockClient<T>,
+    #[cfg(feature = "election")]
+    election_client: v3election::client::ElectionClient<T>,
+    #[cfg(feature = "maintenance")]
+    status_client: client::MaintenanceClient<T>,
+    watch_client: client::WatchClient<T>,
+    /// Endpoints the transport balances across, empty if the client was connected to a
+    /// single endpoint and has never been synced
+    endpoints: Vec<String>,
+    /// Endpoints requests are routed to, leaving out the unhealthy ones
+    routed: Vec<String>,
+    /// Stops the keep-alive task pinging the transport once it is no longer used
+    _keep_alive: Option<oneshot::Sender<()>>,
+}
+
+impl<T: Transport> Services<T> {
+    /// Clients sending requests over `transport`, which routes them to `routed`
+    fn new(
+        transport: T,
+        endpoints: Vec<String>,
+        routed: Vec<String>,
+        config: &ChannelConfig,
+    ) -> Self {
+        let status_client = client::MaintenanceClient::new(transport.clone());
+        let active = config.active.clone();
+        let keep_alive = config
+            .keep_alive
+            .map(|settings| keep_alive(settings, status_client.clone(), active));
+        Self {
+            #[cfg(feature = "auth")]
+            auth_client: client::AuthClient::new(transport.clone()),
+            cluster_client: client::ClusterClient::new(transport.clone()),
+            kv_client: client::KvClient::new(transport.clone()),
+            lease_client: client::LeaseClient::new(transport.clone()),
+            #[cfg(feature = "lock")]
+            lock_client: v3lock::client::LockClient::new(transport.clone()),
+            #[cfg(feature = "election")]
+            election_client: v3election::client::ElectionClient::new(transport.clone()),
+            #[cfg(feature = "maintenance")]
+            status_client,
+            watch_client: client::WatchClient::new(transport),
+            endpoints,
+            routed,
+            _keep_alive: keep_alive,
+        }
+    }
+}
+
+/// Outcome of `connecting`, or a timeout error if it takes longer than `timeout`
+async fn within_connect_timeout<F, R>(timeout: Option<Duration>, connecting: F) -> EtcdResult<R>
+where
+    F: std::future::Future<Output = EtcdResult<R>>,
+{
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return connecting.await,
+    };
+    let started = Instant::now();
+    match runtime::timeout(connecting, timeout).await {
+        Ok(connected) => connected,
+        Err(_) => Err(Error::timeout("timed out connecting", timeout, started)),
+    }
+}
+
+impl EtcdClient<tonic::transport::channel::Channel> {
+    /// Connect to a cluster through the single endpoint `dst`.
+    ///
+    /// Every service client shares one connection, so only one connection is dialled.
+    pub async fn connect<D>(dst: D) -> EtcdResult<Self>
+    where
+        D: TryInto<Endpoint>,
+        D::Error: Into<StdError>,
+    {
+        let channel = Endpoint::new(dst)?.connect().await?;
+        let config = ChannelConfig::default();
+        Ok(Self::with_channel(channel, Vec::new(), config))
+    }
+
+    /// Connect to a cluster through any of `endpoints`, balancing requests across them
+    /// round-robin so the client keeps working while individual members are down.
+    ///
+    /// Connections are established lazily, so unreachable endpoints are only noticed once
+    /// requests are sent to them. Fails if no endpoints are given or any of them is not a
+    /// valid URL. Use [`EtcdClient::builder`] to configure the connections.
+    pub async fn connect_all<I, S>(endpoints: I) -> EtcdResult<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self::builder().endpoints(endpoints).connect().await
+    }
+
+    /// Connect to the cluster configured by the environment variables etcdctl reads, see
+    /// [`EtcdClientBuilder::from_env`]
+    pub async fn from_env() -> EtcdResult<Self> {
+        EtcdClientBuilder::from_env()?.connect().await
+    }
+
+    /// Connect to the cluster configured by the TOML or YAML file at `path`, see
+    /// [`ClientConfig`]. Fails with a message naming the file and the field at fault if the
+    /// file is not valid.
+    #[cfg(feature = "config")]
+    pub async fn from_config_file<P>(path: P) -> EtcdResult<Self>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        EtcdClientBuilder::from_config_file(path)?.connect().await
+    }
+
+    /// Create a client for the single endpoint `dst` without connecting to it, see
+    /// [`EtcdClientBuilder::connect_lazy`]. Must be called from within a tokio runtime.
+    pub fn connect_lazy<S>(dst: S) -> EtcdResult<Self>
+    where
+        S: Into<String>,
+    {
+        Self::builder().endpoint(dst).connect_lazy()
+    }
+
+    /// Builder for a client with TLS, credentials or timeouts
+    pub fn builder() -> EtcdClientBuilder {
+        EtcdClientBuilder::default()
+    }
+
+    /// Client sending every request over `channel`, which balances across `endpoints`
+    fn with_channel(
+        channel: tonic::transport::channel::Channel,
+        endpoints: Vec<String>,
+        config: ChannelConfig,
+    ) -> Self {
+        let connector: Connector<_> = Arc::new(ChannelConfig::balanced_channel);
+        Self::with_transport(channel, endpoints, config, Some(connector))
+    }
+}
+
+impl<T: Transport> EtcdClient<T> {
+    /// Client sending every request over `transport`, such as the transport's channel wrapped
+    /// in `tower` layers, or a custom service.
+    ///
+    /// The client cannot dial endpoints of its own, so connection settings do not apply to it
+    /// and it cannot hedge reads, sync its endpoints or leave out unhealthy ones. Use
+    /// [`EtcdClientBuilder::connect_with_layer`] to wrap the channels a client dials itself.
+    pub fn from_transport(transport: T) -> Self {
+        Self::with_transport(transport, Vec::new(), ChannelConfig::default(), None)
+    }
+
+    /// Client sending every request over `transport`, which balances across `endpoints`
+    fn with_transport(
+        transport: T,
+        endpoints: Vec<String>,
+        mut config: ChannelConfig,
+        connector: Option<Connector<T>>,
+    ) -> Self {
+        // The connection state follows the outcome of every operation
+        config.hooks.push(config.connection.clone());
+        let routed = config.routed_endpoints(&endpoints, &[]);
+        let state = State {
+            services: Arc::new(Services::new(transport, endpoints, routed, &config)),
+            endpoint_updates: None,
+            unhealthy: Vec::new(),
+            health_updates: None,
+            cluster_id: None,
+            hedge_clients: Vec::new(),
+            next_hedge: 0,
+        };
+        Self {
+            state: Arc::new(StdMutex::new(state)),
+            config,
+            connector,
+            namespace: Vec::new().into(),
+        }
+    }
+
+    /// Point the client at the client URLs of the current cluster members, balancing requests
+    /// across them.
+    ///
+    /// Streams which are already open, such as watches, stay on their current connection.
+    pub async fn sync_endpoints(&self) -> EtcdResult<()> {
+        let members = self.member_list(false).await?;
+        let endpoints = member_endpoints(&members);
+        self.set_endpoints(endpoints)
+    }
+
+    /// Spawn a background task which syncs the client's endpoints with the member list every
+    /// `interval`, so long running clients follow members being added and removed.
+    ///
+    /// The client picks up a new endpoint set at the start of its next operation. Must be
+    /// called from within a tokio runtime.
+    pub fn auto_sync_endpoints(&self, interval: Duration) -> EndpointSync {
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let mut stop_rx = future::select(stop_rx, self.config.shutdown.closed());
+        let services = self.services();
+        let (updates_tx, updates_rx) = updates::channel(services.endpoints.clone());
+        let mut cluster_client = services.cluster_client.clone();
+        let mut state = self.state.lock().unwrap();
+        let mut cluster_id = state.cluster_id;
+        let (config, connector) = (self.config.clone(), self.connector.clone());
+        state.endpoint_updates = Some(updates_rx);
+        drop(state);
+
+        runtime::spawn(async move {
+            let mut current = Vec::new();
+            let mut ticks = runtime::Interval::new(interval);
+
+            loop {
+                let tick = ticks.next();
+                futures::pin_mut!(tick);
+                if let Either::Right(_) = future::select(tick, &mut stop_rx).await {
+                    break;
+                }
+
+                let response = match cluster_client
+                    .member_list(etcdserver::MemberListRequest::default())
+                    .await
+                {
+                    Ok(response) => response.into_inner(),
+                    Err(_) => continue,
+                };
+                // Never follow endpoints handed out by a different cluster
+                if pin_cluster_id(&mut cluster_id, response.header.as_ref()).is_err() {
+                    continue;
+                }
+
+                let members = response.members;
+
+                let endpoints = member_endpoints(&members);
+                if endpoints.is_empty() || endpoints == current {
+                    continue;
+                }
+
+                // Follow the new endpoints ourselves, in case the old ones go away
+                if let Some(Ok(transport)) = connector.as_ref().map(|c| c(&config, &endpoints)) {
+                    cluster_client = client::ClusterClient::new(transport);
+                }
+                current = endpoints.clone();
+                if updates_tx.broadcast(endpoints).is_err() {
+                    // The client has gone away
+                    break;
+                }
+            }
+        });
+
+        EndpointSync { _stop: stop_tx }
+    }
+
+    /// Spawn a background task which probes each of the client's endpoints with a status
+    /// request every `interval`, following endpoint syncs.
+    ///
+    /// Endpoints which fail a probe or report errors are left out of the rotation until they answer one again,
+    /// so requests are not sent to a member which is down only to wait for them to time out.
+    /// If every endpoint is unhealthy requests are balanced across all of them as usual.
+    /// Probes time out after the client's request timeout if there is one, otherwise after
+    /// `interval`. Clients connected to a single endpoint are not affected.
+    ///
+    /// With a [circuit breaker](EtcdClientBuilder::circuit_breaker), endpoints are only taken
+    /// out of the rotation after several failed probes, and are probed less often while out.
+    ///
+    /// The client picks up changes at the start of its next operation. Must be called from
+    /// within a tokio runtime.
+    pub fn track_endpoint_health(&self, interval: Duration) -> HealthTracker {
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let mut stop_rx = future::select(stop_rx, self.config.shutdown.closed());
+        let (health_tx, health_rx) = updates::channel(Vec::new());
+        let mut endpoints = self.endpoints();
+        let mut state = self.state.lock().unwrap();
+        let endpoint_updates = state.endpoint_updates.clone();
+        state.health_updates = Some(health_rx);
+        drop(state);
+        let config = self.config.clone();
+        let timeout = config.timeout.unwrap_or(interval);
+        let breaker = config
+            .circuit_breaker
+            .unwrap_or_else(CircuitBreaker::immediate);
+
+        runtime::spawn(async move {
+            let mut current = Vec::new();
+            let mut probes = HashMap::new();
+            let mut circuits: HashMap<String, Circuit> = HashMap::new();
+            let mut ticks = runtime::Interval::new(interval);
+
+            loop {
+                let tick = ticks.next();
+                futures::pin_mut!(tick);
+                if let Either::Right(_) = future::select(tick, &mut stop_rx).await {
+                    break;
+                }
+
+                if let Some(updates) = &endpoint_updates {
+                    endpoints = updates.get_ref().clone();
+                }
+                probes.retain(|url, _| endpoints.contains(url));
+                circuits.retain(|url, _| endpoints.contains(url));
+                let now = Instant::now();
+                let mut checks = Vec::new();
+                for url in &endpoints {
+                    let circuit = circuits.entry(url.clone()).or_default();
+                    if !circuit.probe_due(breaker, now) {
+                        continue;
+                    }
+                    let probe = match probes.get(url) {
+                        Some(probe) => probe,
+                        None => match config.balanced_channel(std::slice::from_ref(url)) {
+                            Ok(channel) => probes
+                                .entry(url.clone())
+                                .or_insert_with(|| client::MaintenanceClient::new(channel)),
+                            Err(_) => continue,
+                        },
+                    };
+                    let mut probe = probe.clone();
+                    checks.push(async move {
+                        let status = probe.status(etcdserver::StatusRequest {});
+                        let healthy = match runtime::timeout(status, timeout).await {
+                            // Members reporting errors, such as alarms, are unhealthy too
+                            Ok(Ok(response)) => response.get_ref().errors.is_empty(),
+                            _ => false,
+                        };
+                        (url, healthy)
+                    });
+                }
+
+                let now = Instant::now();
+                for (url, healthy) in future::join_all(checks).await {
+                    if let Some(circuit) = circuits.get_mut(url) {
+                        circuit.record(healthy, breaker, now);
+                    }
+                }
+                let unhealthy: Vec<String> = endpoints
+                    .iter()
+                    .filter(|url| circuits.get(*url).is_some_and(Circuit::is_open))
+                    .cloned()
+                    .collect();
+                if unhealthy == current {
+                    continue;
+                }
+                current = unhealthy.clone();
+                config.connection.checked(&unhealthy);
+                if health_tx.broadcast(unhealthy).is_err() {
+                    // The client has gone away
+                    break;
+                }
+            }
+        });
+
+        HealthTracker { _stop: stop_tx }
+    }
+
+    /// The service clients to make an operation with, after switching to the latest
+    /// endpoints if they have changed
+    fn services(&self) -> Arc<Services<T>> {
+        let mut state = self.state.lock().unwrap();
+        self.apply_endpoint_updates(&mut state);
+        state.services.clone()
+    }
+
+    pub(crate) fn kv_client(&self) -> client::KvClient<T> {
+        self.services().kv_client.clone()
+    }
+
+    pub(crate) fn lease_client(&self) -> client::LeaseClient<T> {
+        self.services().lease_client.clone()
+    }
+
+    pub(crate) fn watch_client(&self) -> client::WatchClient<T> {
+        self.services().watch_client.clone()
+    }
+
+    #[cfg(feature = "election")]
+    pub(crate) fn election_client(&self) -> v3election::client::ElectionClient<T> {
+        self.services().election_client.clone()
+    }
+
+    #[cfg(feature = "auth")]
+    fn auth_client(&self) -> client::AuthClient<T> {
+        self.services().auth_client.clone()
+    }
+
+    fn cluster_client(&self) -> client::ClusterClient<T> {
+        self.services().cluster_client.clone()
+    }
+
+    #[cfg(feature = "lock")]
+    fn lock_client(&self) -> v3lock::client::LockClient<T> {
+        self.services().lock_client.clone()
+    }
+
+    #[cfg(feature = "maintenance")]
+    fn status_client(&self) -> client::MaintenanceClient<T> {
+        self.services().status_client.clone()
+    }
+
+    /// List the cluster's members, reading them through the leader if `linearizable`
+    pub(crate) async fn member_list(
+        &self,
+        linearizable: bool,
+    ) -> EtcdResult<Vec<etcdserver::Member>> {
+        let request = etcdserver::MemberListRequest { linearizable };
+        let context = self.error_context("member list", None);
+        let rpc = || {
+            let (mut cluster_client, request) = (self.cluster_client(), request.clone());
+            async move { cluster_client.member_list(request).await }
+        };
+        let members = |response: etcdserver::MemberListResponse| Ok(response.members);
+        self.call(
+            "cluster.member_list",
+            context,
+            Budget::Read,
+            Retry::Idempotent,
+            rpc,
+            members,
+        )
+        .await
+    }
+
+    /// Delay before hedging a read, and the KV clients of the two endpoints to send it to, if
+    /// reads are hedged
+    fn hedge_clients(&self) -> Option<(Duration, client::KvClient<T>, client::KvClient<T>)> {
+        let delay = self.config.hedge?;
+        let connector = self.connector.as_ref()?;
+        let services = self.services();
+        let mut state = self.state.lock().unwrap();
+        if state.hedge_clients.is_empty() {
+            for url in &services.routed {
+                let endpoint = std::slice::from_ref(url);
+                let transport = connector(&self.config, endpoint).ok()?;
+                state.hedge_clients.push(client::KvClient::new(transport));
+            }
+        }
+        let count = state.hedge_clients.len();
+        if count < 2 {
+            return None;
+        }
+        let first = state.next_hedge % count;
+        state.next_hedge = state.next_hedge.wrapping_add(1);
+        let second = (first + 1) % count;
+        let clients = &state.hedge_clients;
+        Some((delay, clients[first].clone(), clients[second].clone()))
+    }
+
+    /// Endpoints the client balances requests across, as given to
+    /// [`connect_all`](Self::connect_all) or found by the last member list sync. Empty if the
+    /// client was connected to a single endpoint and has never been synced.
+    pub fn endpoints(&self) -> Vec<String> {
+        self.services().endpoints.clone()
+    }
+
+    /// Endpoints the last health check found to be unhealthy, see
+    /// [`track_endpoint_health`](Self::track_endpoint_health)
+    pub fn unhealthy_endpoints(&self) -> Vec<String> {
+        match &self.state.lock().unwrap().health_updates {
+            Some(health) => health.get_ref().clone(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Subscribe to changes to how the client reaches the cluster: requests failing to reach
+    /// it and getting through again, endpoint switches and unhealthy endpoints, for example
+    /// to fail a readiness probe or shed load while connectivity to etcd is flapping.
+    ///
+    /// Events are delivered from when this is called, to every subscriber, however long they
+    /// take to be read. Endpoints are only found unhealthy while their health is tracked, see
+    /// [`track_endpoint_health`](Self::track_endpoint_health).
+    pub fn connection_events(&self) -> ConnectionEvents {
+        self.config.connection.subscribe()
+    }
+
+    /// ID of the cluster the client is pinned to, if it has seen a response yet.
+    ///
+    /// The client pins itself to the cluster named in the first response header it sees.
+    /// Later responses from a different cluster, for example after failing over to a
+    /// misconfigured endpoint, are rejected with an error rather than silently reading from or
+    /// writing to the wrong cluster.
+    pub fn cluster_id(&self) -> Option<u64> {
+        self.state.lock().unwrap().cluster_id
+    }
+
+    /// A client for the keys under `prefix`, which it reads and writes as if they were the
+    /// whole keyspace, so applications sharing a cluster cannot see or change each other's
+    /// keys.
+    ///
+    /// Keys given to KV, watch, lock and election operations are prefixed, and the prefix is
+    /// stripped from the keys in their results. Ranges ending at `"\0"`, meaning every key
+    /// from the start on, end with the namespace instead. Namespaces nest, and the namespaced
+    /// client shares this client's connections and routing. The recipes in [`discovery`] take
+    /// their prefixes as given.
+    pub fn namespaced<P>(&self, prefix: P) -> Self
+    where
+        P: AsRef<[u8]>,
+    {
+        let mut namespace = self.namespace.to_vec();
+        namespace.extend(prefix.as_ref());
+        Self {
+            namespace: namespace.into(),
+            ..self.clone()
+        }
+    }
+
+    /// Rules every put is checked against, see [`EtcdClientBuilder::key_schema`]
+    pub fn key_schema(&self) -> Option<&schema::KeySchema> {
+        self.config.key_schema.as_deref()
+    }
+
+    /// Prefix of every key the client reads and writes, empty if it is not namespaced
+    pub fn namespace(&self) -> &[u8] {
+        &self.namespace
+    }
+
+    /// How reads are made unless they ask otherwise, see
+    /// [`EtcdClientBuilder::read_consistency`]
+    pub fn read_consistency(&self) -> ReadConsistency {
+        self.config.read_consistency
+    }
+
+    /// Class of the operations which do not give their own, see
+    /// [`EtcdClientBuilder::traffic_class`]
+    pub fn traffic_class(&self) -> TrafficClass {
+        self.config.traffic_class
+    }
+
+    /// Whether reads which do not ask otherwise are serializable
+    pub(crate) fn serializable_reads(&self) -> bool {
+        self.config.read_consistency == ReadConsistency::Serializable
+    }
+
+    /// `key` inside the client's namespace
+    pub(crate) fn namespaced_key(&self, key: &[u8]) -> Vec<u8> {
+        let mut namespaced = self.namespace.to_vec();
+        namespaced.extend(key);
+        namespaced
+    }
+
+    /// The end of a range inside the client's namespace. No end still means a single key, and
+    /// `\0` ends with the namespace rather than the whole keyspace.
+    pub(crate) fn namespaced_range_end(&self, end: &[u8]) -> Vec<u8> {
+        match end {
+            b"" => Vec::new(),
+            b"\0" if !self.namespace.is_empty() => concurrency::prefix_end(&self.namespace),
+            end => self.namespaced_key(end),
+        }
+    }
+
+    /// Context to attach to errors from `operation` on `key`
+    fn error_context(&self, operation: &'static str, key: Option<&[u8]>) -> ErrorContext {
+        let key = key.map(|key| {
+            if self.config.redact_keys {
+                format!("<{} bytes redacted>", key.len())
+            } else {
+                String::from_utf8_lossy(key).into_owned()
+            }
+        });
+        let endpoint = match self.services().routed.as_slice() {
+            [endpoint] => Some(endpoint.clone()),
+            _ => None,
+        };
+        ErrorContext {
+            operation,
+            key,
+            endpoint,
+            ..Default::default()
+        }
+    }
+
+    /// Start the operation `name` described by `context`, running the client's hooks around it
+    fn operation(&self, name: &'static str, context: &ErrorContext) -> Operation {
+        Operation::start(name, context, &self.config.hooks)
+    }
+
+    /// Make the request sent by `rpc` as the operation `name`, described by `context`. The
+    /// request is throttled against `budget` and sent again as `retry` says, and the header of
+    /// its response is checked against the pinned cluster ID before `then` turns the response
+    /// into the result. The client's hooks run around all of it, and errors carry the context.
+    async fn call<F, R, U>(
+        &self,
+        name: &'static str,
+        mut context: ErrorContext,
+        budget: Budget,
+        retry: Retry,
+        mut rpc: impl FnMut() -> F,
+        then: impl FnOnce(R) -> EtcdResult<U>,
+    ) -> EtcdResult<U>
+    where
+        F: std::future::Future<Output = Result<tonic::Response<R>, tonic::Status>>,
+        R: Reply,
+    {
+        let operation = self.operation(name, &context);
+        let call = async {
+            let _in_flight = self.config.throttle(budget).await?;
+            let idempotent = match retry {
+                Retry::Never => None,
+                Retry::Idempotent => Some(true),
+                Retry::Mutation => Some(false),
+            };
+            let response = match idempotent {
+                Some(idempotent) => {
+                    let retries = self.config.retries(context.operation);
+                    let mut attempts = 0;
+                    let response = with_retries(retries, idempotent, &mut attempts, rpc).await;
+                    context.attempt = Some(attempts);
+                    response?
+                }
+                None => rpc().await?,
+            };
+            let response = response.into_inner();
+            context.header = response.header().cloned();
+            self.check_cluster_id(response.header())?;
+            then(response)
+        };
+        let result: EtcdResult<_> = operation.run(call).await;
+        operation.finish(&context, &result);
+        result.map_err(|e| context.attach(e))
+    }
+
+    /// Check a response header against the pinned cluster ID, see [`EtcdClient::cluster_id`].
+    /// Every response from etcd carries a header, so one without is malformed.
+    fn check_cluster_id(&self, header: Option<&etcdserver::ResponseHeader>) -> EtcdResult<()> {
+        if header.is_none() {
+            return Err(Error::invalid_response("response is missing its header"));
+        }
+        let mut state = self.state.lock().unwrap();
+        Ok(pin_cluster_id(&mut state.cluster_id, header)?)
+    }
+
+    fn set_endpoints(&self, endpoints: Vec<String>) -> EtcdResult<()> {
+        self.route(&mut self.state.lock().unwrap(), endpoints)
+    }
+
+    /// Route requests to `endpoints`, leaving out the unhealthy ones
+    fn route(&self, state: &mut State<T>, endpoints: Vec<String>) -> EtcdResult<()> {
+        if endpoints.is_empty() {
+            return Err("cannot sync to an empty set of endpoints".into());
+        }
+        let connector = match &self.connector {
+            Some(connector) => connector,
+            None => return Err("cannot point the client's transport at other endpoints".into()),
+        };
+        let routed = self.config.routed_endpoints(&endpoints, &state.unhealthy);
+        if endpoints == state.services.endpoints && routed == state.services.routed {
+            return Ok(());
+        }
+
+        let transport = connector(&self.config, &routed)?;
+        let services = Services::new(transport, endpoints, routed, &self.config);
+        let previous = std::mem::replace(&mut state.services, Arc::new(services));
+        state.hedge_clients.clear();
+        let routed = &state.services.routed;
+        self.config.connection.switched(routed);
+        if *routed != previous.routed {
+            self.config.diagnostics.emit(|| Diagnostic::Failover {
+                from: previous.routed.clone(),
+                to: routed.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Switch to the latest endpoint set published by an auto-sync task and leave out the
+    /// endpoints last found to be unhealthy, if either has changed
+    fn apply_endpoint_updates(&self, state: &mut State<T>) {
+        if let Some(health) = &state.health_updates {
+            state.unhealthy = health.get_ref().clone();
+        }
+        let latest = match &state.endpoint_updates {
+            Some(updates) => updates.get_ref().clone(),
+            None => state.services.endpoints.clone(),
+        };
+        // Clients connected to a single endpoint have nothing to balance between
+        if latest.is_empty() {
+            return;
+        }
+        // The endpoints were all validated when they were parsed by the sync task
+        let _ = self.route(state, latest);
+    }
+
+    /// Compact the event history up to `revision`, see [`Kv::compact`]
+    pub async fn compact(&self, revision: i64, physical: bool) -> EtcdResult<()> {
+        self.kv().compact(revision, physical).await
+    }
+
+    /// Compact the event history in the background according to `policy`, see
+    /// [`Kv::auto_compact`]
+    pub fn auto_compact(&self, policy: CompactionPolicy, interval: Duration) -> AutoCompactor {
+        self.kv().auto_compact(policy, interval)
+    }
+
+    /// Put each of the `items`, see [`Kv::put_many`]
+    pub async fn put_many<I, K, V>(&self, items: I) -> BatchOutcome<()>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: AsRef<[u8]>,
+    {
+        self.kv().put_many(items).await
+    }
+
+    /// Get the value of each of `keys`, see [`Kv::get_many`]
+    pub async fn get_many<I, K>(&self, keys: I) -> BatchOutcome<Option<String>>
+    where
+        I: IntoIterator<Item = K>,
+        K: Into<String>,
+    {
+        self.kv().get_many(keys).await
+    }
+
+    /// Read each of `ranges` with up to `max_concurrency` reads at once, see
+    /// [`Kv::get_ranges`]
+    pub fn get_ranges<I, K>(&self, ranges: I, max_concurrency: usize) -> RangeReads
+    where
+        I: IntoIterator<Item = (K, Option<K>)>,
+        K: AsRef<[u8]>,
+    {
+        self.kv().get_ranges(ranges, max_concurrency)
+    }
+
+    /// The keys from `start` up to `end`, see [`Kv::range`]
+    pub fn range<K>(&self, start: K, end: Option<K>) -> Range<T>
+    where
+        K: AsRef<[u8]>,
+    {
+        self.kv().range(start, end)
+    }
+
+    /// Acquire the distributed lock `name` using etcd's lock service, waiting until it is free.
+    ///
+    /// Returns the key which exists for as long as the lock is held; pass it to
+    /// [`EtcdClient::unlock`] to release the lock. If `lease` is non-zero the lock is attached
+    /// to that lease and is released automatically when the lease expires or is revoked.
+    #[cfg(feature = "lock")]
+    pub async fn lock<N>(&self, name: N, lease: i64) -> EtcdResult<Vec<u8>>
+    where
+        N: Into<Vec<u8>>,
+    {
+        let name = name.into();
+        let mut context = self.error_context("lock", Some(&name));
+        let request = v3lock::LockRequest {
+            name: self.namespaced_key(&name),
+            lease,
+        };
+        let operation = self.operation("lock.lock", &context);
+        let call = async {
+            let _in_flight = self.config.throttle(Budget::Write).await?;
+            let response = self.lock_client().lock(request).await?.into_inner();
+            context.header = response.header.clone();
+            self.check_cluster_id(response.header.as_ref())?;
+            Ok(strip_namespace(&self.namespace, &response.key).to_vec())
+        };
+        let result: EtcdResult<_> = operation.run(call).await;
+        operation.finish(&context, &result);
+        result.map_err(|e| context.attach(e))
+    }
+
+    /// Release a lock acquired with [`EtcdClient::lock`], given the key it returned
+    #[cfg(feature = "lock")]
+    pub async fn unlock<K>(&self, key: K) -> EtcdResult<()>
+    where
+        K: Into<Vec<u8>>,
+    {
+        let key = key.into();
+        let mut context = self.error_context("unlock", Some(&key));
+        let request = v3lock::UnlockRequest {
+            key: self.namespaced_key(&key),
+        };
+        let operation = self.operation("lock.unlock", &context);
+        let call = async {
+            let _in_flight = self.config.throttle(Budget::Write).await?;
+            let response = self.lock_client().unlock(request).await?;
+            context.header = response.get_ref().header.clone();
+            self.check_cluster_id(response.get_ref().header.as_ref())?;
+            Ok(())
+        };
+        let result: EtcdResult<()> = operation.run(call).await;
+        operation.finish(&context, &result);
+        result.map_err(|e| context.attach(e))
+    }
+
+    /// Reads and writes of keys
+    pub fn kv(&self) -> Kv<T> {
+        Kv {
+            client: self.clone(),
+        }
+    }
+
+    /// Values of type `V` stored under `prefix` and encoded as JSON, see [`TypedKv`]. Use
+    /// [`TypedKv::new`] for other codecs.
+    #[cfg(feature = "serde")]
+    pub fn typed<V, P>(&self, prefix: P) -> TypedKv<V, Json, T>
+    where
+        V: serde::Serialize + serde::de::DeserializeOwned,
+        P: AsRef<[u8]>,
+    {
+        TypedKv::new(self.clone(), prefix, Json)
+    }
+
+    /// Watches following changes to keys
+    pub fn watch(&self) -> Watch<T> {
+        Watch {
+            client: self.clone(),
+        }
+    }
+
+    /// Leases which keys can be attached to
+    pub fn lease(&self) -> Lease<T> {
+        Lease {
+            client: self.clone(),
+        }
+    }
+
+    /// The client's RPCs as `tower` services taking and returning the raw messages, for
+    /// wrapping in `tower` middleware, see the [`service`] module
+    pub fn service(&self) -> service::RpcService<T> {
+        service::RpcService {
+            client: self.clone(),
+        }
+    }
+
+    /// Authentication, users and roles
+    #[cfg(feature = "auth")]
+    pub fn auth(&self) -> Auth<T> {
+        Auth {
+            client: self.clone(),
+        }
+    }
+
+    #[cfg(feature = "cluster")]
+    pub fn cluster(&self) -> Cluster<T> {
+        Cluster {
+            client: self.clone(),
+        }
+    }
+
+    #[cfg(feature = "maintenance")]
+    pub fn maintenance(&self) -> Maintenance<T> {
+        Maintenance {
+            client: self.clone(),
+        }
+    }
+
+    #[cfg(feature = "election")]
+    pub fn election(&self) -> Election<T> {
+        Election {
+            client: self.clone(),
+        }
+    }
+
+    /// Server and cluster version, see [`Maintenance::version`]
+    #[cfg(feature = "maintenance")]
+    pub async fn version(&self) -> EtcdResult<VersionInfo> {
+        self.maintenance().version().await
+    }
+
+    /// Close the client, so process shutdown is deterministic: new requests fail, the
+    /// background tasks started from the client stop, watches are cancelled and sessions
+    /// created from the client have their leases revoked. Then wait up to `timeout` for the
+    /// requests and streams still in flight to finish, failing if they do not.
+    ///
+    /// This applies to everything sharing the client's connections, such as the recipes in
+    /// [`concurrency`] and the client's clones, even if it was created before the client was
+    /// last synced.
+    pub async fn close(self, timeout: Duration) -> EtcdResult<()> {
+        let shutdown = self.config.shutdown.clone();
+        shutdown.close();
+        drop(self);
+
+        let started = Instant::now();
+        let deadline = started + timeout;
+        loop {
+            let in_flight = shutdown.in_flight.load(Ordering::Relaxed);
+            if in_flight == 0 {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                let what = format!("timed out waiting for {} requests and streams", in_flight);
+                return Err(Error::timeout(what, timeout, started));
+            }
+            runtime::delay_for(CLOSE_POLL_INTERVAL).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn connecting_to_instance() {
+        let _client = testing::shared_client().await.unwrap();
+    }
+
+    #[cfg(feature = "cluster")]
+    #[tokio::test]
+    async fn test_waiting_for_ready() {
+        wait_for_ready(vec![testing::shared_endpoint()], Duration::from_secs(5))
+            .await
+            .unwrap();
+    }
+
+    #[cfg(feature = "cluster")]
+    #[tokio::test]
+    async fn test_waiting_for_unreachable_cluster() {
+        // Nothing is listening here
+        let err = wait_for_ready(vec!["http://127.0.0.1:1"], Duration::from_millis(300))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().starts_with("cluster not ready"));
+    }
+
+    /// The vendored code has to be regenerated whenever the proto files or the build script
+    /// change, by building with `ETCD3_VENDOR_PROTOS` set
+    #[cfg(not(feature = "vendored-protos"))]
+    #[test]
+    fn test_vendored_protos_are_up_to_date() {
+        macro_rules! check {
+            ($package:literal) => {
+                let generated = include_str!(concat!(env!("OUT_DIR"), "/", $package, ".rs"));
+                let vendored = include_str!(concat!("proto/vendored/", $package, ".rs"));
+                assert!(generated == vendored, "{} is out of date", $package);
+            };
+        }
+        check!("mvccpb");
+        check!("authpb");
+        check!("etcdserverpb");
+        #[cfg(feature = "lock")]
+        check!("v3lockpb");
+        #[cfg(feature = "election")]
+        check!("v3electionpb");
+    }
+
+    #[test]
+    fn test_member_endpoints() {
+        let member = |urls: &[&str], is_learner| etcdserver::Member {
+            client_ur_ls: urls.iter().map(|u| u.to_string()).collect(),
+            is_learner,
+            ..Default::default()
+        };
+        let members = vec![
+            member(&["http://10.0.0.2:2379", "http://10.0.0.1:2379"], false),
+            member(&["http://10.0.0.1:2379"], false),
+            // Not started yet
+            member(&[], false),
+            member(&["http://10.0.0.3:2379"], true),
+        ];
+
+        assert_eq!(
+            member_endpoints(&members),
+            vec!["http://10.0.0.1:2379", "http://10.0.0.2:2379"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_syncing_endpoints() {
+        let client = testing::namespace().await.unwrap();
+        assert!(client.endpoints().is_empty());
+
+        client.sync_endpoints().await.unwrap();
+        assert_eq!(client.endpoints().len(), 1);
+
+        // Requests still work over the synced endpoints
+        let members = client.member_list(false).await.unwrap();
+        assert_eq!(members.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_connecting_to_several_endpoints() {
+        let endpoints = vec![testing::shared_endpoint(), "http://127.0.0.1:1"];
+        let client = EtcdClient::connect_all(endpoints).await.unwrap();
+        assert_eq!(client.endpoints().len(), 2);
+
+        // Requests are balanced onto the endpoint which is up
+        for _ in 0..4 {
+            client.member_list(false).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_timeouts() {
+        // Nothing listens on port 1
+        let connected = EtcdClient::builder()
+            .endpoint("http://127.0.0.1:1")
+            .connect_timeout(Duration::from_millis(100))
+            .connect()
+            .await;
+        assert!(connected.is_err());
+
+        let client = EtcdClient::builder()
+            .endpoint(testing::shared_endpoint())
+            .connect_timeout(Duration::from_secs(1))
+            .timeout(Duration::from_secs(5))
+            .connect()
+            .await
+            .unwrap();
+        client
+            .range("test_timeouts", None)
+            .timeout(Duration::from_secs(1))
+            .put("value")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_connect_timeout_covers_authentication() {
+        // The listener accepts connections but never answers on them
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let started = Instant::now();
+        let connected = EtcdClient::builder()
+            .endpoint(endpoint)
+            .credentials("root", "secret")
+            .connect_timeout(Duration::from_millis(200))
+            .connect()
+            .await;
+        match connected {
+            Err(Error::Timeout { timeout, .. }) => assert_eq!(timeout, Duration::from_millis(200)),
+            Err(e) => panic!("expected a timeout, got {}", e),
+            Ok(_) => panic!("expected a timeout"),
+        }
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_building_clients() {
+        assert!(EtcdClient::builder().connect().await.is_err());
+
+        let client = EtcdClient::builder()
+            .endpoint("http://127.0.0.1:2379")
+            .endpoints(vec!["http://127.0.0.2:2379", "http://127.0.0.3:2379"])
+            .connect()
+            .await
+            .unwrap();
+        assert_eq!(client.endpoints().len(), 3);
+
+        // Unix domain sockets are dialled through a tunnel on the loopback interface
+        let client = EtcdClient::builder()
+            .endpoint("unix:///var/run/etcd.sock")
+            .connect()
+            .await
+            .unwrap();
+        assert_eq!(client.endpoints(), ["unix:///var/run/etcd.sock"]);
+        assert_eq!(client.config.tunnels.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+            retry_mutations: false,
+        };
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(4), Duration::from_millis(800));
+        assert_eq!(policy.backoff(5), Duration::from_secs(1));
+        assert_eq!(policy.backoff(100), Duration::from_secs(1));
+
+        for _ in 0..100 {
+            let backoff = jitter(Duration::from_millis(100));
+            assert!(backoff >= Duration::from_millis(50));
+            assert!(backoff <= Duration::from_millis(100));
+        }
+    }
+
+    #[test]
+    fn test_reconnect_backoff() {
+        let mut backoff = Backoff::new();
+        let first = backoff.next();
+        assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+        for _ in 0..100 {
+            assert!(backoff.next() <= Duration::from_secs(5));
+        }
+        assert!(backoff.next() >= Duration::from_millis(2500));
+        backoff.reset();
+        assert!(backoff.next() <= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn test_hedging_requests() {
+        async fn respond(
+            after: Duration,
+            response: Result<u32, tonic::Code>,
+        ) -> Result<u32, tonic::Status> {
+            tokio::timer::delay_for(after).await;
+            response.map_err(|code| tonic::Status::new(code, "failed"))
+        }
+        let (fast, slow) = (Duration::from_millis(0), Duration::from_millis(200));
+        let delay = Duration::from_millis(20);
+
+        // The hedged request is only sent once the primary has been given `delay`
+        let sent = AtomicBool::new(false);
+        let hedged = async {
+            sent.store(true, Ordering::Relaxed);
+            Ok(2)
+        };
+        let response = hedge_request(delay, respond(fast, Ok(1)), hedged).await;
+        assert_eq!(response.unwrap(), 1);
+        assert!(!sent.load(Ordering::Relaxed));
+
+        let response = hedge_request(delay, respond(slow, Ok(1)), respond(fast, Ok(2))).await;
+        assert_eq!(response.unwrap(), 2);
+        let failed = Err(tonic::Code::Unavailable);
+        let response = hedge_request(delay, respond(fast, failed), respond(slow, Ok(2))).await;
+        assert_eq!(response.unwrap(), 2);
+        let response = hedge_request(delay, respond(slow, Ok(1)), respond(fast, failed)).await;
+        assert_eq!(response.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_retries() {
+        async fn attempts(policy: RetryPolicy, idempotent: bool, code: tonic::Code) -> u32 {
+            let retried = Arc::new(AtomicUsize::new(0));
+            let counted = retried.clone();
+            let retries = Retries {
+                policy,
+                diagnostics: Diagnostics::new(move |_| {
+                    counted.fetch_add(1, Ordering::Relaxed);
+                }),
+                operation: "test",
+                clock: SharedClock::default(),
+            };
+            let (mut attempts, mut sent) = (0, 0);
+            let result: Result<(), _> = with_retries(retries, idempotent, &mut attempts, || {
+                sent += 1;
+                future::ready(Err(tonic::Status::new(code, "failed")))
+            })
+            .await;
+            assert!(result.is_err());
+            assert_eq!(attempts, sent);
+            // Every attempt but the last is reported as a retry
+            assert_eq!(retried.load(Ordering::Relaxed), sent as usize - 1);
+            attempts
+        }
+
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(1),
+            ..Default::default()
+        };
+        let timed_out = tonic::Code::DeadlineExceeded;
+        assert_eq!(attempts(policy, true, tonic::Code::Unavailable).await, 3);
+        assert_eq!(attempts(policy, true, timed_out).await, 3);
+        assert_eq!(attempts(policy, true, tonic::Code::NotFound).await, 1);
+        assert_eq!(attempts(policy, false, tonic::Code::Unavailable).await, 1);
+        assert_eq!(RetryPolicy::disabled().max_attempts, 1);
+
+        let policy = RetryPolicy {
+            retry_mutations: true,
+            ..policy
+        };
+        assert_eq!(attempts(policy, false, tonic::Code::Unavailable).await, 3);
+    }
+
+    #[tokio::test]
+    async fn test_backing_off_on_clock() {
+        let clock = MockClock::new();
+        let backoff = Duration::from_secs(3600);
+        let retries = Retries {
+            policy: RetryPolicy {
+                initial_backoff: backoff,
+                max_backoff: backoff,
+                ..Default::default()
+            },
+            diagnostics: Diagnostics::default(),
+            operation: "test",
+            clock: SharedClock::new(clock.clone()),
+        };
+        let mut attempts = 0;
+        let retried = with_retries(retries, true, &mut attempts, || {
+            let unavailable = tonic::Status::new(tonic::Code::Unavailable, "unavailable");
+            future::ready(Err::<(), _>(unavailable))
+        });
+        // Each backoff only passes once the clock is moved on
+        let advance = async {
+            for _ in 0..2 {
+                while clock.sleeping() == 0 {
+                    runtime::delay_for(Duration::from_millis(1)).await;
+                }
+                clock.advance(backoff);
+            }
+        };
+        let (result, ()) = future::join(retried, advance).await;
+        assert!(result.is_err());
+        assert_eq!(attempts, 3);
+    }
+
+    #[tokio::test]
+    async fn test_keep_alive() {
+        // Nothing listens on port 1, so only the client's own use of the channel is seen
+        let keep_alive = KeepAlive {
+            interval: Duration::from_millis(10),
+            while_idle: false,
+            ..Default::default()
+        };
+        let client = EtcdClient::builder()
+            .endpoint("http://127.0.0.1:1")
+            .keep_alive(keep_alive)
+            .retry(RetryPolicy::disabled())
+            .connect()
+            .await
+            .unwrap();
+        assert!(!client.config.active.load(Ordering::Relaxed));
+        assert!(client.range("test_keep_alive", None).get().await.is_err());
+        assert!(client.config.active.load(Ordering::Relaxed));
+
+        // The ping after the request resets the client to idle
+        tokio::timer::delay_for(Duration::from_millis(100)).await;
+        assert!(!client.config.active.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn test_connect_with_layer() {
+        use std::task::{Context, Poll};
+        use tonic::codegen::Service;
+
+        /// Counts the requests sent through it
+        #[derive(Clone)]
+        struct Counted<S>(S, Arc<AtomicUsize>);
+
+        impl<S, B> Service<http::Request<B>> for Counted<S>
+        where
+            S: Service<http::Request<B>>,
+        {
+            type Response = S::Response;
+            type Error = S::Error;
+            type Future = S::Future;
+
+            fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+                self.0.poll_ready(cx)
+            }
+
+            fn call(&mut self, request: http::Request<B>) -> Self::Future {
+                self.1.fetch_add(1, Ordering::Relaxed);
+                self.0.call(request)
+            }
+        }
+
+        struct Counting(Arc<AtomicUsize>);
+
+        impl<S> tower_layer::Layer<S> for Counting {
+            type Service = Counted<S>;
+
+            fn layer(&self, inner: S) -> Counted<S> {
+                Counted(inner, self.0.clone())
+            }
+        }
+
+        // Nothing listens on port 1, but the requests still go through the layer
+        let requests = Arc::new(AtomicUsize::new(0));
+        let client = EtcdClient::builder()
+            .endpoint("http://127.0.0.1:1")
+            .retry(RetryPolicy::disabled())
+            .connect_with_layer(Counting(requests.clone()))
+            .await
+            .unwrap();
+        assert!(client.range("layered", None).get().await.is_err());
+        assert_eq!(requests.load(Ordering::Relaxed), 1);
+
+        // Clients dialling other endpoints keep the layer
+        let endpoints = vec!["http://127.0.0.1:2".to_string()];
+        client.set_endpoints(endpoints).unwrap();
+        assert!(client.range("layered", None).get().await.is_err());
+        assert_eq!(requests.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_proxies() {
+        let proxy = Proxy {
+            url: "http://proxy.example.com:3128".into(),
+            no_proxy: vec!["localhost".into(), ".internal.example.com".into()],
+        };
+        assert!(proxy.bypasses("localhost"));
+        assert!(proxy.bypasses("internal.example.com"));
+        assert!(proxy.bypasses("etcd-0.INTERNAL.example.com"));
+        assert!(!proxy.bypasses("notinternal.example.com"));
+        assert!(!proxy.bypasses("etcd.example.com"));
+        assert!(Proxy {
+            no_proxy: vec!["*".into()],
+            ..proxy.clone()
+        }
+        .bypasses("etcd.example.com"));
+
+        assert_eq!(
+            proxy.target("https://etcd.example.com").unwrap(),
+            Some(tunnel::Target::Proxy {
+                proxy: "proxy.example.com:3128".into(),
+                authority: "etcd.example.com:2379".into(),
+            })
+        );
+        assert_eq!(proxy.target("http://localhost:2379").unwrap(), None);
+        let proxy = Proxy::new("http://proxy.example.com");
+        let target = proxy.target("http://10.0.0.1:12379").unwrap();
+        assert_eq!(
+            target,
+            Some(tunnel::Target::Proxy {
+                proxy: "proxy.example.com:80".into(),
+                authority: "10.0.0.1:12379".into(),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connecting_lazily() {
+        // Nothing listens on port 1, which is only noticed once a request is sent
+        let client = EtcdClient::connect_lazy("http://127.0.0.1:1").unwrap();
+        let response = client.range("test_connecting_lazily", None).get().await;
+        assert!(response.is_err());
+
+        assert!(EtcdClient::builder().connect_lazy().is_err());
+        assert!(EtcdClient::connect_lazy("not a url").is_err());
+        let with_credentials = EtcdClient::builder()
+            .endpoint("http://127.0.0.1:2379")
+            .credentials("root", "password")
+            .connect_lazy();
+        assert!(with_credentials.is_err());
+        let with_srv = EtcdClient::builder()
+            .discovery_srv("example.com")
+            .connect_lazy();
+        assert!(with_srv.is_err());
+    }
+
+    #[test]
+    fn test_healthy_endpoints() {
+        let endpoints = vec!["http://a:2379".to_string(), "http://b:2379".to_string()];
+        let unhealthy = vec!["http://a:2379".to_string()];
+        assert_eq!(healthy_endpoints(&endpoints, &unhealthy), ["http://b:2379"]);
+        assert_eq!(healthy_endpoints(&endpoints, &[]), endpoints);
+        // Requests still go somewhere if every endpoint is unhealthy
+        assert_eq!(healthy_endpoints(&endpoints, &endpoints), endpoints);
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let settings = CircuitBreaker {
+            failure_threshold: 2,
+            open_for: Duration::from_secs(10),
+        };
+        let start = Instant::now();
+        let mut circuit = Circuit::default();
+        circuit.record(false, settings, start);
+        assert!(!circuit.is_open());
+        circuit.record(true, settings, start);
+        circuit.record(false, settings, start);
+        assert!(!circuit.is_open());
+        circuit.record(false, settings, start);
+        assert!(circuit.is_open());
+
+        // The circuit half-opens once it has been open for long enough
+        assert!(!circuit.probe_due(settings, start + Duration::from_secs(5)));
+        let half_open = start + Duration::from_secs(10);
+        assert!(circuit.probe_due(settings, half_open));
+        circuit.record(false, settings, half_open);
+        assert!(circuit.is_open());
+        assert!(!circuit.probe_due(settings, half_open + Duration::from_secs(5)));
+        circuit.record(true, settings, half_open + Duration::from_secs(10));
+        assert_eq!(circuit, Circuit::default());
+
+        // Without a circuit breaker, endpoints are out after one failure and always probed
+        let mut circuit = Circuit::default();
+        circuit.record(false, CircuitBreaker::immediate(), start);
+        assert!(circuit.is_open());
+        assert!(circuit.probe_due(CircuitBreaker::immediate(), start));
+    }
+
+    #[test]
+    fn test_rate_limits() {
+        let limiter = Limiter::new(RateLimit {
+            per_second: 10,
+            burst: 2,
+        });
+        let start = Instant::now();
+        assert_eq!(limiter.take(start), Duration::from_secs(0));
+        assert_eq!(limiter.take(start), Duration::from_secs(0));
+        // Once the burst is used up, requests are spaced out at the rate
+        let wait = limiter.take(start);
+        assert!(wait > Duration::from_millis(99) && wait <= Duration::from_millis(100));
+        let wait = limiter.take(start);
+        assert!(wait > Duration::from_millis(199) && wait <= Duration::from_millis(200));
+
+        // The bucket refills over time, but never beyond the burst
+        let later = start + Duration::from_secs(10);
+        assert_eq!(limiter.take(later), Duration::from_secs(0));
+        assert_eq!(limiter.take(later), Duration::from_secs(0));
+        assert!(limiter.take(later) > Duration::from_secs(0));
+    }
+
+    #[tokio::test]
+    async fn test_throttling() {
+        let config = ChannelConfig {
+            write_limit: Some(Arc::new(Limiter::new(RateLimit::per_second(20)))),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        for _ in 0..25 {
+            config.throttle(Budget::Read).await.unwrap();
+        }
+        assert!(start.elapsed() < Duration::from_millis(100));
+        // Once the burst of 20 is used up, 5 more writes take a quarter of a second
+        for _ in 0..25 {
+            config.throttle(Budget::Write).await.unwrap();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn test_in_flight_limit() {
+        let config = ChannelConfig {
+            in_flight: Some(Arc::new(InFlightLimit::new(2))),
+            ..Default::default()
+        };
+        let first = config.throttle(Budget::Read).await.unwrap();
+        let second = config.throttle(Budget::Write).await.unwrap();
+        assert!(first.token.is_some() && second.token.is_some());
+
+        // A third request waits until one of the others finishes
+        let third = config.throttle(Budget::Read);
+        futures::pin_mut!(third);
+        let waited = tokio::timer::Timeout::new(&mut third, Duration::from_millis(50)).await;
+        assert!(waited.is_err());
+        drop(first);
+        let waited = tokio::timer::Timeout::new(&mut third, Duration::from_millis(50)).await;
+        assert!(waited.is_ok());
+
+        let unlimited = ChannelConfig::default();
+        let in_flight = unlimited.throttle(Budget::Read).await.unwrap();
+        assert!(in_flight.token.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_shedding_background_traffic() {
+        let config = ChannelConfig {
+            read_limit: Some(Arc::new(Limiter::new(RateLimit::per_second(2)))),
+            in_flight: Some(Arc::new(InFlightLimit::new(2))),
+            ..Default::default()
+        };
+        let first = config
+            .throttle_as(Budget::Read, TrafficClass::Background)
+            .await
+            .unwrap();
+        assert!(first.token.is_some());
+        let second = config.throttle(Budget::Read).await.unwrap();
+
+        // Background requests are shed once the rate limit or the bound in flight is reached,
+        // while interactive ones wait
+        let shed = config.throttle_as(Budget::Read, TrafficClass::Background);
+        assert!(matches!(shed.await, Err(Error::Shed)));
+        let wrote = config.throttle_as(Budget::Write, TrafficClass::Background);
+        assert!(matches!(wrote.await, Err(Error::Shed)));
+        drop(second);
+        let interactive = config.throttle(Budget::Read).await.unwrap();
+        assert!(interactive.token.is_some());
+        drop((first, interactive));
+        let wrote = config.throttle_as(Budget::Write, TrafficClass::Background);
+        assert!(wrote.await.is_ok());
+        assert!(Error::Shed.is_retryable());
+
+        // Requests shed by the bound in flight leave the rate limit's tokens alone
+        let config = ChannelConfig {
+            read_limit: Some(Arc::new(Limiter::new(RateLimit::per_second(1)))),
+            in_flight: Some(Arc::new(InFlightLimit::new(1))),
+            ..Default::default()
+        };
+        let write = config.throttle(Budget::Write).await.unwrap();
+        let shed = config.throttle_as(Budget::Read, TrafficClass::Background);
+        assert!(matches!(shed.await, Err(Error::Shed)));
+        drop(write);
+        let read = config.throttle_as(Budget::Read, TrafficClass::Background);
+        assert!(read.await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_closing() {
+        let client = EtcdClient::connect_lazy("http://127.0.0.1:1").unwrap();
+        let config = client.config.clone();
+        let closed = config.shutdown.closed();
+        client.close(Duration::from_secs(1)).await.unwrap();
+        assert!(closed.await.is_err());
+        assert!(config.throttle(Budget::Read).await.is_err());
+        assert_eq!(config.shutdown.in_flight.load(Ordering::Relaxed), 0);
+
+        // Closing waits for whatever is still in flight
+        let client = EtcdClient::connect_lazy("http://127.0.0.1:1").unwrap();
+        let in_flight = client.config.throttle(Budget::Write).await.unwrap();
+        let shutdown = client.config.shutdown.clone();
+        tokio::spawn(async move {
+            tokio::timer::delay_for(Duration::from_millis(50)).await;
+            drop(in_flight);
+        });
+        client.close(Duration::from_secs(1)).await.unwrap();
+        assert_eq!(shutdown.in_flight.load(Ordering::Relaxed), 0);
+
+        let client = EtcdClient::connect_lazy("http://127.0.0.1:1").unwrap();
+        let _in_flight = client.config.throttle(Budget::Write).await.unwrap();
+        assert!(client.close(Duration::from_millis(50)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_cloning() {
+        // Nothing listens on ports 1 to 3
+        let endpoints = vec!["http://127.0.0.1:1", "http://127.0.0.1:2"];
+        let client = EtcdClient::connect_all(endpoints).await.unwrap();
+        let clone = client.clone();
+        assert!(Arc::ptr_eq(&client.services(), &clone.services()));
+
+        // Clones share their routing, so endpoints synced through one are used by all
+        let endpoints = vec!["http://127.0.0.1:3".to_string()];
+        clone.set_endpoints(endpoints).unwrap();
+        assert_eq!(client.endpoints(), ["http://127.0.0.1:3"]);
+
+        // Operations take `&self`, so they can run at once on a shared client
+        let ranges: Vec<_> = (0..3).map(|_| client.range("test_cloning", None)).collect();
+        let gets = future::join_all(ranges.iter().map(Range::get)).await;
+        assert!(gets.iter().all(Result::is_err));
+        let (tx, rx) = oneshot::channel();
+        tokio::spawn(async move {
+            let _ = clone.range("test_cloning", None).get().await;
+            let _ = tx.send(clone);
+        });
+        let clone = rx.await.unwrap();
+
+        // Closing any clone closes them all
+        clone.clone().close(Duration::from_secs(1)).await.unwrap();
+        assert!(client.range("test_cloning", None).get().await.is_err());
+        assert!(client.config.shutdown.closed.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_balance_policies() {
+        let endpoints: Vec<String> = vec!["http://a:2379", "http://b:2379", "http://c:2379"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let down = vec![endpoints[0].clone()];
+        let route =
+            |config: &ChannelConfig, down: &[String]| config.routed_endpoints(&endpoints, down);
+        let mut config = ChannelConfig::default();
+        assert_eq!(route(&config, &down), &endpoints[1..]);
+
+        config.balance = BalancePolicy::PickFirst;
+        assert_eq!(route(&config, &[]), &endpoints[..1]);
+        assert_eq!(route(&config, &down), &endpoints[1..2]);
+
+        config.balance = BalancePolicy::Priority;
+        config.priorities.insert(endpoints[0].clone(), 0);
+        config.priorities.insert(endpoints[1].clone(), 1);
+        config.priorities.insert(endpoints[2].clone(), 1);
+        assert_eq!(route(&config, &[]), &endpoints[..1]);
+        assert_eq!(route(&config, &down), &endpoints[1..]);
+        config.priorities.remove(&endpoints[2]);
+        assert_eq!(route(&config, &down), &endpoints[1..2]);
+    }
+
+    #[tokio::test]
+    async fn test_tracking_endpoint_health() {
+        // Nothing listens on ports 1 and 2
+        let endpoints = vec!["http://127.0.0.1:1", "http://127.0.0.1:2"];
+        let client = EtcdClient::connect_all(endpoints).await.unwrap();
+        assert!(client.unhealthy_endpoints().is_empty());
+
+        let _health = client.track_endpoint_health(Duration::from_millis(50));
+        tokio::timer::delay_for(Duration::from_millis(500)).await;
+        let mut unhealthy = client.unhealthy_endpoints();
+        unhealthy.sort();
+        assert_eq!(unhealthy, ["http://127.0.0.1:1", "http://127.0.0.1:2"]);
+
+        let routed = client.services().routed.clone();
+        assert_eq!(routed, client.endpoints());
+    }
+
+    /// A transport counting the requests sent over a channel
+    #[derive(Clone)]
+    struct Counting {
+        channel: tonic::transport::channel::Channel,
+        requests: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl tonic::client::GrpcService<tonic::body::BoxBody> for Counting {
+        type ResponseBody = tonic::transport::Body;
+        type Error = tonic::transport::Error;
+        type Future = tonic::transport::channel::ResponseFuture;
+
+        fn poll_ready(
+            &mut self,
+            cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Result<(), Self::Error>> {
+            self.channel.poll_ready(cx)
+        }
+
+        fn call(&mut self, request: http::Request<tonic::body::BoxBody>) -> Self::Future {
+            self.requests.fetch_add(1, Ordering::Relaxed);
+            self.channel.call(request)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_custom_transports() {
+        // Nothing listens on port 1
+        let endpoint = Endpoint::from_static("http://127.0.0.1:1");
+        let transport = Counting {
+            channel: tonic::transport::channel::Channel::balance_list(vec![endpoint].into_iter()),
+            requests: Arc::new(Default::default()),
+        };
+        let requests = transport.requests.clone();
+        let client = EtcdClient::from_transport(transport);
+        let response = client.range("test_custom_transports", None).get().await;
+        assert!(response.is_err());
+        assert_eq!(requests.load(Ordering::Relaxed), 1);
+        // The client cannot dial other endpoints itself
+        let endpoints = vec!["http://127.0.0.1:2".to_string()];
+        assert!(client.set_endpoints(endpoints).is_err());
+    }
+
+    /// A transport answering every request with the same message, however malformed, as a
+    /// successful gRPC response
+    #[derive(Clone)]
+    struct Canned {
+        message: Vec<u8>,
+    }
+
+    /// Body of a canned response: a single frame, then trailers reporting success
+    struct CannedBody {
+        frame: Option<Frame>,
+    }
+
+    struct Frame(std::io::Cursor<bytes::Bytes>);
+
+    impl bytes::Buf for Frame {
+        fn remaining(&self) -> usize {
+            self.0.remaining()
+        }
+
+        fn bytes(&self) -> &[u8] {
+            self.0.bytes()
+        }
+
+        fn advance(&mut self, count: usize) {
+            self.0.advance(count)
+        }
+    }
+
+    impl From<Frame> for bytes::Bytes {
+        fn from(frame: Frame) -> Self {
+            frame.0.into_inner()
+        }
+    }
+
+    impl tonic::codegen::HttpBody for CannedBody {
+        type Data = Frame;
+        type Error = std::convert::Infallible;
+
+        fn poll_data(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Option<Result<Frame, Self::Error>>> {
+            std::task::Poll::Ready(self.get_mut().frame.take().map(Ok))
+        }
+
+        fn poll_trailers(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Result<Option<http::HeaderMap>, Self::Error>> {
+            let mut trailers = http::HeaderMap::new();
+            trailers.insert("grpc-status", http::HeaderValue::from_static("0"));
+            std::task::Poll::Ready(Ok(Some(trailers)))
+        }
+    }
+
+    impl tonic::client::GrpcService<tonic::body::BoxBody> for Canned {
+        type ResponseBody = CannedBody;
+        type Error = std::convert::Infallible;
+        type Future = futures::future::Ready<Result<http::Response<CannedBody>, Self::Error>>;
+
+        fn poll_ready(
+            &mut self,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Result<(), Self::Error>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: http::Request<tonic::body::BoxBody>) -> Self::Future {
+            let mut frame = vec![0];
+            frame.extend(&(self.message.len() as u32).to_be_bytes());
+            frame.extend(&self.message);
+            let body = CannedBody {
+                frame: Some(Frame(std::io::Cursor::new(frame.into()))),
+            };
+            futures::future::ready(Ok(http::Response::new(body)))
+        }
+    }
+
+    /// Client whose every request is answered with `message`
+    fn canned<M: prost::Message>(message: M) -> EtcdClient<Canned> {
+        let mut encoded = Vec::new();
+        message.encode(&mut encoded).unwrap();
+        EtcdClient::from_transport(Canned { message: encoded })
+    }
+
+    #[tokio::test]
+    async fn test_malformed_responses() {
+        let header = Some(etcdserver::ResponseHeader {
+            cluster_id: 1,
+            ..Default::default()
+        });
+
+        // Every response should carry a header
+        let client = canned(etcdserver::RangeResponse::default());
+        let err = client.range("foo", None).get().await.unwrap_err();
+        assert!(matches!(err.root(), Error::InvalidResponse(_)));
+        let client = canned(etcdserver::PutResponse::default());
+        let err = client.range("foo", None).put("bar").await.unwrap_err();
+        assert!(matches!(err.root(), Error::InvalidResponse(_)));
+
+        let kv = mvccpb::KeyValue {
+            key: b"foo".to_vec(),
+            value: vec![0xff],
+            ..Default::default()
+        };
+        let client = canned(etcdserver::RangeResponse {
+            header: header.clone(),
+            kvs: vec![kv],
+            ..Default::default()
+        });
+        let err = client.kv().get("foo").await.unwrap_err();
+        assert!(matches!(err.root(), Error::Utf8(_)));
+
+        #[cfg(feature = "cluster")]
+        {
+            let client = canned(etcdserver::MemberAddResponse {
+                header,
+                ..Default::default()
+            });
+            let err = client
+                .cluster()
+                .member_add(vec!["http://127.0.0.1:2380"])
+                .await
+                .unwrap_err();
+            assert!(matches!(err.root(), Error::InvalidResponse(_)));
+        }
+
+        // Messages which do not decode at all are reported by the transport
+        let client = EtcdClient::from_transport(Canned {
+            message: vec![0xff; 4],
+        });
+        let err = client.range("foo", None).get().await.unwrap_err();
+        assert!(matches!(err.root(), Error::Status(_)));
+    }
+
+    #[tokio::test]
+    async fn test_sub_clients() {
+        let header = Some(etcdserver::ResponseHeader {
+            cluster_id: 1,
+            ..Default::default()
+        });
+
+        let client = canned(etcdserver::LeaseGrantResponse {
+            header: header.clone(),
+            id: 7,
+            ttl: 60,
+            ..Default::default()
+        });
+        let lease = client.lease().grant(60).await.unwrap();
+        assert_eq!((lease.id, lease.ttl), (7, 60));
+
+        // Leases which have expired are kept alive with a TTL of zero
+        let client = canned(etcdserver::LeaseKeepAliveResponse {
+            header: header.clone(),
+            id: 7,
+            ttl: 0,
+        });
+        let err = client.lease().keep_alive_once(7).await.unwrap_err();
+        assert!(matches!(err.root(), Error::LeaseNotFound(Some(7))));
+
+        #[cfg(feature = "auth")]
+        {
+            let client = canned(etcdserver::AuthUserListResponse {
+                header: header.clone(),
+                users: vec!["root".to_string()],
+            });
+            assert_eq!(client.auth().users().await.unwrap(), ["root"]);
+        }
+
+        // The top-level conveniences go through the sub-clients
+        let client = canned(etcdserver::RangeResponse {
+            header,
+            ..Default::default()
+        });
+        assert_eq!(client.kv().get("foo").await.unwrap(), None);
+        assert!(client.range("foo", None).get().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_namespaces() {
+        let client = canned(etcdserver::RangeResponse {
+            header: Some(etcdserver::ResponseHeader {
+                cluster_id: 1,
+                ..Default::default()
+            }),
+            kvs: vec![mvccpb::KeyValue {
+                key: b"app/foo".to_vec(),
+                value: b"bar".to_vec(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        });
+        let app = client.namespaced("app/");
+        assert_eq!(app.namespace(), b"app/");
+        assert_eq!(app.namespaced("db/").namespace(), b"app/db/");
+        assert_eq!(app.kv().get("foo").await.unwrap(), Some("bar".to_string()));
+
+        assert_eq!(app.namespaced_key(b"foo"), b"app/foo");
+        assert_eq!(app.namespaced_range_end(b""), b"");
+        assert_eq!(app.namespaced_range_end(b"fop"), b"app/fop");
+        // Every key from the start on stops at the end of the namespace
+        assert_eq!(app.namespaced_range_end(b"\0"), b"app0");
+        assert_eq!(client.namespaced_range_end(b"\0"), b"\0");
+        assert_eq!(strip_namespace(b"app/", b"app/foo"), b"foo");
+        assert_eq!(strip_namespace(b"app/", b"other/foo"), b"other/foo");
+    }
+
+    #[tokio::test]
+    async fn test_namespaced_keys() {
+        let client = testing::namespace().await.unwrap();
+        let app = client.namespaced("test_namespaced_keys/");
+        app.kv().put("foo", "bar").await.unwrap();
+        assert_eq!(
+            client.kv().get("test_namespaced_keys/foo").await.unwrap(),
+            Some("bar".to_string())
+        );
+        let values = app.range("", Some("\0")).get().await.unwrap();
+        assert_eq!(values.len(), 1);
+        assert_eq!(values.kvs()[0].key(), b"foo");
+
+        #[cfg(feature = "lock")]
+        {
+            let key = app.lock("lock", 0).await.unwrap();
+            assert!(key.starts_with(b"lock/"));
+            app.unlock(key).await.unwrap();
+        }
+        app.range("", Some("\0")).delete().await.unwrap();
+        let deleted = client.kv().get("test_namespaced_keys/foo").await.unwrap();
+        assert_eq!(deleted, None);
+    }
+
+    #[tokio::test]
+    async fn test_call_contexts() {
+        let start = Instant::now();
+        let context = CallContext::new()
+            .deadline(start + Duration::from_secs(10))
+            .metadata("request-id", "1234")
+            .token("caller-token");
+        assert!(context.remaining().unwrap() <= Duration::from_secs(10));
+        assert_eq!(CallContext::new().remaining(), None);
+        // Derived contexts never extend the deadline
+        let later = context.clone().timeout(Duration::from_secs(60));
+        assert_eq!(later.deadline, context.deadline);
+        let sooner = context.clone().deadline(start + Duration::from_secs(5));
+        assert_eq!(sooner.deadline, Some(start + Duration::from_secs(5)));
+
+        let client = EtcdClient::connect_lazy("http://127.0.0.1:1").unwrap();
+        let range = client.range("test_call_contexts", None).context(&sooner);
+        assert!(range.budget().unwrap() <= Duration::from_secs(5));
+        assert_eq!(
+            range.metadata,
+            [
+                ("request-id".to_string(), "1234".to_string()),
+                ("token".to_string(), "caller-token".to_string()),
+            ]
+        );
+        let range = range.timeout(Duration::from_secs(1));
+        assert!(range.budget().unwrap() <= Duration::from_secs(1));
+
+        let expired = CallContext::new().deadline(start);
+        let range = client.range("test_call_contexts", None).context(&expired);
+        let error = range.get().await.unwrap_err();
+        assert!(error.to_string().contains("timed out"));
+        match error.root() {
+            Error::Timeout { timeout, .. } => assert_eq!(*timeout, Duration::from_secs(0)),
+            error => panic!("unexpected error {:?}", error),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_error_context() {
+        // Nothing listens on port 1, so the request fails
+        let client = EtcdClient::connect_lazy("http://127.0.0.1:1").unwrap();
+        let range = client.range("test_error_context", None);
+        let error = range.get().await.unwrap_err();
+        let context = error.context().unwrap();
+        assert_eq!(context.operation, "get");
+        assert_eq!(context.key.as_deref(), Some("test_error_context"));
+        assert_eq!(context.endpoint.as_deref(), Some("http://127.0.0.1:1"));
+        assert_eq!(context.attempt, Some(1));
+        let message = "get \"test_error_context\" on http://127.0.0.1:1 (attempt 1): ";
+        assert!(error.to_string().starts_with(message));
+
+        let client = EtcdClient::builder()
+            .endpoint("http://127.0.0.1:1")
+            .retry(RetryPolicy::disabled())
+            .redact_keys()
+            .connect_lazy()
+            .unwrap();
+        let error = client.range("secret", None).delete().await.unwrap_err();
+        let context = error.context().unwrap();
+        assert_eq!(context.key.as_deref(), Some("<6 bytes redacted>"));
+        assert_eq!(context.attempt, Some(1));
+        assert!(!error.to_string().contains("secret"));
+    }
+
+    #[tokio::test]
+    async fn test_interceptors() {
+        let intercepted = Arc::new(AtomicBool::new(false));
+        let seen = intercepted.clone();
+        // Nothing listens on port 1, but requests are intercepted before they are sent
+        let client = EtcdClient::builder()
+            .endpoint("http://127.0.0.1:1")
+            .interceptor(|headers| {
+                headers.insert("x-request-source", "tests".parse().unwrap());
+            })
+            .interceptor(move |headers| {
+                seen.store(headers.contains_key("x-request-source"), Ordering::Relaxed);
+            })
+            .retry(RetryPolicy::disabled())
+            .connect()
+            .await
+            .unwrap();
+        let response = client.range("test_interceptors", None).get().await;
+        assert!(response.is_err());
+        assert!(intercepted.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn test_metadata() {
+        /// The x-tenant metadata sent with a get, overridden by `tenant` if given
+        async fn sent_tenant(tenant: Option<&str>) -> http::HeaderValue {
+            let sent = Arc::new(StdMutex::new(None));
+            let seen = sent.clone();
+            // Nothing listens on port 1, but requests are intercepted before they are sent
+            let client = EtcdClient::builder()
+                .endpoint("http://127.0.0.1:1")
+                .metadata("x-tenant", "client")
+                .interceptor(move |headers| {
+                    *seen.lock().unwrap() = headers.get("x-tenant").cloned();
+                })
+                .retry(RetryPolicy::disabled())
+                .connect()
+                .await
+                .unwrap();
+            let mut range = client.range("test_metadata", None);
+            if let Some(tenant) = tenant {
+                range = range.metadata("x-tenant", tenant);
+            }
+            let _ = range.get().await;
+            let sent = sent.lock().unwrap().take();
+            sent.unwrap()
+        }
+
+        assert_eq!(sent_tenant(None).await, "client");
+        assert_eq!(sent_tenant(Some("request")).await, "request");
+
+        let invalid = EtcdClient::builder()
+            .endpoint("http://127.0.0.1:1")
+            .metadata("not a header", "value")
+            .connect_lazy();
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn test_builder_debug() {
+        let builder = EtcdClient::builder()
+            .endpoint("http://127.0.0.1:2379")
+            .credentials("root", "secret")
+            .metadata("authorization", "Bearer token");
+        let printed = format!("{:?}", builder);
+        assert!(printed.contains("\"http://127.0.0.1:2379\""));
+        assert!(printed.contains("\"root\""));
+        assert!(printed.contains("\"authorization\""));
+        assert!(!printed.contains("secret"));
+        assert!(!printed.contains("token"));
+    }
+
+    #[test]
+    fn test_endpoint_lists() {
+        let builder = EtcdClient::builder()
+            .endpoint("https://a:2379, https://b:2379,,https://a:2379")
+            .endpoints(vec!["https://c:2379,https://b:2379", "https://d:2379"]);
+        assert_eq!(
+            builder.endpoints,
+            [
+                "https://a:2379",
+                "https://b:2379",
+                "https://c:2379",
+                "https://d:2379"
+            ]
+        );
+
+        assert!(validate_endpoint("http://127.0.0.1:2379").is_ok());
+        assert!(validate_endpoint("https://etcd-0.example.com").is_ok());
+        assert!(validate_endpoint("unix:///var/run/etcd.sock").is_ok());
+        let err = validate_endpoint("127.0.0.1:2379").unwrap_err().to_string();
+        assert!(err.contains("invalid endpoint \"127.0.0.1:2379\""));
+        assert!(validate_endpoint("ftp://127.0.0.1:2379").is_err());
+        assert!(validate_endpoint("http://").is_err());
+        assert!(validate_endpoint("unix://").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_connecting_to_invalid_endpoints() {
+        assert!(EtcdClient::connect_all(Vec::<String>::new()).await.is_err());
+        let endpoints = vec!["http://127.0.0.1:2379", "not a url"];
+        assert!(EtcdClient::connect_all(endpoints).await.is_err());
+        let endpoints = vec!["unix://"];
+        assert!(EtcdClient::connect_all(endpoints).await.is_err());
+    }
+
+    #[cfg(any(feature = "tls", feature = "tls-native"))]
+    #[test]
+    fn test_tls_config() {
+        assert_eq!(endpoint_host("https://10.0.0.1:2379").unwrap(), "10.0.0.1");
+        assert_eq!(
+            endpoint_host("https://etcd-0.example.com:2379").unwrap(),
+            "etcd-0.example.com"
+        );
+        assert!(endpoint_host("/no/host").is_err());
+
+        let tls = TlsConfig {
+            client_certificate: Some(b"certificate".to_vec()),
+            ..Default::default()
+        };
+        assert!(tls.client_config("https://10.0.0.1:2379").is_err());
+        let tls = TlsConfig {
+            domain_name: Some("etcd.example.com".into()),
+            ..Default::default()
+        };
+        assert!(tls.client_config("https://10.0.0.1:2379").is_ok());
+
+        #[cfg(feature = "tls-native")]
+        {
+            let tls = TlsConfig {
+                backend: TlsBackend::Native,
+                ..Default::default()
+            };
+            assert!(tls.client_config("https://10.0.0.1:2379").is_ok());
+        }
+    }
+
+    #[test]
+    fn test_pinning_cluster_ids() {
+        let header = |cluster_id| etcdserver::ResponseHeader {
+            cluster_id,
+            ..Default::default()
+        };
+
+        let mut pinned = None;
+        assert!(pin_cluster_id(&mut pinned, None).is_ok());
+        assert!(pin_cluster_id(&mut pinned, Some(&header(0))).is_ok());
+        assert_eq!(pinned, None);
+
+        assert!(pin_cluster_id(&mut pinned, Some(&header(0xabc))).is_ok());
+        assert_eq!(pinned, Some(0xabc));
+        assert!(pin_cluster_id(&mut pinned, Some(&header(0xabc))).is_ok());
+        assert!(pin_cluster_id(&mut pinned, None).is_ok());
+
+        let err = pin_cluster_id(&mut pinned, Some(&header(0xdef))).unwrap_err();
+        assert_eq!(
+            err,
+            "response from cluster def, but the client is pinned to cluster abc"
+        );
+        assert_eq!(pinned, Some(0xabc));
+    }
+
+    #[cfg(feature = "maintenance")]
+    #[tokio::test]
+    async fn test_cluster_id() {
+        let client = testing::namespace().await.unwrap();
+        assert_eq!(client.cluster_id(), None);
+        let status = client.maintenance().status().await.unwrap();
+        assert_eq!(client.cluster_id(), status.header.map(|h| h.cluster_id));
+    }
+
+    #[cfg(feature = "lock")]
+    #[tokio::test]
+    async fn test_locking() {
+        let client = testing::namespace().await.unwrap();
+        let key = client.lock("test_locking", 0).await.unwrap();
+        assert!(key.starts_with(b"test_locking/"));
+
+        let held = client
+            .range(std::str::from_utf8(&key).unwrap(), None)
+            .get()
+            .await
+            .unwrap();
+        assert_eq!(held.len(), 1);
+
+        client.unlock(key.clone()).await.unwrap();
+        let held = client
+            .range(std::str::from_utf8(&key).unwrap(), None)
+            .get()
+            .await
+            .unwrap();
+        assert!(held.is_empty());
+    }
+
+    /// Fails to compile unless `T` can be sent to and shared with other threads
+    fn assert_send_sync<T: Send + Sync + 'static>() {}
+
+    /// Fails to compile unless `future` can be spawned
+    fn assert_send<F: std::future::Future + Send>(_future: F) {}
+
+    #[tokio::test]
+    async fn test_send_and_sync() {
+        assert_send_sync::<Error>();
+        assert_send_sync::<EtcdResult<()>>();
+        assert_send_sync::<EtcdClient<tonic::transport::Channel>>();
+
+        // The futures are only built, never polled
+        let client = EtcdClient::connect_lazy("http://127.0.0.1:1").unwrap();
+        assert_send(EtcdClient::connect("http://127.0.0.1:1"));
+        assert_send(client.range("a", None).get());
+        assert_send(client.range("a", None).put("b"));
+        assert_send(client.range("a", None).delete());
+        assert_send(client.get_ranges(vec![("a", None)], 2).next());
+        #[cfg(feature = "cluster")]
+        assert_send(client.cluster().members());
+        #[cfg(feature = "maintenance")]
+        assert_send(client.maintenance().status());
+        #[cfg(feature = "election")]
+        assert_send(client.election().campaign("e", 0, "v"));
+        assert_send(client.watch().watch("a"));
+        #[cfg(feature = "lock")]
+        assert_send(client.lock("l", 0));
+        assert_send(client.compact(1, false));
+        assert_send(concurrency::Session::new(&client, 5));
+        assert_send(discovery::discover::<_, String>(&client, "svc/"));
+        assert_send(resolver::resolve(&client, "svc/"));
+        assert_send(client.close(Duration::from_secs(1)));
+    }
+
+    #[tokio::test]
+    async fn test_owned_handles() {
+        fn assert_static<S: Send + Sync + 'static>(_: &S) {}
+
+        // Handles own a clone of the client, so they outlive it and can be moved into tasks
+        let client = EtcdClient::connect_lazy("http://127.0.0.1:1").unwrap();
+        let range = client.range("a", None).serializable();
+        let kv = client.kv();
+        let watch = client.watch();
+        let lease = client.lease();
+        drop(client);
+        assert_static(&range);
+        assert_static(&kv);
+        assert_static(&watch);
+        assert_static(&lease);
+        assert_send(async move {
+            let _ = range.get().await;
+            let _ = kv.get("b").await;
+        });
     }
 }
@@ -0,0 +1,563 @@
+//! The maintenance service: member status, alarms, versions, hashes and snapshots.
+
+use crate::{
+    client, etcdserver, runtime, AlarmAction, AlarmType, Budget, ChannelConfig, DowngradeAction,
+    Error, EtcdClient, EtcdResult, Retry, Transport,
+};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Semantic version reported by an etcd server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+}
+
+impl Version {
+    pub fn new(major: u64, minor: u64, patch: u64) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Parse a version string such as "3.4.3" or "3.5.0-alpha.0". Any pre-release or build
+    /// suffix is ignored.
+    pub fn parse(s: &str) -> EtcdResult<Self> {
+        let core = s.trim().split(&['-', '+'][..]).next().unwrap_or("");
+        let mut parts = core.split('.').map(|p| p.parse::<u64>());
+
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(Ok(major)), Some(Ok(minor)), patch, None) => {
+                let patch = match patch {
+                    Some(Ok(patch)) => patch,
+                    None => 0,
+                    Some(Err(_)) => return Err(format!("invalid etcd version: {:?}", s).into()),
+                };
+                Ok(Self::new(major, minor, patch))
+            }
+            _ => Err(format!("invalid etcd version: {:?}", s).into()),
+        }
+    }
+}
+
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Server and cluster versions
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VersionInfo {
+    /// Version of the member the client is connected to
+    pub server: Version,
+    /// Version the cluster as a whole runs at: the lowest major.minor of the members whose
+    /// version is known
+    pub cluster: Version,
+    /// IDs of the members whose version is unknown, because they could not be reached or
+    /// reported a version which could not be parsed. The cluster may run at a lower version
+    /// than `cluster` while there are any.
+    pub unknown: Vec<u64>,
+}
+
+impl VersionInfo {
+    /// Return [`Error::Unsupported`] naming `feature` if the cluster version is older than
+    /// `major.minor`.
+    ///
+    /// Use this to fail early with a clear message rather than an opaque `Unimplemented` status
+    /// from the server.
+    ///
+    /// Fails as well, with [`Error::UnknownVersion`], if the version of any member is unknown, as
+    /// the cluster may then run at a lower version than the members which answered.
+    pub fn require(&self, feature: &str, major: u64, minor: u64) -> EtcdResult<()> {
+        if self.cluster < Version::new(major, minor, 0) {
+            return Err(Error::Unsupported {
+                feature: feature.to_string(),
+                required: (major, minor),
+                cluster: (self.cluster.major, self.cluster.minor),
+            });
+        }
+        if !self.unknown.is_empty() {
+            return Err(Error::UnknownVersion {
+                feature: feature.to_string(),
+                members: self.unknown.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Whether the cluster version is known to be new enough for `capability`
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.cluster >= capability.since() && self.unknown.is_empty()
+    }
+
+    /// Return [`Error::Unsupported`] if the cluster version is too old for `capability`
+    pub fn check(&self, capability: Capability) -> EtcdResult<()> {
+        let since = capability.since();
+        self.require(capability.name(), since.major, since.minor)
+    }
+}
+
+/// Part of the API added after etcd 3.3, which the client checks the cluster version for
+/// before using, failing with [`Error::Unsupported`] on older clusters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Capability {
+    /// Adding members as raft learners, since 3.4
+    Learners,
+    /// Promoting learners to voting members, since 3.4
+    MemberPromote,
+    /// Listing members through raft, see
+    /// [`MemberListOptions::linearizable`](crate::cluster::MemberListOptions::linearizable),
+    /// since 3.5
+    LinearizableMemberList,
+    /// Downgrading the cluster version, see [`Maintenance::downgrade`], since 3.5
+    Downgrade,
+}
+
+impl Capability {
+    /// Oldest etcd version with the capability
+    pub fn since(self) -> Version {
+        match self {
+            Capability::Learners | Capability::MemberPromote => Version::new(3, 4, 0),
+            Capability::LinearizableMemberList | Capability::Downgrade => Version::new(3, 5, 0),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Capability::Learners => "adding learners",
+            Capability::MemberPromote => "member promote",
+            Capability::LinearizableMemberList => "linearizable member list",
+            Capability::Downgrade => "downgrade",
+        }
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Maintenance operations
+pub struct Maintenance<T> {
+    pub(crate) client: EtcdClient<T>,
+}
+
+impl<T> fmt::Debug for Maintenance<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Maintenance")
+            .field("client", &self.client)
+            .finish()
+    }
+}
+
+impl<T: Transport> Maintenance<T> {
+    /// Status of the member the client is connected to
+    pub async fn status(&self) -> EtcdResult<etcdserver::StatusResponse> {
+        let request = etcdserver::StatusRequest {};
+        let context = self.client.error_context("status", None);
+        let rpc = || {
+            let (mut status_client, request) = (self.client.status_client(), request.clone());
+            async move { status_client.status(request).await }
+        };
+        self.client
+            .call(
+                "maintenance.status",
+                context,
+                Budget::Read,
+                Retry::Idempotent,
+                rpc,
+                Ok,
+            )
+            .await
+    }
+
+    /// Server version of the connected member, and the cluster version computed from every
+    /// member. Members which cannot be reached or report a version which cannot be parsed are
+    /// listed in [`VersionInfo::unknown`] rather than failing the whole call.
+    pub async fn version(&self) -> EtcdResult<VersionInfo> {
+        let server = Version::parse(&self.status().await?.version)?;
+        let members = self.client.member_list(false).await?;
+
+        let mut cluster = Version::new(server.major, server.minor, 0);
+        let mut unknown = Vec::new();
+        for member in members {
+            let status = async {
+                let mut status_client =
+                    connect_member_maintenance(&self.client.config, &member).await?;
+                let response = status_client.status(etcdserver::StatusRequest {}).await?;
+                Version::parse(&response.into_inner().version)
+            };
+            match status.await {
+                Ok(version) => cluster = cluster.min(Version::new(version.major, version.minor, 0)),
+                Err(_) => unknown.push(member.id),
+            }
+        }
+
+        Ok(VersionInfo {
+            server,
+            cluster,
+            unknown,
+        })
+    }
+
+    /// Hash of the MVCC key space up to `revision` on the connected member. A revision of 0
+    /// hashes up to the current revision.
+    pub async fn hash_kv(&self, revision: i64) -> EtcdResult<etcdserver::HashKvResponse> {
+        let request = etcdserver::HashKvRequest { revision };
+        let context = self.client.error_context("hash kv", None);
+        let rpc = || {
+            let (mut status_client, request) = (self.client.status_client(), request.clone());
+            async move { status_client.hash_kv(request).await }
+        };
+        self.client
+            .call(
+                "maintenance.hash_kv",
+                context,
+                Budget::Read,
+                Retry::Idempotent,
+                rpc,
+                Ok,
+            )
+            .await
+    }
+
+    /// Every alarm raised by a member of the cluster, such as a member running out of space
+    pub async fn alarms(&self) -> EtcdResult<Vec<etcdserver::AlarmMember>> {
+        let request = etcdserver::AlarmRequest {
+            action: AlarmAction::Get.into(),
+            member_id: 0,
+            alarm: AlarmType::None.into(),
+        };
+        let context = self.client.error_context("alarms", None);
+        let rpc = || {
+            let (mut status_client, request) = (self.client.status_client(), request.clone());
+            async move { status_client.alarm(request).await }
+        };
+        self.client
+            .call(
+                "maintenance.alarms",
+                context,
+                Budget::Read,
+                Retry::Idempotent,
+                rpc,
+                |response| Ok(response.alarms),
+            )
+            .await
+    }
+
+    /// Validate, start or cancel downgrading the cluster to `version`, returning the current
+    /// cluster version. The version is given as `major.minor`, and cancelling ignores it.
+    ///
+    /// Requires etcd 3.5 or newer, see [`Capability::Downgrade`].
+    pub async fn downgrade(&self, action: DowngradeAction, version: &str) -> EtcdResult<Version> {
+        self.version().await?.check(Capability::Downgrade)?;
+
+        let request = etcdserver::DowngradeRequest {
+            action: action.into(),
+            version: version.to_string(),
+        };
+        let context = self.client.error_context("downgrade", None);
+        let rpc = || {
+            let (mut status_client, request) = (self.client.status_client(), request.clone());
+            async move { status_client.downgrade(request).await }
+        };
+        self.client
+            .call(
+                "maintenance.downgrade",
+                context,
+                Budget::Write,
+                Retry::Mutation,
+                rpc,
+                |response| Version::parse(&response.version),
+            )
+            .await
+    }
+
+    /// Stream a snapshot of the backend database from the connected member
+    pub async fn snapshot(&self) -> EtcdResult<tonic::Streaming<etcdserver::SnapshotResponse>> {
+        let request = etcdserver::SnapshotRequest {};
+        let context = self.client.error_context("snapshot", None);
+        let operation = self.client.operation("maintenance.snapshot", &context);
+        let call = async {
+            let _in_flight = self.client.config.throttle(Budget::Read).await?;
+            let response = self.client.status_client().snapshot(request).await?;
+            Ok(response.into_inner())
+        };
+        let result: EtcdResult<_> = operation.run(call).await;
+        operation.finish(&context, &result);
+        result.map_err(|e| context.attach(e))
+    }
+
+    /// Save a snapshot of the backend database to `path`.
+    ///
+    /// The snapshot is written to a temporary file next to `path`, verified with
+    /// [`verify_snapshot`] and only then moved into place, so `path` always holds a complete
+    /// snapshot which can be restored with `etcdutl snapshot restore`. The temporary file is
+    /// removed if saving fails.
+    pub async fn save_snapshot<P>(&self, path: P) -> EtcdResult<SnapshotInfo>
+    where
+        P: AsRef<Path>,
+    {
+        use std::io::Write;
+
+        let path = path.as_ref().to_path_buf();
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".part");
+        let partial = PathBuf::from(partial);
+
+        let mut stream = self.snapshot().await?;
+        // Writing, syncing and hashing the file all block, so they are kept off the executor
+        let written = async {
+            let created = partial.clone();
+            let mut file = runtime::blocking(move || std::fs::File::create(created)).await?;
+            let mut revision = None;
+            while let Some(msg) = stream.message().await? {
+                if revision.is_none() {
+                    revision = msg.header.as_ref().map(|h| h.revision).filter(|r| *r > 0);
+                }
+                file = runtime::blocking(move || file.write_all(&msg.blob).map(|_| file)).await?;
+            }
+            let verified = partial.clone();
+            let info = runtime::blocking(move || {
+                file.sync_all()?;
+                drop(file);
+                verify_snapshot(verified)
+            })
+            .await?;
+            Ok(SnapshotInfo { revision, ..info })
+        };
+        let written: EtcdResult<_> = written.await;
+
+        runtime::blocking(move || {
+            let saved = written.and_then(|info| {
+                std::fs::rename(&partial, &path)?;
+                Ok(info)
+            });
+            if saved.is_err() {
+                let _ = std::fs::remove_file(&partial);
+            }
+            saved
+        })
+        .await
+    }
+}
+
+/// Dial the maintenance service of a specific member, using its first client URL and the
+/// client's connection settings
+pub(crate) async fn connect_member_maintenance(
+    config: &ChannelConfig,
+    member: &etcdserver::Member,
+) -> EtcdResult<client::MaintenanceClient<tonic::transport::channel::Channel>> {
+    let url = match member.client_ur_ls.first() {
+        Some(url) => url.clone(),
+        // Members which have not started yet do not advertise client URLs
+        None => return Err(format!("member {:x} has no client URLs", member.id).into()),
+    };
+    let channel = config.member_channel(&url).await?;
+    Ok(client::MaintenanceClient::new(channel))
+}
+
+/// Length of the sha256 checksum etcd appends to the database in a snapshot
+const SNAPSHOT_HASH_LEN: u64 = 32;
+
+/// Metadata about a saved snapshot
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SnapshotInfo {
+    /// Size of the snapshot file, including the integrity checksum
+    pub total_size: u64,
+    /// Size of the backend database contained in the snapshot
+    pub db_size: u64,
+    /// sha256 of the backend database, as stored at the end of the snapshot
+    pub sha256: [u8; 32],
+    /// Store revision the snapshot was taken at, if the server reported it
+    pub revision: Option<i64>,
+}
+
+/// Check that the sha256 checksum etcd appends to a snapshot matches its contents, and return
+/// basic metadata about it.
+///
+/// The revision cannot be determined from the file alone, so it is always `None` here. The
+/// whole file is read while the calling thread blocks, which async code should keep off its
+/// executor.
+pub fn verify_snapshot<P>(path: P) -> EtcdResult<SnapshotInfo>
+where
+    P: AsRef<Path>,
+{
+    use sha2::Digest;
+    use std::io::Read;
+
+    let path = path.as_ref();
+    let mut file = std::fs::File::open(path)?;
+    let total_size = file.metadata()?.len();
+    if total_size <= SNAPSHOT_HASH_LEN {
+        return Err(format!(
+            "snapshot {} is too small ({} bytes) to contain a database and checksum",
+            path.display(),
+            total_size
+        )
+        .into());
+    }
+    let db_size = total_size - SNAPSHOT_HASH_LEN;
+
+    let mut hasher = sha2::Sha256::new();
+    let mut db = (&mut file).take(db_size);
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = db.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.input(&buf[..n]);
+    }
+
+    let mut sha256 = [0; 32];
+    file.read_exact(&mut sha256)?;
+    if hasher.result().as_slice() != sha256 {
+        return Err(format!(
+            "snapshot {} failed its sha256 integrity check",
+            path.display()
+        )
+        .into());
+    }
+
+    Ok(SnapshotInfo {
+        total_size,
+        db_size,
+        sha256,
+        revision: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parsing_versions() {
+        assert_eq!(Version::parse("3.4.3").unwrap(), Version::new(3, 4, 3));
+        assert_eq!(
+            Version::parse("3.5.0-alpha.0").unwrap(),
+            Version::new(3, 5, 0)
+        );
+        assert_eq!(Version::parse("3.4").unwrap(), Version::new(3, 4, 0));
+        assert!(Version::parse("not-a-version").is_err());
+        assert!(Version::parse("3.4.3.1").is_err());
+        assert!(Version::new(3, 3, 20) < Version::new(3, 4, 0));
+    }
+
+    #[test]
+    fn test_requiring_versions() {
+        let info = VersionInfo {
+            server: Version::new(3, 4, 3),
+            cluster: Version::new(3, 3, 0),
+            unknown: Vec::new(),
+        };
+        assert!(info.require("member promote", 3, 3).is_ok());
+        let err = info.require("member promote", 3, 4).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "member promote requires etcd 3.4 or newer, but the cluster version is 3.3"
+        );
+    }
+
+    #[test]
+    fn test_checking_capabilities() {
+        let info = VersionInfo {
+            server: Version::new(3, 4, 3),
+            cluster: Version::new(3, 4, 0),
+            unknown: Vec::new(),
+        };
+        assert!(info.supports(Capability::MemberPromote));
+        assert!(info.check(Capability::Learners).is_ok());
+        assert!(!info.supports(Capability::Downgrade));
+        let err = info.check(Capability::LinearizableMemberList).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::Unsupported {
+                required: (3, 5),
+                cluster: (3, 4),
+                ..
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "linearizable member list requires etcd 3.5 or newer, but the cluster version is 3.4"
+        );
+
+        // A member whose version is unknown may hold the cluster back
+        let info = VersionInfo {
+            unknown: vec![0xabc],
+            ..info
+        };
+        assert!(!info.supports(Capability::Learners));
+        let err = info.check(Capability::Learners).unwrap_err();
+        assert!(matches!(&err, Error::UnknownVersion { members, .. } if *members == [0xabc]));
+        assert_eq!(
+            err.to_string(),
+            "cannot tell whether the cluster supports adding learners: the versions of members \
+             abc are unknown"
+        );
+    }
+
+    #[test]
+    fn test_verifying_snapshots() {
+        use sha2::Digest;
+
+        let dir = std::env::temp_dir();
+        let good = dir.join(format!("etcd3-snapshot-good-{}.db", std::process::id()));
+        let bad = dir.join(format!("etcd3-snapshot-bad-{}.db", std::process::id()));
+
+        let db = b"not really a bolt database".to_vec();
+        let mut contents = db.clone();
+        contents.extend_from_slice(sha2::Sha256::digest(&db).as_slice());
+        std::fs::write(&good, &contents).unwrap();
+
+        let info = verify_snapshot(&good).unwrap();
+        assert_eq!(info.total_size, contents.len() as u64);
+        assert_eq!(info.db_size, db.len() as u64);
+        assert_eq!(info.revision, None);
+
+        contents[0] ^= 0xff;
+        std::fs::write(&bad, &contents).unwrap();
+        assert!(verify_snapshot(&bad).is_err());
+
+        std::fs::remove_file(good).unwrap();
+        std::fs::remove_file(bad).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_saving_snapshots() {
+        let path = std::env::temp_dir().join(format!("etcd3-snapshot-{}.db", std::process::id()));
+        let client = crate::testing::namespace().await.unwrap();
+
+        let info = client.maintenance().save_snapshot(&path).await.unwrap();
+        assert_eq!(info.total_size, std::fs::metadata(&path).unwrap().len());
+        assert_eq!(verify_snapshot(&path).unwrap().sha256, info.sha256);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_alarms() {
+        let client = crate::testing::namespace().await.unwrap();
+        assert!(client.maintenance().alarms().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_version() {
+        let client = crate::testing::namespace().await.unwrap();
+        let info = client.version().await.unwrap();
+        assert_eq!(info.server.major, 3);
+        assert!(info.cluster <= info.server);
+    }
+}
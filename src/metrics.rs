@@ -0,0 +1,271 @@
+//! Metrics of the client's requests, watches and leases, with the `metrics` feature.
+//!
+//! Metrics are recorded through the [`metrics`](https://docs.rs/metrics) facade, so they go
+//! to whichever recorder the application installs, such as `metrics-exporter-prometheus`.
+//! Nothing is recorded until one is installed. Call [`describe`] once the recorder is
+//! installed to give it each metric's description and unit.
+//!
+//! Operations are labelled with their name, such as `kv.put` or `lease.grant`, the same name
+//! as their span with the `tracing` feature, and requests with the outcome of the operation:
+//! `ok`, or the kind of error it failed with, such as `unavailable` or `no_leader`.
+
+use crate::Error;
+use metrics::{counter, gauge, histogram, Unit};
+use std::time::Duration;
+
+/// Counter of finished operations, labelled with `operation` and `status`
+pub const REQUESTS: &str = "etcd_requests_total";
+/// Histogram of the time operations took in seconds, labelled with `operation`
+pub const REQUEST_DURATION: &str = "etcd_request_duration_seconds";
+/// Gauge of operations in progress, labelled with `operation`
+pub const REQUESTS_IN_FLIGHT: &str = "etcd_requests_in_flight";
+/// Counter of watches which broke or failed to be created, and were created again
+pub const WATCH_RECONNECTS: &str = "etcd_watch_reconnects_total";
+/// Gauge of how many revisions the consumer of a watch is behind the store, labelled with the
+/// watched `key`, see
+/// [`EtcdClientBuilder::watch_lag_interval`](crate::EtcdClientBuilder::watch_lag_interval)
+pub const WATCH_LAG: &str = "etcd_watch_lag_revisions";
+/// Counter of lease keep-alive streams which broke, or found the lease expired
+pub const LEASE_KEEP_ALIVE_FAILURES: &str = "etcd_lease_keep_alive_failures_total";
+/// Histogram of the time sessions' keep-alives took to be answered in seconds
+pub const LEASE_KEEP_ALIVE_RTT: &str = "etcd_lease_keep_alive_rtt_seconds";
+/// Counter of sessions' keep-alives still unanswered when the next was due, or when their
+/// stream broke
+pub const LEASE_KEEP_ALIVES_MISSED: &str = "etcd_lease_keep_alives_missed_total";
+/// Counter of sessions whose lease expired, or could not be kept alive before it would have
+pub const LEASES_LOST: &str = "etcd_leases_lost_total";
+
+/// Give the installed recorder the description and unit of every metric
+pub fn describe() {
+    metrics::describe_counter!(REQUESTS, "Operations finished, by operation and status");
+    metrics::describe_histogram!(
+        REQUEST_DURATION,
+        Unit::Seconds,
+        "Time operations took, including retries"
+    );
+    metrics::describe_gauge!(REQUESTS_IN_FLIGHT, "Operations in progress");
+    metrics::describe_counter!(WATCH_RECONNECTS, "Watches created again after breaking");
+    metrics::describe_gauge!(
+        WATCH_LAG,
+        "Revisions the consumer of a watch is behind the store"
+    );
+    metrics::describe_counter!(
+        LEASE_KEEP_ALIVE_FAILURES,
+        "Lease keep-alive streams which broke or found their lease expired"
+    );
+    metrics::describe_histogram!(
+        LEASE_KEEP_ALIVE_RTT,
+        Unit::Seconds,
+        "Time sessions' keep-alives took to be answered"
+    );
+    metrics::describe_counter!(
+        LEASE_KEEP_ALIVES_MISSED,
+        "Sessions' keep-alives which went unanswered"
+    );
+    metrics::describe_counter!(LEASES_LOST, "Sessions whose lease was lost");
+}
+
+/// Record that the operation `name` started
+pub(crate) fn started(name: &'static str) {
+    gauge!(REQUESTS_IN_FLIGHT, "operation" => name).increment(1.0);
+}
+
+/// Record that the operation `name` finished, or was given up on
+pub(crate) fn stopped(name: &'static str) {
+    gauge!(REQUESTS_IN_FLIGHT, "operation" => name).decrement(1.0);
+}
+
+/// Record that the operation `name` finished after `latency`, failing with `error` if given
+pub(crate) fn finished(name: &'static str, latency: Duration, error: Option<&Error>) {
+    let status = error.map_or("ok", status);
+    counter!(REQUESTS, "operation" => name, "status" => status).increment(1);
+    histogram!(REQUEST_DURATION, "operation" => name).record(latency.as_secs_f64());
+}
+
+/// Record that `count` watches were created again
+pub(crate) fn watch_reconnected(count: u32) {
+    counter!(WATCH_RECONNECTS).increment(u64::from(count));
+}
+
+/// Record that the consumer of the watch on `key` is `lag` revisions behind the store
+pub(crate) fn watch_lag(key: &str, lag: i64) {
+    gauge!(WATCH_LAG, "key" => key.to_string()).set(lag as f64);
+}
+
+/// Record that a lease keep-alive stream broke or found its lease expired
+pub(crate) fn keep_alive_failed() {
+    counter!(LEASE_KEEP_ALIVE_FAILURES).increment(1);
+}
+
+/// Record that a session's keep-alive was answered after `rtt`
+pub(crate) fn keep_alive_answered(rtt: Duration) {
+    histogram!(LEASE_KEEP_ALIVE_RTT).record(rtt.as_secs_f64());
+}
+
+/// Record that a session's keep-alive went unanswered
+pub(crate) fn keep_alive_missed() {
+    counter!(LEASE_KEEP_ALIVES_MISSED).increment(1);
+}
+
+/// Record that a session's lease was lost
+pub(crate) fn lease_lost() {
+    counter!(LEASES_LOST).increment(1);
+}
+
+/// Status label of an operation which failed with `error`
+fn status(error: &Error) -> &'static str {
+    match error.root() {
+        Error::Transport(_) => "transport",
+        Error::Status(status) => match status.code() {
+            tonic::Code::Cancelled => "cancelled",
+            tonic::Code::InvalidArgument => "invalid_argument",
+            tonic::Code::NotFound => "not_found",
+            tonic::Code::AlreadyExists => "already_exists",
+            tonic::Code::ResourceExhausted => "resource_exhausted",
+            tonic::Code::FailedPrecondition => "failed_precondition",
+            tonic::Code::Unavailable => "unavailable",
+            tonic::Code::Internal => "internal",
+            _ => "status",
+        },
+        Error::InvalidResponse(_) => "invalid_response",
+        Error::Compacted(_) => "compacted",
+        Error::FutureRevision => "future_revision",
+        Error::LeaseNotFound(_) => "lease_not_found",
+        Error::NoSpace => "no_space",
+        Error::TooManyRequests => "too_many_requests",
+        Error::Shed => "shed",
+        Error::SchemaViolation(_) => "schema_violation",
+        Error::RequestTooLarge => "request_too_large",
+        Error::NoLeader => "no_leader",
+        Error::PermissionDenied => "permission_denied",
+        Error::Auth(_) => "auth",
+        Error::Timeout { .. } => "timeout",
+        Error::ServerTimeout(_) => "server_timeout",
+        _ => "other",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use metrics::{
+        Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn, Key, KeyName, Metadata,
+        Recorder, SharedString,
+    };
+    use std::sync::{Arc, Mutex};
+
+    /// Records every update to a metric as a line, such as `etcd_requests_total{..} += 1`
+    #[derive(Clone, Default)]
+    struct Updates(Arc<Mutex<Vec<String>>>);
+
+    struct Handle {
+        key: String,
+        updates: Updates,
+    }
+
+    impl Handle {
+        fn push(&self, update: String) {
+            let update = format!("{} {}", self.key, update);
+            self.updates.0.lock().unwrap().push(update);
+        }
+    }
+
+    impl CounterFn for Handle {
+        fn increment(&self, value: u64) {
+            self.push(format!("+= {}", value));
+        }
+
+        fn absolute(&self, value: u64) {
+            self.push(format!("= {}", value));
+        }
+    }
+
+    impl GaugeFn for Handle {
+        fn increment(&self, value: f64) {
+            self.push(format!("+= {}", value));
+        }
+
+        fn decrement(&self, value: f64) {
+            self.push(format!("-= {}", value));
+        }
+
+        fn set(&self, value: f64) {
+            self.push(format!("= {}", value));
+        }
+    }
+
+    impl HistogramFn for Handle {
+        fn record(&self, _: f64) {
+            self.push("recorded".to_string());
+        }
+    }
+
+    impl Updates {
+        fn handle(&self, key: &Key) -> Arc<Handle> {
+            let labels: Vec<_> = key
+                .labels()
+                .map(|label| format!("{}={}", label.key(), label.value()))
+                .collect();
+            Arc::new(Handle {
+                key: format!("{}{{{}}}", key.name(), labels.join(",")),
+                updates: self.clone(),
+            })
+        }
+    }
+
+    impl Recorder for Updates {
+        fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+        fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+        fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+        fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+            Counter::from_arc(self.handle(key))
+        }
+
+        fn register_gauge(&self, key: &Key, _: &Metadata<'_>) -> Gauge {
+            Gauge::from_arc(self.handle(key))
+        }
+
+        fn register_histogram(&self, key: &Key, _: &Metadata<'_>) -> Histogram {
+            Histogram::from_arc(self.handle(key))
+        }
+    }
+
+    #[test]
+    fn test_operation_metrics() {
+        let updates = Updates::default();
+        metrics::with_local_recorder(&updates, || {
+            let context = crate::ErrorContext::default();
+            let operation = crate::trace::Operation::start("kv.put", &context, &Default::default());
+            let result: crate::EtcdResult<()> = Err(Error::NoLeader);
+            operation.finish(&context, &result);
+            let operation = crate::trace::Operation::start("kv.get", &context, &Default::default());
+            // Operations given up on are no longer in flight
+            drop(operation);
+            watch_reconnected(2);
+        });
+        assert_eq!(
+            *updates.0.lock().unwrap(),
+            vec![
+                "etcd_requests_in_flight{operation=kv.put} += 1",
+                "etcd_requests_total{operation=kv.put,status=no_leader} += 1",
+                "etcd_request_duration_seconds{operation=kv.put} recorded",
+                "etcd_requests_in_flight{operation=kv.put} -= 1",
+                "etcd_requests_in_flight{operation=kv.get} += 1",
+                "etcd_requests_in_flight{operation=kv.get} -= 1",
+                "etcd_watch_reconnects_total{} += 2",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_status() {
+        let unavailable = tonic::Status::new(tonic::Code::Unavailable, "down");
+        assert_eq!(status(&Error::Status(unavailable)), "unavailable");
+        let context = crate::ErrorContext::default();
+        assert_eq!(status(&context.attach(Error::NoSpace)), "no_space");
+        assert_eq!(status(&Error::KeyNotFound("foo".into())), "other");
+    }
+}
@@ -0,0 +1,582 @@
+//! A local copy of the keys under a prefix, kept up to date by following changes to them.
+//!
+//! The copy can be bounded by a number of keys or of bytes, see [`MirrorOptions`]. Once it is
+//! full the least recently used keys are evicted, and reading a key which is not held fetches
+//! it from the server again, so mirroring a large prefix uses no more memory than the budget
+//! allows. An unbounded mirror holds every key, and answers every read locally.
+
+use crate::concurrency::{prefix_end, read_revision};
+use crate::redact::Key;
+use crate::{client, etcdserver, is_retryable, mvccpb, runtime, Backoff, EtcdClient, EtcdResult};
+use bytes::Bytes;
+use futures::channel::oneshot;
+use futures::future::{self, Either};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Arc, Mutex as StdMutex, MutexGuard};
+use tonic::transport::channel::Channel;
+
+/// Most keys read from the server at once while loading the mirror
+const LOAD_PAGE: usize = 1000;
+
+/// Limits on how much of a prefix a [`Mirror`] holds. Both are unlimited by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MirrorOptions {
+    /// Most keys to hold
+    pub max_entries: Option<usize>,
+    /// Most bytes of keys and values to hold
+    pub max_bytes: Option<usize>,
+}
+
+/// The keys under a prefix, kept up to date by following changes to them, see [`mirror`]
+pub struct Mirror {
+    kv_client: client::KvClient<Channel>,
+    prefix: Vec<u8>,
+    /// Whether keys which are not held are read serializably
+    serializable: bool,
+    state: Arc<StdMutex<State>>,
+    /// Stops following changes once the mirror is dropped
+    _stop: oneshot::Sender<()>,
+}
+
+impl fmt::Debug for Mirror {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state();
+        f.debug_struct("Mirror")
+            .field("prefix", &Key(&self.prefix))
+            .field("entries", &state.entries.len())
+            .field("bytes", &state.bytes)
+            .field("complete", &state.complete)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Mirror the keys under `prefix`, holding no more of them than `options` allow, and start
+/// following changes to them. Must be called from within a tokio runtime.
+///
+/// If the watch following the changes breaks it is re-established with backoff, carrying on
+/// from the last change seen, and if the changes were compacted away the mirror is loaded
+/// again. Should the changes stop being followed for any other reason, the mirror lets go of
+/// its keys and reads them from the server instead.
+pub async fn mirror<P>(
+    client: &EtcdClient<Channel>,
+    prefix: P,
+    options: MirrorOptions,
+) -> EtcdResult<Mirror>
+where
+    P: Into<Vec<u8>>,
+{
+    let prefix = prefix.into();
+    let mut kv_client = client.kv_client();
+    let serializable = client.serializable_reads();
+    let mut state = State::new(options);
+    load(&mut kv_client, &prefix, serializable, &mut state).await?;
+
+    // Follow on from the revision the keys were read at, so no change is missed
+    let mut watch_client = client.watch_client();
+    let responses = watch(&mut watch_client, &prefix, state.revision + 1).await?;
+    let state = Arc::new(StdMutex::new(state));
+    let (stop, stopped) = oneshot::channel();
+    runtime::spawn(follow(
+        kv_client.clone(),
+        watch_client,
+        prefix.clone(),
+        serializable,
+        Arc::clone(&state),
+        responses,
+        stopped,
+    ));
+
+    Ok(Mirror {
+        kv_client,
+        prefix,
+        serializable,
+        state,
+        _stop: stop,
+    })
+}
+
+impl Mirror {
+    /// Value of `key`, from the mirror if it holds the key, otherwise from the server. Keys
+    /// outside of the mirror's prefix are always read from the server.
+    pub async fn get<K>(&self, key: K) -> EtcdResult<Option<Bytes>>
+    where
+        K: AsRef<[u8]>,
+    {
+        let key = key.as_ref();
+        let mirrored = key.starts_with(&self.prefix);
+        if mirrored {
+            let mut state = self.state();
+            if state.stopped.is_none() {
+                if let Some(value) = state.get(key) {
+                    return Ok(Some(value));
+                }
+                if state.complete {
+                    return Ok(None);
+                }
+            }
+        }
+
+        let request = etcdserver::RangeRequest {
+            key: key.to_vec(),
+            serializable: self.serializable,
+            ..Default::default()
+        };
+        let response = self.kv_client.clone().range(request).await?.into_inner();
+        let revision = read_revision(response.header, "range")?;
+        let kv = response.kvs.into_iter().find(|kv| kv.key == key);
+        let value = kv.map(|kv| Bytes::from(kv.value));
+        if let (true, Some(value)) = (mirrored, &value) {
+            let mut state = self.state();
+            // Changes up to the mirror's revision may have been applied already, and the
+            // value read could predate them
+            if state.stopped.is_none() && revision >= state.revision {
+                state.insert(key.to_vec(), value.clone(), revision);
+            }
+        }
+        Ok(value)
+    }
+
+    /// Number of keys held
+    pub fn len(&self) -> usize {
+        self.state().entries.len()
+    }
+
+    /// Whether no keys are held
+    pub fn is_empty(&self) -> bool {
+        self.state().entries.is_empty()
+    }
+
+    /// Bytes of the keys and values held
+    pub fn bytes(&self) -> usize {
+        self.state().bytes
+    }
+
+    /// Whether every key under the prefix is held, which holds until the first eviction
+    pub fn is_complete(&self) -> bool {
+        let state = self.state();
+        state.complete && state.stopped.is_none()
+    }
+
+    /// Number of keys evicted to stay within the mirror's limits
+    pub fn evictions(&self) -> u64 {
+        self.state().evictions
+    }
+
+    /// Revision of the last change applied to the mirror
+    pub fn revision(&self) -> i64 {
+        self.state().revision
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A key held by a mirror
+#[derive(Debug)]
+struct Entry {
+    value: Bytes,
+    /// Revision the value is known to be current at
+    revision: i64,
+    /// When the key was last used, as a position in the order of use
+    used: u64,
+}
+
+#[derive(Debug)]
+struct State {
+    options: MirrorOptions,
+    entries: HashMap<Vec<u8>, Entry>,
+    /// Keys held, from the least recently used
+    by_use: BTreeMap<u64, Vec<u8>>,
+    /// Position of the next use
+    uses: u64,
+    bytes: usize,
+    /// Revision of the last change applied
+    revision: i64,
+    /// Whether every key under the prefix is held, so one which is not does not exist
+    complete: bool,
+    evictions: u64,
+    /// Why changes stopped being followed
+    stopped: Option<String>,
+}
+
+impl State {
+    fn new(options: MirrorOptions) -> Self {
+        State {
+            options,
+            entries: HashMap::new(),
+            by_use: BTreeMap::new(),
+            uses: 0,
+            bytes: 0,
+            revision: 0,
+            complete: true,
+            evictions: 0,
+            stopped: None,
+        }
+    }
+
+    /// Value of `key` if it is held, marking it as the most recently used
+    fn get(&mut self, key: &[u8]) -> Option<Bytes> {
+        let entry = self.entries.get_mut(key)?;
+        self.by_use.remove(&entry.used);
+        entry.used = self.uses;
+        self.uses += 1;
+        self.by_use.insert(entry.used, key.to_vec());
+        Some(entry.value.clone())
+    }
+
+    /// Hold `value` at `key`, evicting the least recently used keys if this goes over the
+    /// limits
+    fn insert(&mut self, key: Vec<u8>, value: Bytes, revision: i64) {
+        self.remove(&key);
+        self.bytes += key.len() + value.len();
+        let used = self.uses;
+        self.uses += 1;
+        self.by_use.insert(used, key.clone());
+        self.entries.insert(
+            key,
+            Entry {
+                value,
+                revision,
+                used,
+            },
+        );
+        while self.over_limits() {
+            self.evict();
+        }
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.by_use.remove(&entry.used);
+            self.bytes -= key.len() + entry.value.len();
+        }
+    }
+
+    fn over_limits(&self) -> bool {
+        let entries = self.options.max_entries.map(|max| self.entries.len() > max);
+        let bytes = self.options.max_bytes.map(|max| self.bytes > max);
+        entries.unwrap_or(false) || bytes.unwrap_or(false)
+    }
+
+    /// Evict the least recently used key
+    fn evict(&mut self) {
+        let used = match self.by_use.keys().next() {
+            Some(&used) => used,
+            None => return,
+        };
+        if let Some(key) = self.by_use.remove(&used) {
+            self.remove(&key);
+            self.complete = false;
+            self.evictions += 1;
+        }
+    }
+
+    /// Let go of every key, to be loaded again
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.by_use.clear();
+        self.bytes = 0;
+        self.complete = true;
+    }
+
+    /// Apply `event`. Puts of keys which are not held are only applied while every key is,
+    /// the others are fetched again when they are read.
+    fn apply(&mut self, event: mvccpb::Event) {
+        let kv = match event.kv {
+            Some(kv) => kv,
+            None => return,
+        };
+        self.revision = self.revision.max(kv.mod_revision);
+        let held = self.entries.get(&kv.key).map(|entry| entry.revision);
+        if held.is_some_and(|revision| revision >= kv.mod_revision) {
+            return;
+        }
+        if event.r#type == mvccpb::event::EventType::Delete as i32 {
+            self.remove(&kv.key);
+        } else if held.is_some() || self.complete {
+            self.insert(kv.key, Bytes::from(kv.value), kv.mod_revision);
+        }
+    }
+
+    /// Stop following changes because of `reason`, letting go of every key
+    fn stop(&mut self, reason: String) {
+        self.clear();
+        self.complete = false;
+        self.stopped = Some(reason);
+    }
+}
+
+/// Load the keys under `prefix` into `state`, a page at a time, until they have all been
+/// loaded or `state` starts evicting them
+async fn load(
+    kv_client: &mut client::KvClient<Channel>,
+    prefix: &[u8],
+    serializable: bool,
+    state: &mut State,
+) -> EtcdResult<()> {
+    let end = prefix_end(prefix);
+    let page = state
+        .options
+        .max_entries
+        .unwrap_or(LOAD_PAGE)
+        .clamp(1, LOAD_PAGE);
+    let mut key = prefix.to_vec();
+    // Every page is read at the revision of the first, so they fit together
+    let mut revision = 0;
+    loop {
+        let request = etcdserver::RangeRequest {
+            key,
+            range_end: end.clone(),
+            limit: page as i64,
+            revision,
+            serializable,
+            ..Default::default()
+        };
+        let response = kv_client.range(request).await?.into_inner();
+        if revision == 0 {
+            revision = read_revision(response.header, "range")?;
+            state.revision = revision;
+        }
+        let more = response.more;
+        let mut next = None;
+        for kv in response.kvs {
+            next = Some(kv.key.clone());
+            state.insert(kv.key, Bytes::from(kv.value), revision);
+        }
+        match next {
+            Some(mut last) if more && state.complete => {
+                last.push(0);
+                key = last;
+            }
+            _ => return Ok(()),
+        }
+    }
+}
+
+/// Follow changes to the keys under `prefix` into `state` until `stopped`
+async fn follow(
+    mut kv_client: client::KvClient<Channel>,
+    mut watch_client: client::WatchClient<Channel>,
+    prefix: Vec<u8>,
+    serializable: bool,
+    state: Arc<StdMutex<State>>,
+    mut responses: tonic::Streaming<etcdserver::WatchResponse>,
+    mut stopped: oneshot::Receiver<()>,
+) {
+    let lock = || state.lock().unwrap_or_else(|e| e.into_inner());
+    let mut backoff = Backoff::new();
+    loop {
+        let message = Box::pin(responses.message());
+        let response = match future::select(&mut stopped, message).await {
+            Either::Left(_) => return,
+            Either::Right((message, _)) => message,
+        };
+        let response = match response {
+            Ok(Some(response)) => response,
+            Err(status) if !is_retryable(&status) => {
+                return lock().stop(status.to_string());
+            }
+            // The watch broke, so carry on from the last change seen
+            _ => {
+                let next = lock().revision + 1;
+                match rewatch(&mut watch_client, &prefix, next, &mut backoff).await {
+                    Ok(rewatched) => responses = rewatched,
+                    Err(reason) => return lock().stop(reason),
+                }
+                continue;
+            }
+        };
+        backoff.reset();
+        if response.canceled && response.compact_revision > 0 {
+            // The changes since the mirror's revision are gone, so load it again
+            let mut reloaded = State::new(lock().options.clone());
+            let rewatched = match load(&mut kv_client, &prefix, serializable, &mut reloaded).await {
+                Ok(()) => watch(&mut watch_client, &prefix, reloaded.revision + 1).await,
+                Err(e) => return lock().stop(e.to_string()),
+            };
+            match rewatched {
+                Ok(rewatched) => responses = rewatched,
+                Err(status) => return lock().stop(status.to_string()),
+            }
+            let mut state = lock();
+            reloaded.evictions += state.evictions;
+            *state = reloaded;
+            continue;
+        }
+        if response.canceled {
+            return lock().stop(format!("watch canceled: {}", response.cancel_reason));
+        }
+        let mut state = lock();
+        for event in response.events {
+            state.apply(event);
+        }
+    }
+}
+
+/// Watch for changes to the keys under `prefix` from `revision` onwards
+async fn watch(
+    watch_client: &mut client::WatchClient<Channel>,
+    prefix: &[u8],
+    revision: i64,
+) -> Result<tonic::Streaming<etcdserver::WatchResponse>, tonic::Status> {
+    let create = etcdserver::WatchCreateRequest {
+        key: prefix.to_vec(),
+        range_end: prefix_end(prefix),
+        start_revision: revision,
+        ..Default::default()
+    };
+    let requests = async_stream::stream! {
+        yield etcdserver::WatchRequest {
+            request_union: Some(etcdserver::watch_request::RequestUnion::CreateRequest(create)),
+        };
+    };
+    Ok(watch_client.watch(requests).await?.into_inner())
+}
+
+/// Re-establish a broken watch from `revision` onwards, backing off between attempts, until
+/// it is re-established or fails with an error which is not worth retrying
+async fn rewatch(
+    watch_client: &mut client::WatchClient<Channel>,
+    prefix: &[u8],
+    revision: i64,
+    backoff: &mut Backoff,
+) -> Result<tonic::Streaming<etcdserver::WatchResponse>, String> {
+    loop {
+        backoff.wait().await;
+        match watch(watch_client, prefix, revision).await {
+            Ok(responses) => {
+                #[cfg(feature = "metrics")]
+                crate::metrics::watch_reconnected(1);
+                return Ok(responses);
+            }
+            Err(status) if is_retryable(&status) => continue,
+            Err(status) => return Err(status.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(event_type: mvccpb::event::EventType, key: &str, revision: i64) -> mvccpb::Event {
+        mvccpb::Event {
+            r#type: event_type as i32,
+            kv: Some(mvccpb::KeyValue {
+                key: key.as_bytes().to_vec(),
+                value: revision.to_string().into_bytes(),
+                mod_revision: revision,
+                ..Default::default()
+            }),
+            prev_kv: None,
+        }
+    }
+
+    #[test]
+    fn test_evicting_least_recently_used() {
+        let mut state = State::new(MirrorOptions {
+            max_entries: Some(2),
+            ..Default::default()
+        });
+        state.insert(b"a".to_vec(), Bytes::from("1"), 1);
+        state.insert(b"b".to_vec(), Bytes::from("2"), 1);
+        assert!(state.complete);
+
+        // Reading a makes b the least recently used
+        assert_eq!(state.get(b"a"), Some(Bytes::from("1")));
+        state.insert(b"c".to_vec(), Bytes::from("3"), 1);
+        assert_eq!(state.get(b"b"), None);
+        assert!(state.get(b"a").is_some());
+        assert!(state.get(b"c").is_some());
+        assert!(!state.complete);
+        assert_eq!(state.evictions, 1);
+        assert_eq!(state.entries.len(), state.by_use.len());
+    }
+
+    #[test]
+    fn test_limiting_bytes() {
+        let mut state = State::new(MirrorOptions {
+            max_bytes: Some(10),
+            ..Default::default()
+        });
+        state.insert(b"a".to_vec(), Bytes::from("1234"), 1);
+        state.insert(b"b".to_vec(), Bytes::from("1234"), 1);
+        assert_eq!(state.bytes, 10);
+
+        // Replacing a value accounts for the one it replaces
+        state.insert(b"b".to_vec(), Bytes::from("12"), 2);
+        assert_eq!(state.bytes, 8);
+        assert!(state.complete);
+
+        state.insert(b"c".to_vec(), Bytes::from("12"), 3);
+        assert_eq!(state.bytes, 6);
+        assert_eq!(state.get(b"a"), None);
+
+        // A value too large to hold at all is evicted straight away
+        state.insert(b"d".to_vec(), Bytes::from(vec![0; 20]), 4);
+        assert!(state.entries.is_empty());
+        assert_eq!(state.bytes, 0);
+    }
+
+    #[test]
+    fn test_applying_events() {
+        use mvccpb::event::EventType;
+
+        let mut state = State::new(MirrorOptions::default());
+        state.apply(event(EventType::Put, "a", 2));
+        state.apply(event(EventType::Put, "b", 3));
+        assert_eq!(state.get(b"a"), Some(Bytes::from("2")));
+        assert_eq!(state.revision, 3);
+
+        // Changes older than the value held are skipped
+        state.insert(b"a".to_vec(), Bytes::from("5"), 5);
+        state.apply(event(EventType::Delete, "a", 4));
+        assert_eq!(state.get(b"a"), Some(Bytes::from("5")));
+        state.apply(event(EventType::Delete, "a", 6));
+        assert_eq!(state.get(b"a"), None);
+
+        // Once keys have been evicted, new keys are left to be fetched when read
+        state.options.max_entries = Some(1);
+        state.insert(b"c".to_vec(), Bytes::from("7"), 7);
+        assert!(!state.complete);
+        state.apply(event(EventType::Put, "d", 8));
+        assert_eq!(state.get(b"d"), None);
+        state.apply(event(EventType::Put, "c", 9));
+        assert_eq!(state.get(b"c"), Some(Bytes::from("9")));
+    }
+
+    #[tokio::test]
+    async fn test_mirror() {
+        let client = crate::testing::namespace().await.unwrap();
+        let prefix = client.key("test_mirror/");
+        let kv = client.kv();
+        for key in &["a", "b", "c"] {
+            kv.put(format!("{}{}", prefix, key), *key).await.unwrap();
+        }
+
+        let options = MirrorOptions {
+            max_entries: Some(2),
+            ..Default::default()
+        };
+        let mirror = mirror(&client, prefix.as_str(), options).await.unwrap();
+        // Loading stops once the mirror is full, evicting the first key loaded
+        assert_eq!(mirror.len(), 2);
+        assert!(!mirror.is_complete());
+        assert_eq!(mirror.evictions(), 1);
+
+        // Keys which are not held are fetched from the server
+        let a = format!("{}a", prefix);
+        assert_eq!(mirror.get(&a).await.unwrap(), Some(Bytes::from("a")));
+        assert_eq!(mirror.len(), 2);
+        assert_eq!(mirror.get(format!("{}d", prefix)).await.unwrap(), None);
+
+        // Changes to keys held are followed
+        let revision = kv.put(&a, "changed").await.unwrap().revision();
+        while mirror.revision() < revision {
+            runtime::delay_for(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(mirror.get(&a).await.unwrap(), Some(Bytes::from("changed")));
+    }
+}
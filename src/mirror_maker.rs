@@ -0,0 +1,481 @@
+//! Mirroring the keys under a prefix from one cluster to another, as `etcdctl make-mirror`
+//! does, for migrating between clusters or keeping a warm standby.
+//!
+//! A [`MirrorMaker`] copies the keys under the prefix from the source cluster, then follows
+//! changes to them and replays each put and delete on the destination cluster. Along with the
+//! changes it writes a checkpoint on the destination: the source revision mirrored up to, so a
+//! mirror maker started again, in this process or another, carries on where the last one
+//! stopped rather than copying every key again.
+//!
+//! ```no_run
+//! # async fn run(
+//! #     source: etcd3::EtcdClient<tonic::transport::Channel>,
+//! #     destination: etcd3::EtcdClient<tonic::transport::Channel>,
+//! # ) -> etcd3::EtcdResult<()> {
+//! use etcd3::mirror_maker::{MirrorMaker, MirrorMakerOptions};
+//!
+//! let options = MirrorMakerOptions {
+//!     destination_prefix: Some(b"standby/config/".to_vec()),
+//!     ..Default::default()
+//! };
+//! let mirror_maker = MirrorMaker::new(&source, &destination, "config/", options);
+//! // Mirrors changes until an error which is not worth retrying
+//! mirror_maker.run().await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Leases are not mirrored: keys attached to a lease on the source are put on the destination
+//! without one, and deleted there once they expire on the source.
+
+use crate::concurrency::{prefix_end, read_revision};
+use crate::redact::Key;
+use crate::{client, etcdserver, mvccpb, Backoff, Error, EtcdClient, EtcdResult};
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::atomic::{AtomicI64, Ordering};
+use tonic::transport::channel::Channel;
+
+/// Key on the destination the checkpoint is written to by default
+pub const DEFAULT_CHECKPOINT_KEY: &[u8] = b"__etcd3_mirror_maker/checkpoint";
+
+/// Most keys read from the source at once while copying them
+const COPY_PAGE: i64 = 1000;
+
+/// Most operations in a transaction, etcd's default limit
+const MAX_TXN_OPS: usize = 128;
+
+/// How keys are mirrored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MirrorMakerOptions {
+    /// Prefix the keys are mirrored under on the destination, in place of the source prefix.
+    /// The keys keep their source prefix by default.
+    pub destination_prefix: Option<Vec<u8>>,
+    /// Key on the destination holding the source revision mirrored up to, which should not be
+    /// under the destination prefix. Each mirror maker writing to the same destination needs a
+    /// key of its own. [`DEFAULT_CHECKPOINT_KEY`] by default.
+    pub checkpoint_key: Vec<u8>,
+}
+
+impl Default for MirrorMakerOptions {
+    fn default() -> Self {
+        Self {
+            destination_prefix: None,
+            checkpoint_key: DEFAULT_CHECKPOINT_KEY.to_vec(),
+        }
+    }
+}
+
+/// Mirrors the keys under a prefix from a source cluster to a destination cluster, see the
+/// [module documentation](self)
+pub struct MirrorMaker {
+    source_kv: client::KvClient<Channel>,
+    source_watch: client::WatchClient<Channel>,
+    destination_kv: client::KvClient<Channel>,
+    prefix: Vec<u8>,
+    options: MirrorMakerOptions,
+    /// Whether the keys are read from the source serializably
+    serializable: bool,
+    /// Source revision mirrored up to, 0 until the first checkpoint is read or written
+    mirrored: AtomicI64,
+}
+
+impl fmt::Debug for MirrorMaker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MirrorMaker")
+            .field("prefix", &Key(&self.prefix))
+            .field(
+                "destination_prefix",
+                &self.options.destination_prefix.as_deref().map(Key),
+            )
+            .field("checkpoint_key", &Key(&self.options.checkpoint_key))
+            .field("mirrored", &self.mirrored())
+            .finish_non_exhaustive()
+    }
+}
+
+impl MirrorMaker {
+    /// Mirror the keys under `prefix` from `source` to `destination`, as `options` say
+    pub fn new<P>(
+        source: &EtcdClient<Channel>,
+        destination: &EtcdClient<Channel>,
+        prefix: P,
+        options: MirrorMakerOptions,
+    ) -> Self
+    where
+        P: Into<Vec<u8>>,
+    {
+        Self {
+            source_kv: source.kv_client(),
+            source_watch: source.watch_client(),
+            destination_kv: destination.kv_client(),
+            prefix: prefix.into(),
+            options,
+            serializable: source.serializable_reads(),
+            mirrored: AtomicI64::new(0),
+        }
+    }
+
+    /// Source revision the destination has been mirrored up to, as of the last checkpoint
+    /// read or written by this mirror maker
+    pub fn mirrored(&self) -> i64 {
+        self.mirrored.load(Ordering::SeqCst)
+    }
+
+    /// Source revision the destination has been mirrored up to, read from its checkpoint.
+    /// `None` if nothing was mirrored to it yet.
+    pub async fn checkpoint(&self) -> EtcdResult<Option<i64>> {
+        let request = etcdserver::RangeRequest {
+            key: self.options.checkpoint_key.clone(),
+            ..Default::default()
+        };
+        let response = self.destination_kv.clone().range(request).await?;
+        let kv = match response.into_inner().kvs.into_iter().next() {
+            Some(kv) => kv,
+            None => return Ok(None),
+        };
+        let checkpoint = parse_checkpoint(&kv.value)?;
+        self.mirrored.store(checkpoint, Ordering::SeqCst);
+        Ok(Some(checkpoint))
+    }
+
+    /// Copy every key under the prefix, as of the source's current revision, and delete the
+    /// keys on the destination which are not on the source, then checkpoint that revision and
+    /// return it.
+    ///
+    /// The destination's keys are changed in several transactions, so until the copy is
+    /// checkpointed they may be a mix of the old and new keys.
+    pub async fn copy(&self) -> EtcdResult<i64> {
+        let mut source_kv = self.source_kv.clone();
+        let end = prefix_end(&self.prefix);
+        let mut copied = HashSet::new();
+        let mut key = self.prefix.clone();
+        // Every page is read at the revision of the first, so they fit together
+        let mut revision = 0;
+        loop {
+            let request = etcdserver::RangeRequest {
+                key,
+                range_end: end.clone(),
+                limit: COPY_PAGE,
+                revision,
+                serializable: self.serializable,
+                ..Default::default()
+            };
+            let response = source_kv.range(request).await?.into_inner();
+            if revision == 0 {
+                revision = read_revision(response.header, "range")?;
+            }
+            let mut puts = Vec::with_capacity(response.kvs.len());
+            for kv in &response.kvs {
+                let destination_key = self.destination_key(&kv.key);
+                copied.insert(destination_key.clone());
+                puts.push(put(destination_key, kv.value.clone()));
+            }
+            for chunk in puts.chunks(MAX_TXN_OPS) {
+                self.commit(chunk.to_vec()).await?;
+            }
+            match response.kvs.last() {
+                Some(last) if response.more => {
+                    key = last.key.clone();
+                    key.push(0);
+                }
+                _ => break,
+            }
+        }
+
+        self.delete_uncopied(&copied).await?;
+        self.commit(vec![self.checkpoint_op(revision)]).await?;
+        self.mirrored.store(revision, Ordering::SeqCst);
+        Ok(revision)
+    }
+
+    /// Mirror the keys for as long as the source and destination can be reached, copying them
+    /// first unless the destination has a checkpoint to carry on from.
+    ///
+    /// Errors worth retrying, such as either cluster being unavailable, are retried with
+    /// backoff from the last checkpoint, and if the changes since it were compacted away on
+    /// the source the keys are copied again. Only returns with an error which is not worth
+    /// retrying.
+    pub async fn run(&self) -> EtcdResult<()> {
+        let mut backoff = Backoff::new();
+        loop {
+            let error = match self.follow(&mut backoff).await {
+                Ok(never) => match never {},
+                Err(e) => e,
+            };
+            if !error.is_retryable() {
+                return Err(error);
+            }
+            backoff.wait().await;
+        }
+    }
+
+    /// Follow changes from the last checkpoint onwards until an error
+    async fn follow(&self, backoff: &mut Backoff) -> EtcdResult<std::convert::Infallible> {
+        let mut revision = match self.checkpoint().await? {
+            Some(revision) => revision,
+            None => self.copy().await?,
+        };
+        let mut watch_client = self.source_watch.clone();
+        let mut responses = watch(&mut watch_client, &self.prefix, revision + 1).await?;
+        loop {
+            let response = match responses.message().await {
+                Ok(Some(response)) => response,
+                Ok(None) => {
+                    let closed = tonic::Status::new(tonic::Code::Unavailable, "watch closed");
+                    return Err(Error::Status(closed));
+                }
+                Err(status) => return Err(status.into()),
+            };
+            backoff.reset();
+            if response.canceled && response.compact_revision > 0 {
+                // The changes since the checkpoint are gone, so copy the keys again
+                revision = self.copy().await?;
+                responses = watch(&mut watch_client, &self.prefix, revision + 1).await?;
+                continue;
+            }
+            if response.canceled {
+                let reason = format!("watch canceled: {}", response.cancel_reason);
+                return Err(reason.into());
+            }
+            revision = self.replay(response.events, revision).await?;
+        }
+    }
+
+    /// Replay `events` on the destination, checkpointing along with them, and return the
+    /// revision mirrored up to
+    async fn replay(&self, events: Vec<mvccpb::Event>, mut revision: i64) -> EtcdResult<i64> {
+        let mut events = events.into_iter().filter_map(|e| {
+            let event_type = e.r#type;
+            e.kv.map(|kv| (event_type, kv))
+        });
+        let mut next = events.next();
+        while next.is_some() {
+            let mut ops = Vec::new();
+            while ops.len() < MAX_TXN_OPS - 1 {
+                let (event_type, kv) = match next.take() {
+                    Some(event) => event,
+                    None => break,
+                };
+                revision = revision.max(kv.mod_revision);
+                let key = self.destination_key(&kv.key);
+                ops.push(if event_type == mvccpb::event::EventType::Delete as i32 {
+                    delete(key)
+                } else {
+                    put(key, kv.value)
+                });
+                next = events.next();
+            }
+            // A transaction's changes may be split across several of ours, so only its
+            // revision's predecessor is mirrored until the last of them is applied
+            let checkpoint = match &next {
+                Some((_, kv)) if kv.mod_revision == revision => revision - 1,
+                _ => revision,
+            };
+            ops.push(self.checkpoint_op(checkpoint));
+            self.commit(ops).await?;
+            self.mirrored.store(checkpoint, Ordering::SeqCst);
+        }
+        Ok(revision)
+    }
+
+    /// Delete the keys under the destination prefix which are not in `copied`
+    async fn delete_uncopied(&self, copied: &HashSet<Vec<u8>>) -> EtcdResult<()> {
+        let mut destination_kv = self.destination_kv.clone();
+        let prefix = self.destination_key(&self.prefix);
+        let end = prefix_end(&prefix);
+        let mut key = prefix;
+        loop {
+            let request = etcdserver::RangeRequest {
+                key,
+                range_end: end.clone(),
+                limit: COPY_PAGE,
+                keys_only: true,
+                ..Default::default()
+            };
+            let response = destination_kv.range(request).await?.into_inner();
+            let deletes: Vec<_> = response
+                .kvs
+                .iter()
+                .filter(|kv| !copied.contains(&kv.key))
+                .map(|kv| delete(kv.key.clone()))
+                .collect();
+            for chunk in deletes.chunks(MAX_TXN_OPS) {
+                self.commit(chunk.to_vec()).await?;
+            }
+            match response.kvs.last() {
+                Some(last) if response.more => {
+                    key = last.key.clone();
+                    key.push(0);
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Apply `ops` on the destination at once
+    async fn commit(&self, ops: Vec<etcdserver::RequestOp>) -> EtcdResult<()> {
+        let request = etcdserver::TxnRequest {
+            compare: Vec::new(),
+            success: ops,
+            failure: Vec::new(),
+        };
+        self.destination_kv.clone().txn(request).await?;
+        Ok(())
+    }
+
+    /// Operation writing `revision` as the checkpoint
+    fn checkpoint_op(&self, revision: i64) -> etcdserver::RequestOp {
+        let value = revision.to_string().into_bytes();
+        put(self.options.checkpoint_key.clone(), value)
+    }
+
+    /// Key on the destination mirroring the source key `key`
+    fn destination_key(&self, key: &[u8]) -> Vec<u8> {
+        match &self.options.destination_prefix {
+            Some(destination_prefix) => {
+                let mut destination_key = destination_prefix.clone();
+                destination_key.extend_from_slice(&key[self.prefix.len().min(key.len())..]);
+                destination_key
+            }
+            None => key.to_vec(),
+        }
+    }
+}
+
+/// Source revision stored as a checkpoint
+fn parse_checkpoint(value: &[u8]) -> EtcdResult<i64> {
+    match std::str::from_utf8(value)?.parse() {
+        Ok(revision) => Ok(revision),
+        Err(_) => Err(format!(
+            "invalid mirror checkpoint {:?}",
+            String::from_utf8_lossy(value)
+        )
+        .into()),
+    }
+}
+
+fn put(key: Vec<u8>, value: Vec<u8>) -> etcdserver::RequestOp {
+    let put = etcdserver::PutRequest {
+        key,
+        value,
+        ..Default::default()
+    };
+    etcdserver::RequestOp {
+        request: Some(etcdserver::request_op::Request::RequestPut(put)),
+    }
+}
+
+fn delete(key: Vec<u8>) -> etcdserver::RequestOp {
+    let delete = etcdserver::DeleteRangeRequest {
+        key,
+        ..Default::default()
+    };
+    etcdserver::RequestOp {
+        request: Some(etcdserver::request_op::Request::RequestDeleteRange(delete)),
+    }
+}
+
+/// Watch for changes to the keys under `prefix` from `revision` onwards
+async fn watch(
+    watch_client: &mut client::WatchClient<Channel>,
+    prefix: &[u8],
+    revision: i64,
+) -> Result<tonic::Streaming<etcdserver::WatchResponse>, tonic::Status> {
+    let create = etcdserver::WatchCreateRequest {
+        key: prefix.to_vec(),
+        range_end: prefix_end(prefix),
+        start_revision: revision,
+        ..Default::default()
+    };
+    let requests = async_stream::stream! {
+        yield etcdserver::WatchRequest {
+            request_union: Some(etcdserver::watch_request::RequestUnion::CreateRequest(create)),
+        };
+    };
+    Ok(watch_client.watch(requests).await?.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_mirroring_between_clusters() {
+        let source = crate::testing::namespace().await.unwrap();
+        let destination = crate::testing::namespace().await.unwrap();
+        let prefix = source.key("test_mirroring_between_clusters/");
+        let standby = destination.key("test_mirroring_between_clusters_standby/");
+        let options = MirrorMakerOptions {
+            destination_prefix: Some(standby.into_bytes()),
+            checkpoint_key: destination
+                .key("test_mirroring_between_clusters_checkpoint")
+                .into_bytes(),
+        };
+        let source_kv = source.kv();
+        source_kv
+            .put("test_mirroring_between_clusters/a", "1")
+            .await
+            .unwrap();
+        let destination_kv = destination.kv();
+        destination_kv
+            .put("test_mirroring_between_clusters_standby/stale", "0")
+            .await
+            .unwrap();
+
+        let mirror_maker =
+            MirrorMaker::new(&source, &destination, prefix.as_str(), options.clone());
+        assert_eq!(mirror_maker.checkpoint().await.unwrap(), None);
+        let copied = mirror_maker.copy().await.unwrap();
+        assert_eq!(mirror_maker.checkpoint().await.unwrap(), Some(copied));
+        assert_eq!(
+            destination_kv
+                .get("test_mirroring_between_clusters_standby/a")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("1")
+        );
+        assert_eq!(
+            destination_kv
+                .get("test_mirroring_between_clusters_standby/stale")
+                .await
+                .unwrap(),
+            None
+        );
+
+        // Carries on from the checkpoint, replaying the changes made since
+        source_kv
+            .put("test_mirroring_between_clusters/b", "2")
+            .await
+            .unwrap();
+        let deleted = source_kv
+            .delete("test_mirroring_between_clusters/a")
+            .await
+            .unwrap();
+        let running = MirrorMaker::new(&source, &destination, prefix.as_str(), options);
+        let run = Box::pin(running.run());
+        let caught_up = async {
+            while running.mirrored() < deleted.revision() {
+                crate::runtime::delay_for(std::time::Duration::from_millis(10)).await;
+            }
+        };
+        futures::pin_mut!(caught_up);
+        futures::future::select(run, caught_up).await;
+        assert_eq!(
+            destination_kv
+                .get("test_mirroring_between_clusters_standby/b")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("2")
+        );
+        assert_eq!(
+            destination_kv
+                .get("test_mirroring_between_clusters_standby/a")
+                .await
+                .unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,81 @@
+//! Propagation of the current OpenTelemetry trace context to the cluster, with the `otel`
+//! feature.
+//!
+//! Every request carries the span which is current in OpenTelemetry's
+//! [`Context`](opentelemetry::Context) when it is sent, as W3C `traceparent` and
+//! `tracestate` metadata, so proxies and gateways in front of the cluster which understand
+//! tracing can attach their spans to the caller's trace. Requests sent outside of any span
+//! carry no trace context.
+
+use opentelemetry::trace::TraceContextExt;
+use opentelemetry::Context;
+
+/// Version of the `traceparent` format written
+const VERSION: u8 = 0;
+
+/// Add the current trace context to the headers of a request, unless the request was given
+/// one of its own
+pub(crate) fn inject(headers: &mut http::HeaderMap) {
+    if headers.contains_key("traceparent") {
+        return;
+    }
+    let context = Context::current();
+    let span = context.span();
+    let span_context = span.span_context();
+    if !span_context.is_valid() {
+        return;
+    }
+    let traceparent = format!(
+        "{:02x}-{}-{}-{:02x}",
+        VERSION,
+        span_context.trace_id(),
+        span_context.span_id(),
+        span_context.trace_flags().to_u8()
+    );
+    if let Ok(traceparent) = http::HeaderValue::from_str(&traceparent) {
+        headers.insert("traceparent", traceparent);
+    }
+    let tracestate = span_context.trace_state().header();
+    if tracestate.is_empty() {
+        return;
+    }
+    if let Ok(tracestate) = http::HeaderValue::from_str(&tracestate) {
+        headers.insert("tracestate", tracestate);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::{SpanContext, SpanId, TraceFlags, TraceId, TraceState};
+
+    #[test]
+    fn test_inject() {
+        let mut headers = http::HeaderMap::new();
+        inject(&mut headers);
+        assert!(headers.is_empty());
+
+        let span_context = SpanContext::new(
+            TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap(),
+            SpanId::from_hex("00f067aa0ba902b7").unwrap(),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::from_key_value(vec![("vendor", "value")]).unwrap(),
+        );
+        let context = Context::current().with_remote_span_context(span_context);
+        let _attached = context.attach();
+        inject(&mut headers);
+        assert_eq!(
+            headers["traceparent"],
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+        );
+        assert_eq!(headers["tracestate"], "vendor=value");
+
+        // A trace context given for a single request is kept
+        let mut headers = http::HeaderMap::new();
+        headers.insert("traceparent", http::HeaderValue::from_static("given"));
+        inject(&mut headers);
+        assert_eq!(headers["traceparent"], "given");
+        assert!(!headers.contains_key("tracestate"));
+    }
+}
@@ -0,0 +1,46 @@
+//! The types most programs using the client need, to import at once with
+//! `use etcd3::prelude::*;`.
+//!
+//! This covers the client and its builder, the service handles, the options and comparisons
+//! operations take, the responses they give, errors, and the recipes of [`concurrency`]. Types
+//! for less common tasks, such as TLS, proxies or rate limits, are imported from the crate
+//! root.
+//!
+//! [`concurrency`]: crate::concurrency
+
+#[cfg(feature = "auth")]
+pub use crate::Auth;
+#[cfg(feature = "election")]
+pub use crate::Election;
+#[cfg(feature = "maintenance")]
+pub use crate::Maintenance;
+pub use crate::{
+    compare_create_revision, compare_mod_revision, compare_value, compare_version, CompareResult,
+    CompareTarget, ConsistencyToken, DeleteResponse, Error, EtcdClient, EtcdClientBuilder,
+    EtcdResult, EventKind, GetResponse, KeyValue, Kv, Lease, PutOptions, PutResponse, Range,
+    SortOrder, SortTarget, TxnOpResponse, TxnResponse, Watch, WatchEvent, WatchResponse,
+    WatchStream,
+};
+#[cfg(feature = "cluster")]
+pub use crate::{Cluster, MemberListOptions};
+
+pub use crate::chunked::{Chunked, ChunkedOptions};
+#[cfg(feature = "election")]
+pub use crate::concurrency::LeaderElector;
+pub use crate::concurrency::{
+    Barrier, DoubleBarrier, Mutex, PriorityQueue, Queue, RwLock, Semaphore, Session, Singleton,
+    Stm, WorkQueue,
+};
+pub use crate::discovery::{Discovery, Instance, Registration};
+pub use crate::informer::{Informer, InformerEvent};
+pub use crate::mirror::{Mirror, MirrorOptions};
+pub use crate::mirror_maker::{MirrorMaker, MirrorMakerOptions};
+pub use crate::resolver::ResolvedChannel;
+#[cfg(feature = "signal")]
+pub use crate::shutdown::Shutdown;
+pub use crate::ttl_map::{TtlEvent, TtlMap, TtlMapOptions};
+
+#[cfg(feature = "serde")]
+pub use crate::json::ToJson;
+#[cfg(feature = "serde")]
+pub use crate::{ConfigWatcher, Json, TypedKv};
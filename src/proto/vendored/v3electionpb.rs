@@ -0,0 +1,196 @@
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CampaignRequest {
+    /// name is the election's identifier for the campaign.
+    #[prost(bytes, tag = "1")]
+    pub name: std::vec::Vec<u8>,
+    /// lease is the ID of the lease attached to leadership of the election. If the
+    /// lease expires or is revoked before resigning leadership, then the
+    /// leadership is transferred to the next campaigner, if any.
+    #[prost(int64, tag = "2")]
+    pub lease: i64,
+    /// value is the initial proclaimed value set when the campaigner wins the
+    /// election.
+    #[prost(bytes, tag = "3")]
+    pub value: std::vec::Vec<u8>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CampaignResponse {
+    #[prost(message, optional, tag = "1")]
+    pub header: ::std::option::Option<super::etcdserverpb::ResponseHeader>,
+    /// leader describes the resources used for holding leadereship of the election.
+    #[prost(message, optional, tag = "2")]
+    pub leader: ::std::option::Option<LeaderKey>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LeaderKey {
+    /// name is the election identifier that correponds to the leadership key.
+    #[prost(bytes, tag = "1")]
+    pub name: std::vec::Vec<u8>,
+    /// key is an opaque key representing the ownership of the election. If the key
+    /// is deleted, then leadership is lost.
+    #[prost(bytes, tag = "2")]
+    pub key: std::vec::Vec<u8>,
+    /// rev is the creation revision of the key. It can be used to test for ownership
+    /// of an election during transactions by testing the key's creation revision
+    /// matches rev.
+    #[prost(int64, tag = "3")]
+    pub rev: i64,
+    /// lease is the lease ID of the election leader.
+    #[prost(int64, tag = "4")]
+    pub lease: i64,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LeaderRequest {
+    /// name is the election identifier for the leadership information.
+    #[prost(bytes, tag = "1")]
+    pub name: std::vec::Vec<u8>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LeaderResponse {
+    #[prost(message, optional, tag = "1")]
+    pub header: ::std::option::Option<super::etcdserverpb::ResponseHeader>,
+    /// kv is the key-value pair representing the latest leader update.
+    #[prost(message, optional, tag = "2")]
+    pub kv: ::std::option::Option<super::mvccpb::KeyValue>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ResignRequest {
+    /// leader is the leadership to relinquish by resignation.
+    #[prost(message, optional, tag = "1")]
+    pub leader: ::std::option::Option<LeaderKey>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ResignResponse {
+    #[prost(message, optional, tag = "1")]
+    pub header: ::std::option::Option<super::etcdserverpb::ResponseHeader>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProclaimRequest {
+    /// leader is the leadership hold on the election.
+    #[prost(message, optional, tag = "1")]
+    pub leader: ::std::option::Option<LeaderKey>,
+    /// value is an update meant to overwrite the leader's current value.
+    #[prost(bytes, tag = "2")]
+    pub value: std::vec::Vec<u8>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProclaimResponse {
+    #[prost(message, optional, tag = "1")]
+    pub header: ::std::option::Option<super::etcdserverpb::ResponseHeader>,
+}
+#[doc = r" Generated client implementations."]
+pub mod client {
+    #![allow(unused_variables, dead_code, missing_docs)]
+    use tonic::codegen::*;
+    #[doc = " The election service exposes client-side election facilities as a gRPC interface."]
+    pub struct ElectionClient<T> {
+        inner: tonic::client::Grpc<T>,
+    }
+    impl ElectionClient<tonic::transport::Channel> {
+        #[doc = r" Attempt to create a new client by connecting to a given endpoint."]
+        pub async fn connect<D>(dst: D) -> Result<Self, tonic::transport::Error>
+        where
+            D: std::convert::TryInto<tonic::transport::Endpoint>,
+            D::Error: Into<StdError>,
+        {
+            let conn = tonic::transport::Endpoint::new(dst)?.connect().await?;
+            Ok(Self::new(conn))
+        }
+    }
+    impl<T> ElectionClient<T>
+    where
+        T: tonic::client::GrpcService<tonic::body::BoxBody>,
+        T::ResponseBody: Body + HttpBody + Send + 'static,
+        T::Error: Into<StdError>,
+        <T::ResponseBody as HttpBody>::Error: Into<StdError> + Send,
+        <T::ResponseBody as HttpBody>::Data: Into<bytes::Bytes> + Send,
+    {
+        pub fn new(inner: T) -> Self {
+            let inner = tonic::client::Grpc::new(inner);
+            Self { inner }
+        }
+        #[doc = r" Check if the service is ready."]
+        pub async fn ready(&mut self) -> Result<(), tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })
+        }
+        #[doc = " Campaign waits to acquire leadership in an election, returning a LeaderKey"]
+        #[doc = " representing the leadership if successful. The LeaderKey can then be used"]
+        #[doc = " to issue new values on the election, transactionally guard API requests on"]
+        #[doc = " leadership still being held, and resign from the election."]
+        pub async fn campaign(
+            &mut self,
+            request: impl tonic::IntoRequest<super::CampaignRequest>,
+        ) -> Result<tonic::Response<super::CampaignResponse>, tonic::Status> {
+            self.ready().await?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/v3electionpb.Election/Campaign");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        #[doc = " Proclaim updates the leader's posted value with a new value."]
+        pub async fn proclaim(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ProclaimRequest>,
+        ) -> Result<tonic::Response<super::ProclaimResponse>, tonic::Status> {
+            self.ready().await?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/v3electionpb.Election/Proclaim");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        #[doc = " Leader returns the current election proclamation, if any."]
+        pub async fn leader(
+            &mut self,
+            request: impl tonic::IntoRequest<super::LeaderRequest>,
+        ) -> Result<tonic::Response<super::LeaderResponse>, tonic::Status> {
+            self.ready().await?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/v3electionpb.Election/Leader");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        #[doc = " Observe streams election proclamations in-order as made by the election's"]
+        #[doc = " elected leaders."]
+        pub async fn observe(
+            &mut self,
+            request: impl tonic::IntoRequest<super::LeaderRequest>,
+        ) -> Result<tonic::Response<tonic::codec::Streaming<super::LeaderResponse>>, tonic::Status>
+        {
+            self.ready().await?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/v3electionpb.Election/Observe");
+            self.inner
+                .server_streaming(request.into_request(), path, codec)
+                .await
+        }
+        #[doc = " Resign releases election leadership so other campaigners may acquire"]
+        #[doc = " leadership on the election."]
+        pub async fn resign(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ResignRequest>,
+        ) -> Result<tonic::Response<super::ResignResponse>, tonic::Status> {
+            self.ready().await?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/v3electionpb.Election/Resign");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+    }
+    impl<T: Clone> Clone for ElectionClient<T> {
+        fn clone(&self) -> Self {
+            Self {
+                inner: self.inner.clone(),
+            }
+        }
+    }
+}
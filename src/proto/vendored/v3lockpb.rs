@@ -0,0 +1,114 @@
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LockRequest {
+    /// name is the identifier for the distributed shared lock to be acquired.
+    #[prost(bytes, tag = "1")]
+    pub name: std::vec::Vec<u8>,
+    /// lease is the ID of the lease that will be attached to ownership of the
+    /// lock. If the lease expires or is revoked and currently holds the lock,
+    /// the lock is automatically released. Calls to Lock with the same lease will
+    /// be treated as a single acquisition; locking twice with the same lease is a
+    /// no-op.
+    #[prost(int64, tag = "2")]
+    pub lease: i64,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LockResponse {
+    #[prost(message, optional, tag = "1")]
+    pub header: ::std::option::Option<super::etcdserverpb::ResponseHeader>,
+    /// key is a key that will exist on etcd for the duration that the Lock caller
+    /// owns the lock. Users should not modify this key or the lock may exhibit
+    /// undefined behavior.
+    #[prost(bytes, tag = "2")]
+    pub key: std::vec::Vec<u8>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UnlockRequest {
+    /// key is the lock ownership key granted by Lock.
+    #[prost(bytes, tag = "1")]
+    pub key: std::vec::Vec<u8>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UnlockResponse {
+    #[prost(message, optional, tag = "1")]
+    pub header: ::std::option::Option<super::etcdserverpb::ResponseHeader>,
+}
+#[doc = r" Generated client implementations."]
+pub mod client {
+    #![allow(unused_variables, dead_code, missing_docs)]
+    use tonic::codegen::*;
+    #[doc = " The lock service exposes client-side locking facilities as a gRPC interface."]
+    pub struct LockClient<T> {
+        inner: tonic::client::Grpc<T>,
+    }
+    impl LockClient<tonic::transport::Channel> {
+        #[doc = r" Attempt to create a new client by connecting to a given endpoint."]
+        pub async fn connect<D>(dst: D) -> Result<Self, tonic::transport::Error>
+        where
+            D: std::convert::TryInto<tonic::transport::Endpoint>,
+            D::Error: Into<StdError>,
+        {
+            let conn = tonic::transport::Endpoint::new(dst)?.connect().await?;
+            Ok(Self::new(conn))
+        }
+    }
+    impl<T> LockClient<T>
+    where
+        T: tonic::client::GrpcService<tonic::body::BoxBody>,
+        T::ResponseBody: Body + HttpBody + Send + 'static,
+        T::Error: Into<StdError>,
+        <T::ResponseBody as HttpBody>::Error: Into<StdError> + Send,
+        <T::ResponseBody as HttpBody>::Data: Into<bytes::Bytes> + Send,
+    {
+        pub fn new(inner: T) -> Self {
+            let inner = tonic::client::Grpc::new(inner);
+            Self { inner }
+        }
+        #[doc = r" Check if the service is ready."]
+        pub async fn ready(&mut self) -> Result<(), tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })
+        }
+        #[doc = " Lock acquires a distributed shared lock on a given named lock."]
+        #[doc = " On success, it will return a unique key that exists so long as the"]
+        #[doc = " lock is held by the caller. This key can be used in conjunction with"]
+        #[doc = " transactions to safely ensure updates to etcd only occur while holding"]
+        #[doc = " lock ownership. The lock is held until Unlock is called on the key or the"]
+        #[doc = " lease associate with the owner expires."]
+        pub async fn lock(
+            &mut self,
+            request: impl tonic::IntoRequest<super::LockRequest>,
+        ) -> Result<tonic::Response<super::LockResponse>, tonic::Status> {
+            self.ready().await?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/v3lockpb.Lock/Lock");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        #[doc = " Unlock takes a key returned by Lock and releases the hold on lock. The"]
+        #[doc = " next Lock caller waiting for the lock will then be woken up and given"]
+        #[doc = " ownership of the lock."]
+        pub async fn unlock(
+            &mut self,
+            request: impl tonic::IntoRequest<super::UnlockRequest>,
+        ) -> Result<tonic::Response<super::UnlockResponse>, tonic::Status> {
+            self.ready().await?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/v3lockpb.Lock/Unlock");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+    }
+    impl<T: Clone> Clone for LockClient<T> {
+        fn clone(&self) -> Self {
+            Self {
+                inner: self.inner.clone(),
+            }
+        }
+    }
+}
@@ -0,0 +1,630 @@
+//! Recording a client's requests and responses, and replaying them later, for golden tests of
+//! application logic against interactions captured from a real cluster.
+//!
+//! A [`Recorder`] is a `tower` layer for
+//! [`EtcdClientBuilder::connect_with_layer`](crate::EtcdClientBuilder::connect_with_layer)
+//! keeping every request sent through it and the response to it, which can be
+//! [saved](Recorder::save) to a file. [`Replay`] is a transport for
+//! [`EtcdClient::from_transport`](crate::EtcdClient::from_transport) answering requests with
+//! the recorded responses, without a cluster:
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! use etcd3::recording::{Recorder, Replay};
+//! use etcd3::EtcdClient;
+//!
+//! let recorder = Recorder::new();
+//! let client = EtcdClient::builder()
+//!     .endpoint("http://127.0.0.1:2379")
+//!     .connect_with_layer(recorder.clone())
+//!     .await?;
+//! client.range("foo", None).put("bar").await?;
+//! recorder.save("tests/golden/put.etcd")?;
+//!
+//! let replay = Replay::load("tests/golden/put.etcd")?;
+//! let client = EtcdClient::from_transport(replay.clone());
+//! client.range("foo", None).put("bar").await?;
+//! assert_eq!(replay.remaining(), 0);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Responses are replayed in the order they were recorded, separately for each method, so
+//! the code under test has to make the same calls as it did while recording, but what it
+//! sends is not checked. Streams, such as watches, are replayed as the responses recorded
+//! before the stream ended or was dropped.
+
+use bytes::Buf;
+use futures::future::{BoxFuture, FutureExt};
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::fmt::{self, Write as _};
+use std::fs;
+use std::io::{self, Cursor};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tonic::body::BoxBody;
+use tonic::client::GrpcService;
+use tonic::codegen::{HttpBody, HyperBody, Service};
+
+/// First line of a saved recording, naming its format
+const HEADER: &str = "# etcd3 recording v1";
+
+/// A request and the response to it, as gRPC frames
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Exchange {
+    /// The method called, such as `/etcdserverpb.KV/Range`
+    pub path: String,
+    /// The request messages sent, each framed as on the wire
+    pub request: Vec<u8>,
+    /// The response messages received, each framed as on the wire
+    pub response: Vec<u8>,
+    /// The status the call ended with
+    pub code: tonic::Code,
+    /// The status's message
+    pub message: String,
+}
+
+impl Exchange {
+    fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            request: Vec::new(),
+            response: Vec::new(),
+            // Until the call ends, such as when its stream is dropped first
+            code: tonic::Code::Cancelled,
+            message: String::new(),
+        }
+    }
+
+    /// A successful call to `path` answered with `response`, for tests replaying calls
+    #[cfg(test)]
+    pub(crate) fn answered<M: prost::Message>(path: &str, response: M) -> Self {
+        let mut message = Vec::new();
+        response.encode(&mut message).unwrap();
+        let mut framed = vec![0];
+        framed.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        framed.extend_from_slice(&message);
+        Self {
+            response: framed,
+            code: tonic::Code::Ok,
+            ..Self::new(path)
+        }
+    }
+
+    /// Record the status in `headers`, if there is one
+    fn ended(&mut self, headers: &http::HeaderMap) {
+        let status = headers
+            .get("grpc-status")
+            .and_then(|code| code.to_str().ok()?.parse().ok());
+        if let Some(code) = status {
+            self.code = tonic::Code::from_i32(code);
+            self.message = headers
+                .get("grpc-message")
+                .and_then(|message| message.to_str().ok())
+                .map(percent_decode)
+                .unwrap_or_default();
+        }
+    }
+
+    /// The exchange as a line of a saved recording
+    fn to_line(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}",
+            self.path,
+            self.code as i32,
+            hex(self.message.as_bytes()),
+            hex(&self.request),
+            hex(&self.response)
+        )
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        let mut fields = line.split('\t');
+        let path = fields.next()?.to_string();
+        let code = tonic::Code::from_i32(fields.next()?.parse().ok()?);
+        let message = String::from_utf8(unhex(fields.next()?)?).ok()?;
+        let request = unhex(fields.next()?)?;
+        let response = unhex(fields.next()?)?;
+        if fields.next().is_some() {
+            return None;
+        }
+        Some(Self {
+            path,
+            request,
+            response,
+            code,
+            message,
+        })
+    }
+}
+
+/// A `tower` layer recording the requests sent through it and their responses. Clones share
+/// their recording.
+#[derive(Debug, Clone, Default)]
+pub struct Recorder {
+    /// Every exchange so far, in the order the calls were made
+    exchanges: Arc<Mutex<Vec<Exchange>>>,
+}
+
+impl Recorder {
+    /// A recorder which has not recorded anything yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The exchanges recorded so far, in the order the calls were made
+    pub fn exchanges(&self) -> Vec<Exchange> {
+        self.exchanges.lock().unwrap().clone()
+    }
+
+    /// Save the exchanges recorded so far to `path`, for [`Replay::load`]
+    pub fn save<P>(&self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let mut saved = format!("{}\n", HEADER);
+        for exchange in self.exchanges.lock().unwrap().iter() {
+            saved.push_str(&exchange.to_line());
+            saved.push('\n');
+        }
+        fs::write(path, saved)
+    }
+
+    /// Start recording a call to `path`, returning its index
+    fn start(&self, path: &str) -> usize {
+        let mut exchanges = self.exchanges.lock().unwrap();
+        exchanges.push(Exchange::new(path));
+        exchanges.len() - 1
+    }
+
+    fn update<F>(&self, index: usize, update: F)
+    where
+        F: FnOnce(&mut Exchange),
+    {
+        update(&mut self.exchanges.lock().unwrap()[index]);
+    }
+}
+
+impl<S> tower_layer::Layer<S> for Recorder {
+    type Service = Recording<S>;
+
+    fn layer(&self, inner: S) -> Recording<S> {
+        Recording {
+            inner,
+            recorder: self.clone(),
+        }
+    }
+}
+
+/// A service recording its requests and the responses to them, see [`Recorder`]
+#[derive(Debug, Clone)]
+pub struct Recording<S> {
+    inner: S,
+    recorder: Recorder,
+}
+
+impl<S> Service<http::Request<BoxBody>> for Recording<S>
+where
+    S: Service<http::Request<BoxBody>, Response = http::Response<HyperBody>>,
+    S::Future: Send + 'static,
+    S::Error: fmt::Display,
+{
+    type Response = http::Response<RecordedBody>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        let recorder = self.recorder.clone();
+        let index = recorder.start(request.uri().path());
+        let sent = recorder.clone();
+        let request = request.map(|body| {
+            BoxBody::new(RecordedRequest {
+                inner: body,
+                recorder: sent,
+                index,
+            })
+        });
+        let response = self.inner.call(request);
+        async move {
+            match response.await {
+                Ok(response) => {
+                    // Calls which fail straight away only have headers
+                    recorder.update(index, |exchange| exchange.ended(response.headers()));
+                    Ok(response.map(|body| RecordedBody {
+                        inner: body,
+                        recorder,
+                        index,
+                    }))
+                }
+                Err(e) => {
+                    let message = e.to_string();
+                    recorder.update(index, |exchange| {
+                        exchange.code = tonic::Code::Unavailable;
+                        exchange.message = message;
+                    });
+                    Err(e)
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+/// Body of a request being recorded
+struct RecordedRequest {
+    inner: BoxBody,
+    recorder: Recorder,
+    index: usize,
+}
+
+impl HttpBody for RecordedRequest {
+    type Data = Cursor<bytes::Bytes>;
+    type Error = tonic::Status;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let data = Pin::new(&mut self.inner).poll_data(cx);
+        if let Poll::Ready(Some(Ok(data))) = &data {
+            let sent = data.bytes();
+            self.recorder
+                .update(self.index, |exchange| exchange.request.extend(sent));
+        }
+        data
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<http::HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+}
+
+/// Body of a response being recorded, see [`Recording`]
+pub struct RecordedBody {
+    inner: HyperBody,
+    recorder: Recorder,
+    index: usize,
+}
+
+impl HttpBody for RecordedBody {
+    type Data = <HyperBody as HttpBody>::Data;
+    type Error = <HyperBody as HttpBody>::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let data = Pin::new(&mut self.inner).poll_data(cx);
+        if let Poll::Ready(Some(Ok(data))) = &data {
+            let received = data.bytes();
+            self.recorder
+                .update(self.index, |exchange| exchange.response.extend(received));
+        }
+        data
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<http::HeaderMap>, Self::Error>> {
+        let trailers = Pin::new(&mut self.inner).poll_trailers(cx);
+        if let Poll::Ready(Ok(Some(trailers))) = &trailers {
+            self.recorder
+                .update(self.index, |exchange| exchange.ended(trailers));
+        }
+        trailers
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+}
+
+/// A transport answering requests with recorded responses, see [`Recorder`]. Clones share
+/// the responses left to replay.
+#[derive(Debug, Clone)]
+pub struct Replay {
+    /// Exchanges left to replay, by method
+    exchanges: Arc<Mutex<HashMap<String, VecDeque<Exchange>>>>,
+}
+
+impl Replay {
+    /// Replay `exchanges`, such as those of a [`Recorder`]
+    pub fn new<I>(exchanges: I) -> Self
+    where
+        I: IntoIterator<Item = Exchange>,
+    {
+        let mut by_path: HashMap<_, VecDeque<_>> = HashMap::new();
+        for exchange in exchanges {
+            by_path
+                .entry(exchange.path.clone())
+                .or_default()
+                .push_back(exchange);
+        }
+        Self {
+            exchanges: Arc::new(Mutex::new(by_path)),
+        }
+    }
+
+    /// Replay the exchanges saved to `path` with [`Recorder::save`]
+    pub fn load<P>(path: P) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let saved = fs::read_to_string(path)?;
+        let mut lines = saved.lines();
+        if lines.next() != Some(HEADER) {
+            let message = format!("not a recording, expected it to start with {:?}", HEADER);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        }
+        let exchanges = lines
+            .enumerate()
+            .map(|(number, line)| {
+                Exchange::from_line(line).ok_or_else(|| {
+                    let message = format!("invalid exchange on line {}", number + 2);
+                    io::Error::new(io::ErrorKind::InvalidData, message)
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(Self::new(exchanges))
+    }
+
+    /// How many recorded responses have not been replayed yet
+    pub fn remaining(&self) -> usize {
+        self.exchanges
+            .lock()
+            .unwrap()
+            .values()
+            .map(VecDeque::len)
+            .sum()
+    }
+}
+
+/// Frames of a response being replayed
+type Chunk = <HyperBody as HttpBody>::Data;
+
+/// Body of a response being replayed, see [`Replay`]
+pub struct ReplayedBody {
+    response: Option<Chunk>,
+    trailers: Option<http::HeaderMap>,
+}
+
+impl HttpBody for ReplayedBody {
+    type Data = Chunk;
+    type Error = Infallible;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+    ) -> Poll<Option<Result<Chunk, Infallible>>> {
+        Poll::Ready(self.response.take().map(Ok))
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+    ) -> Poll<Result<Option<http::HeaderMap>, Infallible>> {
+        Poll::Ready(Ok(self.trailers.take()))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.response.is_none() && self.trailers.is_none()
+    }
+}
+
+impl GrpcService<BoxBody> for Replay {
+    type ResponseBody = ReplayedBody;
+    type Error = Infallible;
+    type Future = futures::future::Ready<Result<http::Response<ReplayedBody>, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        let path = request.uri().path();
+        let replayed = self
+            .exchanges
+            .lock()
+            .unwrap()
+            .get_mut(path)
+            .and_then(VecDeque::pop_front);
+        let exchange = replayed.unwrap_or_else(|| Exchange {
+            code: tonic::Code::Unimplemented,
+            message: format!("no recorded response left for {}", path),
+            ..Exchange::new(path)
+        });
+
+        let mut status = http::HeaderMap::new();
+        status.insert("grpc-status", http::HeaderValue::from(exchange.code as i32));
+        // Messages are percent-decoded, so only the escape character needs escaping
+        let message = exchange.message.replace('%', "%25");
+        if let Ok(message) = http::HeaderValue::from_str(&message) {
+            status.insert("grpc-message", message);
+        }
+        // Calls which failed without a response only had headers, carrying the status
+        if exchange.response.is_empty() {
+            let body = ReplayedBody {
+                response: None,
+                trailers: None,
+            };
+            let mut response = http::Response::new(body);
+            *response.headers_mut() = status;
+            return futures::future::ready(Ok(response));
+        }
+        let body = ReplayedBody {
+            response: Some(Chunk::from(exchange.response)),
+            trailers: Some(status),
+        };
+        futures::future::ready(Ok(http::Response::new(body)))
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    hex
+}
+
+fn unhex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// `message` with its `%XX` escapes decoded, as gRPC status messages are sent
+fn percent_decode(message: &str) -> String {
+    let bytes = message.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes.get(i + 1..i + 3).and_then(|hex| {
+            let hex = std::str::from_utf8(hex).ok()?;
+            u8::from_str_radix(hex, 16).ok()
+        });
+        match (bytes[i], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{etcdserver, mvccpb, EtcdClient, RetryPolicy};
+
+    /// A response to `path` carrying `message`
+    fn exchange<M: prost::Message>(path: &str, message: M) -> Exchange {
+        let mut encoded = Vec::new();
+        message.encode(&mut encoded).unwrap();
+        let mut response = vec![0];
+        response.extend(&(encoded.len() as u32).to_be_bytes());
+        response.extend(encoded);
+        Exchange {
+            response,
+            code: tonic::Code::Ok,
+            ..Exchange::new(path)
+        }
+    }
+
+    #[test]
+    fn test_saving_exchanges() {
+        let exchange = Exchange {
+            request: vec![0, 1, 2],
+            response: vec![255],
+            code: tonic::Code::Unavailable,
+            message: "etcdserver: no leader\twhile electing".into(),
+            ..Exchange::new("/etcdserverpb.KV/Range")
+        };
+        assert_eq!(Exchange::from_line(&exchange.to_line()), Some(exchange));
+        assert_eq!(Exchange::from_line("/etcdserverpb.KV/Range\t0\t\t0"), None);
+
+        assert_eq!(percent_decode("no%20leader%"), "no leader%");
+        assert_eq!(unhex("0aff"), Some(vec![10, 255]));
+        assert_eq!(unhex("0g"), None);
+    }
+
+    #[tokio::test]
+    async fn test_replaying() {
+        let header = Some(etcdserver::ResponseHeader {
+            revision: 2,
+            ..Default::default()
+        });
+        let kv = mvccpb::KeyValue {
+            key: b"foo".to_vec(),
+            value: b"bar".to_vec(),
+            ..Default::default()
+        };
+        let range = etcdserver::RangeResponse {
+            header: header.clone(),
+            kvs: vec![kv],
+            count: 1,
+            ..Default::default()
+        };
+        let future = Exchange {
+            code: tonic::Code::OutOfRange,
+            message: "etcdserver: mvcc: required revision is a future revision".into(),
+            ..Exchange::new("/etcdserverpb.KV/Range")
+        };
+        let recorded = vec![
+            exchange("/etcdserverpb.KV/Range", range),
+            future,
+            exchange(
+                "/etcdserverpb.KV/Put",
+                etcdserver::PutResponse {
+                    header,
+                    ..Default::default()
+                },
+            ),
+        ];
+        let path = std::env::temp_dir().join(format!("etcd3-replay-{}", std::process::id()));
+        let recorder = Recorder {
+            exchanges: Arc::new(Mutex::new(recorded)),
+        };
+        recorder.save(&path).unwrap();
+        let replay = Replay::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let client = EtcdClient::from_transport(replay.clone());
+        client.range("foo", None).put("bar").await.unwrap();
+        let values = client.range("foo", None).get().await.unwrap();
+        assert_eq!(values.kvs()[0].value(), b"bar");
+        let err = client.range("foo", None).get().await.unwrap_err();
+        assert!(matches!(err.root(), crate::Error::FutureRevision));
+        assert_eq!(replay.remaining(), 0);
+
+        let err = client.range("foo", None).delete().await.unwrap_err();
+        assert!(err.to_string().contains("no recorded response left"));
+    }
+
+    #[tokio::test]
+    async fn test_recording() {
+        // Nothing listens on port 1, so the request fails to be sent
+        let recorder = Recorder::new();
+        let client = EtcdClient::builder()
+            .endpoint("http://127.0.0.1:1")
+            .retry(RetryPolicy::disabled())
+            .connect_with_layer(recorder.clone())
+            .await
+            .unwrap();
+        assert!(client.range("foo", None).get().await.is_err());
+
+        let exchanges = recorder.exchanges();
+        assert_eq!(exchanges.len(), 1);
+        assert_eq!(exchanges[0].path, "/etcdserverpb.KV/Range");
+        assert!(!exchanges[0].message.is_empty());
+        assert_eq!(exchanges[0].code, tonic::Code::Unavailable);
+
+        // Replaying the failure fails the same way
+        let replay = EtcdClient::from_transport(Replay::new(exchanges));
+        assert!(replay.range("foo", None).get().await.is_err());
+    }
+}
@@ -0,0 +1,119 @@
+//! Redaction of the values stored in etcd from `Debug` output.
+//!
+//! Responses and handles print keys and values as text where they are UTF-8. Values often
+//! hold secrets, so logging a response could leak them: with [`set_value_redaction`] values
+//! are printed as their length, or their length and a hash, instead. The setting applies to
+//! the whole process, as `Debug` output is usually produced far from the client which read
+//! the values. Keys are always printed; leave them out of errors with
+//! [`EtcdClientBuilder::redact_keys`](crate::EtcdClientBuilder::redact_keys).
+
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// How values are printed by `Debug` implementations
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Redaction {
+    /// Print values in full, the default
+    #[default]
+    None,
+    /// Print only the length of values, such as `<12 bytes>`
+    Length,
+    /// Print the length of values and the start of their SHA-256 hash, such as
+    /// `<12 bytes, sha256 5e884898da28>`, so equal values can be told apart from different
+    /// ones
+    Hash,
+}
+
+static REDACTION: AtomicU8 = AtomicU8::new(0);
+
+/// Print values according to `redaction` from now on, in every client of the process
+pub fn set_value_redaction(redaction: Redaction) {
+    let redaction = match redaction {
+        Redaction::None => 0,
+        Redaction::Length => 1,
+        Redaction::Hash => 2,
+    };
+    REDACTION.store(redaction, Ordering::Relaxed);
+}
+
+/// How values are currently printed, see [`set_value_redaction`]
+pub fn value_redaction() -> Redaction {
+    match REDACTION.load(Ordering::Relaxed) {
+        0 => Redaction::None,
+        1 => Redaction::Length,
+        _ => Redaction::Hash,
+    }
+}
+
+/// Prints a key: as a string if it is UTF-8, otherwise as an escaped byte string
+pub(crate) struct Key<'a>(pub(crate) &'a [u8]);
+
+impl fmt::Debug for Key<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match std::str::from_utf8(self.0) {
+            Ok(key) => fmt::Debug::fmt(key, f),
+            Err(_) => {
+                f.write_str("b\"")?;
+                for &byte in self.0 {
+                    write!(f, "{}", std::ascii::escape_default(byte))?;
+                }
+                f.write_str("\"")
+            }
+        }
+    }
+}
+
+/// Prints a value like a [`Key`], unless values are redacted
+pub(crate) struct Value<'a>(pub(crate) &'a [u8]);
+
+impl fmt::Debug for Value<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match value_redaction() {
+            Redaction::None => Key(self.0).fmt(f),
+            Redaction::Length => write!(f, "<{} bytes>", self.0.len()),
+            Redaction::Hash => {
+                write!(f, "<{} bytes, sha256 ", self.0.len())?;
+                for byte in &Sha256::digest(self.0)[..6] {
+                    write!(f, "{:02x}", byte)?;
+                }
+                f.write_str(">")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redaction() {
+        assert_eq!(format!("{:?}", Key(b"foo")), "\"foo\"");
+        assert_eq!(format!("{:?}", Key(b"a\xff\n")), "b\"a\\xff\\n\"");
+
+        // Other tests print values, so every setting is checked in this one
+        assert_eq!(value_redaction(), Redaction::None);
+        assert_eq!(format!("{:?}", Value(b"secret")), "\"secret\"");
+        set_value_redaction(Redaction::Length);
+        assert_eq!(format!("{:?}", Value(b"secret")), "<6 bytes>");
+        let kv = crate::mvccpb::KeyValue {
+            key: b"password".to_vec(),
+            value: b"secret".to_vec(),
+            mod_revision: 3,
+            ..Default::default()
+        };
+        let kv = crate::KeyValue::new(kv, &[]);
+        assert_eq!(
+            format!("{:?}", kv),
+            "KeyValue { key: \"password\", value: <6 bytes>, create_revision: 0, \
+             mod_revision: 3, version: 0, lease: None }"
+        );
+        set_value_redaction(Redaction::Hash);
+        assert_eq!(
+            format!("{:?}", Value(b"secret")),
+            "<6 bytes, sha256 2bb80d537b1d>"
+        );
+        set_value_redaction(Redaction::None);
+    }
+}
@@ -0,0 +1,258 @@
+//! Channels to gRPC services registered in etcd, for the other tonic clients of a process.
+//!
+//! [`resolve`] discovers the instances registered under a service prefix, see [`discovery`],
+//! and returns a [`ResolvedChannel`] balancing calls across them. The channel follows the
+//! instances as they register and deregister, so it can be handed to any tonic client:
+//!
+//! ```no_run
+//! # async fn run(client: etcd3::EtcdClient<tonic::transport::Channel>) -> etcd3::EtcdResult<()> {
+//! // Each instance registers its address, such as "10.0.0.1:50051"
+//! let channel = etcd3::resolver::resolve(&client, "services/greeter/").await?;
+//! // let greeter = GreeterClient::new(channel);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`discovery`]: crate::discovery
+
+use crate::discovery::{self, Discovery};
+use crate::{runtime, EtcdClient, EtcdResult};
+use futures::channel::oneshot;
+use futures::future::{self, BoxFuture, Either, FutureExt};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::task::{Context, Poll};
+use tonic::body::BoxBody;
+use tonic::codegen::{HyperBody, Service, StdError};
+use tonic::transport::{Channel, Endpoint};
+
+/// Endpoint to reach the instance registered with `payload` at, or `None` to leave it out
+type ToEndpoint = dyn Fn(&str) -> Option<Endpoint> + Send + Sync;
+
+/// A channel to the instances of a service registered in etcd, see the
+/// [module documentation](self).
+///
+/// Clones share the instances, which are followed until the last clone is dropped. Calls
+/// made while no instance is registered fail.
+#[derive(Clone)]
+pub struct ResolvedChannel {
+    state: Arc<StdMutex<State>>,
+    _stop: Arc<oneshot::Sender<()>>,
+}
+
+struct State {
+    /// Address of each instance with a valid endpoint, by ID
+    addresses: BTreeMap<String, String>,
+    /// Channel balancing across the instances, if there are any
+    channel: Option<Channel>,
+    prefix: String,
+}
+
+impl fmt::Debug for ResolvedChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.lock();
+        f.debug_struct("ResolvedChannel")
+            .field("prefix", &state.prefix)
+            .field("addresses", &state.addresses)
+            .finish()
+    }
+}
+
+/// Resolve the instances registered under the service `prefix`, each with its address as its
+/// payload: either a URL, or a `host:port` reached over plain HTTP/2. Must be called from
+/// within a tokio runtime.
+///
+/// Instances whose address is not valid are left out. Should the instances stop being
+/// followed, the channel keeps balancing across the last ones resolved.
+pub async fn resolve<P>(client: &EtcdClient<Channel>, prefix: P) -> EtcdResult<ResolvedChannel>
+where
+    P: Into<Vec<u8>>,
+{
+    resolve_with(client, prefix, default_endpoint).await
+}
+
+/// Resolve the instances registered under the service `prefix` like [`resolve`], reaching
+/// each at the endpoint `endpoint` returns for its payload, for example to read addresses out
+/// of a richer payload or to set timeouts or TLS on the connections.
+pub async fn resolve_with<P, F>(
+    client: &EtcdClient<Channel>,
+    prefix: P,
+    endpoint: F,
+) -> EtcdResult<ResolvedChannel>
+where
+    P: Into<Vec<u8>>,
+    F: Fn(&str) -> Option<Endpoint> + Send + Sync + 'static,
+{
+    let prefix = prefix.into();
+    let mut discovery: Discovery<String> = discovery::discover(client, prefix.clone()).await?;
+    let mut state = State {
+        addresses: BTreeMap::new(),
+        channel: None,
+        prefix: String::from_utf8_lossy(&prefix).into_owned(),
+    };
+    state.update(discovery.instances(), &endpoint);
+    let state = Arc::new(StdMutex::new(state));
+
+    let (stop, mut stopped) = oneshot::channel();
+    let following = Arc::clone(&state);
+    runtime::spawn(async move {
+        let endpoint: Box<ToEndpoint> = Box::new(endpoint);
+        loop {
+            let changed = match future::select(&mut stopped, Box::pin(discovery.changed())).await {
+                Either::Left(_) => return,
+                Either::Right((changed, _)) => changed,
+            };
+            match changed {
+                Some(Ok(_)) => {
+                    let mut state = following.lock().unwrap_or_else(|e| e.into_inner());
+                    state.update(discovery.instances(), &endpoint);
+                }
+                // An instance with an undecodable payload keeps its previous one
+                Some(Err(_)) => {}
+                None => return,
+            }
+        }
+    });
+
+    Ok(ResolvedChannel {
+        state,
+        _stop: Arc::new(stop),
+    })
+}
+
+/// Endpoint for an instance registered with its URL or `host:port` address
+fn default_endpoint(address: &str) -> Option<Endpoint> {
+    let address = address.trim();
+    if address.starts_with("http://") || address.starts_with("https://") {
+        Channel::from_shared(address.to_string()).ok()
+    } else {
+        Channel::from_shared(format!("http://{}", address)).ok()
+    }
+}
+
+impl State {
+    /// Balance across `instances`, if their addresses changed
+    fn update(&mut self, instances: &BTreeMap<String, String>, endpoint: &ToEndpoint) {
+        let addresses: BTreeMap<_, _> = instances
+            .iter()
+            .filter(|(_, address)| endpoint(address).is_some())
+            .map(|(id, address)| (id.clone(), address.clone()))
+            .collect();
+        if addresses == self.addresses && (self.channel.is_some() || addresses.is_empty()) {
+            return;
+        }
+        // The transport's balanced channels cannot change their endpoints, so the channel is
+        // replaced, dialling every instance again
+        let endpoints: Vec<_> = addresses.values().filter_map(|a| endpoint(a)).collect();
+        self.channel = if endpoints.is_empty() {
+            None
+        } else {
+            Some(Channel::balance_list(endpoints.into_iter()))
+        };
+        self.addresses = addresses;
+    }
+}
+
+impl ResolvedChannel {
+    /// Addresses of the instances calls are currently balanced across, by instance ID
+    pub fn addresses(&self) -> BTreeMap<String, String> {
+        self.lock().addresses.clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Service<http::Request<BoxBody>> for ResolvedChannel {
+    type Response = http::Response<HyperBody>;
+    type Error = StdError;
+    type Future = BoxFuture<'static, Result<Self::Response, StdError>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), StdError>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        // The channel may be replaced between readiness and the call, so each call waits for
+        // the readiness of the channel it is sent on
+        let (channel, prefix) = {
+            let state = self.lock();
+            (state.channel.clone(), state.prefix.clone())
+        };
+        async move {
+            let mut channel = match channel {
+                Some(channel) => channel,
+                None => return Err(format!("no instances registered under {:?}", prefix).into()),
+            };
+            future::poll_fn(|cx| tonic::client::GrpcService::poll_ready(&mut channel, cx))
+                .await
+                .map_err(StdError::from)?;
+            let response = tonic::client::GrpcService::call(&mut channel, request).await?;
+            Ok(response)
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::concurrency::Session;
+    use crate::{client, etcdserver};
+
+    #[tokio::test]
+    async fn test_updating_endpoints() {
+        let mut state = State {
+            addresses: BTreeMap::new(),
+            channel: None,
+            prefix: "svc/".to_string(),
+        };
+        let mut instances = BTreeMap::new();
+        instances.insert("a".to_string(), "10.0.0.1:50051".to_string());
+        instances.insert("b".to_string(), "https://greeter:443".to_string());
+        instances.insert("c".to_string(), "not an address".to_string());
+        state.update(&instances, &default_endpoint);
+        assert_eq!(state.addresses.len(), 2);
+        assert!(!state.addresses.contains_key("c"));
+        assert!(state.channel.is_some());
+
+        state.update(&BTreeMap::new(), &default_endpoint);
+        assert!(state.addresses.is_empty());
+        assert!(state.channel.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_resolving_instances() {
+        let client = crate::testing::namespace().await.unwrap();
+        let session = Session::new(&client, 5).await.unwrap();
+        let prefix = "test_resolving_instances/";
+
+        let channel = resolve(&client, prefix).await.unwrap();
+        let mut kv_client = client::KvClient::new(channel.clone());
+        let request = etcdserver::RangeRequest {
+            key: b"test_resolving_instances".to_vec(),
+            ..Default::default()
+        };
+        assert!(kv_client.range(request.clone()).await.is_err());
+
+        // etcd is a gRPC service like any other, so the instance registered is etcd itself
+        let address = crate::testing::shared_endpoint().to_string();
+        let registration = discovery::register(&session, prefix, "etcd", &address)
+            .await
+            .unwrap();
+        while channel.addresses().is_empty() {
+            runtime::delay_for(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(channel.addresses()["etcd"], address);
+        kv_client.range(request.clone()).await.unwrap();
+
+        registration.deregister().await.unwrap();
+        while !channel.addresses().is_empty() {
+            runtime::delay_for(std::time::Duration::from_millis(10)).await;
+        }
+        assert!(kv_client.range(request).await.is_err());
+        session.close().await.unwrap();
+    }
+}
@@ -0,0 +1,673 @@
+//! Responses to KV, transaction and watch operations.
+//!
+//! These wrap the messages the server sends, exposing what callers need through accessors.
+//! Keys are given outside of the client's [namespace](crate::EtcdClient::namespaced).
+
+use crate::redact::{Key, Value};
+use crate::{etcdserver, mvccpb, strip_namespace, Error, EtcdResult, EventKind};
+use bytes::Bytes;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// A key and its value, as of the revision it was read at. `Debug` prints the value unless
+/// values are [redacted](crate::redact).
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+// Serialized as the message it wraps
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct KeyValue {
+    inner: mvccpb::KeyValue,
+}
+
+impl KeyValue {
+    /// `kv` from the server, with its key outside of `namespace`
+    pub(crate) fn new(mut kv: mvccpb::KeyValue, namespace: &[u8]) -> Self {
+        if !namespace.is_empty() {
+            // Stripped in place, as the key's buffer is reused
+            let stripped = strip_namespace(namespace, &kv.key).len();
+            kv.key.drain(..kv.key.len() - stripped);
+        }
+        Self { inner: kv }
+    }
+
+    /// The key
+    pub fn key(&self) -> &[u8] {
+        &self.inner.key
+    }
+
+    /// The key, failing if it is not valid UTF-8
+    pub fn key_str(&self) -> EtcdResult<&str> {
+        Ok(std::str::from_utf8(&self.inner.key)?)
+    }
+
+    /// The value
+    pub fn value(&self) -> &[u8] {
+        &self.inner.value
+    }
+
+    /// The value, failing if it is not valid UTF-8
+    pub fn value_str(&self) -> EtcdResult<&str> {
+        Ok(std::str::from_utf8(&self.inner.value)?)
+    }
+
+    /// Revision the key was last created at
+    pub fn create_revision(&self) -> i64 {
+        self.inner.create_revision
+    }
+
+    /// Revision the key was last modified at
+    pub fn mod_revision(&self) -> i64 {
+        self.inner.mod_revision
+    }
+
+    /// Number of times the key has been modified since it was created
+    pub fn version(&self) -> i64 {
+        self.inner.version
+    }
+
+    /// ID of the lease the key is attached to, if any
+    pub fn lease(&self) -> Option<i64> {
+        match self.inner.lease {
+            0 => None,
+            lease => Some(lease),
+        }
+    }
+
+    /// The key and the value
+    pub fn into_key_value(self) -> (Vec<u8>, Vec<u8>) {
+        (self.inner.key, self.inner.value)
+    }
+
+    /// The value, taking over the buffer it was decoded into rather than copying it
+    pub fn into_value(self) -> Bytes {
+        Bytes::from(self.inner.value)
+    }
+
+    /// The value as a string, taking over the buffer it was decoded into rather than copying
+    /// it, failing if it is not valid UTF-8
+    pub fn into_value_string(self) -> EtcdResult<String> {
+        Ok(String::from_utf8(self.inner.value)?)
+    }
+}
+
+impl fmt::Debug for KeyValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyValue")
+            .field("key", &Key(self.key()))
+            .field("value", &Value(self.value()))
+            .field("create_revision", &self.create_revision())
+            .field("mod_revision", &self.mod_revision())
+            .field("version", &self.version())
+            .field("lease", &self.lease())
+            .finish()
+    }
+}
+
+/// Revision of the store as of the response carrying `header`
+fn revision(header: Option<etcdserver::ResponseHeader>, response: &str) -> EtcdResult<i64> {
+    crate::concurrency::read_revision(header, response)
+}
+
+/// Revision of the store as of an operation, for later reads to be at least as fresh as it.
+///
+/// Serializable reads are served by whichever member the client reaches, which may not have
+/// applied a write made moments before. Reads given the token of the write with
+/// [`Range::min_revision`](crate::Range::min_revision) see it, without every read having to
+/// be linearizable. Tokens print as the revision and parse back from it, to be handed to
+/// other processes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct ConsistencyToken(i64);
+
+impl ConsistencyToken {
+    /// Token of the store as of `revision`
+    pub fn new(revision: i64) -> Self {
+        Self(revision)
+    }
+
+    /// Revision of the store the token stands for
+    pub fn revision(self) -> i64 {
+        self.0
+    }
+
+    /// Take in `other`, so this token is at least as fresh as both, as for keeping track of
+    /// the writes made in a session
+    pub fn observe(&mut self, other: ConsistencyToken) {
+        self.0 = self.0.max(other.0);
+    }
+}
+
+impl fmt::Display for ConsistencyToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::str::FromStr for ConsistencyToken {
+    type Err = Error;
+
+    fn from_str(s: &str) -> EtcdResult<Self> {
+        match s.parse() {
+            Ok(revision) if revision >= 0 => Ok(Self(revision)),
+            _ => Err(format!("invalid consistency token {:?}", s).into()),
+        }
+    }
+}
+
+/// Response to a put
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PutResponse {
+    revision: i64,
+    prev_kv: Option<KeyValue>,
+}
+
+impl PutResponse {
+    /// `response` from the server, made at `revision`, with keys outside of `namespace`
+    fn new(response: etcdserver::PutResponse, revision: i64, namespace: &[u8]) -> Self {
+        Self {
+            revision,
+            prev_kv: response.prev_kv.map(|kv| KeyValue::new(kv, namespace)),
+        }
+    }
+
+    pub(crate) fn from_raw(
+        response: etcdserver::PutResponse,
+        namespace: &[u8],
+    ) -> EtcdResult<Self> {
+        let revision = revision(response.header.clone(), "put")?;
+        Ok(Self::new(response, revision, namespace))
+    }
+
+    /// Revision of the store once the key was put
+    pub fn revision(&self) -> i64 {
+        self.revision
+    }
+
+    /// Token of the store once the key was put, for reads which must see it
+    pub fn token(&self) -> ConsistencyToken {
+        ConsistencyToken(self.revision)
+    }
+
+    /// The key as it was before it was put, if it existed and was asked for with
+    /// [`PutOptions::prev_kv`](crate::PutOptions::prev_kv)
+    pub fn prev_kv(&self) -> Option<&KeyValue> {
+        self.prev_kv.as_ref()
+    }
+}
+
+/// Response to a get
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GetResponse {
+    revision: i64,
+    kvs: Vec<KeyValue>,
+    more: bool,
+    count: i64,
+}
+
+impl GetResponse {
+    /// `response` from the server, made at `revision`, with keys outside of `namespace`
+    fn new(response: etcdserver::RangeResponse, revision: i64, namespace: &[u8]) -> Self {
+        let kvs = response.kvs.into_iter();
+        Self {
+            revision,
+            kvs: kvs.map(|kv| KeyValue::new(kv, namespace)).collect(),
+            more: response.more,
+            count: response.count,
+        }
+    }
+
+    pub(crate) fn from_raw(
+        response: etcdserver::RangeResponse,
+        namespace: &[u8],
+    ) -> EtcdResult<Self> {
+        let revision = revision(response.header.clone(), "range")?;
+        Ok(Self::new(response, revision, namespace))
+    }
+
+    /// Revision of the store the keys were read at
+    pub fn revision(&self) -> i64 {
+        self.revision
+    }
+
+    /// Token of the store the keys were read at, for later reads which must be no older
+    pub fn token(&self) -> ConsistencyToken {
+        ConsistencyToken(self.revision)
+    }
+
+    /// The keys read, in key order
+    pub fn kvs(&self) -> &[KeyValue] {
+        &self.kvs
+    }
+
+    /// The keys read, in key order
+    pub fn into_kvs(self) -> Vec<KeyValue> {
+        self.kvs
+    }
+
+    /// The keys read, in key order, for collecting into whichever collection the caller needs
+    pub fn iter(&self) -> std::slice::Iter<'_, KeyValue> {
+        self.kvs.iter()
+    }
+
+    /// The key `key`, if it was read
+    pub fn get(&self, key: &[u8]) -> Option<&KeyValue> {
+        self.kvs.iter().find(|kv| kv.key() == key)
+    }
+
+    /// Number of keys read
+    pub fn len(&self) -> usize {
+        self.kvs.len()
+    }
+
+    /// Whether no keys were read
+    pub fn is_empty(&self) -> bool {
+        self.kvs.is_empty()
+    }
+
+    /// Whether there are more keys in the range than were read, because of a limit
+    pub fn more(&self) -> bool {
+        self.more
+    }
+
+    /// Number of keys in the range, including any not read because of a limit
+    pub fn count(&self) -> i64 {
+        self.count
+    }
+}
+
+impl IntoIterator for GetResponse {
+    type Item = KeyValue;
+    type IntoIter = std::vec::IntoIter<KeyValue>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.kvs.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a GetResponse {
+    type Item = &'a KeyValue;
+    type IntoIter = std::slice::Iter<'a, KeyValue>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.kvs.iter()
+    }
+}
+
+/// Response to a delete
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeleteResponse {
+    revision: i64,
+    deleted: i64,
+    prev_kvs: Vec<KeyValue>,
+}
+
+impl DeleteResponse {
+    /// `response` from the server, made at `revision`, with keys outside of `namespace`
+    fn new(response: etcdserver::DeleteRangeResponse, revision: i64, namespace: &[u8]) -> Self {
+        let prev_kvs = response.prev_kvs.into_iter();
+        Self {
+            revision,
+            deleted: response.deleted,
+            prev_kvs: prev_kvs.map(|kv| KeyValue::new(kv, namespace)).collect(),
+        }
+    }
+
+    pub(crate) fn from_raw(
+        response: etcdserver::DeleteRangeResponse,
+        namespace: &[u8],
+    ) -> EtcdResult<Self> {
+        let revision = revision(response.header.clone(), "delete range")?;
+        Ok(Self::new(response, revision, namespace))
+    }
+
+    /// Revision of the store once the keys were deleted
+    pub fn revision(&self) -> i64 {
+        self.revision
+    }
+
+    /// Token of the store once the keys were deleted, for reads which must see it
+    pub fn token(&self) -> ConsistencyToken {
+        ConsistencyToken(self.revision)
+    }
+
+    /// Number of keys deleted
+    pub fn deleted(&self) -> i64 {
+        self.deleted
+    }
+
+    /// The keys as they were before they were deleted, if they were asked for
+    pub fn prev_kvs(&self) -> &[KeyValue] {
+        &self.prev_kvs
+    }
+}
+
+/// Response to a transaction
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TxnResponse {
+    revision: i64,
+    succeeded: bool,
+    responses: Vec<TxnOpResponse>,
+}
+
+/// Response to one of the operations of a transaction
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TxnOpResponse {
+    Put(PutResponse),
+    Get(GetResponse),
+    Delete(DeleteResponse),
+    Txn(TxnResponse),
+}
+
+impl TxnResponse {
+    /// `response` from the server, made at `revision`, with keys outside of `namespace`
+    fn new(response: etcdserver::TxnResponse, revision: i64, namespace: &[u8]) -> EtcdResult<Self> {
+        use etcdserver::response_op::Response;
+
+        let mut responses = Vec::new();
+        for op in response.responses {
+            // Operations within a transaction all see the revision the transaction made
+            let op = match op.response {
+                Some(Response::ResponsePut(put)) => {
+                    TxnOpResponse::Put(PutResponse::new(put, revision, namespace))
+                }
+                Some(Response::ResponseRange(range)) => {
+                    TxnOpResponse::Get(GetResponse::new(range, revision, namespace))
+                }
+                Some(Response::ResponseDeleteRange(delete)) => {
+                    TxnOpResponse::Delete(DeleteResponse::new(delete, revision, namespace))
+                }
+                Some(Response::ResponseTxn(txn)) => {
+                    TxnOpResponse::Txn(TxnResponse::new(txn, revision, namespace)?)
+                }
+                None => {
+                    let missing = "txn response is missing the response to an operation";
+                    return Err(Error::invalid_response(missing));
+                }
+            };
+            responses.push(op);
+        }
+        Ok(Self {
+            revision,
+            succeeded: response.succeeded,
+            responses,
+        })
+    }
+
+    pub(crate) fn from_raw(
+        response: etcdserver::TxnResponse,
+        namespace: &[u8],
+    ) -> EtcdResult<Self> {
+        let revision = revision(response.header.clone(), "txn")?;
+        Self::new(response, revision, namespace)
+    }
+
+    /// Revision of the store once the transaction was applied
+    pub fn revision(&self) -> i64 {
+        self.revision
+    }
+
+    /// Token of the store once the transaction was applied, for reads which must see it
+    pub fn token(&self) -> ConsistencyToken {
+        ConsistencyToken(self.revision)
+    }
+
+    /// Whether every comparison held, so the success operations were applied rather than the
+    /// failure ones
+    pub fn succeeded(&self) -> bool {
+        self.succeeded
+    }
+
+    /// Responses to the operations applied, in order
+    pub fn responses(&self) -> &[TxnOpResponse] {
+        &self.responses
+    }
+}
+
+/// A change to a key, reported by a watch
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WatchEvent {
+    kind: EventKind,
+    kv: KeyValue,
+    prev_kv: Option<KeyValue>,
+}
+
+impl WatchEvent {
+    /// `event` from the server, with keys outside of `namespace`
+    pub(crate) fn new(event: mvccpb::Event, namespace: &[u8]) -> EtcdResult<Self> {
+        let kind = EventKind::try_from(event.r#type)?;
+        let kv = match event.kv {
+            Some(kv) => KeyValue::new(kv, namespace),
+            None => return Err(Error::invalid_response("watch event is missing its key")),
+        };
+        Ok(Self {
+            kind,
+            kv,
+            prev_kv: event.prev_kv.map(|kv| KeyValue::new(kv, namespace)),
+        })
+    }
+
+    /// Whether the key was put or deleted
+    pub fn kind(&self) -> EventKind {
+        self.kind
+    }
+
+    /// The key once changed. Deleted keys have only their key and the revision they were
+    /// deleted at.
+    pub fn kv(&self) -> &KeyValue {
+        &self.kv
+    }
+
+    /// The key as it was before the change, if it existed and was asked for
+    pub fn prev_kv(&self) -> Option<&KeyValue> {
+        self.prev_kv.as_ref()
+    }
+}
+
+/// A batch of changes reported by a watch
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WatchResponse {
+    revision: i64,
+    events: Vec<WatchEvent>,
+    canceled: bool,
+    cancel_reason: String,
+    compact_revision: i64,
+}
+
+impl WatchResponse {
+    /// `response` from the server, with keys outside of `namespace`
+    pub(crate) fn from_raw(
+        response: etcdserver::WatchResponse,
+        namespace: &[u8],
+    ) -> EtcdResult<Self> {
+        let revision = revision(response.header, "watch")?;
+        let events = response.events.into_iter();
+        Ok(Self {
+            revision,
+            events: events
+                .map(|event| WatchEvent::new(event, namespace))
+                .collect::<EtcdResult<_>>()?,
+            canceled: response.canceled,
+            cancel_reason: response.cancel_reason,
+            compact_revision: response.compact_revision,
+        })
+    }
+
+    /// Revision of the store as of the response
+    pub fn revision(&self) -> i64 {
+        self.revision
+    }
+
+    /// The changes, in the order they were made
+    pub fn events(&self) -> &[WatchEvent] {
+        &self.events
+    }
+
+    /// The changes, in the order they were made
+    pub fn into_events(self) -> Vec<WatchEvent> {
+        self.events
+    }
+
+    /// Add the changes of `later`, a response which came after this one
+    pub(crate) fn merge(&mut self, later: WatchResponse) {
+        self.revision = later.revision;
+        self.events.extend(later.events);
+    }
+
+    /// Whether the server cancelled the watch, see [`cancel_reason`](Self::cancel_reason)
+    pub fn is_canceled(&self) -> bool {
+        self.canceled
+    }
+
+    /// Why the server cancelled the watch, if it says
+    pub fn cancel_reason(&self) -> &str {
+        &self.cancel_reason
+    }
+
+    /// The revision history has been compacted up to, if the watch was cancelled because the
+    /// revision it started from had been compacted. Zero otherwise.
+    pub fn compact_revision(&self) -> i64 {
+        self.compact_revision
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(revision: i64) -> Option<etcdserver::ResponseHeader> {
+        Some(etcdserver::ResponseHeader {
+            revision,
+            ..Default::default()
+        })
+    }
+
+    fn kv(key: &str, value: &str) -> mvccpb::KeyValue {
+        mvccpb::KeyValue {
+            key: key.into(),
+            value: value.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_responses() {
+        let get = etcdserver::RangeResponse {
+            header: header(7),
+            kvs: vec![kv("app/a", "1"), kv("app/b", "2")],
+            count: 3,
+            more: true,
+        };
+        let get = GetResponse::from_raw(get, b"app/").unwrap();
+        assert_eq!(get.revision(), 7);
+        assert_eq!((get.count(), get.more()), (3, true));
+        assert_eq!(get.get(b"b").unwrap().value_str().unwrap(), "2");
+        assert_eq!(get.kvs()[0].key(), b"a");
+        assert_eq!(get.kvs()[0].lease(), None);
+        let keys: Vec<_> = get.iter().map(KeyValue::key).collect();
+        assert_eq!(keys, vec![b"a", b"b"]);
+        let b = get.clone().into_iter().nth(1).unwrap();
+        assert_eq!(b.clone().into_value(), Bytes::from_static(b"2"));
+        assert_eq!(b.into_value_string().unwrap(), "2");
+
+        let put = etcdserver::PutResponse {
+            header: header(8),
+            prev_kv: Some(kv("app/a", "1")),
+        };
+        let put = PutResponse::from_raw(put, b"app/").unwrap();
+        assert_eq!(put.revision(), 8);
+        assert_eq!(put.prev_kv().unwrap().key(), b"a");
+
+        // Responses without a header have no revision to report
+        let delete = etcdserver::DeleteRangeResponse::default();
+        assert!(DeleteResponse::from_raw(delete, b"").is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serializing() {
+        let get = etcdserver::RangeResponse {
+            header: header(7),
+            kvs: vec![kv("a", "1")],
+            count: 1,
+            more: false,
+        };
+        let get = GetResponse::from_raw(get, b"").unwrap();
+        let json = serde_json::to_value(&get).unwrap();
+        assert_eq!(json["revision"], 7);
+        // Keys and values are the wrapped messages' bytes
+        assert_eq!(json["kvs"][0]["key"], serde_json::json!([b'a']));
+        let back: GetResponse = serde_json::from_value(json).unwrap();
+        assert_eq!(back, get);
+
+        let kind = serde_json::to_string(&EventKind::Delete).unwrap();
+        assert_eq!(kind, r#""Delete""#);
+    }
+
+    #[test]
+    fn test_txn_responses() {
+        use etcdserver::response_op::Response;
+
+        let op = |response| etcdserver::ResponseOp {
+            response: Some(response),
+        };
+        let txn = etcdserver::TxnResponse {
+            header: header(9),
+            succeeded: true,
+            responses: vec![
+                op(Response::ResponsePut(Default::default())),
+                op(Response::ResponseDeleteRange(
+                    etcdserver::DeleteRangeResponse {
+                        deleted: 2,
+                        ..Default::default()
+                    },
+                )),
+            ],
+        };
+        let txn = TxnResponse::from_raw(txn, b"").unwrap();
+        assert!(txn.succeeded());
+        match txn.responses() {
+            [TxnOpResponse::Put(put), TxnOpResponse::Delete(delete)] => {
+                assert_eq!(put.revision(), 9);
+                assert_eq!(delete.deleted(), 2);
+            }
+            responses => panic!("unexpected responses {:?}", responses),
+        }
+
+        let txn = etcdserver::TxnResponse {
+            header: header(9),
+            responses: vec![etcdserver::ResponseOp { response: None }],
+            ..Default::default()
+        };
+        assert!(TxnResponse::from_raw(txn, b"").is_err());
+    }
+
+    #[test]
+    fn test_watch_events() {
+        let event = mvccpb::Event {
+            r#type: mvccpb::event::EventType::Delete as i32,
+            kv: Some(kv("app/foo", "")),
+            prev_kv: Some(kv("app/foo", "bar")),
+        };
+        let event = WatchEvent::new(event, b"app/").unwrap();
+        assert_eq!(event.kind(), EventKind::Delete);
+        assert_eq!(event.kv().key(), b"foo");
+        assert_eq!(event.prev_kv().unwrap().value(), b"bar");
+
+        let unknown = mvccpb::Event {
+            r#type: 7,
+            kv: Some(kv("foo", "")),
+            prev_kv: None,
+        };
+        assert!(WatchEvent::new(unknown, b"").is_err());
+        let missing = mvccpb::Event::default();
+        assert!(WatchEvent::new(missing, b"").is_err());
+    }
+}
@@ -0,0 +1,256 @@
+//! The async runtime the client spawns its background tasks on and takes its timers from.
+//!
+//! Keep-alives, watches, health checks, retries and timeouts all need an executor and a
+//! timer. They get them from the [`Runtime`] installed with [`set_runtime`], which is
+//! [`Tokio`] unless another one is installed, so the client can run on `async-std`, `smol` or
+//! a custom executor. The transport's own [`Channel`](tonic::transport::channel::Channel)
+//! still needs tokio, so clients on other runtimes send their requests over a transport of
+//! their own, see [`EtcdClient::from_transport`](crate::EtcdClient::from_transport).
+//! Endpoint discovery through SRV records, unix socket and proxy endpoints and the
+//! blocking client use tokio's IO and are only available with tokio.
+
+use futures::future::{self, BoxFuture, Either};
+use futures::Stream;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::OnceLock;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+/// An executor and timer for the client's background tasks and timeouts
+pub trait Runtime: Send + Sync + 'static {
+    /// Run `task` in the background. Fails if it cannot be run, such as when there is no
+    /// executor to run it on.
+    fn spawn(&self, task: BoxFuture<'static, ()>) -> io::Result<()>;
+
+    /// A future which completes once `deadline` has passed
+    fn delay_until(&self, deadline: Instant) -> Delay;
+
+    /// Run the blocking `task`, such as file IO, where it does not hold up the executor. Runs
+    /// it on a thread of its own unless the runtime has a pool of threads for blocking work.
+    fn spawn_blocking(&self, task: Box<dyn FnOnce() + Send>) -> io::Result<()> {
+        std::thread::Builder::new().spawn(task).map(drop)
+    }
+}
+
+/// A future which completes once a deadline has passed, see [`Runtime::delay_until`]. It is
+/// `Sync` as timers are held across the streams of requests sent to the server.
+pub type Delay = Pin<Box<dyn Future<Output = ()> + Send + Sync>>;
+
+/// tokio's default executor and timer, the runtime used unless another one is installed
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Tokio;
+
+impl Runtime for Tokio {
+    fn spawn(&self, task: BoxFuture<'static, ()>) -> io::Result<()> {
+        use tokio::executor::{DefaultExecutor, Executor};
+        DefaultExecutor::current()
+            .spawn(task)
+            .map_err(|e| io::Error::other(format!("{:?}", e)))
+    }
+
+    fn delay_until(&self, deadline: Instant) -> Delay {
+        Box::pin(tokio::timer::delay(deadline))
+    }
+
+    fn spawn_blocking(&self, task: Box<dyn FnOnce() + Send>) -> io::Result<()> {
+        // The task is queued on the pool whether or not its result is waited for
+        drop(tokio_executor::blocking::run(task));
+        Ok(())
+    }
+}
+
+static RUNTIME: OnceLock<Box<dyn Runtime>> = OnceLock::new();
+
+/// Install `runtime` for every client in the process. Must be called before any client is
+/// created, and fails if a runtime was already installed or the default one already used.
+pub fn set_runtime<R>(runtime: R) -> Result<(), R>
+where
+    R: Runtime,
+{
+    let mut runtime = Some(runtime);
+    RUNTIME.get_or_init(|| Box::new(runtime.take().unwrap()));
+    match runtime {
+        Some(runtime) => Err(runtime),
+        None => Ok(()),
+    }
+}
+
+/// The installed runtime
+fn current() -> &'static dyn Runtime {
+    RUNTIME.get_or_init(|| Box::new(Tokio)).as_ref()
+}
+
+/// Run `task` in the background. Panics if it cannot be run, like `tokio::spawn`.
+pub(crate) fn spawn<F>(task: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    if let Err(e) = current().spawn(Box::pin(task)) {
+        panic!("failed to spawn a task on the runtime: {}", e);
+    }
+}
+
+/// Run `task` in the background if possible, for cleanup which may happen outside of any
+/// runtime
+pub(crate) fn try_spawn<F>(task: F) -> io::Result<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    current().spawn(Box::pin(task))
+}
+
+/// A future which completes once `deadline` has passed
+pub(crate) fn delay_until(deadline: Instant) -> Delay {
+    current().delay_until(deadline)
+}
+
+/// A future which completes after `duration`
+pub(crate) fn delay_for(duration: Duration) -> Delay {
+    delay_until(Instant::now() + duration)
+}
+
+/// Run the blocking `f`, such as hashing a large file, where it does not hold up the executor
+/// and wait for its result. Panics if it cannot be run, and with `f`'s panic if it panics.
+#[cfg(feature = "maintenance")]
+pub(crate) async fn blocking<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let (tx, rx) = futures::channel::oneshot::channel();
+    let task = move || {
+        let _ = tx.send(std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)));
+    };
+    if let Err(e) = current().spawn_blocking(Box::new(task)) {
+        panic!("failed to run a blocking task on the runtime: {}", e);
+    }
+    match rx.await {
+        Ok(Ok(output)) => output,
+        Ok(Err(panic)) => std::panic::resume_unwind(panic),
+        Err(_) => panic!("the runtime dropped a blocking task without running it"),
+    }
+}
+
+/// A timeout elapsed before the future it bounds completed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Elapsed;
+
+/// `future`'s output, unless it does not complete before `deadline`
+pub(crate) async fn timeout_at<F>(future: F, deadline: Instant) -> Result<F::Output, Elapsed>
+where
+    F: Future,
+{
+    futures::pin_mut!(future);
+    match future::select(future, delay_until(deadline)).await {
+        Either::Left((output, _)) => Ok(output),
+        Either::Right(_) => Err(Elapsed),
+    }
+}
+
+/// `future`'s output, unless it does not complete within `timeout`
+pub(crate) async fn timeout<F>(future: F, timeout: Duration) -> Result<F::Output, Elapsed>
+where
+    F: Future,
+{
+    timeout_at(future, Instant::now() + timeout).await
+}
+
+/// Ticks every `period`, starting one period from now. Ticks which were missed because the
+/// stream was not polled in time are made up for straight away.
+pub(crate) struct Interval {
+    next: Instant,
+    period: Duration,
+    delay: Delay,
+    delay_until: Box<dyn Fn(Instant) -> Delay + Send + Sync>,
+}
+
+impl Interval {
+    pub(crate) fn new(period: Duration) -> Self {
+        Self::with(period, Instant::now(), delay_until)
+    }
+
+    /// Ticks every `period` from `now`, waiting for each with `delay_until`
+    pub(crate) fn with<F>(period: Duration, now: Instant, delay_until: F) -> Self
+    where
+        F: Fn(Instant) -> Delay + Send + Sync + 'static,
+    {
+        let next = now + period;
+        Interval {
+            next,
+            period,
+            delay: delay_until(next),
+            delay_until: Box::new(delay_until),
+        }
+    }
+
+    /// The next tick
+    pub(crate) async fn next(&mut self) -> Option<Instant> {
+        futures::StreamExt::next(self).await
+    }
+}
+
+impl Stream for Interval {
+    type Item = Instant;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Instant>> {
+        if self.delay.as_mut().poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+        let tick = self.next;
+        self.next = tick + self.period;
+        self.delay = (self.delay_until)(self.next);
+        Poll::Ready(Some(tick))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_timers() {
+        assert_eq!(timeout(async { 1 }, Duration::from_secs(1)).await, Ok(1));
+        let never = future::pending::<()>();
+        assert_eq!(
+            timeout(never, Duration::from_millis(10)).await,
+            Err(Elapsed)
+        );
+
+        let started = Instant::now();
+        let mut ticks = Interval::new(Duration::from_millis(20));
+        let first = ticks.next().await.unwrap();
+        let second = ticks.next().await.unwrap();
+        assert!(first >= started + Duration::from_millis(20));
+        assert_eq!(second - first, Duration::from_millis(20));
+        assert!(Instant::now() >= second);
+    }
+
+    #[tokio::test]
+    async fn test_spawning() {
+        let (tx, rx) = futures::channel::oneshot::channel();
+        spawn(async move {
+            delay_for(Duration::from_millis(1)).await;
+            let _ = tx.send(());
+        });
+        rx.await.unwrap();
+        // The default runtime is already in use
+        assert!(set_runtime(Tokio).is_err());
+    }
+
+    #[cfg(feature = "maintenance")]
+    #[tokio::test]
+    async fn test_blocking() {
+        use futures::FutureExt;
+
+        let caller = std::thread::current().id();
+        let ran_on = blocking(|| std::thread::current().id()).await;
+        assert_ne!(ran_on, caller);
+
+        let panicking = blocking(|| panic!("hashing failed"));
+        let panicked = std::panic::AssertUnwindSafe(panicking).catch_unwind().await;
+        let payload = panicked.unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"hashing failed"));
+    }
+}
@@ -0,0 +1,309 @@
+//! Rules the keys and values a client writes must follow, checked before they are sent.
+//!
+//! Teams sharing a cluster can agree on where their keys live and what they look like, and
+//! enforce it in every client with [`EtcdClientBuilder::key_schema`]. A [`KeySchema`] allows
+//! the keys under the prefixes of its [`KeyRule`]s, each bounding the depth, characters and
+//! size of the keys under it and the size of their values. Puts, including those in
+//! transactions, which break a rule fail with [`Error::SchemaViolation`] without reaching the
+//! cluster:
+//!
+//! ```
+//! use etcd3::schema::{Charset, KeyRule, KeySchema, Violation};
+//!
+//! let schema = KeySchema::new()
+//!     .rule(KeyRule {
+//!         prefix: b"payments/".to_vec(),
+//!         max_depth: Some(3),
+//!         charset: Charset::AlphanumericAnd("-_/".into()),
+//!         max_value_size: Some(64 * 1024),
+//!         ..Default::default()
+//!     })
+//!     .rule(KeyRule::new("search/"));
+//! assert!(schema.check(b"payments/invoices/2024-01", b"{}").is_ok());
+//! assert!(matches!(
+//!     schema.check(b"payments/invoices/2024/01", b"{}"),
+//!     Err(Violation::Depth { depth: 4, max: 3, .. })
+//! ));
+//! assert!(matches!(schema.check(b"tmp/foo", b""), Err(Violation::Prefix { .. })));
+//! ```
+//!
+//! Keys are checked as they are stored, including the namespace of a
+//! [namespaced](crate::EtcdClient::namespaced) client. Deletes are not checked, so keys
+//! written before the schema was adopted can still be cleaned up, and neither are keys the
+//! recipes write for themselves, such as those of locks and elections.
+//!
+//! [`EtcdClientBuilder::key_schema`]: crate::EtcdClientBuilder::key_schema
+//! [`Error::SchemaViolation`]: crate::Error::SchemaViolation
+
+use crate::etcdserver;
+
+/// Which characters keys may be made of
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Charset {
+    /// Any bytes
+    #[default]
+    Any,
+    /// Valid UTF-8
+    Utf8,
+    /// ASCII letters and digits, and the characters given
+    AlphanumericAnd(String),
+}
+
+impl Charset {
+    /// Index of the first byte of `key` outside the charset, if there is one
+    fn first_invalid(&self, key: &[u8]) -> Option<usize> {
+        match self {
+            Charset::Any => None,
+            Charset::Utf8 => std::str::from_utf8(key).err().map(|e| e.valid_up_to()),
+            Charset::AlphanumericAnd(others) => key.iter().position(|&byte| {
+                !byte.is_ascii_alphanumeric() && !others.as_bytes().contains(&byte)
+            }),
+        }
+    }
+}
+
+/// What the keys under a prefix and their values must look like
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyRule {
+    /// Keys the rule allows and applies to. Empty for every key.
+    pub prefix: Vec<u8>,
+    /// Most `/` separated segments a key may have, counting those of the prefix, so that
+    /// `a/b/c` has a depth of 3. Unbounded if `None`.
+    pub max_depth: Option<usize>,
+    /// Which characters keys may be made of. Any bytes by default.
+    pub charset: Charset,
+    /// Most bytes a key may have. Unbounded if `None`.
+    pub max_key_size: Option<usize>,
+    /// Most bytes a value may have. Unbounded if `None`, leaving it to the cluster's request
+    /// size limit.
+    pub max_value_size: Option<usize>,
+}
+
+impl KeyRule {
+    /// A rule allowing every key under `prefix`, with no other bounds
+    pub fn new<P>(prefix: P) -> Self
+    where
+        P: Into<Vec<u8>>,
+    {
+        Self {
+            prefix: prefix.into(),
+            ..Default::default()
+        }
+    }
+
+    /// The first bound `key` or `value` breaks, if any
+    fn check(&self, key: &[u8], value: &[u8]) -> Result<(), Violation> {
+        let size = key.len();
+        match self.max_key_size {
+            Some(max) if size > max => {
+                let key = key.to_vec();
+                return Err(Violation::KeySize { key, size, max });
+            }
+            _ => {}
+        }
+        let depth = key.split(|&byte| byte == b'/').count();
+        match self.max_depth {
+            Some(max) if depth > max => {
+                let key = key.to_vec();
+                return Err(Violation::Depth { key, depth, max });
+            }
+            _ => {}
+        }
+        if let Some(position) = self.charset.first_invalid(key) {
+            let key = key.to_vec();
+            return Err(Violation::Charset { key, position });
+        }
+        let size = value.len();
+        match self.max_value_size {
+            Some(max) if size > max => {
+                let key = key.to_vec();
+                Err(Violation::ValueSize { key, size, max })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The rules of the keys a client may write, see the [module documentation](self)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeySchema {
+    rules: Vec<KeyRule>,
+}
+
+impl KeySchema {
+    /// A schema with no rules, which allows every key
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow the keys under the rule's prefix, as it says. A key under the prefixes of several
+    /// rules follows the rule with the longest prefix.
+    pub fn rule(mut self, rule: KeyRule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Rules of the schema, in the order they were added
+    pub fn rules(&self) -> &[KeyRule] {
+        &self.rules
+    }
+
+    /// Whether `value` may be written at `key`. Every key may if the schema has no rules.
+    pub fn check(&self, key: &[u8], value: &[u8]) -> Result<(), Violation> {
+        if self.rules.is_empty() {
+            return Ok(());
+        }
+        let rule = self
+            .rules
+            .iter()
+            .filter(|rule| key.starts_with(&rule.prefix))
+            .max_by_key(|rule| rule.prefix.len());
+        match rule {
+            Some(rule) => rule.check(key, value),
+            None => Err(Violation::Prefix { key: key.to_vec() }),
+        }
+    }
+
+    /// Check every put of the transaction `request`, including those of nested transactions
+    pub(crate) fn check_txn(&self, request: &etcdserver::TxnRequest) -> Result<(), Violation> {
+        use etcdserver::request_op::Request;
+
+        for op in request.success.iter().chain(&request.failure) {
+            match &op.request {
+                Some(Request::RequestPut(put)) => self.check(&put.key, &put.value)?,
+                Some(Request::RequestTxn(txn)) => self.check_txn(txn)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A write breaking a [`KeySchema`]. The messages leave the key out, as it is attached to the
+/// error's context unless the client redacts keys.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum Violation {
+    /// The key is not under the prefix of any rule
+    #[error("key is not under an allowed prefix")]
+    Prefix { key: Vec<u8> },
+    /// The key has more segments than its rule allows
+    #[error("key has {depth} segments, more than the {max} allowed")]
+    Depth {
+        key: Vec<u8>,
+        depth: usize,
+        max: usize,
+    },
+    /// The key has a character outside its rule's charset, at byte `position`
+    #[error("key has a character which is not allowed at byte {position}")]
+    Charset { key: Vec<u8>, position: usize },
+    /// The key is longer than its rule allows
+    #[error("key is {size} bytes, more than the {max} allowed")]
+    KeySize {
+        key: Vec<u8>,
+        size: usize,
+        max: usize,
+    },
+    /// The value is longer than its key's rule allows
+    #[error("value is {size} bytes, more than the {max} allowed")]
+    ValueSize {
+        key: Vec<u8>,
+        size: usize,
+        max: usize,
+    },
+}
+
+impl Violation {
+    /// The key of the write
+    pub fn key(&self) -> &[u8] {
+        match self {
+            Violation::Prefix { key }
+            | Violation::Depth { key, .. }
+            | Violation::Charset { key, .. }
+            | Violation::KeySize { key, .. }
+            | Violation::ValueSize { key, .. } => key,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checking_keys() {
+        let schema = KeySchema::new()
+            .rule(KeyRule {
+                prefix: b"app/".to_vec(),
+                max_depth: Some(3),
+                charset: Charset::AlphanumericAnd("-/".into()),
+                max_key_size: Some(16),
+                max_value_size: Some(4),
+            })
+            .rule(KeyRule {
+                charset: Charset::Utf8,
+                ..KeyRule::new("app/blobs/")
+            });
+        assert!(KeySchema::new().check(b"\xff", b"").is_ok());
+        assert!(schema.check(b"app/jobs/job-1", b"done").is_ok());
+        let violation = schema.check(b"other", b"").unwrap_err();
+        assert_eq!(
+            violation,
+            Violation::Prefix {
+                key: b"other".to_vec()
+            }
+        );
+        assert_eq!(violation.key(), b"other");
+        assert!(matches!(
+            schema.check(b"app/jobs/1/2", b""),
+            Err(Violation::Depth {
+                depth: 4,
+                max: 3,
+                ..
+            })
+        ));
+        assert!(matches!(
+            schema.check(b"app/jobs/x y", b""),
+            Err(Violation::Charset { position: 10, .. })
+        ));
+        assert!(matches!(
+            schema.check(b"app/a-very-long-key", b""),
+            Err(Violation::KeySize { size: 19, .. })
+        ));
+        assert!(matches!(
+            schema.check(b"app/jobs", b"too long"),
+            Err(Violation::ValueSize {
+                size: 8,
+                max: 4,
+                ..
+            })
+        ));
+        // The longest prefix wins
+        assert!(schema.check(b"app/blobs/a/b/c d", b"too long").is_ok());
+        assert!(matches!(
+            schema.check(b"app/blobs/\xff", b""),
+            Err(Violation::Charset { position: 10, .. })
+        ));
+
+        let nested = etcdserver::TxnRequest {
+            failure: vec![etcdserver::RequestOp {
+                request: Some(etcdserver::request_op::Request::RequestPut(
+                    etcdserver::PutRequest {
+                        key: b"other".to_vec(),
+                        ..Default::default()
+                    },
+                )),
+            }],
+            ..Default::default()
+        };
+        let request = etcdserver::TxnRequest {
+            success: vec![etcdserver::RequestOp {
+                request: Some(etcdserver::request_op::Request::RequestTxn(nested)),
+            }],
+            ..Default::default()
+        };
+        assert!(matches!(
+            schema.check_txn(&request),
+            Err(Violation::Prefix { .. })
+        ));
+    }
+}
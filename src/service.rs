@@ -0,0 +1,234 @@
+//! The client's RPCs as `tower` services, for composing middleware from the `tower`
+//! ecosystem, such as retries, buffers or load shedding, around individual etcd calls.
+//!
+//! [`RpcService`] is a [`Service`] for each request message of the KV and lease services, as
+//! well as the member list and status calls with the `cluster` and `maintenance` features,
+//! answering with the matching response message:
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! use etcd3::{EtcdClient, RangeRequest};
+//! use futures::future;
+//! use tonic::codegen::Service;
+//!
+//! let client = EtcdClient::connect("http://127.0.0.1:2379").await?;
+//! let mut service = client.service();
+//! future::poll_fn(|cx| Service::<RangeRequest>::poll_ready(&mut service, cx)).await?;
+//! let request = RangeRequest {
+//!     key: b"foo".to_vec(),
+//!     ..Default::default()
+//! };
+//! let response = service.call(request).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The services are built on the `tower` version of the transport, and are always ready.
+//! Requests are sent once, as given: the client's retry policy and request timeout do not
+//! apply, so that middleware can take their place, and keys are not moved into the client's
+//! namespace. Everything else about the client does, such as its limits and hooks.
+
+use crate::{etcdserver, Budget, Error, EtcdClient, EtcdResult, Retry, Transport};
+use futures::future::{BoxFuture, FutureExt};
+use std::fmt;
+use std::task::{Context, Poll};
+use tonic::codegen::Service;
+
+/// The client's RPCs as `tower` services, see the [module documentation](self) and
+/// [`EtcdClient::service`]
+#[derive(Clone)]
+pub struct RpcService<T> {
+    pub(crate) client: EtcdClient<T>,
+}
+
+impl<T> fmt::Debug for RpcService<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RpcService")
+            .field("client", &self.client)
+            .finish()
+    }
+}
+
+macro_rules! rpc {
+    (
+        $(#[$meta:meta])*
+        $request:ident => $response:ident,
+        $client:ident.$method:ident,
+        $operation:literal,
+        $context:literal,
+        $budget:ident,
+        $key:expr
+    ) => {
+        $(#[$meta])*
+        impl<T: Transport> Service<etcdserver::$request> for RpcService<T> {
+            type Response = etcdserver::$response;
+            type Error = Error;
+            type Future = BoxFuture<'static, EtcdResult<etcdserver::$response>>;
+
+            fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<EtcdResult<()>> {
+                Poll::Ready(Ok(()))
+            }
+
+            fn call(&mut self, request: etcdserver::$request) -> Self::Future {
+                let client = self.client.clone();
+                let key: fn(&etcdserver::$request) -> Option<&[u8]> = $key;
+                let context = client.error_context($context, key(&request));
+                async move {
+                    let rpc = || {
+                        let (mut rpc_client, request) = (client.$client(), request.clone());
+                        async move { rpc_client.$method(request).await }
+                    };
+                    let budget = Budget::$budget;
+                    client.call($operation, context, budget, Retry::Never, rpc, Ok).await
+                }
+                .boxed()
+            }
+        }
+    };
+}
+
+rpc!(
+    RangeRequest => RangeResponse,
+    kv_client.range,
+    "kv.range",
+    "range",
+    Read,
+    |request| Some(&request.key)
+);
+rpc!(
+    PutRequest => PutResponse,
+    kv_client.put,
+    "kv.put",
+    "put",
+    Write,
+    |request| Some(&request.key)
+);
+rpc!(
+    DeleteRangeRequest => DeleteRangeResponse,
+    kv_client.delete_range,
+    "kv.delete",
+    "delete",
+    Write,
+    |request| Some(&request.key)
+);
+rpc!(
+    TxnRequest => TxnResponse,
+    kv_client.txn,
+    "kv.txn",
+    "txn",
+    Write,
+    |_| None
+);
+rpc!(
+    CompactionRequest => CompactionResponse,
+    kv_client.compact,
+    "kv.compact",
+    "compact",
+    Write,
+    |_| None
+);
+rpc!(
+    LeaseGrantRequest => LeaseGrantResponse,
+    lease_client.lease_grant,
+    "lease.grant",
+    "lease grant",
+    Write,
+    |_| None
+);
+rpc!(
+    LeaseRevokeRequest => LeaseRevokeResponse,
+    lease_client.lease_revoke,
+    "lease.revoke",
+    "lease revoke",
+    Write,
+    |_| None
+);
+rpc!(
+    LeaseTimeToLiveRequest => LeaseTimeToLiveResponse,
+    lease_client.lease_time_to_live,
+    "lease.time_to_live",
+    "lease time to live",
+    Read,
+    |_| None
+);
+rpc!(
+    LeaseLeasesRequest => LeaseLeasesResponse,
+    lease_client.lease_leases,
+    "lease.leases",
+    "lease leases",
+    Read,
+    |_| None
+);
+rpc!(
+    #[cfg(feature = "cluster")]
+    MemberListRequest => MemberListResponse,
+    cluster_client.member_list,
+    "cluster.member_list",
+    "member list",
+    Read,
+    |_| None
+);
+rpc!(
+    #[cfg(feature = "maintenance")]
+    StatusRequest => StatusResponse,
+    status_client.status,
+    "maintenance.status",
+    "status",
+    Read,
+    |_| None
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recording::{Exchange, Replay};
+    use futures::future;
+
+    #[tokio::test]
+    async fn test_calling_rpcs() {
+        let header = Some(etcdserver::ResponseHeader {
+            revision: 3,
+            ..Default::default()
+        });
+        let lease_not_found = Exchange {
+            code: tonic::Code::NotFound,
+            message: "etcdserver: requested lease not found".into(),
+            ..Exchange::answered(
+                "/etcdserverpb.Lease/LeaseRevoke",
+                etcdserver::LeaseRevokeResponse::default(),
+            )
+        };
+        let replay = Replay::new(vec![
+            Exchange::answered(
+                "/etcdserverpb.KV/Range",
+                etcdserver::RangeResponse {
+                    header,
+                    count: 2,
+                    ..Default::default()
+                },
+            ),
+            lease_not_found,
+        ]);
+        let client = EtcdClient::from_transport(replay.clone());
+        let mut service = client.service();
+
+        let request = etcdserver::RangeRequest {
+            key: b"foo".to_vec(),
+            ..Default::default()
+        };
+        future::poll_fn(|cx| Service::<etcdserver::RangeRequest>::poll_ready(&mut service, cx))
+            .await
+            .unwrap();
+        let response = service.call(request).await.unwrap();
+        assert_eq!(response.header.unwrap().revision, 3);
+        assert_eq!(response.count, 2);
+
+        let err = service
+            .call(etcdserver::LeaseRevokeRequest { id: 1 })
+            .await
+            .unwrap_err();
+        assert!(matches!(err.root(), Error::LeaseNotFound(None)));
+        assert_eq!(err.context().unwrap().operation, "lease revoke");
+        assert_eq!(replay.remaining(), 0);
+    }
+}
@@ -0,0 +1,265 @@
+//! Cleaning up a process's presence in etcd as it is asked to stop.
+//!
+//! Registrations and leadership are tied to a session's lease, so an instance which exits
+//! without cleaning up stays visible, and keeps its leadership, until the lease expires.
+//! [`Shutdown`] gathers what the process holds and, once SIGTERM or ctrl-c is received,
+//! deregisters its instances, resigns its leadership and closes its sessions, so others see it
+//! gone straight away:
+//!
+//! ```no_run
+//! # async fn run(client: etcd3::EtcdClient<tonic::transport::Channel>) -> etcd3::EtcdResult<()> {
+//! use etcd3::concurrency::Session;
+//! use etcd3::discovery;
+//! use etcd3::shutdown::Shutdown;
+//!
+//! let session = Session::new(&client, 10).await?;
+//! let address = "10.0.0.1:50051".to_string();
+//! let registration = discovery::register(&session, "services/greeter/", "a", &address).await?;
+//!
+//! let mut shutdown = Shutdown::new();
+//! shutdown.deregister(registration).close(session);
+//! // Serve requests until the process is asked to stop, then clean up
+//! shutdown.on_signal().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::concurrency::Session;
+use crate::discovery::Registration;
+use crate::{runtime, Error, EtcdResult, Transport};
+use futures::future::{self, BoxFuture, FutureExt};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+/// How long each step of a shutdown is given by default, see [`Shutdown::timeout`]
+pub const DEFAULT_STEP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A cleanup step, described by what it cleans up
+struct Step {
+    what: String,
+    cleanup: BoxFuture<'static, EtcdResult<()>>,
+}
+
+/// What a process holds in etcd, given up when it stops, see the
+/// [module documentation](self)
+///
+/// Registrations and leadership are given up first, in the order they were added, and
+/// sessions closed last, as closing a session deletes every key attached to its lease.
+pub struct Shutdown {
+    steps: Vec<Step>,
+    sessions: Vec<Step>,
+    timeout: Duration,
+}
+
+impl fmt::Debug for Shutdown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let steps: Vec<_> = self
+            .steps
+            .iter()
+            .chain(&self.sessions)
+            .map(|s| &s.what)
+            .collect();
+        f.debug_struct("Shutdown")
+            .field("steps", &steps)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shutdown {
+    /// A shutdown with nothing to clean up yet
+    pub fn new() -> Self {
+        Self {
+            steps: Vec::new(),
+            sessions: Vec::new(),
+            timeout: DEFAULT_STEP_TIMEOUT,
+        }
+    }
+
+    /// Give each step `timeout` to complete, so an unreachable cluster cannot hold up the
+    /// process's exit for long. Steps which time out are left to their leases to clean up.
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Deregister the instance of `registration`
+    pub fn deregister(&mut self, registration: Registration) -> &mut Self {
+        let what = format!(
+            "deregistering {}",
+            String::from_utf8_lossy(registration.key())
+        );
+        self.steps.push(Step {
+            what,
+            cleanup: registration.deregister().boxed(),
+        });
+        self
+    }
+
+    /// Resign the leadership of `leader` in `election`, letting the next campaigner take over
+    #[cfg(feature = "election")]
+    pub fn resign<T>(
+        &mut self,
+        election: crate::election::Election<T>,
+        leader: crate::v3election::LeaderKey,
+    ) -> &mut Self
+    where
+        T: crate::Transport + Send + Sync + 'static,
+    {
+        let what = format!("resigning {}", String::from_utf8_lossy(&leader.name));
+        self.steps.push(Step {
+            what,
+            cleanup: async move { election.resign(leader).await }.boxed(),
+        });
+        self
+    }
+
+    /// Close `session`, revoking its lease and with it every key attached to it, such as the
+    /// keys of the locks and leadership held under it
+    pub fn close<T: Transport>(&mut self, session: Session<T>) -> &mut Self {
+        let what = format!("closing session {:x}", session.lease());
+        self.sessions.push(Step {
+            what,
+            cleanup: session.close().boxed(),
+        });
+        self
+    }
+
+    /// Run `cleanup` along with the other steps, for anything else to give up
+    pub fn on_shutdown<W, F>(&mut self, what: W, cleanup: F) -> &mut Self
+    where
+        W: Into<String>,
+        F: Future<Output = EtcdResult<()>> + Send + 'static,
+    {
+        self.steps.push(Step {
+            what: what.into(),
+            cleanup: cleanup.boxed(),
+        });
+        self
+    }
+
+    /// Wait for SIGTERM or ctrl-c, then clean up, see [`run`](Self::run). Must be called from
+    /// within a tokio runtime.
+    pub async fn on_signal(self) -> EtcdResult<()> {
+        let signal = signal()?;
+        self.on(signal).await
+    }
+
+    /// Wait for `trigger` to complete, then clean up, see [`run`](Self::run)
+    pub async fn on<F>(self, trigger: F) -> EtcdResult<()>
+    where
+        F: Future,
+    {
+        trigger.await;
+        self.run().await
+    }
+
+    /// Clean up straight away. Every step is run even if some fail, with the error of the
+    /// first to fail returned.
+    pub async fn run(self) -> EtcdResult<()> {
+        let mut result = Ok(());
+        for step in self.steps.into_iter().chain(self.sessions) {
+            let started = Instant::now();
+            let outcome = match runtime::timeout(step.cleanup, self.timeout).await {
+                Ok(outcome) => outcome,
+                Err(_) => Err(Error::timeout(&step.what, self.timeout, started)),
+            };
+            if let Err(e) = outcome {
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }
+}
+
+/// A future completing once the process receives SIGTERM or ctrl-c. Must be called from
+/// within a tokio runtime.
+pub fn signal() -> EtcdResult<impl Future<Output = ()>> {
+    let ctrl_c = received(tokio::net::signal::ctrl_c()?).boxed();
+    #[cfg(unix)]
+    {
+        use tokio_net::signal::unix::{signal, SignalKind};
+        let terminate = received(signal(SignalKind::terminate())?).boxed();
+        Ok(future::select(ctrl_c, terminate).map(|_| ()).boxed())
+    }
+    #[cfg(not(unix))]
+    Ok(ctrl_c)
+}
+
+/// The first notification of the signal `stream`, which tokio's streams of this version yield
+/// through their own `Stream` trait
+async fn received<S>(mut stream: S)
+where
+    S: tokio::stream::Stream<Item = ()> + Unpin,
+{
+    future::poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_running_every_step() {
+        let order = Arc::new(AtomicUsize::new(0));
+        let step = |expected: usize, outcome: EtcdResult<()>| {
+            let order = Arc::clone(&order);
+            async move {
+                assert_eq!(order.fetch_add(1, Ordering::SeqCst), expected);
+                outcome
+            }
+        };
+        let mut shutdown = Shutdown::new();
+        shutdown
+            .timeout(Duration::from_millis(50))
+            .on_shutdown("first", step(0, Err(Error::PermissionDenied)))
+            .on_shutdown("hanging", future::pending())
+            .on_shutdown("third", step(1, Err(Error::LeaseNotFound(None))));
+        let err = shutdown.on(future::ready(())).await.unwrap_err();
+        assert!(matches!(err, Error::PermissionDenied));
+        assert_eq!(order.load(Ordering::SeqCst), 2);
+
+        let mut shutdown = Shutdown::new();
+        shutdown
+            .timeout(Duration::from_millis(10))
+            .on_shutdown("hanging", future::pending());
+        let err = shutdown.run().await.unwrap_err();
+        assert!(matches!(err, Error::Timeout { ref what, .. } if what == "hanging"));
+    }
+
+    #[tokio::test]
+    async fn test_deregistering_and_closing() {
+        let client = crate::testing::namespace().await.unwrap();
+        let session = Session::new(&client, 60).await.unwrap();
+        let prefix = client.key("test_deregistering_and_closing/");
+        let address = "10.0.0.1:50051".to_string();
+        let registration = crate::discovery::register(&session, prefix.as_str(), "a", &address)
+            .await
+            .unwrap();
+        let lock = client.key("test_deregistering_and_closing_lock");
+        let mut mutex = crate::concurrency::Mutex::new(&session, lock.as_str());
+        mutex.lock().await.unwrap();
+
+        let mut shutdown = Shutdown::new();
+        shutdown.close(session).deregister(registration);
+        shutdown.run().await.unwrap();
+        let kv = client.kv();
+        assert_eq!(
+            kv.get("test_deregistering_and_closing/a").await.unwrap(),
+            None
+        );
+        assert!(!mutex.is_held().await.unwrap());
+    }
+}
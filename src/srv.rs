@@ -0,0 +1,364 @@
+//! Endpoint discovery through DNS SRV records.
+//!
+//! Clusters can publish their client URLs as `_etcd-client-ssl._tcp.<domain>` and
+//! `_etcd-client._tcp.<domain>` SRV records, like `etcd --discovery-srv` does for peers. Only as
+//! much DNS as looking those up needs is implemented: queries go to the nameservers in
+//! `/etc/resolv.conf` over UDP, and are sent again over TCP if the response is truncated.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+
+/// Time to wait for each nameserver to answer a query
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+const TYPE_SRV: u16 = 33;
+const CLASS_IN: u16 = 1;
+
+/// An SRV record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Record {
+    pub(crate) priority: u16,
+    pub(crate) weight: u16,
+    pub(crate) port: u16,
+    pub(crate) target: String,
+}
+
+/// Client URLs published for the cluster under `domain`, TLS endpoints first, in order of
+/// the records' priority then weight
+pub(crate) async fn endpoints(domain: &str) -> io::Result<Vec<String>> {
+    let resolv_conf = std::fs::read_to_string("/etc/resolv.conf").unwrap_or_default();
+    let servers = nameservers(&resolv_conf);
+    let mut endpoints = Vec::new();
+    for (service, scheme) in &[("_etcd-client-ssl", "https"), ("_etcd-client", "http")] {
+        let name = format!("{}._tcp.{}", service, domain);
+        for record in lookup(&servers, &name).await? {
+            endpoints.push(format!("{}://{}:{}", scheme, record.target, record.port));
+        }
+    }
+    Ok(endpoints)
+}
+
+/// Nameservers listed in the contents of a `resolv.conf`, or the local one if none are
+fn nameservers(resolv_conf: &str) -> Vec<SocketAddr> {
+    let servers: Vec<SocketAddr> = resolv_conf
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            match fields.next() {
+                Some("nameserver") => fields.next()?.parse::<IpAddr>().ok(),
+                _ => None,
+            }
+        })
+        .map(|ip| SocketAddr::new(ip, 53))
+        .collect();
+    if servers.is_empty() {
+        vec![SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 53)]
+    } else {
+        servers
+    }
+}
+
+/// Look up the SRV records for `name`, sorted by priority then weight, trying each of the
+/// `servers` in turn until one answers. A name with no records has an empty set of them.
+async fn lookup(servers: &[SocketAddr], name: &str) -> io::Result<Vec<Record>> {
+    let id = query_id();
+    let query = encode_query(id, name)?;
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no nameservers");
+    for server in servers {
+        let response = match query_server(*server, &query).await {
+            Ok(response) => response,
+            Err(e) => {
+                last_error = e;
+                continue;
+            }
+        };
+        let mut records = parse_response(id, &response)?;
+        // Lower priorities are preferred, then higher weights
+        records.sort_by_key(|record| (record.priority, std::cmp::Reverse(record.weight)));
+        return Ok(records);
+    }
+    Err(last_error)
+}
+
+/// Send `query` to `server`, over TCP if the response over UDP is truncated
+async fn query_server(server: SocketAddr, query: &[u8]) -> io::Result<Vec<u8>> {
+    let udp = async {
+        let local: SocketAddr = match server {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (std::net::Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let mut socket = UdpSocket::bind(local).await?;
+        socket.send_to(query, &server).await?;
+        let mut buf = vec![0; 4096];
+        loop {
+            let (len, from) = socket.recv_from(&mut buf).await?;
+            // Ignore anything which is not an answer to this query
+            if from == server && len >= 2 && buf[..2] == query[..2] {
+                buf.truncate(len);
+                return Ok::<_, io::Error>(buf);
+            }
+        }
+    };
+    let response = timeout(udp).await?;
+    if !is_truncated(&response) {
+        return Ok(response);
+    }
+
+    let tcp = async {
+        let mut stream = TcpStream::connect(&server).await?;
+        let len = query.len() as u16;
+        stream.write_all(&len.to_be_bytes()).await?;
+        stream.write_all(query).await?;
+        let mut len = [0; 2];
+        stream.read_exact(&mut len).await?;
+        let mut response = vec![0; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut response).await?;
+        Ok(response)
+    };
+    timeout(tcp).await
+}
+
+/// Give up on `query` after [`QUERY_TIMEOUT`]
+async fn timeout<F>(query: F) -> io::Result<Vec<u8>>
+where
+    F: std::future::Future<Output = io::Result<Vec<u8>>>,
+{
+    match tokio::timer::Timeout::new(query, QUERY_TIMEOUT).await {
+        Ok(response) => response,
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "nameserver did not answer",
+        )),
+    }
+}
+
+/// A random ID to match a query with its response
+fn query_id() -> u16 {
+    use std::hash::{BuildHasher, Hasher};
+
+    let random = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    random as u16
+}
+
+fn invalid<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// A recursive query for the SRV records of `name`
+fn encode_query(id: u16, name: &str) -> io::Result<Vec<u8>> {
+    let mut query = Vec::new();
+    query.extend(&id.to_be_bytes());
+    // Recursion desired, one question
+    query.extend(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid domain name {:?}", name),
+            ));
+        }
+        query.push(label.len() as u8);
+        query.extend(label.as_bytes());
+    }
+    query.push(0);
+    query.extend(&TYPE_SRV.to_be_bytes());
+    query.extend(&CLASS_IN.to_be_bytes());
+    Ok(query)
+}
+
+fn is_truncated(message: &[u8]) -> bool {
+    message.len() > 2 && message[2] & 0x02 != 0
+}
+
+/// Read a big-endian `u16` at `offset` in `message`
+fn read_u16(message: &[u8], offset: usize) -> io::Result<u16> {
+    match message.get(offset..offset + 2) {
+        Some(bytes) => Ok(u16::from_be_bytes([bytes[0], bytes[1]])),
+        None => Err(invalid("DNS message too short")),
+    }
+}
+
+/// Read the possibly compressed name at `offset` in `message`, returning it and the offset
+/// just past it
+fn read_name(message: &[u8], mut offset: usize) -> io::Result<(String, usize)> {
+    let mut labels = Vec::new();
+    // Where the name ends in the message, once a compression pointer has been followed
+    let mut end = None;
+    // Compression pointers must point backwards, so loops are impossible
+    let mut limit = offset;
+    loop {
+        let len = *message
+            .get(offset)
+            .ok_or_else(|| invalid("DNS message too short"))? as usize;
+        match len {
+            0 => {
+                let end = end.unwrap_or(offset + 1);
+                return Ok((labels.join("."), end));
+            }
+            len if len & 0xc0 == 0xc0 => {
+                let pointer = (read_u16(message, offset)? & 0x3fff) as usize;
+                if pointer >= limit {
+                    return Err(invalid("invalid compression pointer in DNS message"));
+                }
+                end.get_or_insert(offset + 2);
+                limit = pointer;
+                offset = pointer;
+            }
+            len => {
+                let label = message
+                    .get(offset + 1..offset + 1 + len)
+                    .ok_or_else(|| invalid("DNS message too short"))?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                offset += 1 + len;
+            }
+        }
+    }
+}
+
+/// The SRV records answering the query `id`
+fn parse_response(id: u16, message: &[u8]) -> io::Result<Vec<Record>> {
+    if read_u16(message, 0)? != id {
+        return Err(invalid("DNS response does not answer the query"));
+    }
+    let flags = read_u16(message, 2)?;
+    match flags & 0x000f {
+        0 => {}
+        // The name does not exist, so it has no records
+        3 => return Ok(Vec::new()),
+        rcode => return Err(invalid(format!("DNS query failed with code {}", rcode))),
+    }
+    let questions = read_u16(message, 4)?;
+    let answers = read_u16(message, 6)?;
+
+    let mut offset = 12;
+    for _ in 0..questions {
+        offset = read_name(message, offset)?.1 + 4;
+    }
+    let mut records = Vec::new();
+    for _ in 0..answers {
+        offset = read_name(message, offset)?.1;
+        let record_type = read_u16(message, offset)?;
+        let len = read_u16(message, offset + 8)? as usize;
+        let data = offset + 10;
+        offset = data + len;
+        if offset > message.len() {
+            return Err(invalid("DNS message too short"));
+        }
+        // Answers can include the CNAME records the name was reached through
+        if record_type != TYPE_SRV {
+            continue;
+        }
+        records.push(Record {
+            priority: read_u16(message, data)?,
+            weight: read_u16(message, data + 2)?,
+            port: read_u16(message, data + 4)?,
+            target: read_name(message, data + 6)?.0,
+        });
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A response to `query` with an SRV answer for `(priority, weight, port, target)`, the
+    /// answer's name compressed to point at the question
+    fn response(query: &[u8], answers: &[(u16, u16, u16, &str)]) -> Vec<u8> {
+        let mut response = query.to_vec();
+        response[2] |= 0x80;
+        response[7] = answers.len() as u8;
+        for (priority, weight, port, target) in answers {
+            response.extend(&[0xc0, 12]);
+            response.extend(&TYPE_SRV.to_be_bytes());
+            response.extend(&CLASS_IN.to_be_bytes());
+            response.extend(&[0, 0, 0, 60]);
+            let mut data = Vec::new();
+            data.extend(&priority.to_be_bytes());
+            data.extend(&weight.to_be_bytes());
+            data.extend(&port.to_be_bytes());
+            for label in target.split('.') {
+                data.push(label.len() as u8);
+                data.extend(label.as_bytes());
+            }
+            data.push(0);
+            response.extend(&(data.len() as u16).to_be_bytes());
+            response.extend(data);
+        }
+        response
+    }
+
+    #[test]
+    fn test_nameservers() {
+        let resolv_conf = "# comment\nsearch example.com\nnameserver 10.0.0.1\nnameserver ::1\n";
+        assert_eq!(
+            nameservers(resolv_conf),
+            ["10.0.0.1:53".parse().unwrap(), "[::1]:53".parse().unwrap()]
+        );
+        assert_eq!(nameservers(""), ["127.0.0.1:53".parse().unwrap()]);
+    }
+
+    #[test]
+    fn test_parsing_responses() {
+        let query = encode_query(7, "_etcd-client._tcp.example.com.").unwrap();
+        assert_eq!(
+            read_name(&query, 12).unwrap().0,
+            "_etcd-client._tcp.example.com"
+        );
+
+        let answers = [
+            (10, 5, 2379, "a.example.com"),
+            (0, 1, 2380, "b.example.com"),
+        ];
+        let records = parse_response(7, &response(&query, &answers)).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            records[1],
+            Record {
+                priority: 0,
+                weight: 1,
+                port: 2380,
+                target: "b.example.com".into(),
+            }
+        );
+
+        assert!(parse_response(8, &response(&query, &answers)).is_err());
+        let mut missing = response(&query, &[]);
+        missing[3] |= 3;
+        assert_eq!(parse_response(7, &missing).unwrap(), []);
+        let mut truncated = response(&query, &answers);
+        truncated.truncate(truncated.len() - 4);
+        assert!(parse_response(7, &truncated).is_err());
+        assert!(encode_query(7, "example..com").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_lookup() {
+        let mut server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0; 512];
+            let (len, from) = server.recv_from(&mut buf).await.unwrap();
+            let answers = [
+                (10, 5, 2379, "a.example.com"),
+                (0, 1, 2379, "b.example.com"),
+            ];
+            let response = response(&buf[..len], &answers);
+            server.send_to(&response, &from).await.unwrap();
+        });
+
+        let records = lookup(&[addr], "_etcd-client._tcp.example.com")
+            .await
+            .unwrap();
+        let targets: Vec<&str> = records.iter().map(|r| r.target.as_str()).collect();
+        assert_eq!(targets, ["b.example.com", "a.example.com"]);
+    }
+}
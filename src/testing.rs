@@ -0,0 +1,502 @@
+//! A throwaway single-member etcd for tests, so they don't need a cluster started by hand
+//! (`test-util` feature).
+//!
+//! [`EtcdServer::start`] launches etcd on free ports with a fresh data directory, waits until
+//! it reports itself healthy and stops it again when dropped. etcd is run from a binary, which
+//! is downloaded from the etcd releases and cached if it is not installed, or from a container
+//! image, see [`Launch`].
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! use etcd3::testing::EtcdServer;
+//!
+//! let etcd = EtcdServer::start()?;
+//! let client = etcd.client().await?;
+//! client.range("foo", None).put("bar").await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Launching etcd takes a moment, so tests can share one server per process through
+//! [`shared_endpoint`] and [`shared_client`] instead. Tests sharing a server can each take a
+//! [`namespace`] of their own, whose keys other tests never see and which are deleted once
+//! the test is done, like the crate's own tests do:
+//!
+//! ```no_run
+//! # async fn run() -> etcd3::EtcdResult<()> {
+//! let client = etcd3::testing::namespace().await?;
+//! client.range("foo", None).put("bar").await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::concurrency::prefix_end;
+use crate::{EtcdClient, EtcdResult};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use tonic::transport::Channel;
+
+/// The etcd release downloaded unless `ETCD_VERSION` names another one
+pub const DEFAULT_VERSION: &str = "v3.5.17";
+
+/// How long etcd has to become healthy after being launched
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long deleting a [`Namespace`]'s keys may take once it is dropped
+const CLEANUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often a launched etcd is checked for being healthy
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Waits for its stdin to close, then stops the process `$1` and removes the directory `$2`.
+/// Stdin also closes when the test process exits without dropping the server, so etcd never
+/// outlives the tests.
+const WATCHDOG: &str = r#"read _
+kill "$1" 2>/dev/null
+while kill -0 "$1" 2>/dev/null; do sleep 0.1; done
+rm -rf "$2""#;
+
+/// How to run etcd
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Launch {
+    /// Run the etcd binary at this path
+    Binary(PathBuf),
+    /// Run a release of etcd, such as `v3.5.17`, downloading it from GitHub with `curl` the
+    /// first time. Releases are cached in `$ETCD3_TEST_CACHE`, or in the temporary directory.
+    Download {
+        /// The release's tag
+        version: String,
+    },
+    /// Run an image, such as `quay.io/coreos/etcd:v3.5.17`, with `docker`
+    Container {
+        /// The image, which must have `etcd` on its `PATH`
+        image: String,
+    },
+}
+
+impl Launch {
+    /// How the environment says to run etcd: the image in `ETCD_IMAGE` if set, or the binary
+    /// in `ETCD_BIN`, or `etcd` if it is on the `PATH`, or else the release in `ETCD_VERSION`,
+    /// which defaults to [`DEFAULT_VERSION`].
+    pub fn from_env() -> Self {
+        if let Ok(image) = std::env::var("ETCD_IMAGE") {
+            return Launch::Container { image };
+        }
+        if let Some(path) = std::env::var_os("ETCD_BIN") {
+            return Launch::Binary(path.into());
+        }
+        if let Some(path) = on_path("etcd") {
+            return Launch::Binary(path);
+        }
+        let version = std::env::var("ETCD_VERSION").unwrap_or_else(|_| DEFAULT_VERSION.into());
+        Launch::Download { version }
+    }
+}
+
+/// A single-member etcd listening on `127.0.0.1`, which is stopped and has its data removed
+/// when dropped
+#[derive(Debug)]
+pub struct EtcdServer {
+    endpoint: String,
+    etcd: Child,
+    watchdog: Child,
+}
+
+impl EtcdServer {
+    /// Launch etcd as the environment says to, see [`Launch::from_env`], and wait until it is
+    /// healthy. Blocks the calling thread while it does.
+    pub fn start() -> io::Result<Self> {
+        Self::launch(Launch::from_env())
+    }
+
+    /// Launch etcd as `launch` says to and wait until it is healthy. Blocks the calling thread
+    /// while it does.
+    pub fn launch(launch: Launch) -> io::Result<Self> {
+        let client_port = free_port()?;
+        let peer_port = free_port()?;
+        let endpoint = format!("http://127.0.0.1:{}", client_port);
+        let peer = format!("http://127.0.0.1:{}", peer_port);
+
+        let (mut command, listen_client, listen_peer) = match &launch {
+            Launch::Binary(path) => (Command::new(path), endpoint.clone(), peer.clone()),
+            Launch::Download { version } => {
+                let command = Command::new(download(version)?);
+                (command, endpoint.clone(), peer.clone())
+            }
+            Launch::Container { image } => {
+                // Inside the container etcd listens on its usual ports, published on the
+                // ones picked here. Stopping docker stops the container, which removes itself.
+                let published = format!("127.0.0.1:{}:2379", client_port);
+                let mut command = Command::new("docker");
+                command.args(["run", "--rm", "-p", &published, image, "etcd"]);
+                let listen_client = "http://0.0.0.0:2379".into();
+                (command, listen_client, "http://127.0.0.1:2380".into())
+            }
+        };
+        let dir = temp_dir("etcd3-test")?;
+        let data_dir = match launch {
+            Launch::Container { .. } => "/tmp/etcd-data".into(),
+            _ => dir.join("data"),
+        };
+        let initial_cluster = format!("test={}", listen_peer);
+        command
+            .arg("--name=test")
+            .arg("--data-dir")
+            .arg(data_dir)
+            .args(["--listen-client-urls", &listen_client])
+            .args(["--advertise-client-urls", &endpoint])
+            .args(["--listen-peer-urls", &listen_peer])
+            .args(["--initial-advertise-peer-urls", &listen_peer])
+            .args(["--initial-cluster", &initial_cluster]);
+
+        let log = dir.join("etcd.log");
+        let output = File::create(&log)?;
+        let etcd = command
+            .stdin(Stdio::null())
+            .stdout(output.try_clone()?)
+            .stderr(output)
+            .spawn()
+            .inspect_err(|_| {
+                let _ = fs::remove_dir_all(&dir);
+            })?;
+        let watchdog = Command::new("sh")
+            .args(["-c", WATCHDOG, "sh", &etcd.id().to_string()])
+            .arg(&dir)
+            .stdin(Stdio::piped())
+            .spawn()?;
+        let mut server = EtcdServer {
+            endpoint,
+            etcd,
+            watchdog,
+        };
+        match server.wait_until_healthy() {
+            Ok(()) => Ok(server),
+            Err(e) => {
+                // Read the log before dropping the server removes it
+                let output = fs::read_to_string(&log).unwrap_or_default();
+                let message = format!("{}, etcd printed:\n{}", e, output);
+                Err(io::Error::new(e.kind(), message))
+            }
+        }
+    }
+
+    /// The client URL, such as `http://127.0.0.1:40123`
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    /// A client connected to the server
+    pub async fn client(&self) -> EtcdResult<EtcdClient<Channel>> {
+        EtcdClient::connect(self.endpoint.clone()).await
+    }
+
+    /// Stop the server and remove its data, waiting until it has
+    pub fn stop(mut self) -> io::Result<()> {
+        self.shut_down()
+    }
+
+    fn shut_down(&mut self) -> io::Result<()> {
+        // Closing stdin tells the watchdog to stop etcd, which waits for it to be reaped
+        drop(self.watchdog.stdin.take());
+        self.etcd.wait()?;
+        self.watchdog.wait().map(drop)
+    }
+
+    /// Poll `/health` until etcd says it is healthy
+    fn wait_until_healthy(&mut self) -> io::Result<()> {
+        let address = self.endpoint.trim_start_matches("http://");
+        let started = Instant::now();
+        loop {
+            if let Ok(true) = healthy(address) {
+                return Ok(());
+            }
+            if let Some(status) = self.etcd.try_wait()? {
+                let message = format!("etcd exited with {}", status);
+                return Err(io::Error::other(message));
+            }
+            if started.elapsed() > READY_TIMEOUT {
+                let message = format!("etcd not healthy after {:?}", READY_TIMEOUT);
+                return Err(io::Error::new(io::ErrorKind::TimedOut, message));
+            }
+            std::thread::sleep(READY_POLL_INTERVAL);
+        }
+    }
+}
+
+impl Drop for EtcdServer {
+    fn drop(&mut self) {
+        let _ = self.shut_down();
+    }
+}
+
+/// The endpoint of a server shared by every test in the process, launched on first use with
+/// [`EtcdServer::start`]. Set `ETCD3_TEST_ENDPOINT` to use a running cluster instead.
+///
+/// # Panics
+///
+/// If the server can't be launched.
+pub fn shared_endpoint() -> &'static str {
+    static ENDPOINT: OnceLock<String> = OnceLock::new();
+    static SERVER: OnceLock<EtcdServer> = OnceLock::new();
+    ENDPOINT.get_or_init(|| {
+        if let Ok(endpoint) = std::env::var("ETCD3_TEST_ENDPOINT") {
+            return endpoint;
+        }
+        let server = EtcdServer::start().expect("failed to launch etcd for tests");
+        SERVER.get_or_init(|| server).endpoint.clone()
+    })
+}
+
+/// A client connected to [`shared_endpoint`]
+pub async fn shared_client() -> EtcdResult<EtcdClient<Channel>> {
+    EtcdClient::connect(shared_endpoint()).await
+}
+
+/// A client of [`shared_endpoint`] namespaced to a prefix of its own, see [`namespace`]
+pub async fn namespace() -> EtcdResult<Namespace> {
+    let prefix = format!(
+        "etcd3-test/{}-{:016x}/",
+        std::process::id(),
+        crate::random()
+    );
+    let client = shared_client().await?.namespaced(&prefix);
+    Ok(Namespace { client, prefix })
+}
+
+/// A client whose keys are all under a prefix no other namespace uses, so tests sharing a
+/// server don't see each other's keys. Derefs to the namespaced client.
+///
+/// Every key under the prefix is deleted when the namespace is dropped, including when the
+/// test panics, over a connection of its own so it doesn't matter which runtime the test ran
+/// on. The recipes which take their prefixes as given, such as those in
+/// [`discovery`](crate::discovery), should be given prefixes from [`prefix`](Self::prefix) to
+/// be cleaned up too.
+#[derive(Debug)]
+pub struct Namespace {
+    client: EtcdClient<Channel>,
+    prefix: String,
+}
+
+impl Namespace {
+    /// The prefix of every key in the namespace
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// `key` in the namespace, for the clients and recipes which don't namespace keys
+    /// themselves
+    pub fn key(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+}
+
+impl Deref for Namespace {
+    type Target = EtcdClient<Channel>;
+
+    fn deref(&self) -> &EtcdClient<Channel> {
+        &self.client
+    }
+}
+
+impl Drop for Namespace {
+    fn drop(&mut self) {
+        let prefix = self.prefix.clone().into_bytes();
+        let cleanup = std::thread::spawn(move || {
+            let runtime = tokio::runtime::Runtime::new()?;
+            runtime.block_on(async {
+                let client = EtcdClient::builder()
+                    .endpoint(shared_endpoint())
+                    .timeout(CLEANUP_TIMEOUT)
+                    .connect()
+                    .await?;
+                let end = prefix_end(&prefix);
+                client.range(prefix, Some(end)).delete().await?;
+                Ok::<_, Box<dyn std::error::Error + Send + Sync>>(())
+            })
+        });
+        // Leftover keys are harmless besides, and panicking while the test is panicking
+        // would abort it
+        let _ = cleanup.join();
+    }
+}
+
+fn healthy(address: &str) -> io::Result<bool> {
+    let mut stream = TcpStream::connect(address)?;
+    stream.set_read_timeout(Some(READY_POLL_INTERVAL * 10))?;
+    write!(stream, "GET /health HTTP/1.0\r\nHost: {}\r\n\r\n", address)?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    Ok(response.contains(r#""health":"true""#))
+}
+
+/// The etcd binary of the release `version`, downloaded unless it is already cached
+fn download(version: &str) -> io::Result<PathBuf> {
+    let cache = std::env::var_os("ETCD3_TEST_CACHE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| std::env::temp_dir().join("etcd3-test-util"));
+    let release = cache.join(version);
+    let binary = release.join("etcd");
+    if binary.exists() {
+        return Ok(binary);
+    }
+
+    let (os, extension) = match std::env::consts::OS {
+        "linux" => ("linux", "tar.gz"),
+        "macos" => ("darwin", "zip"),
+        os => return Err(unsupported(os)),
+    };
+    let arch = match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        arch => return Err(unsupported(arch)),
+    };
+    let name = format!("etcd-{}-{}-{}", version, os, arch);
+    let url = format!(
+        "https://github.com/etcd-io/etcd/releases/download/{}/{}.{}",
+        version, name, extension
+    );
+
+    // Unpack in the cache, then move into place, so concurrent downloads don't see each
+    // other's partial files
+    let unpacked = cache.join(format!(".{}-{}", version, std::process::id()));
+    fs::create_dir_all(&unpacked)?;
+    let cached = unpack(&url, &unpacked, extension).and_then(|()| {
+        fs::rename(&unpacked, &release).or_else(|e| {
+            // Another process may have cached the release first
+            if binary.exists() {
+                Ok(())
+            } else {
+                Err(e)
+            }
+        })
+    });
+    let _ = fs::remove_dir_all(&unpacked);
+    cached.map(|()| binary)
+}
+
+/// Download the archive at `url` and unpack it into `dir`
+fn unpack(url: &str, dir: &Path, extension: &str) -> io::Result<()> {
+    let archive = dir.join(format!("etcd.{}", extension));
+    run(Command::new("curl")
+        .args(["-fsSL", "-o"])
+        .arg(&archive)
+        .arg(url))?;
+    // tar unpacks zip files too where it is bsdtar, as on macOS
+    run(Command::new("tar")
+        .arg("-xf")
+        .arg(&archive)
+        .arg("-C")
+        .arg(dir)
+        .arg("--strip-components=1"))?;
+    fs::remove_file(&archive)
+}
+
+fn run(command: &mut Command) -> io::Result<()> {
+    let status = command.status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        let message = format!("{:?} failed with {}", command, status);
+        Err(io::Error::other(message))
+    }
+}
+
+fn unsupported(platform: &str) -> io::Error {
+    let message = format!(
+        "no etcd release for {}, install etcd and set ETCD_BIN",
+        platform
+    );
+    io::Error::new(io::ErrorKind::Unsupported, message)
+}
+
+/// The first `name` on the `PATH`
+fn on_path(name: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(name))
+        .find(|candidate| candidate.is_file())
+}
+
+/// A port nothing listens on right now
+fn free_port() -> io::Result<u16> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    Ok(listener.local_addr()?.port())
+}
+
+/// A new, empty directory in the temporary directory
+fn temp_dir(prefix: &str) -> io::Result<PathBuf> {
+    static CREATED: AtomicUsize = AtomicUsize::new(0);
+    let created = CREATED.fetch_add(1, Ordering::Relaxed);
+    let name = format!("{}-{}-{}", prefix, std::process::id(), created);
+    let dir = std::env::temp_dir().join(name);
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_launching_from_env() {
+        std::env::set_var("ETCD_IMAGE", "quay.io/coreos/etcd:v3.5.17");
+        let launch = Launch::from_env();
+        std::env::remove_var("ETCD_IMAGE");
+        let image = "quay.io/coreos/etcd:v3.5.17".to_string();
+        assert_eq!(launch, Launch::Container { image });
+    }
+
+    #[test]
+    fn test_failing_to_launch() {
+        let missing = PathBuf::from("/nonexistent/etcd");
+        let err = EtcdServer::launch(Launch::Binary(missing)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+        // Exits straight away, ignoring the arguments for etcd
+        let exiting = on_path("false").unwrap();
+        let err = EtcdServer::launch(Launch::Binary(exiting)).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("etcd exited with exit status: 1"));
+    }
+
+    #[tokio::test]
+    async fn test_namespaces() {
+        let client = shared_client().await.unwrap();
+        let first = namespace().await.unwrap();
+        let second = namespace().await.unwrap();
+        assert_ne!(first.prefix(), second.prefix());
+        first.range("foo", None).put("bar").await.unwrap();
+        assert!(second.range("foo", None).get().await.unwrap().is_empty());
+
+        let key = first.key("foo");
+        let prefix = first.prefix().as_bytes().to_vec();
+        let panicked = std::thread::spawn(move || {
+            let _first = first;
+            panic!("the test failed");
+        });
+        assert!(panicked.join().is_err());
+        assert!(client.range(key, None).get().await.unwrap().is_empty());
+        let end = prefix_end(&prefix);
+        let left = client.range(prefix, Some(end)).get().await.unwrap();
+        assert!(left.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_server() {
+        let etcd = EtcdServer::start().unwrap();
+        let client = etcd.client().await.unwrap();
+        client.range("test_server", None).put("bar").await.unwrap();
+        etcd.stop().unwrap();
+
+        assert!(client.range("test_server", None).get().await.is_err());
+    }
+}
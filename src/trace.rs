@@ -0,0 +1,179 @@
+//! Spans, metrics and hooks for the client's operations.
+//!
+//! Each operation, such as `kv.put`, `watch.create` or `lease.keep_alive`, runs inside an
+//! `etcd` span, so calls to etcd show up in the traces of the requests which made them. The
+//! span's `otel.name` is the operation, for subscribers exporting to OpenTelemetry. Its
+//! fields are the key or prefix operated on, left out like in errors if the client redacts
+//! keys, the endpoint the request was sent to, the revision of the response, how many times
+//! it was sent and how long the operation took. The same operations are counted and timed
+//! by [`metrics`](crate::metrics), and passed to the client's [`ClientHook`](crate::ClientHook)s.
+
+use crate::hook::{Call, Hooks};
+use crate::{ErrorContext, EtcdResult};
+use std::future::Future;
+use std::time::Instant;
+#[cfg(feature = "tracing")]
+use tracing::Instrument;
+
+/// An operation in progress, see the [module docs](self)
+pub(crate) struct Operation {
+    name: &'static str,
+    hooks: Hooks,
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+    started: Instant,
+}
+
+impl Operation {
+    /// Start the operation `name`, on the key and endpoint of `context`, running `hooks`
+    /// around it
+    pub(crate) fn start(name: &'static str, context: &ErrorContext, hooks: &Hooks) -> Self {
+        #[cfg(feature = "metrics")]
+        crate::metrics::started(name);
+        let call = Call { name, context };
+        for hook in hooks.iter() {
+            hook.on_request(&call);
+        }
+        Self {
+            name,
+            hooks: hooks.clone(),
+            #[cfg(feature = "tracing")]
+            span: tracing::info_span!(
+                "etcd",
+                otel.name = name,
+                key = context.key.as_deref(),
+                endpoint = context.endpoint.as_deref(),
+                revision = tracing::field::Empty,
+                attempts = tracing::field::Empty,
+                latency_ms = tracing::field::Empty,
+                error = tracing::field::Empty,
+            ),
+            started: Instant::now(),
+        }
+    }
+
+    /// Run `future` as part of the operation
+    pub(crate) fn run<F>(&self, future: F) -> impl Future<Output = F::Output>
+    where
+        F: Future,
+    {
+        #[cfg(feature = "tracing")]
+        let future = future.instrument(self.span.clone());
+        future
+    }
+
+    /// Finish the operation with `result`, recording what `context` learned about it
+    pub(crate) fn finish<R>(self, context: &ErrorContext, result: &EtcdResult<R>) {
+        let latency = self.started.elapsed();
+        let call = Call {
+            name: self.name,
+            context,
+        };
+        for hook in self.hooks.iter() {
+            match result {
+                Ok(_) => hook.on_response(&call, latency),
+                Err(e) => hook.on_error(&call, latency, e),
+            }
+        }
+        #[cfg(feature = "metrics")]
+        crate::metrics::finished(self.name, latency, result.as_ref().err());
+        #[cfg(feature = "tracing")]
+        {
+            let span = &self.span;
+            if let Some(header) = &context.header {
+                span.record("revision", header.revision);
+            }
+            if let Some(attempts) = context.attempt {
+                span.record("attempts", attempts);
+            }
+            span.record("latency_ms", latency.as_secs_f64() * 1000.0);
+            if let Err(e) = result {
+                span.record("error", tracing::field::display(e));
+            }
+        }
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl Drop for Operation {
+    fn drop(&mut self) {
+        crate::metrics::stopped(self.name);
+    }
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use super::*;
+    use std::fmt;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    /// Collects the fields of every span
+    #[derive(Clone, Default)]
+    struct Fields(Arc<Mutex<Vec<(String, String)>>>);
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            let field = (field.name().to_string(), format!("{:?}", value));
+            self.0.lock().unwrap().push(field);
+        }
+    }
+
+    impl Subscriber for Fields {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            span.record(&mut self.clone());
+            Id::from_u64(1)
+        }
+
+        fn record(&self, _: &Id, values: &Record<'_>) {
+            values.record(&mut self.clone());
+        }
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, _: &Event<'_>) {}
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn test_operation_span() {
+        let fields = Fields::default();
+        tracing::subscriber::with_default(fields.clone(), || {
+            let mut context = ErrorContext {
+                operation: "put",
+                key: Some("foo".to_string()),
+                ..Default::default()
+            };
+            let operation = Operation::start("kv.put", &context, &Hooks::default());
+            context.attempt = Some(2);
+            context.header = Some(crate::etcdserver::ResponseHeader {
+                revision: 7,
+                ..Default::default()
+            });
+            let result: EtcdResult<()> = Err(crate::Error::NoLeader);
+            operation.finish(&context, &result);
+        });
+
+        let fields = fields.0.lock().unwrap();
+        let field = |name: &str| {
+            let mut values = fields.iter().filter(|(field, _)| field == name);
+            values.next().map(|(_, value)| value.clone())
+        };
+        assert_eq!(field("otel.name").as_deref(), Some("\"kv.put\""));
+        assert_eq!(field("key").as_deref(), Some("\"foo\""));
+        assert_eq!(field("endpoint"), None);
+        assert_eq!(field("revision").as_deref(), Some("7"));
+        assert_eq!(field("attempts").as_deref(), Some("2"));
+        assert_eq!(field("error").as_deref(), Some("no leader"));
+        assert!(field("latency_ms").is_some());
+    }
+}
@@ -0,0 +1,483 @@
+//! A map whose entries expire, shared by every process using the same prefix.
+//!
+//! Each entry of a [`TtlMap`] is a key under the map's prefix, attached to a lease which
+//! expires after the entry's TTL, so entries disappear from the cluster without anyone having
+//! to remove them. Leases are granted per entry, or shared by the entries expiring around the
+//! same time when [`TtlMapOptions::bucket`] is set, trading the precision of the expiry for
+//! far fewer leases. [`TtlMap::events`] follows the entries which expire or are removed:
+//!
+//! ```no_run
+//! # async fn run(client: etcd3::EtcdClient<tonic::transport::Channel>) -> etcd3::EtcdResult<()> {
+//! use etcd3::ttl_map::{TtlEvent, TtlMap, TtlMapOptions};
+//! use std::time::Duration;
+//!
+//! let sessions = TtlMap::new(&client, "sessions/", TtlMapOptions::default());
+//! let mut events = sessions.events().await?;
+//! sessions.insert("alice", "token", Duration::from_secs(30)).await?;
+//! while let Some(event) = events.next().await {
+//!     if let Ok(TtlEvent::Expired { key, .. }) = event {
+//!         println!("session of {} expired", String::from_utf8_lossy(&key));
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::concurrency::prefix_end;
+use crate::redact::Key;
+use crate::{client, etcdserver, is_retryable, mvccpb, runtime, Backoff, EtcdClient, EtcdResult};
+use bytes::Bytes;
+use futures::channel::mpsc;
+use futures::{SinkExt, StreamExt};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Mutex as StdMutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tonic::transport::channel::Channel;
+
+/// Most expired leases remembered by [`TtlMap::events`], so the keys of an expired bucket
+/// are told apart without asking about its lease for each of them
+const EXPIRED_LEASES: usize = 1024;
+
+/// How the entries of a [`TtlMap`] are attached to leases
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TtlMapOptions {
+    /// Share a lease between the entries expiring within the same span of this length, so
+    /// an entry expires up to this much later than its TTL. Each entry has a lease of its own
+    /// by default.
+    pub bucket: Option<Duration>,
+}
+
+/// An entry of a [`TtlMap`] which is gone
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TtlEvent {
+    /// The entry's TTL ran out
+    Expired { key: Vec<u8>, value: Bytes },
+    /// The entry was removed before its TTL ran out
+    Removed { key: Vec<u8>, value: Bytes },
+}
+
+impl TtlEvent {
+    /// Key of the entry, without the map's prefix
+    pub fn key(&self) -> &[u8] {
+        match self {
+            Self::Expired { key, .. } | Self::Removed { key, .. } => key,
+        }
+    }
+
+    /// Value the entry had when it went
+    pub fn value(&self) -> &Bytes {
+        match self {
+            Self::Expired { value, .. } | Self::Removed { value, .. } => value,
+        }
+    }
+}
+
+/// Entries under a prefix which expire after their TTL, see the [module documentation](self)
+pub struct TtlMap {
+    kv_client: client::KvClient<Channel>,
+    lease_client: client::LeaseClient<Channel>,
+    watch_client: client::WatchClient<Channel>,
+    prefix: Vec<u8>,
+    options: TtlMapOptions,
+    /// Whether entries are read serializably
+    serializable: bool,
+    /// Leases shared by the entries expiring in each bucket, by the end of the bucket
+    buckets: StdMutex<HashMap<u64, i64>>,
+}
+
+impl fmt::Debug for TtlMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TtlMap")
+            .field("prefix", &Key(&self.prefix))
+            .field("options", &self.options)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TtlMap {
+    /// The map of the entries under `prefix`, inside the client's
+    /// [namespace](EtcdClient::namespaced)
+    pub fn new<P>(client: &EtcdClient<Channel>, prefix: P, options: TtlMapOptions) -> Self
+    where
+        P: Into<Vec<u8>>,
+    {
+        Self {
+            kv_client: client.kv_client(),
+            lease_client: client.lease_client(),
+            watch_client: client.watch_client(),
+            prefix: client.namespaced_key(&prefix.into()),
+            options,
+            serializable: client.serializable_reads(),
+            buckets: StdMutex::new(HashMap::new()),
+        }
+    }
+
+    /// Set `key` to `value` for `ttl`, rounded up to the second, replacing its value and TTL
+    /// if it is already set
+    pub async fn insert<K, V>(&self, key: K, value: V, ttl: Duration) -> EtcdResult<()>
+    where
+        K: AsRef<[u8]>,
+        V: Into<Vec<u8>>,
+    {
+        let lease = self.lease_for(ttl).await?;
+        let request = etcdserver::PutRequest {
+            key: self.key(key.as_ref()),
+            value: value.into(),
+            lease,
+            ..Default::default()
+        };
+        self.kv_client.clone().put(request).await?;
+        Ok(())
+    }
+
+    /// Value of `key`, if it is set and has not expired
+    pub async fn get<K>(&self, key: K) -> EtcdResult<Option<Bytes>>
+    where
+        K: AsRef<[u8]>,
+    {
+        Ok(self
+            .entry(key.as_ref())
+            .await?
+            .map(|kv| Bytes::from(kv.value)))
+    }
+
+    /// Time left until `key` expires, if it is set
+    pub async fn ttl<K>(&self, key: K) -> EtcdResult<Option<Duration>>
+    where
+        K: AsRef<[u8]>,
+    {
+        let kv = match self.entry(key.as_ref()).await? {
+            Some(kv) if kv.lease != 0 => kv,
+            _ => return Ok(None),
+        };
+        let request = etcdserver::LeaseTimeToLiveRequest {
+            id: kv.lease,
+            keys: false,
+        };
+        let response = self
+            .lease_client
+            .clone()
+            .lease_time_to_live(request)
+            .await?;
+        // Leases which are gone have a TTL of -1, and so are their keys by now
+        let ttl = response.into_inner().ttl;
+        if ttl < 0 {
+            return Ok(None);
+        }
+        Ok(Some(Duration::from_secs(ttl as u64)))
+    }
+
+    /// Remove `key`, returning its value if it was set
+    pub async fn remove<K>(&self, key: K) -> EtcdResult<Option<Bytes>>
+    where
+        K: AsRef<[u8]>,
+    {
+        let request = etcdserver::DeleteRangeRequest {
+            key: self.key(key.as_ref()),
+            prev_kv: true,
+            ..Default::default()
+        };
+        let response = self.kv_client.clone().delete_range(request).await?;
+        let kv = response.into_inner().prev_kvs.into_iter().next();
+        Ok(kv.map(|kv| Bytes::from(kv.value)))
+    }
+
+    /// Follow the entries which expire or are removed from now on, by any process. Must be
+    /// called from within a tokio runtime.
+    ///
+    /// An entry removed just as its lease expired may be reported as having expired.
+    pub async fn events(&self) -> EtcdResult<TtlEvents> {
+        let mut watch_client = self.watch_client.clone();
+        let mut responses = watch(&mut watch_client, &self.prefix, 0).await?;
+        let mut lease_client = self.lease_client.clone();
+        let prefix = self.prefix.clone();
+        let (mut tx, rx) = mpsc::channel(16);
+
+        runtime::spawn(async move {
+            let mut backoff = Backoff::new();
+            // Revision of the last response seen, to carry on from if the watch breaks
+            let mut revision = 0;
+            let mut expired = HashSet::new();
+            loop {
+                let response = match responses.message().await {
+                    Ok(Some(response)) => response,
+                    Err(status) if !is_retryable(&status) => {
+                        let _ = tx.send(Err(status.to_string())).await;
+                        return;
+                    }
+                    // The watch broke, so carry on from the last change seen, or from now if
+                    // it broke before it was created
+                    _ => {
+                        let next = if revision > 0 { revision + 1 } else { 0 };
+                        match rewatch(&mut watch_client, &prefix, next, &mut backoff, &mut tx).await
+                        {
+                            Some(rewatched) => responses = rewatched,
+                            None => return,
+                        }
+                        continue;
+                    }
+                };
+                backoff.reset();
+                if response.canceled && response.compact_revision > 0 {
+                    // The entries which went in the meantime cannot be told any more
+                    let next = response.compact_revision;
+                    match rewatch(&mut watch_client, &prefix, next, &mut backoff, &mut tx).await {
+                        Some(rewatched) => responses = rewatched,
+                        None => return,
+                    }
+                    continue;
+                }
+                if response.canceled {
+                    let reason = format!("watch canceled: {}", response.cancel_reason);
+                    let _ = tx.send(Err(reason)).await;
+                    return;
+                }
+                if let Some(header) = &response.header {
+                    revision = revision.max(header.revision);
+                }
+                for event in response.events {
+                    let event = gone(&mut lease_client, &mut expired, &prefix, event).await;
+                    if let Some(event) = event {
+                        if tx.send(event).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+
+        Ok(TtlEvents { events: rx })
+    }
+
+    /// Full key of the entry `key`
+    fn key(&self, key: &[u8]) -> Vec<u8> {
+        let mut full = self.prefix.clone();
+        full.extend_from_slice(key);
+        full
+    }
+
+    /// The entry `key`, if it is set
+    async fn entry(&self, key: &[u8]) -> EtcdResult<Option<mvccpb::KeyValue>> {
+        let key = self.key(key);
+        let request = etcdserver::RangeRequest {
+            key: key.clone(),
+            serializable: self.serializable,
+            ..Default::default()
+        };
+        let response = self.kv_client.clone().range(request).await?.into_inner();
+        Ok(response.kvs.into_iter().find(|kv| kv.key == key))
+    }
+
+    /// A lease for an entry which expires after `ttl`, shared with the other entries of its
+    /// bucket if the entries are bucketed
+    async fn lease_for(&self, ttl: Duration) -> EtcdResult<i64> {
+        let ttl = ttl_secs(ttl);
+        let bucket = match self.options.bucket {
+            Some(bucket) => bucket,
+            None => return self.grant(ttl).await,
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let end = bucket_end(now, ttl, ttl_secs(bucket) as u64);
+        {
+            let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+            buckets.retain(|&end, _| end > now);
+            if let Some(&lease) = buckets.get(&end) {
+                return Ok(lease);
+            }
+        }
+        let lease = self.grant((end - now) as i64).await?;
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(*buckets.entry(end).or_insert(lease))
+    }
+
+    /// Grant a lease of `ttl` seconds
+    async fn grant(&self, ttl: i64) -> EtcdResult<i64> {
+        let request = etcdserver::LeaseGrantRequest { ttl, id: 0 };
+        let mut lease_client = self.lease_client.clone();
+        let response = lease_client.lease_grant(request).await?.into_inner();
+        if !response.error.is_empty() {
+            return Err(format!("failed to grant lease: {}", response.error).into());
+        }
+        Ok(response.id)
+    }
+}
+
+/// `ttl` in whole seconds, rounded up, and at least one
+fn ttl_secs(ttl: Duration) -> i64 {
+    let secs = ttl.as_secs() + u64::from(ttl.subsec_nanos() > 0);
+    secs.max(1) as i64
+}
+
+/// End of the bucket of `bucket` seconds which an entry of `ttl` seconds put at `now` expires
+/// in, in seconds since the Unix epoch
+fn bucket_end(now: u64, ttl: i64, bucket: u64) -> u64 {
+    let expiry = now + ttl as u64;
+    expiry.div_ceil(bucket) * bucket
+}
+
+/// The entry gone with `event`, if it is a deletion. Entries whose lease no longer exists
+/// have expired, as the map never revokes leases itself.
+async fn gone(
+    lease_client: &mut client::LeaseClient<Channel>,
+    expired: &mut HashSet<i64>,
+    prefix: &[u8],
+    event: mvccpb::Event,
+) -> Option<Result<TtlEvent, String>> {
+    if event.r#type != mvccpb::event::EventType::Delete as i32 {
+        return None;
+    }
+    let kv = event.prev_kv?;
+    let key = kv.key[prefix.len().min(kv.key.len())..].to_vec();
+    let value = Bytes::from(kv.value);
+    if kv.lease == 0 {
+        return Some(Ok(TtlEvent::Removed { key, value }));
+    }
+    if !expired.contains(&kv.lease) {
+        let request = etcdserver::LeaseTimeToLiveRequest {
+            id: kv.lease,
+            keys: false,
+        };
+        match lease_client.lease_time_to_live(request).await {
+            Ok(response) if response.get_ref().ttl >= 0 => {
+                return Some(Ok(TtlEvent::Removed { key, value }))
+            }
+            Ok(_) => {}
+            Err(status) => return Some(Err(status.to_string())),
+        }
+        if expired.len() >= EXPIRED_LEASES {
+            expired.clear();
+        }
+        expired.insert(kv.lease);
+    }
+    Some(Ok(TtlEvent::Expired { key, value }))
+}
+
+/// Entries of a [`TtlMap`] which expire or are removed, see [`TtlMap::events`]
+pub struct TtlEvents {
+    events: mpsc::Receiver<Result<TtlEvent, String>>,
+}
+
+impl fmt::Debug for TtlEvents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TtlEvents").finish_non_exhaustive()
+    }
+}
+
+impl TtlEvents {
+    /// The next entry to go. Returns `None` once the entries can no longer be followed, after
+    /// the error which ended them.
+    pub async fn next(&mut self) -> Option<Result<TtlEvent, String>> {
+        self.events.next().await
+    }
+}
+
+/// Watch for changes to the keys under `prefix` from `revision` onwards, with the keys'
+/// values before they changed
+async fn watch(
+    watch_client: &mut client::WatchClient<Channel>,
+    prefix: &[u8],
+    revision: i64,
+) -> Result<tonic::Streaming<etcdserver::WatchResponse>, tonic::Status> {
+    let create = etcdserver::WatchCreateRequest {
+        key: prefix.to_vec(),
+        range_end: prefix_end(prefix),
+        start_revision: revision,
+        prev_kv: true,
+        ..Default::default()
+    };
+    let requests = async_stream::stream! {
+        yield etcdserver::WatchRequest {
+            request_union: Some(etcdserver::watch_request::RequestUnion::CreateRequest(create)),
+        };
+    };
+    Ok(watch_client.watch(requests).await?.into_inner())
+}
+
+/// Re-establish a broken watch from `revision` onwards, backing off between attempts. Returns
+/// `None` once `events` is closed or after sending it the error which stopped the watch
+/// being re-established.
+async fn rewatch(
+    watch_client: &mut client::WatchClient<Channel>,
+    prefix: &[u8],
+    revision: i64,
+    backoff: &mut Backoff,
+    events: &mut mpsc::Sender<Result<TtlEvent, String>>,
+) -> Option<tonic::Streaming<etcdserver::WatchResponse>> {
+    loop {
+        backoff.wait().await;
+        if events.is_closed() {
+            return None;
+        }
+        match watch(watch_client, prefix, revision).await {
+            Ok(responses) => {
+                #[cfg(feature = "metrics")]
+                crate::metrics::watch_reconnected(1);
+                return Some(responses);
+            }
+            Err(status) if is_retryable(&status) => continue,
+            Err(status) => {
+                let _ = events.send(Err(status.to_string())).await;
+                return None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucketing_expiries() {
+        assert_eq!(ttl_secs(Duration::from_millis(1500)), 2);
+        assert_eq!(ttl_secs(Duration::from_secs(0)), 1);
+
+        // Entries expiring within the same minute share its end
+        assert_eq!(bucket_end(1000, 30, 60), 1080);
+        assert_eq!(bucket_end(1010, 60, 60), 1080);
+        assert_eq!(bucket_end(1021, 59, 60), 1080);
+        assert_eq!(bucket_end(1021, 60, 60), 1140);
+    }
+
+    #[tokio::test]
+    async fn test_expiring_entries() {
+        let client = crate::testing::namespace().await.unwrap();
+        let options = TtlMapOptions {
+            bucket: Some(Duration::from_secs(1)),
+        };
+        let map = TtlMap::new(&client, "test_expiring_entries/", options);
+        let mut events = map.events().await.unwrap();
+
+        map.insert("a", "1", Duration::from_secs(1)).await.unwrap();
+        map.insert("b", "2", Duration::from_secs(60)).await.unwrap();
+        assert_eq!(map.get("a").await.unwrap(), Some(Bytes::from("1")));
+        assert!(map.ttl("b").await.unwrap().unwrap() > Duration::from_secs(30));
+        // Entries are inside the client's namespace
+        let entry = client.kv().get("test_expiring_entries/b").await.unwrap();
+        assert_eq!(entry.as_deref(), Some("2"));
+        assert_eq!(map.remove("b").await.unwrap(), Some(Bytes::from("2")));
+
+        let removed = events.next().await.unwrap().unwrap();
+        assert_eq!(
+            removed,
+            TtlEvent::Removed {
+                key: b"b".to_vec(),
+                value: Bytes::from("2"),
+            }
+        );
+        let expired = events.next().await.unwrap().unwrap();
+        assert_eq!(
+            expired,
+            TtlEvent::Expired {
+                key: b"a".to_vec(),
+                value: Bytes::from("1"),
+            }
+        );
+        assert_eq!(map.get("a").await.unwrap(), None);
+        assert_eq!(map.ttl("a").await.unwrap(), None);
+    }
+}
@@ -0,0 +1,234 @@
+//! Tunnels to endpoints the transport cannot dial itself.
+//!
+//! The transport only dials TCP, and offers no way to plug in a connector for anything else.
+//! Endpoints reached some other way are served through a listener on the loopback interface
+//! instead, which forwards every connection it accepts to the endpoint.
+
+use futures::channel::oneshot;
+use futures::future::{self, Either};
+use std::convert::TryFrom;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UnixStream};
+
+/// Where the connections accepted by a tunnel are forwarded to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Target {
+    /// The unix domain socket at a path
+    Unix(PathBuf),
+    /// The host and port `authority`, reached by tunnelling through the HTTP proxy at `proxy`
+    /// with a CONNECT request
+    Proxy { proxy: String, authority: String },
+}
+
+impl Target {
+    /// Target of an endpoint URL, if the transport cannot dial it itself
+    pub(crate) fn parse(url: &str) -> Result<Option<Self>, String> {
+        if !url.starts_with("unix:") {
+            return Ok(None);
+        }
+        let path = url.trim_start_matches("unix:").trim_start_matches("//");
+        if path.is_empty() {
+            return Err(format!("endpoint {:?} has no socket path", url));
+        }
+        Ok(Some(Target::Unix(path.into())))
+    }
+}
+
+/// A listener on the loopback interface forwarding the connections it accepts to its target,
+/// until it is dropped. Connections already forwarded are left open.
+#[derive(Debug)]
+pub(crate) struct Tunnel {
+    addr: SocketAddr,
+    _stop: oneshot::Sender<()>,
+}
+
+impl Tunnel {
+    /// Open a tunnel to `target`. Must be called from within a tokio runtime.
+    pub(crate) fn open(target: Target) -> io::Result<Self> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let mut listener = TcpListener::try_from(listener)?;
+        let (stop_tx, mut stop_rx) = oneshot::channel();
+
+        tokio::spawn(async move {
+            loop {
+                let accept = listener.accept();
+                futures::pin_mut!(accept);
+                let inbound = match future::select(accept, &mut stop_rx).await {
+                    Either::Left((Ok((inbound, _)), _)) => inbound,
+                    // Failing to accept one connection does not stop others being accepted
+                    Either::Left((Err(_), _)) => continue,
+                    Either::Right(_) => break,
+                };
+                let target = target.clone();
+                tokio::spawn(async move {
+                    // The client sees the tunnelled connection fail like any other
+                    let _ = forward(inbound, target).await;
+                });
+            }
+        });
+
+        Ok(Tunnel {
+            addr,
+            _stop: stop_tx,
+        })
+    }
+
+    /// Address of the tunnel's end on the loopback interface
+    pub(crate) fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+/// Forward everything sent over `inbound` to `target`, and back again
+async fn forward(inbound: TcpStream, target: Target) -> io::Result<()> {
+    match target {
+        Target::Unix(path) => splice(inbound, UnixStream::connect(path).await?).await,
+        Target::Proxy { proxy, authority } => {
+            let mut outbound = TcpStream::connect(proxy.as_str()).await?;
+            let request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n\r\n", authority);
+            outbound.write_all(request.as_bytes()).await?;
+            connected(&mut outbound).await?;
+            splice(inbound, outbound).await
+        }
+    }
+}
+
+/// Read the proxy's response to a CONNECT request from `stream`, failing unless the proxy
+/// opened the tunnel. Nothing after the response's headers is read.
+async fn connected(stream: &mut TcpStream) -> io::Result<()> {
+    const MAX_RESPONSE: usize = 8192;
+
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() >= MAX_RESPONSE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "proxy response too long",
+            ));
+        }
+        let mut byte = [0];
+        stream.read_exact(&mut byte).await?;
+        response.push(byte[0]);
+    }
+
+    let response = String::from_utf8_lossy(&response);
+    let status = response.lines().next().unwrap_or_default();
+    match status.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("proxy refused to connect: {}", status),
+        )),
+    }
+}
+
+/// Copy between `a` and `b` in both directions until both have shut down
+async fn splice<A, B>(a: A, b: B) -> io::Result<()>
+where
+    A: AsyncRead + AsyncWrite + Unpin,
+    B: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut a_read, mut a_write) = tokio::io::split(a);
+    let (mut b_read, mut b_write) = tokio::io::split(b);
+    let a_to_b = async {
+        a_read.copy(&mut b_write).await?;
+        b_write.shutdown().await
+    };
+    let b_to_a = async {
+        b_read.copy(&mut a_write).await?;
+        a_write.shutdown().await
+    };
+    future::try_join(a_to_b, b_to_a).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_targets() {
+        assert_eq!(Target::parse("http://127.0.0.1:2379"), Ok(None));
+        assert_eq!(
+            Target::parse("unix:///var/run/etcd.sock"),
+            Ok(Some(Target::Unix("/var/run/etcd.sock".into())))
+        );
+        assert_eq!(
+            Target::parse("unix://localhost:2379"),
+            Ok(Some(Target::Unix("localhost:2379".into())))
+        );
+        assert!(Target::parse("unix://").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tunnel() {
+        let path = std::env::temp_dir().join(format!("etcd3-tunnel-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut listener = tokio::net::UnixListener::bind(&path).unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 4];
+            stream.read_exact(&mut buf).await.unwrap();
+            stream.write_all(&buf).await.unwrap();
+        });
+
+        let tunnel = Tunnel::open(Target::Unix(path.clone())).unwrap();
+        let mut stream = TcpStream::connect(tunnel.addr()).await.unwrap();
+        stream.write_all(b"ping").await.unwrap();
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// A proxy which answers CONNECT requests with `status` then echoes what it is sent
+    async fn proxy(status: &'static str) -> String {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                let mut byte = [0];
+                stream.read_exact(&mut byte).await.unwrap();
+                request.push(byte[0]);
+            }
+            assert!(request.starts_with(b"CONNECT etcd.example.com:2379 HTTP/1.1\r\n"));
+            let response = format!("HTTP/1.1 {}\r\n\r\n", status);
+            stream.write_all(response.as_bytes()).await.unwrap();
+
+            let mut buf = [0; 4];
+            if stream.read_exact(&mut buf).await.is_ok() {
+                stream.write_all(&buf).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_proxy_tunnel() {
+        let target = Target::Proxy {
+            proxy: proxy("200 Connection established").await,
+            authority: "etcd.example.com:2379".into(),
+        };
+        let tunnel = Tunnel::open(target).unwrap();
+        let mut stream = TcpStream::connect(tunnel.addr()).await.unwrap();
+        stream.write_all(b"ping").await.unwrap();
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        // The tunnelled connection is closed if the proxy refuses to connect
+        let target = Target::Proxy {
+            proxy: proxy("403 Forbidden").await,
+            authority: "etcd.example.com:2379".into(),
+        };
+        let tunnel = Tunnel::open(target).unwrap();
+        let mut stream = TcpStream::connect(tunnel.addr()).await.unwrap();
+        assert_eq!(stream.read(&mut buf).await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,607 @@
+//! Typed keys and values: the KV and watch services for values of one type, stored under a
+//! prefix and encoded with a [`Codec`].
+//!
+//! JSON is always available with the `serde` feature, bincode and MessagePack with the
+//! `bincode` and `msgpack` features. Values are named by their key without the prefix.
+//!
+//! A [`ConfigWatcher`] keeps a single typed value, such as a service's settings, up to date
+//! with its key, for the value to be reloaded while the program runs.
+
+use crate::concurrency::{prefix_end, read_revision};
+use crate::redact::Key;
+use crate::{
+    client, etcdserver, is_retryable, mvccpb, runtime, strip_namespace, Backoff, DeleteResponse,
+    Error, EtcdClient, EtcdResult, EventKind, PutResponse, Transport, WatchStream,
+};
+use futures::future::{self, Either};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use tokio::sync::watch;
+use tonic::transport::channel::Channel;
+
+/// Encoding of values to and from the bytes stored in etcd
+pub trait Codec {
+    /// The bytes to store for `value`
+    fn encode<V: Serialize>(&self, value: &V) -> EtcdResult<Vec<u8>>;
+
+    /// The value stored as `bytes`
+    fn decode<V: DeserializeOwned>(&self, bytes: &[u8]) -> EtcdResult<V>;
+}
+
+/// Failure of a codec, as an [`Error`]
+fn codec_error<E>(e: E) -> Error
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    Error::Codec(Box::new(e))
+}
+
+/// JSON, which can be read and written with etcdctl
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json;
+
+impl Codec for Json {
+    fn encode<V: Serialize>(&self, value: &V) -> EtcdResult<Vec<u8>> {
+        serde_json::to_vec(value).map_err(codec_error)
+    }
+
+    fn decode<V: DeserializeOwned>(&self, bytes: &[u8]) -> EtcdResult<V> {
+        serde_json::from_slice(bytes).map_err(codec_error)
+    }
+}
+
+/// bincode, compact but only readable by Rust programs using the same types
+#[cfg(feature = "bincode")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl Codec for Bincode {
+    fn encode<V: Serialize>(&self, value: &V) -> EtcdResult<Vec<u8>> {
+        bincode::serialize(value).map_err(codec_error)
+    }
+
+    fn decode<V: DeserializeOwned>(&self, bytes: &[u8]) -> EtcdResult<V> {
+        bincode::deserialize(bytes).map_err(codec_error)
+    }
+}
+
+/// MessagePack, keeping the names of struct fields so types can gain optional fields
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MessagePack {
+    fn encode<V: Serialize>(&self, value: &V) -> EtcdResult<Vec<u8>> {
+        rmp_serde::to_vec_named(value).map_err(codec_error)
+    }
+
+    fn decode<V: DeserializeOwned>(&self, bytes: &[u8]) -> EtcdResult<V> {
+        rmp_serde::from_slice(bytes).map_err(codec_error)
+    }
+}
+
+/// Values of type `V` stored under a prefix and encoded with `C`, see [`EtcdClient::typed`]
+pub struct TypedKv<V, C, T> {
+    client: EtcdClient<T>,
+    prefix: Vec<u8>,
+    codec: C,
+    _values: PhantomData<fn() -> V>,
+}
+
+impl<V, C, T> fmt::Debug for TypedKv<V, C, T>
+where
+    C: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TypedKv")
+            .field("prefix", &Key(&self.prefix))
+            .field("codec", &self.codec)
+            .field("client", &self.client)
+            .finish()
+    }
+}
+
+impl<V, C, T> TypedKv<V, C, T>
+where
+    V: Serialize + DeserializeOwned,
+    C: Codec + Clone,
+    T: Transport,
+{
+    /// Values under `prefix`, encoded with `codec`
+    pub fn new<P>(client: EtcdClient<T>, prefix: P, codec: C) -> Self
+    where
+        P: AsRef<[u8]>,
+    {
+        Self {
+            client,
+            prefix: prefix.as_ref().to_vec(),
+            codec,
+            _values: PhantomData,
+        }
+    }
+
+    /// The prefix values are stored under
+    pub fn prefix(&self) -> &[u8] {
+        &self.prefix
+    }
+
+    /// Key of the value `name`
+    fn key(&self, name: &[u8]) -> Vec<u8> {
+        [&self.prefix[..], name].concat()
+    }
+
+    /// Put `value` as `name`
+    pub async fn put<N>(&self, name: N, value: &V) -> EtcdResult<PutResponse>
+    where
+        N: AsRef<[u8]>,
+    {
+        let value = self.codec.encode(value)?;
+        self.client.kv().put(self.key(name.as_ref()), value).await
+    }
+
+    /// The value `name`, if it exists
+    pub async fn get<N>(&self, name: N) -> EtcdResult<Option<V>>
+    where
+        N: AsRef<[u8]>,
+    {
+        let key = self.key(name.as_ref());
+        let response = self.client.range(&key[..], None).get().await?;
+        match response.get(&key) {
+            Some(kv) => Ok(Some(self.codec.decode(kv.value())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Delete the value `name`
+    pub async fn delete<N>(&self, name: N) -> EtcdResult<DeleteResponse>
+    where
+        N: AsRef<[u8]>,
+    {
+        self.client.kv().delete(self.key(name.as_ref())).await
+    }
+
+    /// Every value under the prefix, by name in key order
+    pub async fn list(&self) -> EtcdResult<Vec<(Vec<u8>, V)>> {
+        let end = prefix_end(&self.prefix);
+        let response = self
+            .client
+            .range(self.prefix.clone(), Some(end))
+            .get()
+            .await?;
+        let mut values = Vec::with_capacity(response.len());
+        for kv in response.kvs() {
+            let name = strip_namespace(&self.prefix, kv.key()).to_vec();
+            values.push((name, self.codec.decode(kv.value())?));
+        }
+        Ok(values)
+    }
+
+    /// Watch the value `name` for changes made from now on, see [`Watch::watch`]
+    ///
+    /// [`Watch::watch`]: crate::Watch::watch
+    pub async fn watch<N>(&self, name: N) -> EtcdResult<TypedWatchStream<V, C>>
+    where
+        N: AsRef<[u8]>,
+    {
+        let inner = self.client.watch().watch(self.key(name.as_ref())).await?;
+        Ok(TypedWatchStream {
+            inner,
+            prefix: self.prefix.clone(),
+            codec: self.codec.clone(),
+            _values: PhantomData,
+        })
+    }
+}
+
+/// A change to a typed value, reported by a [`TypedWatchStream`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypedEvent<V> {
+    /// The value `name` was put
+    Put { name: Vec<u8>, value: V },
+    /// The value `name` was deleted
+    Delete { name: Vec<u8> },
+}
+
+/// The changes to a typed value, see [`TypedKv::watch`]
+pub struct TypedWatchStream<V, C> {
+    inner: WatchStream,
+    prefix: Vec<u8>,
+    codec: C,
+    _values: PhantomData<fn() -> V>,
+}
+
+impl<V, C> fmt::Debug for TypedWatchStream<V, C>
+where
+    C: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TypedWatchStream")
+            .field("prefix", &Key(&self.prefix))
+            .field("codec", &self.codec)
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+impl<V, C> TypedWatchStream<V, C>
+where
+    V: DeserializeOwned,
+    C: Codec,
+{
+    /// The next batch of changes, or `None` once the watch has ended. A batch may be empty,
+    /// such as the one confirming the watch was created.
+    pub async fn message(&mut self) -> EtcdResult<Option<Vec<TypedEvent<V>>>> {
+        let response = match self.inner.message().await? {
+            Some(response) => response,
+            None => return Ok(None),
+        };
+        let mut events = Vec::with_capacity(response.events().len());
+        for event in response.events() {
+            let name = strip_namespace(&self.prefix, event.kv().key()).to_vec();
+            events.push(match event.kind() {
+                EventKind::Put => TypedEvent::Put {
+                    name,
+                    value: self.codec.decode(event.kv().value())?,
+                },
+                EventKind::Delete => TypedEvent::Delete { name },
+            });
+        }
+        Ok(Some(events))
+    }
+}
+
+/// Callback told why a change to a [`ConfigWatcher`]'s key could not be applied
+type OnError = dyn Fn(&Error) + Send + Sync;
+
+/// A value of type `T` kept up to date with the key it is stored at, see
+/// [`ConfigWatcher::new`]
+pub struct ConfigWatcher<T> {
+    key: Vec<u8>,
+    receiver: watch::Receiver<Arc<T>>,
+}
+
+impl<T> fmt::Debug for ConfigWatcher<T>
+where
+    T: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConfigWatcher")
+            .field("key", &Key(&self.key))
+            .field("value", &*self.receiver.get_ref())
+            .finish()
+    }
+}
+
+impl<T> ConfigWatcher<T>
+where
+    T: DeserializeOwned + Send + Sync + 'static,
+{
+    /// Load the JSON value at `key`, and start following changes to it. Must be called from
+    /// within a tokio runtime. Fails if the key does not exist or its value does not decode.
+    ///
+    /// Values put later which do not decode are skipped, keeping the last value which did,
+    /// see [`with_codec`](Self::with_codec) to be told of them. Changes are followed until
+    /// the watcher and every [receiver](Self::receiver) of it are dropped.
+    pub async fn new<K>(client: &EtcdClient<Channel>, key: K) -> EtcdResult<Self>
+    where
+        K: AsRef<[u8]>,
+    {
+        Self::with_codec(client, key, Json, |_| {}).await
+    }
+
+    /// Load the value at `key`, decoded with `codec`, and start following changes to it like
+    /// [`new`](Self::new), calling `on_error` with why a change could not be applied: a value
+    /// which does not decode, the key being deleted, or the changes no longer being followed.
+    /// The last value applied is kept in each case.
+    pub async fn with_codec<K, C, F>(
+        client: &EtcdClient<Channel>,
+        key: K,
+        codec: C,
+        on_error: F,
+    ) -> EtcdResult<Self>
+    where
+        K: AsRef<[u8]>,
+        C: Codec + Send + Sync + 'static,
+        F: Fn(&Error) + Send + Sync + 'static,
+    {
+        let name = key.as_ref().to_vec();
+        let key = client.namespaced_key(&name);
+        let mut kv_client = client.kv_client();
+        let serializable = client.serializable_reads();
+        let (revision, value) = load(&mut kv_client, &key, serializable, &codec).await?;
+        let value = value.ok_or_else(|| not_found(&name))?;
+
+        // Follow on from the revision the value was read at, so no change is missed
+        let mut watch_client = client.watch_client();
+        let responses = watch_key(&mut watch_client, &key, revision + 1).await?;
+        let (updates, receiver) = watch::channel(Arc::new(value));
+        let follower = Follower {
+            kv_client,
+            watch_client,
+            key,
+            name: name.clone(),
+            serializable,
+            codec,
+            on_error: Box::new(on_error),
+            updates,
+        };
+        runtime::spawn(follower.run(responses, revision));
+        Ok(Self {
+            key: name,
+            receiver,
+        })
+    }
+
+    /// The key the value is stored at
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// The value as of the last change applied
+    pub fn get(&self) -> Arc<T> {
+        Arc::clone(&self.receiver.get_ref())
+    }
+
+    /// Receiver which sees every value applied, for tasks reloading as they change
+    pub fn receiver(&self) -> watch::Receiver<Arc<T>> {
+        self.receiver.clone()
+    }
+}
+
+/// The error for the value at `name` not existing
+fn not_found(name: &[u8]) -> Error {
+    Error::KeyNotFound(String::from_utf8_lossy(name).into_owned())
+}
+
+/// Revision the value at `key` was read at, and the value if the key exists
+async fn load<C, T>(
+    kv_client: &mut client::KvClient<Channel>,
+    key: &[u8],
+    serializable: bool,
+    codec: &C,
+) -> EtcdResult<(i64, Option<T>)>
+where
+    C: Codec,
+    T: DeserializeOwned,
+{
+    let request = etcdserver::RangeRequest {
+        key: key.to_vec(),
+        serializable,
+        ..Default::default()
+    };
+    let response = kv_client.range(request).await?.into_inner();
+    let revision = read_revision(response.header, "range")?;
+    match response.kvs.into_iter().next() {
+        Some(kv) => Ok((revision, Some(codec.decode(&kv.value)?))),
+        None => Ok((revision, None)),
+    }
+}
+
+/// Watch for changes to `key` from `revision` onwards
+async fn watch_key(
+    watch_client: &mut client::WatchClient<Channel>,
+    key: &[u8],
+    revision: i64,
+) -> Result<tonic::Streaming<etcdserver::WatchResponse>, tonic::Status> {
+    let create = etcdserver::WatchCreateRequest {
+        key: key.to_vec(),
+        start_revision: revision,
+        ..Default::default()
+    };
+    let requests = async_stream::stream! {
+        yield etcdserver::WatchRequest {
+            request_union: Some(etcdserver::watch_request::RequestUnion::CreateRequest(create)),
+        };
+    };
+    Ok(watch_client.watch(requests).await?.into_inner())
+}
+
+/// The task applying the changes to a [`ConfigWatcher`]'s key
+struct Follower<T, C> {
+    kv_client: client::KvClient<Channel>,
+    watch_client: client::WatchClient<Channel>,
+    key: Vec<u8>,
+    /// The key without the client's namespace, to report errors with
+    name: Vec<u8>,
+    /// Whether the value is read again serializably
+    serializable: bool,
+    codec: C,
+    on_error: Box<OnError>,
+    updates: watch::Sender<Arc<T>>,
+}
+
+impl<T, C> Follower<T, C>
+where
+    T: DeserializeOwned,
+    C: Codec,
+{
+    /// Apply the changes from `responses`, a watch from after `revision`, until every
+    /// receiver is dropped or the changes can no longer be followed
+    async fn run(
+        mut self,
+        mut responses: tonic::Streaming<etcdserver::WatchResponse>,
+        mut revision: i64,
+    ) {
+        let mut backoff = Backoff::new();
+        loop {
+            let message = Box::pin(responses.message());
+            let response = match future::select(Box::pin(self.updates.closed()), message).await {
+                Either::Left(_) => return,
+                Either::Right((message, _)) => message,
+            };
+            let response = match response {
+                Ok(Some(response)) => response,
+                Err(status) if !is_retryable(&status) => return (self.on_error)(&status.into()),
+                // The watch broke, so carry on from the last change seen
+                _ => {
+                    backoff.wait().await;
+                    match watch_key(&mut self.watch_client, &self.key, revision + 1).await {
+                        Ok(rewatched) => responses = rewatched,
+                        Err(status) if is_retryable(&status) => {}
+                        Err(status) => return (self.on_error)(&status.into()),
+                    }
+                    continue;
+                }
+            };
+            backoff.reset();
+            if response.canceled && response.compact_revision > 0 {
+                // The changes since the value's revision are gone, so load it again
+                let reloaded = load(
+                    &mut self.kv_client,
+                    &self.key,
+                    self.serializable,
+                    &self.codec,
+                );
+                let reloaded = match reloaded.await {
+                    Ok((reloaded, value)) => {
+                        revision = reloaded;
+                        value.ok_or_else(|| not_found(&self.name))
+                    }
+                    Err(e) => Err(e),
+                };
+                self.apply(reloaded);
+                match watch_key(&mut self.watch_client, &self.key, revision + 1).await {
+                    Ok(rewatched) => responses = rewatched,
+                    Err(status) => return (self.on_error)(&status.into()),
+                }
+                continue;
+            }
+            if response.canceled {
+                let reason = format!("watch canceled: {}", response.cancel_reason);
+                return (self.on_error)(&reason.into());
+            }
+            for event in response.events {
+                let kv = match event.kv {
+                    Some(kv) => kv,
+                    None => continue,
+                };
+                revision = revision.max(kv.mod_revision);
+                if event.r#type == mvccpb::event::EventType::Delete as i32 {
+                    self.apply(Err(not_found(&self.name)));
+                } else {
+                    self.apply(self.codec.decode(&kv.value));
+                }
+            }
+        }
+    }
+
+    /// Hand `value` to the receivers, or the reason there is no new value to `on_error`
+    fn apply(&self, value: EtcdResult<T>) {
+        match value {
+            Ok(value) => {
+                let _ = self.updates.broadcast(Arc::new(value));
+            }
+            Err(e) => (self.on_error)(&e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use std::collections::BTreeMap;
+
+    fn roundtrip<C: Codec>(codec: C) {
+        let mut value = BTreeMap::new();
+        value.insert("replicas".to_string(), 3);
+        let bytes = codec.encode(&value).unwrap();
+        assert_eq!(
+            codec.decode::<BTreeMap<String, u32>>(&bytes).unwrap(),
+            value
+        );
+    }
+
+    #[test]
+    fn test_codecs() {
+        roundtrip(Json);
+        assert_eq!(Json.encode(&("a", 1)).unwrap(), br#"["a",1]"#);
+        let err = Json.decode::<u32>(b"not json").unwrap_err();
+        assert!(matches!(err, Error::Codec(_)));
+
+        #[cfg(feature = "bincode")]
+        roundtrip(Bincode);
+        #[cfg(feature = "msgpack")]
+        roundtrip(MessagePack);
+    }
+
+    #[tokio::test]
+    async fn test_typed_kv() {
+        let client = crate::testing::namespace().await.unwrap();
+        let services = client.typed::<Vec<u32>, _>("test_typed_kv/");
+        services.put("a", &vec![1, 2]).await.unwrap();
+        services.put("b", &vec![3]).await.unwrap();
+        assert_eq!(services.get("a").await.unwrap(), Some(vec![1, 2]));
+        assert_eq!(services.get("c").await.unwrap(), None);
+        assert_eq!(
+            services.list().await.unwrap(),
+            vec![(b"a".to_vec(), vec![1, 2]), (b"b".to_vec(), vec![3])]
+        );
+
+        let mut changes = services.watch("a").await.unwrap();
+        services.put("a", &vec![4]).await.unwrap();
+        let mut events = Vec::new();
+        while events.is_empty() {
+            events = changes.message().await.unwrap().unwrap();
+        }
+        assert_eq!(
+            events,
+            vec![TypedEvent::Put {
+                name: b"a".to_vec(),
+                value: vec![4]
+            }]
+        );
+
+        // Values which do not decode are reported rather than skipped
+        client
+            .kv()
+            .put("test_typed_kv/c", "not json")
+            .await
+            .unwrap();
+        let err = services.get("c").await.unwrap_err();
+        assert!(matches!(err, Error::Codec(_)));
+
+        for name in &["a", "b", "c"] {
+            services.delete(name).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_config_watcher() {
+        let client = crate::testing::namespace().await.unwrap();
+        let kv = client.kv();
+        let key = "test_config_watcher";
+        let missing = ConfigWatcher::<BTreeMap<String, u32>>::new(&client, key).await;
+        assert!(matches!(missing.unwrap_err(), Error::KeyNotFound(_)));
+
+        kv.put(key, r#"{"replicas":3}"#).await.unwrap();
+        let (errors, mut errored) = futures::channel::mpsc::unbounded();
+        let on_error = move |e: &Error| {
+            let _ = errors.unbounded_send(e.to_string());
+        };
+        let config =
+            ConfigWatcher::<BTreeMap<String, u32>>::with_codec(&client, key, Json, on_error)
+                .await
+                .unwrap();
+        assert_eq!(config.get()["replicas"], 3);
+
+        let mut receiver = config.receiver();
+        kv.put(key, r#"{"replicas":5}"#).await.unwrap();
+        while config.get()["replicas"] != 5 {
+            receiver.recv().await.unwrap();
+        }
+
+        // Bad payloads are reported and the last good value kept
+        kv.put(key, "not json").await.unwrap();
+        assert!(errored.next().await.is_some());
+        assert_eq!(config.get()["replicas"], 5);
+        kv.delete(key).await.unwrap();
+        assert!(errored.next().await.unwrap().contains("not found"));
+        assert_eq!(config.get()["replicas"], 5);
+    }
+}
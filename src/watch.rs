@@ -0,0 +1,501 @@
+//! The watch service: following changes to keys as they are made.
+
+use crate::api::WatchEvents;
+#[cfg(feature = "metrics")]
+use crate::client;
+use crate::clock::SharedClock;
+use crate::redact::Key;
+use crate::{
+    etcdserver, runtime, with_retries, Budget, Diagnostic, EtcdClient, EtcdResult, Shutdown,
+    Transport, WatchResponse,
+};
+#[cfg(feature = "metrics")]
+use futures::channel::oneshot;
+use futures::future::{self, Either, FutureExt};
+use futures::StreamExt;
+use std::fmt;
+#[cfg(feature = "metrics")]
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Watches following changes to keys, see [`EtcdClient::watch`]
+pub struct Watch<T> {
+    pub(crate) client: EtcdClient<T>,
+}
+
+impl<T> fmt::Debug for Watch<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Watch")
+            .field("client", &self.client)
+            .finish()
+    }
+}
+
+impl<T: Transport> Watch<T> {
+    /// Watch `key` for changes made from now on. The watch is cancelled when the client is
+    /// [closed](EtcdClient::close).
+    pub async fn watch<K>(&self, key: K) -> EtcdResult<WatchStream>
+    where
+        K: AsRef<[u8]>,
+    {
+        let create = etcdserver::WatchCreateRequest {
+            key: key.as_ref().to_vec(),
+            ..Default::default()
+        };
+        self.watch_request(create).await
+    }
+
+    /// Watch with whichever of the server's options `create` sets, such as a range end, a
+    /// revision to start from and filters. Its keys are inside the client's namespace.
+    pub(crate) async fn watch_request(
+        &self,
+        mut create: etcdserver::WatchCreateRequest,
+    ) -> EtcdResult<WatchStream> {
+        let mut context = self.client.error_context("watch", Some(&create.key));
+        create.key = self.client.namespaced_key(&create.key);
+        create.range_end = self.client.namespaced_range_end(&create.range_end);
+        let operation = self.client.operation("watch.create", &context);
+        let call = async {
+            let _in_flight = self.client.config.throttle(Budget::Read).await?;
+            let watch_client = self.client.watch_client();
+            let shutdown = self.client.config.shutdown.clone();
+            #[cfg(feature = "metrics")]
+            let lag_interval = self.client.config.watch_lag;
+            #[cfg(not(feature = "metrics"))]
+            let lag_interval: Option<Duration> = None;
+            let retries = self.client.config.retries(context.operation);
+            let diagnostics = self.client.config.diagnostics.clone();
+            let mut attempts = 0;
+            let response = with_retries(retries, true, &mut attempts, || {
+                let mut watch_client = watch_client.clone();
+                let shutdown = shutdown.clone();
+                let watch_create_req = create.clone();
+                let request = async_stream::stream! {
+                    // The watch is in flight until it is cancelled by closing the client
+                    let _in_flight = shutdown.track().ok();
+                    let request_union = etcdserver::watch_request::RequestUnion::CreateRequest(watch_create_req);
+                    let request = etcdserver::WatchRequest {
+                        request_union: Some(request_union),
+                    };
+
+                    yield request;
+
+                    // Ask for the watch's progress whenever its lag is measured, so watches
+                    // seeing no events still catch up with the store's revision
+                    let mut ticks = lag_interval.map(runtime::Interval::new);
+                    while progress_due(&mut ticks, &shutdown).await {
+                        yield progress_request();
+                    }
+                    let _ = shutdown.closed().await;
+                    // The only watch on the stream is always given the first ID
+                    let cancel = etcdserver::WatchCancelRequest { watch_id: 0 };
+                    let request_union = etcdserver::watch_request::RequestUnion::CancelRequest(cancel);
+                    yield etcdserver::WatchRequest {
+                        request_union: Some(request_union),
+                    };
+                };
+                async move { watch_client.watch(request).await }
+            })
+            .await;
+            context.attempt = Some(attempts);
+            #[cfg(feature = "metrics")]
+            if attempts > 1 {
+                crate::metrics::watch_reconnected(attempts - 1);
+            }
+            if attempts > 1 {
+                let key = context.key.clone();
+                let attempts = attempts - 1;
+                diagnostics.emit(|| Diagnostic::WatchRecreated { key, attempts });
+            }
+            Ok(response?.into_inner())
+        };
+        let result: EtcdResult<_> = operation.run(call).await;
+        operation.finish(&context, &result);
+        let inbound = result.map_err(|e| context.attach(e))?;
+
+        Ok(WatchStream {
+            inbound,
+            namespace: self.client.namespace.clone(),
+            clock: self.client.config.clock.clone(),
+            #[cfg(feature = "metrics")]
+            lag: self.client.config.watch_lag.map(|interval| {
+                let label = context.key.unwrap_or_default();
+                let closed = self.client.config.shutdown.closed();
+                Lag::track(self.client.kv_client(), create.key, label, interval, closed)
+            }),
+        })
+    }
+}
+
+/// Wait for the next tick of `ticks`, returning whether progress should be asked for then, or
+/// false once the client has been closed or if there are no ticks
+async fn progress_due(ticks: &mut Option<runtime::Interval>, shutdown: &Shutdown) -> bool {
+    let ticks = match ticks {
+        Some(ticks) => ticks,
+        None => return false,
+    };
+    let tick = Box::pin(ticks.next());
+    matches!(
+        future::select(shutdown.closed(), tick).await,
+        Either::Right(_)
+    )
+}
+
+/// Request for the progress of the watches on a stream
+fn progress_request() -> etcdserver::WatchRequest {
+    let progress = etcdserver::WatchProgressRequest {};
+    etcdserver::WatchRequest {
+        request_union: Some(etcdserver::watch_request::RequestUnion::ProgressRequest(
+            progress,
+        )),
+    }
+}
+
+/// The responses to a watch, see [`Watch::watch`]
+pub struct WatchStream {
+    inbound: tonic::Streaming<etcdserver::WatchResponse>,
+    /// Namespace of the client which opened the watch, stripped from the keys of its events
+    namespace: Arc<[u8]>,
+    /// Clock of the client which opened the watch, which batches wait on
+    clock: SharedClock,
+    /// Tracker of how far the watch is behind the store, if the client measures it
+    #[cfg(feature = "metrics")]
+    lag: Option<Lag>,
+}
+
+impl fmt::Debug for WatchStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WatchStream")
+            .field("namespace", &Key(&self.namespace))
+            .finish_non_exhaustive()
+    }
+}
+
+impl WatchStream {
+    /// The next batch of changes, or `None` once the watch has ended
+    pub async fn message(&mut self) -> EtcdResult<Option<WatchResponse>> {
+        loop {
+            let response = match self.inbound.message().await? {
+                Some(response) => response,
+                None => return Ok(None),
+            };
+            if self.measure_lag(&response) {
+                continue;
+            }
+            return Ok(Some(WatchResponse::from_raw(response, &self.namespace)?));
+        }
+    }
+
+    /// Record the revision `response` was delivered at for the watch's lag, returning whether
+    /// it is progress which was only asked for to measure the lag
+    #[cfg(feature = "metrics")]
+    fn measure_lag(&self, response: &etcdserver::WatchResponse) -> bool {
+        let lag = match &self.lag {
+            Some(lag) => lag,
+            None => return false,
+        };
+        if let Some(header) = &response.header {
+            lag.delivered.fetch_max(header.revision, Ordering::Relaxed);
+        }
+        response.events.is_empty()
+            && !response.created
+            && !response.canceled
+            && response.compact_revision == 0
+    }
+
+    #[cfg(not(feature = "metrics"))]
+    fn measure_lag(&self, _response: &etcdserver::WatchResponse) -> bool {
+        false
+    }
+
+    /// The changes in batches of whole responses, rather than a response at a time, see
+    /// [`BatchOptions`]
+    pub fn batched(self, options: BatchOptions) -> WatchBatches {
+        let clock = self.clock.clone();
+        // The watch ends after its first error
+        let responses = futures::stream::unfold(Some(self), |stream| async move {
+            let mut stream = stream?;
+            match stream.message().await {
+                Ok(Some(response)) => Some((Ok(response), Some(stream))),
+                Ok(None) => None,
+                Err(e) => Some((Err(e), None)),
+            }
+        });
+        WatchBatches::new(Box::pin(responses), options, clock)
+    }
+}
+
+/// When the batches of a [`WatchBatches`] are handed over
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchOptions {
+    /// Hand a batch over once it holds at least this many events. Responses are never split,
+    /// so the events of a revision are always handed over together.
+    pub max_events: usize,
+    /// How long the first response of a batch waits for more to join it. Zero hands over the
+    /// responses which have already arrived, without waiting for more.
+    pub max_delay: Duration,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        Self {
+            max_events: 1024,
+            max_delay: Duration::from_secs(0),
+        }
+    }
+}
+
+/// The responses to a watch, merged into batches so consumers of busy keys are woken once
+/// per batch rather than once per response, see [`WatchStream::batched`]
+pub struct WatchBatches {
+    responses: WatchEvents,
+    options: BatchOptions,
+    clock: SharedClock,
+    /// What ended the last batch without joining it, handed over next
+    held: Option<Option<EtcdResult<WatchResponse>>>,
+}
+
+impl fmt::Debug for WatchBatches {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WatchBatches")
+            .field("options", &self.options)
+            .finish_non_exhaustive()
+    }
+}
+
+impl WatchBatches {
+    pub(crate) fn new(responses: WatchEvents, options: BatchOptions, clock: SharedClock) -> Self {
+        Self {
+            responses,
+            options,
+            clock,
+            held: None,
+        }
+    }
+
+    /// The next batch of changes, as a single response at the revision of the last response
+    /// in it, or `None` once the watch has ended. Cancellations are handed over on their own,
+    /// after the changes which came before them.
+    pub async fn message(&mut self) -> EtcdResult<Option<WatchResponse>> {
+        let first = match self.held.take() {
+            Some(held) => held,
+            None => self.responses.next().await,
+        };
+        let mut batch = match first.transpose()? {
+            Some(response) if !response.is_canceled() => response,
+            response => return Ok(response),
+        };
+        let deadline = self.clock.now() + self.options.max_delay;
+        while batch.events().len() < self.options.max_events {
+            let next = match self.options.max_delay > Duration::from_secs(0) {
+                true => self
+                    .clock
+                    .timeout_at(self.responses.next(), deadline)
+                    .await
+                    .ok(),
+                false => self.responses.next().now_or_never(),
+            };
+            match next {
+                // Nothing more arrived in time
+                None => break,
+                Some(Some(Ok(response))) if !response.is_canceled() => batch.merge(response),
+                Some(next) => {
+                    self.held = Some(next);
+                    break;
+                }
+            }
+        }
+        Ok(Some(batch))
+    }
+}
+
+/// Measures how many revisions a watch's consumer is behind the store, see
+/// [`EtcdClientBuilder::watch_lag_interval`](crate::EtcdClientBuilder::watch_lag_interval)
+#[cfg(feature = "metrics")]
+struct Lag {
+    /// Revision of the last response handed to the consumer, which has seen every event up to
+    /// it
+    delivered: Arc<AtomicI64>,
+    /// Stops the measurements once the watch is dropped
+    _stop: oneshot::Sender<()>,
+}
+
+#[cfg(feature = "metrics")]
+impl Lag {
+    /// Measure the lag of the watch on `key` every `interval`, reading the store's revision
+    /// with `kv_client`, and record it labelled with `label`, until the client is `closed`
+    fn track<T: Transport>(
+        mut kv_client: client::KvClient<T>,
+        key: Vec<u8>,
+        label: String,
+        interval: Duration,
+        closed: crate::Closed,
+    ) -> Self {
+        let delivered = Arc::new(AtomicI64::new(0));
+        let (stop, stopped) = oneshot::channel();
+        let measured = delivered.clone();
+        runtime::spawn(async move {
+            let measure = async {
+                let mut ticks = runtime::Interval::new(interval);
+                while ticks.next().await.is_some() {
+                    // The cheapest read which gives the store's current revision
+                    let request = etcdserver::RangeRequest {
+                        key: key.clone(),
+                        count_only: true,
+                        ..Default::default()
+                    };
+                    let revision = match kv_client.range(request).await {
+                        Ok(response) => match &response.get_ref().header {
+                            Some(header) => header.revision,
+                            None => continue,
+                        },
+                        Err(_) => continue,
+                    };
+                    let delivered = measured.load(Ordering::Relaxed);
+                    crate::metrics::watch_lag(&label, (revision - delivered).max(0));
+                }
+            };
+            futures::pin_mut!(measure);
+            let _ = future::select(measure, future::select(stopped, closed)).await;
+            crate::metrics::watch_lag(&label, 0);
+        });
+        Self {
+            delivered,
+            _stop: stop,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_progress_due() {
+        let shutdown = Shutdown::default();
+        assert!(!progress_due(&mut None, &shutdown).await);
+        let mut ticks = Some(runtime::Interval::new(Duration::from_millis(1)));
+        assert!(progress_due(&mut ticks, &shutdown).await);
+        shutdown.close();
+        assert!(!progress_due(&mut ticks, &shutdown).await);
+    }
+
+    fn response(revision: i64, keys: &[&str], canceled: bool) -> EtcdResult<WatchResponse> {
+        let events = keys.iter().map(|key| crate::mvccpb::Event {
+            kv: Some(crate::mvccpb::KeyValue {
+                key: key.as_bytes().to_vec(),
+                mod_revision: revision,
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        let response = etcdserver::WatchResponse {
+            header: Some(etcdserver::ResponseHeader {
+                revision,
+                ..Default::default()
+            }),
+            events: events.collect(),
+            canceled,
+            ..Default::default()
+        };
+        WatchResponse::from_raw(response, b"")
+    }
+
+    fn keys(response: &WatchResponse) -> Vec<&[u8]> {
+        response
+            .events()
+            .iter()
+            .map(|event| event.kv().key())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_batching_events() {
+        let (tx, rx) = futures::channel::mpsc::unbounded();
+        let options = BatchOptions {
+            max_events: 3,
+            ..Default::default()
+        };
+        let mut batches = WatchBatches::new(Box::pin(rx), options, SharedClock::default());
+        let responses: [(i64, &[&str]); 3] = [(2, &["a", "b"]), (3, &["c", "d"]), (4, &["e"])];
+        for (revision, keys) in responses {
+            tx.unbounded_send(response(revision, keys, false)).unwrap();
+        }
+        tx.unbounded_send(response(5, &["f"], true)).unwrap();
+        tx.unbounded_send(Err(crate::Error::NoLeader)).unwrap();
+
+        // Revisions are kept whole, even past the size of a batch
+        let batch = batches.message().await.unwrap().unwrap();
+        assert_eq!(keys(&batch), vec![b"a", b"b", b"c", b"d"]);
+        assert_eq!(batch.revision(), 3);
+        let batch = batches.message().await.unwrap().unwrap();
+        assert_eq!((keys(&batch), batch.revision()), (vec![&b"e"[..]], 4));
+        assert!(batches.message().await.unwrap().unwrap().is_canceled());
+        assert!(batches.message().await.is_err());
+        drop(tx);
+        assert_eq!(batches.message().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_flushing_batches_on_time() {
+        let mock = crate::MockClock::new();
+        let (tx, rx) = futures::channel::mpsc::unbounded();
+        let options = BatchOptions {
+            max_events: 10,
+            max_delay: Duration::from_secs(1),
+        };
+        let clock = SharedClock::new(mock.clone());
+        let mut batches = WatchBatches::new(Box::pin(rx), options, clock);
+        tx.unbounded_send(response(2, &["a"], false)).unwrap();
+        let send_later = async {
+            while mock.sleeping() == 0 {
+                runtime::delay_for(Duration::from_millis(1)).await;
+            }
+            tx.unbounded_send(response(3, &["b"], false)).unwrap();
+            runtime::delay_for(Duration::from_millis(10)).await;
+            mock.advance(Duration::from_secs(1));
+        };
+        let (batch, ()) = future::join(batches.message(), send_later).await;
+        assert_eq!(keys(&batch.unwrap().unwrap()), vec![b"a", b"b"]);
+    }
+
+    #[tokio::test]
+    async fn test_watching() {
+        use futures::channel::oneshot;
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        let client = crate::testing::namespace().await.unwrap();
+
+        // Get a stream of events from etcd for the "foo" key
+        let mut stream = client.watch().watch("foo").await.unwrap();
+
+        // Channel coordinates the watch task, to wait until the value has been seen, then end the
+        // task. This ensures we do not need sleeps in the test, but that the two separate spawned
+        // tasks can run concurrently.
+        let (tx, mut rx) = oneshot::channel();
+
+        // Have we seen a change yet?
+        let seen = Arc::new(AtomicBool::new(false));
+        let cp = seen.clone();
+
+        // Spawn a task to poll any changes
+        tokio::spawn(async move {
+            if let Some(_val) = stream.message().await.unwrap() {
+                cp.store(true, Ordering::SeqCst);
+                tx.send(true).unwrap();
+            }
+        });
+
+        // Get a range so we can change the "foo" node value
+        let range = client.range("foo", None);
+        range.put("bar").await.unwrap();
+
+        // Wait until the task finishes
+        rx.try_recv().unwrap();
+
+        // Check that we've seen the change
+        assert!(seen.load(Ordering::SeqCst));
+    }
+}